@@ -12,15 +12,12 @@ pub struct Node<'a> {
 
 impl<'a> HashRingNode<'a> for Node<'a> {
     fn id(&self) -> &'a str {
-        &self.name
+        self.name
     }
 }
 
 fn main() {
-    let config = Config {
-        replication_factor: 3,
-        partition_count: 100,
-    };
+    let config = Config::new(3, 100);
 
     // Create a new HashRing using the configuration
     let mut hash_ring = HashRing::new(config).unwrap();
@@ -0,0 +1,61 @@
+//! Feeds arbitrary node IDs and keys to a ring and checks the routing invariants.
+//!
+//! The first byte picks the hasher and replication factor: the CRC16 hasher makes
+//! virtual node collisions likely. The rest is split on `0xff` into node IDs, up to
+//! the first empty segment, followed by keys.
+
+#![no_main]
+
+use std::sync::Arc;
+
+use hashring::{Config, Crc16BuildHasher, HashRing, Node};
+use libfuzzer_sys::fuzz_target;
+
+#[derive(Debug)]
+struct FuzzNode<'a> {
+    id: &'a str,
+}
+
+impl<'a> Node<'a> for FuzzNode<'a> {
+    fn id(&self) -> &'a str {
+        self.id
+    }
+}
+
+fuzz_target!(|data: &[u8]| {
+    let Some((&selector, rest)) = data.split_first() else {
+        return;
+    };
+    let config = Config::new(1 + (selector >> 1) as usize % 16, 16);
+
+    let mut segments = rest.split(|byte| *byte == 0xff);
+    let ids: Vec<&str> = segments
+        .by_ref()
+        .take_while(|segment| !segment.is_empty())
+        .filter_map(|segment| std::str::from_utf8(segment).ok())
+        .collect();
+    let keys: Vec<&[u8]> = segments.collect();
+
+    if selector & 1 == 0 {
+        run(HashRing::new(config).unwrap(), &ids, &keys);
+    } else {
+        run(HashRing::with_hasher(config, Crc16BuildHasher::default()).unwrap(), &ids, &keys);
+    }
+});
+
+fn run<'a, H: std::hash::BuildHasher>(mut hash_ring: HashRing<'a, H>, ids: &[&'a str], keys: &[&[u8]]) {
+    for id in ids {
+        // Duplicate IDs and unresolvable collisions are rejected, which is fine.
+        let _ = hash_ring.add_node(Arc::new(FuzzNode { id }));
+        hash_ring.check_invariants().unwrap();
+    }
+    for key in keys {
+        hash_ring.check_key(key).unwrap();
+    }
+    if let Some(id) = ids.first() {
+        if hash_ring.virtual_nodes_per_node().contains_key(*id) {
+            hash_ring.check_remove_readd(id, keys).unwrap();
+            hash_ring.check_invariants().unwrap();
+        }
+    }
+}
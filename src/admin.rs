@@ -0,0 +1,158 @@
+//! Ring introspection over HTTP, behind the `admin-http` feature.
+//!
+//! `admin_router` returns an axum router serving JSON views of a ring, meant to be
+//! nested under an admin path of a service:
+//!
+//! * `GET /ring` - The snapshot of the ring, as exported by `to_json`.
+//! * `GET /lookup?key=` - The partition, owner and preference list of a key.
+//! * `GET /stats` - Counts, load skew and virtual node spacing.
+//! * `GET /nodes` - Every node with its virtual nodes, partitions and keyspace share.
+//!
+//! The router holds a handle made with `share`, which shares the membership of the
+//! ring, so nodes added to or removed from the original ring are reflected in every
+//! response.
+
+use std::hash::BuildHasher;
+use std::sync::Arc;
+
+use axum::extract::{Query, State};
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::routing::get;
+use axum::{Json, Router};
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use crate::HashRing;
+
+type SharedRing<H, K> = Arc<HashRing<'static, H, K>>;
+
+#[derive(Debug, Deserialize)]
+struct LookupQuery {
+    key: String,
+}
+
+/// Returns a router exposing `/ring`, `/lookup`, `/stats` and `/nodes` for a ring.
+///
+/// # Example
+///
+/// ```
+/// use std::sync::Arc;
+/// use hashring::{admin_router, HashRing, Config, Node};
+///
+/// #[derive(Debug)]
+/// struct MyNode {
+///     name: &'static str,
+/// }
+///
+/// impl Node<'static> for MyNode {
+///     fn id(&self) -> &'static str {
+///         self.name
+///     }
+/// }
+///
+/// let mut hash_ring = HashRing::new(Config::default()).unwrap();
+/// hash_ring.add_node(Arc::new(MyNode { name: "node1" })).unwrap();
+///
+/// // Serve `app` with `axum::serve` next to the service's own routes.
+/// let app: axum::Router = axum::Router::new().nest("/admin", admin_router(hash_ring.share()));
+/// ```
+pub fn admin_router<H, K>(hash_ring: HashRing<'static, H, K>) -> Router
+where
+    H: BuildHasher + Send + Sync + 'static,
+    K: BuildHasher + Send + Sync + 'static,
+{
+    Router::new()
+        .route("/ring", get(ring::<H, K>))
+        .route("/lookup", get(lookup::<H, K>))
+        .route("/stats", get(stats::<H, K>))
+        .route("/nodes", get(nodes::<H, K>))
+        .with_state(Arc::new(hash_ring))
+}
+
+async fn ring<H: BuildHasher, K: BuildHasher>(State(hash_ring): State<SharedRing<H, K>>) -> Json<Value> {
+    Json(json!(hash_ring.snapshot()))
+}
+
+async fn lookup<H: BuildHasher, K: BuildHasher>(State(hash_ring): State<SharedRing<H, K>>, Query(query): Query<LookupQuery>) -> Response {
+    let key = query.key.as_bytes();
+    let replicas = hash_ring.get_preference_list(key);
+    let owner = match hash_ring.get_key_id(key) {
+        Some(owner) => owner,
+        None => return (StatusCode::SERVICE_UNAVAILABLE, Json(json!({ "error": "ring has no nodes" }))).into_response(),
+    };
+    Json(json!({
+        "key": query.key,
+        "partition": hash_ring.get_partition(key),
+        "node": owner,
+        "preference_list": replicas.iter().map(|replica| replica.node.id()).collect::<Vec<_>>(),
+    }))
+    .into_response()
+}
+
+async fn stats<H: BuildHasher, K: BuildHasher>(State(hash_ring): State<SharedRing<H, K>>) -> Json<Value> {
+    let audit = hash_ring.audit();
+    Json(json!({
+        "version": hash_ring.version(),
+        "fingerprint": format!("{:016x}", hash_ring.fingerprint()),
+        "nodes": hash_ring.snapshot().nodes.len(),
+        "virtual_nodes": audit.vnode_count,
+        "partitions": hash_ring.partition_ids().len(),
+        "load_skew": hash_ring.load_skew(),
+        "max_gap_ratio": audit.max_gap_ratio(),
+        "largest_arc_node": audit.largest_arc_node,
+        "largest_arc_fraction": audit.largest_arc_fraction,
+    }))
+}
+
+async fn nodes<H: BuildHasher, K: BuildHasher>(State(hash_ring): State<SharedRing<H, K>>) -> Json<Value> {
+    let vnodes = hash_ring.virtual_nodes_per_node();
+    let mut node_ids = hash_ring.snapshot().nodes;
+    node_ids.sort_unstable();
+    let nodes: Vec<Value> = node_ids
+        .iter()
+        .map(|id| {
+            json!({
+                "id": id,
+                "virtual_nodes": vnodes.get(id).copied().unwrap_or(0),
+                "partitions": hash_ring.partitions_of(id).count(),
+                "keyspace_fraction": hash_ring.keyspace_fraction(id),
+            })
+        })
+        .collect();
+    Json(Value::Array(nodes))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use axum::extract::{Query, State};
+    use axum::http::StatusCode;
+    use axum::Json;
+
+    use super::{lookup, nodes, stats, LookupQuery};
+    use crate::tests::test_nodes;
+    use crate::{Config, HashRing};
+
+    #[tokio::test]
+    async fn test_admin_handlers_follow_the_ring() {
+        let mut hash_ring = HashRing::new(Config::default()).unwrap();
+        let shared = Arc::new(hash_ring.share());
+        let query = || Query(LookupQuery { key: "user:1".to_string() });
+        let response = lookup(State(shared.clone()), query()).await;
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+
+        // Nodes added to the original ring are visible through the shared handle.
+        for node in test_nodes() {
+            hash_ring.add_node(node).unwrap();
+        }
+        assert_eq!(lookup(State(shared.clone()), query()).await.status(), StatusCode::OK);
+        let Json(stats) = stats(State(shared.clone())).await;
+        assert_eq!(stats["nodes"], 3);
+        assert_eq!(stats["version"], hash_ring.version());
+        let Json(nodes) = nodes(State(shared)).await;
+        assert_eq!(nodes[0]["id"], "node1");
+        assert_eq!(nodes.as_array().unwrap().len(), 3);
+    }
+}
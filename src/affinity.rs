@@ -0,0 +1,174 @@
+//! Named groups of keys that are always routed together.
+//!
+//! Every key of an affinity group, such as all the data of one tenant, belongs to the
+//! owner of the group ID instead of the owner of the key. A group can also be pinned to
+//! a node, which moves it as a unit regardless of where its ID hashes to. Pinned groups
+//! fall back to hashing while their node is not in the ring.
+
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::hash::BuildHasher;
+use std::sync::Arc;
+
+use crate::{HashRing, Node};
+
+impl<'a, H, K> HashRing<'a, H, K>
+where
+    H: BuildHasher,
+    K: BuildHasher,
+{
+    /// Registers an affinity group, routed by hashing its ID.
+    ///
+    /// # Arguments
+    ///
+    /// * `group` - The ID of the group.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<(), Box<dyn Error>>` - An error if the group is already registered.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use std::sync::Arc;
+    /// use hashring::{HashRing, Config, Node};
+    ///
+    /// #[derive(Debug)]
+    /// struct MyNode<'a> {
+    ///     name: &'a str,
+    /// }
+    ///
+    /// impl<'a> Node<'a> for MyNode<'a> {
+    ///     fn id(&self) -> &'a str {
+    ///         self.name
+    ///     }
+    /// }
+    ///
+    /// let mut hash_ring = HashRing::new(Config::default()).unwrap();
+    /// hash_ring.add_node(Arc::new(MyNode { name: "node1" })).unwrap();
+    /// hash_ring.add_node(Arc::new(MyNode { name: "node2" })).unwrap();
+    ///
+    /// hash_ring.add_group("tenant-42").unwrap();
+    /// hash_ring.move_group("tenant-42", "node2").unwrap();
+    /// assert_eq!(hash_ring.get_group("tenant-42").unwrap().id(), "node2");
+    /// ```
+    pub fn add_group(&mut self, group: &str) -> Result<(), Box<dyn Error>> {
+        let mut groups = self.groups.write()?;
+        if groups.contains_key(group) {
+            return Err(format!("group {} already exist", group).into());
+        }
+        groups.insert(group.to_string(), None);
+        drop(groups);
+        self.bump_version();
+        Ok(())
+    }
+
+    /// Unregisters an affinity group.
+    pub fn remove_group(&mut self, group: &str) -> Result<(), Box<dyn Error>> {
+        let mut groups = self.groups.write()?;
+        groups.remove(group).ok_or_else(|| format!("group {} not found", group))?;
+        drop(groups);
+        self.bump_version();
+        Ok(())
+    }
+
+    /// Retrieves the node responsible for every key of the group.
+    pub fn get_group(&self, group: &str) -> Option<Arc<dyn Node<'a> + 'a>> {
+        let pinned = self.groups.read().ok()?.get(group)?.clone();
+        if let Some(id) = pinned {
+            if let Some(node) = self.nodes.read().ok()?.get(&id) {
+                return Some(node.clone());
+            }
+        }
+        self.get_key(group.as_bytes())
+    }
+
+    /// Moves a whole group to the given node, pinning it there.
+    ///
+    /// # Arguments
+    ///
+    /// * `group` - The ID of the group.
+    /// * `node_id` - The ID of the node that takes over the group.
+    pub fn move_group(&mut self, group: &str, node_id: &str) -> Result<(), Box<dyn Error>> {
+        if !self.nodes.read()?.contains_key(node_id) {
+            return Err("node not found".into());
+        }
+        let mut groups = self.groups.write()?;
+        let pinned = groups.get_mut(group).ok_or_else(|| format!("group {} not found", group))?;
+        *pinned = Some(node_id.to_string());
+        drop(groups);
+
+        self.bump_version();
+        Ok(())
+    }
+
+    /// Routes the group by hashing its ID again.
+    pub fn unpin_group(&mut self, group: &str) -> Result<(), Box<dyn Error>> {
+        let mut groups = self.groups.write()?;
+        let pinned = groups.get_mut(group).ok_or_else(|| format!("group {} not found", group))?;
+        if pinned.take().is_some() {
+            drop(groups);
+            self.bump_version();
+        }
+        Ok(())
+    }
+
+    /// Returns the node responsible for every registered group, by ID.
+    ///
+    /// Groups are left out while the ring has no nodes.
+    pub fn group_owners(&self) -> BTreeMap<String, String> {
+        let names: Vec<String> = self.groups.wait_read().keys().cloned().collect();
+        names
+            .into_iter()
+            .filter_map(|group| {
+                let owner = self.get_group(&group)?;
+                Some((group, owner.id().to_string()))
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use crate::tests::test_nodes;
+    use crate::{Config, HashRing, Node};
+
+    #[test]
+    fn test_groups_move_as_a_unit() {
+        let nodes = test_nodes();
+        let mut hash_ring = HashRing::new(Config::default()).unwrap();
+        for node in &nodes {
+            hash_ring.add_node(node.clone()).unwrap();
+        }
+        let version = hash_ring.version();
+        hash_ring.add_group("tenant-1").unwrap();
+        hash_ring.add_group("tenant-2").unwrap();
+        assert_eq!(hash_ring.version(), version + 2);
+        assert!(hash_ring.add_group("tenant-1").is_err());
+        assert!(hash_ring.get_group("unknown").is_none());
+        assert_eq!(hash_ring.get_group("tenant-1").unwrap().id(), hash_ring.get_key(b"tenant-1").unwrap().id());
+
+        let target = if hash_ring.get_group("tenant-1").unwrap().id() == "node1" { "node2" } else { "node1" };
+        let version = hash_ring.version();
+        hash_ring.move_group("tenant-1", target).unwrap();
+        assert!(hash_ring.version() > version);
+        assert!(hash_ring.move_group("tenant-1", "unknown").is_err());
+        assert_eq!(hash_ring.group_owners().get("tenant-1").map(String::as_str), Some(target));
+
+        // Pinned groups survive a snapshot, and fall back to hashing without their node.
+        let mut restored = HashRing::new(Config::default()).unwrap();
+        restored
+            .restore(&hash_ring.snapshot(), |id| nodes.iter().find(|n| n.id() == id).map(|n| n.clone() as Arc<dyn Node<'static>>))
+            .unwrap();
+        assert_eq!(restored.group_owners(), hash_ring.group_owners());
+
+        hash_ring.remove_node(target).unwrap();
+        assert_eq!(hash_ring.get_group("tenant-1").unwrap().id(), hash_ring.get_key(b"tenant-1").unwrap().id());
+        let version = hash_ring.version();
+        hash_ring.remove_group("tenant-1").unwrap();
+        assert!(hash_ring.version() > version);
+        assert_eq!(hash_ring.group_owners().len(), 1);
+    }
+}
@@ -0,0 +1,313 @@
+//! AnchorHash, from "AnchorHash: A Scalable Consistent Hash" (Mendelson et al.).
+//!
+//! The ring is sized for a fixed number of buckets, the anchor, of which any subset
+//! is working. A key hashes to a bucket of the anchor. If that bucket was removed,
+//! the key is rehashed among the buckets that were working when it was removed,
+//! which only ever moves keys away from removed buckets. Lookups take expected O(1)
+//! time and the state is four integer arrays the size of the anchor.
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::hash::{BuildHasher, Hasher};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use crate::sync::RwLock;
+use crate::{Node, Ring, XxHash64Hasher};
+
+#[derive(Debug, Clone)]
+struct AnchorState<'a> {
+    /// The working set size when a bucket was removed, or `0` for working buckets.
+    a: Vec<usize>,
+    /// The bucket a removed bucket was replaced by in the working set.
+    k: Vec<usize>,
+    /// The working buckets, in their first `n` positions.
+    w: Vec<usize>,
+    /// The position of each bucket in `w`.
+    l: Vec<usize>,
+    /// The removed buckets, most recently removed last.
+    r: Vec<usize>,
+    /// The number of working buckets.
+    n: usize,
+    buckets: Vec<Option<Arc<dyn Node<'a> + 'a>>>,
+    ids: HashMap<String, usize>,
+}
+
+impl<'a> AnchorState<'a> {
+    fn new(capacity: usize) -> AnchorState<'a> {
+        AnchorState {
+            a: (0..capacity).collect(),
+            k: (0..capacity).collect(),
+            w: (0..capacity).collect(),
+            l: (0..capacity).collect(),
+            r: (0..capacity).rev().collect(),
+            n: 0,
+            buckets: vec![None; capacity],
+            ids: HashMap::new(),
+        }
+    }
+
+    fn add_bucket(&mut self) -> Option<usize> {
+        let b = self.r.pop()?;
+        self.a[b] = 0;
+        self.l[self.w[self.n]] = self.n;
+        self.w[self.l[b]] = b;
+        self.k[b] = b;
+        self.n += 1;
+        Some(b)
+    }
+
+    fn remove_bucket(&mut self, b: usize) {
+        self.r.push(b);
+        self.n -= 1;
+        self.a[b] = self.n;
+        let last = self.w[self.n];
+        self.w[self.l[b]] = last;
+        self.l[last] = self.l[b];
+        self.k[b] = last;
+    }
+
+    fn get_bucket<H: BuildHasher>(&self, hasher: &H, key: &[u8]) -> usize {
+        let capacity = self.a.len() as u64;
+        let mut b = (hash(hasher, key, None) % capacity) as usize;
+        while self.a[b] > 0 {
+            let mut h = (hash(hasher, key, Some(b)) % self.a[b] as u64) as usize;
+            while self.a[h] >= self.a[b] {
+                h = self.k[h];
+            }
+            b = h;
+        }
+        b
+    }
+}
+
+fn hash<H: BuildHasher>(hasher: &H, key: &[u8], bucket: Option<usize>) -> u64 {
+    let mut hasher = hasher.build_hasher();
+    hasher.write(key);
+    if let Some(bucket) = bucket {
+        hasher.write(&(bucket as u64).to_le_bytes());
+    }
+    hasher.finish()
+}
+
+/// A ring placing keys with AnchorHash.
+#[derive(Debug, Clone)]
+pub struct AnchorRing<'a, H = XxHash64Hasher> {
+    hasher: H,
+    state: Arc<RwLock<AnchorState<'a>>>,
+    version: Arc<AtomicU64>,
+}
+
+impl<'a> AnchorRing<'a, XxHash64Hasher> {
+    /// Creates an empty `AnchorRing` able to hold up to `capacity` nodes.
+    pub fn new(capacity: usize) -> Result<AnchorRing<'a, XxHash64Hasher>, Box<dyn Error>> {
+        AnchorRing::with_hasher(capacity, XxHash64Hasher::default())
+    }
+}
+
+impl<'a, H> AnchorRing<'a, H>
+where
+    H: BuildHasher,
+{
+    /// Creates an empty `AnchorRing` able to hold up to `capacity` nodes, with the specified hasher.
+    ///
+    /// The capacity can't be changed later, and lookups stay fast as long as the
+    /// number of nodes is not tiny compared to it. A capacity of a few times the
+    /// expected cluster size is typical.
+    pub fn with_hasher(capacity: usize, hasher: H) -> Result<AnchorRing<'a, H>, Box<dyn Error>> {
+        if capacity == 0 {
+            return Err("capacity must be greater than 0".into());
+        }
+        Ok(AnchorRing {
+            hasher,
+            state: Arc::new(RwLock::new(AnchorState::new(capacity), &Arc::default())),
+            version: Arc::new(AtomicU64::new(0)),
+        })
+    }
+
+    /// Adds a node, reusing the most recently removed bucket.
+    ///
+    /// Removing a node and adding one back restores the previous placement of every key.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use std::sync::Arc;
+    /// use hashring::{AnchorRing, Node};
+    ///
+    /// #[derive(Debug)]
+    /// struct MyNode<'a> {
+    ///     name: &'a str,
+    /// }
+    ///
+    /// impl<'a> Node<'a> for MyNode<'a> {
+    ///     fn id(&self) -> &'a str {
+    ///         self.name
+    ///     }
+    /// }
+    ///
+    /// let mut ring = AnchorRing::new(64).unwrap();
+    /// ring.add_node(Arc::new(MyNode { name: "node1" })).unwrap();
+    /// ring.add_node(Arc::new(MyNode { name: "node2" })).unwrap();
+    ///
+    /// let node = ring.get_key(b"some_key").unwrap();
+    /// println!("some_key belongs to {}", node.id());
+    /// ```
+    pub fn add_node(&mut self, node: Arc<dyn Node<'a> + 'a>) -> Result<Arc<dyn Node<'a> + 'a>, Box<dyn Error>> {
+        let mut state = self.state.wait_write();
+        if state.ids.contains_key(node.id()) {
+            return Err("node already exist".into());
+        }
+        let b = state.add_bucket().ok_or("anchor is full")?;
+        state.buckets[b] = Some(node.clone());
+        state.ids.insert(node.id().to_string(), b);
+        self.version.fetch_add(1, Ordering::Release);
+        Ok(node)
+    }
+
+    /// Removes a node. Only keys of the removed node move.
+    pub fn remove_node(&mut self, id: &str) -> Result<(), Box<dyn Error>> {
+        let mut state = self.state.wait_write();
+        let b = *state.ids.get(id).ok_or("node not found")?;
+        state.ids.remove(id);
+        state.buckets[b] = None;
+        state.remove_bucket(b);
+        self.version.fetch_add(1, Ordering::Release);
+        Ok(())
+    }
+
+    /// Retrieves the node responsible for the given key.
+    pub fn get_key(&self, key: &[u8]) -> Option<Arc<dyn Node<'a> + 'a>> {
+        let state = self.state.wait_read();
+        if state.n == 0 {
+            return None;
+        }
+        let b = state.get_bucket(&self.hasher, key);
+        state.buckets[b].clone()
+    }
+
+    /// Retrieves up to `n` distinct nodes for the given key.
+    ///
+    /// The second node is where the key would move if the first were removed, and so on,
+    /// so the list matches the failover order of the ring.
+    pub fn get_nodes(&self, key: &[u8], n: usize) -> Vec<Arc<dyn Node<'a> + 'a>> {
+        let state = self.state.wait_read();
+        let mut nodes = Vec::new();
+        if state.n == 0 || n == 0 {
+            return nodes;
+        }
+
+        let b = state.get_bucket(&self.hasher, key);
+        nodes.extend(state.buckets[b].clone());
+        if n > 1 && state.n > 1 {
+            let mut scratch = state.clone();
+            scratch.remove_bucket(b);
+            while nodes.len() < n && scratch.n > 0 {
+                let b = scratch.get_bucket(&self.hasher, key);
+                nodes.extend(scratch.buckets[b].clone());
+                scratch.remove_bucket(b);
+            }
+        }
+        nodes
+    }
+
+    /// Returns the number of nodes.
+    pub fn len(&self) -> usize {
+        self.state.wait_read().n
+    }
+
+    /// Returns `true` if the ring has no nodes.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the maximum number of nodes.
+    pub fn capacity(&self) -> usize {
+        self.state.wait_read().a.len()
+    }
+}
+
+impl<'a, H> Ring<'a> for AnchorRing<'a, H>
+where
+    H: BuildHasher,
+{
+    fn lookup(&self, key: &[u8]) -> Option<Arc<dyn Node<'a> + 'a>> {
+        self.get_key(key)
+    }
+
+    fn preference_list(&self, key: &[u8], n: usize) -> Vec<Arc<dyn Node<'a> + 'a>> {
+        self.get_nodes(key, n)
+    }
+
+    fn members(&self) -> Vec<Arc<dyn Node<'a> + 'a>> {
+        let state = self.state.wait_read();
+        let mut members: Vec<Arc<dyn Node<'a> + 'a>> = state.buckets.iter().flatten().cloned().collect();
+        members.sort_by(|a, b| a.id().cmp(b.id()));
+        members
+    }
+
+    fn version(&self) -> u64 {
+        self.version.load(Ordering::Acquire)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use crate::tests::test_nodes;
+    use crate::{AnchorRing, Ring};
+
+    fn owners(ring: &AnchorRing<'static>) -> Vec<&'static str> {
+        (0..3000).map(|i| ring.get_key(format!("key-{}", i).as_bytes()).unwrap().id()).collect()
+    }
+
+    #[test]
+    fn test_anchor_balance_and_minimal_disruption() {
+        let mut ring = AnchorRing::new(16).unwrap();
+        assert!(ring.get_key(b"some_key").is_none());
+        for node in test_nodes() {
+            ring.add_node(node).unwrap();
+        }
+
+        let before = owners(&ring);
+        let mut counts: HashMap<&str, usize> = HashMap::new();
+        for owner in &before {
+            *counts.entry(owner).or_insert(0) += 1;
+        }
+        assert!(counts.values().all(|c| *c > 800 && *c < 1200), "{:?}", counts);
+
+        ring.remove_node("node2").unwrap();
+        let after = owners(&ring);
+        for (b, a) in before.iter().zip(after.iter()) {
+            assert_ne!(*a, "node2");
+            if *b != "node2" {
+                assert_eq!(a, b);
+            }
+        }
+
+        ring.add_node(test_nodes()[1].clone()).unwrap();
+        assert_eq!(owners(&ring), before);
+    }
+
+    #[test]
+    fn test_anchor_preference_list_follows_failover() {
+        let mut ring = AnchorRing::new(4).unwrap();
+        for node in test_nodes() {
+            ring.add_node(node).unwrap();
+        }
+        assert_eq!(ring.members().len(), 3);
+
+        let nodes = ring.get_nodes(b"some_key", 3);
+        assert_eq!(nodes.len(), 3);
+        ring.remove_node(nodes[0].id()).unwrap();
+        assert_eq!(ring.get_key(b"some_key").unwrap().id(), nodes[1].id());
+
+        ring.add_node(nodes[0].clone()).unwrap();
+        assert_eq!(ring.get_key(b"some_key").unwrap().id(), nodes[0].id());
+
+        let mut full = AnchorRing::new(1).unwrap();
+        full.add_node(test_nodes()[0].clone()).unwrap();
+        assert!(full.add_node(test_nodes()[1].clone()).is_err());
+    }
+}
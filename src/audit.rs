@@ -0,0 +1,162 @@
+//! Statistics on how evenly virtual nodes are spread over the ring.
+//!
+//! Virtual nodes are placed by hashing, so an unlucky set of node IDs can cluster them
+//! and leave one node with a long arc of the keyspace. The audit measures the gaps
+//! between consecutive virtual nodes and the longest run of the ring owned by a single
+//! node. With the `tracing` feature, `add_node` reports the same figures as a debug event.
+
+use std::collections::BTreeMap;
+use std::hash::BuildHasher;
+use std::sync::Arc;
+
+use crate::{HashRing, Node};
+
+/// The spacing of virtual nodes on a ring.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RingAudit {
+    pub vnode_count: usize,
+    /// The smallest distance between two consecutive virtual nodes.
+    pub min_gap: u64,
+    /// The largest distance between two consecutive virtual nodes, saturating at
+    /// `u64::MAX` for a ring with a single virtual node.
+    pub max_gap: u64,
+    /// The node owning the longest contiguous arc of the ring, which may span several of
+    /// its virtual nodes.
+    pub largest_arc_node: Option<String>,
+    /// The length of that arc, as a fraction of the keyspace.
+    pub largest_arc_fraction: f64,
+}
+
+impl RingAudit {
+    /// Returns the ratio between the largest gap and the gap of a perfectly even ring.
+    pub fn max_gap_ratio(&self) -> f64 {
+        if self.vnode_count == 0 {
+            return 1.0;
+        }
+        self.max_gap as f64 / (KEYSPACE / self.vnode_count as f64)
+    }
+}
+
+/// The number of points on the ring.
+const KEYSPACE: f64 = u64::MAX as f64 + 1.0;
+
+/// Audits the virtual nodes of a ring, given by position.
+pub(crate) fn audit_vnodes<'a>(sorted_set: &BTreeMap<u64, Arc<dyn Node<'a> + 'a>>) -> RingAudit {
+    let mut audit = RingAudit {
+        vnode_count: sorted_set.len(),
+        min_gap: 0,
+        max_gap: 0,
+        largest_arc_node: None,
+        largest_arc_fraction: 0.0,
+    };
+    let (first, last) = match (sorted_set.iter().next(), sorted_set.iter().next_back()) {
+        (Some(first), Some(last)) => (first, last),
+        _ => return audit,
+    };
+    if sorted_set.len() == 1 {
+        audit.min_gap = u64::MAX;
+        audit.max_gap = u64::MAX;
+        audit.largest_arc_node = Some(first.1.id().to_string());
+        audit.largest_arc_fraction = 1.0;
+        return audit;
+    }
+
+    // The arc of a virtual node runs from its predecessor, exclusive, to itself.
+    let arcs = std::iter::once((first.1, first.0.wrapping_sub(*last.0)))
+        .chain(sorted_set.iter().zip(sorted_set.iter().skip(1)).map(|((prev, _), (point, node))| (node, point - prev)));
+    audit.min_gap = u64::MAX;
+    let mut runs: Vec<(&str, f64)> = Vec::new();
+    for (node, gap) in arcs {
+        audit.min_gap = audit.min_gap.min(gap);
+        audit.max_gap = audit.max_gap.max(gap);
+        match runs.last_mut() {
+            Some((id, length)) if *id == node.id() => *length += gap as f64,
+            _ => runs.push((node.id(), gap as f64)),
+        }
+    }
+    // The first run continues the last one when both belong to the same node.
+    if runs.len() > 1 && runs[0].0 == runs[runs.len() - 1].0 {
+        let (_, length) = runs.pop().unwrap();
+        runs[0].1 += length;
+    }
+    if let Some((id, length)) = runs.iter().max_by(|a, b| a.1.total_cmp(&b.1)) {
+        audit.largest_arc_node = Some(id.to_string());
+        audit.largest_arc_fraction = length / KEYSPACE;
+    }
+    audit
+}
+
+impl<'a, H, K> HashRing<'a, H, K>
+where
+    H: BuildHasher,
+    K: BuildHasher,
+{
+    /// Measures how evenly the virtual nodes are spread over the ring.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use std::sync::Arc;
+    /// use hashring::{HashRing, Config, Node};
+    ///
+    /// #[derive(Debug)]
+    /// struct MyNode<'a> {
+    ///     name: &'a str,
+    /// }
+    ///
+    /// impl<'a> Node<'a> for MyNode<'a> {
+    ///     fn id(&self) -> &'a str {
+    ///         self.name
+    ///     }
+    /// }
+    ///
+    /// let mut hash_ring = HashRing::new(Config::default()).unwrap();
+    /// hash_ring.add_node(Arc::new(MyNode { name: "node1" })).unwrap();
+    /// hash_ring.add_node(Arc::new(MyNode { name: "node2" })).unwrap();
+    ///
+    /// let audit = hash_ring.audit();
+    /// if audit.largest_arc_fraction > 0.25 {
+    ///     println!("{:?} owns {:.0}% of the keyspace in one arc", audit.largest_arc_node, audit.largest_arc_fraction * 100.0);
+    /// }
+    /// ```
+    pub fn audit(&self) -> RingAudit {
+        audit_vnodes(&self.sorted_nodes_hash_set.wait_read())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+    use std::sync::Arc;
+
+    use super::audit_vnodes;
+    use crate::tests::{test_nodes, TestNode};
+    use crate::{Config, HashRing, Node};
+
+    #[test]
+    fn test_audit_measures_gaps_and_arcs() {
+        let hash_ring = HashRing::new(Config::default()).unwrap();
+        assert_eq!(hash_ring.audit().vnode_count, 0);
+
+        let nodes = test_nodes();
+        let quarter = 1u64 << 62;
+        let mut sorted_set: BTreeMap<u64, Arc<dyn Node<'static>>> = BTreeMap::new();
+        sorted_set.insert(quarter, nodes[0].clone());
+        sorted_set.insert(2 * quarter, nodes[1].clone());
+        sorted_set.insert(2 * quarter + 16, nodes[2].clone());
+        sorted_set.insert(3 * quarter, nodes[0].clone());
+        let audit = audit_vnodes(&sorted_set);
+        assert_eq!(audit.min_gap, 16);
+        assert_eq!(audit.max_gap, 2 * quarter);
+        assert_eq!(audit.max_gap_ratio(), 2.0);
+        // node1 owns the arc from just after node3 at 2/4 to its virtual node at 1/4,
+        // wrapping around through its other virtual node at 3/4.
+        assert_eq!(audit.largest_arc_node.as_deref(), Some("node1"));
+        assert_eq!(audit.largest_arc_fraction, (3 * quarter - 16) as f64 / 2f64.powi(64));
+
+        let mut hash_ring = HashRing::new(Config::default()).unwrap();
+        hash_ring.add_node(Arc::new(TestNode { ip_addr: "127.0.0.1".to_string(), name: "node1" })).unwrap();
+        assert_eq!(hash_ring.audit().vnode_count, 20);
+        assert!((hash_ring.audit().largest_arc_fraction - 1.0).abs() < 1e-9);
+    }
+}
@@ -0,0 +1,237 @@
+//! A tower load balancer dispatching requests by consistent hashing, behind the `tower` feature.
+//!
+//! `ConsistentHashBalance` keeps one inner service per ring node and sends every request
+//! to the service of the node owning its key. Services are created and dropped as nodes
+//! join and leave, replaying the ring's changelog when it is enabled and comparing
+//! members otherwise. Requests are routed by the ring as of the last `poll_ready`, so
+//! a change made in between can't send one to a node without a ready service.
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::future::Future;
+use std::hash::BuildHasher;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use tower::Service;
+
+use crate::{Change, FrozenRing, HashRing, Node, XxHash64Hasher};
+
+/// The error of a `ConsistentHashBalance`: either the inner service's or a routing error.
+pub type BoxError = Box<dyn Error + Send + Sync>;
+
+/// The function creating the service of a node.
+type MakeService<S> = Box<dyn Fn(&Arc<dyn Node<'static>>) -> S + Send + Sync>;
+
+/// The function extracting the routing key of a request.
+type KeyFn<Req> = Box<dyn Fn(&Req) -> Vec<u8> + Send + Sync>;
+
+/// A service dispatching requests to per-node services by the key of each request.
+pub struct ConsistentHashBalance<S, Req, H = XxHash64Hasher, K = H> {
+    hash_ring: HashRing<'static, H, K>,
+    make_service: MakeService<S>,
+    key: KeyFn<Req>,
+    services: HashMap<String, S>,
+    /// The view of the ring the services were last synced to.
+    routing: FrozenRing<'static, K>,
+}
+
+impl<S, Req, H, K> ConsistentHashBalance<S, Req, H, K>
+where
+    H: BuildHasher,
+    K: BuildHasher + Clone,
+{
+    /// Creates a balancer over the nodes of the ring.
+    ///
+    /// # Arguments
+    ///
+    /// * `hash_ring` - The ring routing keys to nodes. Changes made through handles sharing its state, made with `share`, are picked up.
+    /// * `make_service` - Creates the service of a node.
+    /// * `key` - Extracts the routing key of a request.
+    pub fn new<M, F>(hash_ring: HashRing<'static, H, K>, make_service: M, key: F) -> ConsistentHashBalance<S, Req, H, K>
+    where
+        M: Fn(&Arc<dyn Node<'static>>) -> S + Send + Sync + 'static,
+        F: Fn(&Req) -> Vec<u8> + Send + Sync + 'static,
+    {
+        let routing = hash_ring.freeze();
+        let services = routing.nodes().iter().map(|node| (node.id().to_string(), make_service(node))).collect();
+        ConsistentHashBalance {
+            hash_ring,
+            make_service: Box::new(make_service),
+            key: Box::new(key),
+            services,
+            routing,
+        }
+    }
+
+    /// Returns the ring routing keys to nodes.
+    pub fn ring(&self) -> &HashRing<'static, H, K> {
+        &self.hash_ring
+    }
+
+    /// Returns the number of inner services.
+    pub fn len(&self) -> usize {
+        self.services.len()
+    }
+
+    /// Returns `true` if there are no inner services.
+    pub fn is_empty(&self) -> bool {
+        self.services.is_empty()
+    }
+
+    /// Creates and drops inner services to match the members of the ring.
+    fn sync(&mut self) {
+        if self.hash_ring.version() == self.routing.version() {
+            return;
+        }
+        let routing = self.hash_ring.freeze();
+        let nodes = routing.nodes();
+        let find = |id: &str| nodes.binary_search_by(|node| node.id().cmp(id)).ok().map(|idx| &nodes[idx]);
+        match self.hash_ring.changelog(self.routing.version()) {
+            Some(entries) => {
+                // Changes made after the ring was frozen are picked up by the next sync.
+                let entries = entries.into_iter().filter(|entry| entry.version <= routing.version());
+                for change in entries.flat_map(|entry| entry.changes) {
+                    match change {
+                        Change::NodeAdded(id) => {
+                            if let Some(node) = find(&id) {
+                                self.services.insert(id, (self.make_service)(node));
+                            }
+                        }
+                        Change::NodeRemoved(id) => {
+                            self.services.remove(&id);
+                        }
+                        _ => {}
+                    }
+                }
+            }
+            None => {
+                self.services.retain(|id, _| find(id).is_some());
+                for node in nodes {
+                    if !self.services.contains_key(node.id()) {
+                        self.services.insert(node.id().to_string(), (self.make_service)(node));
+                    }
+                }
+            }
+        }
+        self.routing = routing;
+    }
+}
+
+impl<S, Req, H, K> Service<Req> for ConsistentHashBalance<S, Req, H, K>
+where
+    S: Service<Req>,
+    S::Error: Into<BoxError>,
+    S::Future: Send + 'static,
+    H: BuildHasher,
+    K: BuildHasher + Clone,
+{
+    type Response = S::Response;
+    type Error = BoxError;
+    type Future = Pin<Box<dyn Future<Output = Result<S::Response, BoxError>> + Send>>;
+
+    /// Ready once every inner service is, since the target of the next request is not known yet.
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), BoxError>> {
+        self.sync();
+        for service in self.services.values_mut() {
+            match service.poll_ready(cx) {
+                Poll::Ready(Ok(())) => {}
+                Poll::Ready(Err(err)) => return Poll::Ready(Err(err.into())),
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, request: Req) -> Self::Future {
+        let service = self.routing.get_key(&(self.key)(&request)).and_then(|node| self.services.get_mut(node.id()));
+        match service {
+            Some(service) => {
+                let future = service.call(request);
+                Box::pin(async move { future.await.map_err(Into::into) })
+            }
+            None => Box::pin(async { Err("no service for the key".into()) }),
+        }
+    }
+}
+
+impl<S, Req, H, K> fmt::Debug for ConsistentHashBalance<S, Req, H, K>
+where
+    H: fmt::Debug,
+    K: fmt::Debug,
+{
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt.debug_struct("ConsistentHashBalance")
+            .field("hash_ring", &self.hash_ring)
+            .field("services", &self.services.keys().collect::<Vec<_>>())
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::future::{poll_fn, Ready};
+    use std::task::{Context, Poll};
+
+    use tower::Service;
+
+    use crate::tests::test_nodes;
+    use crate::{Config, ConsistentHashBalance, HashRing};
+
+    /// Answers every request with the ID of its node.
+    struct Named(String);
+
+    impl Service<String> for Named {
+        type Response = String;
+        type Error = std::convert::Infallible;
+        type Future = Ready<Result<String, Self::Error>>;
+
+        fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn call(&mut self, _request: String) -> Self::Future {
+            std::future::ready(Ok(self.0.clone()))
+        }
+    }
+
+    #[test]
+    fn test_services_follow_membership() {
+        for capacity in [0, 16] {
+            let mut hash_ring = HashRing::new(Config::default()).unwrap();
+            hash_ring.set_changelog_capacity(capacity);
+            let nodes = test_nodes();
+            hash_ring.add_node(nodes[0].clone()).unwrap();
+
+            let mut balance: ConsistentHashBalance<String, String> = ConsistentHashBalance::new(hash_ring.share(), |node| node.id().to_string(), |request: &String| request.as_bytes().to_vec());
+            assert_eq!(balance.len(), 1);
+            hash_ring.add_node(nodes[1].clone()).unwrap();
+            hash_ring.add_node(nodes[2].clone()).unwrap();
+            hash_ring.remove_node("node1").unwrap();
+            balance.sync();
+
+            let mut ids: Vec<&String> = balance.services.values().collect();
+            ids.sort();
+            assert_eq!(ids, ["node2", "node3"]);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_ring_change_between_ready_and_call() {
+        let mut hash_ring = HashRing::new(Config::default()).unwrap();
+        let nodes = test_nodes();
+        hash_ring.add_node(nodes[0].clone()).unwrap();
+        let mut balance = ConsistentHashBalance::new(hash_ring.share(), |node| Named(node.id().to_string()), |request: &String| request.as_bytes().to_vec());
+
+        poll_fn(|cx| balance.poll_ready(cx)).await.unwrap();
+        hash_ring.add_node(nodes[1].clone()).unwrap();
+        hash_ring.remove_node("node1").unwrap();
+        // The request goes where the ring routed it when the balancer became ready.
+        assert_eq!(balance.call("some_key".to_string()).await.unwrap(), "node1");
+
+        poll_fn(|cx| balance.poll_ready(cx)).await.unwrap();
+        assert_eq!(balance.call("some_key".to_string()).await.unwrap(), "node2");
+    }
+}
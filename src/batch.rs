@@ -0,0 +1,141 @@
+//! Bulk lookups for multi-key operations.
+//!
+//! A multi-get or multi-put sends one request per destination node. Calling `get_key` in
+//! a loop takes the ring's read locks once per key; `group_keys_by_node` takes them once
+//! for the whole batch and returns the keys already grouped by destination.
+
+use std::collections::HashMap;
+use std::hash::BuildHasher;
+
+use crate::{canary, util, HashRing, NodeId};
+
+impl<'a, H, K> HashRing<'a, H, K>
+where
+    H: BuildHasher,
+    K: BuildHasher,
+{
+    /// Groups keys by the node `get_key` would route each of them to.
+    ///
+    /// The ring is read under a single acquisition of its locks, so every key is routed
+    /// against the same membership even if nodes are added concurrently.
+    ///
+    /// # Arguments
+    ///
+    /// * `keys` - The keys to route.
+    ///
+    /// # Returns
+    ///
+    /// * `HashMap<NodeId, Vec<&'k [u8]>>` - The keys of each node, in the order they were
+    ///   given. Empty if the ring has no nodes.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use std::sync::Arc;
+    /// use hashring::{HashRing, Config, Node};
+    ///
+    /// #[derive(Debug)]
+    /// struct MyNode<'a> {
+    ///     name: &'a str,
+    /// }
+    ///
+    /// impl<'a> Node<'a> for MyNode<'a> {
+    ///     fn id(&self) -> &'a str {
+    ///         self.name
+    ///     }
+    /// }
+    ///
+    /// let mut hash_ring = HashRing::new(Config::default()).unwrap();
+    /// hash_ring.add_node(Arc::new(MyNode { name: "node1" })).unwrap();
+    /// hash_ring.add_node(Arc::new(MyNode { name: "node2" })).unwrap();
+    ///
+    /// let keys: [&[u8]; 3] = [b"user:1", b"user:2", b"user:3"];
+    /// for (node_id, keys) in hash_ring.group_keys_by_node(keys.into_iter()) {
+    ///     println!("MGET on {}: {} keys", node_id, keys.len());
+    /// }
+    /// ```
+    pub fn group_keys_by_node<'k>(&self, keys: impl Iterator<Item = &'k [u8]>) -> HashMap<NodeId, Vec<&'k [u8]>> {
+        let mut groups: HashMap<NodeId, Vec<&'k [u8]>> = HashMap::new();
+        let config = self.config();
+        // Locks are taken in the order the mutators take them.
+        let nodes = self.nodes.wait_read();
+        let sorted_set = self.sorted_nodes_hash_set.wait_read();
+        let first = match sorted_set.values().next() {
+            Some(first) => first,
+            None => return groups,
+        };
+        let routes = self.routes.wait_read();
+
+        for key in keys {
+            let hash = util::hash_key(&self.key_hasher, &config, key);
+            let point = routes.point(config.partition_count, &sorted_set, hash);
+            let owner = sorted_set.range(point..).next().map(|(_, node)| node).unwrap_or(first);
+            let id = match canary::split_target(&routes.traffic_splits, owner.id(), hash) {
+                Some(canary) if nodes.contains_key(canary) => canary,
+                _ => owner.id(),
+            };
+            match groups.get_mut(id) {
+                Some(group) => group.push(key),
+                None => {
+                    groups.insert(NodeId::from_member(id), vec![key]);
+                }
+            }
+        }
+        groups
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::tests::test_nodes;
+    use crate::{Config, HashRing};
+
+    #[test]
+    fn test_group_keys_by_node_matches_get_key() {
+        let mut hash_ring = HashRing::new(Config::default()).unwrap();
+        let keys: Vec<String> = (0..500).map(|i| format!("key-{}", i)).collect();
+        assert!(hash_ring.group_keys_by_node(keys.iter().map(|key| key.as_bytes())).is_empty());
+
+        for node in test_nodes() {
+            hash_ring.add_node(node).unwrap();
+        }
+        hash_ring.set_traffic_split("node1", "node2", 0.5).unwrap();
+        let groups = hash_ring.group_keys_by_node(keys.iter().map(|key| key.as_bytes()));
+        assert_eq!(groups.len(), 3);
+        assert_eq!(groups.values().map(Vec::len).sum::<usize>(), keys.len());
+        for (id, group) in &groups {
+            assert!(group.iter().all(|key| hash_ring.get_key(key).unwrap().id() == id.as_str()));
+        }
+        // Keys keep their relative order within a group.
+        let node1: Vec<&[u8]> = keys.iter().map(|key| key.as_bytes()).filter(|key| hash_ring.get_key(key).unwrap().id() == "node1").collect();
+        assert_eq!(groups["node1"], node1);
+    }
+
+    #[test]
+    fn test_group_keys_by_node_during_membership_changes() {
+        let nodes = test_nodes();
+        let mut hash_ring = HashRing::new(Config::default()).unwrap();
+        for node in nodes.iter() {
+            hash_ring.add_node(node.clone()).unwrap();
+        }
+        hash_ring.set_traffic_split("node1", "node2", 0.5).unwrap();
+        let keys: Vec<String> = (0..100).map(|i| format!("key-{}", i)).collect();
+
+        // Lookups take the locks in the order membership changes do, so neither side blocks for good.
+        let mut writer = hash_ring.share();
+        std::thread::scope(|scope| {
+            scope.spawn(|| {
+                for _ in 0..200 {
+                    writer.remove_node("node3").unwrap();
+                    writer.add_node(nodes[2].clone()).unwrap();
+                }
+            });
+            for _ in 0..200 {
+                let groups = hash_ring.group_keys_by_node(keys.iter().map(|key| key.as_bytes()));
+                assert_eq!(groups.values().map(Vec::len).sum::<usize>(), keys.len());
+                assert!(hash_ring.get_key(b"some_key").is_some());
+                assert!(hash_ring.get_key_id(b"some_key").is_some());
+            }
+        });
+    }
+}
@@ -0,0 +1,124 @@
+//! Inspect a ring definition file from the command line.
+//!
+//! The file is a snapshot exported with `to_json`, `to_toml` or `save_to_path`,
+//! selected by its extension (`.json`, `.toml`, anything else is binary).
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::error::Error;
+use std::path::Path;
+use std::process;
+use std::sync::Arc;
+
+use hashring::{HashRing, Node, RingSnapshot};
+
+const USAGE: &str = "usage: hashring-cli <ring-file> <command>
+
+commands:
+    lookup <key>         print the node responsible for the key
+    owners <key>         print the preference list for the key
+    stats                print virtual node and partition counts per node
+    diff <other-file>    print membership and partition changes between two rings";
+
+#[derive(Debug)]
+struct CliNode {
+    id: &'static str,
+}
+
+impl Node<'static> for CliNode {
+    fn id(&self) -> &'static str {
+        self.id
+    }
+}
+
+fn load_snapshot(path: &str) -> Result<RingSnapshot, Box<dyn Error>> {
+    match Path::new(path).extension().and_then(|ext| ext.to_str()) {
+        Some("json") => RingSnapshot::from_json(&std::fs::read_to_string(path)?),
+        Some("toml") => RingSnapshot::from_toml(&std::fs::read_to_string(path)?),
+        _ => RingSnapshot::load_from_path(path),
+    }
+}
+
+fn load_ring(path: &str) -> Result<HashRing<'static>, Box<dyn Error>> {
+    let snapshot = load_snapshot(path)?;
+    HashRing::from_snapshot(&snapshot, Default::default(), Default::default(), |id| {
+        // The process exits right after answering, so leaking the IDs is fine.
+        let id: &'static str = Box::leak(id.to_string().into_boxed_str());
+        Some(Arc::new(CliNode { id }) as Arc<dyn Node<'static>>)
+    })
+}
+
+fn partitions_per_node(snapshot: &RingSnapshot) -> BTreeMap<&str, usize> {
+    let mut counts: BTreeMap<&str, usize> = snapshot.nodes.iter().map(|id| (id.as_str(), 0)).collect();
+    for id in snapshot.partitions.values() {
+        *counts.entry(id.as_str()).or_insert(0) += 1;
+    }
+    counts
+}
+
+fn run(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let (path, command) = match args {
+        [path, command, ..] => (path.as_str(), command.as_str()),
+        _ => return Err(USAGE.into()),
+    };
+    let ring = load_ring(path)?;
+
+    match (command, args.get(2)) {
+        ("lookup", Some(key)) => match ring.get_key(key.as_bytes()) {
+            Some(node) => println!("{}", node),
+            None => return Err("ring has no nodes".into()),
+        },
+        ("owners", Some(key)) => {
+            for (i, replica) in ring.get_preference_list(key.as_bytes()).iter().enumerate() {
+                println!("{}\t{}", i, replica.node);
+            }
+        }
+        ("stats", None) => {
+            let snapshot = ring.snapshot();
+            let vnodes = ring.virtual_nodes_per_node();
+            println!("nodes: {}", snapshot.nodes.len());
+            println!("replication_factor: {}", snapshot.config.replication_factor);
+            println!("partition_count: {}", snapshot.config.partition_count);
+            println!();
+            println!("node\tvnodes\tpartitions");
+            for (id, partitions) in partitions_per_node(&snapshot) {
+                println!("{}\t{}\t{}", id, vnodes.get(id).copied().unwrap_or(0), partitions);
+            }
+        }
+        ("diff", Some(other)) => {
+            let before = ring.snapshot();
+            let after = load_ring(other)?.snapshot();
+
+            let before_nodes: BTreeSet<&String> = before.nodes.iter().collect();
+            let after_nodes: BTreeSet<&String> = after.nodes.iter().collect();
+            for id in after_nodes.difference(&before_nodes) {
+                println!("+ node {}", id);
+            }
+            for id in before_nodes.difference(&after_nodes) {
+                println!("- node {}", id);
+            }
+
+            let part_ids: BTreeSet<&usize> = before.partitions.keys().chain(after.partitions.keys()).collect();
+            let mut moved = 0;
+            for part_id in part_ids {
+                let from = before.partitions.get(part_id).map(String::as_str).unwrap_or("-");
+                let to = after.partitions.get(part_id).map(String::as_str).unwrap_or("-");
+                if from != to {
+                    println!("~ partition {}: {} -> {}", part_id, from, to);
+                    moved += 1;
+                }
+            }
+            println!("{} of {} partitions moved", moved, after.config.partition_count);
+        }
+        _ => return Err(USAGE.into()),
+    }
+
+    Ok(())
+}
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if let Err(err) = run(&args) {
+        eprintln!("{}", err);
+        process::exit(1);
+    }
+}
@@ -0,0 +1,317 @@
+//! Browse a ring definition file interactively.
+//!
+//! The file is loaded like `hashring-cli` does. The left pane lists the nodes, the right
+//! pane shows details of the selected node or the result of the last command, and
+//! previews of adding or removing a node are computed on a copy of the ring, so the
+//! file is never modified.
+
+use std::error::Error;
+use std::io;
+use std::path::Path;
+use std::process;
+use std::sync::Arc;
+
+use crossterm::event::{self, Event, KeyCode, KeyEventKind};
+use crossterm::terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen};
+use crossterm::ExecutableCommand;
+use hashring::{HashRing, Node, RingSnapshot, ShadowedRing};
+use ratatui::backend::CrosstermBackend;
+use ratatui::layout::{Constraint, Direction, Layout};
+use ratatui::style::{Modifier, Style};
+use ratatui::text::Line;
+use ratatui::widgets::{Block, Borders, List, ListItem, ListState, Paragraph, Wrap};
+use ratatui::{Frame, Terminal};
+
+const USAGE: &str = "usage: hashring-tui <ring-file>";
+
+const HELP: &str = "up/down select   / search key   a preview add   d preview remove   q quit";
+
+#[derive(Debug)]
+struct TuiNode {
+    id: &'static str,
+}
+
+impl Node<'static> for TuiNode {
+    fn id(&self) -> &'static str {
+        self.id
+    }
+}
+
+/// Returns a node with the given ID. IDs are leaked, which is bounded by the number of
+/// nodes loaded and previewed during a session.
+fn tui_node(id: &str) -> Arc<dyn Node<'static>> {
+    let id: &'static str = Box::leak(id.to_string().into_boxed_str());
+    Arc::new(TuiNode { id })
+}
+
+fn load_snapshot(path: &str) -> Result<RingSnapshot, Box<dyn Error>> {
+    match Path::new(path).extension().and_then(|ext| ext.to_str()) {
+        Some("json") => RingSnapshot::from_json(&std::fs::read_to_string(path)?),
+        Some("toml") => RingSnapshot::from_toml(&std::fs::read_to_string(path)?),
+        _ => RingSnapshot::load_from_path(path),
+    }
+}
+
+fn build_ring(snapshot: &RingSnapshot) -> Result<HashRing<'static>, Box<dyn Error>> {
+    HashRing::from_snapshot(snapshot, Default::default(), Default::default(), |id| Some(tui_node(id)))
+}
+
+/// What the text typed in the footer is for.
+#[derive(Debug, Clone, Copy)]
+enum Prompt {
+    Search,
+    Add,
+}
+
+impl Prompt {
+    fn label(&self) -> &'static str {
+        match self {
+            Prompt::Search => "key",
+            Prompt::Add => "node to add",
+        }
+    }
+}
+
+struct App {
+    path: String,
+    ring: HashRing<'static>,
+    node_ids: Vec<String>,
+    selected: ListState,
+    input: Option<(Prompt, String)>,
+    output: Vec<String>,
+}
+
+impl App {
+    fn new(path: &str) -> Result<App, Box<dyn Error>> {
+        let snapshot = load_snapshot(path)?;
+        let ring = build_ring(&snapshot)?;
+        let mut node_ids = snapshot.nodes.clone();
+        node_ids.sort_unstable();
+        let mut app = App {
+            path: path.to_string(),
+            ring,
+            node_ids,
+            selected: ListState::default(),
+            input: None,
+            output: Vec::new(),
+        };
+        app.select(0);
+        Ok(app)
+    }
+
+    fn selected_id(&self) -> Option<&str> {
+        self.selected.selected().and_then(|idx| self.node_ids.get(idx)).map(String::as_str)
+    }
+
+    fn select(&mut self, idx: usize) {
+        if self.node_ids.is_empty() {
+            self.output = vec!["the ring has no nodes".to_string()];
+            return;
+        }
+        let idx = idx.min(self.node_ids.len() - 1);
+        self.selected.select(Some(idx));
+        let id = &self.node_ids[idx];
+
+        let report = self.ring.simulate_failure(&[id.as_str()]);
+        self.output = vec![
+            format!("node {}", id),
+            format!("virtual nodes: {}", self.ring.virtual_nodes_per_node().get(id).copied().unwrap_or(0)),
+            format!("partitions: {}", self.ring.partitions_of(id).count()),
+            format!("keyspace: {:.2}%", self.ring.keyspace_fraction(id) * 100.0),
+            String::new(),
+            "if it fails:".to_string(),
+            format!("  partitions losing their primary: {}", report.lost_primary.len()),
+            format!("  partitions losing quorum: {}", report.lost_quorum.len()),
+            format!("  partitions unavailable: {}", report.unavailable.len()),
+        ];
+    }
+
+    fn move_selection(&mut self, up: bool) {
+        let idx = self.selected.selected().unwrap_or(0);
+        let idx = if up { idx.saturating_sub(1) } else { idx + 1 };
+        self.select(idx);
+    }
+
+    fn search(&mut self, key: &str) {
+        let replicas = self.ring.get_preference_list(key.as_bytes());
+        let primary = match replicas.primary() {
+            Some(primary) => primary.node.id(),
+            None => {
+                self.output = vec!["the ring has no nodes".to_string()];
+                return;
+            }
+        };
+        self.output = vec![
+            format!("key {:?}", key),
+            format!("partition: {}", self.ring.get_partition(key.as_bytes())),
+            String::new(),
+            "preference list:".to_string(),
+        ];
+        for (i, replica) in replicas.iter().enumerate() {
+            self.output.push(format!("  {}  {}", i, replica.node));
+        }
+        if let Some(idx) = self.node_ids.iter().position(|id| id.as_str() == primary) {
+            self.selected.select(Some(idx));
+        }
+    }
+
+    fn preview(&mut self, action: &str, change: impl FnOnce(&mut HashRing<'static>) -> Result<(), Box<dyn Error>>) {
+        let result = (|| -> Result<Vec<String>, Box<dyn Error>> {
+            let before = self.ring.snapshot();
+            let mut target = build_ring(&before)?;
+            change(&mut target)?;
+            let after = target.snapshot();
+            let comparison = ShadowedRing::new(self.ring.share(), target).compare()?;
+
+            let mut lines = vec![
+                action.to_string(),
+                format!("keys moving: {:.2}% in {} ranges", comparison.moved_fraction * 100.0, comparison.moved_ranges.len()),
+                String::new(),
+                "partitions moving:".to_string(),
+            ];
+            let mut moves: Vec<(&usize, &str, &str)> = after
+                .partitions
+                .iter()
+                .map(|(part_id, to)| (part_id, before.partitions.get(part_id).map(String::as_str).unwrap_or("-"), to.as_str()))
+                .filter(|(_, from, to)| from != to)
+                .collect();
+            moves.sort_unstable();
+            if moves.is_empty() {
+                lines.push("  none".to_string());
+            }
+            for (part_id, from, to) in moves {
+                lines.push(format!("  {:>5}  {} -> {}", part_id, from, to));
+            }
+            Ok(lines)
+        })();
+        self.output = match result {
+            Ok(lines) => lines,
+            Err(err) => vec![format!("{}: {}", action, err)],
+        };
+    }
+
+    fn submit(&mut self, prompt: Prompt, text: String) {
+        match prompt {
+            Prompt::Search => self.search(&text),
+            Prompt::Add => self.preview(&format!("adding {}", text), |ring| ring.add_node(tui_node(&text)).map(|_| ())),
+        }
+    }
+
+    /// Handles a key press, returning `false` once the user quits.
+    fn on_key(&mut self, code: KeyCode) -> bool {
+        if let Some((prompt, mut text)) = self.input.take() {
+            match code {
+                KeyCode::Enter => self.submit(prompt, text),
+                KeyCode::Esc => {}
+                KeyCode::Backspace => {
+                    text.pop();
+                    self.input = Some((prompt, text));
+                }
+                KeyCode::Char(c) => {
+                    text.push(c);
+                    self.input = Some((prompt, text));
+                }
+                _ => self.input = Some((prompt, text)),
+            }
+            return true;
+        }
+        match code {
+            KeyCode::Char('q') | KeyCode::Esc => return false,
+            KeyCode::Up | KeyCode::Char('k') => self.move_selection(true),
+            KeyCode::Down | KeyCode::Char('j') => self.move_selection(false),
+            KeyCode::Char('/') => self.input = Some((Prompt::Search, String::new())),
+            KeyCode::Char('a') => self.input = Some((Prompt::Add, String::new())),
+            KeyCode::Char('d') => {
+                if let Some(id) = self.selected_id().map(str::to_string) {
+                    self.preview(&format!("removing {}", id), |ring| ring.remove_node(&id));
+                }
+            }
+            _ => {}
+        }
+        true
+    }
+}
+
+fn draw(frame: &mut Frame, app: &mut App) {
+    let rows = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([Constraint::Min(3), Constraint::Length(3)])
+        .split(frame.size());
+    let columns = Layout::default()
+        .direction(Direction::Horizontal)
+        .constraints([Constraint::Percentage(40), Constraint::Percentage(60)])
+        .split(rows[0]);
+
+    let vnodes = app.ring.virtual_nodes_per_node();
+    let width = app.node_ids.iter().map(String::len).max().unwrap_or(0);
+    let items: Vec<ListItem> = app
+        .node_ids
+        .iter()
+        .map(|id| {
+            ListItem::new(format!(
+                "{:<width$}  {:>5} vnodes  {:>6.2}%",
+                id,
+                vnodes.get(id).copied().unwrap_or(0),
+                app.ring.keyspace_fraction(id) * 100.0
+            ))
+        })
+        .collect();
+    let title = format!(" {} ({} nodes, v{}) ", app.path, app.node_ids.len(), app.ring.version());
+    let list = List::new(items)
+        .block(Block::default().borders(Borders::ALL).title(title))
+        .highlight_style(Style::default().add_modifier(Modifier::REVERSED));
+    frame.render_stateful_widget(list, columns[0], &mut app.selected);
+
+    let lines: Vec<Line> = app.output.iter().map(|line| Line::from(line.as_str())).collect();
+    let details = Paragraph::new(lines)
+        .block(Block::default().borders(Borders::ALL).title(" details "))
+        .wrap(Wrap { trim: false });
+    frame.render_widget(details, columns[1]);
+
+    let footer = match &app.input {
+        Some((prompt, text)) => format!("{}: {}_", prompt.label(), text),
+        None => HELP.to_string(),
+    };
+    frame.render_widget(Paragraph::new(footer).block(Block::default().borders(Borders::ALL)), rows[1]);
+}
+
+fn run(app: &mut App) -> Result<(), Box<dyn Error>> {
+    let mut terminal = Terminal::new(CrosstermBackend::new(io::stdout()))?;
+    loop {
+        terminal.draw(|frame| draw(frame, app))?;
+        if let Event::Key(key) = event::read()? {
+            if key.kind == KeyEventKind::Press && !app.on_key(key.code) {
+                return Ok(());
+            }
+        }
+    }
+}
+
+fn main() {
+    let path = match std::env::args().nth(1) {
+        Some(path) => path,
+        None => {
+            eprintln!("{}", USAGE);
+            process::exit(1);
+        }
+    };
+    let mut app = match App::new(&path) {
+        Ok(app) => app,
+        Err(err) => {
+            eprintln!("{}", err);
+            process::exit(1);
+        }
+    };
+
+    let result = enable_raw_mode()
+        .and_then(|_| io::stdout().execute(EnterAlternateScreen).map(|_| ()))
+        .map_err(Box::<dyn Error>::from)
+        .and_then(|_| run(&mut app));
+    // Restore the terminal before reporting errors.
+    let _ = disable_raw_mode();
+    let _ = io::stdout().execute(LeaveAlternateScreen);
+    if let Err(err) = result {
+        eprintln!("{}", err);
+        process::exit(1);
+    }
+}
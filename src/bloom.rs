@@ -0,0 +1,287 @@
+//! Negative ownership filters.
+//!
+//! Checking that a request reached the owner of its key takes a lookup in the ring. An
+//! `OwnershipFilter` answers the cheaper question of whether a node may own a key hash
+//! at all: the ring is cut into `2^16` buckets by the top bits of the hash, and a Bloom
+//! filter holds the buckets a node owns part of. A hash in a bucket missing from the
+//! filter is definitely not owned by the node, so a misrouted request can be rejected
+//! without consulting the ring; a hit only means the node may own it.
+//!
+//! Filters are built for one version of the ring and cached per node until the ring
+//! changes. `to_bytes` and `from_bytes` let clients download them.
+//!
+//! Layout (little endian):
+//!
+//! ```text
+//! magic "HRBF" | version: u64 | node id | hash count: u8 | words: u32 | bits: u64... |
+//! crc32 of everything before: u32
+//! ```
+
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::hash::BuildHasher;
+use std::sync::Arc;
+
+use crate::crc::crc32;
+use crate::persist::{Decoder, Encoder};
+use crate::{util, HashRing, Node};
+
+const MAGIC: &[u8; 4] = b"HRBF";
+/// The ring is cut into `2^BUCKET_BITS` buckets.
+const BUCKET_BITS: u32 = 16;
+/// About a 1% false positive rate.
+const BITS_PER_BUCKET: usize = 10;
+const HASH_COUNT: u8 = 7;
+
+/// A Bloom filter of the ring buckets a node owns part of, at one version of the ring.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OwnershipFilter {
+    version: u64,
+    node_id: String,
+    hash_count: u8,
+    bits: Vec<u64>,
+}
+
+impl OwnershipFilter {
+    fn new(version: u64, node_id: &str, buckets: &[u64]) -> OwnershipFilter {
+        let words = (buckets.len() * BITS_PER_BUCKET).div_ceil(64).max(1);
+        let mut filter = OwnershipFilter {
+            version,
+            node_id: node_id.to_string(),
+            hash_count: HASH_COUNT,
+            bits: vec![0; words],
+        };
+        for bucket in buckets {
+            for bit in filter.positions(*bucket) {
+                filter.bits[bit / 64] |= 1 << (bit % 64);
+            }
+        }
+        filter
+    }
+
+    /// The bits of a bucket, by double hashing two mixes of it.
+    fn positions(&self, bucket: u64) -> impl Iterator<Item = usize> {
+        let len = self.bits.len() as u64 * 64;
+        let h1 = mix(bucket);
+        let h2 = mix(h1) | 1;
+        (0..self.hash_count as u64).map(move |i| (h1.wrapping_add(i.wrapping_mul(h2)) % len) as usize)
+    }
+
+    /// Returns `false` if the node definitely doesn't own the key hash, `true` if it may.
+    pub fn may_own_hash(&self, hash: u64) -> bool {
+        self.positions(bucket(hash)).all(|bit| self.bits[bit / 64] & (1 << (bit % 64)) != 0)
+    }
+
+    /// Returns the version of the ring the filter was built for.
+    pub fn version(&self) -> u64 {
+        self.version
+    }
+
+    /// Returns the ID of the node the filter describes.
+    pub fn node_id(&self) -> &str {
+        &self.node_id
+    }
+
+    /// Encodes the filter, to send it to clients.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut enc = Encoder::new();
+        enc.raw(MAGIC);
+        enc.u64(self.version);
+        enc.str(&self.node_id);
+        enc.u8(self.hash_count);
+        enc.u32(self.bits.len() as u32);
+        for word in &self.bits {
+            enc.u64(*word);
+        }
+        let mut buf = enc.into_inner();
+        let checksum = crc32(&buf);
+        buf.extend_from_slice(&checksum.to_le_bytes());
+        buf
+    }
+
+    /// Decodes a filter encoded by `to_bytes`.
+    pub fn from_bytes(data: &[u8]) -> Result<OwnershipFilter, Box<dyn Error>> {
+        if data.len() < MAGIC.len() + 4 {
+            return Err("ownership filter is truncated".into());
+        }
+        let (body, checksum) = data.split_at(data.len() - 4);
+        if crc32(body) != u32::from_le_bytes(checksum.try_into()?) {
+            return Err("ownership filter checksum mismatch".into());
+        }
+        let mut dec = Decoder::new(body);
+        if dec.take(MAGIC.len())? != MAGIC {
+            return Err("not an ownership filter".into());
+        }
+        let version = dec.u64()?;
+        let node_id = dec.string()?;
+        let hash_count = dec.u8()?;
+        let words = dec.u32()? as usize;
+        if words == 0 || hash_count == 0 {
+            return Err("ownership filter is empty".into());
+        }
+        let bits = (0..words).map(|_| dec.u64()).collect::<Result<Vec<u64>, _>>()?;
+        if !dec.is_empty() {
+            return Err("trailing bytes after ownership filter".into());
+        }
+        Ok(OwnershipFilter { version, node_id, hash_count, bits })
+    }
+}
+
+fn bucket(hash: u64) -> u64 {
+    hash >> (64 - BUCKET_BITS)
+}
+
+/// The SplitMix64 finalizer.
+fn mix(mut z: u64) -> u64 {
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+    z ^ (z >> 31)
+}
+
+/// Returns the buckets overlapping the arcs owned by a node, sorted.
+fn owned_buckets<'a>(sorted_set: &BTreeMap<u64, Arc<dyn Node<'a> + 'a>>, node_id: &str) -> Vec<u64> {
+    let mut buckets = Vec::new();
+    for (point, _) in sorted_set.iter().filter(|(_, node)| node.id() == node_id) {
+        let (previous, _) = util::previous_point(sorted_set, *point).expect("the ring has a point");
+        if previous == *point {
+            // The only point owns the whole ring.
+            return (0..1 << BUCKET_BITS).collect();
+        }
+        // The arc (previous, point], which may wrap past u64::MAX.
+        let first = bucket(previous.wrapping_add(1));
+        let last = bucket(*point);
+        if previous < *point {
+            buckets.extend(first..=last);
+        } else {
+            if previous != u64::MAX {
+                buckets.extend(first..1 << BUCKET_BITS);
+            }
+            buckets.extend(0..=last);
+        }
+    }
+    buckets.sort_unstable();
+    buckets.dedup();
+    buckets
+}
+
+impl<'a, H, K> HashRing<'a, H, K>
+where
+    H: BuildHasher,
+    K: BuildHasher,
+{
+    /// Returns the ownership filter of a node at the current version of the ring.
+    ///
+    /// Filters are cached until the ring changes.
+    ///
+    /// # Arguments
+    ///
+    /// * `node_id` - The ID of the node.
+    ///
+    /// # Returns
+    ///
+    /// * `Option<Arc<OwnershipFilter>>` - The filter, or `None` if the node isn't a member.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use std::sync::Arc;
+    /// use hashring::{HashRing, Config, Node, OwnershipFilter};
+    ///
+    /// #[derive(Debug)]
+    /// struct MyNode<'a> {
+    ///     name: &'a str,
+    /// }
+    ///
+    /// impl<'a> Node<'a> for MyNode<'a> {
+    ///     fn id(&self) -> &'a str {
+    ///         self.name
+    ///     }
+    /// }
+    ///
+    /// let mut hash_ring = HashRing::new(Config::default()).unwrap();
+    /// hash_ring.add_node(Arc::new(MyNode { name: "node1" })).unwrap();
+    /// hash_ring.add_node(Arc::new(MyNode { name: "node2" })).unwrap();
+    ///
+    /// // Served by node1 and downloaded by a client.
+    /// let filter = OwnershipFilter::from_bytes(&hash_ring.ownership_filter("node1").unwrap().to_bytes()).unwrap();
+    /// if !hash_ring.may_own(&filter, b"some_key") {
+    ///     assert_eq!(hash_ring.get_key(b"some_key").unwrap().id(), "node2");
+    /// }
+    /// ```
+    pub fn ownership_filter(&self, node_id: &str) -> Option<Arc<OwnershipFilter>> {
+        let version = self.version();
+        if let Some(filter) = self.ownership_filters.read().ok()?.get(node_id) {
+            if filter.version == version {
+                return Some(filter.clone());
+            }
+        }
+        let nodes = self.nodes.read().ok()?;
+        if !nodes.contains_key(node_id) {
+            return None;
+        }
+        let sorted_set = self.sorted_nodes_hash_set.read().ok()?;
+        let filter = Arc::new(OwnershipFilter::new(version, node_id, &owned_buckets(&sorted_set, node_id)));
+        drop(sorted_set);
+        drop(nodes);
+        let mut filters = self.ownership_filters.write().ok()?;
+        filters.retain(|_, filter| filter.version == version);
+        filters.insert(node_id.to_string(), filter.clone());
+        Some(filter)
+    }
+
+    /// Returns `false` if the node of the filter definitely doesn't own the key.
+    ///
+    /// A filter built for another version of the ring can't rule anything out, so it
+    /// always returns `true`.
+    pub fn may_own(&self, filter: &OwnershipFilter, key: &[u8]) -> bool {
+        if filter.version != self.version() {
+            return true;
+        }
+        let point = self
+            .sorted_nodes_hash_set
+            .read()
+            .and_then(|sorted_set| self.route(&sorted_set, self.hash_key(key)?));
+        match point {
+            Ok(point) => filter.may_own_hash(point),
+            Err(_) => true,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::tests::test_nodes;
+    use crate::{Config, HashRing, OwnershipFilter};
+
+    #[test]
+    fn test_ownership_filter_has_no_false_negatives() {
+        let mut hash_ring = HashRing::new(Config::default()).unwrap();
+        assert!(hash_ring.ownership_filter("node1").is_none());
+        for node in test_nodes() {
+            hash_ring.add_node(node).unwrap();
+        }
+        let filter = hash_ring.ownership_filter("node1").unwrap();
+        assert!(std::sync::Arc::ptr_eq(&filter, &hash_ring.ownership_filter("node1").unwrap()));
+        assert_eq!(OwnershipFilter::from_bytes(&filter.to_bytes()).unwrap(), *filter);
+        let mut corrupted = filter.to_bytes();
+        corrupted[10] ^= 1;
+        assert!(OwnershipFilter::from_bytes(&corrupted).is_err());
+
+        let mut rejected = 0;
+        for i in 0..1000 {
+            let key = format!("key{}", i);
+            let owner = hash_ring.get_key(key.as_bytes()).unwrap();
+            if owner.id() == "node1" {
+                assert!(hash_ring.may_own(&filter, key.as_bytes()));
+            } else if !hash_ring.may_own(&filter, key.as_bytes()) {
+                rejected += 1;
+            }
+        }
+        assert!(rejected > 500);
+
+        // A stale filter rules nothing out, and a new one is built.
+        hash_ring.remove_node("node2").unwrap();
+        assert!((0..100).all(|i| hash_ring.may_own(&filter, format!("key{}", i).as_bytes())));
+        assert_eq!(hash_ring.ownership_filter("node1").unwrap().version(), hash_ring.version());
+    }
+}
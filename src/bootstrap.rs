@@ -0,0 +1,153 @@
+//! Populating the initial membership from common discovery sources.
+//!
+//! `from_env` reads node IDs from an environment variable, as set by container
+//! orchestrators and deploy scripts, and `from_dns_srv` reads them from the SRV records
+//! of a service, behind the `dns` feature. Both add every discovered node in a single
+//! transaction and leave the ring to be kept up to date by other means afterwards.
+//!
+//! Discovered IDs are turned into nodes by a resolver, as with `from_snapshot`; for SRV
+//! records the ID is `host:port`.
+
+use std::error::Error;
+use std::sync::Arc;
+
+use crate::{Config, HashRing, Node, XxHash64Hasher};
+
+/// Splits a list of node IDs separated by commas or whitespace, skipping empty entries.
+fn parse_node_list(list: &str) -> Vec<&str> {
+    list.split(|c: char| c == ',' || c.is_whitespace()).filter(|id| !id.is_empty()).collect()
+}
+
+impl<'a> HashRing<'a, XxHash64Hasher> {
+    /// Creates a ring whose members are listed in an environment variable.
+    ///
+    /// # Arguments
+    ///
+    /// * `config` - The configuration of the ring.
+    /// * `var` - The name of the variable, holding node IDs separated by commas or whitespace.
+    /// * `resolver` - Returns the node for a listed ID, or `None` if it is unknown.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<HashRing<'a, XxHash64Hasher>, Box<dyn Error>>` - The ring, or an error if the
+    ///   variable is unset, lists no nodes or lists a node that can't be resolved.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use std::sync::Arc;
+    /// use hashring::{HashRing, Config, Node};
+    ///
+    /// #[derive(Debug)]
+    /// struct MyNode {
+    ///     addr: &'static str,
+    /// }
+    ///
+    /// impl Node<'static> for MyNode {
+    ///     fn id(&self) -> &'static str {
+    ///         self.addr
+    ///     }
+    /// }
+    ///
+    /// std::env::set_var("RING_NODES", "10.0.0.1:11211, 10.0.0.2:11211");
+    /// let hash_ring = HashRing::from_env(Config::default(), "RING_NODES", |id| {
+    ///     // Bootstrapped nodes live as long as the process.
+    ///     let addr: &'static str = Box::leak(id.to_string().into_boxed_str());
+    ///     Some(Arc::new(MyNode { addr }) as Arc<dyn Node<'static>>)
+    /// }).unwrap();
+    /// assert_eq!(hash_ring.virtual_nodes_per_node().len(), 2);
+    /// ```
+    pub fn from_env<F>(config: Config, var: &str, resolver: F) -> Result<HashRing<'a, XxHash64Hasher>, Box<dyn Error>>
+    where
+        F: FnMut(&str) -> Option<Arc<dyn Node<'a> + 'a>>,
+    {
+        let list = std::env::var(var).map_err(|err| format!("unable to read {}: {}", var, err))?;
+        let ids = parse_node_list(&list);
+        if ids.is_empty() {
+            return Err(format!("{} lists no nodes", var).into());
+        }
+        HashRing::bootstrap(config, &ids, resolver)
+    }
+
+    /// Creates a ring whose members are the targets of the SRV records of a service,
+    /// looked up with the system's resolver configuration.
+    ///
+    /// Only the targets with the lowest priority are added, since the others are meant
+    /// as fallbacks. Record weights are ignored.
+    ///
+    /// # Arguments
+    ///
+    /// * `config` - The configuration of the ring.
+    /// * `name` - The name of the service, e.g. `_cache._tcp.example.com`.
+    /// * `resolver` - Returns the node for a `host:port` ID, or `None` if it is unknown.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<HashRing<'a, XxHash64Hasher>, Box<dyn Error>>` - The ring, or an error if the
+    ///   lookup fails, returns no records or returns a target that can't be resolved.
+    #[cfg(feature = "dns")]
+    pub fn from_dns_srv<F>(config: Config, name: &str, resolver: F) -> Result<HashRing<'a, XxHash64Hasher>, Box<dyn Error>>
+    where
+        F: FnMut(&str) -> Option<Arc<dyn Node<'a> + 'a>>,
+    {
+        let lookup = hickory_resolver::Resolver::from_system_conf()?.srv_lookup(name)?;
+        let priority = lookup.iter().map(|srv| srv.priority()).min().ok_or_else(|| format!("{} has no SRV records", name))?;
+        let mut ids: Vec<String> = lookup
+            .iter()
+            .filter(|srv| srv.priority() == priority)
+            .map(|srv| format!("{}:{}", srv.target().to_utf8().trim_end_matches('.'), srv.port()))
+            .collect();
+        ids.sort_unstable();
+        ids.dedup();
+        let ids: Vec<&str> = ids.iter().map(String::as_str).collect();
+        HashRing::bootstrap(config, &ids, resolver)
+    }
+
+    fn bootstrap<F>(config: Config, ids: &[&str], mut resolver: F) -> Result<HashRing<'a, XxHash64Hasher>, Box<dyn Error>>
+    where
+        F: FnMut(&str) -> Option<Arc<dyn Node<'a> + 'a>>,
+    {
+        let mut hash_ring = HashRing::new(config)?;
+        hash_ring.transaction(|tx| {
+            for id in ids {
+                let node = resolver(id).ok_or_else(|| format!("unable to resolve node {}", id))?;
+                if node.id() != *id {
+                    return Err(format!("resolved node {} has a different id {}", id, node.id()).into());
+                }
+                tx.add(node)?;
+            }
+            Ok(())
+        })?;
+        Ok(hash_ring)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::parse_node_list;
+    use crate::tests::test_nodes;
+    use crate::{Config, HashRing, Node};
+
+    #[test]
+    fn test_from_env_bootstraps_members() {
+        assert_eq!(parse_node_list(" node1,node2\n node3 ,,"), ["node1", "node2", "node3"]);
+
+        let nodes = test_nodes();
+        let resolve = |id: &str| nodes.iter().find(|node| node.id() == id).map(|node| node.clone() as Arc<dyn Node<'static>>);
+        assert!(HashRing::from_env(Config::default(), "HASHRING_TEST_UNSET_NODES", resolve).is_err());
+
+        std::env::set_var("HASHRING_TEST_BOOTSTRAP_NODES", "node1, node3");
+        let hash_ring = HashRing::from_env(Config::default(), "HASHRING_TEST_BOOTSTRAP_NODES", resolve).unwrap();
+        let mut members: Vec<String> = hash_ring.virtual_nodes_per_node().into_keys().collect();
+        members.sort();
+        assert_eq!(members, ["node1", "node3"]);
+        assert_eq!(hash_ring.version(), 1);
+
+        std::env::set_var("HASHRING_TEST_BOOTSTRAP_NODES", "node1, node9");
+        assert!(HashRing::from_env(Config::default(), "HASHRING_TEST_BOOTSTRAP_NODES", resolve).is_err());
+        std::env::set_var("HASHRING_TEST_BOOTSTRAP_NODES", " , ");
+        assert!(HashRing::from_env(Config::default(), "HASHRING_TEST_BOOTSTRAP_NODES", resolve).is_err());
+    }
+}
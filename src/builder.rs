@@ -0,0 +1,110 @@
+use std::error::Error;
+use std::hash::BuildHasher;
+
+use crate::{Config, HashRing, XxHash64Hasher};
+
+/// Builds a `HashRing` with independently configured node and key hashers.
+///
+/// # Example
+///
+/// ```
+/// use hashring::{Config, Crc32BuildHasher, HashRing};
+///
+/// let hash_ring = HashRing::builder()
+///     .config(Config::default())
+///     .key_hasher(Crc32BuildHasher::default())
+///     .build()
+///     .unwrap();
+/// ```
+#[derive(Debug, Clone)]
+pub struct HashRingBuilder<H, K> {
+    config: Config,
+    node_hasher: H,
+    key_hasher: K,
+}
+
+impl HashRingBuilder<XxHash64Hasher, XxHash64Hasher> {
+    pub fn new() -> HashRingBuilder<XxHash64Hasher, XxHash64Hasher> {
+        HashRingBuilder {
+            config: Config::default(),
+            node_hasher: XxHash64Hasher::default(),
+            key_hasher: XxHash64Hasher::default(),
+        }
+    }
+}
+
+impl Default for HashRingBuilder<XxHash64Hasher, XxHash64Hasher> {
+    fn default() -> Self {
+        HashRingBuilder::new()
+    }
+}
+
+impl<H, K> HashRingBuilder<H, K>
+where
+    H: BuildHasher,
+    K: BuildHasher,
+{
+    /// Sets the configuration of the ring.
+    pub fn config(mut self, config: Config) -> HashRingBuilder<H, K> {
+        self.config = config;
+        self
+    }
+
+    /// Sets the hasher used to place virtual nodes and partitions on the ring.
+    pub fn node_hasher<H2: BuildHasher>(self, node_hasher: H2) -> HashRingBuilder<H2, K> {
+        HashRingBuilder {
+            config: self.config,
+            node_hasher,
+            key_hasher: self.key_hasher,
+        }
+    }
+
+    /// Sets the hasher used to hash keys on lookup.
+    pub fn key_hasher<K2: BuildHasher>(self, key_hasher: K2) -> HashRingBuilder<H, K2> {
+        HashRingBuilder {
+            config: self.config,
+            node_hasher: self.node_hasher,
+            key_hasher,
+        }
+    }
+
+    /// Validates the configuration and creates the `HashRing`.
+    pub fn build<'a>(self) -> Result<HashRing<'a, H, K>, Box<dyn Error>> {
+        HashRing::with_hashers(self.config, self.node_hasher, self.key_hasher)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::hash::{BuildHasher, Hasher};
+    use std::sync::Arc;
+
+    use crate::tests::TestNode;
+    use crate::{Config, Crc32BuildHasher, HashRing};
+
+    #[test]
+    fn test_separate_key_hasher() {
+        let config = Config {
+            partition_count: 10,
+            ..Config::default()
+        };
+        let mut hash_ring = HashRing::builder()
+            .config(config)
+            .key_hasher(Crc32BuildHasher::default())
+            .build()
+            .unwrap();
+
+        hash_ring
+            .add_node(Arc::new(TestNode {
+                ip_addr: "127.0.0.1".to_string(),
+                name: "node1",
+            }))
+            .unwrap();
+
+        let mut hasher = Crc32BuildHasher::default().build_hasher();
+        hasher.write(b"123456789");
+        assert_eq!(hash_ring.hash_key(b"123456789").unwrap(), hasher.finish());
+        assert_eq!(hash_ring.get_partition(b"123456789"), (hasher.finish() % 10) as usize);
+        assert_eq!(hash_ring.get_key(b"123456789").unwrap().id(), "node1");
+    }
+}
@@ -0,0 +1,193 @@
+//! Diverting a fraction of a node's keys to a canary node.
+//!
+//! When a new version of a storage node is deployed as a canary, a traffic split sends
+//! part of the keys of an existing node to it. Which keys are diverted depends only on
+//! the key hash, so a key is always served by the same node while the split is in place,
+//! and raising the fraction only adds keys to the canary.
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::hash::BuildHasher;
+use std::sync::Arc;
+
+use crate::{HashRing, Node};
+
+/// The share of a node's keys diverted to a canary node.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TrafficSplit {
+    pub canary: String,
+    /// The fraction of keys diverted, from `0.0` to `1.0`.
+    pub fraction: f64,
+}
+
+/// Maps a key hash to a uniformly distributed value in `[0, 1)`, independent of the
+/// key's position on the ring.
+fn bucket(hash: u64) -> f64 {
+    let mut z = hash.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    (z ^ (z >> 31)) as f64 / (u64::MAX as f64 + 1.0)
+}
+
+impl<'a, H, K> HashRing<'a, H, K>
+where
+    H: BuildHasher,
+    K: BuildHasher,
+{
+    /// Diverts a fraction of the keys owned by a node to a canary node.
+    ///
+    /// Only `get_key` follows the split; preference lists and partition owners are left
+    /// unchanged. The split is cleared when either node leaves the ring.
+    ///
+    /// # Arguments
+    ///
+    /// * `node_id` - The ID of the node whose keys are diverted.
+    /// * `canary_id` - The ID of the node receiving them.
+    /// * `fraction` - The fraction of keys diverted, from `0.0` to `1.0`.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use std::sync::Arc;
+    /// use hashring::{HashRing, Config, Node};
+    ///
+    /// #[derive(Debug)]
+    /// struct MyNode<'a> {
+    ///     name: &'a str,
+    /// }
+    ///
+    /// impl<'a> Node<'a> for MyNode<'a> {
+    ///     fn id(&self) -> &'a str {
+    ///         self.name
+    ///     }
+    /// }
+    ///
+    /// let mut hash_ring = HashRing::new(Config::default()).unwrap();
+    /// hash_ring.add_node(Arc::new(MyNode { name: "node1" })).unwrap();
+    /// hash_ring.add_node(Arc::new(MyNode { name: "node1-canary" })).unwrap();
+    ///
+    /// // Send every key of node1 to the canary.
+    /// hash_ring.set_traffic_split("node1", "node1-canary", 1.0).unwrap();
+    /// for key in [b"key-1", b"key-2", b"key-3"] {
+    ///     assert_eq!(hash_ring.get_key(key).unwrap().id(), "node1-canary");
+    /// }
+    /// ```
+    pub fn set_traffic_split(&mut self, node_id: &str, canary_id: &str, fraction: f64) -> Result<(), Box<dyn Error>> {
+        let nodes = self.nodes.read()?;
+        if !nodes.contains_key(node_id) || !nodes.contains_key(canary_id) {
+            return Err("node not found".into());
+        }
+        drop(nodes);
+
+        self.routes.write()?.set_traffic_split(node_id, canary_id, fraction)?;
+        self.bump_version();
+        Ok(())
+    }
+
+    /// Sends every key of the node back to it, returning the split that was in place.
+    pub fn clear_traffic_split(&mut self, node_id: &str) -> Option<TrafficSplit> {
+        let split = self.routes.write().ok()?.traffic_splits.remove(node_id)?;
+        self.bump_version();
+        Some(split)
+    }
+
+    /// Returns the traffic split of the node, if any.
+    pub fn traffic_split(&self, node_id: &str) -> Option<TrafficSplit> {
+        self.routes.read().ok()?.traffic_splits.get(node_id).cloned()
+    }
+
+    /// Returns the canary the key is diverted to, if its owner has a traffic split covering it.
+    pub(crate) fn divert(&self, owner: &Arc<dyn Node<'a> + 'a>, hash: u64) -> Option<Arc<dyn Node<'a> + 'a>> {
+        // The routes are released before the nodes are read, since mutators take the nodes first.
+        let canary = self.routes.read().ok()?.canary(owner.id(), hash)?.to_string();
+        self.nodes.read().ok()?.get(&canary).cloned()
+    }
+}
+
+/// Returns the ID of the canary a key hash owned by `owner_id` is diverted to, if any.
+pub(crate) fn split_target<'s>(splits: &'s HashMap<String, TrafficSplit>, owner_id: &str, hash: u64) -> Option<&'s str> {
+    let split = splits.get(owner_id)?;
+    if bucket(hash) >= split.fraction {
+        return None;
+    }
+    Some(&split.canary)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::tests::test_nodes;
+    use crate::{Config, HashRing};
+
+    #[test]
+    fn test_traffic_split_diverts_a_fraction() {
+        let mut hash_ring = HashRing::new(Config::default()).unwrap();
+        for node in test_nodes() {
+            hash_ring.add_node(node).unwrap();
+        }
+        let keys: Vec<String> = (0..3000).map(|i| format!("key-{}", i)).collect();
+        let owned: Vec<&String> = keys.iter().filter(|key| hash_ring.get_key(key.as_bytes()).unwrap().id() == "node1").collect();
+
+        assert!(hash_ring.set_traffic_split("node1", "node1", 0.5).is_err());
+        assert!(hash_ring.set_traffic_split("node1", "node3", 1.5).is_err());
+        hash_ring.set_traffic_split("node1", "node3", 0.25).unwrap();
+        let diverted: Vec<&&String> = owned.iter().filter(|key| hash_ring.get_key(key.as_bytes()).unwrap().id() == "node3").collect();
+        let share = diverted.len() as f64 / owned.len() as f64;
+        assert!(share > 0.2 && share < 0.3, "{}", share);
+
+        // Raising the fraction keeps the keys already diverted on the canary.
+        hash_ring.set_traffic_split("node1", "node3", 0.5).unwrap();
+        assert!(diverted.iter().all(|key| hash_ring.get_key(key.as_bytes()).unwrap().id() == "node3"));
+
+        // Keys go back to their owner once the split is cleared.
+        assert_eq!(hash_ring.clear_traffic_split("node1").unwrap().fraction, 0.5);
+        assert!(hash_ring.traffic_split("node1").is_none());
+        assert_eq!(hash_ring.get_key(diverted[0].as_bytes()).unwrap().id(), "node1");
+    }
+
+    #[test]
+    fn test_removed_nodes_lose_their_traffic_splits() {
+        let nodes = test_nodes();
+        let mut hash_ring = HashRing::new(Config::default()).unwrap();
+        for node in nodes.iter() {
+            hash_ring.add_node(node.clone()).unwrap();
+        }
+        hash_ring.set_traffic_split("node1", "node3", 1.0).unwrap();
+        hash_ring.set_traffic_split("node2", "node1", 1.0).unwrap();
+
+        // Removing the canary clears its split, so it doesn't take the keys back on rejoin.
+        hash_ring.remove_node("node3").unwrap();
+        assert!(hash_ring.traffic_split("node1").is_none());
+        assert!(hash_ring.traffic_split("node2").is_some());
+        hash_ring.add_node(nodes[2].clone()).unwrap();
+        let key = (0..100).map(|i| format!("key-{}", i)).find(|key| hash_ring.get_preference_list_n(key.as_bytes(), 1)[0].id() == "node1").unwrap();
+        assert_eq!(hash_ring.get_key(key.as_bytes()).unwrap().id(), "node1");
+
+        // Removing the owner clears its split too, whichever path removes it.
+        hash_ring.transaction(|tx| tx.remove("node2")).unwrap();
+        assert!(hash_ring.traffic_split("node2").is_none());
+        hash_ring.check_invariants().unwrap();
+    }
+
+    #[test]
+    fn test_traffic_split_applies_to_every_lookup() {
+        let mut hash_ring = HashRing::new(Config::default()).unwrap();
+        for node in test_nodes() {
+            hash_ring.add_node(node).unwrap();
+        }
+        hash_ring.set_traffic_split("node1", "node3", 0.5).unwrap();
+        let frozen = hash_ring.freeze();
+
+        let mut diverted = 0;
+        for i in 0..500 {
+            let key = format!("key-{}", i);
+            let owner = hash_ring.get_key(key.as_bytes()).unwrap();
+            assert_eq!(frozen.get_key(key.as_bytes()).unwrap().id(), owner.id());
+            assert_eq!(hash_ring.get_key_with_previous(key.as_bytes()).current.unwrap().id(), owner.id());
+            hash_ring.check_key(key.as_bytes()).unwrap();
+            if owner.id() != hash_ring.get_preference_list_n(key.as_bytes(), 1)[0].id() {
+                diverted += 1;
+            }
+        }
+        assert!(diverted > 0);
+    }
+}
@@ -0,0 +1,164 @@
+//! Capacity-aware placement.
+//!
+//! Storage nodes fill up at different rates. Nodes report their capacity and usage with
+//! `report_capacity`, and a node whose usage leaves less free space than
+//! `Config::capacity_headroom` of its capacity is full: it keeps serving the partitions
+//! it owns, but partitions it would gain in a later placement go to the next node with
+//! headroom on the ring instead, and `rebalance` doesn't move partitions to it. Nodes
+//! that never reported a capacity always have headroom.
+
+use std::collections::{BTreeMap, HashSet};
+use std::error::Error;
+use std::hash::BuildHasher;
+use std::sync::Arc;
+
+use crate::{HashRing, Node};
+
+/// The default fraction of its capacity a node keeps free.
+pub(crate) const DEFAULT_CAPACITY_HEADROOM: f64 = 0.1;
+
+/// The capacity and usage last reported by a node, in units of the caller's choice.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NodeCapacity {
+    pub capacity: u64,
+    pub used: u64,
+}
+
+impl NodeCapacity {
+    /// Returns `true` if less than `headroom` of the capacity is free.
+    pub fn is_full(&self, headroom: f64) -> bool {
+        self.used as f64 > self.capacity as f64 * (1.0 - headroom)
+    }
+}
+
+impl<'a, H, K> HashRing<'a, H, K>
+where
+    H: BuildHasher,
+    K: BuildHasher,
+{
+    /// Records the capacity and current usage of a node.
+    ///
+    /// The report is taken into account by the next placement: a node running out of
+    /// headroom isn't unassigned its partitions right away.
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - The ID of the node, which may not have joined the ring yet.
+    /// * `capacity` - The total capacity of the node. Must be greater than 0.
+    /// * `used` - How much of the capacity is in use.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use std::sync::Arc;
+    /// use hashring::{HashRing, Config, Node};
+    ///
+    /// #[derive(Debug)]
+    /// struct MyNode<'a> {
+    ///     name: &'a str,
+    /// }
+    ///
+    /// impl<'a> Node<'a> for MyNode<'a> {
+    ///     fn id(&self) -> &'a str {
+    ///         self.name
+    ///     }
+    /// }
+    ///
+    /// let mut hash_ring = HashRing::new(Config::default()).unwrap();
+    /// hash_ring.add_node(Arc::new(MyNode { name: "node1" })).unwrap();
+    ///
+    /// // 95% used leaves less than the default 10% headroom.
+    /// hash_ring.report_capacity("node1", 1000, 950).unwrap();
+    /// assert!(hash_ring.is_full("node1"));
+    ///
+    /// // Node1 keeps its partitions, but new ones go to node2.
+    /// let owned = hash_ring.partitions_of("node1").count();
+    /// hash_ring.add_node(Arc::new(MyNode { name: "node2" })).unwrap();
+    /// assert!(hash_ring.partitions_of("node1").count() <= owned);
+    /// ```
+    pub fn report_capacity(&mut self, id: &str, capacity: u64, used: u64) -> Result<(), Box<dyn Error>> {
+        if capacity == 0 {
+            return Err("capacity must be greater than 0".into());
+        }
+        let mut capacities = self.capacities.write()?;
+        capacities.insert(id.to_string(), NodeCapacity { capacity, used });
+        Ok(())
+    }
+
+    /// Returns the capacity last reported by a node, if any.
+    pub fn node_capacity(&self, id: &str) -> Option<NodeCapacity> {
+        self.capacities.read().ok()?.get(id).copied()
+    }
+
+    /// Forgets the capacity of a node, giving it headroom again.
+    pub fn clear_capacity(&self, id: &str) {
+        self.capacities.wait_write().remove(id);
+    }
+
+    /// Returns `true` if the node has less free capacity than the configured headroom.
+    pub fn is_full(&self, id: &str) -> bool {
+        self.node_capacity(id).is_some_and(|capacity| capacity.is_full(self.config().capacity_headroom))
+    }
+
+    /// Returns the IDs of the nodes without headroom.
+    pub(crate) fn full_nodes(&self) -> HashSet<String> {
+        let capacities = self.capacities.wait_read();
+        capacities
+            .iter()
+            .filter(|(_, capacity)| capacity.is_full(self.config().capacity_headroom))
+            .map(|(id, _)| id.clone())
+            .collect()
+    }
+}
+
+/// Returns the owner of a partition when some nodes are full: its previous owner if the
+/// partition stays on it, or else the first node with headroom from its position.
+///
+/// Falls back to the closest node when every node is full.
+pub(crate) fn owner_with_headroom<'s, 'a>(
+    sorted_set: &'s BTreeMap<u64, Arc<dyn Node<'a> + 'a>>,
+    hashed_part_id: u64,
+    previous: Option<&Arc<dyn Node<'a> + 'a>>,
+    full: &HashSet<String>,
+) -> Option<&'s Arc<dyn Node<'a> + 'a>> {
+    let mut candidates = sorted_set.range(hashed_part_id..).chain(sorted_set.range(..hashed_part_id)).map(|(_, node)| node);
+    let closest = candidates.clone().next()?;
+    if !full.contains(closest.id()) || previous.is_some_and(|node| node.id() == closest.id()) {
+        return Some(closest);
+    }
+    candidates.find(|node| !full.contains(node.id())).or(Some(closest))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::tests::test_nodes;
+    use crate::{Config, HashRing};
+
+    #[test]
+    fn test_full_node_keeps_but_gains_no_partitions() {
+        let mut hash_ring = HashRing::new(Config::default()).unwrap();
+        let nodes = test_nodes();
+        hash_ring.add_node(nodes[0].clone()).unwrap();
+        hash_ring.add_node(nodes[1].clone()).unwrap();
+        assert!(hash_ring.report_capacity("node1", 0, 0).is_err());
+        hash_ring.report_capacity("node1", 100, 95).unwrap();
+        hash_ring.report_capacity("node2", 100, 50).unwrap();
+        assert!(hash_ring.is_full("node1"));
+        assert!(!hash_ring.is_full("node2"));
+        assert!(!hash_ring.is_full("node3"));
+
+        let before: Vec<usize> = hash_ring.partitions_of("node1").collect();
+        hash_ring.add_node(nodes[2].clone()).unwrap();
+        hash_ring.remove_node("node2").unwrap();
+        // Node1 lost partitions to node3 but gained none of node2's.
+        let after: Vec<usize> = hash_ring.partitions_of("node1").collect();
+        assert!(after.iter().all(|part_id| before.contains(part_id)));
+        assert_eq!(after.len() + hash_ring.partitions_of("node3").count(), hash_ring.partition_ids().len());
+
+        // Once it has headroom again, node1 takes its share back.
+        hash_ring.clear_capacity("node1");
+        hash_ring.add_node(nodes[1].clone()).unwrap();
+        hash_ring.remove_node("node2").unwrap();
+        assert!(hash_ring.partitions_of("node1").count() > after.len());
+    }
+}
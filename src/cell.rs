@@ -0,0 +1,247 @@
+//! Cell-based placement: keys pick a cell first, then a node inside it.
+//!
+//! Cell-based architectures split a fleet into independent cells so a bad deploy or an
+//! overloaded node only affects the keys of one cell. A `CellRing` keeps a ring of named
+//! cells and a ring of nodes per cell. Unlike `HierarchicalRing`, membership is explicit
+//! rather than taken from `Node::zone`, every cell has its own replication factor, and
+//! preference lists never leave the cell of the key, so the blast radius of a cell is
+//! bounded by its nodes.
+
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::hash::BuildHasher;
+use std::sync::Arc;
+
+use crate::{Config, HashRing, Node, ReplicaSet, XxHash64Hasher};
+
+/// A cell, placed on the cell ring like a node.
+#[derive(Debug)]
+struct Cell<'a> {
+    name: &'a str,
+}
+
+impl<'a> Node<'a> for Cell<'a> {
+    fn id(&self) -> &'a str {
+        self.name
+    }
+}
+
+/// A ring of named cells, each holding a ring of nodes.
+#[derive(Debug, Clone)]
+pub struct CellRing<'a, H = XxHash64Hasher, K = H> {
+    config: Config,
+    node_hasher: H,
+    key_hasher: K,
+    cells: HashRing<'a, H, K>,
+    cell_rings: BTreeMap<&'a str, HashRing<'a, H, K>>,
+}
+
+impl<'a> CellRing<'a, XxHash64Hasher> {
+    /// Creates an empty cell ring. `config` is used for the cell ring and as the default
+    /// configuration of every cell.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use std::sync::Arc;
+    /// use hashring::{CellRing, Config, Node};
+    ///
+    /// #[derive(Debug)]
+    /// struct MyNode<'a> {
+    ///     name: &'a str,
+    /// }
+    ///
+    /// impl<'a> Node<'a> for MyNode<'a> {
+    ///     fn id(&self) -> &'a str {
+    ///         self.name
+    ///     }
+    /// }
+    ///
+    /// let mut ring = CellRing::new(Config::default()).unwrap();
+    /// ring.add_cell("cell-1", 3).unwrap();
+    /// ring.add_cell("cell-2", 2).unwrap();
+    /// for name in ["node1", "node2", "node3"] {
+    ///     ring.add_node("cell-1", Arc::new(MyNode { name })).unwrap();
+    /// }
+    /// for name in ["node4", "node5"] {
+    ///     ring.add_node("cell-2", Arc::new(MyNode { name })).unwrap();
+    /// }
+    ///
+    /// let cell = ring.get_cell(b"some_key").unwrap();
+    /// let replicas = ring.get_preference_list(b"some_key");
+    /// assert!(replicas.iter().all(|replica| ring.cell_of(replica.node.id()) == Some(cell)));
+    /// ```
+    pub fn new(config: Config) -> Result<CellRing<'a, XxHash64Hasher>, Box<dyn Error>> {
+        CellRing::with_hashers(config, XxHash64Hasher::default(), XxHash64Hasher::default())
+    }
+}
+
+impl<'a, H, K> CellRing<'a, H, K>
+where
+    H: BuildHasher + Clone,
+    K: BuildHasher + Clone,
+{
+    /// Creates an empty cell ring with separate hashers for placement and keys.
+    pub fn with_hashers(config: Config, node_hasher: H, key_hasher: K) -> Result<CellRing<'a, H, K>, Box<dyn Error>> {
+        let cells = HashRing::with_hashers(config.clone(), node_hasher.clone(), key_hasher.clone())?;
+        Ok(CellRing {
+            config,
+            node_hasher,
+            key_hasher,
+            cells,
+            cell_rings: BTreeMap::new(),
+        })
+    }
+
+    /// Adds an empty cell whose nodes are placed with their own replication factor.
+    ///
+    /// # Arguments
+    ///
+    /// * `cell` - The name of the cell.
+    /// * `replication_factor` - The number of virtual nodes per node, and of replicas per key, in the cell.
+    pub fn add_cell(&mut self, cell: &'a str, replication_factor: usize) -> Result<(), Box<dyn Error>> {
+        if self.cell_rings.contains_key(cell) {
+            return Err("cell already exist".into());
+        }
+        let config = Config {
+            replication_factor,
+            ..self.config.clone()
+        };
+        let ring = HashRing::with_hashers(config, self.node_hasher.clone(), self.key_hasher.clone())?;
+        self.cells.add_node(Arc::new(Cell { name: cell }))?;
+        self.cell_rings.insert(cell, ring);
+        Ok(())
+    }
+
+    /// Removes a cell. Its nodes must be removed first, so that removing a cell never
+    /// moves keys to other cells by surprise.
+    pub fn remove_cell(&mut self, cell: &str) -> Result<(), Box<dyn Error>> {
+        let ring = self.cell_rings.get(cell).ok_or("cell not found")?;
+        if !ring.nodes.read()?.is_empty() {
+            return Err("cell still has nodes".into());
+        }
+        self.cell_rings.remove(cell);
+        self.cells.remove_node(cell)
+    }
+
+    /// Changes the replication factor of a cell, leaving every other cell untouched.
+    pub fn set_cell_replication_factor(&mut self, cell: &str, replication_factor: usize) -> Result<(), Box<dyn Error>> {
+        let ring = self.cell_rings.get_mut(cell).ok_or("cell not found")?;
+        ring.set_replication_factor(replication_factor)?;
+        Ok(())
+    }
+
+    /// Adds a node to a cell.
+    ///
+    /// # Arguments
+    ///
+    /// * `cell` - The name of the cell, which must exist.
+    /// * `node` - The node to add. Node IDs are unique across cells.
+    pub fn add_node(&mut self, cell: &str, node: Arc<dyn Node<'a> + 'a>) -> Result<Arc<dyn Node<'a> + 'a>, Box<dyn Error>> {
+        if self.cell_of(node.id()).is_some() {
+            return Err("node already exist".into());
+        }
+        self.cell_rings.get_mut(cell).ok_or("cell not found")?.add_node(node)
+    }
+
+    /// Removes a node from its cell.
+    pub fn remove_node(&mut self, id: &str) -> Result<(), Box<dyn Error>> {
+        let cell = self.cell_of(id).ok_or("node not found")?;
+        self.cell_rings.get_mut(cell).ok_or("cell not found")?.remove_node(id)
+    }
+
+    /// Returns the cells, sorted by name.
+    pub fn cells(&self) -> Vec<&'a str> {
+        self.cell_rings.keys().copied().collect()
+    }
+
+    /// Returns the cell a node belongs to.
+    pub fn cell_of(&self, id: &str) -> Option<&'a str> {
+        self.cell_rings
+            .iter()
+            .find(|(_, ring)| ring.nodes.read().is_ok_and(|nodes| nodes.contains_key(id)))
+            .map(|(cell, _)| *cell)
+    }
+
+    /// Returns the ring of nodes of a cell.
+    pub fn cell_ring(&self, cell: &str) -> Option<&HashRing<'a, H, K>> {
+        self.cell_rings.get(cell)
+    }
+
+    /// Returns the cell the key is assigned to.
+    ///
+    /// Cells without nodes are skipped, so a cell only starts taking keys once its first
+    /// node is added.
+    pub fn get_cell(&self, key: &[u8]) -> Option<&'a str> {
+        self.cells
+            .get_preference_list_n(key, self.cell_rings.len())
+            .iter()
+            .map(|cell| cell.id())
+            .find(|cell| self.cell_rings.get(cell).is_some_and(|ring| !ring.sorted_nodes_hash_set.wait_read().is_empty()))
+    }
+
+    /// Retrieves the node responsible for the given key within its cell.
+    pub fn get_key(&self, key: &[u8]) -> Option<Arc<dyn Node<'a> + 'a>> {
+        self.cell_rings.get(self.get_cell(key)?)?.get_key(key)
+    }
+
+    /// Retrieves up to `replication_factor` distinct nodes for the given key, using the
+    /// replication factor of its cell and only nodes from that cell.
+    pub fn get_preference_list(&self, key: &[u8]) -> ReplicaSet<'a> {
+        match self.get_cell(key).and_then(|cell| self.cell_rings.get(cell)) {
+            Some(ring) => ring.get_preference_list(key),
+            None => Vec::new().into(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::tests::test_nodes;
+    use crate::{CellRing, Config};
+
+    #[test]
+    fn test_cells_bound_placement() {
+        let nodes = test_nodes();
+        let mut ring = CellRing::new(Config::default()).unwrap();
+        assert!(ring.add_node("a", nodes[0].clone()).is_err());
+        ring.add_cell("a", 2).unwrap();
+        ring.add_cell("b", 1).unwrap();
+        assert!(ring.add_cell("a", 2).is_err());
+        assert!(ring.get_key(b"some_key").is_none());
+
+        ring.add_node("a", nodes[0].clone()).unwrap();
+        ring.add_node("a", nodes[1].clone()).unwrap();
+        ring.add_node("b", nodes[2].clone()).unwrap();
+        assert!(ring.add_node("b", nodes[0].clone()).is_err());
+        assert_eq!(ring.cells(), vec!["a", "b"]);
+        assert_eq!(ring.cell_ring("a").unwrap().virtual_nodes_per_node().get("node1"), Some(&2));
+
+        let keys: Vec<String> = (0..200).map(|i| format!("key-{}", i)).collect();
+        let mut seen = [false; 2];
+        for key in &keys {
+            let cell = ring.get_cell(key.as_bytes()).unwrap();
+            seen[(cell == "b") as usize] = true;
+            let replicas = ring.get_preference_list(key.as_bytes());
+            assert_eq!(replicas.len(), if cell == "a" { 2 } else { 1 });
+            assert!(replicas.iter().all(|replica| ring.cell_of(replica.node.id()) == Some(cell)));
+            assert_eq!(ring.cell_of(ring.get_key(key.as_bytes()).unwrap().id()), Some(cell));
+        }
+        assert_eq!(seen, [true, true]);
+
+        // Changes inside cell a don't move keys of cell b.
+        let before: Vec<&str> = keys.iter().map(|key| ring.get_key(key.as_bytes()).unwrap().id()).collect();
+        ring.remove_node("node2").unwrap();
+        ring.set_cell_replication_factor("a", 5).unwrap();
+        for (key, owner) in keys.iter().zip(&before) {
+            if ring.get_cell(key.as_bytes()) == Some("b") {
+                assert_eq!(ring.get_key(key.as_bytes()).unwrap().id(), *owner);
+            }
+        }
+
+        assert!(ring.remove_cell("b").is_err());
+        ring.remove_node("node3").unwrap();
+        ring.remove_cell("b").unwrap();
+        assert!(keys.iter().all(|key| ring.get_cell(key.as_bytes()) == Some("a")));
+    }
+}
@@ -0,0 +1,270 @@
+//! A bounded log of topology changes.
+//!
+//! Members joining late, or falling behind, can catch up by replaying the changes made
+//! since the version they last saw instead of transferring a full snapshot. The log
+//! keeps the last `capacity` versions; older versions have to be restored from a snapshot.
+
+use std::collections::{BTreeSet, HashMap, VecDeque};
+use std::hash::BuildHasher;
+use std::sync::Arc;
+
+use crate::trace::OpTimer;
+use crate::{HashRing, Node, RingEvent};
+
+/// A single change to the topology of the ring.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum Change {
+    NodeAdded(String),
+    NodeRemoved(String),
+    /// The partition is now owned by `node`.
+    PartitionAssigned { partition: usize, node: String },
+    /// The partition no longer exists or has no owner.
+    PartitionUnassigned { partition: usize },
+}
+
+/// The changes that produced a version of the ring.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ChangelogEntry {
+    pub version: u64,
+    pub changes: Vec<Change>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Changelog {
+    capacity: usize,
+    /// The oldest version the entries can be replayed from.
+    base_version: u64,
+    entries: VecDeque<ChangelogEntry>,
+    members: BTreeSet<String>,
+    assignments: HashMap<usize, String>,
+}
+
+impl Changelog {
+    pub(crate) fn is_enabled(&self) -> bool {
+        self.capacity > 0
+    }
+
+    /// Starts recording from the given topology, dropping every entry.
+    ///
+    /// The topology is tracked while the changelog is enabled or event sinks are
+    /// registered, since both are fed from the differences between versions.
+    pub(crate) fn track<'a>(&mut self, version: u64, nodes: &HashMap<String, Arc<dyn Node<'a> + 'a>>, partitions: &HashMap<usize, Arc<dyn Node<'a> + 'a>>) {
+        self.base_version = version;
+        self.entries.clear();
+        self.members = nodes.keys().cloned().collect();
+        self.assignments = partitions.iter().map(|(p, node)| (*p, node.id().to_string())).collect();
+    }
+}
+
+impl<'a, H, K> HashRing<'a, H, K>
+where
+    H: BuildHasher,
+    K: BuildHasher,
+{
+    /// Sets how many versions of the ring are kept in the changelog.
+    ///
+    /// Defaults to `0`, which disables the changelog. Enabling it starts recording from
+    /// the current version.
+    pub fn set_changelog_capacity(&mut self, capacity: usize) {
+        let nodes = self.nodes.wait_read();
+        let partitions = self.partitions.wait_read();
+        let mut changelog = self.changelog.wait_write();
+        if changelog.capacity == 0 {
+            changelog.track(self.version(), &nodes, &partitions);
+        }
+        changelog.capacity = capacity;
+        while changelog.entries.len() > capacity {
+            if let Some(entry) = changelog.entries.pop_front() {
+                changelog.base_version = entry.version;
+            }
+        }
+    }
+
+    /// Returns the changes made after `since_version`, oldest first.
+    ///
+    /// # Arguments
+    ///
+    /// * `since_version` - The last version the caller has applied.
+    ///
+    /// # Returns
+    ///
+    /// * `Option<Vec<ChangelogEntry>>` - The entries to replay, or `None` if the changelog is
+    ///   disabled or no longer reaches back to `since_version`, in which case the caller has
+    ///   to restore a full snapshot.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use std::sync::Arc;
+    /// use hashring::{HashRing, Config, Node, Change};
+    ///
+    /// #[derive(Debug)]
+    /// struct MyNode<'a> {
+    ///     name: &'a str,
+    /// }
+    ///
+    /// impl<'a> Node<'a> for MyNode<'a> {
+    ///     fn id(&self) -> &'a str {
+    ///         self.name
+    ///     }
+    /// }
+    ///
+    /// let mut hash_ring = HashRing::new(Config::default()).unwrap();
+    /// hash_ring.set_changelog_capacity(64);
+    /// let seen = hash_ring.version();
+    ///
+    /// hash_ring.add_node(Arc::new(MyNode { name: "node1" })).unwrap();
+    ///
+    /// let entries = hash_ring.changelog(seen).unwrap();
+    /// assert_eq!(entries[0].changes[0], Change::NodeAdded("node1".to_string()));
+    /// ```
+    pub fn changelog(&self, since_version: u64) -> Option<Vec<ChangelogEntry>> {
+        let changelog = self.changelog.read().ok()?;
+        if changelog.capacity == 0 || since_version < changelog.base_version || since_version > self.version() {
+            return None;
+        }
+        Some(changelog.entries.iter().filter(|entry| entry.version > since_version).cloned().collect())
+    }
+
+    /// Records the difference between the logged topology and the given one under the current
+    /// version, and queues an event reporting it to the sinks as the outcome of `op`.
+    ///
+    /// `members` is `None` when the operation cannot change membership.
+    pub(crate) fn log_changes(
+        &self,
+        op: &'static str,
+        timer: &OpTimer,
+        members: Option<&HashMap<String, Arc<dyn Node<'a> + 'a>>>,
+        partitions: &HashMap<usize, Arc<dyn Node<'a> + 'a>>,
+    ) {
+        let sinks = self.event_sinks.wait_read();
+        let mut changelog = self.changelog.wait_write();
+        if changelog.capacity == 0 && sinks.is_empty() {
+            return;
+        }
+
+        let mut changes = Vec::new();
+        if let Some(members) = members {
+            let current: BTreeSet<String> = members.keys().cloned().collect();
+            changes.extend(changelog.members.difference(&current).map(|id| Change::NodeRemoved(id.clone())));
+            changes.extend(current.difference(&changelog.members).map(|id| Change::NodeAdded(id.clone())));
+            changelog.members = current;
+        }
+
+        let mut part_ids: BTreeSet<usize> = changelog.assignments.keys().copied().collect();
+        part_ids.extend(partitions.keys());
+        for partition in part_ids {
+            match (changelog.assignments.get(&partition), partitions.get(&partition)) {
+                (Some(before), Some(after)) if before == after.id() => {}
+                (_, Some(after)) => {
+                    let node = after.id().to_string();
+                    changelog.assignments.insert(partition, node.clone());
+                    changes.push(Change::PartitionAssigned { partition, node });
+                }
+                (Some(_), None) => {
+                    changelog.assignments.remove(&partition);
+                    changes.push(Change::PartitionUnassigned { partition });
+                }
+                (None, None) => {}
+            }
+        }
+        if changes.is_empty() {
+            return;
+        }
+
+        let version = self.version();
+        if !sinks.is_empty() {
+            let event = RingEvent {
+                op,
+                version,
+                changes: changes.clone(),
+                duration: timer.elapsed(),
+            };
+            self.pending_events.push_event(event);
+        }
+        if changelog.capacity == 0 {
+            return;
+        }
+        match changelog.entries.back_mut() {
+            Some(last) if last.version == version => last.changes.extend(changes),
+            _ => changelog.entries.push_back(ChangelogEntry { version, changes }),
+        }
+        while changelog.entries.len() > changelog.capacity {
+            if let Some(entry) = changelog.entries.pop_front() {
+                changelog.base_version = entry.version;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use crate::tests::test_nodes;
+    use crate::{Change, Config, HashRing};
+
+    #[test]
+    fn test_changelog_replays_to_current_assignment() {
+        let nodes = test_nodes();
+        let mut hash_ring = HashRing::new(Config {
+            partition_count: 20,
+            ..Config::default()
+        })
+        .unwrap();
+        assert!(hash_ring.changelog(0).is_none());
+        hash_ring.add_node(nodes[0].clone()).unwrap();
+
+        hash_ring.set_changelog_capacity(8);
+        let seen = hash_ring.version();
+        let mut members = vec!["node1".to_string()];
+        let mut assignments: HashMap<usize, String> = (0..20)
+            .map(|p| (p, hash_ring.get_partition_owner(p).unwrap().id().to_string()))
+            .collect();
+
+        hash_ring.add_node(nodes[1].clone()).unwrap();
+        hash_ring.begin_update();
+        hash_ring.add_node(nodes[2].clone()).unwrap();
+        hash_ring.remove_node("node1").unwrap();
+        hash_ring.commit();
+        hash_ring.split_partition(3).unwrap();
+
+        for entry in hash_ring.changelog(seen).unwrap() {
+            for change in entry.changes {
+                match change {
+                    Change::NodeAdded(id) => members.push(id),
+                    Change::NodeRemoved(id) => members.retain(|m| *m != id),
+                    Change::PartitionAssigned { partition, node } => {
+                        assignments.insert(partition, node);
+                    }
+                    Change::PartitionUnassigned { partition } => {
+                        assignments.remove(&partition);
+                    }
+                }
+            }
+        }
+
+        members.sort();
+        assert_eq!(members, vec!["node2".to_string(), "node3".to_string()]);
+        assert_eq!(assignments.len(), hash_ring.partition_ids().len());
+        for (partition, node) in assignments {
+            assert_eq!(hash_ring.get_partition_owner(partition).unwrap().id(), node);
+        }
+        assert!(hash_ring.changelog(hash_ring.version()).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_changelog_is_bounded() {
+        let mut hash_ring = HashRing::new(Config::default()).unwrap();
+        hash_ring.set_changelog_capacity(2);
+        let seen = hash_ring.version();
+        for node in test_nodes() {
+            hash_ring.add_node(node).unwrap();
+        }
+        assert!(hash_ring.changelog(seen).is_none());
+        assert_eq!(hash_ring.changelog(hash_ring.version() - 2).unwrap().len(), 2);
+    }
+}
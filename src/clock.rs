@@ -0,0 +1,154 @@
+//! The time source of a ring.
+//!
+//! TTL registrations, grace periods, weight ramps and flap quarantines all measure time.
+//! They read it from the `Clock` of the ring rather than from `Instant::now`, so tests
+//! and simulations can swap in a `MockClock` with `set_clock` and move time forward with
+//! `advance` instead of sleeping. Rings use the `SystemClock` unless told otherwise.
+
+use std::fmt::Debug;
+use std::hash::BuildHasher;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::HashRing;
+
+/// A source of the current time.
+pub trait Clock: Send + Sync + Debug {
+    /// Returns the current instant.
+    fn now(&self) -> Instant;
+}
+
+/// The clock of the operating system.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+}
+
+/// A clock that only moves when told to. Clones share the same time.
+#[derive(Debug, Clone)]
+pub struct MockClock {
+    start: Instant,
+    elapsed_nanos: Arc<AtomicU64>,
+}
+
+impl MockClock {
+    /// Creates a clock stopped at the current instant.
+    pub fn new() -> MockClock {
+        MockClock {
+            start: Instant::now(),
+            elapsed_nanos: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
+    /// Moves the clock forward.
+    pub fn advance(&self, duration: Duration) {
+        let nanos = u64::try_from(duration.as_nanos()).unwrap_or(u64::MAX);
+        let _ = self
+            .elapsed_nanos
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |elapsed| Some(elapsed.saturating_add(nanos)));
+    }
+
+    /// Returns how far the clock has moved since it was created.
+    pub fn elapsed(&self) -> Duration {
+        Duration::from_nanos(self.elapsed_nanos.load(Ordering::SeqCst))
+    }
+}
+
+impl Default for MockClock {
+    fn default() -> Self {
+        MockClock::new()
+    }
+}
+
+impl Clock for MockClock {
+    fn now(&self) -> Instant {
+        self.start + self.elapsed()
+    }
+}
+
+impl<'a, H, K> HashRing<'a, H, K>
+where
+    H: BuildHasher,
+    K: BuildHasher,
+{
+    /// Replaces the clock the ring measures TTLs, grace periods, ramps and quarantines with.
+    ///
+    /// Deadlines already set keep the instants computed from the previous clock.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use std::sync::Arc;
+    /// use std::time::Duration;
+    /// use hashring::{HashRing, Config, Node, MockClock};
+    ///
+    /// #[derive(Debug)]
+    /// struct MyNode<'a> {
+    ///     name: &'a str,
+    /// }
+    ///
+    /// impl<'a> Node<'a> for MyNode<'a> {
+    ///     fn id(&self) -> &'a str {
+    ///         self.name
+    ///     }
+    /// }
+    ///
+    /// let clock = MockClock::new();
+    /// let mut hash_ring = HashRing::new(Config::default()).unwrap();
+    /// hash_ring.set_clock(Arc::new(clock.clone()));
+    /// hash_ring.add_node_with_ttl(Arc::new(MyNode { name: "node1" }), Duration::from_secs(30)).unwrap();
+    ///
+    /// clock.advance(Duration::from_secs(31));
+    /// assert_eq!(hash_ring.expire_stale(hash_ring.now()).unwrap(), ["node1"]);
+    /// ```
+    pub fn set_clock(&self, clock: Arc<dyn Clock>) {
+        *self.clock.wait_write() = clock;
+    }
+
+    /// Returns the current instant of the clock of the ring.
+    pub fn now(&self) -> Instant {
+        self.clock.wait_read().now()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use crate::tests::test_nodes;
+    use crate::{Config, HashRing, MockClock};
+
+    #[test]
+    fn test_mock_clock_drives_timed_behavior() {
+        let nodes = test_nodes();
+        let clock = MockClock::new();
+        let mut hash_ring = HashRing::new(Config::default()).unwrap();
+        hash_ring.set_clock(Arc::new(clock.clone()));
+
+        hash_ring.add_node_with_ttl(nodes[0].clone(), Duration::from_secs(30)).unwrap();
+        hash_ring.add_node(nodes[1].clone()).unwrap();
+        hash_ring.join_gradually(nodes[2].clone(), Duration::from_secs(100)).unwrap();
+        assert_eq!(hash_ring.tick().unwrap(), 0);
+
+        clock.advance(Duration::from_secs(50));
+        assert_eq!(hash_ring.tick().unwrap(), 10);
+        assert_eq!(hash_ring.node_ttl("node1"), Some(Duration::ZERO));
+        assert!(hash_ring.renew("node1").is_err());
+        assert_eq!(hash_ring.expire_stale(hash_ring.now()).unwrap(), ["node1"]);
+
+        hash_ring.remove_node_with_grace("node2", Duration::from_secs(10)).unwrap();
+        assert_eq!(hash_ring.tombstones(), [("node2".to_string(), Duration::from_secs(10))]);
+        clock.advance(Duration::from_secs(10));
+        assert!(hash_ring.tombstones().is_empty());
+
+        clock.advance(Duration::from_secs(40));
+        assert_eq!(hash_ring.tick().unwrap(), 10);
+        assert_eq!(clock.elapsed(), Duration::from_secs(100));
+    }
+}
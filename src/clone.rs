@@ -0,0 +1,301 @@
+//! Copying and comparing rings.
+//!
+//! Cloning a ring copies its state: changes made to the clone don't affect the original
+//! and the other way around. Handles sharing the state of a ring, to hand to a
+//! background task or a router that must follow its membership, are made with `share`.
+//!
+//! Two rings are equal when they have the same configuration, the same members at the
+//! same virtual node positions, the same partition assignment and route keys the same
+//! way: the same split partitions, partitions moved by a rebalance and traffic splits.
+//! Load statistics, history, event sinks and the other bookkeeping of a ring are not
+//! compared. Neither are the hashers, which can't be inspected: only rings of the same
+//! hasher types compare, and two rings whose key hashers are seeded differently, such
+//! as `SipBuildHasher`s with different keys, compare equal while routing keys differently.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::hash::BuildHasher;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use crate::{events, load};
+use crate::sync::{LockMonitor, RwLock};
+use crate::{HashRing, TrafficSplit};
+
+/// The parts of a ring compared by `PartialEq`, with nodes identified by ID.
+#[derive(PartialEq)]
+struct Layout {
+    nodes: BTreeSet<String>,
+    vnodes: BTreeMap<u64, String>,
+    partitions: BTreeMap<usize, String>,
+    splits: BTreeSet<usize>,
+    /// The virtual node each moved partition is routed to.
+    moved: BTreeMap<usize, u64>,
+    traffic_splits: BTreeMap<String, TrafficSplit>,
+}
+
+/// Copies the value behind a lock into a new lock reporting to `locks`.
+fn copy<T: Clone>(lock: &RwLock<T>, locks: &Arc<LockMonitor>) -> Arc<RwLock<T>> {
+    Arc::new(RwLock::new(lock.wait_read().clone(), locks))
+}
+
+impl<'a, H, K> HashRing<'a, H, K>
+where
+    H: BuildHasher,
+    K: BuildHasher,
+{
+    /// Returns a handle sharing the state of this ring.
+    ///
+    /// Nodes added or removed through either handle are seen by both, which is what
+    /// background tasks and routers following the membership of a ring need. The
+    /// configuration, history depth and batches started with `begin_update` are shared too.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use std::sync::Arc;
+    /// use hashring::{HashRing, Config, Node};
+    ///
+    /// #[derive(Debug)]
+    /// struct MyNode<'a> {
+    ///     name: &'a str,
+    /// }
+    ///
+    /// impl<'a> Node<'a> for MyNode<'a> {
+    ///     fn id(&self) -> &'a str {
+    ///         self.name
+    ///     }
+    /// }
+    ///
+    /// let mut hash_ring = HashRing::new(Config::default()).unwrap();
+    /// let shared = hash_ring.share();
+    /// let copy = hash_ring.clone();
+    ///
+    /// hash_ring.add_node(Arc::new(MyNode { name: "node1" })).unwrap();
+    /// assert!(shared.get_key(b"some_key").is_some());
+    /// assert!(copy.get_key(b"some_key").is_none());
+    /// assert!(shared == hash_ring && copy != hash_ring);
+    /// ```
+    pub fn share(&self) -> HashRing<'a, H, K>
+    where
+        H: Clone,
+        K: Clone,
+    {
+        HashRing {
+            config: self.config.clone(),
+            node_hasher: self.node_hasher.clone(),
+            key_hasher: self.key_hasher.clone(),
+            nodes: self.nodes.clone(),
+            sorted_nodes_hash_set: self.sorted_nodes_hash_set.clone(),
+            partitions: self.partitions.clone(),
+            partition_load: self.partition_load.clone(),
+            weights: self.weights.clone(),
+            routes: self.routes.clone(),
+            version: self.version.clone(),
+            drains: self.drains.clone(),
+            ramps: self.ramps.clone(),
+            tenants: self.tenants.clone(),
+            changelog: self.changelog.clone(),
+            event_sinks: self.event_sinks.clone(),
+            pending_events: self.pending_events.clone(),
+            flaps: self.flaps.clone(),
+            tombstones: self.tombstones.clone(),
+            registrations: self.registrations.clone(),
+            capacities: self.capacities.clone(),
+            cost_model: self.cost_model.clone(),
+            partition_states: self.partition_states.clone(),
+            read_policy: self.read_policy.clone(),
+            groups: self.groups.clone(),
+            history: self.history.clone(),
+            ownership_filters: self.ownership_filters.clone(),
+            rng: self.rng.clone(),
+            peer: self.peer.clone(),
+            clock: self.clock.clone(),
+            locks: self.locks.clone(),
+            history_depth: self.history_depth.clone(),
+            update_depth: self.update_depth.clone(),
+            partitions_dirty: self.partitions_dirty.clone(),
+        }
+    }
+
+    fn layout(&self) -> Layout {
+        let nodes = self.nodes.wait_read();
+        let sorted_set = self.sorted_nodes_hash_set.wait_read();
+        let partitions = self.partitions.wait_read();
+        let routes = self.routes.wait_read();
+        Layout {
+            nodes: nodes.keys().cloned().collect(),
+            vnodes: sorted_set.iter().map(|(hash, node)| (*hash, node.id().to_string())).collect(),
+            partitions: partitions.iter().map(|(part_id, node)| (*part_id, node.id().to_string())).collect(),
+            splits: routes.splits.clone(),
+            moved: routes
+                .moved
+                .keys()
+                .filter_map(|part_id| load::moved_point(&routes.moved, &sorted_set, *part_id).map(|point| (*part_id, point)))
+                .collect(),
+            traffic_splits: routes.traffic_splits.iter().map(|(id, split)| (id.clone(), split.clone())).collect(),
+        }
+    }
+}
+
+impl<'a, H, K> Clone for HashRing<'a, H, K>
+where
+    H: Clone,
+    K: Clone,
+{
+    /// Copies the ring. The copy starts with the same lock timeout, but its own lock
+    /// counters, and no event sinks: the sinks of the original only hear of its changes.
+    fn clone(&self) -> Self {
+        let locks = Arc::new(LockMonitor::default());
+        locks.set_timeout(self.locks.timeout());
+        // The members, virtual nodes and partitions are copied together so that they
+        // match; the rest is copied one lock at a time.
+        let (nodes, sorted_set, partitions) = {
+            let nodes = self.nodes.wait_read();
+            let sorted_set = self.sorted_nodes_hash_set.wait_read();
+            let partitions = self.partitions.wait_read();
+            (
+                Arc::new(RwLock::new(nodes.clone(), &locks)),
+                Arc::new(RwLock::new(sorted_set.clone(), &locks)),
+                Arc::new(RwLock::new(partitions.clone(), &locks)),
+            )
+        };
+        HashRing {
+            config: copy(&self.config, &locks),
+            node_hasher: self.node_hasher.clone(),
+            key_hasher: self.key_hasher.clone(),
+            nodes,
+            sorted_nodes_hash_set: sorted_set,
+            partitions,
+            partition_load: copy(&self.partition_load, &locks),
+            weights: copy(&self.weights, &locks),
+            routes: copy(&self.routes, &locks),
+            version: Arc::new(AtomicU64::new(self.version.load(Ordering::Acquire))),
+            drains: copy(&self.drains, &locks),
+            ramps: copy(&self.ramps, &locks),
+            tenants: copy(&self.tenants, &locks),
+            changelog: copy(&self.changelog, &locks),
+            event_sinks: Arc::new(RwLock::new(Vec::new(), &locks)),
+            pending_events: Arc::new(events::PendingEvents::default()),
+            flaps: copy(&self.flaps, &locks),
+            tombstones: copy(&self.tombstones, &locks),
+            registrations: copy(&self.registrations, &locks),
+            capacities: copy(&self.capacities, &locks),
+            cost_model: copy(&self.cost_model, &locks),
+            partition_states: copy(&self.partition_states, &locks),
+            read_policy: copy(&self.read_policy, &locks),
+            groups: copy(&self.groups, &locks),
+            history: copy(&self.history, &locks),
+            ownership_filters: copy(&self.ownership_filters, &locks),
+            rng: copy(&self.rng, &locks),
+            peer: copy(&self.peer, &locks),
+            clock: copy(&self.clock, &locks),
+            locks,
+            history_depth: Arc::new(AtomicUsize::new(self.history_depth.load(Ordering::Acquire))),
+            update_depth: Arc::new(AtomicUsize::new(self.update_depth.load(Ordering::Acquire))),
+            partitions_dirty: Arc::new(AtomicBool::new(self.partitions_dirty.load(Ordering::Acquire))),
+        }
+    }
+}
+
+impl<'a, H, K> PartialEq for HashRing<'a, H, K>
+where
+    H: BuildHasher,
+    K: BuildHasher,
+{
+    fn eq(&self, other: &HashRing<'a, H, K>) -> bool {
+        // Each layout is taken on its own, so comparing never holds the locks of both rings.
+        *self.config() == *other.config() && self.layout() == other.layout()
+    }
+}
+
+/// Configurations and traffic split fractions are validated when set, so they hold no NaN.
+impl<'a, H, K> Eq for HashRing<'a, H, K>
+where
+    H: BuildHasher,
+    K: BuildHasher,
+{
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    use crate::tests::{access, key_on, test_nodes};
+    use crate::{Config, HashRing, RingEvent};
+
+    #[test]
+    fn test_clones_are_independent_and_comparable() {
+        let mut hash_ring = HashRing::new(Config::default()).unwrap();
+        let nodes = test_nodes();
+        hash_ring.add_node(nodes[0].clone()).unwrap();
+        hash_ring.add_node(nodes[1].clone()).unwrap();
+        hash_ring.record_partition_load(0, 10);
+
+        let mut copy = hash_ring.clone();
+        assert!(copy == hash_ring);
+        assert_eq!(copy.version(), hash_ring.version());
+        copy.add_node(nodes[2].clone()).unwrap();
+        assert!(copy != hash_ring);
+        assert_eq!(hash_ring.virtual_nodes_per_node().len(), 2);
+        assert_eq!(hash_ring.version() + 1, copy.version());
+
+        // Equality ignores load and the order in which nodes joined.
+        let mut other = HashRing::new(Config::default()).unwrap();
+        other.add_node(nodes[1].clone()).unwrap();
+        other.add_node(nodes[0].clone()).unwrap();
+        assert!(other == hash_ring);
+        let namespaced = HashRing::new(Config {
+            key_namespace: Some("tenant".to_string()),
+            ..Config::default()
+        })
+        .unwrap();
+        assert!(namespaced != HashRing::new(Config::default()).unwrap());
+
+        // Rings routing keys differently aren't equal, even with the same partition table.
+        let mut rebalanced = hash_ring.clone();
+        let (hot, part_id) = key_on(&rebalanced, "node1", &[]);
+        let (warm, _) = key_on(&rebalanced, "node1", &[part_id]);
+        access(&rebalanced, &hot, 100);
+        access(&rebalanced, &warm, 100);
+        assert_eq!(rebalanced.rebalance(1).len(), 1);
+        assert!(rebalanced != hash_ring);
+        let mut canary = hash_ring.clone();
+        canary.set_traffic_split("node1", "node2", 0.5).unwrap();
+        assert!(canary != hash_ring);
+
+        let mut shared = hash_ring.share();
+        shared.remove_node("node1").unwrap();
+        assert_eq!(hash_ring.virtual_nodes_per_node().len(), 1);
+        assert!(shared == hash_ring);
+    }
+
+    #[test]
+    fn test_shared_handles_share_batches_and_clones_drop_sinks() {
+        let mut hash_ring = HashRing::new(Config::default()).unwrap();
+        let nodes = test_nodes();
+        let mut shared = hash_ring.share();
+        shared.set_history_depth(2);
+        assert_eq!(hash_ring.history_depth(), 2);
+
+        // A batch started through one handle defers the partitions of changes made through the other.
+        hash_ring.begin_update();
+        shared.add_node(nodes[0].clone()).unwrap();
+        assert!(shared.is_updating());
+        assert!(hash_ring.get_partition_owner(0).is_none());
+        assert!(shared.commit());
+        assert!(hash_ring.get_partition_owner(0).is_some());
+        hash_ring.check_invariants().unwrap();
+
+        let events = Arc::new(AtomicUsize::new(0));
+        let counter = events.clone();
+        hash_ring.add_event_sink(Arc::new(move |_: &RingEvent| {
+            counter.fetch_add(1, Ordering::SeqCst);
+        }));
+        let mut copy = hash_ring.clone();
+        copy.add_node(nodes[1].clone()).unwrap();
+        assert_eq!(events.load(Ordering::SeqCst), 0);
+        shared.add_node(nodes[2].clone()).unwrap();
+        assert_eq!(events.load(Ordering::SeqCst), 1);
+    }
+}
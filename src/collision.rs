@@ -0,0 +1,269 @@
+//! Handling of virtual nodes that hash to an occupied point.
+//!
+//! Replica `i` of a node is placed at the hash of `"{id}:{i}"`. When that point is
+//! already taken by another virtual node, the `CollisionPolicy` of the ring decides
+//! what happens instead of silently overwriting it. Rehashing tries `"{id}:{i}:{salt}"`
+//! for increasing salts, so placement stays deterministic.
+//!
+//! With IDs containing `:` these strings are ambiguous: replica 2 of `"a:1"` and salt 2
+//! of replica 1 of `"a"` are both `"a:1:2"`. `VnodeEncoding::LengthPrefixed` hashes the
+//! ID length, the ID, the replica and the salt instead, which cannot collide. It places
+//! virtual nodes elsewhere, so it is only used from `RingFormatVersion::V2` on.
+
+use std::collections::{BTreeMap, HashSet};
+use std::error::Error;
+use std::hash::{BuildHasher, Hasher};
+use std::ops::{Deref, Range};
+use std::sync::Arc;
+
+use crate::{HashRing, LocalNode, Node};
+
+/// The number of salts tried before giving up on placing a virtual node.
+const MAX_REHASH_ATTEMPTS: usize = 16;
+
+/// How the ID, replica and salt of a virtual node are encoded before hashing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum VnodeEncoding {
+    /// `"{id}:{replica}"`, or `"{id}:{replica}:{salt}"` when rehashing.
+    #[default]
+    Legacy,
+    /// The length of the ID, the ID, the replica and the salt, with numbers as 4-byte
+    /// big-endian integers.
+    LengthPrefixed,
+}
+
+/// What to do when a virtual node hashes to a point that is already taken.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum CollisionPolicy {
+    /// Reject the node.
+    Error,
+    /// Place the virtual node at a salted hash instead.
+    #[default]
+    Rehash,
+    /// Keep the existing virtual node, so the new node has one virtual node fewer.
+    KeepFirst,
+}
+
+impl<'a, H, K> HashRing<'a, H, K>
+where
+    H: BuildHasher,
+    K: BuildHasher,
+{
+    pub(crate) fn hash_with_salt(&self, name: &str, replica: usize, salt: usize) -> u64 {
+        vnode_hash(&self.node_hasher, self.config().format_version.vnode_encoding(), name, replica, salt)
+    }
+
+    /// Inserts the given replicas of a node according to `policy`.
+    ///
+    /// Placement is computed before anything is inserted, so on error `sorted_set` is unchanged.
+    pub(crate) fn place_vnodes(
+        &self,
+        sorted_set: &mut BTreeMap<u64, Arc<dyn Node<'a> + 'a>>,
+        node: &Arc<dyn Node<'a> + 'a>,
+        replicas: Range<usize>,
+        policy: CollisionPolicy,
+    ) -> Result<usize, Box<dyn Error>> {
+        place_vnodes(sorted_set, node, replicas, policy, |replica, salt| self.hash_with_salt(node.id(), replica, salt))
+    }
+
+    /// Removes the given replicas of a node, wherever they were placed.
+    pub(crate) fn remove_vnodes(&self, sorted_set: &mut BTreeMap<u64, Arc<dyn Node<'a> + 'a>>, id: &str, replicas: Range<usize>) -> usize {
+        remove_vnodes(sorted_set, id, replicas, |replica, salt| self.hash_with_salt(id, replica, salt))
+    }
+}
+
+/// Hashes replica `replica` of the node `name`, rehashed with `salt` if it isn't 0.
+pub(crate) fn vnode_hash(node_hasher: &impl BuildHasher, encoding: VnodeEncoding, name: &str, replica: usize, salt: usize) -> u64 {
+    let mut hasher = node_hasher.build_hasher();
+    match encoding {
+        VnodeEncoding::Legacy if salt == 0 => hasher.write(format!("{}:{}", name, replica).as_bytes()),
+        VnodeEncoding::Legacy => hasher.write(format!("{}:{}:{}", name, replica, salt).as_bytes()),
+        VnodeEncoding::LengthPrefixed => {
+            hasher.write(&(name.len() as u32).to_be_bytes());
+            hasher.write(name.as_bytes());
+            hasher.write(&(replica as u32).to_be_bytes());
+            hasher.write(&(salt as u32).to_be_bytes());
+        }
+    }
+    hasher.finish()
+}
+
+/// Inserts the given replicas of a node, hashed by `hash(replica, salt)`, according to
+/// `policy`. Works with any pointer to a node, so that every ring places virtual nodes
+/// the same way.
+pub(crate) fn place_vnodes<'a, N>(
+    sorted_set: &mut BTreeMap<u64, N>,
+    node: &N,
+    replicas: Range<usize>,
+    policy: CollisionPolicy,
+    hash: impl Fn(usize, usize) -> u64,
+) -> Result<usize, Box<dyn Error>>
+where
+    N: Deref + Clone,
+    N::Target: LocalNode<'a>,
+{
+    let mut placed: HashSet<u64> = HashSet::new();
+    for replica in replicas {
+        let taken = |hash: &u64| sorted_set.contains_key(hash) || placed.contains(hash);
+        let first = hash(replica, 0);
+        if !taken(&first) {
+            placed.insert(first);
+            continue;
+        }
+        match policy {
+            CollisionPolicy::Error => {
+                let other = sorted_set.get(&first).map_or(node.id(), |n| n.id());
+                return Err(format!("virtual node {} of {} collides with {}", replica, node.id(), other).into());
+            }
+            CollisionPolicy::KeepFirst => {}
+            CollisionPolicy::Rehash => {
+                let salted = (1..MAX_REHASH_ATTEMPTS)
+                    .map(|salt| hash(replica, salt))
+                    .find(|hash| !taken(hash))
+                    .ok_or_else(|| format!("unable to place virtual node {} of {}", replica, node.id()))?;
+                placed.insert(salted);
+            }
+        }
+    }
+
+    let count = placed.len();
+    for hash in placed {
+        sorted_set.insert(hash, node.clone());
+    }
+    Ok(count)
+}
+
+/// Removes the given replicas of a node, hashed by `hash(replica, salt)`, wherever they
+/// were placed.
+pub(crate) fn remove_vnodes<'a, N>(sorted_set: &mut BTreeMap<u64, N>, id: &str, replicas: Range<usize>, hash: impl Fn(usize, usize) -> u64) -> usize
+where
+    N: Deref,
+    N::Target: LocalNode<'a>,
+{
+    let mut removed = 0;
+    for replica in replicas {
+        for salt in 0..MAX_REHASH_ATTEMPTS {
+            let hash = hash(replica, salt);
+            if sorted_set.get(&hash).is_some_and(|n| n.id() == id) {
+                sorted_set.remove(&hash);
+                removed += 1;
+                break;
+            }
+        }
+    }
+    removed
+}
+
+#[cfg(test)]
+mod tests {
+    use std::hash::{BuildHasherDefault, Hasher};
+    use std::sync::Arc;
+
+    use crate::tests::TestNode;
+    use crate::{CollisionPolicy, Config, FlapPolicy, HashRing, Node, RingFormatVersion};
+
+    /// Ignores everything before the first `:`, so replica `i` of every node collides.
+    #[derive(Default)]
+    struct IdBlindHasher {
+        hash: u64,
+        seen_colon: bool,
+    }
+
+    impl Hasher for IdBlindHasher {
+        fn write(&mut self, bytes: &[u8]) {
+            for byte in bytes {
+                self.seen_colon |= *byte == b':';
+                if self.seen_colon {
+                    self.hash = self.hash.wrapping_mul(31).wrapping_add(*byte as u64);
+                }
+            }
+        }
+
+        fn finish(&self) -> u64 {
+            self.hash
+        }
+    }
+
+    fn ring(policy: CollisionPolicy) -> HashRing<'static, BuildHasherDefault<IdBlindHasher>> {
+        let config = Config {
+            replication_factor: 4,
+            partition_count: 10,
+            collision_policy: policy,
+            ..Config::default()
+        };
+        let mut hash_ring = HashRing::with_hasher(config, Default::default()).unwrap();
+        hash_ring.add_node(node("node1")).unwrap();
+        hash_ring
+    }
+
+    fn node(name: &'static str) -> Arc<dyn Node<'static>> {
+        Arc::new(TestNode {
+            ip_addr: "127.0.0.1".to_string(),
+            name,
+        })
+    }
+
+    #[test]
+    fn test_error_policy_rejects_node() {
+        let mut hash_ring = ring(CollisionPolicy::Error);
+        assert!(hash_ring.add_node(node("node2")).is_err());
+        assert_eq!(hash_ring.virtual_nodes_per_node().get("node1"), Some(&4));
+        assert_eq!(hash_ring.virtual_nodes_per_node().get("node2"), None);
+    }
+
+    #[test]
+    fn test_rejected_joins_do_not_count_as_flaps() {
+        let mut hash_ring = ring(CollisionPolicy::Error);
+        hash_ring.set_flap_policy(Some(FlapPolicy {
+            max_changes: 1,
+            ..FlapPolicy::default()
+        }));
+        for _ in 0..3 {
+            assert!(hash_ring.add_node(node("node2")).is_err());
+        }
+        assert!(!hash_ring.is_quarantined("node2"));
+    }
+
+    #[test]
+    fn test_keep_first_policy_keeps_existing_vnodes() {
+        let mut hash_ring = ring(CollisionPolicy::KeepFirst);
+        hash_ring.add_node(node("node2")).unwrap();
+        assert_eq!(hash_ring.virtual_nodes_per_node().get("node2"), None);
+
+        hash_ring.remove_node("node2").unwrap();
+        assert_eq!(hash_ring.virtual_nodes_per_node().get("node1"), Some(&4));
+    }
+
+    #[test]
+    fn test_rehash_policy_places_every_vnode() {
+        let mut hash_ring = ring(CollisionPolicy::Rehash);
+        hash_ring.add_node(node("node2")).unwrap();
+        assert_eq!(hash_ring.virtual_nodes_per_node().get("node2"), Some(&4));
+        // Finger tables start from where the rehashed virtual nodes ended up.
+        let table = hash_ring.finger_table("node2", 8).unwrap();
+        assert_eq!(hash_ring.sorted_nodes_hash_set.read().unwrap()[&table.position].id(), "node2");
+
+        hash_ring.remove_node("node1").unwrap();
+        assert_eq!(hash_ring.virtual_nodes_per_node().get("node2"), Some(&4));
+        hash_ring.remove_node("node2").unwrap();
+        assert!(hash_ring.virtual_nodes_per_node().is_empty());
+    }
+
+    #[test]
+    fn test_length_prefixed_encoding_is_unambiguous() {
+        let legacy = HashRing::new(Config::default()).unwrap();
+        assert_eq!(legacy.hash_with_salt("a:1", 2, 0), legacy.hash_with_salt("a", 1, 2));
+
+        let prefixed = HashRing::new(Config {
+            format_version: RingFormatVersion::V2,
+            ..Config::default()
+        })
+        .unwrap();
+        assert_ne!(prefixed.hash_with_salt("a:1", 2, 0), prefixed.hash_with_salt("a", 1, 2));
+        assert_ne!(prefixed.hash_with_salt("a", 1, 0), legacy.hash_with_salt("a", 1, 0));
+    }
+}
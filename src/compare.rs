@@ -0,0 +1,223 @@
+//! Measuring how many keys a migration between two rings moves.
+//!
+//! Before switching to the target ring of a `ShadowedRing`, operators want to know how
+//! much data the switch remaps and which parts of the keyspace to copy. When both rings
+//! hash keys the same way and only node placement changes (a new node hasher, more
+//! virtual nodes, different members), `compare` gives the exact answer as ranges of key
+//! hashes. When the key hasher itself changes, e.g. from the standard library's
+//! `DefaultHasher` to xxh3, a key's position differs between the rings and there are no
+//! ranges to compare: `remap_fraction` measures the change over a sample of real keys.
+
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::hash::BuildHasher;
+use std::sync::Arc;
+
+use crate::{Node, ShadowedRing};
+
+/// A range of key hashes whose owner changes between the current and the target ring,
+/// or between two versions of a ring.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MovedRange {
+    /// The first key hash of the range.
+    pub start: u64,
+    /// The last key hash of the range, inclusive.
+    pub end: u64,
+    /// The owner in the current ring or older version, `None` if it has no nodes.
+    pub from: Option<String>,
+    /// The owner in the target ring or newer version, `None` if it has no nodes.
+    pub to: Option<String>,
+}
+
+/// The exact difference in key placement between two rings.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RingComparison {
+    /// The fraction of the keyspace that changes owner, from `0.0` to `1.0`.
+    pub moved_fraction: f64,
+    /// The ranges that change owner, in ascending order, adjacent ranges with the same
+    /// owners merged.
+    pub moved_ranges: Vec<MovedRange>,
+}
+
+fn owner_at<'a>(sorted_set: &BTreeMap<u64, Arc<dyn Node<'a> + 'a>>, hash: u64) -> Option<String> {
+    sorted_set
+        .range(hash..)
+        .next()
+        .or_else(|| sorted_set.iter().next())
+        .map(|(_, node)| node.id().to_string())
+}
+
+/// Returns the ranges of key hashes whose owner differs between two sets of virtual
+/// nodes, and the fraction of the keyspace they cover.
+pub(crate) fn diff_ranges<'a>(
+    current_set: &BTreeMap<u64, Arc<dyn Node<'a> + 'a>>,
+    target_set: &BTreeMap<u64, Arc<dyn Node<'a> + 'a>>,
+) -> (Vec<MovedRange>, f64) {
+    // Between two consecutive boundaries of either ring, both owners are constant.
+    let mut boundaries: Vec<u64> = current_set.keys().chain(target_set.keys()).copied().collect();
+    boundaries.sort_unstable();
+    boundaries.dedup();
+
+    let mut moved_ranges: Vec<MovedRange> = Vec::new();
+    let mut moved: u128 = 0;
+    let mut add_range = |start: u64, end: u64| {
+        let from = owner_at(current_set, end);
+        let to = owner_at(target_set, end);
+        if from == to {
+            return;
+        }
+        moved += (end - start) as u128 + 1;
+        match moved_ranges.last_mut() {
+            Some(last) if last.end.wrapping_add(1) == start && last.from == from && last.to == to => last.end = end,
+            _ => moved_ranges.push(MovedRange { start, end, from, to }),
+        }
+    };
+    match (boundaries.first(), boundaries.last()) {
+        (Some(&first), Some(&last)) => {
+            // The ranges after the last boundary belong to the first one.
+            add_range(0, first);
+            for pair in boundaries.windows(2) {
+                add_range(pair[0] + 1, pair[1]);
+            }
+            if last < u64::MAX {
+                add_range(last + 1, u64::MAX);
+            }
+        }
+        _ => add_range(0, u64::MAX),
+    }
+    (moved_ranges, moved as f64 / (u64::MAX as f64 + 1.0))
+}
+
+impl<'a, H, K, TH, TK> ShadowedRing<'a, H, K, TH, TK>
+where
+    H: BuildHasher,
+    K: BuildHasher,
+    TH: BuildHasher,
+    TK: BuildHasher,
+{
+    /// Computes exactly which key hashes change owner when switching to the target ring.
+    ///
+    /// Both rings must hash keys identically, with the same key hasher and key namespace.
+    /// Traffic splits are ignored.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<RingComparison, Box<dyn Error>>` - The moved ranges, or an error if the
+    ///   key namespaces differ.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use std::sync::Arc;
+    /// use hashring::{HashRing, Config, Node, ShadowedRing};
+    ///
+    /// #[derive(Debug)]
+    /// struct MyNode<'a> {
+    ///     name: &'a str,
+    /// }
+    ///
+    /// impl<'a> Node<'a> for MyNode<'a> {
+    ///     fn id(&self) -> &'a str {
+    ///         self.name
+    ///     }
+    /// }
+    ///
+    /// let mut current = HashRing::new(Config::default()).unwrap();
+    /// let mut config = Config::default();
+    /// config.replication_factor = 40;
+    /// let mut target = HashRing::new(config).unwrap();
+    /// for name in ["node1", "node2"] {
+    ///     current.add_node(Arc::new(MyNode { name })).unwrap();
+    ///     target.add_node(Arc::new(MyNode { name })).unwrap();
+    /// }
+    ///
+    /// let comparison = ShadowedRing::new(current, target).compare().unwrap();
+    /// println!("{:.1}% of the keys move", comparison.moved_fraction * 100.0);
+    /// for range in &comparison.moved_ranges {
+    ///     println!("{:016x}..={:016x}: {:?} -> {:?}", range.start, range.end, range.from, range.to);
+    /// }
+    /// ```
+    pub fn compare(&self) -> Result<RingComparison, Box<dyn Error>> {
+        let (current, target) = (self.current(), self.target());
+        if current.config().key_namespace != target.config().key_namespace {
+            return Err("rings with different key namespaces cannot be compared by range".into());
+        }
+        let current_set = current.sorted_nodes_hash_set.read()?;
+        let target_set = target.sorted_nodes_hash_set.read()?;
+        let (moved_ranges, moved_fraction) = diff_ranges(&current_set, &target_set);
+
+        Ok(RingComparison {
+            moved_fraction,
+            moved_ranges,
+        })
+    }
+    /// Returns the fraction of the given keys that change owner when switching to the
+    /// target ring, whatever hashers either ring uses.
+    pub fn remap_fraction<'k>(&self, keys: impl Iterator<Item = &'k [u8]>) -> f64 {
+        let (mut total, mut moved) = (0usize, 0usize);
+        for key in keys {
+            total += 1;
+            if self.get_key(key).is_moving() {
+                moved += 1;
+            }
+        }
+        if total == 0 {
+            return 0.0;
+        }
+        moved as f64 / total as f64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::hash_map::DefaultHasher;
+    use std::hash::BuildHasherDefault;
+
+    use crate::tests::test_nodes;
+    use crate::{Config, HashRing, ShadowedRing};
+
+    #[test]
+    fn test_compare_is_exact() {
+        let nodes = test_nodes();
+        let mut current = HashRing::new(Config::default()).unwrap();
+        let mut target = HashRing::new(Config::default()).unwrap();
+        for node in &nodes {
+            current.add_node(node.clone()).unwrap();
+        }
+        target.add_node(nodes[0].clone()).unwrap();
+        target.add_node(nodes[1].clone()).unwrap();
+
+        // Removing node3 moves exactly its share of the keyspace, and only its keys.
+        let share = current.keyspace_fraction("node3");
+        let shadowed = ShadowedRing::new(current, target);
+        let comparison = shadowed.compare().unwrap();
+        assert!((comparison.moved_fraction - share).abs() < 1e-9);
+        assert!(comparison.moved_ranges.iter().all(|range| range.from.as_deref() == Some("node3") && range.start <= range.end));
+        assert!(comparison.moved_ranges.windows(2).all(|pair| pair[0].end < pair[1].start));
+
+        let keys: Vec<String> = (0..2000).map(|i| format!("key-{}", i)).collect();
+        let fraction = shadowed.remap_fraction(keys.iter().map(|key| key.as_bytes()));
+        assert!((fraction - share).abs() < 0.05, "{} {}", fraction, share);
+
+        let identical = ShadowedRing::new(shadowed.target().clone(), shadowed.target().clone());
+        assert_eq!(identical.compare().unwrap().moved_fraction, 0.0);
+        let empty = ShadowedRing::new(HashRing::new(Config::default()).unwrap(), shadowed.target().clone());
+        assert_eq!(empty.compare().unwrap().moved_fraction, 1.0);
+    }
+
+    #[test]
+    fn test_remap_fraction_across_key_hashers() {
+        let mut current: HashRing<BuildHasherDefault<DefaultHasher>> = HashRing::with_hasher(Config::default(), BuildHasherDefault::default()).unwrap();
+        let mut target = HashRing::new(Config::default()).unwrap();
+        for node in test_nodes() {
+            current.add_node(node.clone()).unwrap();
+            target.add_node(node).unwrap();
+        }
+        let shadowed = ShadowedRing::new(current, target);
+        let keys: Vec<String> = (0..3000).map(|i| format!("key-{}", i)).collect();
+        let fraction = shadowed.remap_fraction(keys.iter().map(|key| key.as_bytes()));
+        // Unrelated hashers place keys independently: about 2/3 move with three nodes.
+        assert!(fraction > 0.4 && fraction < 0.9, "{}", fraction);
+        assert_eq!(shadowed.remap_fraction(std::iter::empty()), 0.0);
+    }
+}
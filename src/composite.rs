@@ -0,0 +1,108 @@
+//! Keys made of several fields, such as a tenant and an object ID.
+//!
+//! Joining fields with a separator is ambiguous (`"a:b" + "c"` and `"a" + "b:c"`) and
+//! every client has to agree on the separator. Composite keys are encoded instead as the
+//! concatenation of every field prefixed with its length as a 4-byte big-endian integer:
+//!
+//! ```text
+//! ["tenant-1", "42"] => 00 00 00 08 't' 'e' 'n' 'a' 'n' 't' '-' '1' 00 00 00 02 '4' '2'
+//! ```
+//!
+//! The encoding is part of the crate's stable format, so clients in other languages can
+//! reproduce it and hash composite keys identically.
+
+use std::hash::BuildHasher;
+use std::sync::Arc;
+
+use crate::{HashRing, Node};
+
+/// Encodes the fields of a composite key into the bytes that are hashed.
+///
+/// # Example
+///
+/// ```
+/// use hashring::composite_key;
+///
+/// assert_eq!(composite_key(&[b"ab", b"c"]), b"\0\0\0\x02ab\0\0\0\x01c");
+/// assert_ne!(composite_key(&[b"ab", b"c"]), composite_key(&[b"a", b"bc"]));
+/// ```
+pub fn composite_key(parts: &[&[u8]]) -> Vec<u8> {
+    let mut key = Vec::with_capacity(parts.iter().map(|part| 4 + part.len()).sum());
+    for part in parts {
+        key.extend_from_slice(&(part.len() as u32).to_be_bytes());
+        key.extend_from_slice(part);
+    }
+    key
+}
+
+impl<'a, H, K> HashRing<'a, H, K>
+where
+    H: BuildHasher,
+    K: BuildHasher,
+{
+    /// Retrieves the node responsible for a composite key.
+    ///
+    /// # Arguments
+    ///
+    /// * `parts` - The fields of the key, in order.
+    ///
+    /// # Returns
+    ///
+    /// * `Option<Arc<dyn Node<'a> + 'a>>` - The owner of `composite_key(parts)`.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use std::sync::Arc;
+    /// use hashring::{composite_key, HashRing, Config, Node};
+    ///
+    /// #[derive(Debug)]
+    /// struct MyNode<'a> {
+    ///     name: &'a str,
+    /// }
+    ///
+    /// impl<'a> Node<'a> for MyNode<'a> {
+    ///     fn id(&self) -> &'a str {
+    ///         self.name
+    ///     }
+    /// }
+    ///
+    /// let mut hash_ring = HashRing::new(Config::default()).unwrap();
+    /// hash_ring.add_node(Arc::new(MyNode { name: "node1" })).unwrap();
+    /// hash_ring.add_node(Arc::new(MyNode { name: "node2" })).unwrap();
+    ///
+    /// let node = hash_ring.get_composite(&[b"tenant-1", b"object-42"]).unwrap();
+    /// assert_eq!(node.id(), hash_ring.get_key(&composite_key(&[b"tenant-1", b"object-42"])).unwrap().id());
+    /// ```
+    pub fn get_composite(&self, parts: &[&[u8]]) -> Option<Arc<dyn Node<'a> + 'a>> {
+        self.get_key(&composite_key(parts))
+    }
+
+    /// Returns the partition a composite key falls into.
+    pub fn get_composite_partition(&self, parts: &[&[u8]]) -> usize {
+        self.get_partition(&composite_key(parts))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::composite_key;
+    use crate::tests::test_nodes;
+    use crate::{Config, HashRing};
+
+    #[test]
+    fn test_composite_keys_are_unambiguous() {
+        assert_eq!(composite_key(&[]), b"");
+        assert_eq!(composite_key(&[b""]), b"\0\0\0\0");
+        assert_ne!(composite_key(&[b"", b"a"]), composite_key(&[b"a", b""]));
+        assert_eq!(composite_key(&[b"tenant-1", b"42"]), b"\0\0\0\x08tenant-1\0\0\0\x0242");
+
+        let mut hash_ring = HashRing::new(Config::default()).unwrap();
+        for node in test_nodes() {
+            hash_ring.add_node(node).unwrap();
+        }
+        let key = composite_key(&[b"tenant-1", b"42"]);
+        assert_eq!(hash_ring.get_composite(&[b"tenant-1", b"42"]).unwrap().id(), hash_ring.get_key(&key).unwrap().id());
+        assert_eq!(hash_ring.get_composite_partition(&[b"tenant-1", b"42"]), hash_ring.get_partition(&key));
+    }
+}
@@ -0,0 +1,257 @@
+//! Applying ring mutations through a consensus layer.
+//!
+//! When replicas of the ring sit behind Raft or another consensus protocol, mutations
+//! must not be applied where they are made. Instead a `ProposedChange` is encoded with
+//! `to_bytes` and proposed to the log, and every replica applies it with
+//! `apply_committed` once the log commits it. Changes are applied deterministically, so
+//! replicas applying the same log end up with the same ring, and a change that fails,
+//! such as adding a node that is already a member, fails on every replica alike.
+//!
+//! Layout (little endian):
+//!
+//! ```text
+//! magic "HRPC" | kind: u8 | node id | partition: u64 (assignments only) |
+//! crc32 of everything before: u32
+//! ```
+
+use std::error::Error;
+use std::hash::BuildHasher;
+use std::sync::Arc;
+
+use crate::crc::crc32;
+use crate::persist::{Decoder, Encoder};
+use crate::trace::OpTimer;
+use crate::{HashRing, Node};
+
+const MAGIC: &[u8; 4] = b"HRPC";
+
+/// A mutation of the ring, proposed to a consensus log.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum ProposedChange {
+    AddNode(String),
+    RemoveNode(String),
+    /// Assigns the partition to the node, routing its keys to it, until the next membership change.
+    AssignPartition { partition: usize, node: String },
+}
+
+impl ProposedChange {
+    /// Encodes the change, to propose it to the log.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut enc = Encoder::new();
+        enc.raw(MAGIC);
+        match self {
+            ProposedChange::AddNode(id) => {
+                enc.u8(0);
+                enc.str(id);
+            }
+            ProposedChange::RemoveNode(id) => {
+                enc.u8(1);
+                enc.str(id);
+            }
+            ProposedChange::AssignPartition { partition, node } => {
+                enc.u8(2);
+                enc.str(node);
+                enc.u64(*partition as u64);
+            }
+        }
+        let mut buf = enc.into_inner();
+        let checksum = crc32(&buf);
+        buf.extend_from_slice(&checksum.to_le_bytes());
+        buf
+    }
+
+    /// Decodes a change encoded by `to_bytes`, e.g. from a committed log entry.
+    pub fn from_bytes(data: &[u8]) -> Result<ProposedChange, Box<dyn Error>> {
+        if data.len() < MAGIC.len() + 4 {
+            return Err("proposed change is truncated".into());
+        }
+        let (body, checksum) = data.split_at(data.len() - 4);
+        if crc32(body) != u32::from_le_bytes(checksum.try_into()?) {
+            return Err("proposed change checksum mismatch".into());
+        }
+        let mut dec = Decoder::new(body);
+        if dec.take(MAGIC.len())? != MAGIC {
+            return Err("not a proposed change".into());
+        }
+        let change = match dec.u8()? {
+            0 => ProposedChange::AddNode(dec.string()?),
+            1 => ProposedChange::RemoveNode(dec.string()?),
+            2 => {
+                let node = dec.string()?;
+                let partition = usize::try_from(dec.u64()?)?;
+                ProposedChange::AssignPartition { partition, node }
+            }
+            kind => return Err(format!("unknown proposed change kind {}", kind).into()),
+        };
+        if !dec.is_empty() {
+            return Err("trailing bytes after proposed change".into());
+        }
+        Ok(change)
+    }
+}
+
+impl<'a, H, K> HashRing<'a, H, K>
+where
+    H: BuildHasher,
+    K: BuildHasher,
+{
+    /// Checks that a change would apply to the ring as it is now, so a leader can reject it
+    /// before proposing it.
+    ///
+    /// # Arguments
+    ///
+    /// * `change` - The change to check.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<(), Box<dyn Error>>` - An error describing why the change would fail.
+    pub fn check_proposal(&self, change: &ProposedChange) -> Result<(), Box<dyn Error>> {
+        let nodes = self.nodes.read()?;
+        match change {
+            ProposedChange::AddNode(id) => {
+                self.config().node_id_policy.validate(id)?;
+                if nodes.contains_key(id) {
+                    return Err("node already exist".into());
+                }
+            }
+            ProposedChange::RemoveNode(id) => {
+                if !nodes.contains_key(id) {
+                    return Err("node not found".into());
+                }
+            }
+            ProposedChange::AssignPartition { partition, node } => {
+                if !nodes.contains_key(node) {
+                    return Err("node not found".into());
+                }
+                drop(nodes);
+                if !self.has_partition(*partition) {
+                    return Err(format!("partition {} does not exist", partition).into());
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Applies a change committed by the consensus log.
+    ///
+    /// # Arguments
+    ///
+    /// * `change` - The committed change.
+    /// * `resolver` - Returns the node for the ID of an added node, or `None` if it is unknown.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<u64, Box<dyn Error>>` - The version of the ring after the change. On error the ring is unchanged.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use std::sync::Arc;
+    /// use hashring::{HashRing, Config, Node, ProposedChange};
+    ///
+    /// #[derive(Debug)]
+    /// struct MyNode<'a> {
+    ///     name: &'a str,
+    /// }
+    ///
+    /// impl<'a> Node<'a> for MyNode<'a> {
+    ///     fn id(&self) -> &'a str {
+    ///         self.name
+    ///     }
+    /// }
+    ///
+    /// let mut hash_ring = HashRing::new(Config::default()).unwrap();
+    /// let change = ProposedChange::AddNode("node1".to_string());
+    /// hash_ring.check_proposal(&change).unwrap();
+    ///
+    /// // Proposed to the log, and applied once committed.
+    /// let committed = ProposedChange::from_bytes(&change.to_bytes()).unwrap();
+    /// hash_ring.apply_committed(&committed, |id| match id {
+    ///     "node1" => Some(Arc::new(MyNode { name: "node1" })),
+    ///     _ => None,
+    /// }).unwrap();
+    /// assert_eq!(hash_ring.get_key(b"some_key").unwrap().id(), "node1");
+    /// ```
+    pub fn apply_committed<F>(&mut self, change: &ProposedChange, mut resolver: F) -> Result<u64, Box<dyn Error>>
+    where
+        F: FnMut(&str) -> Option<Arc<dyn Node<'a> + 'a>>,
+    {
+        match change {
+            ProposedChange::AddNode(id) => {
+                let node = resolver(id).ok_or_else(|| format!("unable to resolve node {}", id))?;
+                if node.id() != id {
+                    return Err(format!("resolved node {} has a different id {}", id, node.id()).into());
+                }
+                self.add_node(node)?;
+            }
+            ProposedChange::RemoveNode(id) => self.remove_node(id)?,
+            ProposedChange::AssignPartition { partition, node } => {
+                let _events = self.dispatch_events();
+                let timer = OpTimer::start();
+                let nodes = self.nodes.read()?;
+                let target = nodes.get(node).ok_or("node not found")?.clone();
+                if !self.has_partition(*partition) {
+                    return Err(format!("partition {} does not exist", partition).into());
+                }
+                let sorted_set = self.sorted_nodes_hash_set.read()?;
+                let mut partitions = self.partitions.write()?;
+                self.move_partition(&sorted_set, &mut partitions, *partition, target);
+                self.bump_version();
+                self.log_changes("assign_partition", &timer, None, &partitions);
+            }
+        }
+        Ok(self.version())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use crate::tests::test_nodes;
+    use crate::{Config, HashRing, Node, ProposedChange};
+
+    #[test]
+    fn test_replicas_applying_the_same_log_agree() {
+        let nodes = test_nodes();
+        let resolve = |id: &str| nodes.iter().find(|node| node.id() == id).map(|node| node.clone() as Arc<dyn Node<'static>>);
+        let log: Vec<Vec<u8>> = [
+            ProposedChange::AddNode("node1".to_string()),
+            ProposedChange::AddNode("node2".to_string()),
+            ProposedChange::AssignPartition { partition: 7, node: "node2".to_string() },
+            ProposedChange::AddNode("node3".to_string()),
+            ProposedChange::RemoveNode("node1".to_string()),
+        ]
+        .iter()
+        .map(ProposedChange::to_bytes)
+        .collect();
+
+        let mut replicas = [HashRing::new(Config::default()).unwrap(), HashRing::new(Config::default()).unwrap()];
+        for entry in &log {
+            let change = ProposedChange::from_bytes(entry).unwrap();
+            for replica in replicas.iter_mut() {
+                replica.check_proposal(&change).unwrap();
+                replica.apply_committed(&change, resolve).unwrap();
+            }
+            if let ProposedChange::AssignPartition { partition, node } = &change {
+                assert_eq!(replicas[0].get_partition_owner(*partition).unwrap().id(), node);
+            }
+        }
+        assert!(replicas[0] == replicas[1]);
+        assert_eq!(replicas[0].version(), replicas[1].version());
+
+        // A failing change leaves every replica as it was.
+        let change = ProposedChange::AddNode("node2".to_string());
+        assert!(replicas[0].check_proposal(&change).is_err());
+        let version = replicas[0].version();
+        assert!(replicas[0].apply_committed(&change, resolve).is_err());
+        assert!(replicas[0].apply_committed(&ProposedChange::AddNode("node9".to_string()), resolve).is_err());
+        assert!(replicas[0]
+            .apply_committed(&ProposedChange::AssignPartition { partition: 100_000, node: "node2".to_string() }, resolve)
+            .is_err());
+        assert_eq!(replicas[0].version(), version);
+        assert!(ProposedChange::from_bytes(&log[0][..log[0].len() - 1]).is_err());
+    }
+}
@@ -0,0 +1,154 @@
+//! Migration costs for rebalancing.
+//!
+//! By default `rebalance` moves the hottest partition that lowers the load of the
+//! busiest node, as if every move cost the same. Partitions holding more data, or nodes
+//! on a slower network, make some moves more expensive than others. A `CostModel` gives
+//! the cost of moving a partition between two nodes and how much a node can ingest per
+//! rebalance; the rebalancer then picks the moves relieving the most load per unit of
+//! cost, within the ingest limits.
+
+use std::fmt;
+use std::hash::BuildHasher;
+use std::sync::Arc;
+
+use crate::HashRing;
+
+/// The cost of moving partitions, consulted by `rebalance`.
+pub trait CostModel: Send + Sync {
+    /// Returns the cost of moving a partition from one node to another. Costs are
+    /// relative and should be greater than 0.
+    fn move_cost(&self, partition: usize, from: &str, to: &str) -> f64;
+
+    /// Returns the total cost of the moves a node may receive in one rebalance, or
+    /// `None` for no limit.
+    fn ingest_limit(&self, _node_id: &str) -> Option<f64> {
+        None
+    }
+}
+
+impl fmt::Debug for dyn CostModel {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt.write_str("CostModel")
+    }
+}
+
+/// The cost model used when none is set: every move costs 1 and nodes have no limit.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct UniformCost;
+
+impl CostModel for UniformCost {
+    fn move_cost(&self, _partition: usize, _from: &str, _to: &str) -> f64 {
+        1.0
+    }
+}
+
+impl<'a, H, K> HashRing<'a, H, K>
+where
+    H: BuildHasher,
+    K: BuildHasher,
+{
+    /// Sets the cost model consulted by `rebalance`, or restores `UniformCost` with `None`.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use std::sync::Arc;
+    /// use hashring::{CostModel, HashRing, Config, Node};
+    ///
+    /// #[derive(Debug)]
+    /// struct MyNode<'a> {
+    ///     name: &'a str,
+    /// }
+    ///
+    /// impl<'a> Node<'a> for MyNode<'a> {
+    ///     fn id(&self) -> &'a str {
+    ///         self.name
+    ///     }
+    /// }
+    ///
+    /// /// Moves across regions cost ten times more, and node2 is on a slow link.
+    /// struct RegionCost;
+    ///
+    /// impl CostModel for RegionCost {
+    ///     fn move_cost(&self, _partition: usize, from: &str, to: &str) -> f64 {
+    ///         if from[..2] == to[..2] { 1.0 } else { 10.0 }
+    ///     }
+    ///
+    ///     fn ingest_limit(&self, node_id: &str) -> Option<f64> {
+    ///         (node_id == "eu-node2").then_some(5.0)
+    ///     }
+    /// }
+    ///
+    /// let mut hash_ring = HashRing::new(Config::default()).unwrap();
+    /// hash_ring.add_node(Arc::new(MyNode { name: "eu-node1" })).unwrap();
+    /// hash_ring.add_node(Arc::new(MyNode { name: "eu-node2" })).unwrap();
+    /// hash_ring.set_cost_model(Some(Arc::new(RegionCost)));
+    /// ```
+    pub fn set_cost_model(&self, cost_model: Option<Arc<dyn CostModel>>) {
+        *self.cost_model.wait_write() = cost_model;
+    }
+
+    /// Returns the cost model consulted by `rebalance`.
+    pub(crate) fn current_cost_model(&self) -> Arc<dyn CostModel> {
+        match &*self.cost_model.wait_read() {
+            Some(cost_model) => cost_model.clone(),
+            None => Arc::new(UniformCost),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use crate::tests::{access, key_on, test_nodes};
+    use crate::{Config, CostModel, HashRing};
+
+    /// Partition `expensive` costs 100 to move, node3 takes nothing.
+    struct TestCost {
+        expensive: usize,
+    }
+
+    impl CostModel for TestCost {
+        fn move_cost(&self, partition: usize, _from: &str, _to: &str) -> f64 {
+            if partition == self.expensive {
+                100.0
+            } else {
+                1.0
+            }
+        }
+
+        fn ingest_limit(&self, node_id: &str) -> Option<f64> {
+            (node_id == "node3").then_some(0.0)
+        }
+    }
+
+    #[test]
+    fn test_rebalance_minimizes_cost() {
+        let hot_ring = || {
+            let mut hash_ring = HashRing::new(Config::default()).unwrap();
+            for node in test_nodes() {
+                hash_ring.add_node(node).unwrap();
+            }
+            let (hot, part_id) = key_on(&hash_ring, "node1", &[]);
+            let (warm, other) = key_on(&hash_ring, "node1", &[part_id]);
+            access(&hash_ring, &hot, 100);
+            access(&hash_ring, &warm, 60);
+            (hash_ring, part_id, other)
+        };
+
+        // The hot partition is hotter, but the other partition relieves more load per cost.
+        let (mut hash_ring, part_id, other) = hot_ring();
+        hash_ring.set_cost_model(Some(Arc::new(TestCost { expensive: part_id })));
+        let moves = hash_ring.rebalance(1);
+        assert_eq!(moves.len(), 1);
+        assert_eq!(moves[0].partition, other);
+        assert_eq!(moves[0].from, "node1");
+        assert_ne!(moves[0].to, "node3");
+
+        let (mut hash_ring, part_id, _) = hot_ring();
+        hash_ring.set_cost_model(Some(Arc::new(TestCost { expensive: part_id })));
+        hash_ring.set_cost_model(None);
+        assert_eq!(hash_ring.rebalance(1)[0].partition, part_id);
+    }
+}
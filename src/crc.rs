@@ -0,0 +1,145 @@
+//! Cyclic redundancy checks used for protocol-compatible key hashing.
+//!
+//! Redis Cluster routes keys with CRC16 (XMODEM) and many memcached clients
+//! distribute keys with CRC32, so both are available as `Hasher`s that can be
+//! plugged into the ring.
+
+use std::hash::{BuildHasherDefault, Hasher};
+
+const CRC16_TABLE: [u16; 256] = crc16_table();
+const CRC32_TABLE: [u32; 256] = crc32_table();
+
+const fn crc16_table() -> [u16; 256] {
+    let mut table = [0u16; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = (i as u16) << 8;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 0x8000 != 0 { (crc << 1) ^ 0x1021 } else { crc << 1 };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+}
+
+const fn crc32_table() -> [u32; 256] {
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xEDB8_8320 } else { crc >> 1 };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+}
+
+/// Continues a CRC16 (XMODEM) computation from `crc` over `data`.
+pub(crate) fn crc16_update(crc: u16, data: &[u8]) -> u16 {
+    data.iter().fold(crc, |crc, byte| {
+        (crc << 8) ^ CRC16_TABLE[(((crc >> 8) as u8) ^ byte) as usize]
+    })
+}
+
+/// Computes the CRC16 (XMODEM) checksum of `data`, as used by Redis Cluster.
+pub fn crc16(data: &[u8]) -> u16 {
+    crc16_update(0, data)
+}
+
+fn crc32_update(crc: u32, data: &[u8]) -> u32 {
+    data.iter().fold(crc, |crc, byte| {
+        (crc >> 8) ^ CRC32_TABLE[((crc as u8) ^ byte) as usize]
+    })
+}
+
+/// Computes the CRC32 (IEEE) checksum of `data`.
+pub fn crc32(data: &[u8]) -> u32 {
+    !crc32_update(!0, data)
+}
+
+/// A `Hasher` computing CRC16 (XMODEM).
+///
+/// `finish` places the checksum in the high 16 bits so that hashes spread over the
+/// whole ring while preserving their order. Use [`crc16`] for the raw checksum.
+#[derive(Debug, Clone, Default)]
+pub struct Crc16Hasher {
+    crc: u16,
+}
+
+impl Hasher for Crc16Hasher {
+    fn write(&mut self, bytes: &[u8]) {
+        self.crc = crc16_update(self.crc, bytes);
+    }
+
+    fn finish(&self) -> u64 {
+        (self.crc as u64) << 48
+    }
+}
+
+/// A `Hasher` computing CRC32 (IEEE).
+///
+/// `finish` places the checksum in the high 32 bits so that hashes spread over the
+/// whole ring while preserving their order. Use [`crc32`] for the raw checksum.
+#[derive(Debug, Clone)]
+pub struct Crc32Hasher {
+    crc: u32,
+}
+
+impl Default for Crc32Hasher {
+    fn default() -> Self {
+        Crc32Hasher { crc: !0 }
+    }
+}
+
+impl Hasher for Crc32Hasher {
+    fn write(&mut self, bytes: &[u8]) {
+        self.crc = crc32_update(self.crc, bytes);
+    }
+
+    fn finish(&self) -> u64 {
+        (!self.crc as u64) << 32
+    }
+}
+
+pub type Crc16BuildHasher = BuildHasherDefault<Crc16Hasher>;
+pub type Crc32BuildHasher = BuildHasherDefault<Crc32Hasher>;
+
+#[cfg(test)]
+mod tests {
+    use std::hash::BuildHasher;
+
+    use super::*;
+
+    #[test]
+    fn test_crc16_xmodem() {
+        assert_eq!(crc16(b"123456789"), 0x31C3);
+        assert_eq!(crc16(b""), 0);
+        assert_eq!(crc16_update(crc16(b"1234"), b"56789"), 0x31C3);
+    }
+
+    #[test]
+    fn test_crc32() {
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+        assert_eq!(crc32(b""), 0);
+    }
+
+    #[test]
+    fn test_crc_hashers() {
+        let mut hasher = Crc16BuildHasher::default().build_hasher();
+        hasher.write(b"1234");
+        hasher.write(b"56789");
+        assert_eq!(hasher.finish() >> 48, 0x31C3);
+
+        let mut hasher = Crc32BuildHasher::default().build_hasher();
+        hasher.write(b"1234");
+        hasher.write(b"56789");
+        assert_eq!(hasher.finish() >> 32, 0xCBF4_3926);
+    }
+}
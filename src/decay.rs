@@ -0,0 +1,171 @@
+//! Decaying nodes out of the ring.
+//!
+//! Removing a node with a large local cache moves all of its keys at once, and the nodes
+//! taking them over start cold. `decay` instead removes a fraction of the node's virtual
+//! nodes per call, so it sheds its keys progressively while still serving the rest.
+//! The node stays a member, owning nothing once every virtual node is gone, until it is
+//! removed with `remove_node`. Calls are made by the caller, or by a tokio task with the
+//! `tokio` feature.
+
+use std::error::Error;
+use std::hash::BuildHasher;
+use std::sync::atomic::Ordering;
+#[cfg(feature = "tokio")]
+use std::time::Duration;
+
+use crate::trace::OpTimer;
+use crate::HashRing;
+
+impl<'a, H, K> HashRing<'a, H, K>
+where
+    H: BuildHasher,
+    K: BuildHasher,
+{
+    /// Scales the number of virtual nodes of a node by `factor`, rounding down, so it
+    /// only loses keys to the other nodes.
+    ///
+    /// The node's highest-numbered virtual nodes are removed first. Its weight is lowered
+    /// to match, so it keeps its reduced share if the replication factor changes. A node
+    /// being ramped in stops ramping.
+    ///
+    /// # Arguments
+    ///
+    /// * `node_id` - The ID of the node.
+    /// * `factor` - The fraction of its virtual nodes the node keeps, from `0.0` to `1.0`.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<usize, Box<dyn Error>>` - The number of virtual nodes the node has left.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use std::sync::Arc;
+    /// use hashring::{HashRing, Config, Node};
+    ///
+    /// #[derive(Debug)]
+    /// struct MyNode<'a> {
+    ///     name: &'a str,
+    /// }
+    ///
+    /// impl<'a> Node<'a> for MyNode<'a> {
+    ///     fn id(&self) -> &'a str {
+    ///         self.name
+    ///     }
+    /// }
+    ///
+    /// let mut hash_ring = HashRing::new(Config::default()).unwrap();
+    /// hash_ring.add_node(Arc::new(MyNode { name: "node1" })).unwrap();
+    /// hash_ring.add_node(Arc::new(MyNode { name: "node2" })).unwrap();
+    ///
+    /// // Called periodically until the node's cache has moved.
+    /// while hash_ring.decay("node2", 0.5).unwrap() > 0 {}
+    /// assert_eq!(hash_ring.get_key(b"some_key").unwrap().id(), "node1");
+    /// hash_ring.remove_node("node2").unwrap();
+    /// ```
+    pub fn decay(&mut self, node_id: &str, factor: f64) -> Result<usize, Box<dyn Error>> {
+        let _events = self.dispatch_events();
+        let timer = OpTimer::start();
+        if !(0.0..=1.0).contains(&factor) {
+            return Err("decay factor must be between 0 and 1".into());
+        }
+        let nodes = self.nodes.read()?;
+        if !nodes.contains_key(node_id) {
+            return Err("node not found".into());
+        }
+        let mut sorted_set = self.sorted_nodes_hash_set.write()?;
+        let mut partitions = self.partitions.write()?;
+        let mut weights = self.weights.write()?;
+
+        let current = sorted_set.values().filter(|node| node.id() == node_id).count();
+        let target = (current as f64 * factor) as usize;
+        if target == current {
+            return Ok(current);
+        }
+        let previous = self.keeps_history().then(|| sorted_set.clone());
+        let removed = self.remove_vnodes(&mut sorted_set, node_id, target..current);
+        weights.insert(node_id.to_string(), target as f64 / self.config().replication_factor as f64);
+        drop(weights);
+        if let Some(previous) = previous {
+            self.record_history(previous);
+        }
+        if self.is_updating() {
+            self.partitions_dirty.store(true, Ordering::Release);
+        } else {
+            *partitions = self.compute_partitions(&sorted_set, &partitions);
+        }
+        self.bump_version();
+        self.log_changes("decay", &timer, None, &partitions);
+        timer.finish("decay", nodes.len(), sorted_set.len());
+        Ok(current - removed)
+    }
+
+    /// Spawns a task calling `decay` with `factor` every `interval` until the node has
+    /// no virtual nodes left or is removed.
+    ///
+    /// The task works on a handle sharing the state of the ring. It must be called
+    /// from within a tokio runtime.
+    #[cfg(feature = "tokio")]
+    pub fn spawn_decay(&self, node_id: &str, factor: f64, interval: Duration) -> tokio::task::JoinHandle<()>
+    where
+        'a: 'static,
+        H: Clone + Send + Sync + 'static,
+        K: Clone + Send + Sync + 'static,
+    {
+        let mut hash_ring = self.share();
+        let node_id = node_id.to_string();
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            // The first tick completes immediately.
+            ticker.tick().await;
+            loop {
+                ticker.tick().await;
+                match hash_ring.decay(&node_id, factor) {
+                    Ok(remaining) if remaining > 0 && factor < 1.0 => {}
+                    _ => break,
+                }
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::tests::test_nodes;
+    use crate::{Config, HashRing};
+
+    #[test]
+    fn test_decay_sheds_keys_progressively() {
+        let mut hash_ring = HashRing::new(Config::default()).unwrap();
+        for node in test_nodes() {
+            hash_ring.add_node(node).unwrap();
+        }
+        assert!(hash_ring.decay("node4", 0.5).is_err());
+        assert!(hash_ring.decay("node1", 1.5).is_err());
+
+        let keys: Vec<String> = (0..300).map(|i| format!("key{}", i)).collect();
+        let owners = |hash_ring: &HashRing<'static>| -> Vec<&'static str> {
+            keys.iter().map(|key| hash_ring.get_key(key.as_bytes()).unwrap().id()).collect()
+        };
+        let mut before = owners(&hash_ring);
+        let mut remaining = Vec::new();
+        loop {
+            let left = hash_ring.decay("node1", 0.5).unwrap();
+            remaining.push(left);
+            let after = owners(&hash_ring);
+            // Only keys of the decaying node move.
+            for (old, new) in before.iter().zip(&after) {
+                assert!(old == new || *old == "node1");
+            }
+            before = after;
+            if left == 0 {
+                break;
+            }
+        }
+        assert_eq!(remaining, [10, 5, 2, 1, 0]);
+        assert!(!before.contains(&"node1"));
+        assert_eq!(hash_ring.decay("node1", 0.5).unwrap(), 0);
+        assert_eq!(hash_ring.virtual_nodes_per_node().get("node1"), None);
+        hash_ring.remove_node("node1").unwrap();
+    }
+}
@@ -0,0 +1,477 @@
+//! Compact binary deltas between two versions of a ring.
+//!
+//! A delta is built from the changelog and carries only the net changes since a
+//! version, so gossiping a large but mostly unchanged topology stays cheap. A ring
+//! applying deltas takes the versions of the ring producing them, so it only accepts
+//! the delta since its own version. Node weights are carried in full, so followers place
+//! virtual nodes like the leader after a weight change or a decay. Affinity groups are
+//! not carried: followers pin their groups themselves, or are sent a full snapshot.
+//!
+//! Layout (little endian):
+//!
+//! ```text
+//! magic "HRDL" | format version: u16 | from: u64 | to: u64 | config | node ids |
+//! added | removed | partitions | splits | weights | crc32 of everything before: u32
+//! ```
+//!
+//! Node IDs are written once and referenced by index. Partitions without an owner
+//! are written with the index `u32::MAX`. Only the weights of members below full
+//! weight are written.
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::error::Error;
+use std::hash::BuildHasher;
+use std::sync::Arc;
+
+use crate::crc::crc32;
+use crate::persist::{Decoder, Encoder};
+use crate::{Change, CollisionPolicy, Config, HashRing, Node, NodeIdPolicy, RingFormatVersion};
+
+const MAGIC: &[u8; 4] = b"HRDL";
+const FORMAT_VERSION: u16 = 1;
+const UNASSIGNED: u32 = u32::MAX;
+
+/// The net changes between two versions of a ring.
+#[derive(Debug, Clone)]
+pub(crate) struct Delta {
+    pub(crate) from: u64,
+    pub(crate) to: u64,
+    pub(crate) config: Config,
+    pub(crate) added: Vec<String>,
+    pub(crate) removed: Vec<String>,
+    /// The new owner of every reassigned partition, or `None` if it lost its owner.
+    pub(crate) partitions: BTreeMap<usize, Option<String>>,
+    pub(crate) splits: BTreeSet<usize>,
+    /// The weights of the members below full weight.
+    pub(crate) weights: BTreeMap<String, f64>,
+}
+
+impl Delta {
+    pub(crate) fn to_bytes(&self) -> Vec<u8> {
+        let mut ids: Vec<&str> = self.added.iter().chain(&self.removed).map(String::as_str).collect();
+        ids.extend(self.partitions.values().flatten().map(String::as_str));
+        ids.extend(self.weights.keys().map(String::as_str));
+        ids.sort_unstable();
+        ids.dedup();
+        let index = |id: &str| ids.binary_search(&id).map_or(UNASSIGNED, |idx| idx as u32);
+
+        let mut enc = Encoder::new();
+        enc.raw(MAGIC);
+        enc.u16(FORMAT_VERSION);
+        enc.u64(self.from);
+        enc.u64(self.to);
+
+        enc.u64(self.config.replication_factor as u64);
+        enc.u64(self.config.partition_count as u64);
+        match self.config.rebalance_skew_threshold {
+            Some(threshold) => {
+                enc.u8(1);
+                enc.u64(threshold.to_bits());
+            }
+            None => enc.u8(0),
+        }
+        enc.u8(match self.config.collision_policy {
+            CollisionPolicy::Error => 0,
+            CollisionPolicy::Rehash => 1,
+            CollisionPolicy::KeepFirst => 2,
+        });
+        match &self.config.key_namespace {
+            Some(namespace) => {
+                enc.u8(1);
+                enc.str(namespace);
+            }
+            None => enc.u8(0),
+        }
+        enc.u16(self.config.format_version.number());
+
+        enc.u32(ids.len() as u32);
+        for id in &ids {
+            enc.str(id);
+        }
+        for changed in [&self.added, &self.removed] {
+            enc.u32(changed.len() as u32);
+            for id in changed {
+                enc.u32(index(id));
+            }
+        }
+        enc.u32(self.partitions.len() as u32);
+        for (part_id, owner) in &self.partitions {
+            enc.u64(*part_id as u64);
+            enc.u32(owner.as_deref().map_or(UNASSIGNED, &index));
+        }
+        enc.u32(self.splits.len() as u32);
+        for part_id in &self.splits {
+            enc.u64(*part_id as u64);
+        }
+        enc.u32(self.weights.len() as u32);
+        for (id, weight) in &self.weights {
+            enc.u32(index(id));
+            enc.u64(weight.to_bits());
+        }
+
+        let mut buf = enc.into_inner();
+        let checksum = crc32(&buf);
+        buf.extend_from_slice(&checksum.to_le_bytes());
+        buf
+    }
+
+    pub(crate) fn from_bytes(data: &[u8]) -> Result<Delta, Box<dyn Error>> {
+        if data.len() < MAGIC.len() + 2 + 4 {
+            return Err("delta is truncated".into());
+        }
+        let (body, checksum) = data.split_at(data.len() - 4);
+        if crc32(body) != u32::from_le_bytes(checksum.try_into()?) {
+            return Err("delta checksum mismatch".into());
+        }
+
+        let mut dec = Decoder::new(body);
+        if dec.take(MAGIC.len())? != MAGIC {
+            return Err("not a ring delta".into());
+        }
+        let format = dec.u16()?;
+        if format != FORMAT_VERSION {
+            return Err(format!("unsupported delta format version {}", format).into());
+        }
+        let from = dec.u64()?;
+        let to = dec.u64()?;
+
+        let replication_factor = dec.u64()? as usize;
+        let partition_count = dec.u64()? as usize;
+        let rebalance_skew_threshold = match dec.u8()? {
+            0 => None,
+            _ => Some(f64::from_bits(dec.u64()?)),
+        };
+        let collision_policy = match dec.u8()? {
+            0 => CollisionPolicy::Error,
+            1 => CollisionPolicy::Rehash,
+            2 => CollisionPolicy::KeepFirst,
+            policy => return Err(format!("unknown collision policy {}", policy).into()),
+        };
+        let key_namespace = match dec.u8()? {
+            0 => None,
+            _ => Some(dec.string()?),
+        };
+        let number = dec.u16()?;
+        let format_version = RingFormatVersion::from_number(number).ok_or_else(|| format!("unsupported ring format version {}", number))?;
+
+        let id_count = dec.u32()? as usize;
+        let mut ids = Vec::with_capacity(id_count.min(body.len()));
+        for _ in 0..id_count {
+            ids.push(dec.string()?);
+        }
+        let id_at = |dec: &mut Decoder| -> Result<Option<String>, Box<dyn Error>> {
+            match dec.u32()? {
+                UNASSIGNED => Ok(None),
+                idx => Ok(Some(ids.get(idx as usize).ok_or("delta references unknown node")?.clone())),
+            }
+        };
+
+        let mut added = Vec::new();
+        for _ in 0..dec.u32()? {
+            added.push(id_at(&mut dec)?.ok_or("delta adds an unknown node")?);
+        }
+        let mut removed = Vec::new();
+        for _ in 0..dec.u32()? {
+            removed.push(id_at(&mut dec)?.ok_or("delta removes an unknown node")?);
+        }
+        let mut partitions = BTreeMap::new();
+        for _ in 0..dec.u32()? {
+            let part_id = dec.u64()? as usize;
+            partitions.insert(part_id, id_at(&mut dec)?);
+        }
+        let mut splits = BTreeSet::new();
+        for _ in 0..dec.u32()? {
+            splits.insert(dec.u64()? as usize);
+        }
+        let mut weights = BTreeMap::new();
+        for _ in 0..dec.u32()? {
+            let id = id_at(&mut dec)?.ok_or("delta weighs an unknown node")?;
+            weights.insert(id, f64::from_bits(dec.u64()?));
+        }
+        if !dec.is_empty() {
+            return Err("trailing data after delta".into());
+        }
+
+        Ok(Delta {
+            from,
+            to,
+            config: Config {
+                replication_factor,
+                partition_count,
+                rebalance_skew_threshold,
+                collision_policy,
+                key_namespace,
+                node_id_policy: NodeIdPolicy::default(),
+                format_version,
+                capacity_headroom: crate::capacity::DEFAULT_CAPACITY_HEADROOM,
+            },
+            added,
+            removed,
+            partitions,
+            splits,
+            weights,
+        })
+    }
+}
+
+impl<'a, H, K> HashRing<'a, H, K>
+where
+    H: BuildHasher,
+    K: BuildHasher,
+{
+    /// Encodes the changes made after `since_version` as a compact binary delta.
+    ///
+    /// Requires the changelog to be enabled with `set_changelog_capacity`. Changes that
+    /// cancel out, like a node added and removed again, are left out.
+    ///
+    /// # Arguments
+    ///
+    /// * `since_version` - The last version the receiver has applied.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Vec<u8>, Box<dyn Error>>` - The delta, or an error if the changelog no longer
+    ///   reaches back to `since_version` and a full snapshot has to be sent instead.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use std::sync::Arc;
+    /// use hashring::{HashRing, Config, Node};
+    ///
+    /// #[derive(Debug)]
+    /// struct MyNode<'a> {
+    ///     name: &'a str,
+    /// }
+    ///
+    /// impl<'a> Node<'a> for MyNode<'a> {
+    ///     fn id(&self) -> &'a str {
+    ///         self.name
+    ///     }
+    /// }
+    ///
+    /// let node1: Arc<dyn Node> = Arc::new(MyNode { name: "node1" });
+    /// let node2: Arc<dyn Node> = Arc::new(MyNode { name: "node2" });
+    ///
+    /// let mut leader = HashRing::new(Config::default()).unwrap();
+    /// leader.set_changelog_capacity(64);
+    /// leader.add_node(node1.clone()).unwrap();
+    ///
+    /// let resolve = |id: &str| [&node1, &node2].into_iter().find(|n| n.id() == id).cloned();
+    /// let mut follower = HashRing::new(Config::default()).unwrap();
+    /// follower.apply_delta(&leader.snapshot_delta(0).unwrap(), resolve).unwrap();
+    ///
+    /// leader.add_node(node2.clone()).unwrap();
+    /// let delta = leader.snapshot_delta(follower.version()).unwrap();
+    /// follower.apply_delta(&delta, resolve).unwrap();
+    ///
+    /// assert_eq!(follower.version(), leader.version());
+    /// assert_eq!(follower.get_key(b"some_key").unwrap().id(), leader.get_key(b"some_key").unwrap().id());
+    /// ```
+    pub fn snapshot_delta(&self, since_version: u64) -> Result<Vec<u8>, Box<dyn Error>> {
+        self.delta(since_version).map(|delta| delta.to_bytes())
+    }
+
+    /// Applies a delta produced by `snapshot_delta` on another ring.
+    ///
+    /// The delta is fully decoded and every added node resolved before the ring is
+    /// modified, so on error the ring is left unchanged. Versions are those of the ring
+    /// that produced the delta: the delta must start at this ring's version, which it then
+    /// brings to the version the delta ends at. A follower starts empty, at version 0,
+    /// and catches up with a delta since version 0. Node weights are replaced by the
+    /// delta's, and affinity groups are left as they are.
+    ///
+    /// # Arguments
+    ///
+    /// * `delta` - The encoded delta.
+    /// * `resolver` - Returns the node for a given ID, or `None` if it is unknown. Only called
+    ///   for nodes added by the delta.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<u64, Box<dyn Error>>` - The version of the sending ring the delta brings this ring to.
+    pub fn apply_delta<F>(&mut self, delta: &[u8], resolver: F) -> Result<u64, Box<dyn Error>>
+    where
+        F: FnMut(&str) -> Option<Arc<dyn Node<'a> + 'a>>,
+    {
+        self.apply(Delta::from_bytes(delta)?, resolver)
+    }
+
+    pub(crate) fn delta(&self, since_version: u64) -> Result<Delta, Box<dyn Error>> {
+        let version = self.version();
+        let entries = self
+            .changelog(since_version)
+            .ok_or_else(|| format!("changelog does not reach back to version {}", since_version))?;
+
+        let mut members: BTreeMap<String, bool> = BTreeMap::new();
+        let mut partitions: BTreeMap<usize, Option<String>> = BTreeMap::new();
+        for change in entries.into_iter().flat_map(|entry| entry.changes) {
+            match change {
+                Change::NodeAdded(id) | Change::NodeRemoved(id) if members.contains_key(&id) => {
+                    members.remove(&id);
+                }
+                Change::NodeAdded(id) => {
+                    members.insert(id, true);
+                }
+                Change::NodeRemoved(id) => {
+                    members.insert(id, false);
+                }
+                Change::PartitionAssigned { partition, node } => {
+                    partitions.insert(partition, Some(node));
+                }
+                Change::PartitionUnassigned { partition } => {
+                    partitions.insert(partition, None);
+                }
+            }
+        }
+        let (added, removed): (Vec<_>, Vec<_>) = members.into_iter().partition(|(_, added)| *added);
+
+        Ok(Delta {
+            from: since_version,
+            to: version,
+            config: self.config().as_ref().clone(),
+            added: added.into_iter().map(|(id, _)| id).collect(),
+            removed: removed.into_iter().map(|(id, _)| id).collect(),
+            partitions,
+            splits: self.routes.read()?.splits.clone(),
+            weights: self.weights.read()?.iter().map(|(id, weight)| (id.clone(), *weight)).collect(),
+        })
+    }
+
+    pub(crate) fn apply<F>(&mut self, delta: Delta, mut resolver: F) -> Result<u64, Box<dyn Error>>
+    where
+        F: FnMut(&str) -> Option<Arc<dyn Node<'a> + 'a>>,
+    {
+        let version = self.version();
+        if delta.from != version || delta.to < delta.from {
+            return Err(format!("delta from version {} does not apply to version {}", delta.from, version).into());
+        }
+        let mut snapshot = self.snapshot();
+        let mut members: BTreeSet<String> = snapshot.nodes.iter().cloned().collect();
+        for id in &delta.added {
+            if !members.insert(id.clone()) {
+                return Err(format!("delta adds existing node {}", id).into());
+            }
+        }
+        for id in &delta.removed {
+            if !members.remove(id) {
+                return Err(format!("delta removes missing node {}", id).into());
+            }
+        }
+        for (part_id, owner) in delta.partitions {
+            match owner {
+                Some(id) => snapshot.partitions.insert(part_id, id),
+                None => snapshot.partitions.remove(&part_id),
+            };
+        }
+
+        let mut resolved: HashMap<String, Arc<dyn Node<'a> + 'a>> = self.nodes.read()?.clone();
+        for id in delta.added {
+            let node = resolver(&id).ok_or_else(|| format!("unable to resolve node {}", id))?;
+            resolved.insert(id, node);
+        }
+
+        snapshot.config = delta.config;
+        snapshot.nodes = members.into_iter().collect();
+        snapshot.partitions.retain(|_, id| snapshot.nodes.binary_search(id).is_ok());
+        snapshot.splits = delta.splits;
+        snapshot.weights = delta.weights;
+        // Rebalances aren't carried either, so the delta's assignment replaces our own moves.
+        snapshot.moved.clear();
+        self.restore_at(&snapshot, |id| resolved.get(id).cloned(), Some(delta.to))?;
+
+        Ok(delta.to)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use crate::tests::test_nodes;
+    use crate::{Config, HashRing, Node};
+
+    #[test]
+    fn test_delta_brings_follower_up_to_date() {
+        let nodes = test_nodes();
+        let resolve = |id: &str| nodes.iter().find(|n| n.id() == id).map(|n| n.clone() as Arc<dyn Node<'static>>);
+        let config = Config {
+            partition_count: 20,
+            ..Config::default()
+        };
+
+        let mut leader = HashRing::new(config.clone()).unwrap();
+        leader.set_changelog_capacity(16);
+        leader.add_node(nodes[0].clone()).unwrap();
+        leader.add_node(nodes[1].clone()).unwrap();
+        let mut follower = HashRing::new(config).unwrap();
+        follower.apply_delta(&leader.snapshot_delta(0).unwrap(), resolve).unwrap();
+        let seen = follower.version();
+        assert_eq!(seen, leader.version());
+
+        leader.add_node(nodes[2].clone()).unwrap();
+        leader.remove_node("node1").unwrap();
+        leader.split_partition(4).unwrap();
+        leader.add_node(nodes[0].clone()).unwrap();
+        leader.remove_node("node1").unwrap();
+
+        let delta = leader.snapshot_delta(seen).unwrap();
+        assert!(delta.len() < leader.snapshot().to_bytes().unwrap().len());
+        assert_eq!(follower.apply_delta(&delta, resolve).unwrap(), leader.version());
+        assert_eq!(follower.snapshot().nodes, leader.snapshot().nodes);
+        assert_eq!(follower.snapshot().partitions, leader.snapshot().partitions);
+        assert_eq!(follower.partition_ids(), leader.partition_ids());
+
+        // A delta doesn't apply twice, nor to a ring that moved on.
+        assert!(follower.apply_delta(&delta, resolve).is_err());
+        let mut stale = HashRing::new(Config::default()).unwrap();
+        stale.add_node(nodes[2].clone()).unwrap();
+        assert!(stale.apply_delta(&leader.snapshot_delta(0).unwrap(), resolve).is_err());
+        assert_eq!(stale.get_key(b"some_key").unwrap().id(), "node3");
+        assert!(leader.snapshot_delta(leader.version() + 1).is_err());
+    }
+
+    #[test]
+    fn test_delta_carries_weights() {
+        let nodes = test_nodes();
+        let resolve = |id: &str| nodes.iter().find(|n| n.id() == id).map(|n| n.clone() as Arc<dyn Node<'static>>);
+        let mut leader = HashRing::new(Config::default()).unwrap();
+        leader.set_changelog_capacity(16);
+        for node in nodes.iter() {
+            leader.add_node(node.clone()).unwrap();
+        }
+        let mut follower = HashRing::new(Config::default()).unwrap();
+        follower.apply_delta(&leader.snapshot_delta(0).unwrap(), resolve).unwrap();
+
+        // A decay changes no membership, but the follower places the same virtual nodes.
+        leader.decay("node1", 0.5).unwrap();
+        follower.apply_delta(&leader.snapshot_delta(follower.version()).unwrap(), resolve).unwrap();
+        assert_eq!(follower.node_weight("node1"), leader.node_weight("node1"));
+        assert_eq!(follower.virtual_nodes_per_node(), leader.virtual_nodes_per_node());
+        for i in 0..200 {
+            let key = format!("key-{}", i);
+            assert_eq!(follower.get_key_id(key.as_bytes()), leader.get_key_id(key.as_bytes()));
+        }
+
+        // A delta in any other format version is rejected.
+        let mut delta = leader.snapshot_delta(0).unwrap();
+        delta[4] = 2;
+        let body = delta.len() - 4;
+        let checksum = crate::crc::crc32(&delta[..body]);
+        delta[body..].copy_from_slice(&checksum.to_le_bytes());
+        assert!(HashRing::new(Config::default()).unwrap().apply_delta(&delta, resolve).is_err());
+    }
+
+    #[test]
+    fn test_corrupt_delta_rejected() {
+        let mut leader = HashRing::new(Config::default()).unwrap();
+        leader.set_changelog_capacity(4);
+        leader.add_node(test_nodes()[0].clone()).unwrap();
+        let mut delta = leader.snapshot_delta(0).unwrap();
+        delta[10] ^= 0xff;
+
+        let mut follower = HashRing::new(Config::default()).unwrap();
+        assert!(follower.apply_delta(&delta, |_| None).is_err());
+        assert!(follower.apply_delta(b"HRDL", |_| None).is_err());
+    }
+}
@@ -0,0 +1,195 @@
+//! Diagrams of a ring's placement.
+//!
+//! `to_dot` and `to_mermaid` draw the ring as a cycle of virtual nodes in hash order,
+//! colored by the node they belong to. The edge into each virtual node is the arc of
+//! the ring it owns, labeled with the number of partitions that fall on it. The output
+//! can be rendered with Graphviz (`circo` keeps the ring round) or pasted into Markdown
+//! that renders Mermaid.
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::hash::BuildHasher;
+
+use crate::HashRing;
+
+/// Fill colors given to nodes in ID order, reused when there are more nodes.
+const PALETTE: [&str; 8] = ["#8dd3c7", "#ffffb3", "#bebada", "#fb8072", "#80b1d3", "#fdb462", "#b3de69", "#fccde5"];
+
+/// A virtual node and the arc of the ring ending at it.
+struct VnodeArc {
+    point: u64,
+    node_id: String,
+    partitions: usize,
+}
+
+impl<'a, H, K> HashRing<'a, H, K>
+where
+    H: BuildHasher,
+    K: BuildHasher,
+{
+    /// Returns the virtual nodes in ring order, with the number of partitions on their arc,
+    /// and the IDs of the nodes in ascending order.
+    fn vnode_arcs(&self) -> (Vec<VnodeArc>, Vec<String>) {
+        let part_ids = self.partition_ids();
+        let mut node_ids: Vec<String> = self.nodes.wait_read().keys().cloned().collect();
+        node_ids.sort_unstable();
+        let sorted_set = self.sorted_nodes_hash_set.wait_read();
+        let mut arcs: Vec<VnodeArc> = sorted_set
+            .iter()
+            .map(|(point, node)| VnodeArc { point: *point, node_id: node.id().to_string(), partitions: 0 })
+            .collect();
+        if !arcs.is_empty() {
+            for part_id in part_ids {
+                let point = self.hash_partition_id(part_id);
+                let idx = arcs.partition_point(|arc| arc.point < point);
+                arcs[idx % sorted_set.len()].partitions += 1;
+            }
+        }
+        (arcs, node_ids)
+    }
+
+    /// Renders the ring as a Graphviz DOT digraph.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use std::sync::Arc;
+    /// use hashring::{HashRing, Config, Node};
+    ///
+    /// #[derive(Debug)]
+    /// struct MyNode<'a> {
+    ///     name: &'a str,
+    /// }
+    ///
+    /// impl<'a> Node<'a> for MyNode<'a> {
+    ///     fn id(&self) -> &'a str {
+    ///         self.name
+    ///     }
+    /// }
+    ///
+    /// let mut hash_ring = HashRing::new(Config::default()).unwrap();
+    /// hash_ring.add_node(Arc::new(MyNode { name: "node1" })).unwrap();
+    /// hash_ring.add_node(Arc::new(MyNode { name: "node2" })).unwrap();
+    ///
+    /// let dot = hash_ring.to_dot();
+    /// assert!(dot.starts_with("digraph ring {"));
+    /// // std::fs::write("ring.dot", dot) and render with `circo -Tsvg ring.dot`.
+    /// ```
+    pub fn to_dot(&self) -> String {
+        let (arcs, node_ids) = self.vnode_arcs();
+        let colors: BTreeMap<&str, &str> = node_ids.iter().map(String::as_str).zip(PALETTE.iter().copied().cycle()).collect();
+
+        // Writing to a `String` cannot fail.
+        let mut dot = String::from("digraph ring {\n    layout=circo;\n    node [shape=circle, style=filled, fontsize=10];\n");
+        for (idx, arc) in arcs.iter().enumerate() {
+            let _ = writeln!(
+                dot,
+                "    v{} [label=\"{}\\n{:016x}\", fillcolor=\"{}\"];",
+                idx,
+                escape_dot(&arc.node_id),
+                arc.point,
+                colors[arc.node_id.as_str()]
+            );
+        }
+        for (idx, arc) in arcs.iter().enumerate() {
+            let previous = (idx + arcs.len() - 1) % arcs.len();
+            let _ = writeln!(dot, "    v{} -> v{} [label=\"{}\"];", previous, idx, arc.partitions);
+        }
+        dot.push_str("}\n");
+        dot
+    }
+
+    /// Renders the ring as a Mermaid flowchart.
+    ///
+    /// Each node gets a class named `n<index>`, in ID order, which sets its color.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use std::sync::Arc;
+    /// use hashring::{HashRing, Config, Node};
+    ///
+    /// #[derive(Debug)]
+    /// struct MyNode<'a> {
+    ///     name: &'a str,
+    /// }
+    ///
+    /// impl<'a> Node<'a> for MyNode<'a> {
+    ///     fn id(&self) -> &'a str {
+    ///         self.name
+    ///     }
+    /// }
+    ///
+    /// let mut hash_ring = HashRing::new(Config::default()).unwrap();
+    /// hash_ring.add_node(Arc::new(MyNode { name: "node1" })).unwrap();
+    ///
+    /// println!("```mermaid\n{}```", hash_ring.to_mermaid());
+    /// ```
+    pub fn to_mermaid(&self) -> String {
+        let (arcs, node_ids) = self.vnode_arcs();
+        let classes: BTreeMap<&str, usize> = node_ids.iter().enumerate().map(|(idx, id)| (id.as_str(), idx)).collect();
+
+        // Writing to a `String` cannot fail.
+        let mut mermaid = String::from("flowchart LR\n");
+        for idx in 0..node_ids.len() {
+            let _ = writeln!(mermaid, "    classDef n{} fill:{}", idx, PALETTE[idx % PALETTE.len()]);
+        }
+        for (idx, arc) in arcs.iter().enumerate() {
+            let _ = writeln!(
+                mermaid,
+                "    v{}((\"{}<br/>{:016x}\")):::n{}",
+                idx,
+                escape_mermaid(&arc.node_id),
+                arc.point,
+                classes[arc.node_id.as_str()]
+            );
+        }
+        for (idx, arc) in arcs.iter().enumerate() {
+            let previous = (idx + arcs.len() - 1) % arcs.len();
+            let _ = writeln!(mermaid, "    v{} -->|{}| v{}", previous, arc.partitions, idx);
+        }
+        mermaid
+    }
+}
+
+/// Escapes a string for a quoted DOT label.
+fn escape_dot(label: &str) -> String {
+    label.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+/// Escapes a string for a quoted Mermaid label.
+fn escape_mermaid(label: &str) -> String {
+    label.replace('"', "#quot;").replace('\n', " ")
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::tests::test_nodes;
+    use crate::{Config, HashRing};
+
+    #[test]
+    fn test_diagrams_cover_every_vnode() {
+        let mut hash_ring = HashRing::new(Config::default()).unwrap();
+        assert_eq!(hash_ring.to_dot().matches("->").count(), 0);
+        for node in test_nodes() {
+            hash_ring.add_node(node).unwrap();
+        }
+        let vnodes = hash_ring.virtual_nodes_per_node().values().sum::<usize>();
+
+        let dot = hash_ring.to_dot();
+        assert_eq!(dot.matches(" -> ").count(), vnodes);
+        assert!(dot.ends_with("}\n"));
+        // Edge labels add up to the partition count.
+        let partitions: usize = dot
+            .lines()
+            .filter(|line| line.contains(" -> "))
+            .map(|line| line.split("label=\"").nth(1).unwrap().trim_end_matches("\"];").parse::<usize>().unwrap())
+            .sum();
+        assert_eq!(partitions, hash_ring.partition_ids().len());
+
+        let mermaid = hash_ring.to_mermaid();
+        assert!(mermaid.starts_with("flowchart LR\n"));
+        assert_eq!(mermaid.matches(" -->|").count(), vnodes);
+        assert_eq!(mermaid.matches("classDef").count(), 3);
+    }
+}
@@ -0,0 +1,167 @@
+//! A human-readable summary of a ring.
+//!
+//! The derived `Debug` prints every virtual node and partition, which is unreadable past
+//! a handful of nodes. `Display` prints one row per node instead, with its virtual nodes,
+//! weight, partitions and share of the keyspace, followed by the ring's imbalance. It is
+//! meant for logs and REPL sessions, not for parsing.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::{self, Write};
+use std::hash::BuildHasher;
+
+use crate::audit::audit_vnodes;
+use crate::HashRing;
+
+/// The number of points on the ring.
+const KEYSPACE: f64 = u64::MAX as f64 + 1.0;
+
+impl<'a, H, K> fmt::Display for HashRing<'a, H, K>
+where
+    H: BuildHasher,
+    K: BuildHasher,
+{
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let nodes = self.nodes.read().map_err(|_| fmt::Error)?;
+        let sorted_set = self.sorted_nodes_hash_set.read().map_err(|_| fmt::Error)?;
+        let partitions = self.partitions.read().map_err(|_| fmt::Error)?;
+
+        let mut vnodes: HashMap<&str, usize> = HashMap::new();
+        let mut keyspace: HashMap<&str, f64> = HashMap::new();
+        let mut previous = sorted_set.keys().next_back().copied().unwrap_or(0);
+        for (point, node) in sorted_set.iter() {
+            *vnodes.entry(node.id()).or_insert(0) += 1;
+            // A single virtual node owns the whole ring.
+            let arc = if sorted_set.len() == 1 { KEYSPACE } else { point.wrapping_sub(previous) as f64 };
+            *keyspace.entry(node.id()).or_insert(0.0) += arc;
+            previous = *point;
+        }
+        let mut owned: HashMap<&str, usize> = HashMap::new();
+        for node in partitions.values() {
+            *owned.entry(node.id()).or_insert(0) += 1;
+        }
+
+        writeln!(
+            fmt,
+            "HashRing v{}: {} nodes, {} virtual nodes, {} partitions",
+            self.version(),
+            nodes.len(),
+            sorted_set.len(),
+            partitions.len()
+        )?;
+        if nodes.is_empty() {
+            return Ok(());
+        }
+
+        let mut ids: Vec<&str> = nodes.keys().map(String::as_str).collect();
+        ids.sort_unstable();
+        let width = ids.iter().map(|id| id.len()).max().unwrap_or(0).max("NODE".len());
+        writeln!(fmt, "{:<width$}  {:>6}  {:>6}  {:>10}  {:>8}", "NODE", "VNODES", "WEIGHT", "PARTITIONS", "KEYSPACE")?;
+        for id in &ids {
+            let count = vnodes.get(id).copied().unwrap_or(0);
+            writeln!(
+                fmt,
+                "{:<width$}  {:>6}  {:>6.2}  {:>10}  {:>7.2}%",
+                id,
+                count,
+                count as f64 / self.config().replication_factor as f64,
+                owned.get(id).copied().unwrap_or(0),
+                keyspace.get(id).copied().unwrap_or(0.0) / KEYSPACE * 100.0
+            )?;
+        }
+
+        // Skews are the ratio between the largest share and the mean share.
+        let mean = 1.0 / nodes.len() as f64;
+        let keyspace_skew = keyspace.values().fold(0.0f64, |max, arc| max.max(*arc)) / KEYSPACE / mean;
+        let partition_skew = match partitions.len() {
+            0 => 1.0,
+            count => owned.values().copied().max().unwrap_or(0) as f64 / count as f64 / mean,
+        };
+        let audit = audit_vnodes(&sorted_set);
+        write!(fmt, "imbalance: keyspace {:.2}x, partitions {:.2}x, max gap {:.2}x", keyspace_skew, partition_skew, audit.max_gap_ratio())?;
+        if let Some(id) = &audit.largest_arc_node {
+            write!(fmt, ", largest arc {:.2}% on {}", audit.largest_arc_fraction * 100.0, id)?;
+        }
+        writeln!(fmt)
+    }
+}
+
+impl<'a, H, K> HashRing<'a, H, K>
+where
+    H: BuildHasher,
+    K: BuildHasher,
+{
+    /// Returns the `Display` summary of the ring followed by the owner of every partition.
+    ///
+    /// Consecutive partitions with the same owner are collapsed into a range.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use std::sync::Arc;
+    /// use hashring::{HashRing, Config, Node};
+    ///
+    /// #[derive(Debug)]
+    /// struct MyNode<'a> {
+    ///     name: &'a str,
+    /// }
+    ///
+    /// impl<'a> Node<'a> for MyNode<'a> {
+    ///     fn id(&self) -> &'a str {
+    ///         self.name
+    ///     }
+    /// }
+    ///
+    /// let mut hash_ring = HashRing::new(Config::default()).unwrap();
+    /// hash_ring.add_node(Arc::new(MyNode { name: "node1" })).unwrap();
+    /// hash_ring.add_node(Arc::new(MyNode { name: "node2" })).unwrap();
+    ///
+    /// println!("{}", hash_ring);
+    /// assert!(hash_ring.dump().contains("node2"));
+    /// ```
+    pub fn dump(&self) -> String {
+        let mut dump = self.to_string();
+        let owners: BTreeMap<usize, String> = match self.partitions.read() {
+            Ok(partitions) => partitions.iter().map(|(part_id, node)| (*part_id, node.id().to_string())).collect(),
+            Err(_) => return dump,
+        };
+        let mut ranges: Vec<(usize, usize, &str)> = Vec::new();
+        for (part_id, id) in &owners {
+            match ranges.last_mut() {
+                Some((_, end, owner)) if *end + 1 == *part_id && *owner == id.as_str() => *end = *part_id,
+                _ => ranges.push((*part_id, *part_id, id)),
+            }
+        }
+        for (start, end, id) in ranges {
+            // Writing to a `String` cannot fail.
+            if start == end {
+                let _ = writeln!(dump, "partition {}: {}", start, id);
+            } else {
+                let _ = writeln!(dump, "partitions {}-{}: {}", start, end, id);
+            }
+        }
+        dump
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::tests::test_nodes;
+    use crate::{Config, HashRing};
+
+    #[test]
+    fn test_display_summarizes_nodes() {
+        let mut hash_ring = HashRing::new(Config::default()).unwrap();
+        assert!(hash_ring.to_string().contains("0 nodes"));
+        for node in test_nodes() {
+            hash_ring.add_node(node).unwrap();
+        }
+        let summary = hash_ring.to_string();
+        assert_eq!(summary.lines().count(), 6);
+        assert!(summary.lines().nth(2).unwrap().starts_with("node1 "));
+        assert!(summary.contains("imbalance: keyspace"));
+
+        let dump = hash_ring.dump();
+        assert!(dump.starts_with(&summary));
+        assert!(dump.lines().skip(6).all(|line| line.starts_with("partition")));
+    }
+}
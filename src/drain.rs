@@ -0,0 +1,316 @@
+//! Tracking the data migration off a node before it is shut down.
+//!
+//! Cordoning a node takes it out of the ring and records every partition it held a
+//! replica of. Operators mark partitions as migrated as their data lands on the new
+//! replicas, and the node is safe to shut down once nothing is pending.
+//!
+//! Under the `tokio` feature, `drain_on_shutdown` ties this to process shutdown: it waits
+//! for a shutdown future, cordons the local node in the shared ring and waits for the
+//! drain to complete, so the process exits only once its data has moved. With the
+//! `signal` feature, `drain_on_sigterm` does the same on `SIGTERM`, as sent by container
+//! orchestrators before killing a process.
+
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::error::Error;
+#[cfg(feature = "tokio")]
+use std::future::Future;
+use std::hash::BuildHasher;
+use std::sync::Arc;
+#[cfg(feature = "tokio")]
+use std::time::Duration;
+
+use crate::{HashRing, Node};
+
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Drain {
+    pending: BTreeSet<usize>,
+    migrated: BTreeSet<usize>,
+}
+
+/// The progress of draining a node.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DrainStatus {
+    pub node: String,
+    /// Partitions whose data still has to be migrated off the node, in ascending order.
+    pub pending: Vec<usize>,
+    /// Partitions marked as migrated, in ascending order.
+    pub migrated: Vec<usize>,
+}
+
+impl DrainStatus {
+    /// Returns `true` once every partition has been migrated.
+    pub fn is_complete(&self) -> bool {
+        self.pending.is_empty()
+    }
+}
+
+impl<'a, H, K> HashRing<'a, H, K>
+where
+    H: BuildHasher,
+    K: BuildHasher,
+{
+    /// Returns the partitions the node currently owns or holds a replica of, in ascending order.
+    ///
+    /// The replicas of a partition are the owner followed by the nodes of the preference
+    /// list starting at the partition's position on the ring.
+    pub fn replica_partitions(&self, id: &str) -> Vec<usize> {
+        let sorted_set = self.sorted_nodes_hash_set.wait_read();
+        let partitions = self.partitions.wait_read();
+        self.replicated_by(&sorted_set, &partitions, id)
+    }
+
+    fn replicated_by(&self, sorted_set: &BTreeMap<u64, Arc<dyn Node<'a> + 'a>>, partitions: &HashMap<usize, Arc<dyn Node<'a> + 'a>>, id: &str) -> Vec<usize> {
+        let mut replicated = Vec::new();
+        for part_id in self.partition_ids() {
+            if partitions.get(&part_id).is_some_and(|owner| owner.id() == id) {
+                replicated.push(part_id);
+                continue;
+            }
+            let point = self.hash_partition_id(part_id);
+            let mut seen = HashSet::new();
+            let in_replica_set = sorted_set
+                .range(point..)
+                .chain(sorted_set.range(..point))
+                .map(|(_, node)| node.id())
+                .filter(|node_id| seen.insert(*node_id))
+                .take(self.config().replication_factor)
+                .any(|node_id| node_id == id);
+            if in_replica_set {
+                replicated.push(part_id);
+            }
+        }
+        replicated
+    }
+
+    /// Removes a node from the ring and starts tracking the migration of its partitions.
+    ///
+    /// The partitions to migrate are read under the same locks as the removal, so a
+    /// concurrent membership change can't make them stale.
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - The ID of the node to drain.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<DrainStatus, Box<dyn Error>>` - The partitions to migrate off the node.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use std::sync::Arc;
+    /// use hashring::{HashRing, Config, Node};
+    ///
+    /// #[derive(Debug)]
+    /// struct MyNode<'a> {
+    ///     name: &'a str,
+    /// }
+    ///
+    /// impl<'a> Node<'a> for MyNode<'a> {
+    ///     fn id(&self) -> &'a str {
+    ///         self.name
+    ///     }
+    /// }
+    ///
+    /// let mut hash_ring = HashRing::new(Config::default()).unwrap();
+    /// hash_ring.add_node(Arc::new(MyNode { name: "node1" })).unwrap();
+    /// hash_ring.add_node(Arc::new(MyNode { name: "node2" })).unwrap();
+    ///
+    /// let status = hash_ring.cordon("node1").unwrap();
+    /// for part_id in status.pending {
+    ///     // Copy the partition to its new replicas, then:
+    ///     hash_ring.mark_partition_migrated("node1", part_id).unwrap();
+    /// }
+    /// assert!(hash_ring.drain_status("node1").unwrap().is_complete());
+    /// ```
+    pub fn cordon(&mut self, id: &str) -> Result<DrainStatus, Box<dyn Error>> {
+        let pending: BTreeSet<usize> = self
+            .remove_member(id, |sorted_set, partitions| self.replicated_by(sorted_set, partitions, id))?
+            .into_iter()
+            .collect();
+
+        let mut drains = self.drains.write()?;
+        drains.insert(
+            id.to_string(),
+            Drain {
+                pending,
+                migrated: BTreeSet::new(),
+            },
+        );
+        drop(drains);
+
+        self.drain_status(id).ok_or_else(|| "node not found".into())
+    }
+
+    /// Returns the progress of draining a cordoned node.
+    pub fn drain_status(&self, id: &str) -> Option<DrainStatus> {
+        let drains = self.drains.read().ok()?;
+        let drain = drains.get(id)?;
+        Some(DrainStatus {
+            node: id.to_string(),
+            pending: drain.pending.iter().copied().collect(),
+            migrated: drain.migrated.iter().copied().collect(),
+        })
+    }
+
+    /// Marks a partition of a cordoned node as migrated.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<bool, Box<dyn Error>>` - `true` if the node has no pending partitions left.
+    pub fn mark_partition_migrated(&self, id: &str, part_id: usize) -> Result<bool, Box<dyn Error>> {
+        let mut drains = self.drains.write()?;
+        let drain = drains.get_mut(id).ok_or("node is not being drained")?;
+        if drain.pending.remove(&part_id) {
+            drain.migrated.insert(part_id);
+        } else if !drain.migrated.contains(&part_id) {
+            return Err(format!("partition {} was not held by {}", part_id, id).into());
+        }
+        Ok(drain.pending.is_empty())
+    }
+
+    /// Stops tracking a drained node, returning its final status.
+    pub fn finish_drain(&self, id: &str) -> Option<DrainStatus> {
+        let status = self.drain_status(id)?;
+        self.drains.write().ok()?.remove(id);
+        Some(status)
+    }
+
+    /// Waits for `shutdown`, then cordons the node and waits until its drain completes or
+    /// `timeout` runs out.
+    ///
+    /// Call it on a handle from `share`, so the tasks migrating the node's partitions see
+    /// it leave and call `mark_partition_migrated` on their own handles. A node already
+    /// being drained isn't cordoned again.
+    ///
+    /// # Arguments
+    ///
+    /// * `node_id` - The ID of the local node.
+    /// * `shutdown` - Completes when the process is asked to shut down.
+    /// * `poll_interval` - How often the drain status is checked.
+    /// * `timeout` - How long to wait for the drain after cordoning the node.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<DrainStatus, Box<dyn Error>>` - The final status, which is not complete if the
+    ///   timeout ran out first. Complete drains stop being tracked.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use std::time::Duration;
+    /// use hashring::{HashRing, Config};
+    ///
+    /// # async fn run(mut hash_ring: HashRing<'static>) {
+    /// // Completed by the application's shutdown handler.
+    /// let (_shutdown_tx, shutdown_rx) = tokio::sync::oneshot::channel::<()>();
+    /// let shutdown = async {
+    ///     let _ = shutdown_rx.await;
+    /// };
+    /// let status = hash_ring
+    ///     .drain_on_shutdown("node1", shutdown, Duration::from_millis(100), Duration::from_secs(30))
+    ///     .await
+    ///     .unwrap();
+    /// if !status.is_complete() {
+    ///     eprintln!("exiting with {} partitions not migrated", status.pending.len());
+    /// }
+    /// # }
+    /// ```
+    #[cfg(feature = "tokio")]
+    pub async fn drain_on_shutdown<S>(&mut self, node_id: &str, shutdown: S, poll_interval: Duration, timeout: Duration) -> Result<DrainStatus, Box<dyn Error>>
+    where
+        S: Future<Output = ()>,
+    {
+        shutdown.await;
+        if self.drain_status(node_id).is_none() {
+            self.cordon(node_id)?;
+        }
+        let deadline = tokio::time::Instant::now() + timeout;
+        loop {
+            let status = self.drain_status(node_id).ok_or("node is not being drained")?;
+            if status.is_complete() {
+                self.finish_drain(node_id);
+                return Ok(status);
+            }
+            if tokio::time::Instant::now() >= deadline {
+                return Ok(status);
+            }
+            tokio::time::sleep(poll_interval.min(deadline - tokio::time::Instant::now())).await;
+        }
+    }
+
+    /// Runs `drain_on_shutdown` once the process receives `SIGTERM`.
+    ///
+    /// Must be called from within a tokio runtime.
+    #[cfg(all(unix, feature = "signal"))]
+    pub async fn drain_on_sigterm(&mut self, node_id: &str, poll_interval: Duration, timeout: Duration) -> Result<DrainStatus, Box<dyn Error>> {
+        let mut sigterm = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())?;
+        let shutdown = async move {
+            sigterm.recv().await;
+        };
+        self.drain_on_shutdown(node_id, shutdown, poll_interval, timeout).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::tests::test_nodes;
+    use crate::{Config, HashRing};
+
+    #[test]
+    fn test_drain_tracks_partitions() {
+        let mut hash_ring = HashRing::new(Config {
+            partition_count: 20,
+            ..Config::default()
+        })
+        .unwrap();
+        for node in test_nodes() {
+            hash_ring.add_node(node).unwrap();
+        }
+        let owned: Vec<usize> = (0..20)
+            .filter(|p| hash_ring.get_partition_owner(*p).unwrap().id() == "node1")
+            .collect();
+
+        let status = hash_ring.cordon("node1").unwrap();
+        assert!(owned.iter().all(|p| status.pending.contains(p)));
+        assert!(hash_ring.get_key(b"some_key").unwrap().id() != "node1");
+        assert!(hash_ring.replica_partitions("node1").is_empty());
+
+        let (last, rest) = status.pending.split_last().unwrap();
+        for part_id in rest {
+            assert!(!hash_ring.mark_partition_migrated("node1", *part_id).unwrap());
+        }
+        assert!(hash_ring.mark_partition_migrated("node1", *last).unwrap());
+        assert!(hash_ring.mark_partition_migrated("node1", *last).unwrap());
+        assert!(hash_ring.mark_partition_migrated("node2", 0).is_err());
+
+        let status = hash_ring.finish_drain("node1").unwrap();
+        assert!(status.is_complete());
+        assert!(hash_ring.drain_status("node1").is_none());
+    }
+
+    #[cfg(feature = "tokio")]
+    #[tokio::test(start_paused = true)]
+    async fn test_drain_on_shutdown_waits_for_migration() {
+        use std::time::Duration;
+
+        let mut hash_ring = HashRing::new(Config::default()).unwrap();
+        let mut shared = hash_ring.share();
+        for node in test_nodes() {
+            shared.add_node(node).unwrap();
+        }
+        let (poll, timeout) = (Duration::from_millis(10), Duration::from_secs(1));
+
+        let status = hash_ring.drain_on_shutdown("node1", async {}, poll, timeout).await.unwrap();
+        assert!(!status.is_complete());
+        assert!(hash_ring.get_key(b"some_key").unwrap().id() != "node1");
+
+        for part_id in &status.pending {
+            shared.mark_partition_migrated("node1", *part_id).unwrap();
+        }
+        let status = hash_ring.drain_on_shutdown("node1", async {}, poll, timeout).await.unwrap();
+        assert!(status.is_complete());
+        assert!(hash_ring.drain_status("node1").is_none());
+        assert!(hash_ring.drain_on_shutdown("node9", async {}, poll, timeout).await.is_err());
+    }
+}
@@ -0,0 +1,273 @@
+//! Structured events for topology changes.
+//!
+//! Every operation that changes membership or partition ownership reports a `RingEvent`
+//! to the registered `EventSink`s: the operation, the version it produced, the changes
+//! in the same form as the changelog, and how long it took. Sinks forward them to a log
+//! pipeline or a metrics system; with the `otel` feature, `OtelEventSink` records them
+//! as OpenTelemetry spans.
+//!
+//! Events are queued while the operation holds the locks of the ring, and dispatched to
+//! the sinks once it has released them.
+
+use std::collections::VecDeque;
+use std::fmt;
+use std::hash::BuildHasher;
+use std::sync::{Arc, Mutex, TryLockError};
+use std::time::Duration;
+
+use crate::sync::{lock, RwLock};
+use crate::{Change, HashRing};
+
+/// A completed operation that changed the topology of the ring.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct RingEvent {
+    /// The operation, such as `"add_node"` or `"split_partition"`.
+    pub op: &'static str,
+    /// The version of the ring after the operation.
+    pub version: u64,
+    pub changes: Vec<Change>,
+    pub duration: Duration,
+}
+
+impl RingEvent {
+    /// Returns the IDs of the nodes that joined.
+    pub fn nodes_added(&self) -> impl Iterator<Item = &str> {
+        self.changes.iter().filter_map(|change| match change {
+            Change::NodeAdded(id) => Some(id.as_str()),
+            _ => None,
+        })
+    }
+
+    /// Returns the IDs of the nodes that left.
+    pub fn nodes_removed(&self) -> impl Iterator<Item = &str> {
+        self.changes.iter().filter_map(|change| match change {
+            Change::NodeRemoved(id) => Some(id.as_str()),
+            _ => None,
+        })
+    }
+
+    /// Returns the number of partitions that got a new owner or were removed.
+    pub fn partitions_moved(&self) -> usize {
+        self.changes
+            .iter()
+            .filter(|change| matches!(change, Change::PartitionAssigned { .. } | Change::PartitionUnassigned { .. }))
+            .count()
+    }
+}
+
+/// A receiver of ring change events.
+///
+/// Sinks are called by the thread that changed the ring, once the change is complete and
+/// the ring is unlocked, so they may look keys up. Events are delivered one at a time and in
+/// order; the events of changes made by a sink are delivered after the one it is handling.
+/// Closures taking a `&RingEvent` are sinks.
+pub trait EventSink: Send + Sync {
+    fn on_event(&self, event: &RingEvent);
+
+    /// Called when a node is quarantined for flapping, see `set_flap_policy`.
+    fn on_quarantine(&self, _node_id: &str, _duration: Duration) {}
+}
+
+impl fmt::Debug for dyn EventSink {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt.write_str("EventSink")
+    }
+}
+
+impl<F> EventSink for F
+where
+    F: Fn(&RingEvent) + Send + Sync,
+{
+    fn on_event(&self, event: &RingEvent) {
+        self(event)
+    }
+}
+
+#[derive(Debug)]
+enum Pending {
+    Event(RingEvent),
+    Quarantine(String, Duration),
+}
+
+/// Events waiting for the operation that recorded them to unlock the ring.
+#[derive(Debug, Default)]
+pub(crate) struct PendingEvents {
+    queue: Mutex<VecDeque<Pending>>,
+    /// Held while events are delivered, so only one thread delivers them at a time.
+    dispatching: Mutex<()>,
+}
+
+impl PendingEvents {
+    pub(crate) fn push_event(&self, event: RingEvent) {
+        lock(&self.queue).push_back(Pending::Event(event));
+    }
+
+    pub(crate) fn push_quarantine(&self, node_id: &str, duration: Duration) {
+        lock(&self.queue).push_back(Pending::Quarantine(node_id.to_string(), duration));
+    }
+}
+
+/// Delivers the pending events of a ring when dropped.
+///
+/// Operations take it before locking the ring, so it is dropped after their lock guards.
+pub(crate) struct EventDispatch {
+    pending: Arc<PendingEvents>,
+    sinks: Arc<RwLock<Vec<Arc<dyn EventSink>>>>,
+}
+
+impl Drop for EventDispatch {
+    fn drop(&mut self) {
+        loop {
+            // The thread already delivering events, possibly this one if a sink changed
+            // the ring, delivers these too.
+            let dispatching = match self.pending.dispatching.try_lock() {
+                Ok(guard) => guard,
+                Err(TryLockError::Poisoned(err)) => err.into_inner(),
+                Err(TryLockError::WouldBlock) => return,
+            };
+            loop {
+                let Some(pending) = lock(&self.pending.queue).pop_front() else { break };
+                let sinks = self.sinks.wait_read().clone();
+                for sink in &sinks {
+                    match &pending {
+                        Pending::Event(event) => sink.on_event(event),
+                        Pending::Quarantine(node_id, duration) => sink.on_quarantine(node_id, *duration),
+                    }
+                }
+            }
+            drop(dispatching);
+            // Events queued after the queue was found empty, but before the dispatch ended,
+            // were left to this thread.
+            if lock(&self.pending.queue).is_empty() {
+                return;
+            }
+        }
+    }
+}
+
+impl<'a, H, K> HashRing<'a, H, K>
+where
+    H: BuildHasher,
+    K: BuildHasher,
+{
+    /// Returns a guard delivering the events recorded meanwhile to the sinks once dropped.
+    pub(crate) fn dispatch_events(&self) -> EventDispatch {
+        EventDispatch {
+            pending: self.pending_events.clone(),
+            sinks: self.event_sinks.clone(),
+        }
+    }
+
+    /// Registers a sink receiving an event for every later topology change.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use std::sync::{Arc, Mutex};
+    /// use hashring::{HashRing, Config, Node, RingEvent};
+    ///
+    /// #[derive(Debug)]
+    /// struct MyNode<'a> {
+    ///     name: &'a str,
+    /// }
+    ///
+    /// impl<'a> Node<'a> for MyNode<'a> {
+    ///     fn id(&self) -> &'a str {
+    ///         self.name
+    ///     }
+    /// }
+    ///
+    /// let events = Arc::new(Mutex::new(Vec::new()));
+    /// let received = events.clone();
+    /// let mut hash_ring = HashRing::new(Config::default()).unwrap();
+    /// hash_ring.add_event_sink(Arc::new(move |event: &RingEvent| received.lock().unwrap().push(event.clone())));
+    ///
+    /// hash_ring.add_node(Arc::new(MyNode { name: "node1" })).unwrap();
+    ///
+    /// let events = events.lock().unwrap();
+    /// assert_eq!(events[0].op, "add_node");
+    /// assert_eq!(events[0].nodes_added().collect::<Vec<_>>(), ["node1"]);
+    /// ```
+    pub fn add_event_sink(&mut self, sink: Arc<dyn EventSink>) {
+        let nodes = self.nodes.wait_read();
+        let partitions = self.partitions.wait_read();
+        let mut sinks = self.event_sinks.wait_write();
+        let mut changelog = self.changelog.wait_write();
+        // Without a changelog the topology isn't tracked yet.
+        if sinks.is_empty() && !changelog.is_enabled() {
+            changelog.track(self.version(), &nodes, &partitions);
+        }
+        sinks.push(sink);
+    }
+
+    /// Removes every event sink.
+    pub fn clear_event_sinks(&mut self) {
+        self.event_sinks.wait_write().clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use crate::tests::test_nodes;
+    use crate::{Change, Config, HashRing, RingEvent};
+
+    #[test]
+    fn test_event_sinks_receive_changes() {
+        let mut hash_ring = HashRing::new(Config::default()).unwrap();
+        let nodes = test_nodes();
+        hash_ring.add_node(nodes[0].clone()).unwrap();
+
+        let events: Arc<Mutex<Vec<RingEvent>>> = Arc::new(Mutex::new(Vec::new()));
+        let received = events.clone();
+        hash_ring.add_event_sink(Arc::new(move |event: &RingEvent| received.lock().unwrap().push(event.clone())));
+        hash_ring.add_node(nodes[1].clone()).unwrap();
+        hash_ring.remove_node("node1").unwrap();
+        hash_ring.split_partition(0).unwrap();
+        // The changelog stays disabled.
+        assert!(hash_ring.changelog(0).is_none());
+
+        let events = events.lock().unwrap();
+        let ops: Vec<&str> = events.iter().map(|event| event.op).collect();
+        assert_eq!(ops, ["add_node", "remove_node", "split_partition"]);
+        assert_eq!(events[0].nodes_added().collect::<Vec<_>>(), ["node2"]);
+        assert!(events[0].partitions_moved() > 0);
+        assert_eq!(events[1].nodes_removed().collect::<Vec<_>>(), ["node1"]);
+        // Node2 is the only node left, so it owns every partition of node1.
+        assert_eq!(events[1].partitions_moved(), events[1].changes.len() - 1);
+        assert!(events[2].changes.contains(&Change::PartitionUnassigned { partition: 0 }));
+        assert_eq!(events[2].version, hash_ring.version());
+
+        hash_ring.clear_event_sinks();
+        hash_ring.add_node(nodes[2].clone()).unwrap();
+        assert_eq!(events.len(), 3);
+    }
+
+    #[test]
+    fn test_sinks_run_with_the_ring_unlocked() {
+        let mut hash_ring = HashRing::new(Config::default()).unwrap();
+        let nodes = test_nodes();
+        hash_ring.add_node(nodes[0].clone()).unwrap();
+
+        // The sink looks keys up and changes the ring through another handle.
+        let shared = Mutex::new(hash_ring.share());
+        let node3 = nodes[2].clone();
+        let added: Arc<Mutex<Vec<String>>> = Arc::new(Mutex::new(Vec::new()));
+        let received = added.clone();
+        hash_ring.add_event_sink(Arc::new(move |event: &RingEvent| {
+            let mut shared = shared.lock().unwrap();
+            assert!(shared.get_key(b"some_key").is_some());
+            if event.nodes_added().any(|id| id == "node2") {
+                shared.add_node(node3.clone()).unwrap();
+            }
+            received.lock().unwrap().extend(event.nodes_added().map(String::from));
+        }));
+        hash_ring.add_node(nodes[1].clone()).unwrap();
+
+        // The event of the change made by the sink follows the one it handled.
+        assert_eq!(*added.lock().unwrap(), ["node2", "node3"]);
+        assert_eq!(hash_ring.virtual_nodes_per_node().len(), 3);
+    }
+}
@@ -0,0 +1,409 @@
+//! Symmetric snapshot exchange between peers.
+//!
+//! Peers keeping their own copy of the ring converge on membership without a central
+//! coordinator by exchanging `TaggedSnapshot`s: a snapshot tagged with the epoch, the
+//! peer it comes from and a vector clock counting the topology changes each peer made.
+//! A peer's own entry is advanced whenever its ring changed since it last tagged or
+//! reconciled a snapshot. `reconcile` applies a remote snapshot by these rules:
+//!
+//! * A higher epoch wins outright and a lower one is ignored, so operators can force a
+//!   topology on every peer with `bump_epoch`.
+//! * Within an epoch, a snapshot whose clock is behind or equal to ours is ignored, and one
+//!   ahead of ours is adopted as is.
+//! * Concurrent snapshots are merged: the members are the union of both sides, and the
+//!   configuration, split partitions and pinned groups of the peer with the greater ID win.
+//!
+//! Layout (little endian):
+//!
+//! ```text
+//! magic "HRVS" | epoch: u64 | originator | clock entries: u32 | (peer, counter: u64)... |
+//! snapshot | crc32 of everything before: u32
+//! ```
+
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::error::Error;
+use std::hash::BuildHasher;
+use std::sync::Arc;
+
+use crate::crc::crc32;
+use crate::persist::{Decoder, Encoder};
+use crate::{HashRing, Node, RingSnapshot};
+
+const MAGIC: &[u8; 4] = b"HRVS";
+
+/// Counts the changes made by every peer.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct VectorClock {
+    counters: BTreeMap<String, u64>,
+}
+
+impl VectorClock {
+    /// Creates an empty clock.
+    pub fn new() -> VectorClock {
+        VectorClock::default()
+    }
+
+    /// Returns the number of changes seen from a peer.
+    pub fn get(&self, peer: &str) -> u64 {
+        self.counters.get(peer).copied().unwrap_or(0)
+    }
+
+    /// Counts a change made by a peer.
+    pub fn increment(&mut self, peer: &str) {
+        *self.counters.entry(peer.to_string()).or_default() += 1;
+    }
+
+    /// Takes the larger counter of both clocks for every peer.
+    pub fn merge(&mut self, other: &VectorClock) {
+        for (peer, counter) in &other.counters {
+            let ours = self.counters.entry(peer.clone()).or_default();
+            *ours = (*ours).max(*counter);
+        }
+    }
+
+    /// Iterates over the peers and their counters, sorted by peer.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, u64)> {
+        self.counters.iter().map(|(peer, counter)| (peer.as_str(), *counter))
+    }
+}
+
+/// Clocks are ordered when one has seen every change the other has; concurrent clocks
+/// are not comparable.
+impl PartialOrd for VectorClock {
+    fn partial_cmp(&self, other: &VectorClock) -> Option<Ordering> {
+        let mut ordering = Ordering::Equal;
+        for peer in self.counters.keys().chain(other.counters.keys()) {
+            match (ordering, self.get(peer).cmp(&other.get(peer))) {
+                (_, Ordering::Equal) => {}
+                (Ordering::Equal, next) => ordering = next,
+                (current, next) if current != next => return None,
+                _ => {}
+            }
+        }
+        Some(ordering)
+    }
+}
+
+/// A snapshot tagged with what `reconcile` needs to order it.
+#[derive(Debug, Clone)]
+pub struct TaggedSnapshot {
+    /// The epoch the snapshot was taken in.
+    pub epoch: u64,
+    /// The ID of the peer that took the snapshot.
+    pub originator: String,
+    /// The changes reflected in the snapshot.
+    pub clock: VectorClock,
+    pub snapshot: RingSnapshot,
+}
+
+impl TaggedSnapshot {
+    /// Encodes the tagged snapshot, to send it to another peer.
+    pub fn to_bytes(&self) -> Result<Vec<u8>, Box<dyn Error>> {
+        let mut enc = Encoder::new();
+        enc.raw(MAGIC);
+        enc.u64(self.epoch);
+        enc.str(&self.originator);
+        enc.u32(self.clock.counters.len() as u32);
+        for (peer, counter) in self.clock.iter() {
+            enc.str(peer);
+            enc.u64(counter);
+        }
+        enc.bytes(&self.snapshot.to_bytes()?);
+        let mut buf = enc.into_inner();
+        let checksum = crc32(&buf);
+        buf.extend_from_slice(&checksum.to_le_bytes());
+        Ok(buf)
+    }
+
+    /// Decodes a tagged snapshot encoded by `to_bytes`.
+    pub fn from_bytes(data: &[u8]) -> Result<TaggedSnapshot, Box<dyn Error>> {
+        if data.len() < MAGIC.len() + 4 {
+            return Err("tagged snapshot is truncated".into());
+        }
+        let (body, checksum) = data.split_at(data.len() - 4);
+        if crc32(body) != u32::from_le_bytes(checksum.try_into()?) {
+            return Err("tagged snapshot checksum mismatch".into());
+        }
+        let mut dec = Decoder::new(body);
+        if dec.take(MAGIC.len())? != MAGIC {
+            return Err("not a tagged snapshot".into());
+        }
+        let epoch = dec.u64()?;
+        let originator = dec.string()?;
+        let mut clock = VectorClock::new();
+        for _ in 0..dec.u32()? {
+            let peer = dec.string()?;
+            clock.counters.insert(peer, dec.u64()?);
+        }
+        let snapshot = RingSnapshot::from_bytes(dec.bytes()?)?;
+        if !dec.is_empty() {
+            return Err("trailing bytes after tagged snapshot".into());
+        }
+        Ok(TaggedSnapshot {
+            epoch,
+            originator,
+            clock,
+            snapshot,
+        })
+    }
+}
+
+/// What `reconcile` did with a remote snapshot.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Reconciliation {
+    /// The snapshot is from an older epoch and was ignored.
+    Stale,
+    /// The ring had already seen every change in the snapshot.
+    UpToDate,
+    /// The snapshot was ahead of the ring and replaced its topology.
+    Adopted,
+    /// The snapshot was concurrent with the ring and was merged into it.
+    Merged,
+}
+
+/// The exchange state of a ring.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct PeerState {
+    id: Option<String>,
+    epoch: u64,
+    clock: VectorClock,
+    /// The version of the ring the clock accounts for.
+    seen_version: u64,
+}
+
+impl<'a, H, K> HashRing<'a, H, K>
+where
+    H: BuildHasher,
+    K: BuildHasher,
+{
+    /// Sets the ID this ring exchanges snapshots under. Every peer needs a distinct one.
+    pub fn set_peer_id(&self, id: &str) {
+        self.peer.wait_write().id = Some(id.to_string());
+    }
+
+    /// Starts a new epoch, whose snapshots win over those of every earlier epoch.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<u64, Box<dyn Error>>` - The new epoch, or an error if no peer ID is set.
+    pub fn bump_epoch(&self) -> Result<u64, Box<dyn Error>> {
+        let mut peer = self.peer.write()?;
+        let id = peer.id.clone().ok_or("peer id is not set")?;
+        peer.epoch += 1;
+        peer.clock = VectorClock::new();
+        peer.clock.increment(&id);
+        peer.seen_version = self.version();
+        Ok(peer.epoch)
+    }
+
+    /// Takes a snapshot tagged with the epoch, peer ID and vector clock of this ring.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<TaggedSnapshot, Box<dyn Error>>` - The tagged snapshot, or an error if no peer ID is set.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use std::sync::Arc;
+    /// use hashring::{HashRing, Config, Node, Reconciliation, TaggedSnapshot};
+    ///
+    /// #[derive(Debug)]
+    /// struct MyNode<'a> {
+    ///     name: &'a str,
+    /// }
+    ///
+    /// impl<'a> Node<'a> for MyNode<'a> {
+    ///     fn id(&self) -> &'a str {
+    ///         self.name
+    ///     }
+    /// }
+    ///
+    /// let node1: Arc<dyn Node> = Arc::new(MyNode { name: "node1" });
+    /// let node2: Arc<dyn Node> = Arc::new(MyNode { name: "node2" });
+    /// let resolve = |id: &str| [&node1, &node2].into_iter().find(|node| node.id() == id).cloned();
+    ///
+    /// let mut a = HashRing::new(Config::default()).unwrap();
+    /// a.set_peer_id("peer-a");
+    /// a.add_node(node1.clone()).unwrap();
+    /// let mut b = HashRing::new(Config::default()).unwrap();
+    /// b.set_peer_id("peer-b");
+    /// b.add_node(node2.clone()).unwrap();
+    ///
+    /// // Each peer sends its snapshot to the other.
+    /// let from_a = TaggedSnapshot::from_bytes(&a.tagged_snapshot().unwrap().to_bytes().unwrap()).unwrap();
+    /// let from_b = b.tagged_snapshot().unwrap();
+    /// assert_eq!(a.reconcile(&from_b, resolve).unwrap(), Reconciliation::Merged);
+    /// assert_eq!(b.reconcile(&from_a, resolve).unwrap(), Reconciliation::Merged);
+    /// assert_eq!(a.snapshot().nodes, b.snapshot().nodes);
+    /// ```
+    pub fn tagged_snapshot(&self) -> Result<TaggedSnapshot, Box<dyn Error>> {
+        let snapshot = self.snapshot();
+        let mut peer = self.peer.write()?;
+        let originator = peer.id.clone().ok_or("peer id is not set")?;
+        self.observe_local_changes(&mut peer, &originator);
+        Ok(TaggedSnapshot {
+            epoch: peer.epoch,
+            originator,
+            clock: peer.clock.clone(),
+            snapshot,
+        })
+    }
+
+    /// Applies a snapshot received from another peer, by the rules of the module docs.
+    ///
+    /// # Arguments
+    ///
+    /// * `remote` - The snapshot of the other peer.
+    /// * `resolver` - Returns the node for an ID this ring has no member for, or `None` if it is unknown.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Reconciliation, Box<dyn Error>>` - What was done with the snapshot. On error the ring is unchanged.
+    pub fn reconcile<F>(&mut self, remote: &TaggedSnapshot, mut resolver: F) -> Result<Reconciliation, Box<dyn Error>>
+    where
+        F: FnMut(&str) -> Option<Arc<dyn Node<'a> + 'a>>,
+    {
+        let (id, epoch, clock) = {
+            let mut peer = self.peer.write()?;
+            let id = peer.id.clone().ok_or("peer id is not set")?;
+            self.observe_local_changes(&mut peer, &id);
+            (id, peer.epoch, peer.clock.clone())
+        };
+
+        let adopt = match remote.epoch.cmp(&epoch) {
+            Ordering::Less => return Ok(Reconciliation::Stale),
+            Ordering::Greater => true,
+            Ordering::Equal => match remote.clock.partial_cmp(&clock) {
+                Some(Ordering::Less | Ordering::Equal) => return Ok(Reconciliation::UpToDate),
+                Some(Ordering::Greater) => true,
+                None => false,
+            },
+        };
+
+        let members: HashMap<String, Arc<dyn Node<'a> + 'a>> = self.nodes.read()?.clone();
+        let mut resolve = |id: &str| members.get(id).cloned().or_else(|| resolver(id));
+        if adopt {
+            self.restore(&remote.snapshot, &mut resolve)?;
+            let mut peer = self.peer.write()?;
+            peer.epoch = remote.epoch;
+            peer.clock = remote.clock.clone();
+            peer.seen_version = self.version();
+            return Ok(Reconciliation::Adopted);
+        }
+
+        let ours = self.snapshot();
+        let (winner, loser) = if remote.originator > id { (&remote.snapshot, &ours) } else { (&ours, &remote.snapshot) };
+        let mut nodes = ours.nodes.clone();
+        nodes.extend(remote.snapshot.nodes.iter().cloned());
+        nodes.sort();
+        nodes.dedup();
+        let mut groups = loser.groups.clone();
+        groups.extend(winner.groups.clone());
+        let mut weights = loser.weights.clone();
+        weights.extend(winner.weights.clone());
+        let merged = RingSnapshot {
+            nodes,
+            config: winner.config.clone(),
+            partitions: BTreeMap::new(),
+            splits: winner.splits.clone(),
+            groups,
+            moved: BTreeSet::new(),
+            weights,
+        };
+        // Without a change of our own, the merge is exactly the remote topology.
+        let contributed = merged.nodes != remote.snapshot.nodes || merged.config != remote.snapshot.config;
+        let changed = merged.nodes != ours.nodes || merged.config != ours.config || merged.splits != ours.splits || merged.groups != ours.groups || merged.weights != ours.weights;
+        if changed {
+            self.restore(&merged, &mut resolve)?;
+        }
+
+        let mut peer = self.peer.write()?;
+        peer.clock.merge(&remote.clock);
+        if contributed {
+            peer.clock.increment(&id);
+        }
+        peer.seen_version = self.version();
+        Ok(Reconciliation::Merged)
+    }
+
+    /// Counts changes made to the ring since the clock was last updated as our own.
+    fn observe_local_changes(&self, peer: &mut PeerState, id: &str) {
+        let version = self.version();
+        if version != peer.seen_version {
+            peer.clock.increment(id);
+            peer.seen_version = version;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cmp::Ordering;
+    use std::sync::Arc;
+
+    use crate::tests::test_nodes;
+    use crate::{Config, HashRing, Node, Reconciliation, TaggedSnapshot, VectorClock};
+
+    #[test]
+    fn test_vector_clock_ordering() {
+        let mut a = VectorClock::new();
+        let mut b = VectorClock::new();
+        assert_eq!(a.partial_cmp(&b), Some(Ordering::Equal));
+        a.increment("a");
+        assert!(a > b);
+        b.increment("b");
+        assert_eq!(a.partial_cmp(&b), None);
+        b.merge(&a);
+        assert!(b > a);
+        assert_eq!(b.iter().collect::<Vec<_>>(), [("a", 1), ("b", 1)]);
+    }
+
+    #[test]
+    fn test_peers_converge_without_coordinator() {
+        let nodes = test_nodes();
+        let resolve = |id: &str| nodes.iter().find(|node| node.id() == id).map(|node| node.clone() as Arc<dyn Node<'static>>);
+        let peer = |id: &str| {
+            let hash_ring = HashRing::new(Config::default()).unwrap();
+            hash_ring.set_peer_id(id);
+            hash_ring
+        };
+        let mut a = peer("a");
+        let mut b = peer("b");
+        assert!(HashRing::new(Config::default()).unwrap().tagged_snapshot().is_err());
+
+        // Concurrent additions are merged on both sides.
+        a.add_node(nodes[0].clone()).unwrap();
+        b.add_node(nodes[1].clone()).unwrap();
+        let from_a = a.tagged_snapshot().unwrap();
+        let from_b = TaggedSnapshot::from_bytes(&b.tagged_snapshot().unwrap().to_bytes().unwrap()).unwrap();
+        assert_eq!(a.reconcile(&from_b, resolve).unwrap(), Reconciliation::Merged);
+        assert_eq!(b.reconcile(&from_a, resolve).unwrap(), Reconciliation::Merged);
+        assert_eq!(a.snapshot().nodes, ["node1", "node2"]);
+        assert_eq!(a.snapshot().partitions, b.snapshot().partitions);
+
+        // One more round makes the clocks agree.
+        let from_a = a.tagged_snapshot().unwrap();
+        let from_b = b.tagged_snapshot().unwrap();
+        assert_eq!(a.reconcile(&from_b, resolve).unwrap(), Reconciliation::Merged);
+        assert_eq!(b.reconcile(&from_a, resolve).unwrap(), Reconciliation::Merged);
+        assert_eq!(a.tagged_snapshot().unwrap().clock, b.tagged_snapshot().unwrap().clock);
+        assert_eq!(a.reconcile(&b.tagged_snapshot().unwrap(), resolve).unwrap(), Reconciliation::UpToDate);
+
+        // A change made after seeing everything is adopted as is, removals included.
+        b.remove_node("node1").unwrap();
+        b.add_node(nodes[2].clone()).unwrap();
+        assert_eq!(a.reconcile(&b.tagged_snapshot().unwrap(), resolve).unwrap(), Reconciliation::Adopted);
+        assert_eq!(a.snapshot().nodes, ["node2", "node3"]);
+        assert_eq!(b.reconcile(&a.tagged_snapshot().unwrap(), resolve).unwrap(), Reconciliation::UpToDate);
+
+        // A new epoch wins over anything from an older one.
+        let stale = b.tagged_snapshot().unwrap();
+        a.remove_node("node3").unwrap();
+        assert_eq!(a.bump_epoch().unwrap(), 1);
+        assert_eq!(b.reconcile(&a.tagged_snapshot().unwrap(), resolve).unwrap(), Reconciliation::Adopted);
+        assert_eq!(b.snapshot().nodes, ["node2"]);
+        assert_eq!(a.reconcile(&stale, resolve).unwrap(), Reconciliation::Stale);
+    }
+}
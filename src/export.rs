@@ -0,0 +1,142 @@
+//! Human-readable export and import of ring snapshots.
+//!
+//! Snapshots carry the configuration and the members, so operators can keep the
+//! topology in config management and bootstrap rings from files. JSON and TOML
+//! are available behind the `json` and `toml` features.
+
+#[cfg(any(feature = "json", feature = "toml"))]
+use std::error::Error;
+#[cfg(any(feature = "json", feature = "toml"))]
+use std::hash::BuildHasher;
+
+#[cfg(any(feature = "json", feature = "toml"))]
+use crate::{HashRing, RingSnapshot};
+
+/// Serializes partition assignments as a map keyed by the partition ID as a string,
+/// since TOML tables only allow string keys.
+pub(crate) mod partition_map {
+    use std::collections::BTreeMap;
+
+    use serde::de::Error;
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    pub fn serialize<S: Serializer>(partitions: &BTreeMap<usize, String>, serializer: S) -> Result<S::Ok, S::Error> {
+        let partitions: BTreeMap<String, &String> = partitions.iter().map(|(part_id, id)| (part_id.to_string(), id)).collect();
+        partitions.serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<BTreeMap<usize, String>, D::Error> {
+        let partitions = BTreeMap::<String, String>::deserialize(deserializer)?;
+        partitions
+            .into_iter()
+            .map(|(part_id, id)| {
+                part_id
+                    .parse::<usize>()
+                    .map(|part_id| (part_id, id))
+                    .map_err(|_| D::Error::custom(format!("invalid partition id {}", part_id)))
+            })
+            .collect()
+    }
+}
+
+#[cfg(feature = "json")]
+impl RingSnapshot {
+    /// Serializes the snapshot as pretty-printed JSON.
+    pub fn to_json(&self) -> Result<String, Box<dyn Error>> {
+        Ok(serde_json::to_string_pretty(self)?)
+    }
+
+    /// Parses a snapshot from JSON.
+    ///
+    /// Missing configuration fields take their default values, and missing partition
+    /// assignments are computed when the snapshot is restored.
+    pub fn from_json(data: &str) -> Result<RingSnapshot, Box<dyn Error>> {
+        Ok(serde_json::from_str(data)?)
+    }
+}
+
+#[cfg(feature = "toml")]
+impl RingSnapshot {
+    /// Serializes the snapshot as TOML.
+    pub fn to_toml(&self) -> Result<String, Box<dyn Error>> {
+        Ok(toml::to_string_pretty(self)?)
+    }
+
+    /// Parses a snapshot from TOML.
+    ///
+    /// Missing configuration fields take their default values, and missing partition
+    /// assignments are computed when the snapshot is restored.
+    pub fn from_toml(data: &str) -> Result<RingSnapshot, Box<dyn Error>> {
+        Ok(toml::from_str(data)?)
+    }
+}
+
+#[cfg(any(feature = "json", feature = "toml"))]
+impl<'a, H, K> HashRing<'a, H, K>
+where
+    H: BuildHasher,
+    K: BuildHasher,
+{
+    /// Exports the configuration, membership and partition assignment as JSON.
+    #[cfg(feature = "json")]
+    pub fn to_json(&self) -> Result<String, Box<dyn Error>> {
+        self.snapshot().to_json()
+    }
+
+    /// Exports the configuration, membership and partition assignment as TOML.
+    #[cfg(feature = "toml")]
+    pub fn to_toml(&self) -> Result<String, Box<dyn Error>> {
+        self.snapshot().to_toml()
+    }
+}
+
+#[cfg(all(test, any(feature = "json", feature = "toml")))]
+mod tests {
+    #[cfg(feature = "json")]
+    use std::sync::Arc;
+
+    use crate::tests::test_nodes;
+    #[cfg(feature = "json")]
+    use crate::Node;
+    use crate::{Config, HashRing, RingSnapshot};
+
+    fn ring() -> HashRing<'static> {
+        let mut hash_ring = HashRing::new(Config::default()).unwrap();
+        for node in test_nodes() {
+            hash_ring.add_node(node).unwrap();
+        }
+        hash_ring
+    }
+
+    #[cfg(feature = "json")]
+    #[test]
+    fn test_json_roundtrip() {
+        let hash_ring = ring();
+        let snapshot = RingSnapshot::from_json(&hash_ring.to_json().unwrap()).unwrap();
+        assert_eq!(snapshot.nodes, hash_ring.snapshot().nodes);
+        assert_eq!(snapshot.partitions, hash_ring.snapshot().partitions);
+    }
+
+    #[cfg(feature = "json")]
+    #[test]
+    fn test_json_bootstrap_from_membership() {
+        let nodes = test_nodes();
+        let snapshot = RingSnapshot::from_json(r#"{ "config": { "partition_count": 10 }, "nodes": ["node1", "node2"] }"#).unwrap();
+        assert_eq!(snapshot.config.replication_factor, Config::default().replication_factor);
+
+        let hash_ring: HashRing = HashRing::from_snapshot(&snapshot, Default::default(), Default::default(), |id| {
+            nodes.iter().find(|n| n.id() == id).map(|n| n.clone() as Arc<dyn Node<'static>>)
+        })
+        .unwrap();
+        assert_eq!(hash_ring.snapshot().partitions.len(), 10);
+    }
+
+    #[cfg(feature = "toml")]
+    #[test]
+    fn test_toml_roundtrip() {
+        let hash_ring = ring();
+        let snapshot = RingSnapshot::from_toml(&hash_ring.to_toml().unwrap()).unwrap();
+        assert_eq!(snapshot.nodes, hash_ring.snapshot().nodes);
+        assert_eq!(snapshot.partitions, hash_ring.snapshot().partitions);
+    }
+}
@@ -0,0 +1,166 @@
+//! What-if analysis of node failures.
+//!
+//! Before a maintenance window, `simulate_failure` reports what taking a set of nodes
+//! down would do to every partition, without touching the ring: which partitions lose
+//! their primary and who takes over, and which lose a majority of their replicas.
+
+use std::collections::{BTreeMap, HashSet};
+use std::hash::BuildHasher;
+
+use crate::HashRing;
+
+/// The effect of a set of nodes failing on the partitions of a ring.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct ImpactReport {
+    /// The partitions whose owner fails, in ascending order.
+    pub lost_primary: Vec<usize>,
+    /// The partitions with fewer than a majority of their replicas left, in ascending order.
+    pub lost_quorum: Vec<usize>,
+    /// The partitions with no replica left, in ascending order.
+    pub unavailable: Vec<usize>,
+    /// The owner each partition in `lost_primary` falls back to, `None` if no node is left.
+    pub new_owners: BTreeMap<usize, Option<String>>,
+}
+
+impl<'a, H, K> HashRing<'a, H, K>
+where
+    H: BuildHasher,
+    K: BuildHasher,
+{
+    /// Returns the replica set of every partition: the distinct nodes of the preference
+    /// list starting at the partition's position, owner first.
+    pub(crate) fn partition_replica_sets(&self) -> Vec<(usize, Vec<String>)> {
+        let sorted_set = self.sorted_nodes_hash_set.wait_read();
+        let mut replica_sets = Vec::new();
+        for part_id in self.partition_ids() {
+            let point = self.hash_partition_id(part_id);
+            let mut seen = HashSet::new();
+            let replicas: Vec<String> = sorted_set
+                .range(point..)
+                .chain(sorted_set.range(..point))
+                .map(|(_, node)| node.id())
+                .filter(|node_id| seen.insert(*node_id))
+                .take(self.config().replication_factor)
+                .map(|node_id| node_id.to_string())
+                .collect();
+            replica_sets.push((part_id, replicas));
+        }
+        replica_sets
+    }
+
+    /// Reports the impact of the given nodes failing at the same time.
+    ///
+    /// Replica sets are the first `replication_factor` distinct nodes from each
+    /// partition's position, and a quorum is a majority of them. IDs of nodes that are
+    /// not in the ring are ignored.
+    ///
+    /// # Arguments
+    ///
+    /// * `node_ids` - The IDs of the nodes to take down.
+    ///
+    /// # Returns
+    ///
+    /// * `ImpactReport` - The partitions affected and their fallback owners.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use std::sync::Arc;
+    /// use hashring::{HashRing, Config, Node};
+    ///
+    /// #[derive(Debug)]
+    /// struct MyNode<'a> {
+    ///     name: &'a str,
+    /// }
+    ///
+    /// impl<'a> Node<'a> for MyNode<'a> {
+    ///     fn id(&self) -> &'a str {
+    ///         self.name
+    ///     }
+    /// }
+    ///
+    /// let mut config = Config::default();
+    /// config.replication_factor = 3;
+    /// let mut hash_ring = HashRing::new(config).unwrap();
+    /// for name in ["node1", "node2", "node3"] {
+    ///     hash_ring.add_node(Arc::new(MyNode { name })).unwrap();
+    /// }
+    ///
+    /// let report = hash_ring.simulate_failure(&["node1"]);
+    /// assert!(report.lost_quorum.is_empty());
+    /// for (part_id, new_owner) in &report.new_owners {
+    ///     println!("partition {} moves to {:?}", part_id, new_owner);
+    /// }
+    /// ```
+    pub fn simulate_failure(&self, node_ids: &[&str]) -> ImpactReport {
+        let failed: HashSet<&str> = node_ids.iter().copied().collect();
+        let mut report = ImpactReport::default();
+        for (part_id, replicas) in self.partition_replica_sets() {
+            let owner = match replicas.first() {
+                Some(owner) => owner,
+                None => continue,
+            };
+            let surviving = replicas.iter().filter(|id| !failed.contains(id.as_str())).count();
+            if failed.contains(owner.as_str()) {
+                report.lost_primary.push(part_id);
+                report.new_owners.insert(part_id, self.surviving_owner(part_id, &failed));
+            }
+            if surviving <= replicas.len() / 2 {
+                report.lost_quorum.push(part_id);
+            }
+            if surviving == 0 {
+                report.unavailable.push(part_id);
+            }
+        }
+        report
+    }
+
+    /// Returns the first node from the partition's position that is not failed, which
+    /// is the owner the partition would get once the failed nodes are removed.
+    fn surviving_owner(&self, part_id: usize, failed: &HashSet<&str>) -> Option<String> {
+        let sorted_set = self.sorted_nodes_hash_set.wait_read();
+        let point = self.hash_partition_id(part_id);
+        sorted_set
+            .range(point..)
+            .chain(sorted_set.range(..point))
+            .map(|(_, node)| node.id())
+            .find(|node_id| !failed.contains(node_id))
+            .map(|node_id| node_id.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::tests::test_nodes;
+    use crate::{Config, HashRing};
+
+    #[test]
+    fn test_simulate_failure_matches_removal() {
+        let mut hash_ring = HashRing::new(Config { replication_factor: 3, ..Config::default() }).unwrap();
+        for node in test_nodes() {
+            hash_ring.add_node(node).unwrap();
+        }
+        let report = hash_ring.simulate_failure(&["node1", "node4"]);
+        let owned: Vec<usize> = hash_ring.partitions_of("node1").collect();
+        assert_eq!(report.lost_primary, owned);
+        assert!(report.lost_quorum.is_empty());
+        assert!(report.unavailable.is_empty());
+        assert_eq!(hash_ring.simulate_failure(&[]), Default::default());
+
+        // Every partition keeps one replica out of three after two failures.
+        let report = hash_ring.simulate_failure(&["node1", "node2"]);
+        assert_eq!(report.lost_quorum, hash_ring.partition_ids());
+        assert!(report.unavailable.is_empty());
+        let new_owners = report.new_owners.clone();
+
+        // Removing the nodes gives the partitions the predicted owners.
+        hash_ring.remove_node("node1").unwrap();
+        hash_ring.remove_node("node2").unwrap();
+        for (part_id, new_owner) in new_owners {
+            assert_eq!(hash_ring.get_partition_owner(part_id).map(|node| node.id().to_string()), new_owner);
+        }
+        let report = hash_ring.simulate_failure(&["node3"]);
+        assert_eq!(report.unavailable, hash_ring.partition_ids());
+        assert!(report.new_owners.values().all(Option::is_none));
+    }
+}
@@ -0,0 +1,208 @@
+//! Chord-style finger tables.
+//!
+//! In a peer-to-peer deployment no process holds the whole ring. Every virtual node
+//! instead keeps its successor and `k` fingers, where finger `i` points to the
+//! successor of `position + 2^(64 - k + i)`. A lookup is forwarded to the farthest
+//! finger preceding the key until the key falls between a node and its successor,
+//! so with `k = 64` lookups take O(log n) hops.
+
+use std::error::Error;
+use std::hash::BuildHasher;
+use std::sync::Arc;
+
+use crate::{HashRing, Node};
+
+/// An entry of a finger table.
+#[derive(Debug, Clone)]
+pub struct Finger<'a> {
+    /// The point on the ring the finger covers.
+    pub start: u64,
+    /// The position of the virtual node at or after `start`.
+    pub point: u64,
+    /// The node owning that virtual node.
+    pub node: Arc<dyn Node<'a> + 'a>,
+}
+
+/// The next step of a lookup.
+#[derive(Debug, Clone)]
+pub enum Route<'a> {
+    /// The key belongs to this virtual node.
+    Owner(Finger<'a>),
+    /// The lookup continues at this virtual node.
+    Forward(Finger<'a>),
+}
+
+/// The routing state of one virtual node.
+#[derive(Debug, Clone)]
+pub struct FingerTable<'a> {
+    /// The position of the virtual node on the ring.
+    pub position: u64,
+    /// The next virtual node on the ring.
+    pub successor: Finger<'a>,
+    /// The fingers, nearest first.
+    pub fingers: Vec<Finger<'a>>,
+}
+
+impl<'a> FingerTable<'a> {
+    /// Decides where a lookup for `hash` goes next.
+    ///
+    /// # Arguments
+    ///
+    /// * `hash` - The hash of the key being looked up.
+    ///
+    /// # Returns
+    ///
+    /// * `Route<'a>` - The owner of the key if it is the successor, otherwise the farthest
+    ///   known virtual node preceding the key.
+    pub fn next_hop(&self, hash: u64) -> Route<'a> {
+        let distance = hash.wrapping_sub(self.position);
+        if distance <= self.successor.point.wrapping_sub(self.position) {
+            return Route::Owner(self.successor.clone());
+        }
+        let finger = self
+            .fingers
+            .iter()
+            .rev()
+            .find(|finger| {
+                let d = finger.point.wrapping_sub(self.position);
+                d > 0 && d < distance
+            })
+            .unwrap_or(&self.successor);
+        Route::Forward(finger.clone())
+    }
+}
+
+impl<'a, H, K> HashRing<'a, H, K>
+where
+    H: BuildHasher,
+    K: BuildHasher,
+{
+    /// Builds the finger table of a node from the ring, positioned at its first virtual node on the ring.
+    ///
+    /// # Arguments
+    ///
+    /// * `node_id` - The ID of the node.
+    /// * `k` - The number of fingers, between 1 and 64.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<FingerTable<'a>, Box<dyn Error>>` - The finger table of the node.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use std::sync::Arc;
+    /// use hashring::{HashRing, Config, Node};
+    ///
+    /// #[derive(Debug)]
+    /// struct MyNode<'a> {
+    ///     name: &'a str,
+    /// }
+    ///
+    /// impl<'a> Node<'a> for MyNode<'a> {
+    ///     fn id(&self) -> &'a str {
+    ///         self.name
+    ///     }
+    /// }
+    ///
+    /// let mut hash_ring = HashRing::new(Config::default()).unwrap();
+    /// hash_ring.add_node(Arc::new(MyNode { name: "node1" })).unwrap();
+    /// hash_ring.add_node(Arc::new(MyNode { name: "node2" })).unwrap();
+    ///
+    /// let table = hash_ring.finger_table("node1", 16).unwrap();
+    /// for finger in &table.fingers {
+    ///     println!("{:016x} -> {}", finger.start, finger.node.id());
+    /// }
+    /// ```
+    pub fn finger_table(&self, node_id: &str, k: usize) -> Result<FingerTable<'a>, Box<dyn Error>> {
+        if !self.nodes.read()?.contains_key(node_id) {
+            return Err("node not found".into());
+        }
+        // Virtual nodes may have been rehashed away from their usual position, or not be
+        // placed at all yet, so the position comes from the ring itself.
+        let position = self
+            .sorted_nodes_hash_set
+            .read()?
+            .iter()
+            .find(|(_, node)| node.id() == node_id)
+            .map(|(hash, _)| *hash)
+            .ok_or("node has no virtual nodes")?;
+        self.finger_table_at(position, k)
+    }
+
+    /// Builds the finger table of the virtual node at `position`.
+    ///
+    /// Peers owning several virtual nodes keep one table per virtual node.
+    ///
+    /// # Arguments
+    ///
+    /// * `position` - The position of the virtual node on the ring.
+    /// * `k` - The number of fingers, between 1 and 64.
+    pub fn finger_table_at(&self, position: u64, k: usize) -> Result<FingerTable<'a>, Box<dyn Error>> {
+        if k == 0 || k > 64 {
+            return Err("finger count must be between 1 and 64".into());
+        }
+        let sorted_set = self.sorted_nodes_hash_set.read()?;
+        if sorted_set.is_empty() {
+            return Err("ring is empty".into());
+        }
+
+        let finger = |start: u64| {
+            let point = Self::find_closest_idx(&sorted_set, start);
+            Finger {
+                start,
+                point,
+                node: sorted_set[&point].clone(),
+            }
+        };
+        let successor = finger(position.wrapping_add(1));
+        let fingers = (0..k).map(|i| finger(position.wrapping_add(1u64 << (64 - k + i)))).collect();
+
+        Ok(FingerTable {
+            position,
+            successor,
+            fingers,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::hash::{BuildHasher, Hasher};
+
+    use crate::tests::test_nodes;
+    use crate::{Config, HashRing, Route};
+
+    #[test]
+    fn test_finger_routing_reaches_owner() {
+        let mut hash_ring = HashRing::new(Config::default()).unwrap();
+        for node in test_nodes() {
+            hash_ring.add_node(node).unwrap();
+        }
+        assert!(hash_ring.finger_table("node1", 0).is_err());
+        assert!(hash_ring.finger_table("missing", 8).is_err());
+
+        let start = hash_ring.finger_table("node1", 64).unwrap();
+        assert_eq!(start.fingers.len(), 64);
+        assert_eq!(hash_ring.sorted_nodes_hash_set.read().unwrap()[&start.position].id(), "node1");
+
+        for i in 0..100 {
+            let key = format!("key-{}", i);
+            let mut hasher = hash_ring.key_hasher.build_hasher();
+            hasher.write(key.as_bytes());
+            let hash = hasher.finish();
+
+            let mut table = start.clone();
+            let mut hops = 0;
+            let owner = loop {
+                match table.next_hop(hash) {
+                    Route::Owner(finger) => break finger.node,
+                    Route::Forward(finger) => table = hash_ring.finger_table_at(finger.point, 64).unwrap(),
+                }
+                hops += 1;
+                assert!(hops <= 64, "lookup for {} did not converge", key);
+            };
+            assert_eq!(owner.id(), hash_ring.get_key(key.as_bytes()).unwrap().id());
+        }
+    }
+}
@@ -0,0 +1,277 @@
+//! Protection against flapping nodes.
+//!
+//! A node whose health check oscillates gets added and removed over and over, and
+//! every change moves its partitions back and forth. With a `FlapPolicy` set, a node
+//! joining or leaving more than `max_changes` times within `window` is quarantined: it
+//! is kept out of the ring, and `add_node` refuses it until the quarantine ends or an
+//! operator releases it. Event sinks are told when a node is quarantined.
+
+use std::collections::{HashMap, VecDeque};
+use std::error::Error;
+use std::hash::BuildHasher;
+use std::time::{Duration, Instant};
+
+use crate::HashRing;
+
+/// When a node counts as flapping and for how long it is kept out.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FlapPolicy {
+    /// The period over which membership changes are counted.
+    pub window: Duration,
+    /// The number of joins and removals of a node allowed within `window`.
+    pub max_changes: usize,
+    /// How long a flapping node is refused.
+    pub quarantine: Duration,
+}
+
+impl Default for FlapPolicy {
+    fn default() -> Self {
+        FlapPolicy {
+            window: Duration::from_secs(60),
+            max_changes: 4,
+            quarantine: Duration::from_secs(300),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Flaps {
+    policy: Option<FlapPolicy>,
+    /// The recent membership changes of each node, oldest first.
+    changes: HashMap<String, VecDeque<Instant>>,
+    /// The end of the quarantine of each quarantined node.
+    quarantined: HashMap<String, Instant>,
+}
+
+impl<'a, H, K> HashRing<'a, H, K>
+where
+    H: BuildHasher,
+    K: BuildHasher,
+{
+    /// Sets the flap policy, or disables flap detection with `None`, the default.
+    ///
+    /// Changing the policy forgets the recorded changes but keeps current quarantines.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use std::sync::Arc;
+    /// use std::time::Duration;
+    /// use hashring::{HashRing, Config, FlapPolicy, Node};
+    ///
+    /// #[derive(Debug)]
+    /// struct MyNode<'a> {
+    ///     name: &'a str,
+    /// }
+    ///
+    /// impl<'a> Node<'a> for MyNode<'a> {
+    ///     fn id(&self) -> &'a str {
+    ///         self.name
+    ///     }
+    /// }
+    ///
+    /// let mut hash_ring = HashRing::new(Config::default()).unwrap();
+    /// hash_ring.set_flap_policy(Some(FlapPolicy { max_changes: 2, ..FlapPolicy::default() }));
+    ///
+    /// let node = Arc::new(MyNode { name: "node1" });
+    /// hash_ring.add_node(node.clone()).unwrap();
+    /// hash_ring.remove_node("node1").unwrap();
+    /// // A third change within a minute quarantines the node.
+    /// assert!(hash_ring.add_node(node.clone()).is_err());
+    /// assert!(hash_ring.is_quarantined("node1"));
+    ///
+    /// hash_ring.release_node("node1");
+    /// hash_ring.add_node(node).unwrap();
+    /// ```
+    pub fn set_flap_policy(&self, policy: Option<FlapPolicy>) {
+        let mut flaps = self.flaps.wait_write();
+        flaps.policy = policy;
+        flaps.changes.clear();
+    }
+
+    /// Returns `true` if the node is quarantined for flapping.
+    pub fn is_quarantined(&self, id: &str) -> bool {
+        let now = self.now();
+        let flaps = self.flaps.wait_read();
+        flaps.quarantined.get(id).is_some_and(|until| now < *until)
+    }
+
+    /// Returns the quarantined nodes with the time left in their quarantine.
+    pub fn quarantined_nodes(&self) -> Vec<(String, Duration)> {
+        let now = self.now();
+        let flaps = self.flaps.wait_read();
+        let mut quarantined: Vec<(String, Duration)> = flaps
+            .quarantined
+            .iter()
+            .filter(|(_, until)| now < **until)
+            .map(|(id, until)| (id.clone(), until.duration_since(now)))
+            .collect();
+        quarantined.sort();
+        quarantined
+    }
+
+    /// Ends the quarantine of a node and forgets its changes.
+    ///
+    /// # Returns
+    ///
+    /// * `bool` - `true` if the node was quarantined.
+    pub fn release_node(&self, id: &str) -> bool {
+        let now = self.now();
+        let mut flaps = self.flaps.wait_write();
+        flaps.changes.remove(id);
+        flaps.quarantined.remove(id).is_some_and(|until| now < until)
+    }
+
+    /// Records a node joining or leaving, quarantining it if it is flapping.
+    ///
+    /// Returns an error if the node is joining while quarantined, in which case the join
+    /// must not proceed. Leaving is always allowed.
+    pub(crate) fn record_membership_change(&self, id: &str, joining: bool) -> Result<(), Box<dyn Error>> {
+        let now = self.now();
+        let mut flaps = self.flaps.write()?;
+        let policy = match &flaps.policy {
+            Some(policy) => policy.clone(),
+            None => return Ok(()),
+        };
+        if flaps.is_quarantined(id, now) {
+            if joining {
+                return Err(format!("node {} is quarantined for flapping", id).into());
+            }
+            return Ok(());
+        }
+        let quarantined = flaps.record(id, now, &policy);
+        drop(flaps);
+        if quarantined {
+            self.notify_quarantine(id, policy.quarantine)?;
+            if joining {
+                return Err(format!("node {} is quarantined for flapping", id).into());
+            }
+        }
+        Ok(())
+    }
+
+    /// Records several nodes leaving and joining at once, for transactions.
+    ///
+    /// Every join is checked first: if one is refused, because the node is quarantined or
+    /// would be by joining, nothing is recorded and an error is returned.
+    pub(crate) fn record_membership_changes(&self, removed: &[&str], joined: &[&str]) -> Result<(), Box<dyn Error>> {
+        let now = self.now();
+        let mut flaps = self.flaps.write()?;
+        let policy = match &flaps.policy {
+            Some(policy) => policy.clone(),
+            None => return Ok(()),
+        };
+        for id in joined {
+            if flaps.is_quarantined(id, now) || flaps.recent_changes(id, now, &policy) >= policy.max_changes {
+                return Err(format!("node {} is quarantined for flapping", id).into());
+            }
+        }
+
+        let mut quarantined = Vec::new();
+        for id in removed.iter().chain(joined) {
+            if !flaps.is_quarantined(id, now) && flaps.record(id, now, &policy) {
+                quarantined.push(*id);
+            }
+        }
+        drop(flaps);
+        for id in quarantined {
+            self.notify_quarantine(id, policy.quarantine)?;
+        }
+        Ok(())
+    }
+
+    fn notify_quarantine(&self, id: &str, duration: Duration) -> Result<(), Box<dyn Error>> {
+        if !self.event_sinks.read()?.is_empty() {
+            self.pending_events.push_quarantine(id, duration);
+        }
+        Ok(())
+    }
+}
+
+impl Flaps {
+    /// Returns `true` if the node is quarantined at `now`, forgetting ended quarantines.
+    fn is_quarantined(&mut self, id: &str, now: Instant) -> bool {
+        match self.quarantined.get(id) {
+            Some(until) if now < *until => true,
+            Some(_) => {
+                self.quarantined.remove(id);
+                false
+            }
+            None => false,
+        }
+    }
+
+    /// Returns the number of changes of the node within the window ending at `now`.
+    fn recent_changes(&self, id: &str, now: Instant, policy: &FlapPolicy) -> usize {
+        self.changes
+            .get(id)
+            .map_or(0, |changes| changes.iter().filter(|at| now.saturating_duration_since(**at) <= policy.window).count())
+    }
+
+    /// Records a change of the node at `now`, returning `true` if it is now quarantined.
+    fn record(&mut self, id: &str, now: Instant, policy: &FlapPolicy) -> bool {
+        let changes = self.changes.entry(id.to_string()).or_default();
+        while changes.front().is_some_and(|at| now.saturating_duration_since(*at) > policy.window) {
+            changes.pop_front();
+        }
+        changes.push_back(now);
+        if changes.len() <= policy.max_changes {
+            return false;
+        }
+        self.changes.remove(id);
+        self.quarantined.insert(id.to_string(), now + policy.quarantine);
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    use crate::tests::test_nodes;
+    use crate::{Config, EventSink, FlapPolicy, HashRing, RingEvent};
+
+    #[derive(Default)]
+    struct Quarantines(Mutex<Vec<String>>);
+
+    impl EventSink for Quarantines {
+        fn on_event(&self, _event: &RingEvent) {}
+
+        fn on_quarantine(&self, node_id: &str, _duration: Duration) {
+            self.0.lock().unwrap().push(node_id.to_string());
+        }
+    }
+
+    #[test]
+    fn test_flapping_node_is_quarantined() {
+        let mut hash_ring = HashRing::new(Config::default()).unwrap();
+        let quarantines = Arc::new(Quarantines::default());
+        hash_ring.add_event_sink(quarantines.clone());
+        hash_ring.set_flap_policy(Some(FlapPolicy {
+            window: Duration::from_secs(60),
+            max_changes: 3,
+            quarantine: Duration::from_millis(50),
+        }));
+        let nodes = test_nodes();
+        hash_ring.add_node(nodes[1].clone()).unwrap();
+        hash_ring.add_node(nodes[0].clone()).unwrap();
+        hash_ring.remove_node("node1").unwrap();
+        hash_ring.add_node(nodes[0].clone()).unwrap();
+        // The fourth change is a removal, which goes through.
+        hash_ring.remove_node("node1").unwrap();
+        assert!(hash_ring.is_quarantined("node1"));
+        assert!(!hash_ring.is_quarantined("node2"));
+        assert_eq!(*quarantines.0.lock().unwrap(), ["node1"]);
+        let version = hash_ring.version();
+        assert!(hash_ring.add_node(nodes[0].clone()).is_err());
+        assert_eq!(hash_ring.version(), version);
+        assert_eq!(hash_ring.virtual_nodes_per_node().get("node1"), None);
+        assert_eq!(hash_ring.quarantined_nodes().len(), 1);
+
+        std::thread::sleep(Duration::from_millis(60));
+        assert!(hash_ring.quarantined_nodes().is_empty());
+        hash_ring.add_node(nodes[0].clone()).unwrap();
+        assert!(!hash_ring.release_node("node1"));
+    }
+}
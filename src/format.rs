@@ -0,0 +1,204 @@
+//! Versioned placement rules and ring fingerprints.
+//!
+//! How virtual nodes are encoded, hashed and mapped to partitions decides where every
+//! key lives, so changing any of it silently moves data. Each such change gets a new
+//! `RingFormatVersion`. A ring keeps the behavior of the version in its `Config`, which
+//! is saved in snapshots and deltas, so upgrading the crate never moves keys until the
+//! version is raised on purpose.
+//!
+//! A fingerprint summarizes everything that decides routing, including the version, so
+//! instances can check they agree on placement by comparing a single number.
+
+use std::collections::BTreeMap;
+use std::hash::{BuildHasher, Hasher};
+
+use crate::load;
+use crate::{HashRing, TrafficSplit, VnodeEncoding, XxHash64Hasher};
+
+/// A version of the rules used to place virtual nodes and partitions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum RingFormatVersion {
+    /// The original rules: virtual nodes are hashed from `"{id}:{replica}"`.
+    #[default]
+    V1,
+    /// Virtual nodes are hashed from a length-prefixed encoding.
+    V2,
+}
+
+impl RingFormatVersion {
+    /// The most recent version, recommended for new rings.
+    pub const LATEST: RingFormatVersion = RingFormatVersion::V2;
+
+    /// Returns the version number.
+    pub fn number(&self) -> u16 {
+        match self {
+            RingFormatVersion::V1 => 1,
+            RingFormatVersion::V2 => 2,
+        }
+    }
+
+    /// Returns the version with the given number, if this build knows it.
+    pub fn from_number(number: u16) -> Option<RingFormatVersion> {
+        match number {
+            1 => Some(RingFormatVersion::V1),
+            2 => Some(RingFormatVersion::V2),
+            _ => None,
+        }
+    }
+
+    /// Returns how virtual nodes are encoded before hashing in this version.
+    pub fn vnode_encoding(&self) -> VnodeEncoding {
+        match self {
+            RingFormatVersion::V1 => VnodeEncoding::Legacy,
+            RingFormatVersion::V2 => VnodeEncoding::LengthPrefixed,
+        }
+    }
+}
+
+impl<'a, H, K> HashRing<'a, H, K>
+where
+    H: BuildHasher,
+    K: BuildHasher,
+{
+    /// Returns a fingerprint of the ring's placement.
+    ///
+    /// It covers the format version, the key namespace, the partition layout, the
+    /// position and owner of every virtual node, the partition assignment, the partitions
+    /// moved by a rebalance and traffic splits. Two rings with the same fingerprint and
+    /// the same key hasher route every key and partition identically.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use std::sync::Arc;
+    /// use hashring::{HashRing, Config, Node, RingFormatVersion};
+    ///
+    /// #[derive(Debug)]
+    /// struct MyNode<'a> {
+    ///     name: &'a str,
+    /// }
+    ///
+    /// impl<'a> Node<'a> for MyNode<'a> {
+    ///     fn id(&self) -> &'a str {
+    ///         self.name
+    ///     }
+    /// }
+    ///
+    /// let mut v1 = HashRing::new(Config::default()).unwrap();
+    /// let mut config = Config::default();
+    /// config.format_version = RingFormatVersion::V2;
+    /// let mut v2 = HashRing::new(config).unwrap();
+    /// v1.add_node(Arc::new(MyNode { name: "node1" })).unwrap();
+    /// v2.add_node(Arc::new(MyNode { name: "node1" })).unwrap();
+    ///
+    /// assert_ne!(v1.fingerprint(), v2.fingerprint());
+    /// ```
+    pub fn fingerprint(&self) -> u64 {
+        let config = self.config();
+        let sorted_set = self.sorted_nodes_hash_set.wait_read();
+        let partitions = self.partitions.wait_read();
+        let routes = self.routes.wait_read();
+
+        let mut hasher = XxHash64Hasher::default().build_hasher();
+        let write_id = |hasher: &mut <XxHash64Hasher as BuildHasher>::Hasher, id: &str| {
+            hasher.write(&(id.len() as u32).to_be_bytes());
+            hasher.write(id.as_bytes());
+        };
+        hasher.write(&config.format_version.number().to_be_bytes());
+        hasher.write(&[config.key_namespace.is_some() as u8]);
+        config.write_namespace(&mut hasher);
+        hasher.write(&(config.partition_count as u64).to_be_bytes());
+        hasher.write(&(routes.splits.len() as u64).to_be_bytes());
+        for part_id in routes.splits.iter() {
+            hasher.write(&(*part_id as u64).to_be_bytes());
+        }
+        for (point, node) in sorted_set.iter() {
+            hasher.write(&point.to_be_bytes());
+            write_id(&mut hasher, node.id());
+        }
+
+        let assignment: BTreeMap<usize, &str> = partitions.iter().map(|(part_id, node)| (*part_id, node.id())).collect();
+        hasher.write(&(assignment.len() as u64).to_be_bytes());
+        for (part_id, id) in assignment {
+            hasher.write(&(part_id as u64).to_be_bytes());
+            write_id(&mut hasher, id);
+        }
+        let moved: BTreeMap<usize, u64> = routes
+            .moved
+            .keys()
+            .filter_map(|part_id| load::moved_point(&routes.moved, &sorted_set, *part_id).map(|point| (*part_id, point)))
+            .collect();
+        hasher.write(&(moved.len() as u64).to_be_bytes());
+        for (part_id, point) in moved {
+            hasher.write(&(part_id as u64).to_be_bytes());
+            hasher.write(&point.to_be_bytes());
+        }
+        let diverted: BTreeMap<&str, &TrafficSplit> = routes.traffic_splits.iter().map(|(id, split)| (id.as_str(), split)).collect();
+        hasher.write(&(diverted.len() as u64).to_be_bytes());
+        for (id, split) in diverted {
+            write_id(&mut hasher, id);
+            write_id(&mut hasher, &split.canary);
+            hasher.write(&split.fraction.to_bits().to_be_bytes());
+        }
+        hasher.finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::RingFormatVersion;
+    use crate::tests::{access, key_on, test_nodes};
+    use crate::{Config, HashRing, Node, RingSnapshot};
+
+    #[test]
+    fn test_format_version_pins_placement() {
+        let mut v1 = HashRing::new(Config::default()).unwrap();
+        let mut v2 = HashRing::new(Config {
+            format_version: RingFormatVersion::V2,
+            ..Config::default()
+        })
+        .unwrap();
+        for node in test_nodes() {
+            v1.add_node(node.clone()).unwrap();
+            v2.add_node(node).unwrap();
+        }
+        assert_ne!(v1.fingerprint(), v2.fingerprint());
+        assert_eq!(RingFormatVersion::from_number(RingFormatVersion::LATEST.number()), Some(RingFormatVersion::LATEST));
+        assert_eq!(RingFormatVersion::from_number(0), None);
+
+        // Restoring a snapshot keeps the version, and so the placement.
+        let snapshot = RingSnapshot::from_bytes(&v2.snapshot().to_bytes().unwrap()).unwrap();
+        assert_eq!(snapshot.config.format_version, RingFormatVersion::V2);
+        let nodes = test_nodes();
+        let resolver = |id: &str| nodes.iter().find(|n| n.id() == id).map(|n| n.clone() as Arc<dyn Node<'static>>);
+        let restored: HashRing = HashRing::from_snapshot(&snapshot, Default::default(), Default::default(), resolver).unwrap();
+        assert_eq!(restored.fingerprint(), v2.fingerprint());
+        v1.restore(&snapshot, resolver).unwrap();
+        assert_eq!(v1.fingerprint(), v2.fingerprint());
+    }
+
+    #[test]
+    fn test_fingerprint_covers_routing() {
+        let mut hash_ring = HashRing::new(Config::default()).unwrap();
+        for node in test_nodes() {
+            hash_ring.add_node(node).unwrap();
+        }
+        let fingerprint = hash_ring.fingerprint();
+
+        // A rebalance keeps the virtual nodes but routes the keys of a partition elsewhere.
+        let (hot, part_id) = key_on(&hash_ring, "node1", &[]);
+        let (warm, _) = key_on(&hash_ring, "node1", &[part_id]);
+        access(&hash_ring, &hot, 100);
+        access(&hash_ring, &warm, 100);
+        assert_eq!(hash_ring.rebalance(1).len(), 1);
+        let rebalanced = hash_ring.fingerprint();
+        assert_ne!(rebalanced, fingerprint);
+
+        hash_ring.set_traffic_split("node2", "node3", 0.5).unwrap();
+        assert_ne!(hash_ring.fingerprint(), rebalanced);
+    }
+}
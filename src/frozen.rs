@@ -0,0 +1,228 @@
+//! Immutable, lock-free views of a ring.
+//!
+//! A `FrozenRing` copies the virtual nodes into a sorted vector and clones the
+//! partition table. Lookups are a binary search with no locking, and the
+//! view never changes underneath a request handler holding it.
+
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::hash::BuildHasher;
+use std::sync::Arc;
+
+use crate::canary::split_target;
+use crate::split::route_partition;
+use crate::{load, util, Config, HashRing, Node, ReplicaSet, TrafficSplit, XxHash64Hasher};
+
+/// An immutable snapshot of a `HashRing`, optimized for lookups.
+#[derive(Debug, Clone)]
+pub struct FrozenRing<'a, K = XxHash64Hasher> {
+    config: Config,
+    key_hasher: K,
+    points: Vec<(u64, Arc<dyn Node<'a> + 'a>)>,
+    partitions: HashMap<usize, Arc<dyn Node<'a> + 'a>>,
+    splits: BTreeSet<usize>,
+    moved: HashMap<usize, &'a str>,
+    traffic_splits: HashMap<String, TrafficSplit>,
+    nodes: Vec<Arc<dyn Node<'a> + 'a>>,
+    version: u64,
+}
+
+impl<'a, H, K> HashRing<'a, H, K>
+where
+    H: BuildHasher,
+    K: BuildHasher + Clone,
+{
+    /// Produces an immutable view of the ring for lookups.
+    ///
+    /// # Returns
+    ///
+    /// * `FrozenRing<'a, K>` - A view that is unaffected by later changes to the ring.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use std::sync::Arc;
+    /// use hashring::{HashRing, Config, Node};
+    ///
+    /// #[derive(Debug)]
+    /// struct MyNode<'a> {
+    ///     name: &'a str,
+    /// }
+    ///
+    /// impl<'a> Node<'a> for MyNode<'a> {
+    ///     fn id(&self) -> &'a str {
+    ///         self.name
+    ///     }
+    /// }
+    ///
+    /// let mut hash_ring = HashRing::new(Config::default()).unwrap();
+    /// hash_ring.add_node(Arc::new(MyNode { name: "node1" })).unwrap();
+    ///
+    /// let frozen = hash_ring.freeze();
+    /// hash_ring.remove_node("node1").unwrap();
+    ///
+    /// assert_eq!(frozen.get_key(b"some_key").unwrap().id(), "node1");
+    /// ```
+    pub fn freeze(&self) -> FrozenRing<'a, K> {
+        let nodes = self.nodes.wait_read();
+        let sorted_set = self.sorted_nodes_hash_set.wait_read();
+        let partitions = self.partitions.wait_read();
+        let routes = self.routes.wait_read();
+
+        let mut members: Vec<Arc<dyn Node<'a> + 'a>> = nodes.values().cloned().collect();
+        members.sort_by(|a, b| a.id().cmp(b.id()));
+
+        FrozenRing {
+            config: self.config().as_ref().clone(),
+            key_hasher: self.key_hasher.clone(),
+            points: sorted_set.iter().map(|(hash, node)| (*hash, node.clone())).collect(),
+            partitions: partitions.clone(),
+            splits: routes.splits.clone(),
+            moved: routes.moved.iter().filter(|(part_id, _)| load::moved_point(&routes.moved, &sorted_set, **part_id).is_some()).map(|(part_id, (_, id))| (*part_id, *id)).collect(),
+            traffic_splits: routes.traffic_splits.clone(),
+            nodes: members,
+            version: self.version(),
+        }
+    }
+}
+
+impl<'a, K> FrozenRing<'a, K>
+where
+    K: BuildHasher,
+{
+    fn hash_key(&self, key: &[u8]) -> u64 {
+        util::hash_key(&self.key_hasher, &self.config, key)
+    }
+
+    /// Returns the point the lookup of a key hash starts from, as `HashRing::route` does.
+    fn route(&self, hashed_key: u64) -> u64 {
+        if self.moved.is_empty() {
+            return hashed_key;
+        }
+        let part_id = route_partition(self.config.partition_count, &self.splits, hashed_key);
+        let Some(node_id) = self.moved.get(&part_id) else {
+            return hashed_key;
+        };
+        let start = self.successor_idx(hashed_key);
+        self.points[start..]
+            .iter()
+            .chain(&self.points[..start])
+            .find(|(_, node)| node.id() == *node_id)
+            .map_or(hashed_key, |(point, _)| *point)
+    }
+
+    fn successor_idx(&self, hash: u64) -> usize {
+        let idx = self.points.partition_point(|(h, _)| *h < hash);
+        if idx == self.points.len() {
+            0
+        } else {
+            idx
+        }
+    }
+
+    /// Retrieves the node responsible for the given key, diverted by the traffic splits
+    /// in place when the ring was frozen.
+    pub fn get_key(&self, key: &[u8]) -> Option<Arc<dyn Node<'a> + 'a>> {
+        if self.points.is_empty() {
+            return None;
+        }
+        let hashed_key = self.hash_key(key);
+        let owner = &self.points[self.successor_idx(self.route(hashed_key))].1;
+        // Diverted keys go to the canary, as long as it was a member when the ring was frozen.
+        let canary = split_target(&self.traffic_splits, owner.id(), hashed_key)
+            .and_then(|id| self.nodes.binary_search_by(|node| node.id().cmp(id)).ok());
+        Some(canary.map_or(owner, |idx| &self.nodes[idx]).clone())
+    }
+
+    /// Retrieves up to `replication_factor` distinct nodes responsible for the given key, primary first.
+    pub fn get_preference_list(&self, key: &[u8]) -> ReplicaSet<'a> {
+        self.get_preference_list_n(key, self.config.replication_factor).into()
+    }
+
+    /// Retrieves up to `n` distinct nodes responsible for the given key.
+    pub fn get_preference_list_n(&self, key: &[u8], n: usize) -> Vec<Arc<dyn Node<'a> + 'a>> {
+        let mut preference_list: Vec<Arc<dyn Node<'a> + 'a>> = Vec::new();
+        if self.points.is_empty() || n == 0 {
+            return preference_list;
+        }
+
+        let start = self.successor_idx(self.route(self.hash_key(key)));
+        let mut unique_nodes = HashSet::new();
+        for (_, node) in self.points[start..].iter().chain(self.points[..start].iter()) {
+            if unique_nodes.insert(node.id()) {
+                preference_list.push(node.clone());
+                if preference_list.len() >= n {
+                    break;
+                }
+            }
+        }
+        preference_list
+    }
+
+    /// Returns the partition the given key falls into.
+    pub fn get_partition(&self, key: &[u8]) -> usize {
+        route_partition(self.config.partition_count, &self.splits, self.hash_key(key))
+    }
+
+    /// Retrieves the node a partition was assigned to when the ring was frozen.
+    pub fn get_partition_owner(&self, part_id: usize) -> Option<Arc<dyn Node<'a> + 'a>> {
+        self.partitions.get(&part_id).cloned()
+    }
+
+    /// Returns the members of the ring, sorted by ID.
+    pub fn nodes(&self) -> &[Arc<dyn Node<'a> + 'a>] {
+        &self.nodes
+    }
+
+    /// Returns the version of the ring when it was frozen.
+    pub fn version(&self) -> u64 {
+        self.version
+    }
+
+    /// Returns the configuration of the ring when it was frozen.
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::tests::test_nodes;
+    use crate::{Config, HashRing};
+
+    fn assert_send_sync<T: Send + Sync>() {}
+
+    #[test]
+    fn test_frozen_matches_ring() {
+        assert_send_sync::<crate::FrozenRing<'static>>();
+
+        let mut hash_ring = HashRing::new(Config::default()).unwrap();
+        for node in test_nodes() {
+            hash_ring.add_node(node).unwrap();
+        }
+        let frozen = hash_ring.freeze();
+
+        for i in 0..100 {
+            let key = format!("key-{}", i);
+            assert_eq!(frozen.get_key(key.as_bytes()).unwrap().id(), hash_ring.get_key(key.as_bytes()).unwrap().id());
+            let expected: Vec<&str> = hash_ring.get_preference_list(key.as_bytes()).iter().map(|r| r.node.id()).collect();
+            let actual: Vec<&str> = frozen.get_preference_list(key.as_bytes()).iter().map(|r| r.node.id()).collect();
+            assert_eq!(actual, expected);
+            assert_eq!(frozen.get_partition(key.as_bytes()), hash_ring.get_partition(key.as_bytes()));
+        }
+        for part_id in 0..hash_ring.config().partition_count {
+            assert_eq!(frozen.get_partition_owner(part_id).unwrap().id(), hash_ring.get_partition_owner(part_id).unwrap().id());
+        }
+    }
+
+    #[test]
+    fn test_frozen_is_isolated_from_changes() {
+        let mut hash_ring = HashRing::new(Config::default()).unwrap();
+        let frozen_empty = hash_ring.freeze();
+        for node in test_nodes() {
+            hash_ring.add_node(node).unwrap();
+        }
+        assert!(frozen_empty.get_key(b"some_key").is_none());
+        assert!(frozen_empty.get_preference_list(b"some_key").is_empty());
+        assert_eq!(hash_ring.freeze().nodes().len(), 3);
+    }
+}
@@ -0,0 +1,154 @@
+//! Removing nodes with a grace period.
+//!
+//! When a cache node leaves, its keys are cold on their new owners. Removing it with
+//! `remove_node_with_grace` leaves a tombstone: the node no longer receives keys, but
+//! `previous_owner` and `get_key_with_previous` still name it for the keys it owned
+//! until the grace period ends, so readers can fall back to it while the data moves.
+
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::hash::BuildHasher;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::{HashRing, Node};
+
+/// A removed node and the ring it was removed from.
+#[derive(Debug, Clone)]
+pub(crate) struct Tombstone<'a> {
+    node: Arc<dyn Node<'a> + 'a>,
+    /// The virtual nodes before the removal.
+    sorted_set: Arc<BTreeMap<u64, Arc<dyn Node<'a> + 'a>>>,
+    expires: Instant,
+}
+
+impl<'a, H, K> HashRing<'a, H, K>
+where
+    H: BuildHasher,
+    K: BuildHasher,
+{
+    /// Removes a node, keeping it as the previous owner of its keys for `ttl`.
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - The ID of the node to remove.
+    /// * `ttl` - How long the node stays queryable through `previous_owner`.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use std::sync::Arc;
+    /// use std::time::Duration;
+    /// use hashring::{HashRing, Config, Node};
+    ///
+    /// #[derive(Debug)]
+    /// struct MyNode<'a> {
+    ///     name: &'a str,
+    /// }
+    ///
+    /// impl<'a> Node<'a> for MyNode<'a> {
+    ///     fn id(&self) -> &'a str {
+    ///         self.name
+    ///     }
+    /// }
+    ///
+    /// let mut hash_ring = HashRing::new(Config::default()).unwrap();
+    /// hash_ring.add_node(Arc::new(MyNode { name: "node1" })).unwrap();
+    /// hash_ring.add_node(Arc::new(MyNode { name: "node2" })).unwrap();
+    ///
+    /// let owner = hash_ring.get_key(b"some_key").unwrap();
+    /// hash_ring.remove_node_with_grace(owner.id(), Duration::from_secs(300)).unwrap();
+    ///
+    /// assert_ne!(hash_ring.get_key(b"some_key").unwrap().id(), owner.id());
+    /// assert_eq!(hash_ring.previous_owner(b"some_key").unwrap().id(), owner.id());
+    /// ```
+    pub fn remove_node_with_grace(&mut self, id: &str, ttl: Duration) -> Result<(), Box<dyn Error>> {
+        let node = self.nodes.read()?.get(id).cloned().ok_or("node not found")?;
+        let sorted_set = Arc::new(self.sorted_nodes_hash_set.read()?.clone());
+        self.remove_node(id)?;
+
+        let now = self.now();
+        let mut tombstones = self.tombstones.write()?;
+        tombstones.retain(|tombstone| tombstone.expires > now && tombstone.node.id() != id);
+        tombstones.push(Tombstone {
+            node,
+            sorted_set,
+            expires: now + ttl,
+        });
+        Ok(())
+    }
+
+    /// Returns the node removed with a grace period that owned the key, if its grace
+    /// period hasn't ended and it hasn't rejoined.
+    ///
+    /// The most recently removed node wins when several owned the key.
+    pub fn previous_owner(&self, key: &[u8]) -> Option<Arc<dyn Node<'a> + 'a>> {
+        self.tombstone_owner(self.hash_key(key).ok()?)
+    }
+
+    pub(crate) fn tombstone_owner(&self, hashed_key: u64) -> Option<Arc<dyn Node<'a> + 'a>> {
+        let now = self.now();
+        let nodes = self.nodes.read().ok()?;
+        let tombstones = self.tombstones.read().ok()?;
+        tombstones
+            .iter()
+            .rev()
+            .filter(|tombstone| tombstone.expires > now && !nodes.contains_key(tombstone.node.id()))
+            .find(|tombstone| {
+                let sorted_set = &tombstone.sorted_set;
+                let owner = sorted_set.range(hashed_key..).next().or_else(|| sorted_set.iter().next());
+                owner.is_some_and(|(_, node)| node.id() == tombstone.node.id())
+            })
+            .map(|tombstone| tombstone.node.clone())
+    }
+
+    /// Returns the nodes in their grace period with the time they have left, by ID.
+    pub fn tombstones(&self) -> Vec<(String, Duration)> {
+        let now = self.now();
+        let tombstones = self.tombstones.wait_read();
+        let mut remaining: Vec<(String, Duration)> = tombstones
+            .iter()
+            .filter(|tombstone| tombstone.expires > now)
+            .map(|tombstone| (tombstone.node.id().to_string(), tombstone.expires.duration_since(now)))
+            .collect();
+        remaining.sort();
+        remaining
+    }
+
+    /// Ends the grace period of every removed node.
+    pub fn clear_tombstones(&self) {
+        self.tombstones.wait_write().clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::tests::test_nodes;
+    use crate::{Config, HashRing};
+
+    #[test]
+    fn test_tombstoned_node_stays_previous_owner() {
+        let mut hash_ring = HashRing::new(Config::default()).unwrap();
+        for node in test_nodes() {
+            hash_ring.add_node(node).unwrap();
+        }
+        let keys: Vec<String> = (0..200).map(|i| format!("key-{}", i)).collect();
+        let owned: Vec<&String> = keys.iter().filter(|key| hash_ring.get_key(key.as_bytes()).unwrap().id() == "node1").collect();
+        assert!(hash_ring.remove_node_with_grace("node4", Duration::from_secs(1)).is_err());
+
+        hash_ring.remove_node_with_grace("node1", Duration::from_millis(50)).unwrap();
+        assert_eq!(hash_ring.tombstones().len(), 1);
+        for key in &keys {
+            let previous = hash_ring.previous_owner(key.as_bytes());
+            assert_eq!(previous.is_some(), owned.contains(&key));
+            assert_eq!(hash_ring.get_key_with_previous(key.as_bytes()).previous.map(|node| node.id()), previous.map(|node| node.id()));
+            assert_ne!(hash_ring.get_key(key.as_bytes()).unwrap().id(), "node1");
+        }
+
+        std::thread::sleep(Duration::from_millis(60));
+        assert!(hash_ring.tombstones().is_empty());
+        assert!(keys.iter().all(|key| hash_ring.previous_owner(key.as_bytes()).is_none()));
+    }
+}
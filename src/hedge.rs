@@ -0,0 +1,150 @@
+//! Targets for hedged reads.
+//!
+//! A hedged read sends the same request to a second node when the first is slow, and
+//! takes whichever answers first. The hedge only helps if the nodes don't slow down
+//! together, so `hedge_targets` picks the backups from other zones than the primary
+//! when the ring spans several, falling back to the next nodes on the ring.
+
+use std::collections::HashSet;
+use std::hash::BuildHasher;
+use std::sync::Arc;
+
+use crate::{HashRing, Node};
+
+impl<'a, H, K> HashRing<'a, H, K>
+where
+    H: BuildHasher,
+    K: BuildHasher,
+{
+    /// Returns the owner of a key followed by up to `n - 1` other nodes to hedge reads
+    /// with, each from a zone not used yet when possible.
+    ///
+    /// The nodes are taken from a single walk of the ring from the key, under one lock
+    /// acquisition, so they are consistent with each other.
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - The key to read.
+    /// * `n` - The total number of nodes to return, primary included.
+    ///
+    /// # Returns
+    ///
+    /// * `Vec<Arc<dyn Node<'a> + 'a>>` - The primary first, then the backups from new zones and then the other nodes, each in ring order.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use std::sync::Arc;
+    /// use hashring::{HashRing, Config, Node};
+    ///
+    /// #[derive(Debug)]
+    /// struct MyNode<'a> {
+    ///     name: &'a str,
+    ///     zone: &'a str,
+    /// }
+    ///
+    /// impl<'a> Node<'a> for MyNode<'a> {
+    ///     fn id(&self) -> &'a str {
+    ///         self.name
+    ///     }
+    ///
+    ///     fn zone(&self) -> Option<&'a str> {
+    ///         Some(self.zone)
+    ///     }
+    /// }
+    ///
+    /// let mut hash_ring = HashRing::new(Config::default()).unwrap();
+    /// hash_ring.add_node(Arc::new(MyNode { name: "node1", zone: "us-east-1a" })).unwrap();
+    /// hash_ring.add_node(Arc::new(MyNode { name: "node2", zone: "us-east-1a" })).unwrap();
+    /// hash_ring.add_node(Arc::new(MyNode { name: "node3", zone: "us-east-1b" })).unwrap();
+    ///
+    /// let targets = hash_ring.hedge_targets(b"some_key", 2);
+    /// assert_ne!(targets[0].zone(), targets[1].zone());
+    /// ```
+    pub fn hedge_targets(&self, key: &[u8], n: usize) -> Vec<Arc<dyn Node<'a> + 'a>> {
+        let Ok(sorted_set) = self.sorted_nodes_hash_set.read() else {
+            return Vec::new();
+        };
+        let Ok(point) = self.hash_key(key).and_then(|hashed_key| self.route(&sorted_set, hashed_key)) else {
+            return Vec::new();
+        };
+        let mut seen = HashSet::new();
+        let candidates: Vec<&Arc<dyn Node<'a> + 'a>> = sorted_set
+            .range(point..)
+            .chain(sorted_set.range(..point))
+            .map(|(_, node)| node)
+            .filter(|node| seen.insert(node.id()))
+            .collect();
+
+        let mut targets: Vec<Arc<dyn Node<'a> + 'a>> = Vec::new();
+        let mut zones = HashSet::new();
+        let mut picked = HashSet::new();
+        // The primary, then one node per new zone, then whatever comes next on the ring.
+        for node in &candidates {
+            if targets.len() >= n {
+                return targets;
+            }
+            let new_zone = !node.zone().is_some_and(|zone| zones.contains(&zone));
+            if targets.is_empty() || new_zone {
+                zones.extend(node.zone());
+                picked.insert(node.id());
+                targets.push((*node).clone());
+            }
+        }
+        for node in candidates {
+            if targets.len() >= n {
+                break;
+            }
+            if picked.insert(node.id()) {
+                targets.push(node.clone());
+            }
+        }
+        targets
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use crate::{Config, HashRing, Node};
+
+    #[derive(Debug)]
+    struct ZonedNode {
+        name: &'static str,
+        zone: &'static str,
+    }
+
+    impl Node<'static> for ZonedNode {
+        fn id(&self) -> &'static str {
+            self.name
+        }
+
+        fn zone(&self) -> Option<&'static str> {
+            Some(self.zone)
+        }
+    }
+
+    #[test]
+    fn test_hedge_targets_spread_across_zones() {
+        let mut hash_ring = HashRing::new(Config::default()).unwrap();
+        assert!(hash_ring.hedge_targets(b"some_key", 2).is_empty());
+        for (name, zone) in [("a1", "a"), ("a2", "a"), ("a3", "a"), ("b1", "b"), ("c1", "c")] {
+            hash_ring.add_node(Arc::new(ZonedNode { name, zone })).unwrap();
+        }
+        for i in 0..50 {
+            let key = format!("key{}", i);
+            let targets = hash_ring.hedge_targets(key.as_bytes(), 3);
+            assert_eq!(targets[0].id(), hash_ring.get_key(key.as_bytes()).unwrap().id());
+            let mut zones: Vec<&str> = targets.iter().filter_map(|node| node.zone()).collect();
+            zones.sort_unstable();
+            assert_eq!(zones, ["a", "b", "c"]);
+
+            // Once every zone is used, the next nodes on the ring fill in.
+            let targets = hash_ring.hedge_targets(key.as_bytes(), 5);
+            let mut ids: Vec<&str> = targets.iter().map(|node| node.id()).collect();
+            ids.sort_unstable();
+            assert_eq!(ids, ["a1", "a2", "a3", "b1", "c1"]);
+        }
+    }
+}
@@ -0,0 +1,271 @@
+//! Two-tier placement: keys pick a zone first, then a node inside it.
+//!
+//! In a flat ring a zone's share of the keys depends on how many nodes it runs, and
+//! adding a node to one zone moves keys from every zone. A `HierarchicalRing` keeps a
+//! ring of zones, such as racks or availability zones, and a ring of nodes per zone:
+//! zones get equal shares, and membership changes inside a zone only move keys within
+//! it. Preference lists take one node per zone before taking a second node from any.
+
+use std::collections::{BTreeMap, HashSet};
+use std::error::Error;
+use std::hash::BuildHasher;
+use std::sync::Arc;
+
+use crate::{Config, HashRing, Node, ReplicaSet, XxHash64Hasher};
+
+/// A zone, placed on the zone ring like a node.
+#[derive(Debug)]
+struct Zone<'a> {
+    name: &'a str,
+}
+
+impl<'a> Node<'a> for Zone<'a> {
+    fn id(&self) -> &'a str {
+        self.name
+    }
+}
+
+/// A ring of zones, each holding a ring of nodes.
+#[derive(Debug, Clone)]
+pub struct HierarchicalRing<'a, H = XxHash64Hasher, K = H> {
+    config: Config,
+    node_hasher: H,
+    key_hasher: K,
+    zones: HashRing<'a, H, K>,
+    zone_rings: BTreeMap<&'a str, HashRing<'a, H, K>>,
+}
+
+impl<'a> HierarchicalRing<'a, XxHash64Hasher> {
+    /// Creates a hierarchical ring whose zone and node rings all use `config`.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use std::sync::Arc;
+    /// use hashring::{Config, HierarchicalRing, Node};
+    ///
+    /// #[derive(Debug)]
+    /// struct MyNode<'a> {
+    ///     name: &'a str,
+    ///     rack: &'a str,
+    /// }
+    ///
+    /// impl<'a> Node<'a> for MyNode<'a> {
+    ///     fn id(&self) -> &'a str {
+    ///         self.name
+    ///     }
+    ///
+    ///     fn zone(&self) -> Option<&'a str> {
+    ///         Some(self.rack)
+    ///     }
+    /// }
+    ///
+    /// let mut ring = HierarchicalRing::new(Config::default()).unwrap();
+    /// ring.add_node(Arc::new(MyNode { name: "node1", rack: "rack-a" })).unwrap();
+    /// ring.add_node(Arc::new(MyNode { name: "node2", rack: "rack-a" })).unwrap();
+    /// ring.add_node(Arc::new(MyNode { name: "node3", rack: "rack-b" })).unwrap();
+    ///
+    /// let zone = ring.get_zone(b"some_key").unwrap();
+    /// assert_eq!(ring.get_key(b"some_key").unwrap().zone(), Some(zone));
+    ///
+    /// // The two replicas are on different racks.
+    /// let replicas = ring.get_preference_list_n(b"some_key", 2);
+    /// assert_ne!(replicas[0].zone(), replicas[1].zone());
+    /// ```
+    pub fn new(config: Config) -> Result<HierarchicalRing<'a, XxHash64Hasher>, Box<dyn Error>> {
+        HierarchicalRing::with_hashers(config, XxHash64Hasher::default(), XxHash64Hasher::default())
+    }
+}
+
+impl<'a, H, K> HierarchicalRing<'a, H, K>
+where
+    H: BuildHasher + Clone,
+    K: BuildHasher + Clone,
+{
+    /// Creates a hierarchical ring with separate hashers for placement and keys.
+    pub fn with_hashers(config: Config, node_hasher: H, key_hasher: K) -> Result<HierarchicalRing<'a, H, K>, Box<dyn Error>> {
+        let zones = HashRing::with_hashers(config.clone(), node_hasher.clone(), key_hasher.clone())?;
+        Ok(HierarchicalRing {
+            config,
+            node_hasher,
+            key_hasher,
+            zones,
+            zone_rings: BTreeMap::new(),
+        })
+    }
+
+    /// Adds an empty zone. Zones are also added by `add_node` when needed.
+    pub fn add_zone(&mut self, zone: &'a str) -> Result<(), Box<dyn Error>> {
+        if self.zone_rings.contains_key(zone) {
+            return Err("zone already exist".into());
+        }
+        let ring = HashRing::with_hashers(self.config.clone(), self.node_hasher.clone(), self.key_hasher.clone())?;
+        self.zones.add_node(Arc::new(Zone { name: zone }))?;
+        self.zone_rings.insert(zone, ring);
+        Ok(())
+    }
+
+    /// Removes a zone together with all of its nodes.
+    pub fn remove_zone(&mut self, zone: &str) -> Result<(), Box<dyn Error>> {
+        if self.zone_rings.remove(zone).is_none() {
+            return Err("zone not found".into());
+        }
+        self.zones.remove_node(zone)
+    }
+
+    /// Adds a node to the ring of its zone, adding the zone if it is new.
+    ///
+    /// # Arguments
+    ///
+    /// * `node` - The node to add. Its `zone` must be set.
+    pub fn add_node(&mut self, node: Arc<dyn Node<'a> + 'a>) -> Result<Arc<dyn Node<'a> + 'a>, Box<dyn Error>> {
+        let zone = node.zone().ok_or("node has no zone")?;
+        if self.zone_rings.values().any(|ring| ring.nodes.read().is_ok_and(|nodes| nodes.contains_key(node.id()))) {
+            return Err("node already exist".into());
+        }
+        if !self.zone_rings.contains_key(zone) {
+            self.add_zone(zone)?;
+        }
+        self.zone_rings.get_mut(zone).ok_or("zone not found")?.add_node(node)
+    }
+
+    /// Removes a node from its zone. The zone is kept, even if it becomes empty.
+    pub fn remove_node(&mut self, id: &str) -> Result<(), Box<dyn Error>> {
+        for ring in self.zone_rings.values_mut() {
+            if ring.nodes.read()?.contains_key(id) {
+                return ring.remove_node(id);
+            }
+        }
+        Err("node not found".into())
+    }
+
+    /// Returns the zones, sorted by name.
+    pub fn zones(&self) -> Vec<&'a str> {
+        self.zone_rings.keys().copied().collect()
+    }
+
+    /// Returns the ring of nodes of a zone.
+    pub fn zone_ring(&self, zone: &str) -> Option<&HashRing<'a, H, K>> {
+        self.zone_rings.get(zone)
+    }
+
+    /// Returns the zone the key is assigned to, skipping zones without nodes.
+    pub fn get_zone(&self, key: &[u8]) -> Option<&'a str> {
+        self.zone_order(key).into_iter().next()
+    }
+
+    /// Retrieves the node responsible for the given key within its zone.
+    pub fn get_key(&self, key: &[u8]) -> Option<Arc<dyn Node<'a> + 'a>> {
+        let zone = self.get_zone(key)?;
+        self.zone_rings.get(zone)?.get_key(key)
+    }
+
+    /// Retrieves up to `replication_factor` distinct nodes for the given key, spread over zones.
+    pub fn get_preference_list(&self, key: &[u8]) -> ReplicaSet<'a> {
+        self.get_preference_list_n(key, self.config.replication_factor).into()
+    }
+
+    /// Retrieves up to `n` distinct nodes for the given key.
+    ///
+    /// The list takes the first node of every zone in the key's zone order, then the
+    /// second node of every zone, and so on, so replicas span as many zones as possible.
+    pub fn get_preference_list_n(&self, key: &[u8], n: usize) -> Vec<Arc<dyn Node<'a> + 'a>> {
+        let per_zone: Vec<Vec<Arc<dyn Node<'a> + 'a>>> = self
+            .zone_order(key)
+            .into_iter()
+            .filter_map(|zone| self.zone_rings.get(zone))
+            .map(|ring| ring.get_preference_list_n(key, n))
+            .collect();
+
+        let mut preference_list: Vec<Arc<dyn Node<'a> + 'a>> = Vec::new();
+        let mut seen = HashSet::new();
+        let depth = per_zone.iter().map(Vec::len).max().unwrap_or(0);
+        for rank in 0..depth {
+            for nodes in &per_zone {
+                if preference_list.len() >= n {
+                    return preference_list;
+                }
+                if let Some(node) = nodes.get(rank) {
+                    if seen.insert(node.id()) {
+                        preference_list.push(node.clone());
+                    }
+                }
+            }
+        }
+        preference_list
+    }
+
+    /// Returns the zones that have nodes, in the key's preference order.
+    fn zone_order(&self, key: &[u8]) -> Vec<&'a str> {
+        self.zones
+            .get_preference_list_n(key, self.zone_rings.len())
+            .iter()
+            .map(|zone| zone.id())
+            .filter(|zone| self.zone_rings.get(zone).is_some_and(|ring| !ring.sorted_nodes_hash_set.wait_read().is_empty()))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use crate::tests::test_nodes;
+    use crate::{Config, HierarchicalRing, Node};
+
+    #[derive(Debug)]
+    struct ZonedNode {
+        name: &'static str,
+        zone: &'static str,
+    }
+
+    impl<'a> Node<'a> for ZonedNode {
+        fn id(&self) -> &'a str {
+            self.name
+        }
+
+        fn zone(&self) -> Option<&'a str> {
+            Some(self.zone)
+        }
+    }
+
+    #[test]
+    fn test_hierarchical_placement() {
+        let mut ring = HierarchicalRing::new(Config::default()).unwrap();
+        assert!(ring.get_key(b"some_key").is_none());
+        assert!(ring.add_node(test_nodes()[0].clone()).is_err());
+
+        for (name, zone) in [("node1", "a"), ("node2", "a"), ("node3", "b"), ("node4", "c")] {
+            ring.add_node(Arc::new(ZonedNode { name, zone })).unwrap();
+        }
+        assert!(ring.add_node(Arc::new(ZonedNode { name: "node1", zone: "b" })).is_err());
+        assert_eq!(ring.zones(), vec!["a", "b", "c"]);
+
+        let keys: Vec<String> = (0..300).map(|i| format!("key-{}", i)).collect();
+        for key in &keys {
+            let node = ring.get_key(key.as_bytes()).unwrap();
+            assert_eq!(node.zone(), ring.get_zone(key.as_bytes()));
+            let zones: Vec<Option<&str>> = ring.get_preference_list_n(key.as_bytes(), 4).iter().map(|node| node.zone()).collect();
+            assert_eq!(zones.len(), 4);
+            assert_eq!(zones[0], node.zone());
+            assert_eq!(zones[3], Some("a"));
+        }
+
+        // Changes inside zone a don't move keys assigned to other zones.
+        let before: Vec<String> = keys.iter().map(|key| ring.get_key(key.as_bytes()).unwrap().id().to_string()).collect();
+        ring.remove_node("node2").unwrap();
+        for (key, owner) in keys.iter().zip(&before) {
+            if ring.get_zone(key.as_bytes()) != Some("a") {
+                assert_eq!(ring.get_key(key.as_bytes()).unwrap().id(), owner);
+            }
+        }
+
+        // Keys of an empty zone fall through to the next zone.
+        ring.remove_node("node4").unwrap();
+        assert_eq!(ring.zones().len(), 3);
+        assert!(keys.iter().all(|key| ring.get_zone(key.as_bytes()) != Some("c")));
+        ring.remove_zone("c").unwrap();
+        assert!(ring.remove_zone("c").is_err());
+        assert!(ring.remove_node("node4").is_err());
+    }
+}
@@ -0,0 +1,289 @@
+//! Lookups against recent versions of the ring.
+//!
+//! With a history depth of `n`, the ring keeps the virtual nodes as they were before
+//! each of the last `n` membership changes. Readers can then fall back to the previous
+//! owner of a key while its data is still being migrated, without keeping old rings around.
+//!
+//! Every kept version is tagged with the last ring version it was current at, so
+//! `moved_ranges` can also tell storage engines exactly which ranges of key hashes
+//! changed owner between two versions, and scan only those during a migration.
+
+use std::collections::{BTreeMap, VecDeque};
+use std::error::Error;
+use std::hash::BuildHasher;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+
+use crate::compare::diff_ranges;
+use crate::{HashRing, MovedRange, Node};
+
+/// The virtual nodes of previous versions of the ring, oldest first, each with the last
+/// ring version it was current at.
+pub(crate) type Versions<'a> = VecDeque<(u64, Arc<BTreeMap<u64, Arc<dyn Node<'a> + 'a>>>)>;
+
+/// The current owner of a key, and its owner before it last moved.
+#[derive(Debug, Clone)]
+pub struct OwnerLookup<'a> {
+    pub current: Option<Arc<dyn Node<'a> + 'a>>,
+    /// The most recent owner different from `current`, if the key moved within the kept history.
+    pub previous: Option<Arc<dyn Node<'a> + 'a>>,
+}
+
+impl<'a, H, K> HashRing<'a, H, K>
+where
+    H: BuildHasher,
+    K: BuildHasher,
+{
+    /// Sets how many previous versions of the ring are kept for `get_key_with_previous`.
+    ///
+    /// Defaults to `0`, which disables the history.
+    pub fn set_history_depth(&mut self, depth: usize) {
+        self.history_depth.store(depth, Ordering::Release);
+        let mut history = self.history.wait_write();
+        while history.len() > depth {
+            history.pop_front();
+        }
+    }
+
+    /// Returns how many previous versions of the ring are kept.
+    pub fn history_depth(&self) -> usize {
+        self.history_depth.load(Ordering::Acquire)
+    }
+
+    pub(crate) fn keeps_history(&self) -> bool {
+        self.history_depth() > 0
+    }
+
+    /// Keeps the virtual nodes replaced by a change. Must be called before the version is
+    /// bumped, so they are tagged with the last version they were current at.
+    pub(crate) fn record_history(&self, previous: BTreeMap<u64, Arc<dyn Node<'a> + 'a>>) {
+        if !self.keeps_history() {
+            return;
+        }
+        let mut history = self.history.wait_write();
+        history.push_back((self.version(), Arc::new(previous)));
+        let depth = self.history_depth();
+        while history.len() > depth {
+            history.pop_front();
+        }
+    }
+
+    /// Retrieves the node responsible for the given key, along with its previous owner
+    /// if the key moved during the last `history_depth` changes, or else the node owning
+    /// it before `remove_node_with_grace` if its grace period hasn't ended.
+    ///
+    /// The current owner is the one `get_key` returns, so a key diverted by a traffic split
+    /// is owned by its canary. Traffic splits are not kept in the history.
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - The key for which the responsible node is to be found.
+    ///
+    /// # Returns
+    ///
+    /// * `OwnerLookup<'a>` - The current owner, and the most recent different owner if any.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use std::sync::Arc;
+    /// use hashring::{HashRing, Config, Node};
+    ///
+    /// #[derive(Debug)]
+    /// struct MyNode<'a> {
+    ///     name: &'a str,
+    /// }
+    ///
+    /// impl<'a> Node<'a> for MyNode<'a> {
+    ///     fn id(&self) -> &'a str {
+    ///         self.name
+    ///     }
+    /// }
+    ///
+    /// let mut hash_ring = HashRing::new(Config::default()).unwrap();
+    /// hash_ring.set_history_depth(4);
+    /// hash_ring.add_node(Arc::new(MyNode { name: "node1" })).unwrap();
+    /// hash_ring.add_node(Arc::new(MyNode { name: "node2" })).unwrap();
+    ///
+    /// let lookup = hash_ring.get_key_with_previous(b"some_key");
+    /// if let (Some(current), Some(previous)) = (lookup.current, lookup.previous) {
+    ///     println!("some_key moved from {} to {}", previous.id(), current.id());
+    /// }
+    /// ```
+    pub fn get_key_with_previous(&self, key: &[u8]) -> OwnerLookup<'a> {
+        let Ok(hashed_key) = self.hash_key(key) else {
+            return OwnerLookup { current: None, previous: None };
+        };
+        let current = self
+            .get_node_for_hash(hashed_key)
+            .map(|owner| self.divert(&owner, hashed_key).unwrap_or(owner));
+
+        let history = self.history.wait_read();
+        let previous = history
+            .iter()
+            .rev()
+            .filter_map(|(_, sorted_set)| sorted_set.range(hashed_key..).next().or_else(|| sorted_set.iter().next()))
+            .map(|(_, node)| node)
+            .find(|node| !matches!(&current, Some(current) if current.id() == node.id()))
+            .cloned();
+        drop(history);
+        let previous = previous.or_else(|| self.tombstone_owner(hashed_key));
+
+        OwnerLookup { current, previous }
+    }
+
+    /// Returns the ranges of key hashes whose owner changed between two versions of the ring.
+    ///
+    /// Both versions must be within the kept history: at least as recent as the last
+    /// version at which the oldest kept virtual nodes were current. Versions bumped without
+    /// moving virtual nodes, e.g. by assigning a partition, move no ranges.
+    ///
+    /// # Arguments
+    ///
+    /// * `from_version` - The version the keys were placed with.
+    /// * `to_version` - The version the keys are moving to, usually the current one.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Vec<MovedRange>, Box<dyn Error>>` - The moved ranges in ascending order, adjacent
+    ///   ranges with the same owners merged, or an error if either version isn't kept.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use std::sync::Arc;
+    /// use hashring::{HashRing, Config, Node};
+    ///
+    /// #[derive(Debug)]
+    /// struct MyNode<'a> {
+    ///     name: &'a str,
+    /// }
+    ///
+    /// impl<'a> Node<'a> for MyNode<'a> {
+    ///     fn id(&self) -> &'a str {
+    ///         self.name
+    ///     }
+    /// }
+    ///
+    /// let mut hash_ring = HashRing::new(Config::default()).unwrap();
+    /// hash_ring.set_history_depth(4);
+    /// hash_ring.add_node(Arc::new(MyNode { name: "node1" })).unwrap();
+    /// let before = hash_ring.version();
+    /// hash_ring.add_node(Arc::new(MyNode { name: "node2" })).unwrap();
+    ///
+    /// for range in hash_ring.moved_ranges(before, hash_ring.version()).unwrap() {
+    ///     assert_eq!(range.from.as_deref(), Some("node1"));
+    ///     assert_eq!(range.to.as_deref(), Some("node2"));
+    /// }
+    /// ```
+    pub fn moved_ranges(&self, from_version: u64, to_version: u64) -> Result<Vec<MovedRange>, Box<dyn Error>> {
+        let current = self.sorted_nodes_hash_set.read()?;
+        let history = self.history.read()?;
+        let version = self.version();
+        for wanted in [from_version, to_version] {
+            if wanted > version {
+                return Err(format!("version {} is newer than the ring", wanted).into());
+            }
+            if history.front().is_some_and(|(until, _)| wanted < *until) || (history.is_empty() && wanted < version) {
+                return Err(format!("version {} is no longer kept, raise the history depth", wanted).into());
+            }
+        }
+        // A kept version was current up to its tag, and the current one since the last tag.
+        let at = |wanted: u64| {
+            history
+                .iter()
+                .find(|(until, _)| wanted <= *until)
+                .map_or(&*current, |(_, sorted_set)| sorted_set.as_ref())
+        };
+        let (from, to) = (at(from_version), at(to_version));
+        Ok(diff_ranges(from, to).0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::tests::test_nodes;
+    use crate::{Config, HashRing};
+
+    #[test]
+    fn test_previous_owner_after_change() {
+        let nodes = test_nodes();
+        let mut hash_ring = HashRing::new(Config::default()).unwrap();
+        hash_ring.set_history_depth(1);
+        hash_ring.add_node(nodes[0].clone()).unwrap();
+        hash_ring.add_node(nodes[1].clone()).unwrap();
+
+        let mut moved = 0;
+        for i in 0..100 {
+            let key = format!("key-{}", i);
+            let lookup = hash_ring.get_key_with_previous(key.as_bytes());
+            match lookup.current.unwrap().id() {
+                "node2" => {
+                    assert_eq!(lookup.previous.unwrap().id(), "node1");
+                    moved += 1;
+                }
+                _ => assert!(lookup.previous.is_none()),
+            }
+        }
+        assert!(moved > 0);
+
+        // The change that moved the keys falls out of a history of depth 1.
+        hash_ring.add_node(nodes[2].clone()).unwrap();
+        for i in 0..100 {
+            let key = format!("key-{}", i);
+            let lookup = hash_ring.get_key_with_previous(key.as_bytes());
+            if let (Some(current), Some(previous)) = (lookup.current, lookup.previous) {
+                assert_eq!(current.id(), "node3");
+                assert_ne!(previous.id(), "node3");
+            }
+        }
+    }
+
+    #[test]
+    fn test_moved_ranges_between_versions() {
+        let nodes = test_nodes();
+        let mut hash_ring = HashRing::new(Config::default()).unwrap();
+        hash_ring.set_history_depth(2);
+        hash_ring.add_node(nodes[0].clone()).unwrap();
+        let v1 = hash_ring.version();
+        hash_ring.add_node(nodes[1].clone()).unwrap();
+        let v2 = hash_ring.version();
+        hash_ring.add_node(nodes[2].clone()).unwrap();
+        let v3 = hash_ring.version();
+
+        assert!(hash_ring.moved_ranges(v3, v3).unwrap().is_empty());
+        assert!(hash_ring.moved_ranges(v3, v3 + 1).is_err());
+        let moved = hash_ring.moved_ranges(v2, v3).unwrap();
+        assert!(moved.iter().all(|range| range.to.as_deref() == Some("node3")));
+        assert!(moved.windows(2).all(|pair| pair[0].end < pair[1].start));
+
+        // Every key whose owner changed lies in exactly the moved ranges.
+        let covered: u64 = hash_ring.moved_ranges(v1, v3).unwrap().iter().map(|range| range.end - range.start + 1).sum();
+        let expected = 1.0 - hash_ring.keyspace_fraction("node1");
+        assert!((covered as f64 / u64::MAX as f64 - expected).abs() < 1e-9);
+        for i in 0..200 {
+            let key = format!("key-{}", i);
+            let hash = hash_ring.hash_key(key.as_bytes()).unwrap();
+            let owner = hash_ring.get_key(key.as_bytes()).unwrap();
+            let range = moved.iter().find(|range| range.start <= hash && hash <= range.end);
+            assert_eq!(range.is_some(), owner.id() == "node3");
+        }
+
+        // The oldest versions fall out of a history of depth 2.
+        hash_ring.remove_node("node1").unwrap();
+        assert!(hash_ring.moved_ranges(v1, v3).is_err());
+        assert_eq!(hash_ring.moved_ranges(v2, v3).unwrap(), moved);
+    }
+
+    #[test]
+    fn test_history_disabled_by_default() {
+        let nodes = test_nodes();
+        let mut hash_ring = HashRing::new(Config::default()).unwrap();
+        hash_ring.add_node(nodes[0].clone()).unwrap();
+        hash_ring.add_node(nodes[1].clone()).unwrap();
+        assert_eq!(hash_ring.history_depth(), 0);
+        for i in 0..100 {
+            assert!(hash_ring.get_key_with_previous(format!("key-{}", i).as_bytes()).previous.is_none());
+        }
+    }
+}
@@ -0,0 +1,245 @@
+//! Sticky routing of HTTP requests, behind the `http` feature.
+//!
+//! `StickyRouter` extracts a routing key from each request, such as a session cookie,
+//! a tenant header or a path segment, and returns the upstream node owning it, so
+//! gateways keep every request of a session on the same backend. With the `tower`
+//! feature, `StickyLayer` does the same as middleware, storing the chosen node in the
+//! request extensions for the proxying service.
+
+use std::fmt;
+use std::hash::BuildHasher;
+use std::sync::Arc;
+
+use ::http::{HeaderMap, Request, Uri};
+
+use crate::{HashRing, Node, XxHash64Hasher};
+
+/// A function extracting a routing key from the URI and headers of a request.
+type Extract = Arc<dyn Fn(&Uri, &HeaderMap) -> Option<Vec<u8>> + Send + Sync>;
+
+/// Where the routing key of a request is read from.
+#[derive(Clone)]
+pub enum RoutingKey {
+    /// The value of a header.
+    Header(String),
+    /// The value of a cookie.
+    Cookie(String),
+    /// A segment of the path, counting from zero and ignoring empty segments.
+    PathSegment(usize),
+    /// A key computed by a function.
+    Custom(Extract),
+}
+
+impl RoutingKey {
+    /// Reads the routing key with a function of the URI and headers of the request.
+    pub fn from_fn<F>(extract: F) -> RoutingKey
+    where
+        F: Fn(&Uri, &HeaderMap) -> Option<Vec<u8>> + Send + Sync + 'static,
+    {
+        RoutingKey::Custom(Arc::new(extract))
+    }
+
+    /// Returns the routing key of the request, or `None` if it doesn't carry one.
+    pub fn extract<B>(&self, request: &Request<B>) -> Option<Vec<u8>> {
+        match self {
+            RoutingKey::Header(name) => request.headers().get(name.as_str()).map(|value| value.as_bytes().to_vec()),
+            RoutingKey::Cookie(name) => request
+                .headers()
+                .get_all(::http::header::COOKIE)
+                .iter()
+                .filter_map(|value| value.to_str().ok())
+                .flat_map(|value| value.split(';'))
+                .filter_map(|cookie| cookie.trim().split_once('='))
+                .find(|(cookie, _)| cookie == name)
+                .map(|(_, value)| value.as_bytes().to_vec()),
+            RoutingKey::PathSegment(index) => request
+                .uri()
+                .path()
+                .split('/')
+                .filter(|segment| !segment.is_empty())
+                .nth(*index)
+                .map(|segment| segment.as_bytes().to_vec()),
+            RoutingKey::Custom(extract) => extract(request.uri(), request.headers()),
+        }
+    }
+}
+
+impl fmt::Debug for RoutingKey {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RoutingKey::Header(name) => fmt.debug_tuple("Header").field(name).finish(),
+            RoutingKey::Cookie(name) => fmt.debug_tuple("Cookie").field(name).finish(),
+            RoutingKey::PathSegment(index) => fmt.debug_tuple("PathSegment").field(index).finish(),
+            RoutingKey::Custom(_) => fmt.write_str("Custom"),
+        }
+    }
+}
+
+/// Routes HTTP requests to the upstream node owning their routing key.
+#[derive(Debug)]
+pub struct StickyRouter<'a, H = XxHash64Hasher, K = H> {
+    hash_ring: HashRing<'a, H, K>,
+    routing_key: RoutingKey,
+}
+
+/// Clones follow the membership of the same ring.
+impl<'a, H, K> Clone for StickyRouter<'a, H, K>
+where
+    H: BuildHasher + Clone,
+    K: BuildHasher + Clone,
+{
+    fn clone(&self) -> Self {
+        StickyRouter {
+            hash_ring: self.hash_ring.share(),
+            routing_key: self.routing_key.clone(),
+        }
+    }
+}
+
+impl<'a, H, K> StickyRouter<'a, H, K>
+where
+    H: BuildHasher,
+    K: BuildHasher,
+{
+    /// Creates a router reading the routing key of every request from `routing_key`.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use std::sync::Arc;
+    /// use hashring::{HashRing, Config, Node, RoutingKey, StickyRouter};
+    ///
+    /// #[derive(Debug)]
+    /// struct MyNode<'a> {
+    ///     name: &'a str,
+    /// }
+    ///
+    /// impl<'a> Node<'a> for MyNode<'a> {
+    ///     fn id(&self) -> &'a str {
+    ///         self.name
+    ///     }
+    /// }
+    ///
+    /// let mut hash_ring = HashRing::new(Config::default()).unwrap();
+    /// hash_ring.add_node(Arc::new(MyNode { name: "10.0.0.1:8080" })).unwrap();
+    ///
+    /// let router = StickyRouter::new(hash_ring, RoutingKey::Cookie("session".to_string()));
+    /// let request = http::Request::get("/").header("cookie", "theme=dark; session=abc123").body(()).unwrap();
+    /// assert_eq!(router.route(&request).unwrap().id(), "10.0.0.1:8080");
+    /// ```
+    pub fn new(hash_ring: HashRing<'a, H, K>, routing_key: RoutingKey) -> StickyRouter<'a, H, K> {
+        StickyRouter { hash_ring, routing_key }
+    }
+
+    /// Returns the ring routing keys to nodes.
+    pub fn ring(&self) -> &HashRing<'a, H, K> {
+        &self.hash_ring
+    }
+
+    /// Returns the upstream node of the request, or `None` if it has no routing key or
+    /// the ring is empty.
+    pub fn route<B>(&self, request: &Request<B>) -> Option<Arc<dyn Node<'a> + 'a>> {
+        let key = self.routing_key.extract(request)?;
+        self.hash_ring.get_key(&key)
+    }
+}
+
+#[cfg(feature = "tower")]
+pub use self::layer::{Sticky, StickyLayer, Upstream};
+
+#[cfg(feature = "tower")]
+mod layer {
+    use std::sync::Arc;
+    use std::task::{Context, Poll};
+
+    use ::http::Request;
+    use tower::{Layer, Service};
+
+    use super::StickyRouter;
+    use crate::{Node, XxHash64Hasher};
+
+    /// The upstream node chosen for a request, stored in its extensions by `Sticky`.
+    #[derive(Debug, Clone)]
+    pub struct Upstream(pub Arc<dyn Node<'static>>);
+
+    /// A layer adding sticky upstream selection to a service.
+    #[derive(Debug, Clone)]
+    pub struct StickyLayer<H = XxHash64Hasher, K = H> {
+        router: Arc<StickyRouter<'static, H, K>>,
+    }
+
+    impl<H, K> StickyLayer<H, K> {
+        /// Creates a layer routing requests with `router`.
+        pub fn new(router: StickyRouter<'static, H, K>) -> StickyLayer<H, K> {
+            StickyLayer { router: Arc::new(router) }
+        }
+    }
+
+    impl<S, H, K> Layer<S> for StickyLayer<H, K> {
+        type Service = Sticky<S, H, K>;
+
+        fn layer(&self, inner: S) -> Sticky<S, H, K> {
+            Sticky {
+                inner,
+                router: self.router.clone(),
+            }
+        }
+    }
+
+    /// A service storing the upstream node of every request as an `Upstream` extension
+    /// before passing it on. Requests without a routing key are passed on unchanged.
+    #[derive(Debug, Clone)]
+    pub struct Sticky<S, H = XxHash64Hasher, K = H> {
+        inner: S,
+        router: Arc<StickyRouter<'static, H, K>>,
+    }
+
+    impl<S, B, H, K> Service<Request<B>> for Sticky<S, H, K>
+    where
+        S: Service<Request<B>>,
+        H: std::hash::BuildHasher,
+        K: std::hash::BuildHasher,
+    {
+        type Response = S::Response;
+        type Error = S::Error;
+        type Future = S::Future;
+
+        fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), S::Error>> {
+            self.inner.poll_ready(cx)
+        }
+
+        fn call(&mut self, mut request: Request<B>) -> S::Future {
+            if let Some(node) = self.router.route(&request) {
+                request.extensions_mut().insert(Upstream(node));
+            }
+            self.inner.call(request)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ::http::Request;
+
+    use crate::tests::test_nodes;
+    use crate::{Config, HashRing, RoutingKey, StickyRouter};
+
+    #[test]
+    fn test_routing_keys_are_extracted() {
+        let request = Request::get("/tenants/acme/orders").header("x-tenant", "acme").header("cookie", "a=1; session=acme").body(()).unwrap();
+        assert_eq!(RoutingKey::Header("x-tenant".to_string()).extract(&request), Some(b"acme".to_vec()));
+        assert_eq!(RoutingKey::Cookie("session".to_string()).extract(&request), Some(b"acme".to_vec()));
+        assert_eq!(RoutingKey::PathSegment(1).extract(&request), Some(b"acme".to_vec()));
+        assert_eq!(RoutingKey::Cookie("missing".to_string()).extract(&request), None);
+        let custom = RoutingKey::from_fn(|uri, _| uri.query().map(|query| query.as_bytes().to_vec()));
+        assert_eq!(custom.extract(&request), None);
+
+        let mut hash_ring = HashRing::new(Config::default()).unwrap();
+        for node in test_nodes() {
+            hash_ring.add_node(node).unwrap();
+        }
+        let router = StickyRouter::new(hash_ring.share(), RoutingKey::PathSegment(1));
+        assert_eq!(router.route(&request).unwrap().id(), hash_ring.get_key(b"acme").unwrap().id());
+        assert!(router.route(&Request::get("/").body(()).unwrap()).is_none());
+    }
+}
@@ -0,0 +1,167 @@
+//! Checks of the invariants key routing relies on.
+//!
+//! These are meant for fuzzing and property tests: they walk the ring's internal state
+//! and return a description of the first violation instead of panicking, so callers can
+//! attach the input that triggered it.
+
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::hash::BuildHasher;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+
+use crate::HashRing;
+
+impl<'a, H, K> HashRing<'a, H, K>
+where
+    H: BuildHasher,
+    K: BuildHasher,
+{
+    /// Checks that the virtual nodes, members and partitions agree with each other.
+    ///
+    /// Every virtual node and every partition must belong to a member, no member may
+    /// hold more than `replication_factor` virtual nodes, and every partition receiving
+    /// keys must have an owner while the ring has virtual nodes.
+    pub fn check_invariants(&self) -> Result<(), Box<dyn Error>> {
+        let nodes = self.nodes.read()?;
+        let sorted_set = self.sorted_nodes_hash_set.read()?;
+        let partitions = self.partitions.read()?;
+
+        let mut vnodes: HashMap<&str, usize> = HashMap::new();
+        for (point, node) in sorted_set.iter() {
+            match nodes.get(node.id()) {
+                Some(member) if Arc::ptr_eq(member, node) => {}
+                _ => return Err(format!("virtual node {:016x} belongs to {}, which is not a member", point, node.id()).into()),
+            }
+            *vnodes.entry(node.id()).or_insert(0) += 1;
+        }
+        if let Some((id, count)) = vnodes.iter().find(|(_, count)| **count > self.config().replication_factor) {
+            return Err(format!("{} has {} virtual nodes, more than the replication factor", id, count).into());
+        }
+
+        for (part_id, node) in partitions.iter() {
+            if !self.has_partition(*part_id) {
+                return Err(format!("partition {} is assigned but does not exist", part_id).into());
+            }
+            if !nodes.contains_key(node.id()) {
+                return Err(format!("partition {} is owned by {}, which is not a member", part_id, node.id()).into());
+            }
+        }
+        if !sorted_set.is_empty() && !self.partitions_dirty.load(Ordering::Acquire) {
+            if let Some(part_id) = self.partition_ids().into_iter().find(|p| !partitions.contains_key(p)) {
+                return Err(format!("partition {} has no owner", part_id).into());
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks that a key maps to exactly one live node, and that its preference list
+    /// starts with the node the ring places it on and contains no duplicates. The two
+    /// differ for keys a traffic split diverts to a canary.
+    pub fn check_key(&self, key: &[u8]) -> Result<(), Box<dyn Error>> {
+        let members: HashSet<String> = self.nodes.read()?.keys().cloned().collect();
+        let owner = match self.get_key(key) {
+            Some(owner) => owner,
+            None if members.is_empty() => return Ok(()),
+            None => return Err("key has no owner although the ring has members".into()),
+        };
+        if !members.contains(owner.id()) {
+            return Err(format!("key is owned by {}, which is not a member", owner.id()).into());
+        }
+
+        let hashed_key = self.hash_key(key)?;
+        let placed = self.get_node_for_hash(hashed_key).ok_or("key has no place on the ring")?;
+        let diverted = self.divert(&placed, hashed_key);
+        if diverted.as_ref().unwrap_or(&placed).id() != owner.id() {
+            return Err(format!("key is owned by {}, but placed on {}", owner.id(), placed.id()).into());
+        }
+        let preference_list = self.get_preference_list_n(key, members.len());
+        if preference_list.first().map(|node| node.id()) != Some(placed.id()) {
+            return Err(format!("preference list does not start with {}", placed.id()).into());
+        }
+        let mut seen = HashSet::new();
+        for node in &preference_list {
+            if !seen.insert(node.id()) {
+                return Err(format!("preference list contains {} twice", node.id()).into());
+            }
+        }
+        let sorted_set = self.sorted_nodes_hash_set.read()?;
+        let expected = sorted_set.values().map(|node| node.id()).collect::<HashSet<_>>().len();
+        if preference_list.len() != expected {
+            return Err(format!("preference list has {} nodes, expected {}", preference_list.len(), expected).into());
+        }
+        Ok(())
+    }
+
+    /// Removes a node and adds it back, checking that only its keys moved in between
+    /// and that every key is routed as before afterwards.
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - The ID of the node to remove and add back.
+    /// * `keys` - The keys whose routing is compared.
+    pub fn check_remove_readd(&mut self, id: &str, keys: &[&[u8]]) -> Result<(), Box<dyn Error>> {
+        let node = self.nodes.read()?.get(id).cloned().ok_or("node not found")?;
+        let owners = |ring: &Self| -> Vec<Option<String>> { keys.iter().map(|key| ring.get_key(key).map(|n| n.id().to_string())).collect() };
+
+        let before = owners(self);
+        self.remove_node(id)?;
+        let during = owners(self);
+        self.add_node(node)?;
+        let after = owners(self);
+
+        for (i, key) in keys.iter().enumerate() {
+            if before[i].as_deref() != Some(id) && during[i] != before[i] {
+                return Err(format!("{:?} moved from {:?} to {:?} although {} did not own it", key, before[i], during[i], id).into());
+            }
+            if after[i] != before[i] {
+                return Err(format!("{:?} is owned by {:?} after re-adding {}, was {:?}", key, after[i], id, before[i]).into());
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use crate::tests::{test_nodes, TestNode};
+    use crate::{Config, Crc16BuildHasher, HashRing, Node};
+
+    #[test]
+    fn test_invariants_hold() {
+        let mut hash_ring = HashRing::new(Config::default()).unwrap();
+        hash_ring.check_invariants().unwrap();
+        hash_ring.check_key(b"some_key").unwrap();
+        for node in test_nodes() {
+            hash_ring.add_node(node).unwrap();
+        }
+        hash_ring.check_invariants().unwrap();
+
+        let keys: Vec<Vec<u8>> = (0..200).map(|i| format!("key-{}", i).into_bytes()).collect();
+        let keys: Vec<&[u8]> = keys.iter().map(|key| key.as_slice()).collect();
+        for key in &keys {
+            hash_ring.check_key(key).unwrap();
+        }
+        hash_ring.check_remove_readd("node2", &keys).unwrap();
+        assert!(hash_ring.check_remove_readd("unknown", &keys).is_err());
+    }
+
+    #[test]
+    fn test_invariants_hold_with_colliding_hasher() {
+        let mut hash_ring = HashRing::with_hasher(Config::default(), Crc16BuildHasher::default()).unwrap();
+        for i in 0..50 {
+            let name: &'static str = Box::leak(format!("n{}", i).into_boxed_str());
+            let node: Arc<dyn Node<'static>> = Arc::new(TestNode {
+                ip_addr: "127.0.0.1".to_string(),
+                name,
+            });
+            hash_ring.add_node(node).unwrap();
+        }
+        hash_ring.check_invariants().unwrap();
+        for i in 0..100 {
+            hash_ring.check_key(format!("key-{}", i).as_bytes()).unwrap();
+        }
+    }
+}
@@ -15,7 +15,7 @@
 // 
 // Example Usage:
 // --------------
-/// ```rust
+// ```rust
 // use core::fmt;
 // use std::sync::Arc;
 //
@@ -43,6 +43,7 @@
 //     let config = Config {
 //         replication_factor: 3,
 //         partition_count: 100,
+//         ..Config::default()
 //     };
 //
 //     // Create a new HashRing using the configuration
@@ -68,24 +69,209 @@
 //         println!("No node found for the key");
 //     }
 // }
-/// ```
+// ```
 
 
 use core::fmt;
-use std::collections::{BTreeMap, HashMap, HashSet};
+use std::collections::{BTreeMap, HashMap};
 use std::error::Error;
 use std::fmt::Debug;
 use std::hash::{BuildHasher, BuildHasherDefault, Hasher};
-use std::sync::{Arc, RwLock};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+use sync::{LockMonitor, RwLock};
+use trace::OpTimer;
 use xxhash_rust::xxh3::Xxh3;
 
-type XxHash64Hasher = BuildHasherDefault<Xxh3>;
+#[cfg(feature = "admin-http")]
+mod admin;
+mod affinity;
+mod anchor;
+mod audit;
+#[cfg(feature = "tower")]
+mod balance;
+mod batch;
+mod bloom;
+mod bootstrap;
+mod builder;
+mod canary;
+mod capacity;
+mod cell;
+mod changelog;
+mod clock;
+mod clone;
+mod collision;
+mod compare;
+mod composite;
+mod consensus;
+mod cost;
+mod crc;
+mod decay;
+mod delta;
+mod diagram;
+mod display;
+mod drain;
+mod events;
+mod exchange;
+#[cfg(feature = "serde")]
+mod export;
+mod failure;
+mod finger;
+mod flap;
+mod format;
+mod frozen;
+mod grace;
+mod hedge;
+mod hierarchical;
+mod history;
+#[cfg(feature = "http")]
+mod http;
+mod invariants;
+mod load;
+mod local;
+#[cfg(feature = "memcache")]
+mod memcache;
+mod merge;
+mod migration;
+mod multiprobe;
+mod node_id;
+#[cfg(feature = "otel")]
+mod otel;
+mod ownership;
+mod partitioner;
+mod persist;
+mod planning;
+#[cfg(feature = "pool")]
+mod pool;
+mod project;
+#[cfg(feature = "proto")]
+pub mod proto;
+mod quorum;
+mod ramp;
+#[cfg(feature = "tokio")]
+mod rebalancer;
+#[cfg(feature = "redis")]
+mod redis;
+mod rendezvous;
+mod repair;
+mod replica;
+mod replication;
+mod resize;
+mod retry;
+mod ring;
+mod rng;
+mod roles;
+mod routing;
+mod sampling;
+mod scoped;
+mod shadow;
+#[cfg(feature = "sharded")]
+mod sharded;
+pub mod sim;
+mod siphash;
+mod slots;
+mod snapshot;
+mod split;
+mod sync;
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
+mod trace;
+mod transaction;
+mod ttl;
+pub mod util;
+#[cfg(feature = "watch")]
+mod watch;
+mod weight;
+
+#[cfg(feature = "admin-http")]
+pub use admin::admin_router;
+pub use anchor::AnchorRing;
+pub use audit::RingAudit;
+#[cfg(feature = "tower")]
+pub use balance::{BoxError, ConsistentHashBalance};
+pub use bloom::OwnershipFilter;
+pub use builder::HashRingBuilder;
+pub use canary::TrafficSplit;
+pub use capacity::NodeCapacity;
+pub use cell::CellRing;
+pub use changelog::{Change, ChangelogEntry};
+pub use clock::{Clock, MockClock, SystemClock};
+pub use collision::{CollisionPolicy, VnodeEncoding};
+pub use compare::{MovedRange, RingComparison};
+pub use composite::composite_key;
+pub use consensus::ProposedChange;
+pub use cost::{CostModel, UniformCost};
+pub use crc::{crc16, crc32, Crc16BuildHasher, Crc16Hasher, Crc32BuildHasher, Crc32Hasher};
+pub use drain::DrainStatus;
+pub use events::{EventSink, RingEvent};
+pub use exchange::{Reconciliation, TaggedSnapshot, VectorClock};
+pub use failure::ImpactReport;
+pub use finger::{Finger, FingerTable, Route};
+pub use flap::FlapPolicy;
+pub use format::RingFormatVersion;
+pub use frozen::FrozenRing;
+pub use hierarchical::HierarchicalRing;
+pub use history::OwnerLookup;
+#[cfg(feature = "http")]
+pub use crate::http::{RoutingKey, StickyRouter};
+#[cfg(all(feature = "http", feature = "tower"))]
+pub use crate::http::{Sticky, StickyLayer, Upstream};
+pub use load::PartitionMove;
+pub use local::{LocalHashRing, LocalNode};
+#[cfg(feature = "memcache")]
+pub use memcache::{MemcacheConn, MemcacheRouter};
+pub use merge::MergePolicy;
+pub use migration::{PartitionState, ReadPolicy};
+pub use multiprobe::{MultiProbeRing, DEFAULT_PROBE_COUNT};
+pub use node_id::{NodeId, NodeIdPolicy};
+#[cfg(feature = "otel")]
+pub use otel::OtelEventSink;
+pub use partitioner::Partitioner;
+pub use planning::{recommend_config, ConfigRecommendation};
+#[cfg(feature = "pool")]
+pub use pool::{PooledConn, PooledRing};
+pub use quorum::{Quorum, QuorumSet};
+#[cfg(feature = "tokio")]
+pub use rebalancer::{DataMover, RebalancePolicy, Rebalancer};
+#[cfg(feature = "redis")]
+pub use crate::redis::RedisRouter;
+pub use rendezvous::RendezvousRing;
+pub use repair::RepairTask;
+pub use replica::{Replica, ReplicaRole, ReplicaSet};
+pub use replication::ReplicationDelta;
+pub use resize::PartitionMigration;
+pub use ring::Ring;
+pub use rng::RingRng;
+pub use roles::{NodeRole, Operation};
+pub use scoped::ScopedRing;
+pub use shadow::{ShadowLookup, ShadowedRing};
+#[cfg(feature = "sharded")]
+pub use sharded::ShardedReader;
+pub use siphash::{SipBuildHasher, SipHasher24};
+pub use snapshot::RingSnapshot;
+pub use sync::{LockError, LockStats};
+pub use transaction::Transaction;
+#[cfg(feature = "watch")]
+pub use watch::{ConfigDelta, ConfigWatcher, FileConfig};
+pub use slots::{key_slot, SlotMap, SlotRoute, SLOT_COUNT};
+
+pub(crate) type XxHash64Hasher = BuildHasherDefault<Xxh3>;
 
 const DEFAULT_PARTITION_COUNT: usize = 271;
 const DEFAULT_REPLICATION_FACTOR: usize = 20;
 
 pub trait Node<'a>: Send + Sync + Debug {
     fn id(&self) -> &'a str;
+
+    /// The zone the node runs in, such as an availability zone or a rack.
+    fn zone(&self) -> Option<&'a str> {
+        None
+    }
+
+    /// Whether the node accepts writes or only serves reads.
+    fn role(&self) -> NodeRole {
+        NodeRole::ReadWrite
+    }
 }
 
 impl<'a> fmt::Display for dyn Node<'a> {
@@ -94,10 +280,34 @@ impl<'a> fmt::Display for dyn Node<'a> {
     }
 }
 
-#[derive(Debug, Clone)]
+/// The settings of a ring.
+///
+/// Settings keep being added, so `Config` can't be built with a struct literal outside
+/// this crate: start from `Config::new` or `Config::default` and set the fields to change.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+#[non_exhaustive]
 pub struct Config {
     pub replication_factor: usize,
     pub partition_count: usize,
+    /// When set, `rebalance_if_skewed` rebalances the ring whenever the ratio between
+    /// the most loaded node and the mean node load exceeds this value.
+    pub rebalance_skew_threshold: Option<f64>,
+    /// What to do when a virtual node hashes to a point that is already taken.
+    pub collision_policy: CollisionPolicy,
+    /// A namespace mixed into every key hash, so applications sharing the same nodes
+    /// get independent key distributions. Node placement is not affected.
+    pub key_namespace: Option<String>,
+    /// The rules the IDs of added nodes must follow. Only checked on addition, so it is
+    /// not part of persisted rings.
+    pub node_id_policy: NodeIdPolicy,
+    /// The placement rules of the ring. Changing it moves every virtual node.
+    pub format_version: RingFormatVersion,
+    /// The fraction of its reported capacity a node keeps free before it stops receiving
+    /// new partitions. Only applies to nodes that reported a capacity, and is not part
+    /// of persisted rings.
+    pub capacity_headroom: f64,
 }
 
 impl Default for Config {
@@ -105,11 +315,28 @@ impl Default for Config {
         Config {
             replication_factor: DEFAULT_REPLICATION_FACTOR,
             partition_count: DEFAULT_PARTITION_COUNT,
+            rebalance_skew_threshold: None,
+            collision_policy: CollisionPolicy::default(),
+            key_namespace: None,
+            node_id_policy: NodeIdPolicy::default(),
+            format_version: RingFormatVersion::default(),
+            capacity_headroom: capacity::DEFAULT_CAPACITY_HEADROOM,
         }
     }
 }
 
 impl Config {
+    /// Creates a configuration with the given replication factor and partition count.
+    ///
+    /// Every other setting takes its default value, and can be changed through its field.
+    pub fn new(replication_factor: usize, partition_count: usize) -> Config {
+        Config {
+            replication_factor,
+            partition_count,
+            ..Config::default()
+        }
+    }
+
     pub fn validate(&self) -> Result<(),Box<dyn Error>> {
         if self.partition_count == 0 {
             return Err("Partition count must be greater than 0")?;
@@ -117,31 +344,86 @@ impl Config {
         if self.replication_factor == 0 {
             return Err("Replication factor must be greater than 0")?;
         }
+        if let Some(threshold) = self.rebalance_skew_threshold {
+            if threshold.is_nan() || threshold <= 1.0 {
+                return Err("Rebalance skew threshold must be greater than 1.0")?;
+            }
+        }
+        if self.node_id_policy.max_len == 0 {
+            return Err("Node ID length limit must be greater than 0")?;
+        }
+        if !(0.0..1.0).contains(&self.capacity_headroom) {
+            return Err("Capacity headroom must be at least 0.0 and less than 1.0")?;
+        }
         Ok(())
     }
+
+    /// Feeds the key namespace to a key hasher, prefixed with its length as a 4-byte
+    /// big-endian integer so it cannot run into the key.
+    pub(crate) fn write_namespace(&self, hasher: &mut impl Hasher) {
+        if let Some(namespace) = &self.key_namespace {
+            hasher.write(&(namespace.len() as u32).to_be_bytes());
+            hasher.write(namespace.as_bytes());
+        }
+    }
 }
 
-#[derive(Debug, Clone)]
-pub struct HashRing<'a, H = XxHash64Hasher> {
-    config: Config,
-    hasher: H,
+#[derive(Debug)]
+pub struct HashRing<'a, H = XxHash64Hasher, K = H> {
+    config: Arc<RwLock<Arc<Config>>>,
+    node_hasher: H,
+    key_hasher: K,
     nodes: Arc<RwLock<HashMap<String, Arc<dyn Node<'a> + 'a>>>>,
     sorted_nodes_hash_set: Arc<RwLock<BTreeMap<u64, Arc<dyn Node<'a> + 'a>>>>,
     partitions: Arc<RwLock<HashMap<usize, Arc<dyn Node<'a> + 'a>>>>,
+    partition_load: Arc<RwLock<HashMap<usize, load::PartitionLoad>>>,
+    weights: Arc<RwLock<weight::Weights>>,
+    routes: Arc<RwLock<routing::Routes<'a>>>,
+    version: Arc<AtomicU64>,
+    drains: Arc<RwLock<HashMap<String, drain::Drain>>>,
+    ramps: Arc<RwLock<HashMap<String, ramp::Ramp>>>,
+    tenants: Arc<RwLock<scoped::TenantPools>>,
+    changelog: Arc<RwLock<changelog::Changelog>>,
+    event_sinks: Arc<RwLock<Vec<Arc<dyn EventSink>>>>,
+    pending_events: Arc<events::PendingEvents>,
+    flaps: Arc<RwLock<flap::Flaps>>,
+    tombstones: Arc<RwLock<Vec<grace::Tombstone<'a>>>>,
+    registrations: Arc<RwLock<HashMap<String, ttl::Registration>>>,
+    capacities: Arc<RwLock<HashMap<String, NodeCapacity>>>,
+    cost_model: Arc<RwLock<Option<Arc<dyn CostModel>>>>,
+    partition_states: Arc<RwLock<migration::PartitionStates>>,
+    read_policy: Arc<RwLock<ReadPolicy>>,
+    groups: Arc<RwLock<BTreeMap<String, Option<String>>>>,
+    history: Arc<RwLock<history::Versions<'a>>>,
+    ownership_filters: Arc<RwLock<HashMap<String, Arc<OwnershipFilter>>>>,
+    rng: Arc<RwLock<RingRng>>,
+    peer: Arc<RwLock<exchange::PeerState>>,
+    clock: Arc<RwLock<Arc<dyn Clock>>>,
+    locks: Arc<LockMonitor>,
+    history_depth: Arc<AtomicUsize>,
+    update_depth: Arc<AtomicUsize>,
+    partitions_dirty: Arc<AtomicBool>,
 }
 
 impl<'a> HashRing<'a, XxHash64Hasher> {
     pub fn new(config: Config) -> Result<HashRing<'a, XxHash64Hasher>, Box<dyn Error>> {
         HashRing::with_hasher(config, XxHash64Hasher::default())
     }
+
+    /// Returns a `HashRingBuilder` for configuring the node and key hashers independently.
+    pub fn builder() -> HashRingBuilder<XxHash64Hasher, XxHash64Hasher> {
+        HashRingBuilder::new()
+    }
 }
 
-impl<'a, H> HashRing<'a, H>
+impl<'a, H> HashRing<'a, H, H>
 where
-    H: BuildHasher,
+    H: BuildHasher + Clone,
 {
     /// Creates a new `HashRing` with the specified configuration and hasher.
     ///
+    /// The hasher is used both for placing virtual nodes and for hashing keys.
+    ///
     /// # Arguments
     ///
     /// * `config` - A `Config` struct that defines the replication factor and partition count.
@@ -159,13 +441,76 @@ where
     /// let hash_ring = HashRing::with_hasher(config, CustomBuildHasher::default()).unwrap();
     /// ```
     pub fn with_hasher(config: Config, hasher: H) -> Result<HashRing<'a, H>, Box<dyn Error>> {
+        HashRing::with_hashers(config, hasher.clone(), hasher)
+    }
+}
+
+impl<'a, H, K> HashRing<'a, H, K>
+where
+    H: BuildHasher,
+    K: BuildHasher,
+{
+    /// Creates a new `HashRing` with separate hashers for node placement and key lookups.
+    ///
+    /// Interop scenarios often need the two to differ, e.g. ketama-style node placement
+    /// combined with CRC32 key digests.
+    ///
+    /// # Arguments
+    ///
+    /// * `config` - A `Config` struct that defines the replication factor and partition count.
+    /// * `node_hasher` - The hasher used to place virtual nodes and partitions on the ring.
+    /// * `key_hasher` - The hasher used to hash keys.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use core::hash::BuildHasherDefault;
+    /// use hashring::{Config, Crc32BuildHasher, HashRing};
+    ///
+    /// type CustomBuildHasher = BuildHasherDefault<std::collections::hash_map::DefaultHasher>;
+    /// let hash_ring = HashRing::with_hashers(
+    ///     Config::default(),
+    ///     CustomBuildHasher::default(),
+    ///     Crc32BuildHasher::default(),
+    /// ).unwrap();
+    /// ```
+    pub fn with_hashers(config: Config, node_hasher: H, key_hasher: K) -> Result<HashRing<'a, H, K>, Box<dyn Error>> {
         config.validate()?;
+        let locks = Arc::new(LockMonitor::default());
         let hash_ring = HashRing {
-            nodes: Arc::new(RwLock::new(HashMap::new())),
-            sorted_nodes_hash_set: Arc::new(RwLock::new(BTreeMap::new())),
-            partitions: Arc::new(RwLock::new(HashMap::new())),
-            config,
-            hasher,
+            nodes: Arc::new(RwLock::new(HashMap::new(), &locks)),
+            sorted_nodes_hash_set: Arc::new(RwLock::new(BTreeMap::new(), &locks)),
+            partitions: Arc::new(RwLock::new(HashMap::new(), &locks)),
+            partition_load: Arc::new(RwLock::new(HashMap::new(), &locks)),
+            weights: Arc::new(RwLock::new(HashMap::new(), &locks)),
+            routes: Arc::new(RwLock::new(routing::Routes::default(), &locks)),
+            version: Arc::new(AtomicU64::new(0)),
+            drains: Arc::new(RwLock::new(HashMap::new(), &locks)),
+            ramps: Arc::new(RwLock::new(HashMap::new(), &locks)),
+            tenants: Arc::new(RwLock::new(HashMap::new(), &locks)),
+            changelog: Arc::new(RwLock::new(changelog::Changelog::default(), &locks)),
+            event_sinks: Arc::new(RwLock::new(Vec::new(), &locks)),
+            pending_events: Arc::new(events::PendingEvents::default()),
+            flaps: Arc::new(RwLock::new(flap::Flaps::default(), &locks)),
+            tombstones: Arc::new(RwLock::new(Vec::new(), &locks)),
+            registrations: Arc::new(RwLock::new(HashMap::new(), &locks)),
+            capacities: Arc::new(RwLock::new(HashMap::new(), &locks)),
+            cost_model: Arc::new(RwLock::new(None, &locks)),
+            partition_states: Arc::new(RwLock::new(HashMap::new(), &locks)),
+            read_policy: Arc::new(RwLock::new(ReadPolicy::default(), &locks)),
+            groups: Arc::new(RwLock::new(BTreeMap::new(), &locks)),
+            history: Arc::new(RwLock::new(history::Versions::new(), &locks)),
+            ownership_filters: Arc::new(RwLock::new(HashMap::new(), &locks)),
+            rng: Arc::new(RwLock::new(RingRng::default(), &locks)),
+            peer: Arc::new(RwLock::new(exchange::PeerState::default(), &locks)),
+            clock: Arc::new(RwLock::new(Arc::new(SystemClock), &locks)),
+            config: Arc::new(RwLock::new(Arc::new(config), &locks)),
+            locks,
+            history_depth: Arc::new(AtomicUsize::new(0)),
+            update_depth: Arc::new(AtomicUsize::new(0)),
+            partitions_dirty: Arc::new(AtomicBool::new(false)),
+            node_hasher,
+            key_hasher,
         };
 
         Ok(hash_ring)
@@ -212,23 +557,50 @@ where
     ///     
     /// hash_ring.add_node(node).unwrap();
     /// ```
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip_all, fields(node = node.id())))]
     pub fn add_node(&mut self, node: Arc<dyn Node<'a> + 'a>) -> Result<Arc<dyn Node<'a> + 'a>, Box<dyn Error>> {
-        let mut nodes = self.nodes.write().map_err(|_| "unable to acquire lock")?;
+        let _events = self.dispatch_events();
+        let timer = OpTimer::start();
+        self.config().node_id_policy.validate(node.id())?;
+        let mut nodes = self.nodes.write()?;
         if nodes.contains_key(node.id()) {
             return Err("node already exist".into());
         }
 
-        let mut sorted_set = self.sorted_nodes_hash_set.write().map_err(|_| "unable to acquire lock")?;
-        for i in 0..self.config.replication_factor {
-            let hash = self.hash_with_replica_idx(&node.id(), i);
-            sorted_set.insert(hash, node.clone());
+        let mut sorted_set = self.sorted_nodes_hash_set.write()?;
+        let mut partitions = self.partitions.write()?;
+        let previous = self.keeps_history().then(|| sorted_set.clone());
+        let replicas = 0..self.config().replication_factor;
+        self.place_vnodes(&mut sorted_set, &node, replicas.clone(), self.config().collision_policy)?;
+        // The join only counts towards flapping once the node could be placed.
+        if let Err(err) = self.record_membership_change(node.id(), true) {
+            self.remove_vnodes(&mut sorted_set, node.id(), replicas);
+            return Err(err);
+        }
+        if let Some(previous) = previous {
+            self.record_history(previous);
         }
 
         nodes.insert(node.id().to_string(), node.clone());
-        drop(nodes);
-        drop(sorted_set);
-
-        self.distribute_partitions();
+        if self.is_updating() {
+            self.partitions_dirty.store(true, Ordering::Release);
+        } else {
+            *partitions = self.compute_partitions(&sorted_set, &partitions);
+        }
+        self.bump_version();
+        self.log_changes("add_node", &timer, Some(&nodes), &partitions);
+        timer.finish("add_node", nodes.len(), sorted_set.len());
+        #[cfg(feature = "tracing")]
+        if tracing::enabled!(tracing::Level::DEBUG) {
+            let audit = audit::audit_vnodes(&sorted_set);
+            tracing::debug!(
+                min_gap = audit.min_gap,
+                max_gap = audit.max_gap,
+                largest_arc_node = audit.largest_arc_node,
+                largest_arc_fraction = audit.largest_arc_fraction,
+                "virtual node placement audited"
+            );
+        }
 
         Ok(node)
     }
@@ -277,61 +649,196 @@ where
     /// hash_ring.add_node(node.clone()).unwrap();
     /// hash_ring.remove_node(node.id()).unwrap();
     /// ```
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip(self)))]
     pub fn remove_node(&mut self, id: &str) -> Result<(), Box<dyn Error>> {
-        let mut sorted_set = self.sorted_nodes_hash_set.write().map_err(|_| "unable to acquire lock")?;
-        let mut nodes = self.nodes.write().map_err(|_| "unable to acquire lock")?;
+        self.remove_member(id, |_, _| ())
+    }
+
+    /// Removes a node like `remove_node`, calling `inspect` with the virtual nodes and the
+    /// partitions as they were before the removal, under the same locks.
+    pub(crate) fn remove_member<R>(
+        &self,
+        id: &str,
+        inspect: impl FnOnce(&BTreeMap<u64, Arc<dyn Node<'a> + 'a>>, &HashMap<usize, Arc<dyn Node<'a> + 'a>>) -> R,
+    ) -> Result<R, Box<dyn Error>> {
+        let _events = self.dispatch_events();
+        let timer = OpTimer::start();
+        let mut nodes = self.nodes.write()?;
         if !nodes.contains_key(id) {
             return Err("node not found".into());
         }
+        self.record_membership_change(id, false)?;
 
-        for i in 0..self.config.replication_factor {
-            let hash = self.hash_with_replica_idx(id, i);
-            sorted_set.remove(&hash);
+        let mut sorted_set = self.sorted_nodes_hash_set.write()?;
+        let mut partitions = self.partitions.write()?;
+        let inspected = inspect(&sorted_set, &partitions);
+        if self.keeps_history() {
+            self.record_history(sorted_set.clone());
         }
+        self.remove_vnodes(&mut sorted_set, id, 0..self.config().replication_factor);
 
         nodes.remove(id);
-        drop(nodes);
-        drop(sorted_set);
+        self.forget_member(id);
+        if self.is_updating() {
+            self.partitions_dirty.store(true, Ordering::Release);
+        } else {
+            *partitions = self.compute_partitions(&sorted_set, &partitions);
+        }
+        self.bump_version();
+        self.log_changes("remove_node", &timer, Some(&nodes), &partitions);
+        timer.finish("remove_node", nodes.len(), sorted_set.len());
 
-        self.distribute_partitions();
-        Ok(())
+        Ok(inspected)
+    }
+
+    /// Drops the state kept for a node that left the ring: its lease, its weight and the
+    /// traffic splits it takes part in.
+    ///
+    /// Every path removing members calls this, so a node rejoins like a new one.
+    pub(crate) fn forget_member(&self, id: &str) {
+        self.forget_registration(id);
+        self.forget_weight(id);
+        self.routes.wait_write().forget_traffic_splits(id);
+    }
+
+    /// Starts a batch of membership changes.
+    ///
+    /// Until the matching `commit`, adding or removing nodes updates the ring immediately
+    /// but defers the partition redistribution, so a rolling restart of many nodes triggers
+    /// a single redistribution. Calls can be nested; only the outermost `commit` redistributes.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use std::sync::Arc;
+    /// use hashring::{HashRing, Config, Node};
+    ///
+    /// #[derive(Debug)]
+    /// struct MyNode<'a> {
+    ///     name: &'a str,
+    /// }
+    ///
+    /// impl<'a> Node<'a> for MyNode<'a> {
+    ///     fn id(&self) -> &'a str {
+    ///         self.name
+    ///     }
+    /// }
+    ///
+    /// let mut hash_ring = HashRing::new(Config::default()).unwrap();
+    ///
+    /// hash_ring.begin_update();
+    /// hash_ring.add_node(Arc::new(MyNode { name: "node1" })).unwrap();
+    /// hash_ring.add_node(Arc::new(MyNode { name: "node2" })).unwrap();
+    /// hash_ring.commit();
+    /// ```
+    pub fn begin_update(&mut self) {
+        self.update_depth.fetch_add(1, Ordering::AcqRel);
+    }
+
+    /// Ends a batch of membership changes started with `begin_update`.
+    ///
+    /// # Returns
+    ///
+    /// * `bool` - `true` if the partitions were redistributed.
+    pub fn commit(&mut self) -> bool {
+        let previous = self
+            .update_depth
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |depth| Some(depth.saturating_sub(1)))
+            .unwrap_or_default();
+        if previous <= 1 && self.partitions_dirty.swap(false, Ordering::AcqRel) {
+            self.distribute_partitions();
+            return true;
+        }
+        false
+    }
+
+    /// Returns `true` while a batch of membership changes is in progress.
+    pub fn is_updating(&self) -> bool {
+        self.update_depth.load(Ordering::Acquire) > 0
     }
 
-    fn hash_with_replica_idx(&self, name: &str, replica: usize) -> u64 {
-        let data = format!("{}:{}", name, replica);
-        let mut hasher: <H as BuildHasher>::Hasher = self.hasher.build_hasher();
-        hasher.write(data.as_bytes());
-        hasher.finish()
+    /// Returns a counter that increases every time the topology of the ring changes.
+    ///
+    /// Callers caching lookups can compare versions to detect stale entries.
+    pub fn version(&self) -> u64 {
+        self.version.load(Ordering::Acquire)
+    }
+
+    fn bump_version(&self) {
+        self.version.fetch_add(1, Ordering::Release);
+    }
+
+    /// Returns the current configuration of the ring.
+    ///
+    /// The configuration is shared by every handle made with `share`, so changes such as
+    /// `set_replication_factor` made through one handle are seen by the others.
+    pub fn config(&self) -> Arc<Config> {
+        self.config.wait_read().clone()
+    }
+
+    /// Replaces the configuration seen by every handle. Callers hold the topology locks,
+    /// so the virtual nodes and the configuration they were placed with change together.
+    pub(crate) fn set_config(&self, config: Config) {
+        *self.config.wait_write() = Arc::new(config);
     }
 
     fn hash_partition_id(&self, part_id: usize) -> u64 {
-        let mut hasher: <H as BuildHasher>::Hasher = self.hasher.build_hasher();
-        hasher.write(&part_id.to_ne_bytes());
-        hasher.finish()
+        routing::hash_partition_id(&self.node_hasher, part_id)
+    }
+
+    /// Hashes a key, giving up after the lock timeout like the lookups calling it.
+    fn hash_key(&self, key: &[u8]) -> Result<u64, LockError> {
+        Ok(util::hash_key(&self.key_hasher, &*self.config.read()?, key))
     }
 
-    fn hash_key(&self, key: &[u8]) -> u64 {
-        let mut hasher: <H as BuildHasher>::Hasher = self.hasher.build_hasher();
-        hasher.write(key);
-        hasher.finish()
+    /// Returns the point the lookup of a key hash starts from: the hash itself, or a
+    /// virtual node of the node its partition was moved to by a rebalance.
+    fn route(&self, sorted_set: &BTreeMap<u64, Arc<dyn Node<'a> + 'a>>, hashed_key: u64) -> Result<u64, LockError> {
+        let partition_count = self.config.read()?.partition_count;
+        Ok(self.routes.read()?.point(partition_count, sorted_set, hashed_key))
     }
 
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip_all, fields(partition_count = self.config().partition_count)))]
     fn distribute_partitions(&self) {
-        let sorted_set = self.sorted_nodes_hash_set.read().unwrap();
-        let mut partitions = self.partitions.write().unwrap();
-        partitions.clear();
-
-        for part_id in 0..self.config.partition_count {
-            let hashed_part_id = self.hash_partition_id(part_id);
-            let idx = self.find_closest_idx(hashed_part_id);
-            if let Some(node) = sorted_set.get(&idx) {
+        let _events = self.dispatch_events();
+        let timer = OpTimer::start();
+        let sorted_set = self.sorted_nodes_hash_set.wait_read();
+        let mut partitions = self.partitions.wait_write();
+        *partitions = self.compute_partitions(&sorted_set, &partitions);
+        self.log_changes("distribute_partitions", &timer, None, &partitions);
+        timer.finish("distribute_partitions", partitions.len(), sorted_set.len());
+    }
+
+    /// Assigns every partition to the node following it on the ring.
+    ///
+    /// Nodes without capacity headroom keep the partitions they own in `previous` but
+    /// are skipped for the others. Partitions moved by a rebalance go back to being
+    /// routed by the ring, since the new table replaces the moves.
+    fn compute_partitions(
+        &self,
+        sorted_set: &BTreeMap<u64, Arc<dyn Node<'a> + 'a>>,
+        previous: &HashMap<usize, Arc<dyn Node<'a> + 'a>>,
+    ) -> HashMap<usize, Arc<dyn Node<'a> + 'a>> {
+        let leaves = self.routes.wait_read().leaves(self.config().partition_count);
+        let full = self.full_nodes();
+        let mut partitions = HashMap::new();
+        for part_id in leaves {
+            if !full.is_empty() {
+                let hashed_part_id = self.hash_partition_id(part_id);
+                if let Some(node) = capacity::owner_with_headroom(sorted_set, hashed_part_id, previous.get(&part_id), &full) {
+                    partitions.insert(part_id, node.clone());
+                }
+                continue;
+            }
+            if let Some(node) = routing::partition_owner(&self.node_hasher, sorted_set, part_id) {
                 partitions.insert(part_id, node.clone());
             }
         }
+        self.routes.wait_write().moved.clear();
+        partitions
     }
 
-    fn find_closest_idx(&self, hashed_part_id: u64) -> u64 {
-        let sorted_set = self.sorted_nodes_hash_set.read().unwrap();
+    fn find_closest_idx(sorted_set: &BTreeMap<u64, Arc<dyn Node<'a> + 'a>>, hashed_part_id: u64) -> u64 {
         sorted_set
             .range(hashed_part_id..)
             .next()
@@ -384,14 +891,106 @@ where
     ///     println!("Node responsible for key: {}", node);
     /// }
     /// ```
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "trace", skip_all))]
     pub fn get_key(&self, key: &[u8]) -> Option<Arc<dyn Node<'a> + 'a>> {
-        let hashed_key = self.hash_key(key);
+        let hashed_key = self.hash_key(key).ok()?;
+        let owner = self.get_node_for_hash(hashed_key)?;
+        self.divert(&owner, hashed_key).or(Some(owner))
+    }
+
+    /// Retrieves the ID of the node responsible for the given key.
+    ///
+    /// Routes like `get_key`, but returns the ID borrowed from the node instead of a clone
+    /// of its `Arc`, so lookups don't touch reference counts or allocate.
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - The key for which the responsible node is to be found.
+    ///
+    /// # Returns
+    ///
+    /// * `Option<&'a str>` - The ID of the node responsible for the given key, if found.
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "trace", skip_all))]
+    pub fn get_key_id(&self, key: &[u8]) -> Option<&'a str> {
+        let (hashed_key, partition_count) = {
+            let config = self.config.read().ok()?;
+            (util::hash_key(&self.key_hasher, &config, key), config.partition_count)
+        };
+        let (owner, canary) = {
+            let sorted_set = self.sorted_nodes_hash_set.read().ok()?;
+            let routes = self.routes.read().ok()?;
+            let owner = routes.owner(partition_count, &sorted_set, hashed_key)?.id();
+            (owner, routes.canary(owner, hashed_key).map(str::to_string))
+        };
+        // The ring is released before the nodes are read, since mutators take the nodes first.
+        match canary {
+            Some(canary) => Some(self.nodes.read().ok()?.get(&canary).map_or(owner, |node| node.id())),
+            None => Some(owner),
+        }
+    }
+
+    /// Retrieves the first node responsible for the given key that is not excluded.
+    ///
+    /// Nodes are tried in ring order, so retrying a request with the nodes that failed
+    /// excluded walks the same sequence as the preference list, without changing membership.
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - The key for which the responsible node is to be found.
+    /// * `excluded` - The IDs of the nodes to skip.
+    ///
+    /// # Returns
+    ///
+    /// * `Option<Arc<dyn Node<'a> + 'a>>` - The first node not excluded, or `None` if every node is.
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "trace", skip_all))]
+    pub fn get_key_excluding(&self, key: &[u8], excluded: &[&str]) -> Option<Arc<dyn Node<'a> + 'a>> {
+        let config = self.config.read().ok()?;
+        let hashed_key = util::hash_key(&self.key_hasher, &config, key);
         let sorted_set = self.sorted_nodes_hash_set.read().ok()?;
-        sorted_set
-            .range(hashed_key..)
-            .next()
-            .or_else(|| sorted_set.iter().next())
-            .map(|(_, node)| node.clone())
+        let routes = self.routes.read().ok()?;
+        let node = routes.walk(config.partition_count, &sorted_set, hashed_key).find(|node| !excluded.contains(&node.id())).cloned();
+        node
+    }
+
+    fn get_node_for_hash(&self, hash: u64) -> Option<Arc<dyn Node<'a> + 'a>> {
+        let config = self.config.read().ok()?;
+        let sorted_set = self.sorted_nodes_hash_set.read().ok()?;
+        let routes = self.routes.read().ok()?;
+        routes.owner(config.partition_count, &sorted_set, hash).cloned()
+    }
+
+    /// Returns the partition the given key falls into.
+    ///
+    /// Unlike the lookups of nodes, this waits for locks however long it takes.
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - The key whose partition is to be found.
+    ///
+    /// # Returns
+    ///
+    /// * `usize` - A partition ID, in `0..partition_count` unless partitions were split.
+    pub fn get_partition(&self, key: &[u8]) -> usize {
+        let config = self.config.wait_read();
+        let hashed_key = util::hash_key(&self.key_hasher, &config, key);
+        self.routes.wait_read().partition(config.partition_count, hashed_key)
+    }
+
+    /// Retrieves the node a partition is currently assigned to.
+    ///
+    /// During a migration this is still the old owner; see `get_partition_owners` for
+    /// reads from the new one.
+    ///
+    /// # Arguments
+    ///
+    /// * `part_id` - The ID of the partition.
+    ///
+    /// # Returns
+    ///
+    /// * `Option<Arc<dyn Node<'a> + 'a>>` - The owner of the partition, if the ring has any nodes.
+    pub fn get_partition_owner(&self, part_id: usize) -> Option<Arc<dyn Node<'a> + 'a>> {
+        let partitions = self.partitions.read().ok()?;
+        partitions.get(&part_id).cloned()
     }
 
     /// Returns a mapping of nodes to their number of virtual nodes in the hash ring.
@@ -436,7 +1035,7 @@ where
     /// ```
     pub fn virtual_nodes_per_node(&self) -> HashMap<String, usize> {
         let mut virtual_nodes = HashMap::new();
-        let sorted_set = self.sorted_nodes_hash_set.read().unwrap();
+        let sorted_set = self.sorted_nodes_hash_set.wait_read();
         for node in sorted_set.values() {
             *virtual_nodes.entry(node.id().to_string()).or_insert(0) += 1;
         }
@@ -451,7 +1050,7 @@ where
     ///
     /// # Returns
     ///
-    /// * `Vec<Arc<dyn Node<'a> + 'a>>` - A vector of nodes in the preference list for the given key.
+    /// * `ReplicaSet<'a>` - The distinct nodes for the given key, with the primary first.
     ///
     /// # Example
     ///
@@ -484,30 +1083,40 @@ where
     ///
     /// let key = b"some_key";
     /// let preference_list = hash_ring.get_preference_list(key);
-    /// for node in preference_list {
-    ///     println!("Node in preference list: {:?}", node);
+    /// for replica in preference_list {
+    ///     println!("{} ({}): {:?}", replica.node.id(), replica.role, replica.zone);
     /// }
     /// ```
-    pub fn get_preference_list(&self, key: &[u8]) -> Vec<Arc<dyn Node<'a> + 'a>> {
-        let mut preference_list: Vec<Arc<dyn Node<'a> + 'a>> = Vec::new();
-        let hashed_key = self.hash_key(key);
-        let sorted_set = self.sorted_nodes_hash_set.read().unwrap();
-        let mut unique_nodes = HashSet::new();
-
-        for (_, node) in sorted_set.range(hashed_key..).chain(sorted_set.range(..hashed_key)) {
-            if unique_nodes.insert(node.id().to_string()) {
-                preference_list.push(node.clone());
-                if preference_list.len() >= self.config.replication_factor {
-                    break;
-                }
-            }
-        }
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "trace", skip_all))]
+    pub fn get_preference_list(&self, key: &[u8]) -> ReplicaSet<'a> {
+        self.get_preference_list_n(key, self.config().replication_factor).into()
+    }
 
-        preference_list
+    /// Retrieves up to `n` distinct nodes for the given key, in ring order.
+    ///
+    /// Unlike `get_preference_list`, the fan-out is independent of the replication factor,
+    /// which is useful for read quorums and repair.
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - The key for which the preference list is to be found.
+    /// * `n` - The maximum number of nodes to return.
+    ///
+    /// # Returns
+    ///
+    /// * `Vec<Arc<dyn Node<'a> + 'a>>` - `min(n, number of nodes)` distinct nodes.
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "trace", skip(self, key)))]
+    pub fn get_preference_list_n(&self, key: &[u8], n: usize) -> Vec<Arc<dyn Node<'a> + 'a>> {
+        let (Ok(config), Ok(sorted_set), Ok(routes)) = (self.config.read(), self.sorted_nodes_hash_set.read(), self.routes.read()) else {
+            return Vec::new();
+        };
+        let hashed_key = util::hash_key(&self.key_hasher, &config, key);
+        routes.preference_list(config.partition_count, &sorted_set, hashed_key, n)
     }
 }
 // Tests
 #[cfg(test)]
+#[allow(clippy::needless_borrow, clippy::bool_assert_comparison)]
 mod tests {
     use super::*;
 
@@ -523,11 +1132,43 @@ mod tests {
         }
     }
 
+    pub fn test_nodes() -> Vec<Arc<TestNode<'static>>> {
+        ["node1", "node2", "node3"]
+            .into_iter()
+            .map(|name| {
+                Arc::new(TestNode {
+                    ip_addr: "127.0.0.1".to_string(),
+                    name,
+                })
+            })
+            .collect()
+    }
+
+    /// Returns a key routed to the node, outside the given partitions, with its partition.
+    pub fn key_on(hash_ring: &HashRing, node_id: &str, except: &[usize]) -> (Vec<u8>, usize) {
+        (0..)
+            .map(|i| format!("key-{}", i).into_bytes())
+            .map(|key| {
+                let part_id = hash_ring.get_partition(&key);
+                (key, part_id)
+            })
+            .find(|(key, part_id)| hash_ring.get_key_id(key) == Some(node_id) && !except.contains(part_id))
+            .unwrap()
+    }
+
+    /// Records `n` accesses to the key.
+    pub fn access(hash_ring: &HashRing, key: &[u8], n: usize) {
+        for _ in 0..n {
+            hash_ring.record_access(key);
+        }
+    }
+
     #[test]
     fn test_add_node() {
         let config = Config {
             replication_factor: 3,
             partition_count: 100,
+            ..Config::default()
         };
 
         let mut hash_ring = HashRing::new(config.clone()).unwrap();
@@ -554,6 +1195,7 @@ mod tests {
         let config = Config {
             replication_factor: 3,
             partition_count: 100,
+            ..Config::default()
         };
 
         let mut hash_ring = HashRing::new(config).unwrap();
@@ -586,6 +1228,7 @@ mod tests {
         let config = Config {
             partition_count: 10,
             replication_factor: 2,
+            ..Config::default()
         };
         let mut hash_ring = HashRing::new(config).unwrap();
 
@@ -612,6 +1255,7 @@ mod tests {
         let config = Config {
             replication_factor: 3,
             partition_count: 100,
+            ..Config::default()
         };
 
         let mut hash_ring = HashRing::new(config).unwrap();
@@ -635,6 +1279,118 @@ mod tests {
         assert_eq!(preference_list.len(), 2);
     }
 
+    #[test]
+    fn test_preference_list_n() {
+        let mut hash_ring = HashRing::new(Config::default()).unwrap();
+        for node in test_nodes() {
+            hash_ring.add_node(node).unwrap();
+        }
+
+        let key = b"some_key";
+        let full = hash_ring.get_preference_list_n(key, 10);
+        assert_eq!(full.len(), 3);
+        assert_eq!(hash_ring.get_preference_list_n(key, 0).len(), 0);
+
+        let two = hash_ring.get_preference_list_n(key, 2);
+        assert_eq!(two.len(), 2);
+        assert_eq!(two[0].id(), full[0].id());
+        assert_eq!(two[1].id(), full[1].id());
+        assert_eq!(hash_ring.get_preference_list_n(key, 1)[0].id(), hash_ring.get_key(key).unwrap().id());
+    }
+
+    #[test]
+    fn test_get_key_excluding() {
+        let mut hash_ring = HashRing::new(Config::default()).unwrap();
+        for node in test_nodes() {
+            hash_ring.add_node(node).unwrap();
+        }
+
+        let key = b"some_key";
+        let preference_list = hash_ring.get_preference_list_n(key, 3);
+        assert_eq!(hash_ring.get_key_excluding(key, &[]).unwrap().id(), preference_list[0].id());
+        assert_eq!(hash_ring.get_key_excluding(key, &[preference_list[0].id()]).unwrap().id(), preference_list[1].id());
+        assert_eq!(
+            hash_ring.get_key_excluding(key, &[preference_list[0].id(), preference_list[1].id()]).unwrap().id(),
+            preference_list[2].id()
+        );
+        assert!(hash_ring.get_key_excluding(key, &["node1", "node2", "node3"]).is_none());
+    }
+
+    #[test]
+    fn test_get_key_id() {
+        let mut hash_ring = HashRing::new(Config::default()).unwrap();
+        assert_eq!(hash_ring.get_key_id(b"some_key"), None);
+        for node in test_nodes() {
+            hash_ring.add_node(node).unwrap();
+        }
+        hash_ring.set_traffic_split("node2", "node3", 0.5).unwrap();
+        for i in 0..200 {
+            let key = format!("key-{}", i);
+            assert_eq!(hash_ring.get_key_id(key.as_bytes()), Some(hash_ring.get_key(key.as_bytes()).unwrap().id()));
+        }
+    }
+
+    /// A key hasher recording the reference count of a ring's configuration whenever
+    /// a key is hashed.
+    #[derive(Clone, Debug, Default)]
+    struct RefcountProbe {
+        config: Arc<std::sync::Mutex<Option<Arc<Config>>>>,
+        counts: Arc<std::sync::Mutex<Vec<usize>>>,
+    }
+
+    impl BuildHasher for RefcountProbe {
+        type Hasher = Xxh3;
+
+        fn build_hasher(&self) -> Xxh3 {
+            if let Some(config) = &*self.config.lock().unwrap() {
+                self.counts.lock().unwrap().push(Arc::strong_count(config));
+            }
+            Xxh3::default()
+        }
+    }
+
+    #[test]
+    fn test_get_key_id_leaves_refcounts_alone() {
+        let probe = RefcountProbe::default();
+        let mut hash_ring = HashRing::with_hashers(Config::default(), XxHash64Hasher::default(), probe.clone()).unwrap();
+        for node in test_nodes() {
+            hash_ring.add_node(node).unwrap();
+        }
+        hash_ring.set_traffic_split("node2", "node3", 0.5).unwrap();
+
+        // Only the ring and the probe hold the configuration while keys are hashed.
+        *probe.config.lock().unwrap() = Some(hash_ring.config());
+        for i in 0..100 {
+            assert!(hash_ring.get_key_id(format!("key-{}", i).as_bytes()).is_some());
+        }
+        let counts = probe.counts.lock().unwrap();
+        assert_eq!(counts.len(), 100);
+        assert!(counts.iter().all(|count| *count == 2));
+    }
+
+    #[test]
+    fn test_key_namespace() {
+        let mut hash_ring = HashRing::new(Config::default()).unwrap();
+        let mut namespaced = HashRing::new(Config {
+            key_namespace: Some("app".to_string()),
+            ..Config::default()
+        })
+        .unwrap();
+        for node in test_nodes() {
+            hash_ring.add_node(node.clone()).unwrap();
+            namespaced.add_node(node).unwrap();
+        }
+
+        // Nodes are placed identically, but keys are distributed independently.
+        assert_eq!(namespaced.virtual_nodes_per_node(), hash_ring.virtual_nodes_per_node());
+        let moved = (0..100)
+            .map(|i| format!("key-{}", i))
+            .filter(|key| namespaced.get_key(key.as_bytes()).unwrap().id() != hash_ring.get_key(key.as_bytes()).unwrap().id())
+            .count();
+        assert!(moved > 0);
+        assert_eq!(namespaced.freeze().get_key(b"some_key").unwrap().id(), namespaced.get_key(b"some_key").unwrap().id());
+    }
+
     #[test]
     fn test_distribute_partitions() {
         type CustomBuildHasher = BuildHasherDefault<std::collections::hash_map::DefaultHasher>;
@@ -643,6 +1399,7 @@ mod tests {
         let config = Config {
             replication_factor: 3,
             partition_count: 10,
+            ..Config::default()
         };
 
         let mut hash_ring : HashRing<CustomBuildHasher>= HashRing::with_hasher(config.clone(), hasher).unwrap();
@@ -668,4 +1425,27 @@ mod tests {
 
         assert_eq!(partitions.read().unwrap().len(), 10);
     }
+
+    #[test]
+    fn test_deferred_distribution() {
+        let config = Config {
+            partition_count: 10,
+            ..Config::default()
+        };
+        let mut hash_ring = HashRing::new(config).unwrap();
+
+        hash_ring.begin_update();
+        hash_ring.begin_update();
+        for node in test_nodes() {
+            hash_ring.add_node(node).unwrap();
+        }
+        assert!(hash_ring.get_key(b"some_key").is_some());
+        assert_eq!(hash_ring.partitions.wait_read().len(), 0);
+
+        assert!(!hash_ring.commit());
+        assert!(hash_ring.is_updating());
+        assert!(hash_ring.commit());
+        assert!(!hash_ring.is_updating());
+        assert_eq!(hash_ring.partitions.wait_read().len(), 10);
+    }
 }
@@ -0,0 +1,535 @@
+//! Load feedback for partitions.
+//!
+//! Callers report traffic per key or per partition, and the ring keeps a running
+//! counter per partition. The counters are used to find hot partitions and to move
+//! them away from overloaded nodes.
+//!
+//! The keys of a partition are spread over the whole ring, so its load is charged to the
+//! nodes that served it: an access to a key is charged to the node the key was routed to,
+//! and load recorded for the partition as a whole is divided between the nodes by their
+//! share of the keyspace. A rebalance moves a partition by routing the lookups of all of
+//! its keys to the new owner, which is then charged with all of its load, until the next
+//! membership change recomputes the partition table.
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::hash::BuildHasher;
+use std::ops::Deref;
+use std::sync::Arc;
+
+use crate::trace::OpTimer;
+use crate::local::LocalNode;
+use crate::{util, HashRing, Node};
+
+/// The partitions moved by a rebalance, with the first virtual node of their new owner
+/// and the ID of the owner. A move ends once that virtual node is removed or taken over.
+pub(crate) type MovedPartitions<'a> = HashMap<usize, (u64, &'a str)>;
+
+/// Returns the first virtual node of the owner of a moved partition, unless the move ended.
+pub(crate) fn moved_point<'a, N>(moved: &MovedPartitions<'a>, sorted_set: &BTreeMap<u64, N>, part_id: usize) -> Option<u64>
+where
+    N: Deref,
+    N::Target: LocalNode<'a>,
+{
+    let (point, id) = moved.get(&part_id)?;
+    sorted_set.get(point).filter(|node| node.id() == *id).map(|_| *point)
+}
+
+/// The load recorded for a partition.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct PartitionLoad {
+    /// The load recorded in total.
+    pub(crate) total: u64,
+    /// The part of the total recorded for keys, by the ID of the node that served them.
+    served: HashMap<String, u64>,
+}
+
+impl PartitionLoad {
+    /// Divides the load between the two children of a split partition.
+    pub(crate) fn halve(self) -> (PartitionLoad, PartitionLoad) {
+        let left = PartitionLoad {
+            total: self.total / 2,
+            served: self.served.iter().map(|(id, l)| (id.clone(), l / 2)).collect(),
+        };
+        let right = PartitionLoad {
+            total: self.total - self.total / 2,
+            served: self.served.into_iter().map(|(id, l)| (id, l - l / 2)).collect(),
+        };
+        (left, right)
+    }
+
+    /// Adds the load of a sibling, for the parent of two merged partitions.
+    pub(crate) fn merge(mut self, other: PartitionLoad) -> PartitionLoad {
+        self.total = self.total.saturating_add(other.total);
+        for (id, l) in other.served {
+            let served = self.served.entry(id).or_insert(0);
+            *served = served.saturating_add(l);
+        }
+        self
+    }
+}
+
+/// Returns the share of the keyspace of every node with virtual nodes, sorted by ID.
+fn keyspace_shares<'a>(sorted_set: &BTreeMap<u64, Arc<dyn Node<'a> + 'a>>) -> Vec<(&'a str, f64)> {
+    let mut shares: HashMap<&'a str, f64> = HashMap::new();
+    if sorted_set.len() == 1 {
+        shares.extend(sorted_set.values().map(|node| (node.id(), 1.0)));
+    } else if let Some(last) = sorted_set.keys().next_back() {
+        let mut previous = *last;
+        for (point, node) in sorted_set.iter() {
+            *shares.entry(node.id()).or_insert(0.0) += util::arc_fraction(util::arc_length(previous, *point));
+            previous = *point;
+        }
+    }
+    let mut shares: Vec<(&'a str, f64)> = shares.into_iter().collect();
+    shares.sort_by(|a, b| a.0.cmp(b.0));
+    shares
+}
+
+/// Divides `amount` in proportion to `shares`, handing the rounding remainder to the
+/// largest fractions so the parts add up to `amount`.
+fn apportion<'s>(amount: u64, shares: &[(&'s str, f64)]) -> Vec<(&'s str, u64)> {
+    let exact: Vec<f64> = shares.iter().map(|(_, share)| amount as f64 * share).collect();
+    let mut parts: Vec<(&'s str, u64)> = shares.iter().zip(&exact).map(|((id, _), part)| (*id, *part as u64)).collect();
+    let assigned: u64 = parts.iter().map(|(_, part)| part).sum();
+    let mut by_remainder: Vec<usize> = (0..parts.len()).collect();
+    by_remainder.sort_by(|a, b| exact[*b].fract().total_cmp(&exact[*a].fract()).then(a.cmp(b)));
+    let remainder = amount.saturating_sub(assigned).min(parts.len() as u64) as usize;
+    for idx in by_remainder.into_iter().take(remainder) {
+        parts[idx].1 += 1;
+    }
+    parts
+}
+
+/// A partition that was reassigned by a rebalance.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PartitionMove {
+    pub partition: usize,
+    pub from: String,
+    pub to: String,
+    pub load: u64,
+}
+
+impl<'a, H, K> HashRing<'a, H, K>
+where
+    H: BuildHasher,
+    K: BuildHasher,
+{
+    /// Records a single access to the given key.
+    ///
+    /// The access is accounted to the partition the key falls into, and charged to the
+    /// node the key is routed to. Recording load never moves partitions: call
+    /// `rebalance_if_skewed` or `rebalance`, or spawn a background rebalancer.
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - The key that was accessed.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use std::sync::Arc;
+    /// use hashring::{HashRing, Config, Node};
+    ///
+    /// #[derive(Debug)]
+    /// struct MyNode<'a> {
+    ///     name: &'a str,
+    /// }
+    ///
+    /// impl<'a> Node<'a> for MyNode<'a> {
+    ///     fn id(&self) -> &'a str {
+    ///         self.name
+    ///     }
+    /// }
+    ///
+    /// let mut hash_ring = HashRing::new(Config::default()).unwrap();
+    /// hash_ring.add_node(Arc::new(MyNode { name: "node1" })).unwrap();
+    ///
+    /// hash_ring.record_access(b"some_key");
+    /// for (part_id, load) in hash_ring.hottest_partitions(1) {
+    ///     println!("Partition {} has load {}", part_id, load);
+    /// }
+    /// ```
+    pub fn record_access(&self, key: &[u8]) {
+        let part_id = self.get_partition(key);
+        self.record_load(part_id, 1, self.get_key_id(key));
+    }
+
+    /// Adds `weight` to the load counter of a partition.
+    ///
+    /// Since the keys of the partition are spread over the ring, the load is charged to
+    /// the nodes by their share of the keyspace, or to the new owner of a moved partition.
+    /// Prefer `record_access` when the key is known.
+    ///
+    /// # Arguments
+    ///
+    /// * `part_id` - The ID of the partition.
+    /// * `weight` - The amount of load to account to the partition.
+    pub fn record_partition_load(&self, part_id: usize, weight: u64) {
+        self.record_load(part_id, weight, None);
+    }
+
+    fn record_load(&self, part_id: usize, weight: u64, served_by: Option<&str>) {
+        if !self.has_partition(part_id) {
+            return;
+        }
+
+        let mut load = self.partition_load.wait_write();
+        let counter = load.entry(part_id).or_default();
+        counter.total = counter.total.saturating_add(weight);
+        if let Some(id) = served_by {
+            let served = counter.served.entry(id.to_string()).or_insert(0);
+            *served = served.saturating_add(weight);
+        }
+    }
+
+    /// Returns the `n` partitions with the highest recorded load.
+    ///
+    /// # Arguments
+    ///
+    /// * `n` - The maximum number of partitions to return.
+    ///
+    /// # Returns
+    ///
+    /// * `Vec<(usize, u64)>` - Partition IDs and their load, hottest first.
+    pub fn hottest_partitions(&self, n: usize) -> Vec<(usize, u64)> {
+        let load = self.partition_load.wait_read();
+        let mut hottest: Vec<(usize, u64)> = load.iter().map(|(p, l)| (*p, l.total)).collect();
+        hottest.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        hottest.truncate(n);
+        hottest
+    }
+
+    /// Returns the recorded load aggregated per node, charged to the nodes that served it.
+    ///
+    /// # Returns
+    ///
+    /// * `HashMap<String, u64>` - A mapping of node IDs to their load. Nodes without load map to `0`.
+    pub fn node_loads(&self) -> HashMap<String, u64> {
+        let mut node_loads: HashMap<String, u64> = self.nodes.wait_read().keys().map(|id| (id.clone(), 0)).collect();
+        for charged in self.charged_loads().values() {
+            for (id, l) in charged {
+                *node_loads.entry(id.clone()).or_insert(0) += *l;
+            }
+        }
+        node_loads
+    }
+
+    /// Returns the load of every loaded partition, by the ID of the node it is charged to.
+    fn charged_loads(&self) -> HashMap<usize, HashMap<String, u64>> {
+        let nodes = self.nodes.wait_read();
+        let sorted_set = self.sorted_nodes_hash_set.wait_read();
+        let routes = self.routes.wait_read();
+        let load = self.partition_load.wait_read();
+        let shares = keyspace_shares(&sorted_set);
+
+        let mut charged_loads = HashMap::new();
+        for (part_id, l) in load.iter() {
+            let mut charged: HashMap<String, u64> = HashMap::new();
+            if moved_point(&routes.moved, &sorted_set, *part_id).is_some() {
+                charged.insert(routes.moved[part_id].1.to_string(), l.total);
+            } else {
+                // Load served by nodes that have left is spread like load recorded without a key.
+                let mut spread = l.total;
+                for (id, served) in l.served.iter().filter(|(id, _)| nodes.contains_key(*id)) {
+                    charged.insert(id.clone(), *served);
+                    spread = spread.saturating_sub(*served);
+                }
+                for (id, part) in apportion(spread, &shares) {
+                    *charged.entry(id.to_string()).or_insert(0) += part;
+                }
+            }
+            charged.retain(|_, l| *l > 0);
+            charged_loads.insert(*part_id, charged);
+        }
+        charged_loads
+    }
+
+    /// Returns the ratio between the most loaded node and the mean node load.
+    ///
+    /// A perfectly balanced ring has a skew of `1.0`, which is also returned
+    /// when no load has been recorded yet.
+    pub fn load_skew(&self) -> f64 {
+        let node_loads = self.node_loads();
+        let total: u64 = node_loads.values().sum();
+        if node_loads.is_empty() || total == 0 {
+            return 1.0;
+        }
+
+        let mean = total as f64 / node_loads.len() as f64;
+        let max = node_loads.values().copied().max().unwrap_or(0);
+        max as f64 / mean
+    }
+
+    /// Clears every recorded load counter.
+    pub fn reset_load(&self) {
+        self.partition_load.wait_write().clear();
+    }
+
+    /// Moves hot partitions from the most loaded node to the least loaded one.
+    ///
+    /// Moving a partition routes the lookups of all of its keys to the new owner, taking
+    /// their load off every node that served them. A partition is only moved when doing
+    /// so lowers the load of the busiest node, so repeated calls converge instead of
+    /// bouncing partitions back and forth. Nodes without capacity headroom don't receive
+    /// partitions. With a `CostModel` set, the move relieving the most load per unit of
+    /// cost is preferred, and nodes don't receive more than their ingest limit.
+    /// Moves are overridden by the next membership change, which recomputes the
+    /// partition assignment from scratch.
+    ///
+    /// # Arguments
+    ///
+    /// * `max_moves` - The maximum number of partitions to move.
+    ///
+    /// # Returns
+    ///
+    /// * `Vec<PartitionMove>` - The partitions that were moved, in order.
+    pub fn rebalance(&mut self, max_moves: usize) -> Vec<PartitionMove> {
+        let _events = self.dispatch_events();
+        let timer = OpTimer::start();
+        let moves = self.plan_rebalance(max_moves);
+        if moves.is_empty() {
+            return moves;
+        }
+
+        let nodes = self.nodes.wait_read();
+        let sorted_set = self.sorted_nodes_hash_set.wait_read();
+        let mut partitions = self.partitions.wait_write();
+        for partition_move in &moves {
+            if let Some(node) = nodes.get(&partition_move.to) {
+                self.move_partition(&sorted_set, &mut partitions, partition_move.partition, node.clone());
+            }
+        }
+        self.bump_version();
+        self.log_changes("rebalance", &timer, None, &partitions);
+        moves
+    }
+
+    /// Rebalances the ring when the node load skew exceeds `rebalance_skew_threshold`.
+    ///
+    /// Nothing is moved when no threshold is configured. Callers decide when the skew is
+    /// checked, for instance after a batch of accesses, off the lookup path.
+    ///
+    /// # Arguments
+    ///
+    /// * `max_moves` - The maximum number of partitions to move.
+    ///
+    /// # Returns
+    ///
+    /// * `Vec<PartitionMove>` - The partitions that were moved, in order.
+    pub fn rebalance_if_skewed(&mut self, max_moves: usize) -> Vec<PartitionMove> {
+        match self.config().rebalance_skew_threshold {
+            Some(threshold) if self.load_skew() > threshold => self.rebalance(max_moves),
+            _ => Vec::new(),
+        }
+    }
+
+    /// Returns the moves `rebalance` would make, without moving any partition.
+    ///
+    /// Callers migrating the data before the owner changes, like the background
+    /// rebalancer with a `DataMover`, hand every move over through `begin_migration`.
+    pub fn plan_rebalance(&self, max_moves: usize) -> Vec<PartitionMove> {
+        let mut charged_loads = self.charged_loads();
+        let mut node_loads: HashMap<String, u64> = self.nodes.wait_read().keys().map(|id| (id.clone(), 0)).collect();
+        for charged in charged_loads.values() {
+            for (id, l) in charged {
+                *node_loads.entry(id.clone()).or_insert(0) += *l;
+            }
+        }
+        // Partitions can only be routed to nodes with virtual nodes.
+        let placed: HashSet<String> = self.sorted_nodes_hash_set.wait_read().values().map(|node| node.id().to_string()).collect();
+        let full = self.full_nodes();
+        let cost_model = self.current_cost_model();
+        let mut ingested: HashMap<String, f64> = HashMap::new();
+        let mut moves = Vec::new();
+
+        while moves.len() < max_moves {
+            let (from, from_load) = match node_loads.iter().max_by(|a, b| a.1.cmp(b.1).then(b.0.cmp(a.0))) {
+                Some((from, from_load)) => (from.clone(), *from_load),
+                None => break,
+            };
+            let mut targets: Vec<(&String, u64)> = node_loads
+                .iter()
+                .filter(|(id, _)| **id != from && !full.contains(*id) && placed.contains(*id))
+                .map(|(id, l)| (id, *l))
+                .collect();
+            targets.sort_by(|a, b| a.1.cmp(&b.1).then(a.0.cmp(b.0)));
+
+            // The idlest target that can take a partition, and the partition relieving the
+            // most load of the busiest node per unit of cost. The target takes over the
+            // load of the partition charged to the other nodes too.
+            let best = targets.into_iter().find_map(|(to, to_load)| {
+                let budget = cost_model.ingest_limit(to).map(|limit| limit - ingested.get(to).copied().unwrap_or(0.0));
+                charged_loads
+                    .iter()
+                    .filter_map(|(part_id, charged)| {
+                        let relief = charged.get(&from).copied().unwrap_or(0);
+                        let total: u64 = charged.values().sum();
+                        let gain = total - charged.get(to).copied().unwrap_or(0);
+                        (relief > 0 && to_load + gain < from_load).then_some((*part_id, relief, total))
+                    })
+                    .map(|(part_id, relief, total)| (part_id, relief, total, cost_model.move_cost(part_id, &from, to).max(f64::MIN_POSITIVE)))
+                    .filter(|(_, _, _, cost)| !budget.is_some_and(|budget| *cost > budget))
+                    .max_by(|a, b| {
+                        let (ratio_a, ratio_b) = (a.1 as f64 / a.3, b.1 as f64 / b.3);
+                        ratio_a.total_cmp(&ratio_b).then(a.1.cmp(&b.1)).then(b.0.cmp(&a.0))
+                    })
+                    .map(|(part_id, _, total, cost)| (to.clone(), part_id, total, cost))
+            });
+
+            let (to, part_id, part_load, cost) = match best {
+                Some(best) => best,
+                None => break,
+            };
+            *ingested.entry(to.clone()).or_insert(0.0) += cost;
+
+            let charged = charged_loads.insert(part_id, HashMap::from([(to.clone(), part_load)])).unwrap_or_default();
+            for (id, l) in charged {
+                if let Some(node_load) = node_loads.get_mut(&id) {
+                    *node_load -= l;
+                }
+            }
+            *node_loads.entry(to.clone()).or_insert(0) += part_load;
+
+            moves.push(PartitionMove {
+                partition: part_id,
+                from,
+                to,
+                load: part_load,
+            });
+        }
+        moves
+    }
+
+    /// Assigns a partition to a node and routes the lookups of its keys to it.
+    ///
+    /// The node must have virtual nodes for lookups to reach it; otherwise only the
+    /// partition table changes.
+    pub(crate) fn move_partition(
+        &self,
+        sorted_set: &BTreeMap<u64, Arc<dyn Node<'a> + 'a>>,
+        partitions: &mut HashMap<usize, Arc<dyn Node<'a> + 'a>>,
+        part_id: usize,
+        node: Arc<dyn Node<'a> + 'a>,
+    ) {
+        self.routes.wait_write().move_partition(sorted_set, part_id, node.id());
+        partitions.insert(part_id, node);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+    use std::sync::Arc;
+
+    use crate::tests::{access, key_on, TestNode};
+    use crate::{Config, HashRing};
+
+    fn ring(config: Config) -> HashRing<'static> {
+        let mut hash_ring = HashRing::new(config).unwrap();
+        for name in ["node1", "node2", "node3"] {
+            hash_ring
+                .add_node(Arc::new(TestNode {
+                    ip_addr: "127.0.0.1".to_string(),
+                    name,
+                }))
+                .unwrap();
+        }
+        hash_ring
+    }
+
+    #[test]
+    fn test_hottest_partitions() {
+        let hash_ring = ring(Config::default());
+
+        hash_ring.record_partition_load(3, 10);
+        hash_ring.record_partition_load(7, 30);
+        hash_ring.record_partition_load(5, 20);
+        hash_ring.record_access(b"some_key");
+
+        let hottest = hash_ring.hottest_partitions(2);
+        assert_eq!(hottest, vec![(7, 30), (5, 20)]);
+        assert_eq!(hash_ring.node_loads().values().sum::<u64>(), 61);
+    }
+
+    #[test]
+    fn test_rebalance_moves_hot_partition() {
+        let mut hash_ring = ring(Config::default());
+        let (hot, part_id) = key_on(&hash_ring, "node1", &[]);
+        let (warm, _) = key_on(&hash_ring, "node1", &[part_id]);
+        access(&hash_ring, &hot, 100);
+        access(&hash_ring, &warm, 10);
+        assert_eq!(hash_ring.node_loads()["node1"], 110);
+        assert!(hash_ring.load_skew() > 1.0);
+
+        let before = hash_ring.clone();
+        let moves = hash_ring.rebalance(5);
+        assert_eq!(moves.len(), 1);
+        assert_eq!((moves[0].partition, moves[0].from.as_str(), moves[0].load), (part_id, "node1", 100));
+        assert_eq!(hash_ring.get_partition_owner(part_id).unwrap().id(), moves[0].to);
+        assert_eq!(hash_ring.node_loads()[&moves[0].to], 100);
+
+        // Every key of the partition follows it, spread over its virtual nodes, and no other key moves.
+        let mut starts = HashSet::new();
+        for i in 0..1000 {
+            let key = format!("key-{}", i).into_bytes();
+            let owner = hash_ring.get_key_id(&key).unwrap();
+            if hash_ring.get_partition(&key) == part_id {
+                let hashed_key = hash_ring.hash_key(&key).unwrap();
+                starts.insert(hash_ring.routes.wait_read().point(hash_ring.config().partition_count, &hash_ring.sorted_nodes_hash_set.wait_read(), hashed_key));
+                assert_eq!(owner, moves[0].to);
+                assert_eq!(hash_ring.get_key(&key).unwrap().id(), owner);
+                assert_eq!(hash_ring.get_preference_list_n(&key, 1)[0].id(), owner);
+                assert_eq!(hash_ring.freeze().get_key(&key).unwrap().id(), owner);
+            } else {
+                assert_eq!(owner, before.get_key_id(&key).unwrap());
+            }
+        }
+        assert!(starts.len() > 1);
+
+        // A membership change recomputes the partition table and ends the move.
+        hash_ring.remove_node("node3").unwrap();
+        assert!(hash_ring.routes.wait_read().moved.is_empty());
+        hash_ring.check_invariants().unwrap();
+    }
+
+    #[test]
+    fn test_rebalance_if_skewed() {
+        let config = Config {
+            rebalance_skew_threshold: Some(1.5),
+            ..Config::default()
+        };
+        let mut hash_ring = ring(config);
+        let (hot, part_id) = key_on(&hash_ring, "node1", &[]);
+        let (other, other_id) = key_on(&hash_ring, "node1", &[part_id]);
+
+        // Recording load never moves partitions by itself.
+        access(&hash_ring, &hot, 50);
+        access(&hash_ring, &other, 50);
+        assert!(hash_ring.load_skew() > 1.5);
+        assert_eq!(hash_ring.get_key_id(&hot), Some("node1"));
+        assert_eq!(hash_ring.get_key_id(&other), Some("node1"));
+        assert!(hash_ring.routes.wait_read().moved.is_empty());
+
+        let moves = hash_ring.rebalance_if_skewed(1);
+        assert_eq!(moves.len(), 1);
+        let owners = [hash_ring.get_key_id(&hot).unwrap(), hash_ring.get_key_id(&other).unwrap()];
+        assert_ne!(owners[0], owners[1]);
+        let moved = if owners[0] == "node1" { other_id } else { part_id };
+        assert_eq!(moves[0].partition, moved);
+        assert_ne!(hash_ring.get_partition_owner(moved).unwrap().id(), "node1");
+
+        // Nothing moves without a threshold.
+        let mut hash_ring = ring(Config::default());
+        access(&hash_ring, &hot, 50);
+        assert!(hash_ring.rebalance_if_skewed(1).is_empty());
+    }
+
+    #[test]
+    fn test_invalid_skew_threshold() {
+        let config = Config {
+            rebalance_skew_threshold: Some(0.5),
+            ..Config::default()
+        };
+        assert!(HashRing::new(config).is_err());
+    }
+}
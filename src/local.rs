@@ -0,0 +1,450 @@
+//! A ring for single-threaded use.
+//!
+//! A `HashRing` keeps its state behind `Arc`s and locks so that handles to it can be
+//! shared between threads. CLI tools, WASM modules and simulations only ever use a ring from
+//! one thread and pay for that synchronization for nothing. A `LocalHashRing` owns its
+//! state, holds its nodes in `Rc`s and is neither `Send` nor `Sync`.
+//!
+//! It places virtual nodes and partitions, and routes keys through split partitions,
+//! reassigned partitions and traffic splits, with the same code as a `HashRing`, so both
+//! route every key to the same node given the same configuration, hashers and changes.
+//! Features built on the shared state of a `HashRing`, such as events, history, drains
+//! or load tracking, are left out. Its nodes implement `LocalNode`, which unlike `Node` doesn't require
+//! `Send + Sync + Debug`, so they can wrap client handles that aren't thread-safe.
+//! Every `Node` is a `LocalNode` too.
+
+use std::collections::{BTreeMap, HashMap};
+use std::error::Error;
+use std::fmt::{self, Debug};
+use std::hash::BuildHasher;
+use std::rc::Rc;
+
+use crate::collision::{place_vnodes, remove_vnodes, vnode_hash};
+use crate::routing::{self, Routes};
+use crate::{split, util, Config, Node, TrafficSplit, XxHash64Hasher};
+
+/// A node of a `LocalHashRing`, without the `Send + Sync + Debug` bounds of `Node`.
+///
+/// Every `Node` is a `LocalNode`. Implement it directly for nodes that can't be shared
+/// between threads, such as nodes holding an `Rc` to a client.
+pub trait LocalNode<'a> {
+    fn id(&self) -> &'a str;
+}
+
+impl<'a, T: Node<'a> + ?Sized> LocalNode<'a> for T {
+    fn id(&self) -> &'a str {
+        Node::id(self)
+    }
+}
+
+/// Without a `Debug` bound, local nodes are shown by their ID.
+impl<'a> Debug for dyn LocalNode<'a> + 'a {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt.debug_tuple("Node").field(&self.id()).finish()
+    }
+}
+
+/// A consistent hash ring without synchronization, for use from a single thread.
+#[derive(Debug, Clone)]
+pub struct LocalHashRing<'a, H = XxHash64Hasher, K = H> {
+    nodes: HashMap<String, Rc<dyn LocalNode<'a> + 'a>>,
+    sorted_nodes_hash_set: BTreeMap<u64, Rc<dyn LocalNode<'a> + 'a>>,
+    partitions: HashMap<usize, Rc<dyn LocalNode<'a> + 'a>>,
+    routes: Routes<'a>,
+    version: u64,
+    config: Config,
+    node_hasher: H,
+    key_hasher: K,
+}
+
+impl<'a> LocalHashRing<'a> {
+    /// Creates a new `LocalHashRing` with the given configuration and the default hashers.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use std::rc::Rc;
+    /// use hashring::{Config, LocalHashRing, Node};
+    ///
+    /// #[derive(Debug)]
+    /// struct MyNode<'a> {
+    ///     name: &'a str,
+    /// }
+    ///
+    /// impl<'a> Node<'a> for MyNode<'a> {
+    ///     fn id(&self) -> &'a str {
+    ///         self.name
+    ///     }
+    /// }
+    ///
+    /// let mut hash_ring = LocalHashRing::new(Config::default()).unwrap();
+    /// hash_ring.add_node(Rc::new(MyNode { name: "node1" })).unwrap();
+    /// hash_ring.add_node(Rc::new(MyNode { name: "node2" })).unwrap();
+    ///
+    /// let node = hash_ring.get_key(b"some_key").unwrap();
+    /// println!("some_key is on {}", node.id());
+    /// ```
+    pub fn new(config: Config) -> Result<LocalHashRing<'a>, Box<dyn Error>> {
+        LocalHashRing::with_hashers(config, XxHash64Hasher::default(), XxHash64Hasher::default())
+    }
+}
+
+impl<'a, H, K> LocalHashRing<'a, H, K>
+where
+    H: BuildHasher,
+    K: BuildHasher,
+{
+    /// Creates a new `LocalHashRing` hashing nodes and partitions with `node_hasher`
+    /// and keys with `key_hasher`.
+    pub fn with_hashers(config: Config, node_hasher: H, key_hasher: K) -> Result<LocalHashRing<'a, H, K>, Box<dyn Error>> {
+        config.validate()?;
+        Ok(LocalHashRing {
+            nodes: HashMap::new(),
+            sorted_nodes_hash_set: BTreeMap::new(),
+            partitions: HashMap::new(),
+            routes: Routes::default(),
+            version: 0,
+            config,
+            node_hasher,
+            key_hasher,
+        })
+    }
+
+    /// Adds a node to the ring.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Rc<dyn LocalNode<'a> + 'a>, Box<dyn Error>>` - The added node.
+    pub fn add_node(&mut self, node: Rc<dyn LocalNode<'a> + 'a>) -> Result<Rc<dyn LocalNode<'a> + 'a>, Box<dyn Error>> {
+        self.config.node_id_policy.validate(node.id())?;
+        if self.nodes.contains_key(node.id()) {
+            return Err("node already exist".into());
+        }
+        let (node_hasher, encoding) = (&self.node_hasher, self.config.format_version.vnode_encoding());
+        place_vnodes(
+            &mut self.sorted_nodes_hash_set,
+            &node,
+            0..self.config.replication_factor,
+            self.config.collision_policy,
+            |replica, salt| vnode_hash(node_hasher, encoding, node.id(), replica, salt),
+        )?;
+        self.nodes.insert(node.id().to_string(), node.clone());
+        self.distribute_partitions();
+        Ok(node)
+    }
+
+    /// Removes the node with the given ID from the ring, along with the traffic splits it
+    /// takes part in.
+    pub fn remove_node(&mut self, id: &str) -> Result<(), Box<dyn Error>> {
+        if self.nodes.remove(id).is_none() {
+            return Err("node not found".into());
+        }
+        let (node_hasher, encoding) = (&self.node_hasher, self.config.format_version.vnode_encoding());
+        remove_vnodes(&mut self.sorted_nodes_hash_set, id, 0..self.config.replication_factor, |replica, salt| {
+            vnode_hash(node_hasher, encoding, id, replica, salt)
+        });
+        self.routes.forget_traffic_splits(id);
+        self.distribute_partitions();
+        Ok(())
+    }
+
+    /// Returns a counter that increases with every change of membership or routing.
+    pub fn version(&self) -> u64 {
+        self.version
+    }
+
+    /// Returns the configuration of the ring.
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    /// Assigns every partition to the node following it on the ring. Partitions assigned
+    /// to other nodes go back to being routed by the ring.
+    fn distribute_partitions(&mut self) {
+        let mut partitions = HashMap::new();
+        for part_id in self.routes.leaves(self.config.partition_count) {
+            if let Some(node) = routing::partition_owner(&self.node_hasher, &self.sorted_nodes_hash_set, part_id) {
+                partitions.insert(part_id, node.clone());
+            }
+        }
+        self.partitions = partitions;
+        self.routes.moved.clear();
+        self.version += 1;
+    }
+
+    fn hash_key(&self, key: &[u8]) -> u64 {
+        util::hash_key(&self.key_hasher, &self.config, key)
+    }
+
+    /// Returns the node serving a key: its owner, or the canary its owner diverts it to.
+    fn serving_node(&self, key: &[u8]) -> Option<&Rc<dyn LocalNode<'a> + 'a>> {
+        let hashed_key = self.hash_key(key);
+        let owner = self.routes.owner(self.config.partition_count, &self.sorted_nodes_hash_set, hashed_key)?;
+        let canary = self.routes.canary(owner.id(), hashed_key).and_then(|id| self.nodes.get(id));
+        Some(canary.unwrap_or(owner))
+    }
+
+    /// Retrieves the node responsible for the given key.
+    pub fn get_key(&self, key: &[u8]) -> Option<Rc<dyn LocalNode<'a> + 'a>> {
+        self.serving_node(key).cloned()
+    }
+
+    /// Retrieves the ID of the node responsible for the given key.
+    pub fn get_key_id(&self, key: &[u8]) -> Option<&'a str> {
+        self.serving_node(key).map(|node| node.id())
+    }
+
+    /// Retrieves the first node for the given key that isn't in `excluded`.
+    pub fn get_key_excluding(&self, key: &[u8], excluded: &[&str]) -> Option<Rc<dyn LocalNode<'a> + 'a>> {
+        self.routes
+            .walk(self.config.partition_count, &self.sorted_nodes_hash_set, self.hash_key(key))
+            .find(|node| !excluded.contains(&node.id()))
+            .cloned()
+    }
+
+    /// Retrieves up to `replication_factor` distinct nodes responsible for the given key, primary first.
+    pub fn get_preference_list(&self, key: &[u8]) -> Vec<Rc<dyn LocalNode<'a> + 'a>> {
+        self.get_preference_list_n(key, self.config.replication_factor)
+    }
+
+    /// Retrieves up to `n` distinct nodes responsible for the given key, primary first.
+    pub fn get_preference_list_n(&self, key: &[u8], n: usize) -> Vec<Rc<dyn LocalNode<'a> + 'a>> {
+        self.routes.preference_list(self.config.partition_count, &self.sorted_nodes_hash_set, self.hash_key(key), n)
+    }
+
+    /// Returns the partition the given key falls into, in `0..partition_count` unless
+    /// partitions were split.
+    pub fn get_partition(&self, key: &[u8]) -> usize {
+        self.routes.partition(self.config.partition_count, self.hash_key(key))
+    }
+
+    /// Returns the IDs of every partition that currently receives keys, in ascending order.
+    pub fn partition_ids(&self) -> Vec<usize> {
+        self.routes.leaves(self.config.partition_count)
+    }
+
+    /// Splits a partition into two children, like `HashRing::split_partition`.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<(usize, usize), Box<dyn Error>>` - The IDs of the two children.
+    pub fn split_partition(&mut self, part_id: usize) -> Result<(usize, usize), Box<dyn Error>> {
+        if !split::is_leaf(self.config.partition_count, &self.routes.splits, part_id) {
+            return Err(format!("partition {} does not exist", part_id).into());
+        }
+        let (left, right) = split::children(self.config.partition_count, part_id)?;
+        self.routes.splits.insert(part_id);
+        self.routes.moved.remove(&part_id);
+        self.partitions.remove(&part_id);
+        for child in [left, right] {
+            if let Some(node) = routing::partition_owner(&self.node_hasher, &self.sorted_nodes_hash_set, child) {
+                self.partitions.insert(child, node.clone());
+            }
+        }
+        self.version += 1;
+        Ok((left, right))
+    }
+
+    /// Merges two sibling partitions back into their parent, like `HashRing::merge_partitions`.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<usize, Box<dyn Error>>` - The ID of the parent partition.
+    pub fn merge_partitions(&mut self, a: usize, b: usize) -> Result<usize, Box<dyn Error>> {
+        for part_id in [a, b] {
+            if !split::is_leaf(self.config.partition_count, &self.routes.splits, part_id) {
+                return Err(format!("partition {} does not exist", part_id).into());
+            }
+        }
+        let parent = split::parent_of_siblings(self.config.partition_count, a, b)?;
+        self.routes.splits.remove(&parent);
+        for child in [a, b] {
+            self.routes.moved.remove(&child);
+            self.partitions.remove(&child);
+        }
+        if let Some(node) = routing::partition_owner(&self.node_hasher, &self.sorted_nodes_hash_set, parent) {
+            self.partitions.insert(parent, node.clone());
+        }
+        self.version += 1;
+        Ok(parent)
+    }
+
+    /// Assigns a partition to a node and routes the lookups of its keys to it, as a
+    /// rebalance of a `HashRing` moves a partition. The assignment lasts until the next
+    /// membership change.
+    pub fn assign_partition(&mut self, part_id: usize, node_id: &str) -> Result<(), Box<dyn Error>> {
+        let node = self.nodes.get(node_id).ok_or("node not found")?.clone();
+        if !split::is_leaf(self.config.partition_count, &self.routes.splits, part_id) {
+            return Err(format!("partition {} does not exist", part_id).into());
+        }
+        self.routes.move_partition(&self.sorted_nodes_hash_set, part_id, node.id());
+        self.partitions.insert(part_id, node);
+        self.version += 1;
+        Ok(())
+    }
+
+    /// Diverts a fraction of the keys owned by a node to a canary node, like
+    /// `HashRing::set_traffic_split`.
+    pub fn set_traffic_split(&mut self, node_id: &str, canary_id: &str, fraction: f64) -> Result<(), Box<dyn Error>> {
+        if !self.nodes.contains_key(node_id) || !self.nodes.contains_key(canary_id) {
+            return Err("node not found".into());
+        }
+        self.routes.set_traffic_split(node_id, canary_id, fraction)?;
+        self.version += 1;
+        Ok(())
+    }
+
+    /// Sends every key of the node back to it, returning the split that was in place.
+    pub fn clear_traffic_split(&mut self, node_id: &str) -> Option<TrafficSplit> {
+        let split = self.routes.traffic_splits.remove(node_id)?;
+        self.version += 1;
+        Some(split)
+    }
+
+    /// Returns the traffic split of the node, if any.
+    pub fn traffic_split(&self, node_id: &str) -> Option<&TrafficSplit> {
+        self.routes.traffic_splits.get(node_id)
+    }
+
+    /// Retrieves the owner of a partition.
+    pub fn get_partition_owner(&self, part_id: usize) -> Option<Rc<dyn LocalNode<'a> + 'a>> {
+        self.partitions.get(&part_id).cloned()
+    }
+
+    /// Returns the partitions owned by a node, in ascending order.
+    pub fn partitions_of(&self, node_id: &str) -> impl Iterator<Item = usize> {
+        let mut part_ids: Vec<usize> = self.partitions.iter().filter(|(_, node)| node.id() == node_id).map(|(part_id, _)| *part_id).collect();
+        part_ids.sort_unstable();
+        part_ids.into_iter()
+    }
+
+    /// Returns the number of virtual nodes of each node.
+    pub fn virtual_nodes_per_node(&self) -> HashMap<String, usize> {
+        let mut virtual_nodes = HashMap::new();
+        for node in self.sorted_nodes_hash_set.values() {
+            *virtual_nodes.entry(node.id().to_string()).or_insert(0) += 1;
+        }
+        virtual_nodes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::rc::Rc;
+
+    use crate::tests::test_nodes;
+    use crate::{Config, HashRing, LocalHashRing, ProposedChange};
+
+    #[test]
+    fn test_local_ring_routes_like_hash_ring() {
+        let mut hash_ring = HashRing::new(Config::default()).unwrap();
+        let mut local = LocalHashRing::new(Config::default()).unwrap();
+        for node in test_nodes() {
+            hash_ring.add_node(node.clone()).unwrap();
+            local.add_node(Rc::new((*node).clone())).unwrap();
+        }
+        assert!(local.add_node(Rc::new((*test_nodes()[0]).clone())).is_err());
+        hash_ring.remove_node("node2").unwrap();
+        local.remove_node("node2").unwrap();
+        assert!(local.remove_node("node2").is_err());
+        assert_eq!(local.version(), 4);
+
+        assert_eq!(local.virtual_nodes_per_node(), hash_ring.virtual_nodes_per_node());
+        for i in 0..100 {
+            let key = format!("key{}", i);
+            assert_eq!(local.get_key_id(key.as_bytes()), hash_ring.get_key_id(key.as_bytes()));
+            assert_eq!(local.get_partition(key.as_bytes()), hash_ring.get_partition(key.as_bytes()));
+        }
+        for part_id in 0..Config::default().partition_count {
+            assert_eq!(local.get_partition_owner(part_id).unwrap().id(), hash_ring.get_partition_owner(part_id).unwrap().id());
+        }
+        let preference_list: Vec<&str> = local.get_preference_list_n(b"some_key", 2).iter().map(|node| node.id()).collect();
+        assert_eq!(preference_list.len(), 2);
+        assert_eq!(local.get_key_excluding(b"some_key", &[preference_list[0]]).unwrap().id(), preference_list[1]);
+    }
+
+    #[test]
+    fn test_local_ring_agrees_with_hash_ring_on_every_key() {
+        let config = Config {
+            partition_count: 16,
+            ..Config::default()
+        };
+        let mut hash_ring = HashRing::new(config.clone()).unwrap();
+        let mut local = LocalHashRing::new(config).unwrap();
+        for node in test_nodes() {
+            hash_ring.add_node(node.clone()).unwrap();
+            local.add_node(Rc::new((*node).clone())).unwrap();
+        }
+
+        // Split a partition and a child, reassign a partition and divert a node's keys.
+        let (left, _) = hash_ring.split_partition(3).unwrap();
+        assert_eq!(local.split_partition(3).unwrap().0, left);
+        let children = hash_ring.split_partition(left).unwrap();
+        assert_eq!(local.split_partition(left).unwrap(), children);
+        let target = if hash_ring.get_partition_owner(5).unwrap().id() == "node1" { "node2" } else { "node1" };
+        let change = ProposedChange::AssignPartition {
+            partition: 5,
+            node: target.to_string(),
+        };
+        hash_ring.apply_committed(&change, |_| None).unwrap();
+        local.assign_partition(5, target).unwrap();
+        hash_ring.set_traffic_split("node3", "node2", 0.5).unwrap();
+        local.set_traffic_split("node3", "node2", 0.5).unwrap();
+        assert!(local.assign_partition(3, "node1").is_err());
+        assert!(local.set_traffic_split("node3", "node3", 0.5).is_err());
+
+        let keys: Vec<String> = (0..2000).map(|i| format!("key-{}", i)).collect();
+        let agree = |hash_ring: &HashRing<'static>, local: &LocalHashRing<'static>| {
+            assert_eq!(local.partition_ids(), hash_ring.partition_ids());
+            for part_id in hash_ring.partition_ids() {
+                assert_eq!(local.get_partition_owner(part_id).map(|node| node.id()), hash_ring.get_partition_owner(part_id).map(|node| node.id()));
+            }
+            for key in keys.iter().map(|key| key.as_bytes()) {
+                assert_eq!(local.get_key_id(key), hash_ring.get_key_id(key));
+                assert_eq!(local.get_partition(key), hash_ring.get_partition(key));
+                let preference_list: Vec<&str> = local.get_preference_list(key).iter().map(|node| node.id()).collect();
+                let expected: Vec<&str> = hash_ring.get_preference_list_n(key, 2).iter().map(|node| node.id()).collect();
+                assert_eq!(preference_list[..2], expected);
+                assert_eq!(local.get_key_excluding(key, &["node1"]).map(|node| node.id()), hash_ring.get_key_excluding(key, &["node1"]).map(|node| node.id()));
+            }
+        };
+        agree(&hash_ring, &local);
+        // Every key of the reassigned partition goes to its new owner.
+        assert!(keys.iter().filter(|key| local.get_partition(key.as_bytes()) == 5).all(|key| local.get_key_id(key.as_bytes()) == Some(target)));
+
+        hash_ring.merge_partitions(children.0, children.1).unwrap();
+        local.merge_partitions(children.0, children.1).unwrap();
+        // Removing a node clears the traffic splits it takes part in, and only those.
+        hash_ring.set_traffic_split("node2", "node1", 0.5).unwrap();
+        local.set_traffic_split("node2", "node1", 0.5).unwrap();
+        hash_ring.remove_node("node1").unwrap();
+        local.remove_node("node1").unwrap();
+        assert!(local.traffic_split("node2").is_none());
+        assert!(local.traffic_split("node3").is_some());
+        agree(&hash_ring, &local);
+    }
+
+    #[test]
+    fn test_unsync_nodes() {
+        use crate::LocalNode;
+
+        /// Holds an `Rc`, like a handle to a single-threaded client.
+        struct ClientNode {
+            name: &'static str,
+            _client: Rc<()>,
+        }
+
+        impl LocalNode<'static> for ClientNode {
+            fn id(&self) -> &'static str {
+                self.name
+            }
+        }
+
+        let mut local = LocalHashRing::new(Config::default()).unwrap();
+        for name in ["node1", "node2"] {
+            local.add_node(Rc::new(ClientNode { name, _client: Rc::new(()) })).unwrap();
+        }
+        let node = local.get_key(b"some_key").unwrap();
+        assert_eq!(format!("{:?}", node), format!("Node({:?})", node.id()));
+    }
+}
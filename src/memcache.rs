@@ -0,0 +1,379 @@
+//! Routing memcached requests over the ring, behind the `memcache` feature.
+//!
+//! `MemcacheRouter` speaks the memcached text protocol to the owner of each key, with
+//! node IDs being the `host:port` addresses of the servers, as in ketama server lists.
+//! Like ketama clients, it ejects a server from the ring after consecutive I/O failures,
+//! retries the request on the key's new owner, and adds the server back once its retry
+//! delay has passed. Only the text protocol is implemented.
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::hash::BuildHasher;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::sync;
+use crate::{HashRing, Node, PooledRing, XxHash64Hasher};
+
+const DEFAULT_MAX_FAILURES: u32 = 2;
+const DEFAULT_RETRY_AFTER: Duration = Duration::from_secs(30);
+const DEFAULT_MAX_IDLE: usize = 4;
+const MAX_KEY_LENGTH: usize = 250;
+
+/// A connection to one memcached server.
+#[derive(Debug)]
+pub struct MemcacheConn {
+    reader: BufReader<TcpStream>,
+    writer: TcpStream,
+}
+
+impl MemcacheConn {
+    /// Connects to the server at `addr`, applying `timeout` to connecting, reads and writes.
+    pub fn connect(addr: &str, timeout: Duration) -> Result<MemcacheConn, Box<dyn Error>> {
+        let addr = addr
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("{} does not resolve", addr)))?;
+        let stream = TcpStream::connect_timeout(&addr, timeout)?;
+        stream.set_read_timeout(Some(timeout))?;
+        stream.set_write_timeout(Some(timeout))?;
+        stream.set_nodelay(true)?;
+        Ok(MemcacheConn {
+            reader: BufReader::new(stream.try_clone()?),
+            writer: stream,
+        })
+    }
+
+    /// Retrieves the value of a key, or `None` if the server doesn't have it.
+    pub fn get(&mut self, key: &[u8]) -> Result<Option<Vec<u8>>, Box<dyn Error>> {
+        self.send(&[b"get ", key, b"\r\n"])?;
+        let line = self.read_line()?;
+        if line == "END" {
+            return Ok(None);
+        }
+        let length = match line.split(' ').collect::<Vec<_>>()[..] {
+            ["VALUE", _, _, length] | ["VALUE", _, _, length, _] => length.parse::<usize>()?,
+            _ => return Err(reply_error(&line)),
+        };
+        let mut value = vec![0; length + 2];
+        self.reader.read_exact(&mut value)?;
+        value.truncate(length);
+        match self.read_line()?.as_str() {
+            "END" => Ok(Some(value)),
+            line => Err(reply_error(line)),
+        }
+    }
+
+    /// Stores a value under a key, expiring after `exptime` seconds, or never if zero.
+    pub fn set(&mut self, key: &[u8], value: &[u8], exptime: u32) -> Result<(), Box<dyn Error>> {
+        let header = format!(" 0 {} {}\r\n", exptime, value.len());
+        self.send(&[b"set ", key, header.as_bytes(), value, b"\r\n"])?;
+        match self.read_line()?.as_str() {
+            "STORED" => Ok(()),
+            line => Err(reply_error(line)),
+        }
+    }
+
+    /// Deletes a key, returning `false` if the server didn't have it.
+    pub fn delete(&mut self, key: &[u8]) -> Result<bool, Box<dyn Error>> {
+        self.send(&[b"delete ", key, b"\r\n"])?;
+        match self.read_line()?.as_str() {
+            "DELETED" => Ok(true),
+            "NOT_FOUND" => Ok(false),
+            line => Err(reply_error(line)),
+        }
+    }
+
+    fn send(&mut self, parts: &[&[u8]]) -> io::Result<()> {
+        let request: Vec<u8> = parts.concat();
+        self.writer.write_all(&request)
+    }
+
+    fn read_line(&mut self) -> io::Result<String> {
+        let mut line = Vec::new();
+        if self.reader.read_until(b'\n', &mut line)? == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        let line = String::from_utf8_lossy(&line);
+        Ok(line.trim_end_matches(['\r', '\n']).to_string())
+    }
+}
+
+fn reply_error(line: &str) -> Box<dyn Error> {
+    format!("unexpected reply from memcached: {}", line).into()
+}
+
+/// Servers removed from the ring after failing, with the time they may be retried at.
+type Ejected<'a> = Vec<(Arc<dyn Node<'a> + 'a>, Instant)>;
+
+/// A memcached client routing every key to its owner in the ring. It speaks the text
+/// protocol only: servers must accept text commands, and the binary protocol and SASL
+/// authentication, which needs it, are not supported.
+pub struct MemcacheRouter<'a, H = XxHash64Hasher, K = H> {
+    hash_ring: Mutex<HashRing<'a, H, K>>,
+    pooled_ring: PooledRing<'a, MemcacheConn, H, K>,
+    max_failures: u32,
+    retry_after: Duration,
+    failures: Mutex<HashMap<String, u32>>,
+    ejected: Mutex<Ejected<'a>>,
+}
+
+impl<'a, H, K> MemcacheRouter<'a, H, K>
+where
+    H: BuildHasher + Clone,
+    K: BuildHasher + Clone,
+{
+    /// Creates a router over the servers of the ring, whose node IDs are `host:port` addresses.
+    ///
+    /// Servers are ejected after 2 consecutive failures and retried after 30 seconds.
+    ///
+    /// # Arguments
+    ///
+    /// * `hash_ring` - The ring of memcached servers.
+    /// * `timeout` - The timeout of connecting to a server and of every read and write.
+    pub fn new(hash_ring: HashRing<'a, H, K>, timeout: Duration) -> MemcacheRouter<'a, H, K> {
+        let pooled_ring = PooledRing::new(hash_ring.share(), DEFAULT_MAX_IDLE, move |node| MemcacheConn::connect(node.id(), timeout));
+        MemcacheRouter {
+            hash_ring: Mutex::new(hash_ring),
+            pooled_ring,
+            max_failures: DEFAULT_MAX_FAILURES,
+            retry_after: DEFAULT_RETRY_AFTER,
+            failures: Mutex::new(HashMap::new()),
+            ejected: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Sets the number of consecutive failures ejecting a server and how long it stays out of the ring.
+    pub fn with_ejection(mut self, max_failures: u32, retry_after: Duration) -> MemcacheRouter<'a, H, K> {
+        self.max_failures = max_failures.max(1);
+        self.retry_after = retry_after;
+        self
+    }
+
+    /// Retrieves the value of a key from its owner.
+    pub fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>, Box<dyn Error>> {
+        self.route(key, |conn| conn.get(key))
+    }
+
+    /// Stores a value on the owner of the key, expiring after `exptime` seconds, or never if zero.
+    pub fn set(&self, key: &[u8], value: &[u8], exptime: u32) -> Result<(), Box<dyn Error>> {
+        self.route(key, |conn| conn.set(key, value, exptime))
+    }
+
+    /// Deletes a key from its owner, returning `false` if it didn't have it.
+    pub fn delete(&self, key: &[u8]) -> Result<bool, Box<dyn Error>> {
+        self.route(key, |conn| conn.delete(key))
+    }
+
+    /// Returns the IDs of the servers currently ejected from the ring.
+    pub fn ejected(&self) -> Vec<String> {
+        sync::lock(&self.ejected).iter().map(|(node, _)| node.id().to_string()).collect()
+    }
+
+    /// Runs the request on the owner of the key, retrying once if the owner gets ejected.
+    fn route<T>(&self, key: &[u8], request: impl Fn(&mut MemcacheConn) -> Result<T, Box<dyn Error>>) -> Result<T, Box<dyn Error>> {
+        if key.is_empty() || key.len() > MAX_KEY_LENGTH || key.iter().any(|byte| byte.is_ascii_whitespace() || byte.is_ascii_control()) {
+            return Err("invalid memcached key".into());
+        }
+        self.restore_ejected();
+
+        let mut attempts = 0;
+        loop {
+            attempts += 1;
+            let node = self.pooled_ring.ring().get_key(key).ok_or("no servers in the ring")?;
+            let result = self.pooled_ring.get_conn(&node).and_then(|mut conn| {
+                let result = request(&mut conn);
+                if matches!(&result, Err(err) if err.is::<io::Error>()) {
+                    conn.discard();
+                }
+                result
+            });
+            match result {
+                Err(err) if err.is::<io::Error>() => {
+                    if !self.record_failure(&node)? || attempts > 1 {
+                        return Err(err);
+                    }
+                }
+                result => {
+                    sync::lock(&self.failures).remove(node.id());
+                    return result;
+                }
+            }
+        }
+    }
+
+    /// Counts a failure of the server, ejecting it if it failed too often. Returns `true` if it was ejected.
+    fn record_failure(&self, node: &Arc<dyn Node<'a> + 'a>) -> Result<bool, Box<dyn Error>> {
+        let mut failures = sync::lock(&self.failures);
+        let count = failures.entry(node.id().to_string()).or_insert(0);
+        *count += 1;
+        if *count < self.max_failures {
+            return Ok(false);
+        }
+        failures.remove(node.id());
+        drop(failures);
+
+        sync::lock(&self.hash_ring).remove_node(node.id())?;
+        sync::lock(&self.ejected).push((node.clone(), Instant::now() + self.retry_after));
+        Ok(true)
+    }
+
+    /// Adds back the ejected servers whose retry delay has passed. A server that can't
+    /// rejoin, e.g. because it is quarantined for flapping, is retried after another delay.
+    fn restore_ejected(&self) {
+        let mut ejected = sync::lock(&self.ejected);
+        if ejected.is_empty() {
+            return;
+        }
+        let now = Instant::now();
+        let mut hash_ring = sync::lock(&self.hash_ring);
+        ejected.retain_mut(|(node, retry_at)| {
+            if *retry_at > now {
+                return true;
+            }
+            // A server added back by other means is no longer ejected.
+            if hash_ring.nodes.wait_read().contains_key(node.id()) {
+                return false;
+            }
+            match hash_ring.add_node(node.clone()) {
+                Ok(_) => false,
+                Err(_err) => {
+                    #[cfg(feature = "tracing")]
+                    tracing::warn!(server = node.id(), error = %_err, "unable to add back an ejected memcached server");
+                    *retry_at = now + self.retry_after;
+                    true
+                }
+            }
+        });
+    }
+}
+
+impl<'a, H, K> fmt::Debug for MemcacheRouter<'a, H, K>
+where
+    H: fmt::Debug,
+    K: fmt::Debug,
+{
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt.debug_struct("MemcacheRouter")
+            .field("hash_ring", &self.hash_ring)
+            .field("max_failures", &self.max_failures)
+            .field("retry_after", &self.retry_after)
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+    use std::time::Duration;
+
+    use crate::tests::TestNode;
+    use crate::{Config, FlapPolicy, HashRing, MemcacheRouter};
+
+    /// Starts a server answering get, set and delete, returning its address.
+    fn start_server() -> &'static str {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = Box::leak(listener.local_addr().unwrap().to_string().into_boxed_str());
+        let store: Arc<Mutex<HashMap<String, Vec<u8>>>> = Arc::default();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut writer = stream.unwrap();
+                let mut reader = BufReader::new(writer.try_clone().unwrap());
+                let store = store.clone();
+                thread::spawn(move || {
+                    let mut line = String::new();
+                    while reader.read_line(&mut line).unwrap_or(0) > 0 {
+                        let words: Vec<String> = line.split_whitespace().map(String::from).collect();
+                        let reply = match words.iter().map(String::as_str).collect::<Vec<_>>()[..] {
+                            ["get", key] => match store.lock().unwrap().get(key) {
+                                Some(value) => format!("VALUE {} 0 {}\r\n{}\r\nEND\r\n", key, value.len(), String::from_utf8_lossy(value)),
+                                None => "END\r\n".to_string(),
+                            },
+                            ["set", key, _, _, length] => {
+                                let mut value = vec![0; length.parse::<usize>().unwrap() + 2];
+                                reader.read_exact(&mut value).unwrap();
+                                value.truncate(value.len() - 2);
+                                store.lock().unwrap().insert(key.to_string(), value);
+                                "STORED\r\n".to_string()
+                            }
+                            ["delete", key] => match store.lock().unwrap().remove(key) {
+                                Some(_) => "DELETED\r\n".to_string(),
+                                None => "NOT_FOUND\r\n".to_string(),
+                            },
+                            _ => "ERROR\r\n".to_string(),
+                        };
+                        writer.write_all(reply.as_bytes()).unwrap();
+                        line.clear();
+                    }
+                });
+            }
+        });
+        addr
+    }
+
+    #[test]
+    fn test_requests_skip_ejected_servers() {
+        let live = start_server();
+        let dead = {
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            &*Box::leak(listener.local_addr().unwrap().to_string().into_boxed_str())
+        };
+        let mut hash_ring = HashRing::new(Config::default()).unwrap();
+        for name in [live, dead] {
+            hash_ring.add_node(Arc::new(TestNode { ip_addr: name.to_string(), name })).unwrap();
+        }
+        let key = (0..)
+            .map(|i| format!("key-{}", i))
+            .find(|key| hash_ring.get_key(key.as_bytes()).unwrap().id() == dead)
+            .unwrap();
+
+        let router = MemcacheRouter::new(hash_ring, Duration::from_secs(1)).with_ejection(1, Duration::from_secs(60));
+        router.set(b"greeting", b"hello", 0).unwrap();
+        router.set(key.as_bytes(), b"value", 0).unwrap();
+        assert_eq!(router.ejected(), vec![dead.to_string()]);
+        assert_eq!(router.get(key.as_bytes()).unwrap(), Some(b"value".to_vec()));
+        assert_eq!(router.get(b"greeting").unwrap(), Some(b"hello".to_vec()));
+
+        assert!(router.delete(key.as_bytes()).unwrap());
+        assert!(!router.delete(key.as_bytes()).unwrap());
+        assert_eq!(router.get(key.as_bytes()).unwrap(), None);
+        assert!(router.get(b"bad key").is_err());
+    }
+
+    #[test]
+    fn test_refused_rejoin_stays_ejected() {
+        let live = start_server();
+        let dead = {
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            &*Box::leak(listener.local_addr().unwrap().to_string().into_boxed_str())
+        };
+        let mut hash_ring = HashRing::new(Config::default()).unwrap();
+        for name in [live, dead] {
+            hash_ring.add_node(Arc::new(TestNode { ip_addr: name.to_string(), name })).unwrap();
+        }
+        // Ejecting the dead server quarantines it, so adding it back is refused.
+        hash_ring.set_flap_policy(Some(FlapPolicy {
+            window: Duration::from_secs(60),
+            max_changes: 1,
+            quarantine: Duration::from_secs(60),
+        }));
+        let key = (0..)
+            .map(|i| format!("key-{}", i))
+            .find(|key| hash_ring.get_key(key.as_bytes()).unwrap().id() == dead)
+            .unwrap();
+
+        let router = MemcacheRouter::new(hash_ring, Duration::from_secs(1)).with_ejection(1, Duration::from_millis(20));
+        router.set(key.as_bytes(), b"value", 0).unwrap();
+        assert_eq!(router.ejected(), vec![dead.to_string()]);
+        thread::sleep(Duration::from_millis(30));
+        assert_eq!(router.get(key.as_bytes()).unwrap(), Some(b"value".to_vec()));
+        assert_eq!(router.ejected(), vec![dead.to_string()]);
+    }
+}
@@ -0,0 +1,154 @@
+//! Merging the membership of two rings.
+//!
+//! When two clusters are consolidated, `merge` adds the members of another ring to this
+//! one in a single transaction. A node present in both rings is a conflict when the
+//! rings hold different node values for its ID, or give it a different weight, that is a
+//! different number of virtual nodes, as happens while a node is ramping up. The
+//! `MergePolicy` decides which side wins, or rejects the merge.
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::hash::BuildHasher;
+use std::sync::Arc;
+
+use crate::{HashRing, Node};
+
+/// How `merge` resolves a node ID present in both rings with a different node or weight.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MergePolicy {
+    /// Keep the node of this ring.
+    #[default]
+    KeepExisting,
+    /// Replace the node of this ring with the one of the other ring, at full weight.
+    PreferOther,
+    /// Fail the merge, leaving this ring untouched.
+    Reject,
+}
+
+impl<'a, H, K> HashRing<'a, H, K>
+where
+    H: BuildHasher,
+    K: BuildHasher,
+{
+    /// Adds the members of another ring to this one.
+    ///
+    /// Virtual nodes are placed with the configuration of this ring, so the rings don't
+    /// need to share hashers or a replication factor. Every change is applied at once.
+    ///
+    /// # Arguments
+    ///
+    /// * `other` - The ring to take the members of.
+    /// * `policy` - How to resolve IDs present in both rings with a different node or weight.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Vec<String>, Box<dyn Error>>` - The IDs of the nodes added or replaced, sorted.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use std::sync::Arc;
+    /// use hashring::{HashRing, Config, MergePolicy, Node};
+    ///
+    /// #[derive(Debug)]
+    /// struct MyNode<'a> {
+    ///     name: &'a str,
+    /// }
+    ///
+    /// impl<'a> Node<'a> for MyNode<'a> {
+    ///     fn id(&self) -> &'a str {
+    ///         self.name
+    ///     }
+    /// }
+    ///
+    /// let mut east = HashRing::new(Config::default()).unwrap();
+    /// east.add_node(Arc::new(MyNode { name: "node1" })).unwrap();
+    /// let mut west = HashRing::new(Config::default()).unwrap();
+    /// west.add_node(Arc::new(MyNode { name: "node2" })).unwrap();
+    ///
+    /// let added = east.merge(&west, MergePolicy::default()).unwrap();
+    /// assert_eq!(added, ["node2"]);
+    /// assert_eq!(east.virtual_nodes_per_node().len(), 2);
+    /// ```
+    pub fn merge<H2, K2>(&mut self, other: &HashRing<'a, H2, K2>, policy: MergePolicy) -> Result<Vec<String>, Box<dyn Error>>
+    where
+        H2: BuildHasher,
+        K2: BuildHasher,
+    {
+        // Both locks are released before the transaction, in case `other` shares state
+        // with this ring.
+        let theirs: HashMap<String, Arc<dyn Node<'a> + 'a>> = other.nodes.read()?.clone();
+        let their_weights = other.virtual_nodes_per_node();
+        let ours = self.nodes.read()?.clone();
+        let our_weights = self.virtual_nodes_per_node();
+
+        self.transaction(|tx| {
+            let mut changed = Vec::new();
+            for (id, node) in theirs {
+                if !tx.contains(&id) {
+                    tx.add(node)?;
+                    changed.push(id);
+                    continue;
+                }
+                let same_node = ours.get(&id).is_some_and(|ours| Arc::ptr_eq(ours, &node));
+                if same_node && our_weights.get(&id) == their_weights.get(&id) {
+                    continue;
+                }
+                match policy {
+                    MergePolicy::KeepExisting => {}
+                    MergePolicy::PreferOther => {
+                        tx.remove(&id)?;
+                        tx.add(node)?;
+                        changed.push(id);
+                    }
+                    MergePolicy::Reject => return Err(format!("node {} differs between the rings", id).into()),
+                }
+            }
+            changed.sort_unstable();
+            Ok(changed)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use crate::tests::{test_nodes, TestNode};
+    use crate::{Config, HashRing, MergePolicy};
+
+    #[test]
+    fn test_merge_resolves_conflicts() {
+        let nodes = test_nodes();
+        let ring = || {
+            let mut hash_ring = HashRing::new(Config::default()).unwrap();
+            hash_ring.add_node(nodes[0].clone()).unwrap();
+            hash_ring.add_node(nodes[1].clone()).unwrap();
+            hash_ring
+        };
+        let mut ours = ring();
+        let mut theirs = HashRing::new(Config::default()).unwrap();
+        theirs.add_node(nodes[1].clone()).unwrap();
+        theirs.add_node(nodes[2].clone()).unwrap();
+
+        // The same node in both rings is not a conflict.
+        assert_eq!(ring().merge(&theirs, MergePolicy::Reject).unwrap(), ["node3"]);
+
+        let replacement = Arc::new(TestNode {
+            ip_addr: "10.0.0.2".to_string(),
+            name: "node2",
+        });
+        theirs.remove_node("node2").unwrap();
+        theirs.add_node(replacement.clone()).unwrap();
+        let version = ours.version();
+        assert!(ours.merge(&theirs, MergePolicy::Reject).is_err());
+        assert_eq!(ours.version(), version);
+        assert_eq!(ours.virtual_nodes_per_node().len(), 2);
+
+        assert_eq!(ring().merge(&theirs, MergePolicy::KeepExisting).unwrap(), ["node3"]);
+        assert_eq!(ours.merge(&theirs, MergePolicy::PreferOther).unwrap(), ["node2", "node3"]);
+        assert_eq!(ours.virtual_nodes_per_node().len(), 3);
+        theirs.add_node(nodes[0].clone()).unwrap();
+        assert_eq!(ours.snapshot().partitions, theirs.snapshot().partitions);
+    }
+}
@@ -0,0 +1,299 @@
+//! Per-partition migration states.
+//!
+//! Moving a partition takes several steps: the data is copied to the new owner while
+//! the old one keeps serving it, then writes are handed off, and only then does the new
+//! owner take over. Rather than each application keeping its own map of migrations on
+//! top of the ring, the ring tracks a `PartitionState` per partition:
+//!
+//! * `begin_migration` moves a `Stable` partition to `MigratingTo(node)`.
+//! * `finish_copy` moves it to `PendingHandoff(node)` once the data is copied.
+//! * `complete_handoff` assigns the partition to the node and makes it `Stable` again.
+//! * `abort_migration` returns it to `Stable` at any point, leaving its owner unchanged.
+//!
+//! States are kept across membership changes; a handoff to a node that left the ring
+//! fails and has to be aborted.
+//!
+//! Until the handoff, `get_partition_owners` returns the nodes to read a migrating
+//! partition from according to the `ReadPolicy`: the old owner, the new one, or both so
+//! that readers can repair whichever copy is behind.
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::hash::BuildHasher;
+use std::sync::Arc;
+
+use crate::load;
+use crate::trace::OpTimer;
+use crate::{HashRing, Node};
+
+/// Where a partition is in its migration.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub enum PartitionState {
+    /// Served by its owner, with no migration in progress.
+    #[default]
+    Stable,
+    /// Being copied to the node, while its owner keeps serving it.
+    MigratingTo(String),
+    /// Copied to the node, waiting for the handoff of ownership.
+    PendingHandoff(String),
+}
+
+impl PartitionState {
+    /// Returns the node the partition is moving to, if it isn't stable.
+    pub fn destination(&self) -> Option<&str> {
+        match self {
+            PartitionState::Stable => None,
+            PartitionState::MigratingTo(to) | PartitionState::PendingHandoff(to) => Some(to),
+        }
+    }
+}
+
+/// Which nodes serve reads of a partition that isn't stable.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ReadPolicy {
+    /// Read from the current owner until the handoff.
+    #[default]
+    Old,
+    /// Read from the node the partition is moving to.
+    New,
+    /// Read from both, the current owner first.
+    Both,
+}
+
+pub(crate) type PartitionStates = HashMap<usize, PartitionState>;
+
+impl<'a, H, K> HashRing<'a, H, K>
+where
+    H: BuildHasher,
+    K: BuildHasher,
+{
+    /// Starts migrating a partition to another node.
+    ///
+    /// # Arguments
+    ///
+    /// * `part_id` - The partition to migrate, which must be `Stable`.
+    /// * `to` - The ID of the node receiving it, which must be a member not already serving
+    ///   every key of the partition. A partition whose keys are routed by the ring can be
+    ///   migrated to its owner, which then serves all of them.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use std::sync::Arc;
+    /// use hashring::{HashRing, Config, Node, PartitionState};
+    ///
+    /// #[derive(Debug)]
+    /// struct MyNode<'a> {
+    ///     name: &'a str,
+    /// }
+    ///
+    /// impl<'a> Node<'a> for MyNode<'a> {
+    ///     fn id(&self) -> &'a str {
+    ///         self.name
+    ///     }
+    /// }
+    ///
+    /// let mut hash_ring = HashRing::new(Config::default()).unwrap();
+    /// hash_ring.add_node(Arc::new(MyNode { name: "node1" })).unwrap();
+    /// hash_ring.add_node(Arc::new(MyNode { name: "node2" })).unwrap();
+    /// let part_id = hash_ring.partitions_of("node1").next().unwrap();
+    ///
+    /// hash_ring.begin_migration(part_id, "node2").unwrap();
+    /// // Copy the data of the partition to node2, then:
+    /// hash_ring.finish_copy(part_id).unwrap();
+    /// assert_eq!(hash_ring.partition_state(part_id), PartitionState::PendingHandoff("node2".to_string()));
+    ///
+    /// hash_ring.complete_handoff(part_id).unwrap();
+    /// assert_eq!(hash_ring.get_partition_owner(part_id).unwrap().id(), "node2");
+    /// assert_eq!(hash_ring.partition_state(part_id), PartitionState::Stable);
+    /// ```
+    pub fn begin_migration(&mut self, part_id: usize, to: &str) -> Result<(), Box<dyn Error>> {
+        let nodes = self.nodes.read()?;
+        let sorted_set = self.sorted_nodes_hash_set.read()?;
+        let partitions = self.partitions.read()?;
+        let owner = partitions.get(&part_id).ok_or_else(|| format!("partition {} not found", part_id))?;
+        if !nodes.contains_key(to) {
+            return Err("node not found".into());
+        }
+        if owner.id() == to && load::moved_point(&self.routes.read()?.moved, &sorted_set, part_id).is_some() {
+            return Err(format!("partition {} is already owned by {}", part_id, to).into());
+        }
+        let mut states = self.partition_states.write()?;
+        if let Some(state) = states.get(&part_id) {
+            return Err(format!("partition {} is not stable: {:?}", part_id, state).into());
+        }
+        states.insert(part_id, PartitionState::MigratingTo(to.to_string()));
+        Ok(())
+    }
+
+    /// Marks the data of a migrating partition as copied, pending the handoff.
+    pub fn finish_copy(&mut self, part_id: usize) -> Result<(), Box<dyn Error>> {
+        let mut states = self.partition_states.write()?;
+        let to = match states.get(&part_id) {
+            Some(PartitionState::MigratingTo(to)) => to.clone(),
+            _ => return Err(format!("partition {} is not migrating", part_id).into()),
+        };
+        states.insert(part_id, PartitionState::PendingHandoff(to));
+        Ok(())
+    }
+
+    /// Hands a partition pending handoff over to its new owner, making it stable.
+    ///
+    /// Lookups of the keys of the partition go to the new owner from then on. Like `rebalance`, the new assignment lasts until the next membership change.
+    pub fn complete_handoff(&mut self, part_id: usize) -> Result<(), Box<dyn Error>> {
+        let _events = self.dispatch_events();
+        let timer = OpTimer::start();
+        let nodes = self.nodes.read()?;
+        let sorted_set = self.sorted_nodes_hash_set.read()?;
+        let mut partitions = self.partitions.write()?;
+        let mut states = self.partition_states.write()?;
+        let to = match states.get(&part_id) {
+            Some(PartitionState::PendingHandoff(to)) => to,
+            _ => return Err(format!("partition {} is not pending handoff", part_id).into()),
+        };
+        let node = nodes.get(to).cloned().ok_or_else(|| format!("node {} left the ring", to))?;
+        states.remove(&part_id);
+        drop(states);
+        self.move_partition(&sorted_set, &mut partitions, part_id, node);
+
+        self.bump_version();
+        self.log_changes("complete_handoff", &timer, None, &partitions);
+        Ok(())
+    }
+
+    /// Cancels the migration of a partition, leaving its owner unchanged.
+    ///
+    /// # Returns
+    ///
+    /// * `PartitionState` - The state the partition was in.
+    pub fn abort_migration(&mut self, part_id: usize) -> PartitionState {
+        self.partition_states.wait_write().remove(&part_id).unwrap_or_default()
+    }
+
+    /// Returns the migration state of a partition.
+    pub fn partition_state(&self, part_id: usize) -> PartitionState {
+        self.partition_states.wait_read().get(&part_id).cloned().unwrap_or_default()
+    }
+
+    /// Sets which nodes `get_partition_owners` returns for partitions that aren't stable.
+    pub fn set_read_policy(&self, policy: ReadPolicy) {
+        *self.read_policy.wait_write() = policy;
+    }
+
+    /// Retrieves the nodes to read a partition from.
+    ///
+    /// A stable partition is read from its owner alone. While it migrates, the nodes
+    /// depend on the `ReadPolicy`; a destination that left the ring is skipped.
+    ///
+    /// # Arguments
+    ///
+    /// * `part_id` - The ID of the partition.
+    ///
+    /// # Returns
+    ///
+    /// * `Vec<Arc<dyn Node<'a> + 'a>>` - The nodes to read from, empty if the ring has no nodes.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use std::sync::Arc;
+    /// use hashring::{HashRing, Config, Node, ReadPolicy};
+    ///
+    /// #[derive(Debug)]
+    /// struct MyNode<'a> {
+    ///     name: &'a str,
+    /// }
+    ///
+    /// impl<'a> Node<'a> for MyNode<'a> {
+    ///     fn id(&self) -> &'a str {
+    ///         self.name
+    ///     }
+    /// }
+    ///
+    /// let mut hash_ring = HashRing::new(Config::default()).unwrap();
+    /// hash_ring.add_node(Arc::new(MyNode { name: "node1" })).unwrap();
+    /// hash_ring.add_node(Arc::new(MyNode { name: "node2" })).unwrap();
+    /// let part_id = hash_ring.partitions_of("node1").next().unwrap();
+    ///
+    /// hash_ring.set_read_policy(ReadPolicy::Both);
+    /// hash_ring.begin_migration(part_id, "node2").unwrap();
+    /// let owners: Vec<&str> = hash_ring.get_partition_owners(part_id).iter().map(|node| node.id()).collect();
+    /// assert_eq!(owners, ["node1", "node2"]);
+    /// ```
+    pub fn get_partition_owners(&self, part_id: usize) -> Vec<Arc<dyn Node<'a> + 'a>> {
+        let policy = *self.read_policy.wait_read();
+        let nodes = self.nodes.wait_read();
+        let partitions = self.partitions.wait_read();
+        let states = self.partition_states.wait_read();
+        let owner = partitions.get(&part_id);
+        let destination = states.get(&part_id).and_then(|state| state.destination()).and_then(|to| nodes.get(to));
+        let owners = match (policy, destination) {
+            (_, None) | (ReadPolicy::Old, _) => [owner, None],
+            (ReadPolicy::New, Some(to)) => [Some(to), None],
+            (ReadPolicy::Both, Some(to)) => [owner, Some(to)],
+        };
+        owners.into_iter().flatten().cloned().collect()
+    }
+
+    /// Returns the partitions that aren't stable with their state, by partition.
+    pub fn migrating_partitions(&self) -> Vec<(usize, PartitionState)> {
+        let states = self.partition_states.wait_read();
+        let mut migrating: Vec<(usize, PartitionState)> = states.iter().map(|(part_id, state)| (*part_id, state.clone())).collect();
+        migrating.sort_unstable_by_key(|(part_id, _)| *part_id);
+        migrating
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::tests::test_nodes;
+    use crate::{Config, HashRing, PartitionState, ReadPolicy};
+
+    #[test]
+    fn test_partition_state_transitions() {
+        let mut hash_ring = HashRing::new(Config::default()).unwrap();
+        for node in test_nodes() {
+            hash_ring.add_node(node).unwrap();
+        }
+        let mut owned = hash_ring.partitions_of("node1");
+        let (first, second) = (owned.next().unwrap(), owned.next().unwrap());
+        assert!(hash_ring.begin_migration(first, "node4").is_err());
+        assert!(hash_ring.finish_copy(first).is_err());
+        assert!(hash_ring.complete_handoff(first).is_err());
+
+        hash_ring.begin_migration(first, "node2").unwrap();
+        hash_ring.begin_migration(second, "node3").unwrap();
+        assert!(hash_ring.begin_migration(first, "node3").is_err());
+        assert!(hash_ring.complete_handoff(first).is_err());
+        hash_ring.finish_copy(first).unwrap();
+        hash_ring.finish_copy(second).unwrap();
+        let owners = |part_id| hash_ring.get_partition_owners(part_id).iter().map(|node| node.id()).collect::<Vec<_>>();
+        assert_eq!(owners(first), ["node1"]);
+        hash_ring.set_read_policy(ReadPolicy::New);
+        assert_eq!(owners(first), ["node2"]);
+        hash_ring.set_read_policy(ReadPolicy::Both);
+        assert_eq!(owners(first), ["node1", "node2"]);
+        assert_eq!(owners(owned.next().unwrap()), ["node1"]);
+        assert_eq!(
+            hash_ring.migrating_partitions(),
+            [
+                (first, PartitionState::PendingHandoff("node2".to_string())),
+                (second, PartitionState::PendingHandoff("node3".to_string())),
+            ]
+        );
+
+        let version = hash_ring.version();
+        hash_ring.complete_handoff(first).unwrap();
+        assert_eq!(hash_ring.version(), version + 1);
+        assert_eq!(hash_ring.get_partition_owner(first).unwrap().id(), "node2");
+        assert!(hash_ring.begin_migration(first, "node2").is_err());
+
+        // A handoff to a node that left fails until the migration is aborted.
+        hash_ring.remove_node("node3").unwrap();
+        assert!(hash_ring.complete_handoff(second).is_err());
+        assert_eq!(hash_ring.get_partition_owners(second)[0].id(), "node1");
+        assert_eq!(hash_ring.get_partition_owners(second).len(), 1);
+        assert_eq!(hash_ring.abort_migration(second), PartitionState::PendingHandoff("node3".to_string()));
+        assert!(hash_ring.migrating_partitions().is_empty());
+    }
+}
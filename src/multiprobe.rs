@@ -0,0 +1,246 @@
+//! Multi-probe consistent hashing, from "Multi-probe consistent hashing" (Appleton, O'Reilly).
+//!
+//! Every node is placed on the ring once. A key is hashed `probes` times, each probe
+//! finds its clockwise successor, and the key belongs to the node closest to any of
+//! its probes. This balances load like virtual nodes do, but the ring only holds one
+//! entry per node instead of `nodes * replication_factor`.
+
+use std::collections::{BTreeMap, HashSet};
+use std::error::Error;
+use std::hash::{BuildHasher, Hasher};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use crate::sync::RwLock;
+use crate::{Node, Ring, XxHash64Hasher};
+
+/// The number of probes suggested by the paper, giving a peak-to-mean load of about 1.05.
+pub const DEFAULT_PROBE_COUNT: usize = 21;
+
+/// A ring placing keys with multi-probe consistent hashing.
+#[derive(Debug, Clone)]
+pub struct MultiProbeRing<'a, H = XxHash64Hasher> {
+    hasher: H,
+    probes: usize,
+    points: Arc<RwLock<BTreeMap<u64, Arc<dyn Node<'a> + 'a>>>>,
+    version: Arc<AtomicU64>,
+}
+
+impl<'a> MultiProbeRing<'a, XxHash64Hasher> {
+    /// Creates an empty `MultiProbeRing` hashing every key `probes` times.
+    pub fn new(probes: usize) -> Result<MultiProbeRing<'a, XxHash64Hasher>, Box<dyn Error>> {
+        MultiProbeRing::with_hasher(probes, XxHash64Hasher::default())
+    }
+}
+
+impl<'a, H> MultiProbeRing<'a, H>
+where
+    H: BuildHasher,
+{
+    /// Creates an empty `MultiProbeRing` hashing every key `probes` times, with the specified hasher.
+    pub fn with_hasher(probes: usize, hasher: H) -> Result<MultiProbeRing<'a, H>, Box<dyn Error>> {
+        if probes == 0 {
+            return Err("probe count must be greater than 0".into());
+        }
+        Ok(MultiProbeRing {
+            hasher,
+            probes,
+            points: Arc::new(RwLock::new(BTreeMap::new(), &Arc::default())),
+            version: Arc::new(AtomicU64::new(0)),
+        })
+    }
+
+    fn hash_node(&self, id: &str) -> u64 {
+        let mut hasher = self.hasher.build_hasher();
+        hasher.write(id.as_bytes());
+        hasher.finish()
+    }
+
+    fn hash_probe(&self, key: &[u8], probe: usize) -> u64 {
+        let mut hasher = self.hasher.build_hasher();
+        hasher.write(key);
+        hasher.write(&(probe as u64).to_le_bytes());
+        hasher.finish()
+    }
+
+    /// Adds a node to the ring.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use std::sync::Arc;
+    /// use hashring::{MultiProbeRing, Node, DEFAULT_PROBE_COUNT};
+    ///
+    /// #[derive(Debug)]
+    /// struct MyNode<'a> {
+    ///     name: &'a str,
+    /// }
+    ///
+    /// impl<'a> Node<'a> for MyNode<'a> {
+    ///     fn id(&self) -> &'a str {
+    ///         self.name
+    ///     }
+    /// }
+    ///
+    /// let mut ring = MultiProbeRing::new(DEFAULT_PROBE_COUNT).unwrap();
+    /// ring.add_node(Arc::new(MyNode { name: "node1" })).unwrap();
+    /// ring.add_node(Arc::new(MyNode { name: "node2" })).unwrap();
+    ///
+    /// let node = ring.get_key(b"some_key").unwrap();
+    /// println!("some_key belongs to {}", node.id());
+    /// ```
+    pub fn add_node(&mut self, node: Arc<dyn Node<'a> + 'a>) -> Result<Arc<dyn Node<'a> + 'a>, Box<dyn Error>> {
+        let mut points = self.points.wait_write();
+        if points.values().any(|n| n.id() == node.id()) {
+            return Err("node already exist".into());
+        }
+        let hash = self.hash_node(node.id());
+        if let Some(existing) = points.get(&hash) {
+            return Err(format!("node {} collides with {}", node.id(), existing.id()).into());
+        }
+        points.insert(hash, node.clone());
+        self.version.fetch_add(1, Ordering::Release);
+        Ok(node)
+    }
+
+    /// Removes a node from the ring.
+    pub fn remove_node(&mut self, id: &str) -> Result<(), Box<dyn Error>> {
+        let mut points = self.points.wait_write();
+        let hash = self.hash_node(id);
+        match points.get(&hash) {
+            Some(node) if node.id() == id => {}
+            _ => return Err("node not found".into()),
+        }
+        points.remove(&hash);
+        self.version.fetch_add(1, Ordering::Release);
+        Ok(())
+    }
+
+    fn closest_point(&self, points: &BTreeMap<u64, Arc<dyn Node<'a> + 'a>>, key: &[u8]) -> Option<u64> {
+        let mut closest: Option<(u64, u64)> = None;
+        for probe in 0..self.probes {
+            let hash = self.hash_probe(key, probe);
+            let (point, _) = points.range(hash..).next().or_else(|| points.iter().next())?;
+            let distance = point.wrapping_sub(hash);
+            if !matches!(closest, Some((d, _)) if d <= distance) {
+                closest = Some((distance, *point));
+            }
+        }
+        closest.map(|(_, point)| point)
+    }
+
+    /// Retrieves the node responsible for the given key.
+    pub fn get_key(&self, key: &[u8]) -> Option<Arc<dyn Node<'a> + 'a>> {
+        let points = self.points.wait_read();
+        let point = self.closest_point(&points, key)?;
+        points.get(&point).cloned()
+    }
+
+    /// Retrieves up to `n` distinct nodes for the given key: the owner followed by
+    /// its clockwise successors.
+    pub fn get_nodes(&self, key: &[u8], n: usize) -> Vec<Arc<dyn Node<'a> + 'a>> {
+        let points = self.points.wait_read();
+        let point = match self.closest_point(&points, key) {
+            Some(point) if n > 0 => point,
+            _ => return Vec::new(),
+        };
+        let mut seen = HashSet::new();
+        points
+            .range(point..)
+            .chain(points.range(..point))
+            .filter(|(_, node)| seen.insert(node.id()))
+            .take(n)
+            .map(|(_, node)| node.clone())
+            .collect()
+    }
+
+    /// Returns the number of nodes.
+    pub fn len(&self) -> usize {
+        self.points.wait_read().len()
+    }
+
+    /// Returns `true` if the ring has no nodes.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl<'a, H> Ring<'a> for MultiProbeRing<'a, H>
+where
+    H: BuildHasher,
+{
+    fn lookup(&self, key: &[u8]) -> Option<Arc<dyn Node<'a> + 'a>> {
+        self.get_key(key)
+    }
+
+    fn preference_list(&self, key: &[u8], n: usize) -> Vec<Arc<dyn Node<'a> + 'a>> {
+        self.get_nodes(key, n)
+    }
+
+    fn members(&self) -> Vec<Arc<dyn Node<'a> + 'a>> {
+        let mut members: Vec<Arc<dyn Node<'a> + 'a>> = self.points.wait_read().values().cloned().collect();
+        members.sort_by(|a, b| a.id().cmp(b.id()));
+        members
+    }
+
+    fn version(&self) -> u64 {
+        self.version.load(Ordering::Acquire)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::sync::Arc;
+
+    use crate::tests::TestNode;
+    use crate::{MultiProbeRing, DEFAULT_PROBE_COUNT};
+
+    fn ring(probes: usize, nodes: usize) -> MultiProbeRing<'static> {
+        let mut ring = MultiProbeRing::new(probes).unwrap();
+        for i in 0..nodes {
+            let name: &'static str = Box::leak(format!("node{}", i).into_boxed_str());
+            ring.add_node(Arc::new(TestNode {
+                ip_addr: "127.0.0.1".to_string(),
+                name,
+            }))
+            .unwrap();
+        }
+        ring
+    }
+
+    fn peak_to_mean(ring: &MultiProbeRing<'static>, nodes: usize) -> f64 {
+        let keys = 20000;
+        let mut counts: HashMap<&str, usize> = HashMap::new();
+        for i in 0..keys {
+            *counts.entry(ring.get_key(format!("key-{}", i).as_bytes()).unwrap().id()).or_insert(0) += 1;
+        }
+        *counts.values().max().unwrap() as f64 / (keys as f64 / nodes as f64)
+    }
+
+    #[test]
+    fn test_probes_improve_balance() {
+        assert!(MultiProbeRing::new(0).is_err());
+        let single = peak_to_mean(&ring(1, 10), 10);
+        let multi = peak_to_mean(&ring(DEFAULT_PROBE_COUNT, 10), 10);
+        assert!(multi < single, "{} >= {}", multi, single);
+        assert!(multi < 1.3, "peak to mean was {}", multi);
+    }
+
+    #[test]
+    fn test_removal_only_moves_keys_of_removed_node() {
+        let mut ring = ring(DEFAULT_PROBE_COUNT, 5);
+        let before: Vec<&str> = (0..1000).map(|i| ring.get_key(format!("key-{}", i).as_bytes()).unwrap().id()).collect();
+        let nodes = ring.get_nodes(b"some_key", 3);
+        assert_eq!(nodes[0].id(), ring.get_key(b"some_key").unwrap().id());
+        assert_eq!(nodes.len(), 3);
+
+        ring.remove_node("node2").unwrap();
+        assert!(ring.remove_node("node2").is_err());
+        for (i, owner) in before.into_iter().enumerate() {
+            if owner != "node2" {
+                assert_eq!(ring.get_key(format!("key-{}", i).as_bytes()).unwrap().id(), owner);
+            }
+        }
+    }
+}
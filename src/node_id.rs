@@ -0,0 +1,189 @@
+//! Validated node IDs.
+//!
+//! Node IDs end up in virtual node hashes, logs, snapshots and connection pools, so an
+//! empty ID, a multi-kilobyte one or one with control characters is almost always a bug
+//! in the caller. The ring checks every node it adds against the `NodeIdPolicy` of its
+//! `Config`. A strict policy also rejects separators such as `:`, which otherwise let
+//! `"node:1"` and replica 1 of `"node"` feed the same string to the node hasher.
+
+use std::borrow::Borrow;
+use std::error::Error;
+use std::fmt;
+use std::ops::Deref;
+
+/// The rules node IDs must follow.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct NodeIdPolicy {
+    /// The maximum length of an ID, in bytes.
+    pub max_len: usize,
+    /// The characters allowed besides ASCII letters and digits, or `None` to allow any
+    /// character except control characters.
+    pub extra_chars: Option<String>,
+}
+
+impl Default for NodeIdPolicy {
+    fn default() -> Self {
+        NodeIdPolicy {
+            max_len: 255,
+            extra_chars: None,
+        }
+    }
+}
+
+impl NodeIdPolicy {
+    /// A policy allowing only ASCII letters, digits, `-`, `_` and `.`.
+    pub fn strict() -> NodeIdPolicy {
+        NodeIdPolicy {
+            extra_chars: Some("-_.".to_string()),
+            ..NodeIdPolicy::default()
+        }
+    }
+
+    /// Checks an ID against the policy.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<(), Box<dyn Error>>` - An error describing the first rule the ID breaks.
+    pub fn validate(&self, id: &str) -> Result<(), Box<dyn Error>> {
+        if id.is_empty() {
+            return Err("node id must not be empty".into());
+        }
+        if id.len() > self.max_len {
+            return Err(format!("node id is {} bytes long, the limit is {}", id.len(), self.max_len).into());
+        }
+        let allowed = |c: char| match &self.extra_chars {
+            Some(extra) => c.is_ascii_alphanumeric() || extra.contains(c),
+            None => !c.is_control(),
+        };
+        if let Some(c) = id.chars().find(|c| !allowed(*c)) {
+            return Err(format!("node id {:?} contains the disallowed character {:?}", id, c).into());
+        }
+        Ok(())
+    }
+
+    /// Validates an ID and wraps it in a `NodeId`.
+    pub fn node_id(&self, id: &str) -> Result<NodeId, Box<dyn Error>> {
+        self.validate(id)?;
+        Ok(NodeId(id.to_string()))
+    }
+}
+
+/// A node ID that passed validation.
+///
+/// It dereferences to `str` and borrows as one, so maps keyed by `NodeId` can be
+/// indexed with plain string slices.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct NodeId(String);
+
+impl NodeId {
+    /// Validates an ID against the default policy.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use hashring::{NodeId, NodeIdPolicy};
+    ///
+    /// let id = NodeId::new("cache-1.eu-west").unwrap();
+    /// assert_eq!(id.as_str(), "cache-1.eu-west");
+    /// assert!(NodeId::new("").is_err());
+    /// assert!(NodeIdPolicy::strict().node_id("cache:1").is_err());
+    /// ```
+    pub fn new(id: &str) -> Result<NodeId, Box<dyn Error>> {
+        NodeIdPolicy::default().node_id(id)
+    }
+
+    /// Wraps the ID of a node that is already in a ring, and so was validated when it joined.
+    pub(crate) fn from_member(id: &str) -> NodeId {
+        NodeId(id.to_string())
+    }
+
+    /// Returns the ID as a string slice.
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl Deref for NodeId {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl Borrow<str> for NodeId {
+    fn borrow(&self) -> &str {
+        &self.0
+    }
+}
+
+impl AsRef<str> for NodeId {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Display for NodeId {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt.write_str(&self.0)
+    }
+}
+
+impl TryFrom<&str> for NodeId {
+    type Error = Box<dyn Error>;
+
+    fn try_from(id: &str) -> Result<NodeId, Box<dyn Error>> {
+        NodeId::new(id)
+    }
+}
+
+impl TryFrom<String> for NodeId {
+    type Error = Box<dyn Error>;
+
+    fn try_from(id: String) -> Result<NodeId, Box<dyn Error>> {
+        NodeIdPolicy::default().validate(&id)?;
+        Ok(NodeId(id))
+    }
+}
+
+impl From<NodeId> for String {
+    fn from(id: NodeId) -> String {
+        id.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::{NodeId, NodeIdPolicy};
+    use crate::tests::TestNode;
+    use crate::{Config, HashRing};
+
+    #[test]
+    fn test_node_id_policy() {
+        let policy = NodeIdPolicy::default();
+        assert!(policy.validate("node:1").is_ok());
+        assert!(policy.validate("noeud-é").is_ok());
+        assert!(policy.validate("node\n1").is_err());
+        assert!(policy.validate(&"a".repeat(256)).is_err());
+        let strict = NodeIdPolicy::strict();
+        assert!(strict.validate("node-1_a.b").is_ok());
+        assert!(strict.validate("node:1").is_err());
+        assert_eq!(NodeId::try_from("node1".to_string()).unwrap().len(), 5);
+
+        let mut hash_ring = HashRing::new(Config {
+            node_id_policy: strict,
+            ..Config::default()
+        })
+        .unwrap();
+        let node = |name| Arc::new(TestNode { ip_addr: "127.0.0.1".to_string(), name });
+        assert!(hash_ring.add_node(node("node:1")).is_err());
+        assert!(hash_ring.add_node(node("")).is_err());
+        assert!(hash_ring.transaction(|tx| tx.add(node("node 1"))).is_err());
+        hash_ring.add_node(node("node-1")).unwrap();
+        assert!(hash_ring.get_key(b"some_key").is_some());
+    }
+}
@@ -0,0 +1,76 @@
+//! Ring change events as OpenTelemetry spans, behind the `otel` feature.
+//!
+//! `OtelEventSink` records every `RingEvent` as a span named `hashring.<op>` covering
+//! the duration of the operation, with the version and counts as attributes and one
+//! span event per node that joined or left. Quarantined nodes get a `hashring.quarantine`
+//! span. The spans go through whatever exporter the tracer was built with.
+
+use std::time::{Duration, SystemTime};
+
+use opentelemetry::trace::{Span, Tracer};
+use opentelemetry::KeyValue;
+
+use crate::{EventSink, RingEvent};
+
+/// An event sink recording ring changes as spans of an OpenTelemetry tracer.
+///
+/// # Example
+///
+/// ```
+/// use std::sync::Arc;
+/// use hashring::{HashRing, Config, OtelEventSink};
+///
+/// let mut hash_ring = HashRing::new(Config::default()).unwrap();
+/// let tracer = opentelemetry::global::tracer("hashring");
+/// hash_ring.add_event_sink(Arc::new(OtelEventSink::new(tracer)));
+/// ```
+#[derive(Debug)]
+pub struct OtelEventSink<T> {
+    tracer: T,
+}
+
+impl<T> OtelEventSink<T> {
+    /// Creates a sink recording spans with `tracer`.
+    pub fn new(tracer: T) -> OtelEventSink<T> {
+        OtelEventSink { tracer }
+    }
+}
+
+impl<T> EventSink for OtelEventSink<T>
+where
+    T: Tracer + Send + Sync,
+{
+    fn on_event(&self, event: &RingEvent) {
+        let end = SystemTime::now();
+        let mut span = self
+            .tracer
+            .span_builder(format!("hashring.{}", event.op))
+            .with_start_time(end.checked_sub(event.duration).unwrap_or(end))
+            .with_attributes(vec![
+                KeyValue::new("hashring.op", event.op),
+                KeyValue::new("hashring.version", event.version as i64),
+                KeyValue::new("hashring.changes", event.changes.len() as i64),
+                KeyValue::new("hashring.partitions_moved", event.partitions_moved() as i64),
+            ])
+            .start(&self.tracer);
+        for id in event.nodes_added() {
+            span.add_event("node_added", vec![KeyValue::new("hashring.node_id", id.to_string())]);
+        }
+        for id in event.nodes_removed() {
+            span.add_event("node_removed", vec![KeyValue::new("hashring.node_id", id.to_string())]);
+        }
+        span.end_with_timestamp(end);
+    }
+
+    fn on_quarantine(&self, node_id: &str, duration: Duration) {
+        let mut span = self
+            .tracer
+            .span_builder("hashring.quarantine")
+            .with_attributes(vec![
+                KeyValue::new("hashring.node_id", node_id.to_string()),
+                KeyValue::new("hashring.quarantine_ms", duration.as_millis() as i64),
+            ])
+            .start(&self.tracer);
+        span.end();
+    }
+}
@@ -0,0 +1,122 @@
+//! What a single node is responsible for.
+//!
+//! Operators and schedulers often need the inverse of a lookup: the partitions a node
+//! owns, to plan a migration, or the share of the keyspace it serves, to size it.
+
+use std::hash::BuildHasher;
+
+use crate::{util, HashRing};
+
+impl<'a, H, K> HashRing<'a, H, K>
+where
+    H: BuildHasher,
+    K: BuildHasher,
+{
+    /// Returns the partitions currently assigned to a node, in ascending order.
+    ///
+    /// # Arguments
+    ///
+    /// * `node_id` - The ID of the node.
+    ///
+    /// # Returns
+    ///
+    /// * `impl Iterator<Item = usize>` - The IDs of its partitions, empty if the node is
+    ///   not in the ring.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use std::sync::Arc;
+    /// use hashring::{HashRing, Config, Node};
+    ///
+    /// #[derive(Debug)]
+    /// struct MyNode<'a> {
+    ///     name: &'a str,
+    /// }
+    ///
+    /// impl<'a> Node<'a> for MyNode<'a> {
+    ///     fn id(&self) -> &'a str {
+    ///         self.name
+    ///     }
+    /// }
+    ///
+    /// let mut hash_ring = HashRing::new(Config::default()).unwrap();
+    /// hash_ring.add_node(Arc::new(MyNode { name: "node1" })).unwrap();
+    /// hash_ring.add_node(Arc::new(MyNode { name: "node2" })).unwrap();
+    ///
+    /// for part_id in hash_ring.partitions_of("node1") {
+    ///     assert_eq!(hash_ring.get_partition_owner(part_id).unwrap().id(), "node1");
+    /// }
+    /// println!("node1 serves {:.1}% of the keyspace", hash_ring.keyspace_fraction("node1") * 100.0);
+    /// ```
+    pub fn partitions_of(&self, node_id: &str) -> impl Iterator<Item = usize> {
+        let mut part_ids: Vec<usize> = match self.partitions.read() {
+            Ok(partitions) => partitions.iter().filter(|(_, node)| node.id() == node_id).map(|(part_id, _)| *part_id).collect(),
+            Err(_) => Vec::new(),
+        };
+        part_ids.sort_unstable();
+        part_ids.into_iter()
+    }
+
+    /// Returns the fraction of the keyspace a node owns on the ring, from `0.0` to `1.0`.
+    ///
+    /// This is the total length of the arcs ending at its virtual nodes, i.e. the share
+    /// of uniformly hashed keys `get_key` sends to it, ignoring traffic splits.
+    pub fn keyspace_fraction(&self, node_id: &str) -> f64 {
+        let sorted_set = match self.sorted_nodes_hash_set.read() {
+            Ok(sorted_set) => sorted_set,
+            Err(_) => return 0.0,
+        };
+        let mut previous = match sorted_set.keys().next_back() {
+            Some(last) => *last,
+            None => return 0.0,
+        };
+        if sorted_set.len() == 1 {
+            return if sorted_set[&previous].id() == node_id { 1.0 } else { 0.0 };
+        }
+        let mut owned = 0.0;
+        for (point, node) in sorted_set.iter() {
+            if node.id() == node_id {
+                owned += util::arc_fraction(util::arc_length(previous, *point));
+            }
+            previous = *point;
+        }
+        owned
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::tests::test_nodes;
+    use crate::{Config, HashRing};
+
+    #[test]
+    fn test_partitions_of_and_keyspace_fraction() {
+        let mut hash_ring = HashRing::new(Config::default()).unwrap();
+        assert_eq!(hash_ring.partitions_of("node1").count(), 0);
+        assert_eq!(hash_ring.keyspace_fraction("node1"), 0.0);
+
+        hash_ring.add_node(test_nodes()[0].clone()).unwrap();
+        assert_eq!(hash_ring.partitions_of("node1").count(), Config::default().partition_count);
+        assert_eq!(hash_ring.keyspace_fraction("node1"), 1.0);
+
+        for node in test_nodes().into_iter().skip(1) {
+            hash_ring.add_node(node).unwrap();
+        }
+        let mut all: Vec<usize> = Vec::new();
+        let mut total = 0.0;
+        for id in ["node1", "node2", "node3"] {
+            let owned: Vec<usize> = hash_ring.partitions_of(id).collect();
+            assert!(owned.windows(2).all(|pair| pair[0] < pair[1]));
+            assert!(owned.iter().all(|part_id| hash_ring.get_partition_owner(*part_id).unwrap().id() == id));
+            all.extend(owned);
+            let fraction = hash_ring.keyspace_fraction(id);
+            assert!(fraction > 0.0 && fraction < 1.0, "{}", fraction);
+            total += fraction;
+        }
+        all.sort_unstable();
+        assert_eq!(all, hash_ring.partition_ids());
+        assert!((total - 1.0).abs() < 1e-9, "{}", total);
+        assert_eq!(hash_ring.partitions_of("node4").count(), 0);
+    }
+}
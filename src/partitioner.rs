@@ -0,0 +1,162 @@
+//! A message-queue partitioner over the ring's partitions.
+//!
+//! Records with a key go to the partition the key hashes to, so all records of a key
+//! stay ordered in one partition. Keyless records follow the sticky strategy of Kafka
+//! producers: they all go to one partition until the current batch is full, then move on
+//! to the next partition with an owner, which keeps batches large without skewing load.
+
+use std::hash::BuildHasher;
+use std::sync::Mutex;
+
+use crate::sync;
+use crate::{HashRing, XxHash64Hasher};
+
+/// The partition keyless records currently stick to.
+#[derive(Debug, Default)]
+struct Sticky {
+    partition: Option<usize>,
+    records: usize,
+    next: usize,
+}
+
+/// Maps records to partitions of the ring.
+#[derive(Debug)]
+pub struct Partitioner<'a, H = XxHash64Hasher, K = H> {
+    hash_ring: HashRing<'a, H, K>,
+    batch_size: usize,
+    sticky: Mutex<Sticky>,
+}
+
+impl<'a, H, K> Partitioner<'a, H, K>
+where
+    H: BuildHasher,
+    K: BuildHasher,
+{
+    /// Creates a partitioner whose keyless records stick to a partition until `on_new_batch` is called.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use std::sync::Arc;
+    /// use hashring::{HashRing, Config, Node, Partitioner};
+    ///
+    /// #[derive(Debug)]
+    /// struct MyNode<'a> {
+    ///     name: &'a str,
+    /// }
+    ///
+    /// impl<'a> Node<'a> for MyNode<'a> {
+    ///     fn id(&self) -> &'a str {
+    ///         self.name
+    ///     }
+    /// }
+    ///
+    /// let mut hash_ring = HashRing::new(Config::default()).unwrap();
+    /// hash_ring.add_node(Arc::new(MyNode { name: "broker1" })).unwrap();
+    ///
+    /// let partitioner = Partitioner::new(hash_ring.share());
+    /// assert_eq!(partitioner.partition(Some(b"order-42")), hash_ring.get_partition(b"order-42"));
+    /// assert_eq!(partitioner.partition(None), partitioner.partition(None));
+    /// ```
+    pub fn new(hash_ring: HashRing<'a, H, K>) -> Partitioner<'a, H, K> {
+        Partitioner {
+            hash_ring,
+            batch_size: 0,
+            sticky: Mutex::new(Sticky::default()),
+        }
+    }
+
+    /// Moves keyless records to the next partition after every `batch_size` records, or
+    /// only on `on_new_batch` if zero.
+    pub fn with_batch_size(mut self, batch_size: usize) -> Partitioner<'a, H, K> {
+        self.batch_size = batch_size;
+        self
+    }
+
+    /// Returns the ring routing partitions to nodes.
+    pub fn ring(&self) -> &HashRing<'a, H, K> {
+        &self.hash_ring
+    }
+
+    /// Returns the partition of a record.
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - The key of the record, if it has one.
+    ///
+    /// # Returns
+    ///
+    /// * `usize` - The partition the key hashes to, or the sticky partition for keyless records.
+    pub fn partition(&self, key: Option<&[u8]>) -> usize {
+        match key {
+            Some(key) => self.hash_ring.get_partition(key),
+            None => self.sticky_partition(),
+        }
+    }
+
+    /// Moves keyless records to another partition, such as after sending a batch.
+    pub fn on_new_batch(&self) {
+        let mut sticky = sync::lock(&self.sticky);
+        sticky.partition = None;
+    }
+
+    /// Returns the partition keyless records go to, switching partitions once the batch is full.
+    fn sticky_partition(&self) -> usize {
+        let mut sticky = sync::lock(&self.sticky);
+        if self.batch_size > 0 && sticky.records >= self.batch_size {
+            sticky.partition = None;
+        }
+        let partition = match sticky.partition {
+            Some(partition) if self.hash_ring.has_partition(partition) => partition,
+            _ => {
+                let partition = self.next_partition(sticky.next, sticky.partition);
+                sticky.next = sticky.next.wrapping_add(1);
+                sticky.partition = Some(partition);
+                sticky.records = 0;
+                partition
+            }
+        };
+        sticky.records += 1;
+        partition
+    }
+
+    /// Picks the partition at position `index` among those with an owner, skipping `previous`
+    /// if there is another one.
+    fn next_partition(&self, index: usize, previous: Option<usize>) -> usize {
+        let mut candidates: Vec<usize> = self.hash_ring.partition_ids().into_iter().filter(|p| self.hash_ring.get_partition_owner(*p).is_some()).collect();
+        if candidates.is_empty() {
+            candidates = self.hash_ring.partition_ids();
+        }
+        if candidates.len() > 1 {
+            candidates.retain(|p| Some(*p) != previous);
+        }
+        candidates[index % candidates.len()]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::tests::test_nodes;
+    use crate::{Config, HashRing, Partitioner};
+
+    #[test]
+    fn test_keyless_records_stick_to_a_partition() {
+        let mut hash_ring = HashRing::new(Config::default()).unwrap();
+        for node in test_nodes() {
+            hash_ring.add_node(node).unwrap();
+        }
+        let partitioner = Partitioner::new(hash_ring.share()).with_batch_size(3);
+        assert_eq!(partitioner.partition(Some(b"some_key")), hash_ring.get_partition(b"some_key"));
+
+        let first = partitioner.partition(None);
+        assert_eq!(partitioner.partition(None), first);
+        assert_eq!(partitioner.partition(None), first);
+        let second = partitioner.partition(None);
+        assert_ne!(second, first);
+
+        partitioner.on_new_batch();
+        let third = partitioner.partition(None);
+        assert_ne!(third, second);
+        assert!(hash_ring.get_partition_owner(third).is_some());
+    }
+}
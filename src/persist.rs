@@ -0,0 +1,505 @@
+//! Compact binary encoding of snapshots, and saving them to disk.
+//!
+//! Layout (little endian):
+//!
+//! ```text
+//! magic "HRNG" | format version: u16 | payload | crc32 of everything before: u32
+//! ```
+//!
+//! Files are written to a temporary sibling and renamed into place, so a reader
+//! never observes a partially written ring.
+
+use std::error::Error;
+use std::fs;
+use std::hash::BuildHasher;
+use std::io::Write;
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use crate::crc::crc32;
+use crate::snapshot::RingSnapshot;
+use crate::{CollisionPolicy, Config, HashRing, Node, NodeIdPolicy, RingFormatVersion};
+
+/// Tells apart the temporary files of saves running at the same time.
+static TMP_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+const MAGIC: &[u8; 4] = b"HRNG";
+const FORMAT_VERSION: u16 = 1;
+
+pub(crate) struct Encoder {
+    buf: Vec<u8>,
+}
+
+impl Encoder {
+    pub(crate) fn new() -> Encoder {
+        Encoder { buf: Vec::new() }
+    }
+
+    pub(crate) fn u8(&mut self, v: u8) {
+        self.buf.push(v);
+    }
+
+    pub(crate) fn u16(&mut self, v: u16) {
+        self.buf.extend_from_slice(&v.to_le_bytes());
+    }
+
+    pub(crate) fn u32(&mut self, v: u32) {
+        self.buf.extend_from_slice(&v.to_le_bytes());
+    }
+
+    pub(crate) fn u64(&mut self, v: u64) {
+        self.buf.extend_from_slice(&v.to_le_bytes());
+    }
+
+    pub(crate) fn bytes(&mut self, v: &[u8]) {
+        self.u32(v.len() as u32);
+        self.buf.extend_from_slice(v);
+    }
+
+    pub(crate) fn str(&mut self, v: &str) {
+        self.bytes(v.as_bytes());
+    }
+
+    pub(crate) fn raw(&mut self, v: &[u8]) {
+        self.buf.extend_from_slice(v);
+    }
+
+    pub(crate) fn into_inner(self) -> Vec<u8> {
+        self.buf
+    }
+}
+
+pub(crate) struct Decoder<'b> {
+    buf: &'b [u8],
+}
+
+impl<'b> Decoder<'b> {
+    pub(crate) fn new(buf: &'b [u8]) -> Decoder<'b> {
+        Decoder { buf }
+    }
+
+    pub(crate) fn take(&mut self, len: usize) -> Result<&'b [u8], Box<dyn Error>> {
+        if self.buf.len() < len {
+            return Err("unexpected end of data".into());
+        }
+        let (head, tail) = self.buf.split_at(len);
+        self.buf = tail;
+        Ok(head)
+    }
+
+    pub(crate) fn u8(&mut self) -> Result<u8, Box<dyn Error>> {
+        Ok(self.take(1)?[0])
+    }
+
+    pub(crate) fn u16(&mut self) -> Result<u16, Box<dyn Error>> {
+        Ok(u16::from_le_bytes(self.take(2)?.try_into()?))
+    }
+
+    pub(crate) fn u32(&mut self) -> Result<u32, Box<dyn Error>> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into()?))
+    }
+
+    pub(crate) fn u64(&mut self) -> Result<u64, Box<dyn Error>> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into()?))
+    }
+
+    pub(crate) fn bytes(&mut self) -> Result<&'b [u8], Box<dyn Error>> {
+        let len = self.u32()? as usize;
+        self.take(len)
+    }
+
+    pub(crate) fn string(&mut self) -> Result<String, Box<dyn Error>> {
+        Ok(String::from_utf8(self.bytes()?.to_vec())?)
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.buf.is_empty()
+    }
+}
+
+impl RingSnapshot {
+    /// Encodes the snapshot into the compact binary format.
+    ///
+    /// Fails if a partition is assigned to a node that isn't a member of the snapshot.
+    pub fn to_bytes(&self) -> Result<Vec<u8>, Box<dyn Error>> {
+        let mut enc = Encoder::new();
+        enc.raw(MAGIC);
+        enc.u16(FORMAT_VERSION);
+
+        enc.u64(self.config.replication_factor as u64);
+        enc.u64(self.config.partition_count as u64);
+        match self.config.rebalance_skew_threshold {
+            Some(threshold) => {
+                enc.u8(1);
+                enc.u64(threshold.to_bits());
+            }
+            None => enc.u8(0),
+        }
+        enc.u8(match self.config.collision_policy {
+            CollisionPolicy::Error => 0,
+            CollisionPolicy::Rehash => 1,
+            CollisionPolicy::KeepFirst => 2,
+        });
+        match &self.config.key_namespace {
+            Some(namespace) => {
+                enc.u8(1);
+                enc.str(namespace);
+            }
+            None => enc.u8(0),
+        }
+        enc.u16(self.config.format_version.number());
+
+        enc.u32(self.nodes.len() as u32);
+        for id in &self.nodes {
+            enc.str(id);
+        }
+
+        enc.u32(self.partitions.len() as u32);
+        for (part_id, id) in &self.partitions {
+            let idx = self
+                .nodes
+                .iter()
+                .position(|n| n == id)
+                .ok_or_else(|| format!("partition {} is assigned to unknown node {}", part_id, id))?;
+            enc.u64(*part_id as u64);
+            enc.u32(idx as u32);
+        }
+
+        enc.u32(self.splits.len() as u32);
+        for part_id in &self.splits {
+            enc.u64(*part_id as u64);
+        }
+
+        enc.u32(self.groups.len() as u32);
+        for (group, pinned) in &self.groups {
+            enc.str(group);
+            match pinned {
+                Some(id) => {
+                    enc.u8(1);
+                    enc.str(id);
+                }
+                None => enc.u8(0),
+            }
+        }
+
+        enc.u32(self.moved.len() as u32);
+        for part_id in &self.moved {
+            enc.u64(*part_id as u64);
+        }
+
+        enc.u32(self.weights.len() as u32);
+        for (id, weight) in &self.weights {
+            enc.str(id);
+            enc.u64(weight.to_bits());
+        }
+
+        let mut buf = enc.into_inner();
+        let checksum = crc32(&buf);
+        buf.extend_from_slice(&checksum.to_le_bytes());
+        Ok(buf)
+    }
+
+    /// Decodes a snapshot from the compact binary format, verifying its header and checksum.
+    pub fn from_bytes(data: &[u8]) -> Result<RingSnapshot, Box<dyn Error>> {
+        if data.len() < MAGIC.len() + 2 + 4 {
+            return Err("snapshot is truncated".into());
+        }
+        let (body, checksum) = data.split_at(data.len() - 4);
+        if crc32(body) != u32::from_le_bytes(checksum.try_into()?) {
+            return Err("snapshot checksum mismatch".into());
+        }
+
+        let mut dec = Decoder::new(body);
+        if dec.take(MAGIC.len())? != MAGIC {
+            return Err("not a ring snapshot".into());
+        }
+        let version = dec.u16()?;
+        if version != FORMAT_VERSION {
+            return Err(format!("unsupported snapshot format version {}", version).into());
+        }
+
+        let replication_factor = dec.u64()? as usize;
+        let partition_count = dec.u64()? as usize;
+        let rebalance_skew_threshold = match dec.u8()? {
+            0 => None,
+            _ => Some(f64::from_bits(dec.u64()?)),
+        };
+        let collision_policy = match dec.u8()? {
+            0 => CollisionPolicy::Error,
+            1 => CollisionPolicy::Rehash,
+            2 => CollisionPolicy::KeepFirst,
+            policy => return Err(format!("unknown collision policy {}", policy).into()),
+        };
+        let key_namespace = match dec.u8()? {
+            0 => None,
+            _ => Some(dec.string()?),
+        };
+        let number = dec.u16()?;
+        let format_version = RingFormatVersion::from_number(number).ok_or_else(|| format!("unsupported ring format version {}", number))?;
+        let config = Config {
+            replication_factor,
+            partition_count,
+            rebalance_skew_threshold,
+            collision_policy,
+            key_namespace,
+            node_id_policy: NodeIdPolicy::default(),
+            format_version,
+            capacity_headroom: crate::capacity::DEFAULT_CAPACITY_HEADROOM,
+        };
+
+        let node_count = dec.u32()? as usize;
+        let mut nodes = Vec::with_capacity(node_count.min(body.len()));
+        for _ in 0..node_count {
+            nodes.push(dec.string()?);
+        }
+
+        let partition_count = dec.u32()? as usize;
+        let mut partitions = std::collections::BTreeMap::new();
+        for _ in 0..partition_count {
+            let part_id = dec.u64()? as usize;
+            let idx = dec.u32()? as usize;
+            let id = nodes.get(idx).ok_or("partition assigned to unknown node")?;
+            partitions.insert(part_id, id.clone());
+        }
+
+        let mut splits = std::collections::BTreeSet::new();
+        let split_count = dec.u32()? as usize;
+        for _ in 0..split_count {
+            splits.insert(dec.u64()? as usize);
+        }
+
+        let mut groups = std::collections::BTreeMap::new();
+        let group_count = dec.u32()? as usize;
+        for _ in 0..group_count {
+            let group = dec.string()?;
+            let pinned = match dec.u8()? {
+                0 => None,
+                _ => Some(dec.string()?),
+            };
+            groups.insert(group, pinned);
+        }
+
+        let mut moved = std::collections::BTreeSet::new();
+        let moved_count = dec.u32()? as usize;
+        for _ in 0..moved_count {
+            moved.insert(dec.u64()? as usize);
+        }
+
+        let mut weights = std::collections::BTreeMap::new();
+        let weight_count = dec.u32()? as usize;
+        for _ in 0..weight_count {
+            let id = dec.string()?;
+            weights.insert(id, f64::from_bits(dec.u64()?));
+        }
+
+        if !dec.is_empty() {
+            return Err("trailing data after snapshot".into());
+        }
+
+        Ok(RingSnapshot {
+            config,
+            nodes,
+            partitions,
+            splits,
+            groups,
+            moved,
+            weights,
+        })
+    }
+
+    /// Atomically writes the snapshot to `path`.
+    ///
+    /// The data is written and synced to a temporary file next to `path`, which is
+    /// then renamed over it, and the directory is synced so the rename survives a crash.
+    /// Each save uses its own temporary file, so concurrent saves to the same path don't
+    /// interfere, and the file is removed if the save fails.
+    pub fn save_to_path<P: AsRef<Path>>(&self, path: P) -> Result<(), Box<dyn Error>> {
+        let data = self.to_bytes()?;
+        let path = path.as_ref();
+        let mut tmp = path.as_os_str().to_owned();
+        tmp.push(format!(".{}.{}.tmp", std::process::id(), TMP_COUNTER.fetch_add(1, Ordering::Relaxed)));
+
+        let mut file = fs::OpenOptions::new().write(true).create_new(true).open(&tmp)?;
+        let written = file
+            .write_all(&data)
+            .and_then(|_| file.sync_all())
+            .and_then(|_| {
+                drop(file);
+                fs::rename(&tmp, path)
+            });
+        if let Err(err) = written {
+            let _ = fs::remove_file(&tmp);
+            return Err(err.into());
+        }
+        sync_parent(path)?;
+        Ok(())
+    }
+
+    /// Reads a snapshot previously written with `save_to_path`.
+    pub fn load_from_path<P: AsRef<Path>>(path: P) -> Result<RingSnapshot, Box<dyn Error>> {
+        let data = fs::read(path)?;
+        RingSnapshot::from_bytes(&data)
+    }
+}
+
+/// Syncs the directory holding `path`, persisting the entry a rename put there.
+#[cfg(unix)]
+fn sync_parent(path: &Path) -> std::io::Result<()> {
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    fs::File::open(dir)?.sync_all()
+}
+
+/// Directories can't be opened for syncing on other platforms, where renames are
+/// made durable by the file system.
+#[cfg(not(unix))]
+fn sync_parent(_path: &Path) -> std::io::Result<()> {
+    Ok(())
+}
+
+impl<'a, H, K> HashRing<'a, H, K>
+where
+    H: BuildHasher,
+    K: BuildHasher,
+{
+    /// Atomically saves the current topology to `path`.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The file to write.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<(), Box<dyn Error>>` - On success, returns `Ok(())`.
+    pub fn save_to_path<P: AsRef<Path>>(&self, path: P) -> Result<(), Box<dyn Error>> {
+        self.snapshot().save_to_path(path)
+    }
+
+    /// Replaces the topology with the one saved at `path`.
+    ///
+    /// The file is fully read, verified and resolved before the ring is modified, so a
+    /// corrupt file or an unknown node leaves the ring untouched.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The file to read.
+    /// * `resolver` - Returns the node for a given ID, or `None` if it is unknown.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<(), Box<dyn Error>>` - On success, returns `Ok(())`.
+    pub fn load_from_path<P, F>(&mut self, path: P, resolver: F) -> Result<(), Box<dyn Error>>
+    where
+        P: AsRef<Path>,
+        F: FnMut(&str) -> Option<Arc<dyn Node<'a> + 'a>>,
+    {
+        let snapshot = RingSnapshot::load_from_path(path)?;
+        self.restore(&snapshot, resolver)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::tests::test_nodes as nodes;
+
+    fn temp_path(name: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("hashring-{}-{}", std::process::id(), name))
+    }
+
+    #[test]
+    fn test_bytes_roundtrip() {
+        let mut hash_ring = HashRing::new(Config {
+            key_namespace: Some("app".to_string()),
+            format_version: RingFormatVersion::V2,
+            ..Config::default()
+        })
+        .unwrap();
+        for node in nodes() {
+            hash_ring.add_node(node).unwrap();
+        }
+        hash_ring.add_group("tenant-1").unwrap();
+        hash_ring.add_group("tenant-2").unwrap();
+        hash_ring.move_group("tenant-2", "node3").unwrap();
+        let snapshot = hash_ring.snapshot();
+        let decoded = RingSnapshot::from_bytes(&snapshot.to_bytes().unwrap()).unwrap();
+
+        assert_eq!(decoded.nodes, snapshot.nodes);
+        assert_eq!(decoded.partitions, snapshot.partitions);
+        assert_eq!(decoded.groups, snapshot.groups);
+        assert_eq!(decoded.config.partition_count, snapshot.config.partition_count);
+        assert_eq!(decoded.config.key_namespace.as_deref(), Some("app"));
+        assert_eq!(decoded.config.format_version, RingFormatVersion::V2);
+    }
+
+    #[test]
+    fn test_corrupt_bytes_rejected() {
+        let hash_ring = HashRing::new(Config::default()).unwrap();
+        let mut data = hash_ring.snapshot().to_bytes().unwrap();
+        data[8] ^= 0xff;
+        assert!(RingSnapshot::from_bytes(&data).is_err());
+        assert!(RingSnapshot::from_bytes(b"HRNG").is_err());
+
+        // Partitions of unknown nodes can't be encoded.
+        let mut snapshot = hash_ring.snapshot();
+        snapshot.partitions.insert(0, "node9".to_string());
+        assert!(snapshot.to_bytes().is_err());
+        assert!(snapshot.save_to_path(temp_path("invalid.bin")).is_err());
+        assert!(!temp_path("invalid.bin").exists());
+    }
+
+    #[test]
+    fn test_save_and_load_path() {
+        let nodes = nodes();
+        let path = temp_path("ring.bin");
+
+        let mut hash_ring = HashRing::new(Config::default()).unwrap();
+        for node in &nodes {
+            hash_ring.add_node(node.clone()).unwrap();
+        }
+        hash_ring.save_to_path(&path).unwrap();
+
+        let mut reloaded = HashRing::new(Config::default()).unwrap();
+        reloaded
+            .load_from_path(&path, |id| {
+                nodes.iter().find(|n| n.id() == id).map(|n| n.clone() as Arc<dyn Node<'static>>)
+            })
+            .unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(reloaded.snapshot().partitions, hash_ring.snapshot().partitions);
+        assert_eq!(reloaded.get_key(b"some_key").unwrap().id(), hash_ring.get_key(b"some_key").unwrap().id());
+    }
+
+    #[test]
+    fn test_concurrent_saves_use_their_own_files() {
+        let dir = temp_path("saves");
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("ring.bin");
+        let snapshot = HashRing::new(Config::default()).unwrap().snapshot();
+
+        std::thread::scope(|scope| {
+            for _ in 0..4 {
+                scope.spawn(|| snapshot.save_to_path(&path).unwrap());
+            }
+        });
+        RingSnapshot::load_from_path(&path).unwrap();
+        let files: Vec<_> = fs::read_dir(&dir).unwrap().map(|entry| entry.unwrap().file_name()).collect();
+        assert_eq!(files, ["ring.bin"]);
+
+        // Renaming over a directory fails, and the temporary file next to it is cleaned up.
+        assert!(snapshot.save_to_path(&dir).is_err());
+        let prefix = format!("{}.", dir.file_name().unwrap().to_str().unwrap());
+        let leftovers = fs::read_dir(dir.parent().unwrap())
+            .unwrap()
+            .filter(|entry| entry.as_ref().unwrap().file_name().to_str().is_some_and(|name| name.starts_with(&prefix)))
+            .count();
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(leftovers, 0);
+    }
+}
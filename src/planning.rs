@@ -0,0 +1,199 @@
+//! Choosing a partition count and replication factor.
+//!
+//! More virtual nodes per node even out the keyspace each node owns, and more partitions
+//! even out the partitions each node is assigned, at the cost of a larger ring and more
+//! partitions to track. `recommend_config` measures both on simulated rings for a range
+//! of candidate pairs and returns the cheapest ones meeting a skew target, instead of
+//! leaving users to guess from the defaults.
+//!
+//! Skews are ratios between the most loaded node and the mean, as with `load_skew`. As a
+//! rule of thumb, the share of a node deviates from the mean by about
+//! `sqrt(1 / replication_factor + nodes / partition_count)` of it, and the most loaded of
+//! many nodes sits two to three such deviations above the mean.
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::sync::Arc;
+
+use crate::{util, Config, HashRing, Node};
+
+/// The candidate numbers of virtual nodes per node.
+const REPLICATION_FACTORS: [usize; 6] = [10, 20, 40, 80, 160, 320];
+/// The candidate partition counts, roughly doubling from the default.
+const PARTITION_COUNTS: [usize; 7] = [271, 541, 1031, 2053, 4099, 8209, 16411];
+
+/// A partition count and replication factor, with the balance measured for them.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConfigRecommendation {
+    /// The number of virtual nodes per node.
+    pub replication_factor: usize,
+    /// The number of partitions.
+    pub partition_count: usize,
+    /// The largest fraction of the keyspace owned by a node, relative to the mean.
+    pub keyspace_skew: f64,
+    /// The largest number of partitions assigned to a node, relative to the mean.
+    pub partition_skew: f64,
+    /// The expected standard deviation of the partitions of a node, relative to the mean.
+    pub expected_deviation: f64,
+    /// The number of virtual nodes on the ring.
+    pub ring_size: usize,
+}
+
+impl ConfigRecommendation {
+    /// Returns the default configuration with this partition count and replication factor.
+    pub fn config(&self) -> Config {
+        Config {
+            replication_factor: self.replication_factor,
+            partition_count: self.partition_count,
+            ..Config::default()
+        }
+    }
+}
+
+#[derive(Debug)]
+struct PlanNode<'a> {
+    id: &'a str,
+}
+
+impl<'a> Node<'a> for PlanNode<'a> {
+    fn id(&self) -> &'a str {
+        self.id
+    }
+}
+
+/// Suggests partition counts and replication factors keeping both skews within a target.
+///
+/// Every candidate pair is measured on a ring of `node_count` nodes with the default
+/// hashers. Real node IDs place virtual nodes elsewhere, so measured skews are estimates
+/// for rings of that size rather than guarantees.
+///
+/// # Arguments
+///
+/// * `node_count` - The number of nodes the ring will have.
+/// * `target_imbalance` - How far above the mean the most loaded node may be, e.g. `0.2` for 20%.
+///
+/// # Returns
+///
+/// * `Result<Vec<ConfigRecommendation>, Box<dyn Error>>` - The pairs meeting the target, smallest
+///   ring first and then fewest partitions. Empty if no candidate meets it.
+///
+/// # Example
+///
+/// ```
+/// use hashring::{recommend_config, HashRing};
+///
+/// let recommendations = recommend_config(10, 0.5).unwrap();
+/// for recommendation in &recommendations {
+///     println!(
+///         "{} vnodes per node and {} partitions: keyspace skew {:.2}, partition skew {:.2}",
+///         recommendation.replication_factor,
+///         recommendation.partition_count,
+///         recommendation.keyspace_skew,
+///         recommendation.partition_skew,
+///     );
+/// }
+/// let hash_ring = HashRing::new(recommendations[0].config()).unwrap();
+/// ```
+pub fn recommend_config(node_count: usize, target_imbalance: f64) -> Result<Vec<ConfigRecommendation>, Box<dyn Error>> {
+    if node_count == 0 {
+        return Err("node count must be greater than 0".into());
+    }
+    if target_imbalance.is_nan() || target_imbalance <= 0.0 {
+        return Err("target imbalance must be greater than 0".into());
+    }
+    let max_skew = 1.0 + target_imbalance;
+    let ids: Vec<String> = (0..node_count).map(|i| format!("node-{}", i)).collect();
+
+    let mut recommendations = Vec::new();
+    for replication_factor in REPLICATION_FACTORS {
+        let mut hash_ring: HashRing = HashRing::new(Config {
+            replication_factor,
+            partition_count: PARTITION_COUNTS[0],
+            ..Config::default()
+        })?;
+        hash_ring.transaction(|tx| {
+            for id in &ids {
+                tx.add(Arc::new(PlanNode { id }))?;
+            }
+            Ok(())
+        })?;
+        let keyspace_skew = keyspace_skew(&hash_ring, node_count);
+        if keyspace_skew > max_skew {
+            continue;
+        }
+
+        for partition_count in PARTITION_COUNTS {
+            hash_ring.set_config(Config {
+                partition_count,
+                ..hash_ring.config().as_ref().clone()
+            });
+            let sorted_set = hash_ring.sorted_nodes_hash_set.wait_read();
+            let partitions = hash_ring.compute_partitions(&sorted_set, &HashMap::new());
+            let ring_size = sorted_set.len();
+            drop(sorted_set);
+
+            let mut counts: HashMap<&str, usize> = HashMap::new();
+            for node in partitions.values() {
+                *counts.entry(node.id()).or_default() += 1;
+            }
+            let mean = partition_count as f64 / node_count as f64;
+            let partition_skew = counts.values().copied().max().unwrap_or(0) as f64 / mean;
+            if partition_skew <= max_skew {
+                recommendations.push(ConfigRecommendation {
+                    replication_factor,
+                    partition_count,
+                    keyspace_skew,
+                    partition_skew,
+                    expected_deviation: (1.0 / replication_factor as f64 + 1.0 / mean).sqrt(),
+                    ring_size,
+                });
+            }
+        }
+    }
+    recommendations.sort_by_key(|recommendation| (recommendation.ring_size, recommendation.partition_count));
+    Ok(recommendations)
+}
+
+/// Returns the largest keyspace fraction of a node relative to the mean, in one pass.
+fn keyspace_skew(hash_ring: &HashRing, node_count: usize) -> f64 {
+    let sorted_set = hash_ring.sorted_nodes_hash_set.wait_read();
+    let mut previous = match sorted_set.keys().next_back() {
+        Some(last) => *last,
+        None => return 0.0,
+    };
+    if sorted_set.len() == 1 {
+        return node_count as f64;
+    }
+    let mut owned: HashMap<&str, f64> = HashMap::new();
+    for (point, node) in sorted_set.iter() {
+        *owned.entry(node.id()).or_default() += util::arc_fraction(util::arc_length(previous, *point));
+        previous = *point;
+    }
+    owned.values().copied().fold(0.0, f64::max) * node_count as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::recommend_config;
+
+    #[test]
+    fn test_recommendations_meet_target() {
+        assert!(recommend_config(0, 0.2).is_err());
+        assert!(recommend_config(10, 0.0).is_err());
+
+        let recommendations = recommend_config(10, 0.3).unwrap();
+        assert!(!recommendations.is_empty());
+        for pair in recommendations.windows(2) {
+            assert!(pair[0].ring_size <= pair[1].ring_size);
+        }
+        for recommendation in &recommendations {
+            assert!(recommendation.keyspace_skew >= 1.0 && recommendation.keyspace_skew <= 1.3);
+            assert!(recommendation.partition_skew >= 1.0 && recommendation.partition_skew <= 1.3);
+            assert_eq!(recommendation.ring_size, recommendation.replication_factor * 10);
+        }
+
+        // A looser target never drops a pair meeting a tighter one.
+        let loose = recommend_config(10, 1.0).unwrap();
+        assert!(recommendations.iter().all(|recommendation| loose.contains(recommendation)));
+    }
+}
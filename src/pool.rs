@@ -0,0 +1,291 @@
+//! Connection pools keyed by ring node, behind the `pool` feature.
+//!
+//! `PooledRing` hands out a connection to the owner of a key, reusing idle connections
+//! to that node and opening new ones through a caller-supplied function. Pools of nodes
+//! that left the ring are dropped the next time the ring's version changes, so callers
+//! don't keep sockets open to decommissioned nodes.
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::hash::BuildHasher;
+use std::ops::{Deref, DerefMut};
+use std::sync::{Arc, Mutex};
+
+use crate::sync;
+use crate::{HashRing, Node, XxHash64Hasher};
+
+/// The function opening a new connection to a node.
+type Connect<'a, C> = Box<dyn Fn(&Arc<dyn Node<'a> + 'a>) -> Result<C, Box<dyn Error>> + Send + Sync + 'a>;
+
+/// The idle connections of every node, as of a ring version.
+struct Pools<C> {
+    version: u64,
+    idle: HashMap<String, Vec<C>>,
+}
+
+/// A ring paired with a pool of connections to each of its nodes.
+pub struct PooledRing<'a, C, H = XxHash64Hasher, K = H> {
+    hash_ring: HashRing<'a, H, K>,
+    connect: Connect<'a, C>,
+    max_idle: usize,
+    pools: Mutex<Pools<C>>,
+}
+
+impl<'a, C, H, K> PooledRing<'a, C, H, K>
+where
+    H: BuildHasher,
+    K: BuildHasher,
+{
+    /// Creates a pooled ring keeping up to `max_idle` idle connections per node.
+    ///
+    /// Nodes added to or removed from a ring sharing its state, made with `share`, are
+    /// picked up by the pools.
+    ///
+    /// # Arguments
+    ///
+    /// * `hash_ring` - The ring routing keys to nodes.
+    /// * `max_idle` - The number of idle connections kept per node.
+    /// * `connect` - Opens a new connection to a node.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use std::sync::Arc;
+    /// use hashring::{HashRing, Config, Node, PooledRing};
+    ///
+    /// #[derive(Debug)]
+    /// struct MyNode<'a> {
+    ///     name: &'a str,
+    /// }
+    ///
+    /// impl<'a> Node<'a> for MyNode<'a> {
+    ///     fn id(&self) -> &'a str {
+    ///         self.name
+    ///     }
+    /// }
+    ///
+    /// let mut hash_ring = HashRing::new(Config::default()).unwrap();
+    /// hash_ring.add_node(Arc::new(MyNode { name: "node1" })).unwrap();
+    ///
+    /// let pooled_ring = PooledRing::new(hash_ring, 4, |node| Ok(format!("connection to {}", node.id())));
+    /// let conn = pooled_ring.get_conn_for_key(b"some_key").unwrap();
+    /// assert_eq!(*conn, "connection to node1");
+    /// ```
+    pub fn new<F>(hash_ring: HashRing<'a, H, K>, max_idle: usize, connect: F) -> PooledRing<'a, C, H, K>
+    where
+        F: Fn(&Arc<dyn Node<'a> + 'a>) -> Result<C, Box<dyn Error>> + Send + Sync + 'a,
+    {
+        let version = hash_ring.version();
+        PooledRing {
+            hash_ring,
+            connect: Box::new(connect),
+            max_idle,
+            pools: Mutex::new(Pools {
+                version,
+                idle: HashMap::new(),
+            }),
+        }
+    }
+
+    /// Returns the ring routing keys to nodes.
+    pub fn ring(&self) -> &HashRing<'a, H, K> {
+        &self.hash_ring
+    }
+
+    /// Returns a connection to the owner of the key, reusing an idle one if possible.
+    ///
+    /// The connection goes back to the pool when dropped, unless it is discarded or its
+    /// node left the ring in the meantime.
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - The key to route.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<PooledConn<'_, 'a, C, H, K>, Box<dyn Error>>` - The connection, or an error if the
+    ///   ring is empty or the connection could not be opened.
+    pub fn get_conn_for_key(&self, key: &[u8]) -> Result<PooledConn<'_, 'a, C, H, K>, Box<dyn Error>> {
+        let node = self.hash_ring.get_key(key).ok_or("no nodes in the ring")?;
+        self.get_conn(&node)
+    }
+
+    /// Returns a connection to the given node, reusing an idle one if possible.
+    pub fn get_conn(&self, node: &Arc<dyn Node<'a> + 'a>) -> Result<PooledConn<'_, 'a, C, H, K>, Box<dyn Error>> {
+        let idle = {
+            let mut pools = sync::lock(&self.pools);
+            self.evict_departed(&mut pools);
+            pools.idle.get_mut(node.id()).and_then(Vec::pop)
+        };
+        let conn = match idle {
+            Some(conn) => conn,
+            None => (self.connect)(node)?,
+        };
+        Ok(PooledConn {
+            pooled_ring: self,
+            node: node.clone(),
+            conn: Some(conn),
+        })
+    }
+
+    /// Returns the number of idle connections to the node.
+    pub fn idle_count(&self, node_id: &str) -> usize {
+        let mut pools = sync::lock(&self.pools);
+        self.evict_departed(&mut pools);
+        pools.idle.get(node_id).map_or(0, Vec::len)
+    }
+
+    /// Closes every idle connection to the node.
+    pub fn evict(&self, node_id: &str) {
+        sync::lock(&self.pools).idle.remove(node_id);
+    }
+
+    /// Drops the pools of nodes that are no longer in the ring, if the ring changed.
+    fn evict_departed(&self, pools: &mut Pools<C>) {
+        let version = self.hash_ring.version();
+        if pools.version == version {
+            return;
+        }
+        let nodes = self.hash_ring.nodes.wait_read();
+        pools.idle.retain(|id, _| nodes.contains_key(id));
+        pools.version = version;
+    }
+
+    /// Puts a connection back into the pool of its node.
+    fn release(&self, node: &Arc<dyn Node<'a> + 'a>, conn: C) {
+        let mut pools = sync::lock(&self.pools);
+        self.evict_departed(&mut pools);
+        if !self.hash_ring.nodes.wait_read().contains_key(node.id()) {
+            return;
+        }
+        let idle = pools.idle.entry(node.id().to_string()).or_default();
+        if idle.len() < self.max_idle {
+            idle.push(conn);
+        }
+    }
+}
+
+impl<'a, C, H, K> fmt::Debug for PooledRing<'a, C, H, K>
+where
+    H: fmt::Debug,
+    K: fmt::Debug,
+{
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt.debug_struct("PooledRing").field("hash_ring", &self.hash_ring).field("max_idle", &self.max_idle).finish_non_exhaustive()
+    }
+}
+
+/// A connection borrowed from a `PooledRing`, returned to its pool when dropped.
+pub struct PooledConn<'p, 'a, C, H = XxHash64Hasher, K = H>
+where
+    H: BuildHasher,
+    K: BuildHasher,
+{
+    pooled_ring: &'p PooledRing<'a, C, H, K>,
+    node: Arc<dyn Node<'a> + 'a>,
+    conn: Option<C>,
+}
+
+impl<'p, 'a, C, H, K> PooledConn<'p, 'a, C, H, K>
+where
+    H: BuildHasher,
+    K: BuildHasher,
+{
+    /// Returns the node the connection is open to.
+    pub fn node(&self) -> &Arc<dyn Node<'a> + 'a> {
+        &self.node
+    }
+
+    /// Closes the connection instead of returning it to the pool, such as after an I/O error.
+    pub fn discard(mut self) {
+        self.conn.take();
+    }
+}
+
+impl<'p, 'a, C, H, K> Deref for PooledConn<'p, 'a, C, H, K>
+where
+    H: BuildHasher,
+    K: BuildHasher,
+{
+    type Target = C;
+
+    fn deref(&self) -> &C {
+        self.conn.as_ref().unwrap()
+    }
+}
+
+impl<'p, 'a, C, H, K> DerefMut for PooledConn<'p, 'a, C, H, K>
+where
+    H: BuildHasher,
+    K: BuildHasher,
+{
+    fn deref_mut(&mut self) -> &mut C {
+        self.conn.as_mut().unwrap()
+    }
+}
+
+impl<'p, 'a, C, H, K> Drop for PooledConn<'p, 'a, C, H, K>
+where
+    H: BuildHasher,
+    K: BuildHasher,
+{
+    fn drop(&mut self) {
+        if let Some(conn) = self.conn.take() {
+            self.pooled_ring.release(&self.node, conn);
+        }
+    }
+}
+
+impl<'p, 'a, C: fmt::Debug, H, K> fmt::Debug for PooledConn<'p, 'a, C, H, K>
+where
+    H: BuildHasher,
+    K: BuildHasher,
+{
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt.debug_struct("PooledConn").field("node", &self.node).field("conn", &self.conn).finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    use crate::tests::test_nodes;
+    use crate::{Config, HashRing, PooledRing};
+
+    #[test]
+    fn test_connections_are_pooled_per_node() {
+        let mut hash_ring = HashRing::new(Config::default()).unwrap();
+        for node in test_nodes() {
+            hash_ring.add_node(node).unwrap();
+        }
+        let opened = Arc::new(AtomicUsize::new(0));
+        let counter = opened.clone();
+        let pooled_ring = PooledRing::new(hash_ring.share(), 1, move |node| Ok((node.id().to_string(), counter.fetch_add(1, Ordering::SeqCst))));
+
+        let owner = hash_ring.get_key(b"some_key").unwrap();
+        let conn = pooled_ring.get_conn_for_key(b"some_key").unwrap();
+        assert_eq!(conn.0, owner.id());
+        assert_eq!(conn.node().id(), owner.id());
+        let other = pooled_ring.get_conn_for_key(b"some_key").unwrap();
+        assert_eq!(opened.load(Ordering::SeqCst), 2);
+        drop(conn);
+        drop(other);
+        assert_eq!(pooled_ring.idle_count(owner.id()), 1);
+
+        // The idle connection is reused, and discarded ones are not returned.
+        let conn = pooled_ring.get_conn_for_key(b"some_key").unwrap();
+        assert_eq!(conn.1, 0);
+        conn.discard();
+        assert_eq!(pooled_ring.idle_count(owner.id()), 0);
+        drop(pooled_ring.get_conn_for_key(b"some_key").unwrap());
+        assert_eq!(opened.load(Ordering::SeqCst), 3);
+
+        // Removing the node from a handle sharing the ring closes its pool.
+        hash_ring.remove_node(owner.id()).unwrap();
+        assert_eq!(pooled_ring.idle_count(owner.id()), 0);
+        assert_ne!(pooled_ring.get_conn_for_key(b"some_key").unwrap().node().id(), owner.id());
+    }
+}
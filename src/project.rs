@@ -0,0 +1,104 @@
+//! Rings restricted to a subset of the nodes.
+//!
+//! Some workloads only run on part of the cluster, such as the nodes with SSDs or the
+//! nodes of one region. `project` builds a ring holding only the nodes matching a
+//! predicate, with the configuration and hashers of the original, so virtual nodes sit
+//! at the same points: a key owned by a matching node in the original ring has the same
+//! owner in the projection, and only the keys of the other nodes are redistributed.
+
+use std::error::Error;
+use std::hash::BuildHasher;
+
+use crate::{HashRing, Node};
+
+impl<'a, H, K> HashRing<'a, H, K>
+where
+    H: BuildHasher + Clone,
+    K: BuildHasher + Clone,
+{
+    /// Returns a new ring containing the nodes matching `predicate`.
+    ///
+    /// The projection is independent of this ring: later membership changes aren't
+    /// reflected in it.
+    ///
+    /// # Arguments
+    ///
+    /// * `predicate` - Whether a node belongs to the projection.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use std::sync::Arc;
+    /// use hashring::{HashRing, Config, Node};
+    ///
+    /// #[derive(Debug)]
+    /// struct MyNode<'a> {
+    ///     name: &'a str,
+    ///     region: &'a str,
+    /// }
+    ///
+    /// impl<'a> Node<'a> for MyNode<'a> {
+    ///     fn id(&self) -> &'a str {
+    ///         self.name
+    ///     }
+    ///
+    ///     fn zone(&self) -> Option<&'a str> {
+    ///         Some(self.region)
+    ///     }
+    /// }
+    ///
+    /// let mut hash_ring = HashRing::new(Config::default()).unwrap();
+    /// hash_ring.add_node(Arc::new(MyNode { name: "node1", region: "eu-west" })).unwrap();
+    /// hash_ring.add_node(Arc::new(MyNode { name: "node2", region: "us-east" })).unwrap();
+    ///
+    /// let eu_west = hash_ring.project(|node| node.zone() == Some("eu-west")).unwrap();
+    /// assert_eq!(eu_west.get_key(b"some_key").unwrap().id(), "node1");
+    /// ```
+    pub fn project<F>(&self, predicate: F) -> Result<HashRing<'a, H, K>, Box<dyn Error>>
+    where
+        F: Fn(&dyn Node<'a>) -> bool,
+    {
+        let matching: Vec<_> = self
+            .nodes.read()?
+            .values()
+            .filter(|node| predicate(node.as_ref()))
+            .cloned()
+            .collect();
+        let mut projection = HashRing::with_hashers(self.config().as_ref().clone(), self.node_hasher.clone(), self.key_hasher.clone())?;
+        projection.transaction(|tx| {
+            for node in matching {
+                tx.add(node)?;
+            }
+            Ok(())
+        })?;
+        Ok(projection)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::tests::test_nodes;
+    use crate::{Config, HashRing};
+
+    #[test]
+    fn test_projection_keeps_owners_of_matching_nodes() {
+        let mut hash_ring = HashRing::new(Config::default()).unwrap();
+        for node in test_nodes() {
+            hash_ring.add_node(node).unwrap();
+        }
+        let projection = hash_ring.project(|node| node.id() != "node2").unwrap();
+        assert_eq!(projection.virtual_nodes_per_node().len(), 2);
+        assert_eq!(hash_ring.virtual_nodes_per_node().len(), 3);
+
+        for i in 0..200 {
+            let key = format!("key-{}", i);
+            let owner = hash_ring.get_key(key.as_bytes()).unwrap();
+            let projected = projection.get_key(key.as_bytes()).unwrap();
+            assert_ne!(projected.id(), "node2");
+            if owner.id() != "node2" {
+                assert_eq!(projected.id(), owner.id());
+            }
+        }
+        assert!(hash_ring.project(|_| false).unwrap().get_key(b"key").is_none());
+    }
+}
@@ -0,0 +1,407 @@
+//! Protocol Buffers messages for exchanging ring state, behind the `proto` feature.
+//!
+//! The message types are generated from `proto/ring.proto` and checked in, so building
+//! the crate doesn't require `protoc`. Services can serve them over gRPC as they are;
+//! the conversions below translate them to and from the ring.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::error::Error;
+use std::hash::BuildHasher;
+use std::sync::Arc;
+
+use crate::delta::Delta;
+use crate::{HashRing, Node};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, ::prost::Enumeration)]
+#[repr(i32)]
+pub enum CollisionPolicy {
+    Rehash = 0,
+    Error = 1,
+    KeepFirst = 2,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, ::prost::Enumeration)]
+#[repr(i32)]
+pub enum RingFormatVersion {
+    V1 = 0,
+    V2 = 1,
+}
+
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Config {
+    #[prost(uint64, tag = "1")]
+    pub replication_factor: u64,
+    #[prost(uint64, tag = "2")]
+    pub partition_count: u64,
+    #[prost(double, optional, tag = "3")]
+    pub rebalance_skew_threshold: ::core::option::Option<f64>,
+    #[prost(enumeration = "CollisionPolicy", tag = "4")]
+    pub collision_policy: i32,
+    #[prost(string, optional, tag = "5")]
+    pub key_namespace: ::core::option::Option<::prost::alloc::string::String>,
+    #[prost(enumeration = "RingFormatVersion", tag = "6")]
+    pub format_version: i32,
+}
+
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct AffinityGroup {
+    #[prost(string, tag = "1")]
+    pub name: ::prost::alloc::string::String,
+    /// The node the group is pinned to, empty when it is routed by hashing its name.
+    #[prost(string, tag = "2")]
+    pub node: ::prost::alloc::string::String,
+}
+
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct PartitionAssignment {
+    #[prost(uint64, tag = "1")]
+    pub partition: u64,
+    /// Empty when the partition lost its owner.
+    #[prost(string, tag = "2")]
+    pub node: ::prost::alloc::string::String,
+}
+
+/// The full topology of a ring.
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct RingSnapshot {
+    #[prost(message, optional, tag = "1")]
+    pub config: ::core::option::Option<Config>,
+    #[prost(string, repeated, tag = "2")]
+    pub nodes: ::prost::alloc::vec::Vec<::prost::alloc::string::String>,
+    #[prost(message, repeated, tag = "3")]
+    pub partitions: ::prost::alloc::vec::Vec<PartitionAssignment>,
+    #[prost(uint64, repeated, tag = "4")]
+    pub splits: ::prost::alloc::vec::Vec<u64>,
+    /// The version of the ring the snapshot was taken at.
+    #[prost(uint64, tag = "5")]
+    pub version: u64,
+    #[prost(message, repeated, tag = "6")]
+    pub groups: ::prost::alloc::vec::Vec<AffinityGroup>,
+    /// Partitions moved by a rebalance, whose keys are all routed to their owner.
+    #[prost(uint64, repeated, tag = "7")]
+    pub moved: ::prost::alloc::vec::Vec<u64>,
+    /// The weights of the members below full weight.
+    #[prost(btree_map = "string, double", tag = "8")]
+    pub weights: ::prost::alloc::collections::BTreeMap<::prost::alloc::string::String, f64>,
+}
+
+/// The net changes between two versions of a ring.
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct RingDelta {
+    #[prost(uint64, tag = "1")]
+    pub from_version: u64,
+    #[prost(uint64, tag = "2")]
+    pub to_version: u64,
+    #[prost(message, optional, tag = "3")]
+    pub config: ::core::option::Option<Config>,
+    #[prost(string, repeated, tag = "4")]
+    pub added_nodes: ::prost::alloc::vec::Vec<::prost::alloc::string::String>,
+    #[prost(string, repeated, tag = "5")]
+    pub removed_nodes: ::prost::alloc::vec::Vec<::prost::alloc::string::String>,
+    #[prost(message, repeated, tag = "6")]
+    pub partitions: ::prost::alloc::vec::Vec<PartitionAssignment>,
+    #[prost(uint64, repeated, tag = "7")]
+    pub splits: ::prost::alloc::vec::Vec<u64>,
+    /// The weights of the members below full weight.
+    #[prost(btree_map = "string, double", tag = "8")]
+    pub weights: ::prost::alloc::collections::BTreeMap<::prost::alloc::string::String, f64>,
+}
+
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct LookupRequest {
+    #[prost(bytes = "vec", tag = "1")]
+    pub key: ::prost::alloc::vec::Vec<u8>,
+    /// The number of distinct nodes to return, including the owner.
+    #[prost(uint32, tag = "2")]
+    pub replicas: u32,
+}
+
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct LookupResponse {
+    /// The owner first, followed by the other replicas.
+    #[prost(string, repeated, tag = "1")]
+    pub nodes: ::prost::alloc::vec::Vec<::prost::alloc::string::String>,
+    #[prost(uint64, tag = "2")]
+    pub partition: u64,
+    #[prost(uint64, tag = "3")]
+    pub version: u64,
+}
+
+impl From<&crate::Config> for Config {
+    fn from(config: &crate::Config) -> Config {
+        Config {
+            replication_factor: config.replication_factor as u64,
+            partition_count: config.partition_count as u64,
+            rebalance_skew_threshold: config.rebalance_skew_threshold,
+            collision_policy: match config.collision_policy {
+                crate::CollisionPolicy::Rehash => CollisionPolicy::Rehash,
+                crate::CollisionPolicy::Error => CollisionPolicy::Error,
+                crate::CollisionPolicy::KeepFirst => CollisionPolicy::KeepFirst,
+            } as i32,
+            key_namespace: config.key_namespace.clone(),
+            format_version: match config.format_version {
+                crate::RingFormatVersion::V1 => RingFormatVersion::V1,
+                crate::RingFormatVersion::V2 => RingFormatVersion::V2,
+            } as i32,
+        }
+    }
+}
+
+impl TryFrom<Config> for crate::Config {
+    type Error = Box<dyn Error>;
+
+    fn try_from(config: Config) -> Result<crate::Config, Box<dyn Error>> {
+        let collision_policy = CollisionPolicy::try_from(config.collision_policy)
+            .map_err(|_| format!("unknown collision policy {}", config.collision_policy))?;
+        let format_version = RingFormatVersion::try_from(config.format_version)
+            .map_err(|_| format!("unsupported ring format version {}", config.format_version + 1))?;
+        Ok(crate::Config {
+            replication_factor: config.replication_factor as usize,
+            partition_count: config.partition_count as usize,
+            rebalance_skew_threshold: config.rebalance_skew_threshold,
+            collision_policy: match collision_policy {
+                CollisionPolicy::Rehash => crate::CollisionPolicy::Rehash,
+                CollisionPolicy::Error => crate::CollisionPolicy::Error,
+                CollisionPolicy::KeepFirst => crate::CollisionPolicy::KeepFirst,
+            },
+            key_namespace: config.key_namespace,
+            node_id_policy: crate::NodeIdPolicy::default(),
+            format_version: match format_version {
+                RingFormatVersion::V1 => crate::RingFormatVersion::V1,
+                RingFormatVersion::V2 => crate::RingFormatVersion::V2,
+            },
+            capacity_headroom: crate::capacity::DEFAULT_CAPACITY_HEADROOM,
+        })
+    }
+}
+
+impl From<&crate::RingSnapshot> for RingSnapshot {
+    fn from(snapshot: &crate::RingSnapshot) -> RingSnapshot {
+        RingSnapshot {
+            config: Some(Config::from(&snapshot.config)),
+            nodes: snapshot.nodes.clone(),
+            partitions: snapshot
+                .partitions
+                .iter()
+                .map(|(part_id, id)| PartitionAssignment {
+                    partition: *part_id as u64,
+                    node: id.clone(),
+                })
+                .collect(),
+            splits: snapshot.splits.iter().map(|part_id| *part_id as u64).collect(),
+            version: 0,
+            groups: snapshot
+                .groups
+                .iter()
+                .map(|(name, pinned)| AffinityGroup {
+                    name: name.clone(),
+                    node: pinned.clone().unwrap_or_default(),
+                })
+                .collect(),
+            moved: snapshot.moved.iter().map(|part_id| *part_id as u64).collect(),
+            weights: snapshot.weights.clone(),
+        }
+    }
+}
+
+impl TryFrom<RingSnapshot> for crate::RingSnapshot {
+    type Error = Box<dyn Error>;
+
+    fn try_from(snapshot: RingSnapshot) -> Result<crate::RingSnapshot, Box<dyn Error>> {
+        Ok(crate::RingSnapshot {
+            config: snapshot.config.ok_or("snapshot has no config")?.try_into()?,
+            nodes: snapshot.nodes,
+            partitions: snapshot
+                .partitions
+                .into_iter()
+                .filter(|assignment| !assignment.node.is_empty())
+                .map(|assignment| (assignment.partition as usize, assignment.node))
+                .collect(),
+            splits: snapshot.splits.into_iter().map(|part_id| part_id as usize).collect(),
+            groups: snapshot
+                .groups
+                .into_iter()
+                .map(|group| (group.name, Some(group.node).filter(|id| !id.is_empty())))
+                .collect(),
+            moved: snapshot.moved.into_iter().map(|part_id| part_id as usize).collect(),
+            weights: snapshot.weights,
+        })
+    }
+}
+
+impl From<Delta> for RingDelta {
+    fn from(delta: Delta) -> RingDelta {
+        RingDelta {
+            from_version: delta.from,
+            to_version: delta.to,
+            config: Some(Config::from(&delta.config)),
+            added_nodes: delta.added,
+            removed_nodes: delta.removed,
+            partitions: delta
+                .partitions
+                .into_iter()
+                .map(|(part_id, owner)| PartitionAssignment {
+                    partition: part_id as u64,
+                    node: owner.unwrap_or_default(),
+                })
+                .collect(),
+            splits: delta.splits.into_iter().map(|part_id| part_id as u64).collect(),
+            weights: delta.weights,
+        }
+    }
+}
+
+impl TryFrom<RingDelta> for Delta {
+    type Error = Box<dyn Error>;
+
+    fn try_from(delta: RingDelta) -> Result<Delta, Box<dyn Error>> {
+        let partitions: BTreeMap<usize, Option<String>> = delta
+            .partitions
+            .into_iter()
+            .map(|assignment| (assignment.partition as usize, Some(assignment.node).filter(|id| !id.is_empty())))
+            .collect();
+        let splits: BTreeSet<usize> = delta.splits.into_iter().map(|part_id| part_id as usize).collect();
+        Ok(Delta {
+            from: delta.from_version,
+            to: delta.to_version,
+            config: delta.config.ok_or("delta has no config")?.try_into()?,
+            added: delta.added_nodes,
+            removed: delta.removed_nodes,
+            partitions,
+            splits,
+            weights: delta.weights,
+        })
+    }
+}
+
+impl<'a, H, K> HashRing<'a, H, K>
+where
+    H: BuildHasher,
+    K: BuildHasher,
+{
+    /// Captures the current topology as a `RingSnapshot` message, stamped with the current version.
+    pub fn proto_snapshot(&self) -> RingSnapshot {
+        let version = self.version();
+        RingSnapshot {
+            version,
+            ..RingSnapshot::from(&self.snapshot())
+        }
+    }
+
+    /// Replaces the topology with the one in a `RingSnapshot` message, taking the version
+    /// of the sending ring so that its deltas apply afterwards.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<u64, Box<dyn Error>>` - The version of the sending ring, to request deltas from.
+    pub fn restore_proto<F>(&mut self, snapshot: RingSnapshot, resolver: F) -> Result<u64, Box<dyn Error>>
+    where
+        F: FnMut(&str) -> Option<Arc<dyn Node<'a> + 'a>>,
+    {
+        let version = snapshot.version;
+        self.restore_at(&snapshot.try_into()?, resolver, Some(version))?;
+        Ok(version)
+    }
+
+    /// Returns the changes made after `since_version` as a `RingDelta` message.
+    ///
+    /// See `snapshot_delta` for the binary equivalent.
+    pub fn proto_delta(&self, since_version: u64) -> Result<RingDelta, Box<dyn Error>> {
+        self.delta(since_version).map(RingDelta::from)
+    }
+
+    /// Applies a `RingDelta` message produced by `proto_delta` on another ring.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<u64, Box<dyn Error>>` - The version of the sending ring the delta brings this ring to.
+    pub fn apply_proto_delta<F>(&mut self, delta: RingDelta, resolver: F) -> Result<u64, Box<dyn Error>>
+    where
+        F: FnMut(&str) -> Option<Arc<dyn Node<'a> + 'a>>,
+    {
+        self.apply(delta.try_into()?, resolver)
+    }
+
+    /// Answers a `LookupRequest` from the ring.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use std::sync::Arc;
+    /// use hashring::proto::LookupRequest;
+    /// use hashring::{HashRing, Config, Node};
+    ///
+    /// #[derive(Debug)]
+    /// struct MyNode<'a> {
+    ///     name: &'a str,
+    /// }
+    ///
+    /// impl<'a> Node<'a> for MyNode<'a> {
+    ///     fn id(&self) -> &'a str {
+    ///         self.name
+    ///     }
+    /// }
+    ///
+    /// let mut hash_ring = HashRing::new(Config::default()).unwrap();
+    /// hash_ring.add_node(Arc::new(MyNode { name: "node1" })).unwrap();
+    /// hash_ring.add_node(Arc::new(MyNode { name: "node2" })).unwrap();
+    ///
+    /// let response = hash_ring.lookup_response(&LookupRequest {
+    ///     key: b"some_key".to_vec(),
+    ///     replicas: 2,
+    /// });
+    /// assert_eq!(response.nodes.len(), 2);
+    /// ```
+    pub fn lookup_response(&self, request: &LookupRequest) -> LookupResponse {
+        let replicas = (request.replicas as usize).max(1);
+        LookupResponse {
+            nodes: self
+                .get_preference_list_n(&request.key, replicas)
+                .iter()
+                .map(|node| node.id().to_string())
+                .collect(),
+            partition: self.get_partition(&request.key) as u64,
+            version: self.version(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use prost::Message;
+
+    use super::*;
+    use crate::tests::test_nodes;
+    use crate::Config;
+
+    #[test]
+    fn test_snapshot_and_delta_roundtrip() {
+        let nodes = test_nodes();
+        let resolve = |id: &str| nodes.iter().find(|n| n.id() == id).map(|n| n.clone() as Arc<dyn Node<'static>>);
+
+        let mut leader = HashRing::new(Config::default()).unwrap();
+        leader.set_changelog_capacity(16);
+        leader.add_node(nodes[0].clone()).unwrap();
+        leader.add_node(nodes[1].clone()).unwrap();
+
+        let snapshot = RingSnapshot::decode(leader.proto_snapshot().encode_to_vec().as_slice()).unwrap();
+        let mut follower = HashRing::new(Config::default()).unwrap();
+        let seen = follower.restore_proto(snapshot, resolve).unwrap();
+        assert_eq!(seen, leader.version());
+
+        leader.add_node(nodes[2].clone()).unwrap();
+        leader.remove_node("node1").unwrap();
+        let delta = RingDelta::decode(leader.proto_delta(seen).unwrap().encode_to_vec().as_slice()).unwrap();
+        assert_eq!(follower.apply_proto_delta(delta, resolve).unwrap(), leader.version());
+
+        assert_eq!(follower.snapshot().nodes, leader.snapshot().nodes);
+        assert_eq!(follower.snapshot().partitions, leader.snapshot().partitions);
+        let request = LookupRequest {
+            key: b"some_key".to_vec(),
+            replicas: 2,
+        };
+        assert_eq!(follower.lookup_response(&request).nodes, leader.lookup_response(&request).nodes);
+    }
+}
@@ -0,0 +1,188 @@
+//! Dynamo-style read and write quorums over a preference list.
+//!
+//! With `N` replicas per key, a write is acknowledged by `W` of them and a read
+//! consults `R` of them. As long as `R + W > N`, every read overlaps the latest
+//! successful write. The first `W` (or `R`) replicas are the ones to contact;
+//! the rest of the `N` stand by in case some of them fail.
+
+use std::collections::HashSet;
+use std::error::Error;
+
+use crate::{Replica, ReplicaSet};
+
+/// Validated `N`, `R` and `W` parameters.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Quorum {
+    n: usize,
+    r: usize,
+    w: usize,
+}
+
+/// The replicas of a key split for one read or write.
+#[derive(Debug, Clone)]
+pub struct QuorumSet<'a> {
+    /// The replicas to contact first.
+    pub required: Vec<Replica<'a>>,
+    /// The remaining replicas, in the order to fall back to them.
+    pub optional: Vec<Replica<'a>>,
+}
+
+impl<'a> QuorumSet<'a> {
+    /// Returns the number of acknowledgements needed.
+    pub fn threshold(&self) -> usize {
+        self.required.len()
+    }
+
+    /// Returns `true` if enough distinct replicas of the set acknowledged.
+    pub fn is_met<'i, I>(&self, acks: I) -> bool
+    where
+        I: IntoIterator<Item = &'i str>,
+    {
+        let members: HashSet<&str> = self.required.iter().chain(&self.optional).map(|r| r.node.id()).collect();
+        let acked: HashSet<&str> = acks.into_iter().filter(|id| members.contains(id)).collect();
+        acked.len() >= self.threshold()
+    }
+}
+
+impl Quorum {
+    /// Creates a quorum of `n` replicas with read quorum `r` and write quorum `w`.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Quorum, Box<dyn Error>>` - An error unless `r` and `w` are between 1 and `n`
+    ///   and `r + w > n`.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use std::sync::Arc;
+    /// use hashring::{HashRing, Config, Node, Quorum};
+    ///
+    /// #[derive(Debug)]
+    /// struct MyNode<'a> {
+    ///     name: &'a str,
+    /// }
+    ///
+    /// impl<'a> Node<'a> for MyNode<'a> {
+    ///     fn id(&self) -> &'a str {
+    ///         self.name
+    ///     }
+    /// }
+    ///
+    /// let mut config = Config::default();
+    /// config.replication_factor = 3;
+    /// let mut hash_ring = HashRing::new(config).unwrap();
+    /// for name in ["node1", "node2", "node3"] {
+    ///     hash_ring.add_node(Arc::new(MyNode { name })).unwrap();
+    /// }
+    ///
+    /// let quorum = Quorum::new(3, 2, 2).unwrap();
+    /// let write = quorum.write_quorum(&hash_ring.get_preference_list(b"some_key")).unwrap();
+    /// assert_eq!(write.required.len(), 2);
+    /// assert_eq!(write.optional.len(), 1);
+    ///
+    /// let acks: Vec<&str> = write.required.iter().map(|r| r.node.id()).collect();
+    /// assert!(write.is_met(acks));
+    /// ```
+    pub fn new(n: usize, r: usize, w: usize) -> Result<Quorum, Box<dyn Error>> {
+        if r == 0 || w == 0 || r > n || w > n {
+            return Err(format!("read and write quorums must be between 1 and {}", n).into());
+        }
+        if r + w <= n {
+            return Err(format!("R + W must be greater than N, got {} + {} <= {}", r, w, n).into());
+        }
+        Ok(Quorum { n, r, w })
+    }
+
+    pub fn n(&self) -> usize {
+        self.n
+    }
+
+    pub fn r(&self) -> usize {
+        self.r
+    }
+
+    pub fn w(&self) -> usize {
+        self.w
+    }
+
+    /// Splits the first `N` replicas into the `W` to write to and the rest.
+    pub fn write_quorum<'a>(&self, replicas: &ReplicaSet<'a>) -> Result<QuorumSet<'a>, Box<dyn Error>> {
+        split(replicas, self.n, self.w)
+    }
+
+    /// Splits the first `N` replicas into the `R` to read from and the rest.
+    pub fn read_quorum<'a>(&self, replicas: &ReplicaSet<'a>) -> Result<QuorumSet<'a>, Box<dyn Error>> {
+        split(replicas, self.n, self.r)
+    }
+}
+
+impl<'a> ReplicaSet<'a> {
+    /// Splits the replicas into the `w` to write to and the rest, with `N` being the size of the set.
+    ///
+    /// Use `Quorum` to also check that reads and writes overlap.
+    pub fn write_quorum(&self, w: usize) -> Result<QuorumSet<'a>, Box<dyn Error>> {
+        split(self, self.len(), w)
+    }
+
+    /// Splits the replicas into the `r` to read from and the rest, with `N` being the size of the set.
+    ///
+    /// Use `Quorum` to also check that reads and writes overlap.
+    pub fn read_quorum(&self, r: usize) -> Result<QuorumSet<'a>, Box<dyn Error>> {
+        split(self, self.len(), r)
+    }
+}
+
+fn split<'a>(replicas: &ReplicaSet<'a>, n: usize, required: usize) -> Result<QuorumSet<'a>, Box<dyn Error>> {
+    if required == 0 || required > n {
+        return Err(format!("quorum must be between 1 and {}", n).into());
+    }
+    if replicas.len() < required {
+        return Err(format!("quorum of {} needs more than the {} available replicas", required, replicas.len()).into());
+    }
+    let mut replicas: Vec<Replica<'a>> = replicas.iter().take(n).cloned().collect();
+    let optional = replicas.split_off(required);
+    Ok(QuorumSet {
+        required: replicas,
+        optional,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::tests::test_nodes;
+    use crate::{Config, HashRing, Quorum};
+
+    #[test]
+    fn test_quorum_validation() {
+        assert!(Quorum::new(3, 2, 2).is_ok());
+        assert!(Quorum::new(3, 1, 3).is_ok());
+        assert!(Quorum::new(3, 1, 2).is_err());
+        assert!(Quorum::new(3, 0, 3).is_err());
+        assert!(Quorum::new(3, 4, 1).is_err());
+    }
+
+    #[test]
+    fn test_quorum_splits_preference_list() {
+        let mut hash_ring = HashRing::new(Config {
+            replication_factor: 3,
+            ..Config::default()
+        })
+        .unwrap();
+        for node in test_nodes() {
+            hash_ring.add_node(node).unwrap();
+        }
+        let replicas = hash_ring.get_preference_list(b"some_key");
+
+        let read = Quorum::new(3, 1, 3).unwrap().read_quorum(&replicas).unwrap();
+        assert_eq!(read.required[0].node.id(), replicas.primary().unwrap().node.id());
+        assert_eq!(read.optional.len(), 2);
+        assert!(read.is_met([read.optional[1].node.id()]));
+        assert!(!read.is_met(["unknown"]));
+
+        let write = replicas.write_quorum(3).unwrap();
+        assert!(write.optional.is_empty());
+        assert!(!write.is_met(replicas.iter().take(2).map(|r| r.node.id())));
+        assert!(replicas.write_quorum(4).is_err());
+    }
+}
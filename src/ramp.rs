@@ -0,0 +1,294 @@
+//! Ramping new nodes into the ring.
+//!
+//! A node joining with a cold cache would be flooded if it took over its full share of
+//! keys at once. `join_gradually` adds it without virtual nodes and `tick` places more
+//! of them as the ramp period elapses, so its share grows linearly from zero to full.
+//! Ticks are driven by the caller, or by a tokio task with the `tokio` feature.
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::hash::BuildHasher;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::trace::OpTimer;
+use crate::{HashRing, Node};
+
+#[derive(Debug, Clone)]
+pub(crate) struct Ramp {
+    started: Instant,
+    duration: Duration,
+    /// The index of the next replica to place.
+    next: usize,
+    /// The number of virtual nodes placed, fewer than `next` if some collided.
+    placed: usize,
+}
+
+impl Ramp {
+    /// Adjusts the ramp to a new replication factor, once `removed` of its virtual nodes
+    /// beyond it were removed.
+    pub(crate) fn resize(&mut self, replication_factor: usize, removed: usize) {
+        self.next = self.next.min(replication_factor);
+        self.placed -= removed;
+    }
+}
+
+impl<'a, H, K> HashRing<'a, H, K>
+where
+    H: BuildHasher,
+    K: BuildHasher,
+{
+    /// Adds a node whose virtual nodes are placed progressively over `ramp`.
+    ///
+    /// The node starts without virtual nodes, so it owns no keys until the next `tick`.
+    /// A node joining an empty ring is added at full weight, since no other node could
+    /// serve its keys.
+    ///
+    /// # Arguments
+    ///
+    /// * `node` - The node to add.
+    /// * `ramp` - The time until the node owns its full share of keys.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use std::sync::Arc;
+    /// use std::time::Duration;
+    /// use hashring::{HashRing, Config, Node};
+    ///
+    /// #[derive(Debug)]
+    /// struct MyNode<'a> {
+    ///     name: &'a str,
+    /// }
+    ///
+    /// impl<'a> Node<'a> for MyNode<'a> {
+    ///     fn id(&self) -> &'a str {
+    ///         self.name
+    ///     }
+    /// }
+    ///
+    /// let mut hash_ring = HashRing::new(Config::default()).unwrap();
+    /// hash_ring.add_node(Arc::new(MyNode { name: "node1" })).unwrap();
+    ///
+    /// hash_ring.join_gradually(Arc::new(MyNode { name: "node2" }), Duration::from_secs(600)).unwrap();
+    /// assert_eq!(hash_ring.get_key(b"some_key").unwrap().id(), "node1");
+    /// assert_eq!(hash_ring.ramp_progress("node2"), Some(0.0));
+    ///
+    /// // Called periodically, e.g. every few seconds.
+    /// hash_ring.tick().unwrap();
+    /// ```
+    pub fn join_gradually(&mut self, node: Arc<dyn Node<'a> + 'a>, ramp: Duration) -> Result<(), Box<dyn Error>> {
+        if self.sorted_nodes_hash_set.read()?.is_empty() {
+            self.add_node(node)?;
+            return Ok(());
+        }
+
+        let _events = self.dispatch_events();
+        let timer = OpTimer::start();
+        self.config().node_id_policy.validate(node.id())?;
+        let mut ramps = self.ramps.write()?;
+        let mut nodes = self.nodes.write()?;
+        if nodes.contains_key(node.id()) {
+            return Err("node already exist".into());
+        }
+        self.record_membership_change(node.id(), true)?;
+        nodes.insert(node.id().to_string(), node.clone());
+        let partitions = self.partitions.read()?;
+        self.bump_version();
+        self.log_changes("join_gradually", &timer, Some(&nodes), &partitions);
+        drop(partitions);
+        drop(nodes);
+
+        ramps.insert(
+            node.id().to_string(),
+            Ramp {
+                started: self.now(),
+                duration: ramp,
+                next: 0,
+                placed: 0,
+            },
+        );
+        Ok(())
+    }
+
+    /// Returns how far the node is into its ramp, from `0.0` to `1.0`, or `None` if it
+    /// is not ramping.
+    pub fn ramp_progress(&self, id: &str) -> Option<f64> {
+        let ramps = self.ramps.read().ok()?;
+        let ramp = ramps.get(id)?;
+        Some(ramp.next as f64 / self.config().replication_factor as f64)
+    }
+
+    /// Places the virtual nodes that ramping nodes are due by now.
+    ///
+    /// Ramps end once a node has all its virtual nodes, or when it is removed or
+    /// re-added in the meantime.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<usize, Box<dyn Error>>` - The number of virtual nodes placed.
+    pub fn tick(&mut self) -> Result<usize, Box<dyn Error>> {
+        self.tick_at(self.now())
+    }
+
+    pub(crate) fn tick_at(&mut self, now: Instant) -> Result<usize, Box<dyn Error>> {
+        let _events = self.dispatch_events();
+        let mut ramps = self.ramps.write()?;
+        if ramps.is_empty() {
+            return Ok(0);
+        }
+        let timer = OpTimer::start();
+        let nodes = self.nodes.read()?;
+        let mut sorted_set = self.sorted_nodes_hash_set.write()?;
+        let mut partitions = self.partitions.write()?;
+        let previous = self.keeps_history().then(|| sorted_set.clone());
+
+        let mut counts: HashMap<&str, usize> = HashMap::new();
+        for node in sorted_set.values() {
+            if ramps.contains_key(node.id()) {
+                *counts.entry(node.id()).or_default() += 1;
+            }
+        }
+
+        let replication_factor = self.config().replication_factor;
+        let mut placed = 0;
+        let mut result = Ok(());
+        ramps.retain(|id, ramp| {
+            let node = match nodes.get(id) {
+                Some(node) if result.is_ok() => node,
+                Some(_) => return true,
+                None => return false,
+            };
+            // A node removed and added back since its last tick has left its ramp.
+            if counts.get(id.as_str()).copied().unwrap_or(0) != ramp.placed {
+                return false;
+            }
+            let elapsed = now.saturating_duration_since(ramp.started);
+            let target = if elapsed >= ramp.duration {
+                replication_factor
+            } else {
+                (replication_factor as f64 * elapsed.as_secs_f64() / ramp.duration.as_secs_f64()) as usize
+            };
+            if target > ramp.next {
+                match self.place_vnodes(&mut sorted_set, node, ramp.next..target, self.config().collision_policy) {
+                    Ok(count) => {
+                        placed += count;
+                        ramp.next = target;
+                        ramp.placed += count;
+                    }
+                    Err(err) => {
+                        result = Err(err);
+                        return true;
+                    }
+                }
+            }
+            target < replication_factor
+        });
+
+        if placed > 0 {
+            if let Some(previous) = previous {
+                self.record_history(previous);
+            }
+            if self.is_updating() {
+                self.partitions_dirty.store(true, Ordering::Release);
+            } else {
+                *partitions = self.compute_partitions(&sorted_set, &partitions);
+            }
+            self.bump_version();
+            self.log_changes("tick", &timer, None, &partitions);
+        }
+        timer.finish("tick", nodes.len(), sorted_set.len());
+        result.map(|_| placed)
+    }
+
+    /// Spawns a task calling `tick` every `interval` until no node is ramping.
+    ///
+    /// The task works on a handle sharing the state of the ring. It must be called
+    /// from within a tokio runtime.
+    #[cfg(feature = "tokio")]
+    pub fn spawn_ramp(&self, interval: Duration) -> tokio::task::JoinHandle<()>
+    where
+        'a: 'static,
+        H: Clone + Send + Sync + 'static,
+        K: Clone + Send + Sync + 'static,
+    {
+        let mut hash_ring = self.share();
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            loop {
+                ticker.tick().await;
+                let _ = hash_ring.tick();
+                let done = match hash_ring.ramps.read() {
+                    Ok(ramps) => ramps.is_empty(),
+                    Err(_) => true,
+                };
+                if done {
+                    break;
+                }
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use crate::tests::test_nodes;
+    use crate::{Config, HashRing};
+
+    #[test]
+    fn test_join_gradually_ramps_vnodes() {
+        let nodes = test_nodes();
+        let mut hash_ring = HashRing::new(Config::default()).unwrap();
+        hash_ring.join_gradually(nodes[0].clone(), Duration::from_secs(100)).unwrap();
+        assert_eq!(hash_ring.ramp_progress("node1"), None);
+
+        let start = Instant::now();
+        hash_ring.join_gradually(nodes[1].clone(), Duration::from_secs(100)).unwrap();
+        assert!(hash_ring.join_gradually(nodes[1].clone(), Duration::from_secs(100)).is_err());
+        assert_eq!(hash_ring.virtual_nodes_per_node().get("node2"), None);
+
+        hash_ring.tick_at(start + Duration::from_secs(50)).unwrap();
+        let progress = hash_ring.ramp_progress("node2").unwrap();
+        assert!(progress > 0.4 && progress < 0.6, "{}", progress);
+
+        assert_eq!(hash_ring.tick_at(start + Duration::from_secs(200)).unwrap() as f64, 20.0 * (1.0 - progress));
+        assert_eq!(hash_ring.ramp_progress("node2"), None);
+        assert_eq!(hash_ring.virtual_nodes_per_node().get("node2"), Some(&20));
+        assert_eq!(hash_ring.tick().unwrap(), 0);
+
+        // Removing a ramping node ends its ramp.
+        hash_ring.join_gradually(nodes[2].clone(), Duration::from_secs(100)).unwrap();
+        hash_ring.remove_node("node3").unwrap();
+        assert_eq!(hash_ring.tick_at(start + Duration::from_secs(200)).unwrap(), 0);
+        assert_eq!(hash_ring.ramp_progress("node3"), None);
+    }
+
+    #[test]
+    fn test_replication_factor_changes_during_ramp() {
+        let nodes = test_nodes();
+        let mut hash_ring = HashRing::new(Config::default()).unwrap();
+        hash_ring.add_node(nodes[0].clone()).unwrap();
+        let start = Instant::now();
+        hash_ring.join_gradually(nodes[1].clone(), Duration::from_secs(100)).unwrap();
+        hash_ring.tick_at(start + Duration::from_secs(50)).unwrap();
+        let placed = hash_ring.virtual_nodes_per_node()["node2"];
+
+        // Growing the ring leaves the ramping node to its ramp, which now goes further.
+        let delta = hash_ring.set_replication_factor(40).unwrap();
+        assert_eq!(delta.added_vnodes, 20);
+        assert_eq!(hash_ring.virtual_nodes_per_node()["node2"], placed);
+        hash_ring.tick_at(start + Duration::from_secs(75)).unwrap();
+        let progress = hash_ring.ramp_progress("node2").unwrap();
+        assert!(progress > 0.7 && progress <= 0.75, "{}", progress);
+
+        // Shrinking it below the ramp's progress ends the ramp at the new factor.
+        hash_ring.set_replication_factor(10).unwrap();
+        assert_eq!(hash_ring.virtual_nodes_per_node()["node2"], 10);
+        assert_eq!(hash_ring.tick_at(start + Duration::from_secs(200)).unwrap(), 0);
+        assert_eq!(hash_ring.ramp_progress("node2"), None);
+        hash_ring.check_invariants().unwrap();
+    }
+}
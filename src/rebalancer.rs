@@ -0,0 +1,261 @@
+//! A background task rebalancing partitions by load, behind the `tokio` feature.
+//!
+//! The task wakes up at a fixed interval, compares the load skew reported by the load
+//! feedback API to the policy's threshold, and moves at most a few partitions at a time
+//! so a single evaluation never reshuffles the whole ring. Every move is sent to the
+//! returned handle.
+//!
+//! With `spawn_rebalancer_with_mover`, partitions don't change owner right away: each
+//! move goes through the partition migration states while the application's
+//! `DataMover` copies the data, and the owner only changes once the copy succeeded.
+
+use std::error::Error;
+use std::future::Future;
+use std::hash::BuildHasher;
+use std::time::Duration;
+
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+
+use crate::{HashRing, PartitionMove};
+
+/// When and how much the background rebalancer moves partitions.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RebalancePolicy {
+    /// The load skew above which partitions are moved.
+    pub skew_threshold: f64,
+    /// The maximum number of partitions moved per evaluation.
+    pub max_moves: usize,
+    /// Whether load counters are cleared after every evaluation, so each one only
+    /// considers the traffic of the last interval.
+    pub reset_load: bool,
+}
+
+impl Default for RebalancePolicy {
+    fn default() -> Self {
+        RebalancePolicy {
+            skew_threshold: 1.25,
+            max_moves: 1,
+            reset_load: true,
+        }
+    }
+}
+
+/// Transfers the data of partitions between nodes, supplied by the application.
+pub trait DataMover: Send + Sync + 'static {
+    /// Copies the data of a partition from one node to another. The partition keeps its
+    /// owner until the returned future succeeds, and is left with it if it fails.
+    fn move_partition(&self, partition: usize, from: &str, to: &str) -> impl Future<Output = Result<(), Box<dyn Error + Send + Sync>>> + Send;
+}
+
+/// A running background rebalancer.
+#[derive(Debug)]
+pub struct Rebalancer {
+    task: JoinHandle<()>,
+    moves: mpsc::UnboundedReceiver<PartitionMove>,
+}
+
+impl Rebalancer {
+    /// Waits for the next partition move. Returns `None` once the task stopped.
+    pub async fn next_move(&mut self) -> Option<PartitionMove> {
+        self.moves.recv().await
+    }
+
+    /// Returns a partition move if one is pending, without waiting.
+    pub fn try_next_move(&mut self) -> Option<PartitionMove> {
+        self.moves.try_recv().ok()
+    }
+
+    /// Stops the task.
+    pub fn abort(&self) {
+        self.task.abort();
+    }
+}
+
+impl Drop for Rebalancer {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+impl<H, K> HashRing<'static, H, K>
+where
+    H: BuildHasher + Clone + Send + Sync + 'static,
+    K: BuildHasher + Clone + Send + Sync + 'static,
+{
+    /// Spawns a task rebalancing the ring every `interval` according to `policy`.
+    ///
+    /// The task works on a handle sharing the state of the ring, and stops when the
+    /// returned handle is dropped. It must be called from within a tokio runtime.
+    ///
+    /// # Arguments
+    ///
+    /// * `interval` - The time between two evaluations of the load skew.
+    /// * `policy` - The skew threshold and the bound on moves per evaluation.
+    ///
+    /// # Returns
+    ///
+    /// * `Rebalancer` - A handle receiving every partition move.
+    pub fn spawn_rebalancer(&self, interval: Duration, policy: RebalancePolicy) -> Rebalancer {
+        let mut hash_ring = self.share();
+        let (sender, moves) = mpsc::unbounded_channel();
+        let task = tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+            // The first tick completes immediately, before any load was recorded.
+            ticker.tick().await;
+            loop {
+                ticker.tick().await;
+                if hash_ring.load_skew() > policy.skew_threshold {
+                    for partition_move in hash_ring.rebalance(policy.max_moves) {
+                        let _ = sender.send(partition_move);
+                    }
+                }
+                if policy.reset_load {
+                    hash_ring.reset_load();
+                }
+            }
+        });
+        Rebalancer { task, moves }
+    }
+
+    /// Spawns a task rebalancing the ring every `interval`, migrating partitions with `mover`.
+    ///
+    /// Every planned move starts a migration with `begin_migration`, awaits
+    /// `DataMover::move_partition`, and completes the handoff once the data is copied.
+    /// Moves are sent to the returned handle after their handoff; failed ones are aborted
+    /// and leave the partition with its owner. Moves are made one at a time.
+    ///
+    /// # Arguments
+    ///
+    /// * `interval` - The time between two evaluations of the load skew.
+    /// * `policy` - The skew threshold and the bound on moves per evaluation.
+    /// * `mover` - The transfer of partition data between nodes.
+    ///
+    /// # Returns
+    ///
+    /// * `Rebalancer` - A handle receiving every completed partition move.
+    pub fn spawn_rebalancer_with_mover<M: DataMover>(&self, interval: Duration, policy: RebalancePolicy, mover: M) -> Rebalancer {
+        let mut hash_ring = self.share();
+        let (sender, moves) = mpsc::unbounded_channel();
+        let task = tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+            ticker.tick().await;
+            loop {
+                ticker.tick().await;
+                if hash_ring.load_skew() > policy.skew_threshold {
+                    for partition_move in hash_ring.plan_rebalance(policy.max_moves) {
+                        let part_id = partition_move.partition;
+                        // Partitions already migrating are left alone.
+                        if hash_ring.begin_migration(part_id, &partition_move.to).is_err() {
+                            continue;
+                        }
+                        let copied = mover.move_partition(part_id, &partition_move.from, &partition_move.to).await;
+                        if copied.is_ok() && hash_ring.finish_copy(part_id).is_ok() && hash_ring.complete_handoff(part_id).is_ok() {
+                            let _ = sender.send(partition_move);
+                        } else {
+                            hash_ring.abort_migration(part_id);
+                        }
+                    }
+                }
+                if policy.reset_load {
+                    hash_ring.reset_load();
+                }
+            }
+        });
+        Rebalancer { task, moves }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::error::Error;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    use crate::tests::{access, key_on, test_nodes};
+    use crate::{Config, DataMover, HashRing, PartitionState, RebalancePolicy};
+
+    #[derive(Default)]
+    struct TestMover {
+        fail: AtomicBool,
+        moved: Mutex<Vec<usize>>,
+    }
+
+    impl DataMover for Arc<TestMover> {
+        async fn move_partition(&self, partition: usize, _from: &str, _to: &str) -> Result<(), Box<dyn Error + Send + Sync>> {
+            tokio::time::sleep(Duration::from_secs(1)).await;
+            if self.fail.load(Ordering::SeqCst) {
+                return Err("copy failed".into());
+            }
+            self.moved.lock().unwrap().push(partition);
+            Ok(())
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_rebalancer_moves_hot_partitions() {
+        let mut hash_ring = HashRing::new(Config::default()).unwrap();
+        for node in test_nodes() {
+            hash_ring.add_node(node).unwrap();
+        }
+        let policy = RebalancePolicy {
+            max_moves: 2,
+            ..RebalancePolicy::default()
+        };
+        let mut rebalancer = hash_ring.spawn_rebalancer(Duration::from_secs(10), policy);
+
+        let (hot, part_id) = key_on(&hash_ring, "node1", &[]);
+        let (warm, other) = key_on(&hash_ring, "node1", &[part_id]);
+        access(&hash_ring, &hot, 100);
+        access(&hash_ring, &warm, 100);
+        let partition_move = rebalancer.next_move().await.unwrap();
+        assert_eq!(partition_move.from, "node1");
+        assert!([part_id, other].contains(&partition_move.partition));
+        assert_eq!(hash_ring.get_partition_owner(partition_move.partition).unwrap().id(), partition_move.to);
+        let moved_key = if partition_move.partition == part_id { &hot } else { &warm };
+        assert_eq!(hash_ring.get_key(moved_key).unwrap().id(), partition_move.to);
+
+        tokio::time::sleep(Duration::from_secs(10)).await;
+        assert_eq!(hash_ring.load_skew(), 1.0);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_rebalancer_awaits_data_mover() {
+        let mut hash_ring = HashRing::new(Config::default()).unwrap();
+        for node in test_nodes() {
+            hash_ring.add_node(node).unwrap();
+        }
+        let mover = Arc::new(TestMover::default());
+        mover.fail.store(true, Ordering::SeqCst);
+        let policy = RebalancePolicy {
+            reset_load: false,
+            ..RebalancePolicy::default()
+        };
+        let mut rebalancer = hash_ring.spawn_rebalancer_with_mover(Duration::from_secs(10), policy, mover.clone());
+        let (hot, part_id) = key_on(&hash_ring, "node1", &[]);
+        let (warm, other) = key_on(&hash_ring, "node1", &[part_id]);
+        access(&hash_ring, &hot, 100);
+        access(&hash_ring, &warm, 60);
+        let owner = hash_ring.get_partition_owner(part_id).unwrap();
+
+        // A failed copy leaves the partition with its owner.
+        tokio::time::sleep(Duration::from_secs(15)).await;
+        assert!(rebalancer.try_next_move().is_none());
+        assert_eq!(hash_ring.get_partition_owner(part_id).unwrap().id(), owner.id());
+        assert_eq!(hash_ring.get_key(&hot).unwrap().id(), "node1");
+        assert_eq!(hash_ring.partition_state(part_id), PartitionState::Stable);
+
+        mover.fail.store(false, Ordering::SeqCst);
+        let partition_move = rebalancer.next_move().await.unwrap();
+        assert_eq!(partition_move.partition, part_id);
+        assert_eq!(*mover.moved.lock().unwrap(), [part_id]);
+        assert_eq!(hash_ring.get_partition_owner(part_id).unwrap().id(), partition_move.to);
+        assert_eq!(hash_ring.get_key(&hot).unwrap().id(), partition_move.to);
+        assert_eq!(hash_ring.get_key(&warm).unwrap().id(), "node1");
+        assert_eq!(hash_ring.partition_state(part_id), PartitionState::Stable);
+        assert_eq!(hash_ring.partition_state(other), PartitionState::Stable);
+    }
+}
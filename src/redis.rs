@@ -0,0 +1,257 @@
+//! Routing Redis commands over the ring, behind the `redis` feature.
+//!
+//! `RedisRouter` keeps one `redis::Client` per ring node, with a few idle connections
+//! to reuse, and sends each command to the owner of its key. Hash tags are respected as in Redis Cluster: only the `{...}`
+//! section of a key is hashed, so keys sharing a tag land on the same node and can be
+//! used together in multi-key commands.
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::hash::BuildHasher;
+use std::sync::{Arc, Mutex};
+
+use ::redis::{Client, Cmd, Connection, FromRedisValue, RedisResult};
+
+use crate::slots::hash_tag;
+use crate::sync;
+use crate::{HashRing, Node, XxHash64Hasher};
+
+const MAX_IDLE: usize = 4;
+
+/// The function creating the client of a node.
+type Open<'a> = Box<dyn Fn(&Arc<dyn Node<'a> + 'a>) -> RedisResult<Client> + Send + Sync + 'a>;
+
+/// The clients and idle connections of every node, as of a ring version.
+struct Clients {
+    version: u64,
+    by_node: HashMap<String, Client>,
+    idle: HashMap<String, Vec<Connection>>,
+}
+
+/// A Redis client routing every command to the owner of its key in the ring.
+pub struct RedisRouter<'a, H = XxHash64Hasher, K = H> {
+    hash_ring: HashRing<'a, H, K>,
+    open: Open<'a>,
+    clients: Mutex<Clients>,
+}
+
+impl<'a, H, K> RedisRouter<'a, H, K>
+where
+    H: BuildHasher,
+    K: BuildHasher,
+{
+    /// Creates a router over the ring, whose node IDs are Redis connection URLs such as
+    /// `redis://10.0.0.1:6379`.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use std::sync::Arc;
+    /// use hashring::{HashRing, Config, Node, RedisRouter};
+    ///
+    /// #[derive(Debug)]
+    /// struct MyNode<'a> {
+    ///     name: &'a str,
+    /// }
+    ///
+    /// impl<'a> Node<'a> for MyNode<'a> {
+    ///     fn id(&self) -> &'a str {
+    ///         self.name
+    ///     }
+    /// }
+    ///
+    /// let mut hash_ring = HashRing::new(Config::default()).unwrap();
+    /// hash_ring.add_node(Arc::new(MyNode { name: "redis://10.0.0.1:6379" })).unwrap();
+    /// hash_ring.add_node(Arc::new(MyNode { name: "redis://10.0.0.2:6379" })).unwrap();
+    ///
+    /// let router = RedisRouter::new(hash_ring);
+    /// let _: () = router.route_cmd(b"{user1000}.name", redis::cmd("SET").arg("{user1000}.name").arg("alice")).unwrap();
+    /// ```
+    pub fn new(hash_ring: HashRing<'a, H, K>) -> RedisRouter<'a, H, K> {
+        RedisRouter::with_client_fn(hash_ring, |node| Client::open(node.id()))
+    }
+
+    /// Creates a router whose clients are created by `open`, for nodes whose IDs are not
+    /// connection URLs.
+    pub fn with_client_fn<F>(hash_ring: HashRing<'a, H, K>, open: F) -> RedisRouter<'a, H, K>
+    where
+        F: Fn(&Arc<dyn Node<'a> + 'a>) -> RedisResult<Client> + Send + Sync + 'a,
+    {
+        let version = hash_ring.version();
+        RedisRouter {
+            hash_ring,
+            open: Box::new(open),
+            clients: Mutex::new(Clients {
+                version,
+                by_node: HashMap::new(),
+                idle: HashMap::new(),
+            }),
+        }
+    }
+
+    /// Returns the ring routing keys to nodes.
+    pub fn ring(&self) -> &HashRing<'a, H, K> {
+        &self.hash_ring
+    }
+
+    /// Retrieves the node owning the key, hashing only its hash tag if it has one.
+    pub fn get_node(&self, key: &[u8]) -> Option<Arc<dyn Node<'a> + 'a>> {
+        self.hash_ring.get_key(hash_tag(key))
+    }
+
+    /// Returns the owner of the key along with its client, creating the client on first use.
+    ///
+    /// Clients of nodes that left the ring are dropped once the ring's version changes.
+    pub fn client_for_key(&self, key: &[u8]) -> Result<(Arc<dyn Node<'a> + 'a>, Client), Box<dyn Error>> {
+        let node = self.get_node(key).ok_or("no nodes in the ring")?;
+        let mut clients = sync::lock(&self.clients);
+        self.evict_departed(&mut clients);
+        let client = match clients.by_node.get(node.id()) {
+            Some(client) => client.clone(),
+            None => {
+                let client = (self.open)(&node)?;
+                clients.by_node.insert(node.id().to_string(), client.clone());
+                client
+            }
+        };
+        Ok((node, client))
+    }
+
+    /// Runs the command on the owner of the key, over an idle connection to it if there is one.
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - The key the command operates on. Every key of a multi-key command must share its hash tag.
+    /// * `cmd` - The command to run.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<T, Box<dyn Error>>` - The reply, or an error if the ring is empty or the command failed.
+    pub fn route_cmd<T: FromRedisValue>(&self, key: &[u8], cmd: &Cmd) -> Result<T, Box<dyn Error>> {
+        let (node, client) = self.client_for_key(key)?;
+        let idle = sync::lock(&self.clients).idle.get_mut(node.id()).and_then(Vec::pop);
+        let mut conn = match idle {
+            Some(conn) => conn,
+            None => client.get_connection()?,
+        };
+        let reply = cmd.query(&mut conn);
+        // A connection that failed at the I/O level may be broken, so it isn't reused.
+        if !matches!(&reply, Err(err) if err.is_io_error() || err.is_connection_dropped()) {
+            self.release(&node, conn);
+        }
+        Ok(reply?)
+    }
+
+    /// Drops the clients and connections of nodes that left, once the ring's version changes.
+    fn evict_departed(&self, clients: &mut Clients) {
+        let version = self.hash_ring.version();
+        if clients.version == version {
+            return;
+        }
+        let nodes = self.hash_ring.nodes.wait_read();
+        clients.by_node.retain(|id, _| nodes.contains_key(id));
+        clients.idle.retain(|id, _| nodes.contains_key(id));
+        clients.version = version;
+    }
+
+    /// Keeps a connection for the next command to the node, unless it left the ring.
+    fn release(&self, node: &Arc<dyn Node<'a> + 'a>, conn: Connection) {
+        let mut clients = sync::lock(&self.clients);
+        self.evict_departed(&mut clients);
+        if !clients.by_node.contains_key(node.id()) {
+            return;
+        }
+        let idle = clients.idle.entry(node.id().to_string()).or_default();
+        if idle.len() < MAX_IDLE {
+            idle.push(conn);
+        }
+    }
+}
+
+impl<'a, H, K> fmt::Debug for RedisRouter<'a, H, K>
+where
+    H: fmt::Debug,
+    K: fmt::Debug,
+{
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt.debug_struct("RedisRouter").field("hash_ring", &self.hash_ring).finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::thread;
+
+    use crate::tests::TestNode;
+    use crate::{Config, HashRing, RedisRouter};
+
+    /// Starts a server answering every command with `OK`, returning its URL and the
+    /// number of connections it accepted.
+    fn start_server() -> (&'static str, Arc<AtomicUsize>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = Box::leak(format!("redis://{}", listener.local_addr().unwrap()).into_boxed_str());
+        let accepted = Arc::new(AtomicUsize::new(0));
+        let counter = accepted.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                counter.fetch_add(1, Ordering::SeqCst);
+                let mut writer = stream.unwrap();
+                let mut reader = BufReader::new(writer.try_clone().unwrap());
+                thread::spawn(move || {
+                    let mut line = String::new();
+                    while reader.read_line(&mut line).unwrap_or(0) > 0 {
+                        // A command is an array of bulk strings, each a length line and a data line.
+                        let args: usize = line.trim_start_matches('*').trim().parse().unwrap_or(0);
+                        for _ in 0..args * 2 {
+                            reader.read_line(&mut String::new()).unwrap();
+                        }
+                        writer.write_all(b"+OK\r\n").unwrap();
+                        line.clear();
+                    }
+                });
+            }
+        });
+        (url, accepted)
+    }
+
+    #[test]
+    fn test_hash_tags_share_a_node() {
+        let mut hash_ring = HashRing::new(Config::default()).unwrap();
+        for name in ["redis://127.0.0.1:7000", "redis://127.0.0.1:7001", "redis://127.0.0.1:7002"] {
+            hash_ring.add_node(Arc::new(TestNode { ip_addr: name.to_string(), name })).unwrap();
+        }
+        let router = RedisRouter::new(hash_ring.share());
+
+        for i in 0..20 {
+            let tag = format!("user{}", i);
+            let (following, _) = router.client_for_key(format!("{{{}}}.following", tag).as_bytes()).unwrap();
+            let (followers, _) = router.client_for_key(format!("{{{}}}.followers", tag).as_bytes()).unwrap();
+            assert_eq!(following.id(), followers.id());
+            assert_eq!(following.id(), hash_ring.get_key(tag.as_bytes()).unwrap().id());
+        }
+
+        hash_ring.remove_node("redis://127.0.0.1:7000").unwrap();
+        let (node, _) = router.client_for_key(b"some_key").unwrap();
+        assert_ne!(node.id(), "redis://127.0.0.1:7000");
+        assert!(!router.clients.lock().unwrap().by_node.contains_key("redis://127.0.0.1:7000"));
+    }
+
+    #[test]
+    fn test_connections_are_reused() {
+        let (url, accepted) = start_server();
+        let mut hash_ring = HashRing::new(Config::default()).unwrap();
+        hash_ring.add_node(Arc::new(TestNode { ip_addr: url.to_string(), name: url })).unwrap();
+        let router = RedisRouter::new(hash_ring);
+
+        for i in 0..5 {
+            let key = format!("key-{}", i);
+            let _: () = router.route_cmd(key.as_bytes(), redis::cmd("SET").arg(&key).arg(i)).unwrap();
+        }
+        assert_eq!(accepted.load(Ordering::SeqCst), 1);
+    }
+}
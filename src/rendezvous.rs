@@ -0,0 +1,239 @@
+//! Weighted rendezvous (highest random weight) hashing.
+//!
+//! Every node scores every key, and the key belongs to the node with the highest
+//! score. Using the logarithmic method, the score of a node with weight `w` is
+//! `-w / ln(u)`, where `u` is the hash of the node and the key mapped to `(0, 1)`.
+//! A node then receives a share of the keys proportional to its weight, without
+//! virtual nodes, and changing one node only moves keys to or from that node.
+
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::hash::{BuildHasher, Hasher};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use crate::sync::RwLock;
+use crate::{Node, Ring, XxHash64Hasher};
+
+#[derive(Debug, Clone)]
+struct WeightedNode<'a> {
+    node: Arc<dyn Node<'a> + 'a>,
+    weight: f64,
+}
+
+/// A ring placing keys with weighted rendezvous hashing.
+#[derive(Debug, Clone)]
+pub struct RendezvousRing<'a, H = XxHash64Hasher> {
+    hasher: H,
+    nodes: Arc<RwLock<BTreeMap<String, WeightedNode<'a>>>>,
+    version: Arc<AtomicU64>,
+}
+
+impl<'a> RendezvousRing<'a, XxHash64Hasher> {
+    pub fn new() -> RendezvousRing<'a, XxHash64Hasher> {
+        RendezvousRing::with_hasher(XxHash64Hasher::default())
+    }
+}
+
+impl<'a> Default for RendezvousRing<'a, XxHash64Hasher> {
+    fn default() -> Self {
+        RendezvousRing::new()
+    }
+}
+
+impl<'a, H> RendezvousRing<'a, H>
+where
+    H: BuildHasher,
+{
+    /// Creates a new `RendezvousRing` with the specified hasher.
+    pub fn with_hasher(hasher: H) -> RendezvousRing<'a, H> {
+        RendezvousRing {
+            hasher,
+            nodes: Arc::new(RwLock::new(BTreeMap::new(), &Arc::default())),
+            version: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
+    /// Adds a node with a weight of `1.0`.
+    pub fn add_node(&mut self, node: Arc<dyn Node<'a> + 'a>) -> Result<Arc<dyn Node<'a> + 'a>, Box<dyn Error>> {
+        self.add_weighted_node(node, 1.0)
+    }
+
+    /// Adds a node receiving a share of the keys proportional to `weight`.
+    ///
+    /// # Arguments
+    ///
+    /// * `node` - The node to add.
+    /// * `weight` - The relative capacity of the node. Must be finite and greater than 0.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use std::sync::Arc;
+    /// use hashring::{Node, RendezvousRing};
+    ///
+    /// #[derive(Debug)]
+    /// struct MyNode<'a> {
+    ///     name: &'a str,
+    /// }
+    ///
+    /// impl<'a> Node<'a> for MyNode<'a> {
+    ///     fn id(&self) -> &'a str {
+    ///         self.name
+    ///     }
+    /// }
+    ///
+    /// let mut ring = RendezvousRing::new();
+    /// ring.add_weighted_node(Arc::new(MyNode { name: "small" }), 1.0).unwrap();
+    /// ring.add_weighted_node(Arc::new(MyNode { name: "large" }), 4.0).unwrap();
+    ///
+    /// let node = ring.get_key(b"some_key").unwrap();
+    /// println!("some_key belongs to {}", node.id());
+    /// ```
+    pub fn add_weighted_node(&mut self, node: Arc<dyn Node<'a> + 'a>, weight: f64) -> Result<Arc<dyn Node<'a> + 'a>, Box<dyn Error>> {
+        if !weight.is_finite() || weight <= 0.0 {
+            return Err("weight must be a finite number greater than 0".into());
+        }
+        let mut nodes = self.nodes.wait_write();
+        if nodes.contains_key(node.id()) {
+            return Err("node already exist".into());
+        }
+        nodes.insert(node.id().to_string(), WeightedNode { node: node.clone(), weight });
+        self.version.fetch_add(1, Ordering::Release);
+        Ok(node)
+    }
+
+    /// Changes the weight of a node.
+    pub fn set_weight(&mut self, id: &str, weight: f64) -> Result<(), Box<dyn Error>> {
+        if !weight.is_finite() || weight <= 0.0 {
+            return Err("weight must be a finite number greater than 0".into());
+        }
+        let mut nodes = self.nodes.wait_write();
+        let entry = nodes.get_mut(id).ok_or("node not found")?;
+        entry.weight = weight;
+        self.version.fetch_add(1, Ordering::Release);
+        Ok(())
+    }
+
+    /// Removes a node.
+    pub fn remove_node(&mut self, id: &str) -> Result<(), Box<dyn Error>> {
+        let mut nodes = self.nodes.wait_write();
+        if nodes.remove(id).is_none() {
+            return Err("node not found".into());
+        }
+        self.version.fetch_add(1, Ordering::Release);
+        Ok(())
+    }
+
+    fn score(&self, id: &str, weight: f64, key: &[u8]) -> f64 {
+        let mut hasher = self.hasher.build_hasher();
+        hasher.write(id.as_bytes());
+        hasher.write(key);
+        // Map the top 53 bits onto (0, 1), excluding both ends so the logarithm is finite and non-zero.
+        let unit = ((hasher.finish() >> 11) as f64 + 0.5) / (1u64 << 53) as f64;
+        -weight / unit.ln()
+    }
+
+    /// Retrieves the node responsible for the given key.
+    pub fn get_key(&self, key: &[u8]) -> Option<Arc<dyn Node<'a> + 'a>> {
+        let nodes = self.nodes.wait_read();
+        nodes
+            .iter()
+            .map(|(id, entry)| (self.score(id, entry.weight, key), entry))
+            .max_by(|a, b| a.0.total_cmp(&b.0))
+            .map(|(_, entry)| entry.node.clone())
+    }
+
+    /// Retrieves up to `n` distinct nodes for the given key, highest score first.
+    pub fn get_nodes(&self, key: &[u8], n: usize) -> Vec<Arc<dyn Node<'a> + 'a>> {
+        let nodes = self.nodes.wait_read();
+        let mut scored: Vec<(f64, &WeightedNode<'a>)> = nodes
+            .iter()
+            .map(|(id, entry)| (self.score(id, entry.weight, key), entry))
+            .collect();
+        scored.sort_by(|a, b| b.0.total_cmp(&a.0));
+        scored.into_iter().take(n).map(|(_, entry)| entry.node.clone()).collect()
+    }
+
+    /// Returns the number of nodes.
+    pub fn len(&self) -> usize {
+        self.nodes.wait_read().len()
+    }
+
+    /// Returns `true` if the ring has no nodes.
+    pub fn is_empty(&self) -> bool {
+        self.nodes.wait_read().is_empty()
+    }
+}
+
+impl<'a, H> Ring<'a> for RendezvousRing<'a, H>
+where
+    H: BuildHasher,
+{
+    fn lookup(&self, key: &[u8]) -> Option<Arc<dyn Node<'a> + 'a>> {
+        self.get_key(key)
+    }
+
+    fn preference_list(&self, key: &[u8], n: usize) -> Vec<Arc<dyn Node<'a> + 'a>> {
+        self.get_nodes(key, n)
+    }
+
+    fn members(&self) -> Vec<Arc<dyn Node<'a> + 'a>> {
+        self.nodes.wait_read().values().map(|entry| entry.node.clone()).collect()
+    }
+
+    fn version(&self) -> u64 {
+        self.version.load(Ordering::Acquire)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use crate::tests::test_nodes;
+    use crate::RendezvousRing;
+
+    fn counts(ring: &RendezvousRing<'static>) -> HashMap<&'static str, usize> {
+        let mut counts = HashMap::new();
+        for i in 0..20000 {
+            *counts.entry(ring.get_key(format!("key-{}", i).as_bytes()).unwrap().id()).or_insert(0) += 1;
+        }
+        counts
+    }
+
+    #[test]
+    fn test_weights_are_respected() {
+        let nodes = test_nodes();
+        let mut ring = RendezvousRing::new();
+        ring.add_weighted_node(nodes[0].clone(), 1.0).unwrap();
+        ring.add_weighted_node(nodes[1].clone(), 1.0).unwrap();
+        ring.add_weighted_node(nodes[2].clone(), 2.0).unwrap();
+        assert!(ring.add_weighted_node(nodes[2].clone(), 2.0).is_err());
+        assert!(ring.set_weight("node1", 0.0).is_err());
+
+        let counts = counts(&ring);
+        let share = counts["node3"] as f64 / 20000.0;
+        assert!((share - 0.5).abs() < 0.03, "node3 share was {}", share);
+    }
+
+    #[test]
+    fn test_removal_only_moves_keys_of_removed_node() {
+        let mut ring = RendezvousRing::new();
+        for node in test_nodes() {
+            ring.add_node(node).unwrap();
+        }
+        let before: Vec<&str> = (0..1000).map(|i| ring.get_key(format!("key-{}", i).as_bytes()).unwrap().id()).collect();
+        let nodes = ring.get_nodes(b"some_key", 5);
+        assert_eq!(nodes.len(), 3);
+        assert_eq!(nodes[0].id(), ring.get_key(b"some_key").unwrap().id());
+
+        ring.remove_node("node2").unwrap();
+        for (i, owner) in before.into_iter().enumerate() {
+            let after = ring.get_key(format!("key-{}", i).as_bytes()).unwrap().id();
+            if owner != "node2" {
+                assert_eq!(after, owner);
+            }
+        }
+    }
+}
@@ -0,0 +1,138 @@
+//! Prioritized repair after node failures.
+//!
+//! When nodes go down, the partitions they replicated run with fewer copies until an
+//! anti-entropy process copies them to the nodes that take over. Partitions with the
+//! fewest surviving replicas are the closest to data loss, so `repair_schedule` lists
+//! them first, together with where to copy from and to.
+
+use std::collections::HashSet;
+use std::hash::BuildHasher;
+
+use crate::HashRing;
+
+/// The repair of one partition.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RepairTask {
+    pub part_id: usize,
+    /// The number of replicas left, out of `replicas`.
+    pub remaining: usize,
+    /// The size of the partition's replica set before the failures.
+    pub replicas: usize,
+    /// The surviving replicas to copy the partition from, in preference order.
+    pub sources: Vec<String>,
+    /// The nodes that join the partition's replica set and need a copy, in preference order.
+    pub targets: Vec<String>,
+}
+
+impl<'a, H, K> HashRing<'a, H, K>
+where
+    H: BuildHasher,
+    K: BuildHasher,
+{
+    /// Returns the repairs needed while the given nodes are down, most urgent first.
+    ///
+    /// Only partitions with a replica on a down node are listed, ordered by remaining
+    /// replica count and then by partition ID. Partitions with no replica left come
+    /// first with no sources, since they can only be restored from a backup.
+    ///
+    /// # Arguments
+    ///
+    /// * `down` - The IDs of the nodes that are down.
+    ///
+    /// # Returns
+    ///
+    /// * `Vec<RepairTask>` - The partitions to repair.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use std::sync::Arc;
+    /// use hashring::{HashRing, Config, Node};
+    ///
+    /// #[derive(Debug)]
+    /// struct MyNode<'a> {
+    ///     name: &'a str,
+    /// }
+    ///
+    /// impl<'a> Node<'a> for MyNode<'a> {
+    ///     fn id(&self) -> &'a str {
+    ///         self.name
+    ///     }
+    /// }
+    ///
+    /// let mut config = Config::default();
+    /// config.replication_factor = 2;
+    /// let mut hash_ring = HashRing::new(config).unwrap();
+    /// for name in ["node1", "node2", "node3", "node4"] {
+    ///     hash_ring.add_node(Arc::new(MyNode { name })).unwrap();
+    /// }
+    ///
+    /// for task in hash_ring.repair_schedule(&["node1"]) {
+    ///     println!("partition {}: copy from {:?} to {:?}", task.part_id, task.sources, task.targets);
+    /// }
+    /// ```
+    pub fn repair_schedule(&self, down: &[&str]) -> Vec<RepairTask> {
+        let down: HashSet<&str> = down.iter().copied().collect();
+        let sorted_set = self.sorted_nodes_hash_set.wait_read();
+        let mut schedule = Vec::new();
+        for (part_id, replicas) in self.partition_replica_sets() {
+            if !replicas.iter().any(|id| down.contains(id.as_str())) {
+                continue;
+            }
+            let sources: Vec<String> = replicas.iter().filter(|id| !down.contains(id.as_str())).cloned().collect();
+
+            // The replica set the partition gets without the down nodes.
+            let point = self.hash_partition_id(part_id);
+            let mut seen = HashSet::new();
+            let targets: Vec<String> = sorted_set
+                .range(point..)
+                .chain(sorted_set.range(..point))
+                .map(|(_, node)| node.id())
+                .filter(|node_id| !down.contains(node_id) && seen.insert(*node_id))
+                .take(self.config().replication_factor)
+                .filter(|node_id| !replicas.iter().any(|id| id == node_id))
+                .map(|node_id| node_id.to_string())
+                .collect();
+
+            schedule.push(RepairTask {
+                part_id,
+                remaining: sources.len(),
+                replicas: replicas.len(),
+                sources,
+                targets,
+            });
+        }
+        schedule.sort_by_key(|task| (task.remaining, task.part_id));
+        schedule
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use crate::tests::TestNode;
+    use crate::{Config, HashRing};
+
+    #[test]
+    fn test_repair_schedule_orders_by_remaining_replicas() {
+        let mut hash_ring = HashRing::new(Config { replication_factor: 3, ..Config::default() }).unwrap();
+        for name in ["node1", "node2", "node3", "node4", "node5"] {
+            hash_ring.add_node(Arc::new(TestNode { ip_addr: "127.0.0.1".to_string(), name })).unwrap();
+        }
+        assert!(hash_ring.repair_schedule(&[]).is_empty());
+
+        let schedule = hash_ring.repair_schedule(&["node1", "node2"]);
+        assert!(!schedule.is_empty());
+        assert!(schedule.windows(2).all(|pair| pair[0].remaining <= pair[1].remaining));
+        for task in &schedule {
+            assert_eq!(task.replicas, 3);
+            assert!(task.remaining < 3);
+            assert!(task.sources.iter().chain(&task.targets).all(|id| id != "node1" && id != "node2"));
+            // The survivors and the new replicas form a full replica set again.
+            assert_eq!(task.sources.len() + task.targets.len(), 3);
+        }
+        // Partitions replicated on both failed nodes come first.
+        assert_eq!(schedule[0].remaining, 1);
+    }
+}
@@ -0,0 +1,177 @@
+//! Preference lists annotated with replica roles and zones.
+//!
+//! The first node of a preference list is the primary and the rest are secondaries,
+//! in the order they take over. Each entry carries the zone of its node, so callers
+//! don't have to look it up again to spread reads and writes across zones.
+
+use std::fmt;
+use std::sync::Arc;
+
+use crate::Node;
+
+/// The role of a node in a preference list.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum ReplicaRole {
+    Primary,
+    /// The `n`th secondary, starting at 1.
+    Secondary(usize),
+}
+
+impl fmt::Display for ReplicaRole {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ReplicaRole::Primary => write!(fmt, "primary"),
+            ReplicaRole::Secondary(n) => write!(fmt, "secondary {}", n),
+        }
+    }
+}
+
+/// A node of a preference list.
+#[derive(Debug, Clone)]
+pub struct Replica<'a> {
+    pub node: Arc<dyn Node<'a> + 'a>,
+    pub role: ReplicaRole,
+    pub zone: Option<&'a str>,
+}
+
+/// The distinct nodes responsible for a key, primary first.
+#[derive(Debug, Clone, Default)]
+pub struct ReplicaSet<'a> {
+    replicas: Vec<Replica<'a>>,
+}
+
+impl<'a> ReplicaSet<'a> {
+    /// Returns the primary, or `None` if the ring is empty.
+    pub fn primary(&self) -> Option<&Replica<'a>> {
+        self.replicas.first()
+    }
+
+    /// Returns the secondaries, in the order they take over from the primary.
+    pub fn secondaries(&self) -> &[Replica<'a>] {
+        self.replicas.get(1..).unwrap_or_default()
+    }
+
+    /// Returns the replicas in zone `zone`.
+    pub fn in_zone<'s>(&'s self, zone: &'s str) -> impl Iterator<Item = &'s Replica<'a>> + 's {
+        self.replicas.iter().filter(move |replica| replica.zone == Some(zone))
+    }
+
+    /// Returns the distinct zones of the replicas, in preference order.
+    pub fn zones(&self) -> Vec<&'a str> {
+        let mut zones: Vec<&'a str> = Vec::new();
+        for zone in self.replicas.iter().filter_map(|replica| replica.zone) {
+            if !zones.contains(&zone) {
+                zones.push(zone);
+            }
+        }
+        zones
+    }
+
+    pub fn iter(&self) -> std::slice::Iter<'_, Replica<'a>> {
+        self.replicas.iter()
+    }
+
+    pub fn get(&self, idx: usize) -> Option<&Replica<'a>> {
+        self.replicas.get(idx)
+    }
+
+    pub fn len(&self) -> usize {
+        self.replicas.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.replicas.is_empty()
+    }
+
+    /// Returns the nodes, primary first.
+    pub fn nodes(&self) -> Vec<Arc<dyn Node<'a> + 'a>> {
+        self.replicas.iter().map(|replica| replica.node.clone()).collect()
+    }
+}
+
+impl<'a> From<Vec<Arc<dyn Node<'a> + 'a>>> for ReplicaSet<'a> {
+    fn from(nodes: Vec<Arc<dyn Node<'a> + 'a>>) -> ReplicaSet<'a> {
+        let replicas = nodes
+            .into_iter()
+            .enumerate()
+            .map(|(idx, node)| Replica {
+                role: match idx {
+                    0 => ReplicaRole::Primary,
+                    n => ReplicaRole::Secondary(n),
+                },
+                zone: node.zone(),
+                node,
+            })
+            .collect();
+        ReplicaSet { replicas }
+    }
+}
+
+impl<'a> IntoIterator for ReplicaSet<'a> {
+    type Item = Replica<'a>;
+    type IntoIter = std::vec::IntoIter<Replica<'a>>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.replicas.into_iter()
+    }
+}
+
+impl<'s, 'a> IntoIterator for &'s ReplicaSet<'a> {
+    type Item = &'s Replica<'a>;
+    type IntoIter = std::slice::Iter<'s, Replica<'a>>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.replicas.iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use crate::{Config, HashRing, Node, ReplicaRole};
+
+    #[derive(Debug)]
+    struct ZonedNode {
+        name: &'static str,
+        zone: &'static str,
+    }
+
+    impl<'a> Node<'a> for ZonedNode {
+        fn id(&self) -> &'a str {
+            self.name
+        }
+
+        fn zone(&self) -> Option<&'a str> {
+            Some(self.zone)
+        }
+    }
+
+    #[test]
+    fn test_preference_list_roles_and_zones() {
+        let mut hash_ring = HashRing::new(Config {
+            replication_factor: 3,
+            ..Config::default()
+        })
+        .unwrap();
+        for (name, zone) in [("node1", "a"), ("node2", "b"), ("node3", "a")] {
+            hash_ring.add_node(Arc::new(ZonedNode { name, zone })).unwrap();
+        }
+
+        let replicas = hash_ring.get_preference_list(b"some_key");
+        assert_eq!(replicas.len(), 3);
+        let primary = replicas.primary().unwrap();
+        assert_eq!(primary.role, ReplicaRole::Primary);
+        assert_eq!(primary.node.id(), hash_ring.get_key(b"some_key").unwrap().id());
+        assert_eq!(
+            replicas.secondaries().iter().map(|r| r.role).collect::<Vec<_>>(),
+            vec![ReplicaRole::Secondary(1), ReplicaRole::Secondary(2)]
+        );
+
+        let mut zones = replicas.zones();
+        zones.sort();
+        assert_eq!(zones, vec!["a", "b"]);
+        assert_eq!(replicas.in_zone("a").count(), 2);
+        assert_eq!(replicas.iter().map(|r| r.node.id()).collect::<Vec<_>>(), replicas.nodes().iter().map(|n| n.id()).collect::<Vec<_>>());
+    }
+}
@@ -0,0 +1,200 @@
+//! Changing the replication factor of a live ring.
+//!
+//! Virtual node `i` of a node is placed at the hash of `"{id}:{i}"`, so growing the
+//! replication factor from `m` to `n` only inserts replicas `m..n` of every node, and
+//! shrinking it only removes them. Every other virtual node stays where it is.
+
+use std::error::Error;
+use std::hash::BuildHasher;
+use std::sync::atomic::Ordering;
+
+use crate::trace::OpTimer;
+use crate::{weight, Config, HashRing};
+
+/// The effect of a replication factor change.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReplicationDelta {
+    /// The replication factor before the change.
+    pub previous: usize,
+    /// The replication factor after the change.
+    pub current: usize,
+    /// The number of virtual nodes inserted into the ring.
+    pub added_vnodes: usize,
+    /// The number of virtual nodes removed from the ring.
+    pub removed_vnodes: usize,
+    /// The partitions whose owner changed, in ascending order.
+    pub moved_partitions: Vec<usize>,
+}
+
+impl<'a, H, K> HashRing<'a, H, K>
+where
+    H: BuildHasher,
+    K: BuildHasher,
+{
+    /// Changes the number of virtual nodes per node, adding or removing only the difference.
+    ///
+    /// Nodes below full weight keep their weight, so they get the same fraction of the new
+    /// replication factor.
+    ///
+    /// # Arguments
+    ///
+    /// * `replication_factor` - The new replication factor.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<ReplicationDelta, Box<dyn Error>>` - The virtual nodes and partitions affected.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use std::sync::Arc;
+    /// use hashring::{HashRing, Config, Node};
+    ///
+    /// #[derive(Debug)]
+    /// struct MyNode<'a> {
+    ///     name: &'a str,
+    /// }
+    ///
+    /// impl<'a> Node<'a> for MyNode<'a> {
+    ///     fn id(&self) -> &'a str {
+    ///         self.name
+    ///     }
+    /// }
+    ///
+    /// let mut hash_ring = HashRing::new(Config::default()).unwrap();
+    /// hash_ring.add_node(Arc::new(MyNode { name: "node1" })).unwrap();
+    /// hash_ring.add_node(Arc::new(MyNode { name: "node2" })).unwrap();
+    ///
+    /// let delta = hash_ring.set_replication_factor(40).unwrap();
+    /// assert_eq!(delta.added_vnodes, 40);
+    /// ```
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip(self)))]
+    pub fn set_replication_factor(&mut self, replication_factor: usize) -> Result<ReplicationDelta, Box<dyn Error>> {
+        let _events = self.dispatch_events();
+        let timer = OpTimer::start();
+        if replication_factor == 0 {
+            return Err("Replication factor must be greater than 0".into());
+        }
+
+        let mut ramps = self.ramps.write()?;
+        let nodes = self.nodes.read()?;
+        let mut sorted_set = self.sorted_nodes_hash_set.write()?;
+        let mut partitions = self.partitions.write()?;
+        let weights = self.weights.read()?;
+
+        let previous = self.config().replication_factor;
+        let mut added_vnodes = 0;
+        let mut removed_vnodes = 0;
+        let mut updated = sorted_set.clone();
+        for (id, node) in nodes.iter() {
+            let weight = weights.get(id).copied().unwrap_or(1.0);
+            let (current, target) = (weight::vnode_count(weight, previous), weight::vnode_count(weight, replication_factor));
+            let removed = self.remove_vnodes(&mut updated, id, target..current);
+            removed_vnodes += removed;
+            match ramps.get_mut(id) {
+                // Nodes joining gradually get their replicas from `tick`, up to the new factor.
+                Some(ramp) => ramp.resize(replication_factor, removed),
+                None => added_vnodes += self.place_vnodes(&mut updated, node, current..target, self.config().collision_policy)?,
+            }
+        }
+        self.record_history(std::mem::replace(&mut *sorted_set, updated));
+        self.set_config(Config {
+            replication_factor,
+            ..self.config().as_ref().clone()
+        });
+
+        let mut moved_partitions = Vec::new();
+        if self.is_updating() {
+            self.partitions_dirty.store(true, Ordering::Release);
+        } else {
+            let new_partitions = self.compute_partitions(&sorted_set, &partitions);
+            moved_partitions = new_partitions
+                .iter()
+                .filter(|(part_id, node)| match partitions.get(part_id) {
+                    Some(old) => old.id() != node.id(),
+                    None => true,
+                })
+                .map(|(part_id, _)| *part_id)
+                .collect();
+            moved_partitions.sort_unstable();
+            *partitions = new_partitions;
+        }
+        self.bump_version();
+        self.log_changes("set_replication_factor", &timer, None, &partitions);
+        timer.finish("set_replication_factor", nodes.len(), sorted_set.len());
+
+        Ok(ReplicationDelta {
+            previous,
+            current: replication_factor,
+            added_vnodes,
+            removed_vnodes,
+            moved_partitions,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::tests::test_nodes;
+    use crate::{Config, HashRing};
+
+    #[test]
+    fn test_set_replication_factor_is_incremental() {
+        let mut hash_ring = HashRing::new(Config::default()).unwrap();
+        for node in test_nodes() {
+            hash_ring.add_node(node).unwrap();
+        }
+        let before = hash_ring.snapshot();
+
+        let grown = hash_ring.set_replication_factor(30).unwrap();
+        assert_eq!((grown.previous, grown.current), (20, 30));
+        assert_eq!(grown.added_vnodes, 30);
+        assert_eq!(grown.removed_vnodes, 0);
+        assert_eq!(hash_ring.virtual_nodes_per_node().get("node1"), Some(&30));
+
+        let shrunk = hash_ring.set_replication_factor(20).unwrap();
+        assert_eq!(shrunk.removed_vnodes, 30);
+        assert_eq!(shrunk.moved_partitions, grown.moved_partitions);
+        assert_eq!(hash_ring.snapshot().partitions, before.partitions);
+    }
+
+    #[test]
+    fn test_set_replication_factor_keeps_weights() {
+        let mut hash_ring = HashRing::new(Config::default()).unwrap();
+        for node in test_nodes() {
+            hash_ring.add_node(node).unwrap();
+        }
+        assert_eq!(hash_ring.decay("node1", 0.5).unwrap(), 10);
+
+        let grown = hash_ring.set_replication_factor(40).unwrap();
+        assert_eq!(grown.added_vnodes, 50);
+        assert_eq!(hash_ring.virtual_nodes_per_node().get("node1"), Some(&20));
+        assert_eq!(hash_ring.virtual_nodes_per_node().get("node2"), Some(&40));
+        hash_ring.set_replication_factor(20).unwrap();
+        assert_eq!(hash_ring.virtual_nodes_per_node().get("node1"), Some(&10));
+        assert_eq!(hash_ring.node_weight("node1"), Some(0.5));
+    }
+
+    #[test]
+    fn test_set_replication_factor_rejects_zero() {
+        let mut hash_ring = HashRing::new(Config::default()).unwrap();
+        assert!(hash_ring.set_replication_factor(0).is_err());
+        assert_eq!(hash_ring.config().replication_factor, 20);
+    }
+
+    #[test]
+    fn test_replication_factor_is_shared_between_handles() {
+        let mut hash_ring = HashRing::new(Config::default()).unwrap();
+        for node in test_nodes() {
+            hash_ring.add_node(node).unwrap();
+        }
+        let mut shared = hash_ring.share();
+        hash_ring.set_replication_factor(40).unwrap();
+        assert_eq!(shared.config().replication_factor, 40);
+
+        // The other handle removes every virtual node placed through the first.
+        shared.remove_node("node1").unwrap();
+        assert!(!hash_ring.virtual_nodes_per_node().contains_key("node1"));
+        hash_ring.check_invariants().unwrap();
+    }
+}
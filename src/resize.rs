@@ -0,0 +1,182 @@
+//! Changing the partition count of a live ring.
+//!
+//! Keys map to `hash % partition_count`, so with an old count `m` and a new count `n`,
+//! old partition `p` and new partition `q` share keys exactly when `p ≡ q (mod gcd(m, n))`,
+//! and every such pair holds `1 / lcm(m, n)` of the keyspace. The migration plan lists
+//! the pairs whose owner changes.
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::hash::BuildHasher;
+
+use crate::trace::OpTimer;
+use crate::{Config, HashRing};
+
+/// A slice of the keyspace that changes owner when the partition count changes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PartitionMigration {
+    /// The partition holding the keys before the resize.
+    pub from_partition: usize,
+    /// The partition holding the keys after the resize.
+    pub to_partition: usize,
+    /// The ID of the node owning the keys before the resize.
+    pub from: String,
+    /// The ID of the node owning the keys after the resize.
+    pub to: String,
+}
+
+fn gcd(mut a: usize, mut b: usize) -> usize {
+    while b != 0 {
+        (a, b) = (b, a % b);
+    }
+    a
+}
+
+impl<'a, H, K> HashRing<'a, H, K>
+where
+    H: BuildHasher,
+    K: BuildHasher,
+{
+    /// Changes the partition count and reassigns every partition.
+    ///
+    /// Recorded partition load is cleared since partition IDs change meaning.
+    /// Rings with split partitions must merge them first.
+    /// To add partitions without renumbering the existing ones, use
+    /// `split_all_partitions` instead.
+    ///
+    /// # Arguments
+    ///
+    /// * `new_count` - The new number of partitions.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Vec<PartitionMigration>, Box<dyn Error>>` - The keyspace slices that change owner.
+    ///   Each covers `1 / lcm(old_count, new_count)` of the keyspace.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use std::sync::Arc;
+    /// use hashring::{HashRing, Config, Node};
+    ///
+    /// #[derive(Debug)]
+    /// struct MyNode<'a> {
+    ///     name: &'a str,
+    /// }
+    ///
+    /// impl<'a> Node<'a> for MyNode<'a> {
+    ///     fn id(&self) -> &'a str {
+    ///         self.name
+    ///     }
+    /// }
+    ///
+    /// let mut hash_ring = HashRing::new(Config::default()).unwrap();
+    /// hash_ring.add_node(Arc::new(MyNode { name: "node1" })).unwrap();
+    /// hash_ring.add_node(Arc::new(MyNode { name: "node2" })).unwrap();
+    ///
+    /// for migration in hash_ring.resize_partitions(512).unwrap() {
+    ///     println!("{} -> {}: {} -> {}", migration.from_partition, migration.to_partition, migration.from, migration.to);
+    /// }
+    /// ```
+    pub fn resize_partitions(&mut self, new_count: usize) -> Result<Vec<PartitionMigration>, Box<dyn Error>> {
+        let _events = self.dispatch_events();
+        let timer = OpTimer::start();
+        if new_count == 0 {
+            return Err("Partition count must be greater than 0".into());
+        }
+        if !self.routes.read()?.splits.is_empty() {
+            return Err("cannot resize a ring with split partitions".into());
+        }
+
+        let sorted_set = self.sorted_nodes_hash_set.read()?;
+        let mut partitions = self.partitions.write()?;
+
+        let old_count = self.config().partition_count;
+        self.set_config(Config {
+            partition_count: new_count,
+            ..self.config().as_ref().clone()
+        });
+        // The partitions are renumbered, so nodes without headroom keep none of them.
+        let new_partitions = self.compute_partitions(&sorted_set, &HashMap::new());
+
+        let step = gcd(old_count, new_count);
+        let mut plan = Vec::new();
+        for from_partition in 0..old_count {
+            let from = match partitions.get(&from_partition) {
+                Some(node) => node,
+                None => continue,
+            };
+            for to_partition in (from_partition % step..new_count).step_by(step) {
+                if let Some(to) = new_partitions.get(&to_partition) {
+                    if from.id() != to.id() {
+                        plan.push(PartitionMigration {
+                            from_partition,
+                            to_partition,
+                            from: from.id().to_string(),
+                            to: to.id().to_string(),
+                        });
+                    }
+                }
+            }
+        }
+
+        *partitions = new_partitions;
+        self.partition_load.write()?.clear();
+        self.bump_version();
+        self.log_changes("resize_partitions", &timer, None, &partitions);
+        timer.finish("resize_partitions", new_count, sorted_set.len());
+
+        Ok(plan)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use crate::tests::test_nodes;
+    use crate::{Config, HashRing};
+
+    #[test]
+    fn test_resize_plan_covers_moved_keys() {
+        let mut hash_ring = HashRing::new(Config {
+            partition_count: 6,
+            ..Config::default()
+        })
+        .unwrap();
+        for node in test_nodes() {
+            hash_ring.add_node(node).unwrap();
+        }
+
+        let keys: Vec<String> = (0..1000).map(|i| format!("key-{}", i)).collect();
+        let owner_of = |hash_ring: &HashRing, key: &str| {
+            let part_id = hash_ring.get_partition(key.as_bytes());
+            (part_id, hash_ring.get_partition_owner(part_id).unwrap().id().to_string())
+        };
+        let before: HashMap<&str, (usize, String)> = keys.iter().map(|k| (k.as_str(), owner_of(&hash_ring, k))).collect();
+
+        let plan = hash_ring.resize_partitions(9).unwrap();
+        assert_eq!(hash_ring.config().partition_count, 9);
+        assert_eq!(hash_ring.partition_ids().len(), 9);
+
+        for key in &keys {
+            let (from_partition, from) = &before[key.as_str()];
+            let (to_partition, to) = owner_of(&hash_ring, key);
+            let planned = plan
+                .iter()
+                .any(|m| m.from_partition == *from_partition && m.to_partition == to_partition && &m.from == from && m.to == to);
+            assert_eq!(planned, *from != to);
+        }
+    }
+
+    #[test]
+    fn test_resize_rejects_invalid_counts_and_splits() {
+        let mut hash_ring = HashRing::new(Config::default()).unwrap();
+        assert!(hash_ring.resize_partitions(0).is_err());
+        assert!(hash_ring.resize_partitions(16).unwrap().is_empty());
+
+        hash_ring.split_partition(0).unwrap();
+        assert!(hash_ring.resize_partitions(32).is_err());
+        assert_eq!(hash_ring.config().partition_count, 16);
+    }
+}
@@ -0,0 +1,100 @@
+//! Fallback order for retries.
+//!
+//! When a request to the owner of a key fails, every client should retry against the
+//! same nodes in the same order, or writes for one key scatter across the cluster.
+//! `retry_sequence` yields the owner and then every other member, in the order the
+//! ring reaches them from the key: the rest of the preference list first, then the
+//! nodes beyond it.
+
+use std::collections::HashSet;
+use std::hash::BuildHasher;
+use std::sync::Arc;
+
+use crate::{HashRing, Node};
+
+impl<'a, H, K> HashRing<'a, H, K>
+where
+    H: BuildHasher,
+    K: BuildHasher,
+{
+    /// Returns the nodes to try for a key, in order: its owner, then the distinct nodes
+    /// following the key on the ring until every member has been yielded.
+    ///
+    /// The order only depends on the membership of the ring, so clients agree on it.
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - The key of the request.
+    ///
+    /// # Returns
+    ///
+    /// * `impl Iterator<Item = Arc<dyn Node<'a> + 'a>>` - The nodes to try, empty if the ring has no nodes.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use std::sync::Arc;
+    /// use hashring::{HashRing, Config, Node};
+    ///
+    /// #[derive(Debug)]
+    /// struct MyNode<'a> {
+    ///     name: &'a str,
+    /// }
+    ///
+    /// impl<'a> Node<'a> for MyNode<'a> {
+    ///     fn id(&self) -> &'a str {
+    ///         self.name
+    ///     }
+    /// }
+    ///
+    /// let mut hash_ring = HashRing::new(Config::default()).unwrap();
+    /// for name in ["node1", "node2", "node3"] {
+    ///     hash_ring.add_node(Arc::new(MyNode { name })).unwrap();
+    /// }
+    ///
+    /// let mut targets = hash_ring.retry_sequence(b"some_key");
+    /// assert_eq!(targets.next().unwrap().id(), hash_ring.get_key(b"some_key").unwrap().id());
+    /// // At most two retries.
+    /// let fallbacks: Vec<_> = targets.take(2).collect();
+    /// assert_eq!(fallbacks.len(), 2);
+    /// ```
+    pub fn retry_sequence(&self, key: &[u8]) -> impl Iterator<Item = Arc<dyn Node<'a> + 'a>> {
+        let owner = self.get_key(key);
+        let mut sequence: Vec<Arc<dyn Node<'a> + 'a>> = owner.into_iter().collect();
+        let mut seen: HashSet<&str> = sequence.iter().map(|node| node.id()).collect();
+        let Ok(sorted_set) = self.sorted_nodes_hash_set.read() else {
+            return sequence.into_iter();
+        };
+        let Ok(point) = self.hash_key(key).and_then(|hashed_key| self.route(&sorted_set, hashed_key)) else {
+            return sequence.into_iter();
+        };
+        for (_, node) in sorted_set.range(point..).chain(sorted_set.range(..point)) {
+            if seen.insert(node.id()) {
+                sequence.push(node.clone());
+            }
+        }
+        sequence.into_iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::tests::test_nodes;
+    use crate::{Config, HashRing};
+
+    #[test]
+    fn test_retry_sequence_extends_preference_list() {
+        let mut hash_ring = HashRing::new(Config::default()).unwrap();
+        assert_eq!(hash_ring.retry_sequence(b"some_key").count(), 0);
+        for node in test_nodes() {
+            hash_ring.add_node(node).unwrap();
+        }
+        for i in 0..50 {
+            let key = format!("key{}", i);
+            let sequence: Vec<&str> = hash_ring.retry_sequence(key.as_bytes()).map(|node| node.id()).collect();
+            let preference_list: Vec<&str> = hash_ring.get_preference_list_n(key.as_bytes(), 2).iter().map(|node| node.id()).collect();
+            assert_eq!(sequence.len(), 3);
+            assert_eq!(sequence[..2], preference_list[..]);
+        }
+    }
+}
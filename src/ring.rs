@@ -0,0 +1,112 @@
+//! A common interface over the ring implementations in this crate.
+//!
+//! Code written against `Ring` works with any placement algorithm, either through
+//! generics or as a `dyn Ring` trait object.
+
+use std::hash::BuildHasher;
+use std::sync::Arc;
+
+use crate::{FrozenRing, HashRing, Node};
+
+/// Key placement shared by every ring implementation.
+pub trait Ring<'a> {
+    /// Returns the node responsible for the key.
+    fn lookup(&self, key: &[u8]) -> Option<Arc<dyn Node<'a> + 'a>>;
+
+    /// Returns up to `n` distinct nodes for the key, in order of preference.
+    fn preference_list(&self, key: &[u8], n: usize) -> Vec<Arc<dyn Node<'a> + 'a>>;
+
+    /// Returns the members of the ring, sorted by ID.
+    fn members(&self) -> Vec<Arc<dyn Node<'a> + 'a>>;
+
+    /// Returns a counter that increases whenever the placement of keys may have changed.
+    fn version(&self) -> u64;
+}
+
+impl<'a, H, K> Ring<'a> for HashRing<'a, H, K>
+where
+    H: BuildHasher,
+    K: BuildHasher,
+{
+    fn lookup(&self, key: &[u8]) -> Option<Arc<dyn Node<'a> + 'a>> {
+        self.get_key(key)
+    }
+
+    fn preference_list(&self, key: &[u8], n: usize) -> Vec<Arc<dyn Node<'a> + 'a>> {
+        self.get_preference_list_n(key, n)
+    }
+
+    fn members(&self) -> Vec<Arc<dyn Node<'a> + 'a>> {
+        let nodes = self.nodes.wait_read();
+        let mut members: Vec<Arc<dyn Node<'a> + 'a>> = nodes.values().cloned().collect();
+        members.sort_by(|a, b| a.id().cmp(b.id()));
+        members
+    }
+
+    fn version(&self) -> u64 {
+        HashRing::version(self)
+    }
+}
+
+impl<'a, K> Ring<'a> for FrozenRing<'a, K>
+where
+    K: BuildHasher,
+{
+    fn lookup(&self, key: &[u8]) -> Option<Arc<dyn Node<'a> + 'a>> {
+        self.get_key(key)
+    }
+
+    fn preference_list(&self, key: &[u8], n: usize) -> Vec<Arc<dyn Node<'a> + 'a>> {
+        self.get_preference_list_n(key, n)
+    }
+
+    fn members(&self) -> Vec<Arc<dyn Node<'a> + 'a>> {
+        self.nodes().to_vec()
+    }
+
+    fn version(&self) -> u64 {
+        FrozenRing::version(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::tests::test_nodes;
+    use crate::{Config, HashRing, Ring};
+
+    fn owner(ring: &dyn Ring<'static>, key: &[u8]) -> String {
+        ring.lookup(key).unwrap().id().to_string()
+    }
+
+    #[test]
+    fn test_ring_trait_objects() {
+        let mut hash_ring = HashRing::new(Config::default()).unwrap();
+        assert_eq!(Ring::version(&hash_ring), 0);
+        for node in test_nodes() {
+            hash_ring.add_node(node).unwrap();
+        }
+        let frozen = hash_ring.freeze();
+
+        let rings: Vec<&dyn Ring<'static>> = vec![&hash_ring, &frozen];
+        for ring in rings {
+            assert_eq!(owner(ring, b"some_key"), hash_ring.get_key(b"some_key").unwrap().id());
+            assert_eq!(ring.preference_list(b"some_key", 2).len(), 2);
+            assert_eq!(ring.members().iter().map(|n| n.id()).collect::<Vec<_>>(), vec!["node1", "node2", "node3"]);
+            assert_eq!(ring.version(), 3);
+        }
+    }
+
+    #[test]
+    fn test_version_tracks_changes() {
+        let mut hash_ring = HashRing::new(Config::default()).unwrap();
+        let nodes = test_nodes();
+        hash_ring.add_node(nodes[0].clone()).unwrap();
+        let version = hash_ring.version();
+
+        assert!(hash_ring.add_node(nodes[0].clone()).is_err());
+        assert_eq!(hash_ring.version(), version);
+
+        hash_ring.remove_node("node1").unwrap();
+        assert!(hash_ring.version() > version);
+    }
+}
@@ -0,0 +1,159 @@
+//! The random number generator of a ring.
+//!
+//! Placement decisions that need randomness draw from a `RingRng` kept in the ring
+//! rather than from an ambient source, so replaying the same operations from the same
+//! seed makes the same decisions. The generator is SplitMix64, whose whole state is one
+//! `u64`: `rng_checkpoint` saves it and `restore_rng` rewinds to it, e.g. alongside a
+//! snapshot or at the start of a test.
+//!
+//! Sampling is the only consumer today; multi-probe placement and ramping are derived
+//! from hashes and elapsed time, and take nothing from the generator.
+
+use std::hash::BuildHasher;
+use std::sync::Arc;
+
+use crate::{HashRing, Node};
+
+/// A seedable SplitMix64 generator.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RingRng {
+    state: u64,
+}
+
+impl RingRng {
+    /// Creates a generator from a seed.
+    pub fn new(seed: u64) -> RingRng {
+        RingRng { state: seed }
+    }
+
+    /// Returns the next uniformly distributed `u64`.
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    }
+
+    /// Returns the state of the generator, from which `RingRng::new` resumes the same
+    /// sequence.
+    pub fn checkpoint(&self) -> u64 {
+        self.state
+    }
+}
+
+impl<'a, H, K> HashRing<'a, H, K>
+where
+    H: BuildHasher,
+    K: BuildHasher,
+{
+    /// Reseeds the random number generator of the ring, which starts from seed `0`.
+    ///
+    /// # Arguments
+    ///
+    /// * `seed` - The new seed.
+    pub fn seed_rng(&self, seed: u64) {
+        *self.rng.wait_write() = RingRng::new(seed);
+    }
+
+    /// Returns the current state of the random number generator of the ring.
+    ///
+    /// # Returns
+    ///
+    /// * `u64` - A checkpoint to pass to `restore_rng`.
+    pub fn rng_checkpoint(&self) -> u64 {
+        self.rng.wait_read().checkpoint()
+    }
+
+    /// Rewinds the random number generator of the ring to a checkpoint, so the draws
+    /// made since are made again.
+    ///
+    /// # Arguments
+    ///
+    /// * `checkpoint` - A value returned by `rng_checkpoint`.
+    pub fn restore_rng(&self, checkpoint: u64) {
+        *self.rng.wait_write() = RingRng::new(checkpoint);
+    }
+
+    /// Samples nodes like `sample_nodes`, drawing the random points from the random
+    /// number generator of the ring.
+    ///
+    /// # Arguments
+    ///
+    /// * `count` - The number of nodes to sample.
+    ///
+    /// # Returns
+    ///
+    /// * `Vec<Arc<dyn Node<'a> + 'a>>` - The sampled nodes, empty if the ring has no nodes.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use std::sync::Arc;
+    /// use hashring::{HashRing, Config, Node};
+    ///
+    /// #[derive(Debug)]
+    /// struct MyNode<'a> {
+    ///     name: &'a str,
+    /// }
+    ///
+    /// impl<'a> Node<'a> for MyNode<'a> {
+    ///     fn id(&self) -> &'a str {
+    ///         self.name
+    ///     }
+    /// }
+    ///
+    /// let mut hash_ring = HashRing::new(Config::default()).unwrap();
+    /// hash_ring.add_node(Arc::new(MyNode { name: "node1" })).unwrap();
+    /// hash_ring.add_node(Arc::new(MyNode { name: "node2" })).unwrap();
+    ///
+    /// hash_ring.seed_rng(42);
+    /// let checkpoint = hash_ring.rng_checkpoint();
+    /// let first = hash_ring.sample_nodes_seeded(10);
+    ///
+    /// // Replaying from the checkpoint draws the same nodes.
+    /// hash_ring.restore_rng(checkpoint);
+    /// let replayed = hash_ring.sample_nodes_seeded(10);
+    /// assert!(first.iter().zip(&replayed).all(|(a, b)| a.id() == b.id()));
+    /// ```
+    pub fn sample_nodes_seeded(&self, count: usize) -> Vec<Arc<dyn Node<'a> + 'a>> {
+        let mut rng = self.rng.wait_write();
+        self.sample_nodes(count, || rng.next_u64())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RingRng;
+    use crate::tests::test_nodes;
+    use crate::{Config, HashRing};
+
+    #[test]
+    fn test_ring_rng_replays_from_checkpoints() {
+        let mut rng = RingRng::new(7);
+        rng.next_u64();
+        let mut resumed = RingRng::new(rng.checkpoint());
+        assert_eq!(rng.next_u64(), resumed.next_u64());
+
+        let mut hash_ring = HashRing::new(Config::default()).unwrap();
+        for node in test_nodes() {
+            hash_ring.add_node(node).unwrap();
+        }
+        let ids = |hash_ring: &HashRing<'static>| -> Vec<&'static str> {
+            hash_ring.sample_nodes_seeded(50).iter().map(|node| node.id()).collect()
+        };
+        hash_ring.seed_rng(1);
+        let first = ids(&hash_ring);
+        let checkpoint = hash_ring.rng_checkpoint();
+        let second = ids(&hash_ring);
+        assert_ne!(first, second);
+
+        // Copies carry the state of the generator along.
+        let copy = hash_ring.clone();
+        hash_ring.restore_rng(checkpoint);
+        assert_eq!(ids(&hash_ring), second);
+        assert_eq!(ids(&copy), ids(&hash_ring));
+        hash_ring.seed_rng(1);
+        assert_eq!(ids(&hash_ring), first);
+    }
+}
@@ -0,0 +1,171 @@
+//! Read/write split placement.
+//!
+//! Primary/replica storage tiers mix nodes that accept writes with read-only replicas
+//! serving data copied from them. Nodes advertise which they are with `Node::role`, and
+//! `get_key_for` routes writes to the first read-write node following the key while
+//! reads go to the first node of either role, so read traffic spreads over the replicas
+//! as well as the primaries in proportion to the keyspace they own.
+
+use std::fmt;
+use std::hash::BuildHasher;
+use std::sync::Arc;
+
+use crate::{HashRing, Node};
+
+/// What a node accepts.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum NodeRole {
+    /// The node accepts reads and writes.
+    #[default]
+    ReadWrite,
+    /// The node only serves reads.
+    ReadOnly,
+}
+
+impl fmt::Display for NodeRole {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            NodeRole::ReadWrite => write!(fmt, "read-write"),
+            NodeRole::ReadOnly => write!(fmt, "read-only"),
+        }
+    }
+}
+
+/// The kind of request being routed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Operation {
+    Read,
+    Write,
+}
+
+impl NodeRole {
+    /// Returns `true` if a node with this role can serve the operation.
+    pub fn allows(&self, op: Operation) -> bool {
+        match op {
+            Operation::Read => true,
+            Operation::Write => *self == NodeRole::ReadWrite,
+        }
+    }
+}
+
+impl<'a, H, K> HashRing<'a, H, K>
+where
+    H: BuildHasher,
+    K: BuildHasher,
+{
+    /// Retrieves the node that should serve an operation on the given key.
+    ///
+    /// Reads are routed like `get_key`. Writes go to the first read-write node following
+    /// the key on the ring; a traffic split only applies to them if it diverts to a
+    /// read-write node.
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - The key of the request.
+    /// * `op` - Whether the request reads or writes.
+    ///
+    /// # Returns
+    ///
+    /// * `Option<Arc<dyn Node<'a> + 'a>>` - The node to send the request to, or `None` if no node can serve it.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use std::sync::Arc;
+    /// use hashring::{HashRing, Config, Node, NodeRole, Operation};
+    ///
+    /// #[derive(Debug)]
+    /// struct MyNode<'a> {
+    ///     name: &'a str,
+    ///     role: NodeRole,
+    /// }
+    ///
+    /// impl<'a> Node<'a> for MyNode<'a> {
+    ///     fn id(&self) -> &'a str {
+    ///         self.name
+    ///     }
+    ///
+    ///     fn role(&self) -> NodeRole {
+    ///         self.role
+    ///     }
+    /// }
+    ///
+    /// let mut hash_ring = HashRing::new(Config::default()).unwrap();
+    /// hash_ring.add_node(Arc::new(MyNode { name: "primary", role: NodeRole::ReadWrite })).unwrap();
+    /// hash_ring.add_node(Arc::new(MyNode { name: "replica", role: NodeRole::ReadOnly })).unwrap();
+    ///
+    /// assert_eq!(hash_ring.get_key_for(b"some_key", Operation::Write).unwrap().id(), "primary");
+    /// // Reads may go to either node.
+    /// assert!(hash_ring.get_key_for(b"some_key", Operation::Read).is_some());
+    /// ```
+    pub fn get_key_for(&self, key: &[u8], op: Operation) -> Option<Arc<dyn Node<'a> + 'a>> {
+        if op == Operation::Read {
+            return self.get_key(key);
+        }
+        let hashed_key = self.hash_key(key).ok()?;
+        let owner = {
+            let sorted_set = self.sorted_nodes_hash_set.read().ok()?;
+            let point = self.route(&sorted_set, hashed_key).ok()?;
+            sorted_set
+                .range(point..)
+                .chain(sorted_set.range(..point))
+                .map(|(_, node)| node)
+                .find(|node| node.role().allows(op))
+                .cloned()?
+        };
+        match self.divert(&owner, hashed_key) {
+            Some(target) if target.role().allows(op) => Some(target),
+            _ => Some(owner),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+    use std::sync::Arc;
+
+    use crate::{Config, HashRing, Node, NodeRole, Operation};
+
+    #[derive(Debug)]
+    struct RoleNode {
+        name: &'static str,
+        role: NodeRole,
+    }
+
+    impl Node<'static> for RoleNode {
+        fn id(&self) -> &'static str {
+            self.name
+        }
+
+        fn role(&self) -> NodeRole {
+            self.role
+        }
+    }
+
+    #[test]
+    fn test_writes_skip_read_only_nodes() {
+        let mut hash_ring = HashRing::new(Config::default()).unwrap();
+        hash_ring.add_node(Arc::new(RoleNode { name: "ro1", role: NodeRole::ReadOnly })).unwrap();
+        assert!(hash_ring.get_key_for(b"some_key", Operation::Write).is_none());
+        assert_eq!(hash_ring.get_key_for(b"some_key", Operation::Read).unwrap().id(), "ro1");
+
+        hash_ring.add_node(Arc::new(RoleNode { name: "rw1", role: NodeRole::ReadWrite })).unwrap();
+        hash_ring.add_node(Arc::new(RoleNode { name: "rw2", role: NodeRole::ReadWrite })).unwrap();
+        hash_ring.add_node(Arc::new(RoleNode { name: "ro2", role: NodeRole::ReadOnly })).unwrap();
+
+        let mut readers = HashSet::new();
+        for i in 0..200 {
+            let key = format!("key{}", i);
+            let writer = hash_ring.get_key_for(key.as_bytes(), Operation::Write).unwrap();
+            assert_eq!(writer.role(), NodeRole::ReadWrite);
+            let reader = hash_ring.get_key_for(key.as_bytes(), Operation::Read).unwrap();
+            assert_eq!(reader.id(), hash_ring.get_key(key.as_bytes()).unwrap().id());
+            if reader.role() == NodeRole::ReadWrite {
+                assert_eq!(reader.id(), writer.id());
+            }
+            readers.insert(reader.id());
+        }
+        assert_eq!(readers.len(), 4);
+    }
+}
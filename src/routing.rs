@@ -0,0 +1,166 @@
+//! Routing shared by `HashRing` and `LocalHashRing`.
+//!
+//! Both rings map the points of their virtual nodes to nodes in a `BTreeMap`, holding
+//! `Arc`s in one and `Rc`s in the other. `Routes` keeps everything else lookups depend
+//! on without holding any node, and its lookups are generic over the pointer, so both
+//! rings place partitions and route keys with the same code.
+
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::error::Error;
+use std::hash::{BuildHasher, Hasher};
+use std::ops::Deref;
+
+use crate::local::LocalNode;
+use crate::{canary, load, split, util, TrafficSplit};
+
+/// How lookups are routed besides the virtual nodes: the partitions split in two, the
+/// partitions moved by a rebalance and the traffic diverted to canaries.
+///
+/// `HashRing` keeps them under one lock so a lookup reads them with a single acquisition.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Routes<'a> {
+    pub(crate) splits: BTreeSet<usize>,
+    pub(crate) moved: load::MovedPartitions<'a>,
+    pub(crate) traffic_splits: HashMap<String, TrafficSplit>,
+}
+
+impl<'a> Routes<'a> {
+    /// Returns the partition a key hash falls into, descending through split partitions.
+    pub(crate) fn partition(&self, partition_count: usize, hashed_key: u64) -> usize {
+        split::route_partition(partition_count, &self.splits, hashed_key)
+    }
+
+    /// Returns every partition that receives keys, in ascending order.
+    pub(crate) fn leaves(&self, partition_count: usize) -> Vec<usize> {
+        split::leaf_partitions(partition_count, &self.splits)
+    }
+
+    /// Returns the point the lookup of a key hash starts from: the hash itself, or, when
+    /// its partition was moved by a rebalance, the first virtual node of the new owner
+    /// following the hash, so the keys are spread over all of its virtual nodes.
+    pub(crate) fn point<N>(&self, partition_count: usize, sorted_set: &BTreeMap<u64, N>, hashed_key: u64) -> u64
+    where
+        N: Deref,
+        N::Target: LocalNode<'a>,
+    {
+        if self.moved.is_empty() {
+            return hashed_key;
+        }
+        let part_id = self.partition(partition_count, hashed_key);
+        if load::moved_point(&self.moved, sorted_set, part_id).is_none() {
+            return hashed_key;
+        }
+        let node_id = self.moved[&part_id].1;
+        sorted_set
+            .range(hashed_key..)
+            .chain(sorted_set.range(..hashed_key))
+            .find(|(_, node)| node.id() == node_id)
+            .map_or(hashed_key, |(point, _)| *point)
+    }
+
+    /// Returns the virtual nodes in ring order from the point the lookup of a key hash
+    /// starts from, going around the ring once.
+    pub(crate) fn walk<'s, N>(&self, partition_count: usize, sorted_set: &'s BTreeMap<u64, N>, hashed_key: u64) -> impl Iterator<Item = &'s N>
+    where
+        N: Deref,
+        N::Target: LocalNode<'a>,
+    {
+        let point = self.point(partition_count, sorted_set, hashed_key);
+        sorted_set.range(point..).chain(sorted_set.range(..point)).map(|(_, node)| node)
+    }
+
+    /// Returns the node owning a key hash, before traffic splits.
+    pub(crate) fn owner<'s, N>(&self, partition_count: usize, sorted_set: &'s BTreeMap<u64, N>, hashed_key: u64) -> Option<&'s N>
+    where
+        N: Deref,
+        N::Target: LocalNode<'a>,
+    {
+        let point = self.point(partition_count, sorted_set, hashed_key);
+        util::next_point(sorted_set, point).map(|(_, node)| node)
+    }
+
+    /// Returns the ID of the canary a key hash owned by `owner_id` is diverted to, if any.
+    /// Keys stay with their owner while the canary isn't a member.
+    pub(crate) fn canary(&self, owner_id: &str, hashed_key: u64) -> Option<&str> {
+        if self.traffic_splits.is_empty() {
+            return None;
+        }
+        canary::split_target(&self.traffic_splits, owner_id, hashed_key)
+    }
+
+    /// Returns up to `n` distinct nodes for a key hash, in ring order.
+    pub(crate) fn preference_list<N>(&self, partition_count: usize, sorted_set: &BTreeMap<u64, N>, hashed_key: u64, n: usize) -> Vec<N>
+    where
+        N: Deref + Clone,
+        N::Target: LocalNode<'a>,
+    {
+        let mut preference_list = Vec::new();
+        if n == 0 {
+            return preference_list;
+        }
+        let mut unique_nodes = HashSet::new();
+        for node in self.walk(partition_count, sorted_set, hashed_key) {
+            if unique_nodes.insert(node.id()) {
+                preference_list.push(node.clone());
+                if preference_list.len() >= n {
+                    break;
+                }
+            }
+        }
+        preference_list
+    }
+
+    /// Routes the lookups of the keys of a partition to the node `node_id`, each from the
+    /// virtual node of `node_id` following the key. The move is forgotten if the node has
+    /// no virtual nodes.
+    pub(crate) fn move_partition<N>(&mut self, sorted_set: &BTreeMap<u64, N>, part_id: usize, node_id: &'a str)
+    where
+        N: Deref,
+        N::Target: LocalNode<'a>,
+    {
+        match sorted_set.iter().find(|(_, owner)| owner.id() == node_id) {
+            Some((point, _)) => {
+                self.moved.insert(part_id, (*point, node_id));
+            }
+            None => {
+                self.moved.remove(&part_id);
+            }
+        }
+    }
+
+    /// Clears the traffic splits of a node that left the ring, as owner or as canary.
+    pub(crate) fn forget_traffic_splits(&mut self, node_id: &str) {
+        self.traffic_splits.retain(|owner_id, split| owner_id != node_id && split.canary != node_id);
+    }
+
+    /// Diverts a fraction of the keys of a node to a canary. Callers check that both
+    /// are members.
+    pub(crate) fn set_traffic_split(&mut self, node_id: &str, canary_id: &str, fraction: f64) -> Result<(), Box<dyn Error>> {
+        if !(0.0..=1.0).contains(&fraction) {
+            return Err("traffic split fraction must be between 0.0 and 1.0".into());
+        }
+        if node_id == canary_id {
+            return Err("a node cannot be its own canary".into());
+        }
+        self.traffic_splits.insert(
+            node_id.to_string(),
+            TrafficSplit {
+                canary: canary_id.to_string(),
+                fraction,
+            },
+        );
+        Ok(())
+    }
+}
+
+/// Hashes the ID of a partition to the point its owner is found from.
+pub(crate) fn hash_partition_id(node_hasher: &impl BuildHasher, part_id: usize) -> u64 {
+    let mut hasher = node_hasher.build_hasher();
+    hasher.write(&part_id.to_ne_bytes());
+    hasher.finish()
+}
+
+/// Returns the node a partition is assigned to: the one following it on the ring.
+pub(crate) fn partition_owner<'s, N>(node_hasher: &impl BuildHasher, sorted_set: &'s BTreeMap<u64, N>, part_id: usize) -> Option<&'s N> {
+    util::next_point(sorted_set, hash_partition_id(node_hasher, part_id)).map(|(_, node)| node)
+}
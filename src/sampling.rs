@@ -0,0 +1,167 @@
+//! Sampling nodes in proportion to the keyspace they own.
+//!
+//! Probes, background scrubbing and load tests that should mirror real traffic need to
+//! pick nodes as often as uniformly hashed keys reach them. A uniform random point of the
+//! ring is owned by each node with probability equal to its keyspace fraction, so
+//! `sample_nodes` draws random points and returns their owners.
+//! `sample_nodes_seeded` does the same with the generator of the ring, for reproducible
+//! samples.
+//!
+//! Validation tools testing routing and replication end to end also need keys known to
+//! land in a given partition. `sample_keys_for_partition` searches the synthetic keys
+//! `sample-{partition}-0`, `sample-{partition}-1`, ... for those hashing into it, so the
+//! same ring always yields the same keys.
+
+use std::error::Error;
+use std::hash::BuildHasher;
+use std::sync::Arc;
+
+use crate::{split, util, HashRing, Node};
+
+impl<'a, H, K> HashRing<'a, H, K>
+where
+    H: BuildHasher,
+    K: BuildHasher,
+{
+    /// Samples nodes with replacement, each with probability equal to the fraction of the
+    /// keyspace it owns. Traffic splits are ignored.
+    ///
+    /// # Arguments
+    ///
+    /// * `count` - The number of nodes to sample.
+    /// * `rng` - A source of uniformly distributed random `u64`s.
+    ///
+    /// # Returns
+    ///
+    /// * `Vec<Arc<dyn Node<'a> + 'a>>` - The sampled nodes, empty if the ring has no nodes.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use std::collections::hash_map::RandomState;
+    /// use std::hash::BuildHasher;
+    /// use std::sync::Arc;
+    /// use hashring::{HashRing, Config, Node};
+    ///
+    /// #[derive(Debug)]
+    /// struct MyNode<'a> {
+    ///     name: &'a str,
+    /// }
+    ///
+    /// impl<'a> Node<'a> for MyNode<'a> {
+    ///     fn id(&self) -> &'a str {
+    ///         self.name
+    ///     }
+    /// }
+    ///
+    /// let mut hash_ring = HashRing::new(Config::default()).unwrap();
+    /// hash_ring.add_node(Arc::new(MyNode { name: "node1" })).unwrap();
+    /// hash_ring.add_node(Arc::new(MyNode { name: "node2" })).unwrap();
+    ///
+    /// let random = RandomState::new();
+    /// let mut counter = 0u64;
+    /// let probes = hash_ring.sample_nodes(10, || {
+    ///     counter += 1;
+    ///     random.hash_one(counter)
+    /// });
+    /// assert_eq!(probes.len(), 10);
+    /// ```
+    pub fn sample_nodes(&self, count: usize, mut rng: impl FnMut() -> u64) -> Vec<Arc<dyn Node<'a> + 'a>> {
+        let sorted_set = self.sorted_nodes_hash_set.wait_read();
+        if sorted_set.is_empty() {
+            return Vec::new();
+        }
+        (0..count).filter_map(|_| util::next_point(&sorted_set, rng()).map(|(_, node)| node.clone())).collect()
+    }
+
+    /// Generates keys that hash into the given partition, in a deterministic order.
+    ///
+    /// Keys are routed with the partitions as they are when this is called, so splitting
+    /// or merging partitions while iterating doesn't affect the keys generated.
+    ///
+    /// # Arguments
+    ///
+    /// * `part_id` - The ID of the partition, which must currently receive keys.
+    /// * `n` - The number of keys to generate.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<impl Iterator<Item = Vec<u8>>, Box<dyn Error>>` - The keys, or an error if
+    ///   the partition doesn't exist.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use hashring::{HashRing, Config};
+    ///
+    /// let hash_ring = HashRing::new(Config::default()).unwrap();
+    /// for key in hash_ring.sample_keys_for_partition(7, 3).unwrap() {
+    ///     assert_eq!(hash_ring.get_partition(&key), 7);
+    /// }
+    /// ```
+    pub fn sample_keys_for_partition(&self, part_id: usize, n: usize) -> Result<impl Iterator<Item = Vec<u8>> + '_, Box<dyn Error>> {
+        let splits = self.routes.read()?.splits.clone();
+        let (config, key_hasher) = (self.config(), &self.key_hasher);
+        let partition_count = config.partition_count;
+        if !split::is_leaf(partition_count, &splits, part_id) {
+            return Err(format!("partition {} does not exist", part_id).into());
+        }
+        Ok((0u64..)
+            .map(move |i| format!("sample-{}-{}", part_id, i).into_bytes())
+            .filter(move |key| split::route_partition(partition_count, &splits, util::hash_key(key_hasher, &config, key)) == part_id)
+            .take(n))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use crate::tests::test_nodes;
+    use crate::{Config, HashRing};
+
+    #[test]
+    fn test_samples_follow_keyspace() {
+        let mut hash_ring = HashRing::new(Config::default()).unwrap();
+        assert!(hash_ring.sample_nodes(5, || 0).is_empty());
+        for node in test_nodes() {
+            hash_ring.add_node(node).unwrap();
+        }
+
+        // SplitMix64, for a reproducible uniform sequence.
+        let mut state = 42u64;
+        let rng = || {
+            state = state.wrapping_add(0x9e3779b97f4a7c15);
+            let mut z = state;
+            z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+            z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+            z ^ (z >> 31)
+        };
+        let samples = hash_ring.sample_nodes(20_000, rng);
+        assert_eq!(samples.len(), 20_000);
+        let mut counts: HashMap<&str, usize> = HashMap::new();
+        for node in &samples {
+            *counts.entry(node.id()).or_default() += 1;
+        }
+        for id in ["node1", "node2", "node3"] {
+            let observed = counts[id] as f64 / samples.len() as f64;
+            assert!((observed - hash_ring.keyspace_fraction(id)).abs() < 0.02);
+        }
+    }
+
+    #[test]
+    fn test_sampled_keys_hit_partition() {
+        let mut hash_ring = HashRing::new(Config::default()).unwrap();
+        assert!(hash_ring.sample_keys_for_partition(271, 1).is_err());
+        let keys: Vec<Vec<u8>> = hash_ring.sample_keys_for_partition(42, 20).unwrap().collect();
+        assert_eq!(keys.len(), 20);
+        assert!(keys.iter().all(|key| hash_ring.get_partition(key) == 42));
+        assert_eq!(hash_ring.sample_keys_for_partition(42, 20).unwrap().collect::<Vec<_>>(), keys);
+
+        let (left, right) = hash_ring.split_partition(42).unwrap();
+        assert!(hash_ring.sample_keys_for_partition(42, 1).is_err());
+        for part_id in [left, right] {
+            assert!(hash_ring.sample_keys_for_partition(part_id, 5).unwrap().all(|key| hash_ring.get_partition(&key) == part_id));
+        }
+    }
+}
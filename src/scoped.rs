@@ -0,0 +1,186 @@
+//! Per-tenant views restricted to a dedicated pool of nodes.
+//!
+//! In a multi-tenant deployment some tenants get their own nodes. Rather than keeping
+//! one ring per tenant in sync with membership, a tenant is given an allow-list of node
+//! IDs and looks keys up through a `ScopedRing`: it walks the shared ring from the key's
+//! position and skips nodes outside the list. Nodes joining or leaving the ring are seen
+//! by every tenant, and keys only move within a tenant's pool.
+
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::hash::BuildHasher;
+use std::sync::Arc;
+
+use crate::{HashRing, Node, ReplicaSet};
+
+/// A view of a ring limited to the nodes allowed for one tenant.
+#[derive(Debug)]
+pub struct ScopedRing<'r, 'a, H, K> {
+    ring: &'r HashRing<'a, H, K>,
+    tenant: String,
+    allowed: HashSet<String>,
+}
+
+pub(crate) type TenantPools = HashMap<String, HashSet<String>>;
+
+impl<'a, H, K> HashRing<'a, H, K>
+where
+    H: BuildHasher,
+    K: BuildHasher,
+{
+    /// Sets the nodes a tenant's keys may be placed on, replacing any previous list.
+    ///
+    /// The list may name nodes that have not joined the ring yet.
+    ///
+    /// # Arguments
+    ///
+    /// * `tenant` - The ID of the tenant.
+    /// * `node_ids` - The IDs of the nodes dedicated to it.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use std::sync::Arc;
+    /// use hashring::{HashRing, Config, Node};
+    ///
+    /// #[derive(Debug)]
+    /// struct MyNode<'a> {
+    ///     name: &'a str,
+    /// }
+    ///
+    /// impl<'a> Node<'a> for MyNode<'a> {
+    ///     fn id(&self) -> &'a str {
+    ///         self.name
+    ///     }
+    /// }
+    ///
+    /// let mut hash_ring = HashRing::new(Config::default()).unwrap();
+    /// for name in ["shared1", "shared2", "acme1", "acme2"] {
+    ///     hash_ring.add_node(Arc::new(MyNode { name })).unwrap();
+    /// }
+    /// hash_ring.set_tenant_nodes("acme", &["acme1", "acme2"]).unwrap();
+    ///
+    /// let acme = hash_ring.scoped_ring("acme").unwrap();
+    /// let node = acme.get_key(b"some_key").unwrap();
+    /// assert!(node.id().starts_with("acme"));
+    /// ```
+    pub fn set_tenant_nodes(&mut self, tenant: &str, node_ids: &[&str]) -> Result<(), Box<dyn Error>> {
+        let mut tenants = self.tenants.write()?;
+        tenants.insert(tenant.to_string(), node_ids.iter().map(|id| id.to_string()).collect());
+        Ok(())
+    }
+
+    /// Removes a tenant's allow-list, returning `true` if it had one.
+    pub fn remove_tenant(&self, tenant: &str) -> Result<bool, Box<dyn Error>> {
+        let mut tenants = self.tenants.write()?;
+        Ok(tenants.remove(tenant).is_some())
+    }
+
+    /// Returns a view of the ring limited to a tenant's nodes, or `None` if the tenant
+    /// has no allow-list.
+    ///
+    /// The view reads membership from the ring at every lookup, but keeps the allow-list
+    /// it was created with.
+    pub fn scoped_ring(&self, tenant: &str) -> Option<ScopedRing<'_, 'a, H, K>> {
+        let allowed = self.tenants.read().ok()?.get(tenant)?.clone();
+        Some(ScopedRing {
+            ring: self,
+            tenant: tenant.to_string(),
+            allowed,
+        })
+    }
+}
+
+impl<'r, 'a, H, K> ScopedRing<'r, 'a, H, K>
+where
+    H: BuildHasher,
+    K: BuildHasher,
+{
+    /// Returns the ID of the tenant.
+    pub fn tenant(&self) -> &str {
+        &self.tenant
+    }
+
+    /// Returns the allowed nodes currently in the ring, sorted by ID.
+    pub fn nodes(&self) -> Vec<Arc<dyn Node<'a> + 'a>> {
+        let nodes = self.ring.nodes.wait_read();
+        let mut members: Vec<Arc<dyn Node<'a> + 'a>> = nodes.values().filter(|node| self.allowed.contains(node.id())).cloned().collect();
+        members.sort_by(|a, b| a.id().cmp(b.id()));
+        members
+    }
+
+    /// Retrieves the tenant's node responsible for the given key.
+    pub fn get_key(&self, key: &[u8]) -> Option<Arc<dyn Node<'a> + 'a>> {
+        self.get_preference_list_n(key, 1).pop()
+    }
+
+    /// Retrieves up to `replication_factor` distinct tenant nodes for the given key.
+    pub fn get_preference_list(&self, key: &[u8]) -> ReplicaSet<'a> {
+        self.get_preference_list_n(key, self.ring.config().replication_factor).into()
+    }
+
+    /// Retrieves up to `n` distinct tenant nodes for the given key, in ring order.
+    pub fn get_preference_list_n(&self, key: &[u8], n: usize) -> Vec<Arc<dyn Node<'a> + 'a>> {
+        let mut preference_list: Vec<Arc<dyn Node<'a> + 'a>> = Vec::new();
+        if n == 0 || self.allowed.is_empty() {
+            return preference_list;
+        }
+        let Ok(sorted_set) = self.ring.sorted_nodes_hash_set.read() else {
+            return preference_list;
+        };
+        let Ok(point) = self.ring.hash_key(key).and_then(|hashed_key| self.ring.route(&sorted_set, hashed_key)) else {
+            return preference_list;
+        };
+        let mut unique_nodes = HashSet::new();
+        for (_, node) in sorted_set.range(point..).chain(sorted_set.range(..point)) {
+            if self.allowed.contains(node.id()) && unique_nodes.insert(node.id()) {
+                preference_list.push(node.clone());
+                if preference_list.len() >= n {
+                    break;
+                }
+            }
+        }
+        preference_list
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::tests::test_nodes;
+    use crate::{Config, HashRing};
+
+    #[test]
+    fn test_scoped_ring_isolates_tenants() {
+        let mut hash_ring = HashRing::new(Config::default()).unwrap();
+        for node in test_nodes() {
+            hash_ring.add_node(node).unwrap();
+        }
+        assert!(hash_ring.scoped_ring("acme").is_none());
+        hash_ring.set_tenant_nodes("acme", &["node2", "node3"]).unwrap();
+        hash_ring.set_tenant_nodes("empty", &[]).unwrap();
+        assert!(hash_ring.scoped_ring("empty").unwrap().get_key(b"some_key").is_none());
+
+        let keys: Vec<String> = (0..300).map(|i| format!("key-{}", i)).collect();
+        let acme = hash_ring.scoped_ring("acme").unwrap();
+        assert_eq!(acme.tenant(), "acme");
+        assert_eq!(acme.nodes().iter().map(|node| node.id()).collect::<Vec<_>>(), vec!["node2", "node3"]);
+        let before: Vec<&str> = keys.iter().map(|key| acme.get_key(key.as_bytes()).unwrap().id()).collect();
+        assert!(before.iter().all(|id| *id != "node1"));
+        assert!(before.contains(&"node2") && before.contains(&"node3"));
+        assert_eq!(acme.get_preference_list_n(b"some_key", 3).len(), 2);
+        // Keys owned by an allowed node in the shared ring keep their owner.
+        for key in &keys {
+            let owner = hash_ring.get_key(key.as_bytes()).unwrap();
+            if owner.id() != "node1" {
+                assert_eq!(acme.get_key(key.as_bytes()).unwrap().id(), owner.id());
+            }
+        }
+
+        // Membership is shared: removing node3 leaves node2 with every key of the tenant.
+        hash_ring.remove_node("node3").unwrap();
+        let acme = hash_ring.scoped_ring("acme").unwrap();
+        assert!(keys.iter().all(|key| acme.get_key(key.as_bytes()).unwrap().id() == "node2"));
+        assert!(hash_ring.remove_tenant("acme").unwrap());
+        assert!(!hash_ring.remove_tenant("acme").unwrap());
+    }
+}
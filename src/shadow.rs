@@ -0,0 +1,176 @@
+//! Running two rings side by side during a migration.
+//!
+//! When a cluster changes its hashing parameters, writes go to the owners in both
+//! the current and the target ring, and reads fall back from the target to the
+//! current owner until the data has been repaired. `ShadowedRing` answers both
+//! lookups at once so callers don't have to keep two rings in sync by hand.
+
+use std::collections::HashSet;
+use std::hash::BuildHasher;
+use std::sync::Arc;
+
+use crate::{HashRing, Node, XxHash64Hasher};
+
+/// The owners of a key in the current and the target ring.
+#[derive(Debug, Clone)]
+pub struct ShadowLookup<'a> {
+    pub current: Option<Arc<dyn Node<'a> + 'a>>,
+    pub target: Option<Arc<dyn Node<'a> + 'a>>,
+}
+
+impl<'a> ShadowLookup<'a> {
+    /// Returns `true` if the key changes owner once the migration completes.
+    pub fn is_moving(&self) -> bool {
+        match (&self.current, &self.target) {
+            (Some(current), Some(target)) => current.id() != target.id(),
+            (None, None) => false,
+            _ => true,
+        }
+    }
+}
+
+/// A pair of rings used for double-writes and read repair while migrating between them.
+#[derive(Debug, Clone)]
+pub struct ShadowedRing<'a, H = XxHash64Hasher, K = H, TH = H, TK = K> {
+    current: HashRing<'a, H, K>,
+    target: HashRing<'a, TH, TK>,
+}
+
+impl<'a, H, K, TH, TK> ShadowedRing<'a, H, K, TH, TK>
+where
+    H: BuildHasher,
+    K: BuildHasher,
+    TH: BuildHasher,
+    TK: BuildHasher,
+{
+    /// Creates a shadowed ring migrating from `current` to `target`.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use std::sync::Arc;
+    /// use hashring::{HashRing, Config, Node, ShadowedRing};
+    ///
+    /// #[derive(Debug)]
+    /// struct MyNode<'a> {
+    ///     name: &'a str,
+    /// }
+    ///
+    /// impl<'a> Node<'a> for MyNode<'a> {
+    ///     fn id(&self) -> &'a str {
+    ///         self.name
+    ///     }
+    /// }
+    ///
+    /// let node = Arc::new(MyNode { name: "node1" });
+    /// let mut current = HashRing::new(Config::default()).unwrap();
+    /// current.add_node(node.clone()).unwrap();
+    /// let mut config = Config::default();
+    /// config.replication_factor = 40;
+    /// let mut target = HashRing::new(config).unwrap();
+    /// target.add_node(node).unwrap();
+    ///
+    /// let shadowed = ShadowedRing::new(current, target);
+    /// let lookup = shadowed.get_key(b"some_key");
+    /// assert!(!lookup.is_moving());
+    /// ```
+    pub fn new(current: HashRing<'a, H, K>, target: HashRing<'a, TH, TK>) -> ShadowedRing<'a, H, K, TH, TK> {
+        ShadowedRing { current, target }
+    }
+
+    /// Returns the ring data currently lives on.
+    pub fn current(&self) -> &HashRing<'a, H, K> {
+        &self.current
+    }
+
+    /// Returns the ring data is migrating to.
+    pub fn target(&self) -> &HashRing<'a, TH, TK> {
+        &self.target
+    }
+
+    /// Returns the current ring for membership changes.
+    pub fn current_mut(&mut self) -> &mut HashRing<'a, H, K> {
+        &mut self.current
+    }
+
+    /// Returns the target ring for membership changes.
+    pub fn target_mut(&mut self) -> &mut HashRing<'a, TH, TK> {
+        &mut self.target
+    }
+
+    /// Looks up the owner of a key in both rings.
+    pub fn get_key(&self, key: &[u8]) -> ShadowLookup<'a> {
+        ShadowLookup {
+            current: self.current.get_key(key),
+            target: self.target.get_key(key),
+        }
+    }
+
+    /// Returns the distinct nodes a write must reach during the migration: the target
+    /// preference list followed by any current owners not already in it.
+    pub fn write_set(&self, key: &[u8]) -> Vec<Arc<dyn Node<'a> + 'a>> {
+        let mut write_set = self.target.get_preference_list(key).nodes();
+        let mut seen: HashSet<&str> = write_set.iter().map(|node| node.id()).collect();
+        for node in self.current.get_preference_list(key).nodes() {
+            if seen.insert(node.id()) {
+                write_set.push(node);
+            }
+        }
+        write_set
+    }
+
+    /// Ends the migration, keeping the target ring.
+    pub fn complete(self) -> HashRing<'a, TH, TK> {
+        self.target
+    }
+
+    /// Abandons the migration, keeping the current ring.
+    pub fn abort(self) -> HashRing<'a, H, K> {
+        self.current
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::tests::test_nodes;
+    use crate::{Config, HashRing, ShadowedRing};
+
+    fn shadowed() -> ShadowedRing<'static> {
+        let mut current = HashRing::new(Config::default()).unwrap();
+        let mut target = HashRing::new(Config::default()).unwrap();
+        let nodes = test_nodes();
+        current.add_node(nodes[0].clone()).unwrap();
+        current.add_node(nodes[1].clone()).unwrap();
+        target.add_node(nodes[1].clone()).unwrap();
+        target.add_node(nodes[2].clone()).unwrap();
+        ShadowedRing::new(current, target)
+    }
+
+    #[test]
+    fn test_shadow_lookup() {
+        let shadowed = shadowed();
+        let mut moving = 0;
+        for i in 0..100 {
+            let key = format!("key-{}", i);
+            let lookup = shadowed.get_key(key.as_bytes());
+            assert_eq!(lookup.current.as_ref().unwrap().id(), shadowed.current().get_key(key.as_bytes()).unwrap().id());
+            assert_eq!(lookup.target.as_ref().unwrap().id(), shadowed.target().get_key(key.as_bytes()).unwrap().id());
+            if lookup.current.as_ref().unwrap().id() == "node1" {
+                assert!(lookup.is_moving());
+                moving += 1;
+            }
+        }
+        assert!(moving > 0);
+    }
+
+    #[test]
+    fn test_write_set_covers_both_rings() {
+        let shadowed = shadowed();
+        let ids: Vec<&str> = shadowed.write_set(b"some_key").iter().map(|n| n.id()).collect();
+        assert_eq!(ids.len(), 3);
+        assert_eq!(ids[..2], shadowed.target().get_preference_list(b"some_key").iter().map(|r| r.node.id()).collect::<Vec<_>>()[..]);
+
+        let target = shadowed.complete();
+        assert!(target.get_key(b"some_key").unwrap().id() != "node1");
+    }
+}
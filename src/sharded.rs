@@ -0,0 +1,179 @@
+//! Per-thread copies of a ring for thread-per-core proxies.
+//!
+//! Even uncontended, a read lock writes to the lock's cache line, so lookups on many
+//! cores bounce that line between them. A `ShardedReader` owns a private `FrozenRing`
+//! and only checks a shared epoch counter, which stays in every core's cache until the
+//! ring is republished. After `publish`, each reader copies the new ring on its next
+//! lookup and then goes back to reading its own copy.
+
+use std::hash::BuildHasher;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use crate::sync;
+use crate::{FrozenRing, HashRing, Node, XxHash64Hasher};
+
+#[derive(Debug)]
+struct Published<'a, K> {
+    epoch: AtomicU64,
+    ring: Mutex<Arc<FrozenRing<'a, K>>>,
+}
+
+/// A reader holding its own copy of a ring, refreshed when a new one is published.
+///
+/// Clone one reader per thread; clones share the publication slot but not the copy.
+#[derive(Debug)]
+pub struct ShardedReader<'a, K = XxHash64Hasher> {
+    shared: Arc<Published<'a, K>>,
+    local: FrozenRing<'a, K>,
+    epoch: u64,
+}
+
+impl<'a, K> ShardedReader<'a, K>
+where
+    K: BuildHasher + Clone,
+{
+    /// Creates a reader over the current state of the ring.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use std::sync::Arc;
+    /// use std::thread;
+    /// use hashring::{HashRing, Config, Node, ShardedReader};
+    ///
+    /// #[derive(Debug)]
+    /// struct MyNode<'a> {
+    ///     name: &'a str,
+    /// }
+    ///
+    /// impl<'a> Node<'a> for MyNode<'a> {
+    ///     fn id(&self) -> &'a str {
+    ///         self.name
+    ///     }
+    /// }
+    ///
+    /// let mut hash_ring = HashRing::new(Config::default()).unwrap();
+    /// hash_ring.add_node(Arc::new(MyNode { name: "node1" })).unwrap();
+    /// let reader = ShardedReader::new(&hash_ring);
+    ///
+    /// let workers: Vec<_> = (0..4)
+    ///     .map(|_| {
+    ///         let mut reader = reader.clone();
+    ///         thread::spawn(move || reader.get_key(b"some_key").map(|node| node.id()))
+    ///     })
+    ///     .collect();
+    /// for worker in workers {
+    ///     assert_eq!(worker.join().unwrap(), Some("node1"));
+    /// }
+    ///
+    /// // Membership changes reach the readers once published.
+    /// hash_ring.add_node(Arc::new(MyNode { name: "node2" })).unwrap();
+    /// reader.publish(&hash_ring);
+    /// ```
+    pub fn new<H: BuildHasher>(ring: &HashRing<'a, H, K>) -> ShardedReader<'a, K> {
+        let local = ring.freeze();
+        ShardedReader {
+            shared: Arc::new(Published {
+                epoch: AtomicU64::new(0),
+                ring: Mutex::new(Arc::new(local.clone())),
+            }),
+            local,
+            epoch: 0,
+        }
+    }
+
+    /// Publishes the current state of the ring to every reader sharing this one's slot.
+    ///
+    /// # Returns
+    ///
+    /// * `u64` - The new epoch.
+    pub fn publish<H: BuildHasher>(&self, ring: &HashRing<'a, H, K>) -> u64 {
+        let frozen = Arc::new(ring.freeze());
+        let mut published = sync::lock(&self.shared.ring);
+        *published = frozen;
+        self.shared.epoch.fetch_add(1, Ordering::Release) + 1
+    }
+
+    /// Replaces the local copy if a newer ring was published.
+    ///
+    /// # Returns
+    ///
+    /// * `bool` - `true` if the copy was replaced.
+    pub fn refresh(&mut self) -> bool {
+        if self.shared.epoch.load(Ordering::Acquire) == self.epoch {
+            return false;
+        }
+        let published = sync::lock(&self.shared.ring);
+        self.local = FrozenRing::clone(&published);
+        self.epoch = self.shared.epoch.load(Ordering::Acquire);
+        true
+    }
+
+    /// Returns the local copy, refreshed first.
+    pub fn ring(&mut self) -> &FrozenRing<'a, K> {
+        self.refresh();
+        &self.local
+    }
+
+    /// Returns the epoch of the local copy.
+    pub fn epoch(&self) -> u64 {
+        self.epoch
+    }
+
+    /// Retrieves the node responsible for the given key from the latest published ring.
+    pub fn get_key(&mut self, key: &[u8]) -> Option<Arc<dyn Node<'a> + 'a>> {
+        self.ring().get_key(key)
+    }
+
+    /// Returns the partition the given key falls into in the latest published ring.
+    pub fn get_partition(&mut self, key: &[u8]) -> usize {
+        self.ring().get_partition(key)
+    }
+}
+
+impl<'a, K> Clone for ShardedReader<'a, K>
+where
+    K: Clone,
+{
+    fn clone(&self) -> Self {
+        ShardedReader {
+            shared: self.shared.clone(),
+            local: self.local.clone(),
+            epoch: self.epoch,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ShardedReader;
+    use crate::tests::test_nodes;
+    use crate::{Config, HashRing};
+
+    #[test]
+    fn test_sharded_readers_follow_publication() {
+        let nodes = test_nodes();
+        let mut hash_ring = HashRing::new(Config::default()).unwrap();
+        hash_ring.add_node(nodes[0].clone()).unwrap();
+        let mut reader = ShardedReader::new(&hash_ring);
+        let mut other = reader.clone();
+        assert!(!reader.refresh());
+
+        // Changes are invisible until published.
+        hash_ring.remove_node("node1").unwrap();
+        hash_ring.add_node(nodes[1].clone()).unwrap();
+        assert_eq!(reader.get_key(b"some_key").unwrap().id(), "node1");
+
+        assert_eq!(reader.publish(&hash_ring), 1);
+        assert_eq!(other.epoch(), 0);
+        assert_eq!(other.get_key(b"some_key").unwrap().id(), "node2");
+        assert_eq!(other.epoch(), 1);
+        assert_eq!(reader.get_key(b"some_key").unwrap().id(), "node2");
+        assert_eq!(reader.get_partition(b"some_key"), hash_ring.get_partition(b"some_key"));
+        assert!(!other.refresh());
+
+        let handle = std::thread::spawn(move || other.get_key(b"some_key").map(|node| node.id()));
+        assert_eq!(handle.join().unwrap(), Some("node2"));
+    }
+}
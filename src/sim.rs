@@ -0,0 +1,321 @@
+//! Replaying churn scenarios for capacity planning.
+//!
+//! A `Simulation` applies a script of joins, graceful leaves and crashes to an empty ring
+//! and, after every event, routes a fixed sample of keys to record how many changed
+//! owner, how evenly they spread over the nodes, and how many had to be copied. Keys
+//! leaving a node gracefully are migrated from it; keys of a crashed node are lost from
+//! it and must be restored from replicas instead, so they are counted apart. The report
+//! is written as CSV, one row per event, to be plotted or compared across configurations.
+//!
+//! Scripts have one event per line, `join`, `leave` or `crash` followed by a node ID.
+//! Blank lines and lines starting with `#` are skipped.
+//!
+//! ```
+//! use hashring::sim::Simulation;
+//! use hashring::Config;
+//!
+//! let script = "
+//!     join node1
+//!     join node2
+//!     join node3
+//!     crash node2
+//!     join node4
+//!     leave node1
+//! ";
+//! let simulation = Simulation::from_script(Config::default(), 10_000, script).unwrap();
+//! let report = simulation.run().unwrap();
+//! assert_eq!(report.steps.len(), 6);
+//! assert_eq!(report.steps[3].migrated_keys, 0);
+//! print!("{}", report.to_csv());
+//! ```
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::io::Write;
+use std::sync::Arc;
+
+use crate::{Config, HashRing, Node};
+
+/// A membership change in a scenario.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum ChurnEvent {
+    /// The node joins the ring.
+    Join(String),
+    /// The node leaves the ring, handing its keys over first.
+    Leave(String),
+    /// The node fails, taking its copy of its keys with it.
+    Crash(String),
+}
+
+impl ChurnEvent {
+    /// Returns the ID of the node the event is about.
+    pub fn node_id(&self) -> &str {
+        match self {
+            ChurnEvent::Join(id) | ChurnEvent::Leave(id) | ChurnEvent::Crash(id) => id,
+        }
+    }
+
+    fn kind(&self) -> &'static str {
+        match self {
+            ChurnEvent::Join(_) => "join",
+            ChurnEvent::Leave(_) => "leave",
+            ChurnEvent::Crash(_) => "crash",
+        }
+    }
+}
+
+impl fmt::Display for ChurnEvent {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(fmt, "{} {}", self.kind(), self.node_id())
+    }
+}
+
+/// The measurements taken after one event.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SimStep {
+    /// The position of the event in the script, from `0`.
+    pub step: usize,
+    /// The event applied.
+    pub event: ChurnEvent,
+    /// The number of nodes after the event.
+    pub nodes: usize,
+    /// The number of sample keys that changed owner.
+    pub remapped_keys: usize,
+    /// The fraction of sample keys that changed owner.
+    pub remap_fraction: f64,
+    /// The ratio between the sample keys of the busiest node and the mean, `1.0` without nodes.
+    pub load_skew: f64,
+    /// The number of remapped keys copied from their previous owner.
+    pub migrated_keys: usize,
+    /// The number of remapped keys whose previous owner crashed.
+    pub lost_keys: usize,
+    /// The number of keys migrated since the start of the scenario.
+    pub total_migrated_keys: usize,
+}
+
+/// The measurements of a whole scenario.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SimReport {
+    /// The number of sample keys routed after every event.
+    pub key_count: usize,
+    /// One entry per event, in script order.
+    pub steps: Vec<SimStep>,
+}
+
+impl SimReport {
+    /// The header row of the CSV output.
+    pub const CSV_HEADER: &'static str =
+        "step,event,node,nodes,remapped_keys,remap_fraction,load_skew,migrated_keys,lost_keys,total_migrated_keys";
+
+    /// Writes the report as CSV, with a header row.
+    pub fn write_csv<W: Write>(&self, mut writer: W) -> Result<(), Box<dyn Error>> {
+        writeln!(writer, "{}", SimReport::CSV_HEADER)?;
+        for step in &self.steps {
+            writeln!(
+                writer,
+                "{},{},{},{},{},{:.6},{:.6},{},{},{}",
+                step.step,
+                step.event.kind(),
+                step.event.node_id(),
+                step.nodes,
+                step.remapped_keys,
+                step.remap_fraction,
+                step.load_skew,
+                step.migrated_keys,
+                step.lost_keys,
+                step.total_migrated_keys,
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Returns the report as CSV, with a header row.
+    pub fn to_csv(&self) -> String {
+        let mut buf = Vec::new();
+        // Writing to a `Vec` can't fail.
+        let _ = self.write_csv(&mut buf);
+        String::from_utf8_lossy(&buf).into_owned()
+    }
+}
+
+#[derive(Debug)]
+struct SimNode<'a> {
+    id: &'a str,
+}
+
+impl<'a> Node<'a> for SimNode<'a> {
+    fn id(&self) -> &'a str {
+        self.id
+    }
+}
+
+/// A scripted churn scenario over a sample of keys.
+#[derive(Debug, Clone)]
+pub struct Simulation {
+    config: Config,
+    keys: Vec<Vec<u8>>,
+    events: Vec<ChurnEvent>,
+}
+
+impl Simulation {
+    /// Creates an empty scenario routing `key_count` sample keys.
+    ///
+    /// # Arguments
+    ///
+    /// * `config` - The configuration of the simulated ring.
+    /// * `key_count` - The number of keys, named `key-0` to `key-{key_count - 1}`, routed after every event.
+    pub fn new(config: Config, key_count: usize) -> Result<Simulation, Box<dyn Error>> {
+        config.validate()?;
+        if key_count == 0 {
+            return Err("key count must be greater than 0".into());
+        }
+        Ok(Simulation {
+            config,
+            keys: (0..key_count).map(|i| format!("key-{}", i).into_bytes()).collect(),
+            events: Vec::new(),
+        })
+    }
+
+    /// Creates a scenario from a script of `join`, `leave` and `crash` lines.
+    pub fn from_script(config: Config, key_count: usize, script: &str) -> Result<Simulation, Box<dyn Error>> {
+        let mut simulation = Simulation::new(config, key_count)?;
+        for (line_no, line) in script.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let mut words = line.split_whitespace();
+            let (kind, id) = match (words.next(), words.next(), words.next()) {
+                (Some(kind), Some(id), None) => (kind, id.to_string()),
+                _ => return Err(format!("line {}: expected an event and a node ID", line_no + 1).into()),
+            };
+            let event = match kind {
+                "join" => ChurnEvent::Join(id),
+                "leave" => ChurnEvent::Leave(id),
+                "crash" => ChurnEvent::Crash(id),
+                _ => return Err(format!("line {}: unknown event {}", line_no + 1, kind).into()),
+            };
+            simulation.events.push(event);
+        }
+        Ok(simulation)
+    }
+
+    /// Appends an event to the scenario.
+    pub fn push(&mut self, event: ChurnEvent) -> &mut Simulation {
+        self.events.push(event);
+        self
+    }
+
+    /// Returns the events of the scenario, in order.
+    pub fn events(&self) -> &[ChurnEvent] {
+        &self.events
+    }
+
+    /// Replays the scenario on an empty ring.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<SimReport, Box<dyn Error>>` - The measurements after every event, or an error
+    ///   naming the first event the ring rejected, such as a leave of an unknown node.
+    pub fn run(&self) -> Result<SimReport, Box<dyn Error>> {
+        let mut hash_ring = HashRing::new(self.config.clone())?;
+        let mut owners: Vec<Option<&str>> = vec![None; self.keys.len()];
+        let mut total_migrated_keys = 0;
+        let mut steps = Vec::with_capacity(self.events.len());
+
+        for (step, event) in self.events.iter().enumerate() {
+            let applied = match event {
+                ChurnEvent::Join(id) => hash_ring.add_node(Arc::new(SimNode { id })).map(|_| ()),
+                ChurnEvent::Leave(id) | ChurnEvent::Crash(id) => hash_ring.remove_node(id),
+            };
+            applied.map_err(|err| format!("step {} ({}): {}", step, event, err))?;
+
+            let mut counts: HashMap<&str, usize> = hash_ring.nodes.wait_read().values().map(|node| (node.id(), 0)).collect();
+            let (mut remapped_keys, mut lost_keys) = (0, 0);
+            for (key, owner) in self.keys.iter().zip(owners.iter_mut()) {
+                let current = hash_ring.get_key(key).map(|node| node.id());
+                if let Some(id) = current {
+                    *counts.entry(id).or_default() += 1;
+                }
+                if *owner != current {
+                    // Keys routed nowhere before the first join have nothing to copy.
+                    if owner.is_some() {
+                        remapped_keys += 1;
+                        if matches!(event, ChurnEvent::Crash(id) if Some(id.as_str()) == *owner) {
+                            lost_keys += 1;
+                        }
+                    }
+                    *owner = current;
+                }
+            }
+
+            let migrated_keys = remapped_keys - lost_keys;
+            total_migrated_keys += migrated_keys;
+            let load_skew = match counts.values().copied().max() {
+                Some(max) if max > 0 => max as f64 * counts.len() as f64 / self.keys.len() as f64,
+                _ => 1.0,
+            };
+            steps.push(SimStep {
+                step,
+                event: event.clone(),
+                nodes: counts.len(),
+                remapped_keys,
+                remap_fraction: remapped_keys as f64 / self.keys.len() as f64,
+                load_skew,
+                migrated_keys,
+                lost_keys,
+                total_migrated_keys,
+            });
+        }
+
+        Ok(SimReport {
+            key_count: self.keys.len(),
+            steps,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ChurnEvent, SimReport, Simulation};
+    use crate::Config;
+
+    #[test]
+    fn test_simulation_records_churn() {
+        assert!(Simulation::from_script(Config::default(), 100, "join").is_err());
+        assert!(Simulation::from_script(Config::default(), 100, "restart node1").is_err());
+        assert!(Simulation::new(Config::default(), 0).is_err());
+
+        let script = "join node1\njoin node2\n\n# comment\njoin node3\ncrash node2\nleave node3\n";
+        let mut simulation = Simulation::from_script(Config::default(), 1000, script).unwrap();
+        let report = simulation.run().unwrap();
+        let steps = &report.steps;
+        assert_eq!(steps.len(), 5);
+        assert_eq!(steps.iter().map(|step| step.nodes).collect::<Vec<_>>(), [1, 2, 3, 2, 1]);
+
+        // The first join has nothing to move, and every key then sits on node1.
+        assert_eq!(steps[0].remapped_keys, 0);
+        assert_eq!(steps[0].load_skew, 1.0);
+        assert!(steps[1].remapped_keys > 0);
+        assert_eq!(steps[1].migrated_keys, steps[1].remapped_keys);
+        assert!(steps[2].load_skew >= 1.0);
+
+        // Keys of the crashed node are lost rather than migrated.
+        assert!(steps[3].lost_keys > 0);
+        assert_eq!(steps[3].migrated_keys, 0);
+        assert_eq!(steps[4].lost_keys, 0);
+        assert_eq!(steps[4].migrated_keys, steps[4].remapped_keys);
+        assert_eq!(steps[4].total_migrated_keys, steps.iter().map(|step| step.migrated_keys).sum::<usize>());
+
+        let csv = report.to_csv();
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(lines.len(), 6);
+        assert_eq!(lines[0], SimReport::CSV_HEADER);
+        assert!(lines[4].starts_with("3,crash,node2,2,"));
+
+        simulation.push(ChurnEvent::Leave("node9".to_string()));
+        let err = simulation.run().unwrap_err();
+        assert!(err.to_string().starts_with("step 5 (leave node9)"), "{}", err);
+    }
+}
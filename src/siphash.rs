@@ -0,0 +1,175 @@
+//! Keyed SipHash-2-4 for rings whose keys are chosen by untrusted clients.
+//!
+//! With an unkeyed hash anyone can compute where a key lands and craft many keys owned
+//! by the same node. SipHash-2-4 is a keyed pseudorandom function: without the 128-bit
+//! key, the ring position of a key cannot be predicted. Use the same key on every
+//! instance that has to agree on placement, and a random one otherwise.
+
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+
+/// A `Hasher` computing SipHash-2-4.
+#[derive(Debug, Clone)]
+pub struct SipHasher24 {
+    v0: u64,
+    v1: u64,
+    v2: u64,
+    v3: u64,
+    /// Bytes not yet compressed, in the low bytes of `tail`.
+    tail: u64,
+    tail_len: usize,
+    length: usize,
+}
+
+impl SipHasher24 {
+    /// Creates a hasher keyed with the two little-endian halves of a 128-bit key.
+    pub fn new_with_keys(k0: u64, k1: u64) -> SipHasher24 {
+        SipHasher24 {
+            v0: k0 ^ 0x736f_6d65_7073_6575,
+            v1: k1 ^ 0x646f_7261_6e64_6f6d,
+            v2: k0 ^ 0x6c79_6765_6e65_7261,
+            v3: k1 ^ 0x7465_6462_7974_6573,
+            tail: 0,
+            tail_len: 0,
+            length: 0,
+        }
+    }
+
+    fn round(&mut self) {
+        self.v0 = self.v0.wrapping_add(self.v1);
+        self.v1 = self.v1.rotate_left(13) ^ self.v0;
+        self.v0 = self.v0.rotate_left(32);
+        self.v2 = self.v2.wrapping_add(self.v3);
+        self.v3 = self.v3.rotate_left(16) ^ self.v2;
+        self.v0 = self.v0.wrapping_add(self.v3);
+        self.v3 = self.v3.rotate_left(21) ^ self.v0;
+        self.v2 = self.v2.wrapping_add(self.v1);
+        self.v1 = self.v1.rotate_left(17) ^ self.v2;
+        self.v2 = self.v2.rotate_left(32);
+    }
+
+    fn compress(&mut self, m: u64) {
+        self.v3 ^= m;
+        self.round();
+        self.round();
+        self.v0 ^= m;
+    }
+}
+
+impl Hasher for SipHasher24 {
+    fn write(&mut self, bytes: &[u8]) {
+        self.length += bytes.len();
+        let mut bytes = bytes;
+        while !bytes.is_empty() && (self.tail_len > 0 || bytes.len() < 8) {
+            self.tail |= (bytes[0] as u64) << (8 * self.tail_len);
+            self.tail_len += 1;
+            bytes = &bytes[1..];
+            if self.tail_len == 8 {
+                let m = self.tail;
+                self.compress(m);
+                self.tail = 0;
+                self.tail_len = 0;
+            }
+        }
+        let mut chunks = bytes.chunks_exact(8);
+        for chunk in &mut chunks {
+            self.compress(u64::from_le_bytes(chunk.try_into().unwrap()));
+        }
+        for (i, byte) in chunks.remainder().iter().enumerate() {
+            self.tail |= (*byte as u64) << (8 * i);
+        }
+        self.tail_len += chunks.remainder().len();
+    }
+
+    fn finish(&self) -> u64 {
+        let mut state = self.clone();
+        let m = ((self.length as u64 & 0xff) << 56) | self.tail;
+        state.compress(m);
+        state.v2 ^= 0xff;
+        for _ in 0..4 {
+            state.round();
+        }
+        state.v0 ^ state.v1 ^ state.v2 ^ state.v3
+    }
+}
+
+/// Builds SipHash-2-4 hashers sharing one 128-bit key.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SipBuildHasher {
+    k0: u64,
+    k1: u64,
+}
+
+impl SipBuildHasher {
+    /// Uses the given 128-bit key.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use hashring::{HashRing, Config, SipBuildHasher};
+    ///
+    /// let key = *b"0123456789abcdef";
+    /// let hash_ring: HashRing<SipBuildHasher> = HashRing::with_hasher(Config::default(), SipBuildHasher::new(key)).unwrap();
+    /// ```
+    pub fn new(key: [u8; 16]) -> SipBuildHasher {
+        SipBuildHasher {
+            k0: u64::from_le_bytes(key[..8].try_into().unwrap()),
+            k1: u64::from_le_bytes(key[8..].try_into().unwrap()),
+        }
+    }
+
+    /// Uses a random key, drawn from the same source as the standard library's `HashMap`.
+    pub fn random() -> SipBuildHasher {
+        let random = RandomState::new();
+        SipBuildHasher {
+            k0: random.hash_one(0u8),
+            k1: random.hash_one(1u8),
+        }
+    }
+
+    /// Returns the key, to share it with the other instances of the ring.
+    pub fn key(&self) -> [u8; 16] {
+        let mut key = [0; 16];
+        key[..8].copy_from_slice(&self.k0.to_le_bytes());
+        key[8..].copy_from_slice(&self.k1.to_le_bytes());
+        key
+    }
+}
+
+impl BuildHasher for SipBuildHasher {
+    type Hasher = SipHasher24;
+
+    fn build_hasher(&self) -> SipHasher24 {
+        SipHasher24::new_with_keys(self.k0, self.k1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::hash::{BuildHasher, Hasher};
+
+    use super::*;
+
+    #[test]
+    fn test_siphash_reference_vectors() {
+        // From the SipHash paper: key 00..0f and messages 00, 00 01, ..., 00..n-1.
+        let key: [u8; 16] = std::array::from_fn(|i| i as u8);
+        let message: Vec<u8> = (0..64).collect();
+        let build_hasher = SipBuildHasher::new(key);
+        let expected = [(0, 0x726f_db47_dd0e_0e31), (8, 0x93f5_f579_9a93_2462), (15, 0xa129_ca61_49be_45e5), (63, 0x958a_324c_eb06_4572)];
+        for (len, hash) in expected {
+            let mut hasher = build_hasher.build_hasher();
+            hasher.write(&message[..len]);
+            assert_eq!(hasher.finish(), hash, "length {}", len);
+
+            // Split writes hash like a single one.
+            let mut hasher = build_hasher.build_hasher();
+            for chunk in message[..len].chunks(3) {
+                hasher.write(chunk);
+            }
+            assert_eq!(hasher.finish(), hash, "length {} in chunks", len);
+        }
+        assert_eq!(SipBuildHasher::new(build_hasher.key()), build_hasher);
+        assert_ne!(SipBuildHasher::random(), SipBuildHasher::random());
+    }
+}
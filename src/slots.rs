@@ -0,0 +1,280 @@
+//! Redis-Cluster-style fixed slot routing.
+//!
+//! The keyspace is split into `SLOT_COUNT` slots using CRC16, and every slot is
+//! assigned to a node. Unlike ring lookups, slot assignments are explicit, so a
+//! proxy can answer with `MOVED` redirects when a key belongs to another node.
+
+use core::fmt;
+use std::error::Error;
+use std::hash::BuildHasher;
+use std::ops::RangeInclusive;
+use std::sync::Arc;
+
+use crate::crc::crc16;
+use crate::{HashRing, Node};
+
+/// The number of slots in the keyspace, matching Redis Cluster.
+pub const SLOT_COUNT: u16 = 16384;
+
+/// Returns the slot the given key belongs to.
+///
+/// Hash tags are respected: when the key contains a non-empty `{...}` section,
+/// only that section is hashed, so related keys can be forced into the same slot.
+///
+/// # Example
+///
+/// ```
+/// use hashring::key_slot;
+///
+/// assert_eq!(key_slot(b"{user1000}.following"), key_slot(b"{user1000}.followers"));
+/// ```
+pub fn key_slot(key: &[u8]) -> u16 {
+    crc16(hash_tag(key)) % SLOT_COUNT
+}
+
+/// Returns the part of the key that is hashed, following the Redis hash tag rules.
+pub(crate) fn hash_tag(key: &[u8]) -> &[u8] {
+    if let Some(start) = key.iter().position(|b| *b == b'{') {
+        if let Some(len) = key[start + 1..].iter().position(|b| *b == b'}') {
+            if len > 0 {
+                return &key[start + 1..start + 1 + len];
+            }
+        }
+    }
+    key
+}
+
+/// The outcome of routing a key through a `SlotMap`.
+#[derive(Debug, Clone)]
+pub enum SlotRoute<'a> {
+    /// The slot is served by the local node.
+    Local { slot: u16 },
+    /// The slot is served by another node, which the client should be redirected to.
+    Moved { slot: u16, node: Arc<dyn Node<'a> + 'a> },
+    /// No node is assigned to the slot.
+    Unassigned { slot: u16 },
+}
+
+impl<'a> fmt::Display for SlotRoute<'a> {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SlotRoute::Local { slot } => write!(fmt, "LOCAL {}", slot),
+            SlotRoute::Moved { slot, node } => write!(fmt, "MOVED {} {}", slot, node.id()),
+            SlotRoute::Unassigned { slot } => write!(fmt, "CLUSTERDOWN Hash slot {} not served", slot),
+        }
+    }
+}
+
+/// A fixed assignment of slots to nodes.
+#[derive(Debug, Clone)]
+pub struct SlotMap<'a> {
+    slots: Vec<Option<Arc<dyn Node<'a> + 'a>>>,
+}
+
+impl<'a> Default for SlotMap<'a> {
+    fn default() -> Self {
+        SlotMap::new()
+    }
+}
+
+impl<'a> SlotMap<'a> {
+    /// Creates a `SlotMap` with every slot unassigned.
+    pub fn new() -> SlotMap<'a> {
+        SlotMap {
+            slots: vec![None; SLOT_COUNT as usize],
+        }
+    }
+
+    /// Assigns a single slot to a node.
+    ///
+    /// # Arguments
+    ///
+    /// * `slot` - The slot to assign.
+    /// * `node` - The node that will serve the slot.
+    pub fn assign(&mut self, slot: u16, node: Arc<dyn Node<'a> + 'a>) -> Result<(), Box<dyn Error>> {
+        self.assign_range(slot..=slot, node)
+    }
+
+    /// Assigns an inclusive range of slots to a node.
+    ///
+    /// # Arguments
+    ///
+    /// * `range` - The slots to assign.
+    /// * `node` - The node that will serve the slots.
+    pub fn assign_range(&mut self, range: RangeInclusive<u16>, node: Arc<dyn Node<'a> + 'a>) -> Result<(), Box<dyn Error>> {
+        if *range.end() >= SLOT_COUNT {
+            return Err("slot out of range".into());
+        }
+        for slot in range {
+            self.slots[slot as usize] = Some(node.clone());
+        }
+        Ok(())
+    }
+
+    /// Removes the assignment of a slot, returning its previous owner.
+    pub fn unassign(&mut self, slot: u16) -> Option<Arc<dyn Node<'a> + 'a>> {
+        self.slots.get_mut(slot as usize)?.take()
+    }
+
+    /// Retrieves the node serving the given slot.
+    pub fn owner(&self, slot: u16) -> Option<Arc<dyn Node<'a> + 'a>> {
+        self.slots.get(slot as usize)?.clone()
+    }
+
+    /// Retrieves the node serving the given key.
+    pub fn get_key(&self, key: &[u8]) -> Option<Arc<dyn Node<'a> + 'a>> {
+        self.owner(key_slot(key))
+    }
+
+    /// Returns the contiguous slot ranges served by a node.
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - The ID of the node.
+    ///
+    /// # Returns
+    ///
+    /// * `Vec<RangeInclusive<u16>>` - The ranges in ascending order, as reported by `CLUSTER SLOTS`.
+    pub fn slot_ranges(&self, id: &str) -> Vec<RangeInclusive<u16>> {
+        let mut ranges: Vec<RangeInclusive<u16>> = Vec::new();
+        for (slot, owner) in self.slots.iter().enumerate() {
+            let slot = slot as u16;
+            match owner {
+                Some(node) if node.id() == id => {}
+                _ => continue,
+            }
+            match ranges.last_mut() {
+                Some(last) if *last.end() + 1 == slot => *last = *last.start()..=slot,
+                _ => ranges.push(slot..=slot),
+            }
+        }
+        ranges
+    }
+
+    /// Routes a key on behalf of the node identified by `local_id`.
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - The key being accessed.
+    /// * `local_id` - The ID of the node handling the request.
+    ///
+    /// # Returns
+    ///
+    /// * `SlotRoute<'a>` - Whether the key is served locally, by another node, or not at all.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use std::sync::Arc;
+    /// use hashring::{Node, SlotMap, SlotRoute, SLOT_COUNT};
+    ///
+    /// #[derive(Debug)]
+    /// struct MyNode<'a> {
+    ///     name: &'a str,
+    /// }
+    ///
+    /// impl<'a> Node<'a> for MyNode<'a> {
+    ///     fn id(&self) -> &'a str {
+    ///         self.name
+    ///     }
+    /// }
+    ///
+    /// let mut slot_map = SlotMap::new();
+    /// slot_map.assign_range(0..=SLOT_COUNT - 1, Arc::new(MyNode { name: "127.0.0.1:7001" })).unwrap();
+    ///
+    /// if let SlotRoute::Moved { .. } = slot_map.route(b"some_key", "127.0.0.1:7000") {
+    ///     println!("{}", slot_map.route(b"some_key", "127.0.0.1:7000"));
+    /// }
+    /// ```
+    pub fn route(&self, key: &[u8], local_id: &str) -> SlotRoute<'a> {
+        let slot = key_slot(key);
+        match self.owner(slot) {
+            Some(node) if node.id() == local_id => SlotRoute::Local { slot },
+            Some(node) => SlotRoute::Moved { slot, node },
+            None => SlotRoute::Unassigned { slot },
+        }
+    }
+}
+
+impl<'a, H, K> HashRing<'a, H, K>
+where
+    H: BuildHasher,
+    K: BuildHasher,
+{
+    /// Builds a `SlotMap` by placing every slot on the ring as a virtual bucket.
+    ///
+    /// Each slot is assigned to the node owning the slot's hash on the ring, so adding
+    /// or removing a node only moves the slots adjacent to its virtual nodes.
+    pub fn slot_map(&self) -> SlotMap<'a> {
+        let mut slot_map = SlotMap::new();
+        for slot in 0..SLOT_COUNT {
+            let hash = self.hash_partition_id(slot as usize);
+            slot_map.slots[slot as usize] = self.get_node_for_hash(hash);
+        }
+        slot_map
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::tests::TestNode;
+    use crate::Config;
+
+    fn node(name: &'static str) -> Arc<TestNode<'static>> {
+        Arc::new(TestNode {
+            ip_addr: "127.0.0.1".to_string(),
+            name,
+        })
+    }
+
+    #[test]
+    fn test_key_slot() {
+        assert_eq!(key_slot(b"123456789"), 0x31C3);
+        assert_eq!(key_slot(b"{user1000}.following"), key_slot(b"user1000"));
+        assert_eq!(key_slot(b"foo{}{bar}"), key_slot(b"foo{}{bar}"));
+        assert_ne!(key_slot(b"foo{}{bar}"), key_slot(b"bar"));
+        assert_eq!(key_slot(b"foo{{bar}}zap"), key_slot(b"{bar"));
+    }
+
+    #[test]
+    fn test_route_and_ranges() {
+        let mut slot_map = SlotMap::new();
+        slot_map.assign_range(0..=8191, node("node1")).unwrap();
+        slot_map.assign_range(8192..=16383, node("node2")).unwrap();
+        assert!(slot_map.assign(SLOT_COUNT, node("node3")).is_err());
+
+        assert_eq!(slot_map.slot_ranges("node1"), vec![0..=8191]);
+        assert_eq!(slot_map.slot_ranges("node2"), vec![8192..=16383]);
+
+        // "123456789" hashes to slot 12739.
+        match slot_map.route(b"123456789", "node1") {
+            SlotRoute::Moved { slot, node } => {
+                assert_eq!(slot, 12739);
+                assert_eq!(node.id(), "node2");
+            }
+            other => panic!("unexpected route {}", other),
+        }
+        assert!(matches!(slot_map.route(b"123456789", "node2"), SlotRoute::Local { .. }));
+
+        slot_map.unassign(12739);
+        assert!(matches!(slot_map.route(b"123456789", "node2"), SlotRoute::Unassigned { .. }));
+    }
+
+    #[test]
+    fn test_slot_map_from_ring() {
+        let mut hash_ring = HashRing::new(Config::default()).unwrap();
+        hash_ring.add_node(node("node1")).unwrap();
+        hash_ring.add_node(node("node2")).unwrap();
+
+        let slot_map = hash_ring.slot_map();
+        let assigned: usize = ["node1", "node2"]
+            .iter()
+            .flat_map(|id| slot_map.slot_ranges(id))
+            .map(|r| r.len())
+            .sum();
+        assert_eq!(assigned, SLOT_COUNT as usize);
+    }
+}
@@ -0,0 +1,308 @@
+//! Point-in-time copies of the ring topology.
+//!
+//! A `RingSnapshot` captures the configuration, the members and the partition
+//! assignment by node ID. Nodes are resolved back into `Node` values when the
+//! snapshot is restored, since the ring doesn't know how to construct them.
+//!
+//! Weight ramps, traffic splits and drains aren't part of snapshots, since they are
+//! driven by the ring that started them.
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::error::Error;
+use std::hash::BuildHasher;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+
+use crate::load;
+use crate::split;
+use crate::trace::OpTimer;
+use crate::{weight, Config, HashRing, Node};
+
+/// The topology of a ring, identified by node IDs.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct RingSnapshot {
+    pub nodes: Vec<String>,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub config: Config,
+    #[cfg_attr(feature = "serde", serde(default, with = "crate::export::partition_map"))]
+    pub partitions: BTreeMap<usize, String>,
+    #[cfg_attr(feature = "serde", serde(default, skip_serializing_if = "BTreeSet::is_empty"))]
+    pub splits: BTreeSet<usize>,
+    /// Affinity groups, with the node each one is pinned to.
+    #[cfg_attr(feature = "serde", serde(default, skip_serializing_if = "BTreeMap::is_empty"))]
+    pub groups: BTreeMap<String, Option<String>>,
+    /// Partitions moved by a rebalance, whose keys are all routed to their assigned node.
+    #[cfg_attr(feature = "serde", serde(default, skip_serializing_if = "BTreeSet::is_empty"))]
+    pub moved: BTreeSet<usize>,
+    /// The weights of the members below full weight.
+    #[cfg_attr(feature = "serde", serde(default, skip_serializing_if = "BTreeMap::is_empty"))]
+    pub weights: BTreeMap<String, f64>,
+}
+
+impl<'a, H, K> HashRing<'a, H, K>
+where
+    H: BuildHasher,
+    K: BuildHasher,
+{
+    /// Captures the current configuration, membership, partition assignment, split partitions, affinity groups,
+    /// rebalanced partitions and node weights.
+    ///
+    /// # Returns
+    ///
+    /// * `RingSnapshot` - The snapshot, with nodes sorted by ID.
+    pub fn snapshot(&self) -> RingSnapshot {
+        let nodes = self.nodes.wait_read();
+        let sorted_set = self.sorted_nodes_hash_set.wait_read();
+        let partitions = self.partitions.wait_read();
+        let routes = self.routes.wait_read();
+        let groups = self.groups.wait_read();
+        let weights = self.weights.wait_read();
+
+        let mut node_ids: Vec<String> = nodes.keys().cloned().collect();
+        node_ids.sort();
+
+        RingSnapshot {
+            config: self.config().as_ref().clone(),
+            nodes: node_ids,
+            partitions: partitions
+                .iter()
+                .map(|(part_id, node)| (*part_id, node.id().to_string()))
+                .collect(),
+            splits: routes.splits.clone(),
+            groups: groups.clone(),
+            moved: routes
+                .moved
+                .keys()
+                .filter(|part_id| load::moved_point(&routes.moved, &sorted_set, **part_id).is_some())
+                .copied()
+                .collect(),
+            weights: weights.iter().map(|(id, weight)| (id.clone(), *weight)).collect(),
+        }
+    }
+
+    /// Replaces the configuration, membership, partition assignment, split partitions, affinity groups,
+    /// rebalanced partitions and node weights with the snapshot's.
+    ///
+    /// The node ID policy and capacity headroom aren't part of snapshots, so the ring keeps its own.
+    /// Neither are weight ramps, traffic splits and drains: a node being ramped in is restored at
+    /// its full weight, and the ring keeps the traffic splits and drains it had.
+    /// Partitions missing from the snapshot are assigned from the ring as usual, and the
+    /// keys of partitions the snapshot moved or assigns elsewhere are routed like after a rebalance.
+    /// Every node is resolved before the ring is touched, so on error the ring is left unchanged.
+    ///
+    /// # Arguments
+    ///
+    /// * `snapshot` - The snapshot to restore.
+    /// * `resolver` - Returns the node for a given ID, or `None` if it is unknown.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<(), Box<dyn Error>>` - On success, returns `Ok(())`.
+    pub fn restore<F>(&mut self, snapshot: &RingSnapshot, resolver: F) -> Result<(), Box<dyn Error>>
+    where
+        F: FnMut(&str) -> Option<Arc<dyn Node<'a> + 'a>>,
+    {
+        self.restore_at(snapshot, resolver, None)
+    }
+
+    /// Restores a snapshot like `restore`, leaving the ring at `version` if given: rings
+    /// following another through its deltas take the versions of the ring they follow.
+    pub(crate) fn restore_at<F>(&mut self, snapshot: &RingSnapshot, mut resolver: F, version: Option<u64>) -> Result<(), Box<dyn Error>>
+    where
+        F: FnMut(&str) -> Option<Arc<dyn Node<'a> + 'a>>,
+    {
+        let _events = self.dispatch_events();
+        let timer = OpTimer::start();
+        snapshot.config.validate()?;
+        split::validate_splits(snapshot.config.partition_count, &snapshot.splits)?;
+
+        let mut resolved: HashMap<String, Arc<dyn Node<'a> + 'a>> = HashMap::new();
+        for id in &snapshot.nodes {
+            let node = resolver(id).ok_or_else(|| format!("unable to resolve node {}", id))?;
+            if node.id() != id {
+                return Err(format!("resolved node {} has a different id {}", id, node.id()).into());
+            }
+            resolved.insert(id.clone(), node);
+        }
+        for (id, weight) in &snapshot.weights {
+            if !resolved.contains_key(id) {
+                return Err(format!("weight given for {}, which is not a member", id).into());
+            }
+            if !(0.0..=1.0).contains(weight) {
+                return Err(format!("weight of {} must be between 0 and 1", id).into());
+            }
+        }
+
+        let mut assignments = HashMap::new();
+        for (part_id, id) in &snapshot.partitions {
+            if !split::is_leaf(snapshot.config.partition_count, &snapshot.splits, *part_id) {
+                return Err(format!("partition {} does not exist", part_id).into());
+            }
+            let node = resolved
+                .get(id)
+                .ok_or_else(|| format!("partition {} is assigned to unknown node {}", part_id, id))?;
+            assignments.insert(*part_id, node.clone());
+        }
+
+        let mut nodes = self.nodes.write()?;
+        let mut sorted_set = self.sorted_nodes_hash_set.write()?;
+        let mut current_partitions = self.partitions.write()?;
+        let mut weights = self.weights.write()?;
+
+        // Virtual nodes are placed by the rules of the snapshot's format version. The rules
+        // for adding nodes aren't persisted, so the ring keeps its own.
+        let previous_config = self.config().as_ref().clone();
+        self.set_config(Config {
+            node_id_policy: previous_config.node_id_policy.clone(),
+            capacity_headroom: previous_config.capacity_headroom,
+            ..snapshot.config.clone()
+        });
+        let mut rebuilt = BTreeMap::new();
+        for id in &snapshot.nodes {
+            let weight = snapshot.weights.get(id).copied().unwrap_or(1.0);
+            let replicas = 0..weight::vnode_count(weight, snapshot.config.replication_factor);
+            if let Err(err) = self.place_vnodes(&mut rebuilt, &resolved[id], replicas, snapshot.config.collision_policy) {
+                self.set_config(previous_config);
+                return Err(err);
+            }
+        }
+
+        self.routes.write()?.splits = snapshot.splits.clone();
+        self.record_history(std::mem::replace(&mut *sorted_set, rebuilt));
+        let mut partitions = self.compute_partitions(&sorted_set, &current_partitions);
+        for (part_id, node) in assignments {
+            if snapshot.moved.contains(&part_id) || partitions.get(&part_id).map(|owner| owner.id()) != Some(node.id()) {
+                self.move_partition(&sorted_set, &mut partitions, part_id, node);
+            }
+        }
+
+        *nodes = resolved;
+        *current_partitions = partitions;
+        *weights = snapshot.weights.iter().filter(|(_, weight)| **weight < 1.0).map(|(id, weight)| (id.clone(), *weight)).collect();
+        *self.groups.write()? = snapshot.groups.clone();
+        match version {
+            Some(version) => self.version.store(version, Ordering::Release),
+            None => self.bump_version(),
+        }
+        self.log_changes("restore", &timer, Some(&nodes), &current_partitions);
+
+        Ok(())
+    }
+
+    /// Creates a new `HashRing` from a snapshot, using the given hashers.
+    ///
+    /// # Arguments
+    ///
+    /// * `snapshot` - The snapshot to restore.
+    /// * `node_hasher` - The hasher used to place virtual nodes and partitions on the ring.
+    /// * `key_hasher` - The hasher used to hash keys.
+    /// * `resolver` - Returns the node for a given ID, or `None` if it is unknown.
+    pub fn from_snapshot<F>(snapshot: &RingSnapshot, node_hasher: H, key_hasher: K, resolver: F) -> Result<HashRing<'a, H, K>, Box<dyn Error>>
+    where
+        F: FnMut(&str) -> Option<Arc<dyn Node<'a> + 'a>>,
+    {
+        let mut hash_ring = HashRing::with_hashers(snapshot.config.clone(), node_hasher, key_hasher)?;
+        hash_ring.restore(snapshot, resolver)?;
+        Ok(hash_ring)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use crate::tests::{access, key_on, test_nodes as nodes};
+    use crate::{Config, HashRing, Node, NodeIdPolicy, RingSnapshot};
+
+    #[test]
+    fn test_snapshot_restore() {
+        let nodes = nodes();
+        let mut hash_ring = HashRing::new(Config::default()).unwrap();
+        for node in &nodes {
+            hash_ring.add_node(node.clone()).unwrap();
+        }
+        let (hot, part_id) = key_on(&hash_ring, "node1", &[]);
+        let (warm, _) = key_on(&hash_ring, "node1", &[part_id]);
+        access(&hash_ring, &hot, 100);
+        access(&hash_ring, &warm, 100);
+        assert_eq!(hash_ring.rebalance(1).len(), 1);
+
+        let snapshot = hash_ring.snapshot();
+        assert_eq!(snapshot.nodes, vec!["node1", "node2", "node3"]);
+
+        let resolver = |id: &str| -> Option<Arc<dyn Node<'static>>> {
+            nodes.iter().find(|n| n.id() == id).map(|n| n.clone() as Arc<dyn Node<'static>>)
+        };
+        let restored: HashRing = HashRing::from_snapshot(&snapshot, Default::default(), Default::default(), resolver).unwrap();
+
+        for part_id in 0..restored.config().partition_count {
+            assert_eq!(
+                restored.get_partition_owner(part_id).unwrap().id(),
+                hash_ring.get_partition_owner(part_id).unwrap().id()
+            );
+        }
+        assert_eq!(restored.get_key(b"some_key").unwrap().id(), hash_ring.get_key(b"some_key").unwrap().id());
+        // The moved partition is routed to its new owner after the restore too.
+        for key in [&hot, &warm] {
+            assert_eq!(restored.get_key(key).unwrap().id(), hash_ring.get_key(key).unwrap().id());
+        }
+    }
+
+    #[test]
+    fn test_restore_partial_partitions() {
+        let nodes = nodes();
+        let mut hash_ring = HashRing::new(Config::default()).unwrap();
+        for node in &nodes {
+            hash_ring.add_node(node.clone()).unwrap();
+        }
+        let mut snapshot = hash_ring.snapshot();
+        snapshot.partitions.clear();
+
+        let mut restored = HashRing::new(Config::default()).unwrap();
+        restored
+            .restore(&snapshot, |id| nodes.iter().find(|n| n.id() == id).map(|n| n.clone() as Arc<dyn Node<'static>>))
+            .unwrap();
+        assert_eq!(restored.snapshot().partitions, hash_ring.snapshot().partitions);
+    }
+
+    #[test]
+    fn test_restore_unknown_node_leaves_ring_unchanged() {
+        let nodes = nodes();
+        let mut hash_ring = HashRing::new(Config::default()).unwrap();
+        hash_ring.add_node(nodes[0].clone()).unwrap();
+        let snapshot = hash_ring.snapshot();
+
+        let mut other = HashRing::new(Config::default()).unwrap();
+        other.add_node(nodes[1].clone()).unwrap();
+        assert!(other.restore(&snapshot, |_| None).is_err());
+        assert_eq!(other.get_key(b"some_key").unwrap().id(), "node2");
+    }
+
+    #[test]
+    fn test_restore_keeps_unpersisted_config() {
+        let nodes = nodes();
+        let resolve = |id: &str| nodes.iter().find(|n| n.id() == id).map(|n| n.clone() as Arc<dyn Node<'static>>);
+        let mut leader = HashRing::new(Config::default()).unwrap();
+        leader.set_changelog_capacity(8);
+        leader.add_node(nodes[0].clone()).unwrap();
+        let snapshot = leader.snapshot();
+        leader.add_node(nodes[1].clone()).unwrap();
+
+        let mut follower = HashRing::new(Config {
+            node_id_policy: NodeIdPolicy {
+                max_len: 8,
+                extra_chars: Some("-".to_string()),
+            },
+            capacity_headroom: 0.25,
+            ..Config::default()
+        })
+        .unwrap();
+        let local = follower.config();
+        follower.apply_delta(&leader.snapshot_delta(0).unwrap(), resolve).unwrap();
+        assert_eq!(follower.config(), local);
+        assert_eq!(follower.snapshot().partitions, leader.snapshot().partitions);
+        follower.restore(&RingSnapshot::from_bytes(&snapshot.to_bytes().unwrap()).unwrap(), resolve).unwrap();
+        assert_eq!(follower.config(), local);
+    }
+}
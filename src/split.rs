@@ -0,0 +1,455 @@
+//! Splitting and merging of individual partitions.
+//!
+//! Every root partition `r` in `0..partition_count` is the root of a binary tree. The
+//! tree node with heap index `h` (the root is `1`, the children of `h` are `2h` and
+//! `2h + 1`) has the partition ID `r + partition_count * (h - 1)`. Root IDs are therefore
+//! unchanged, and child IDs are the same regardless of the order partitions were split in.
+//!
+//! A key first picks its root with `hash % partition_count`, then descends through
+//! split partitions using the bits of `hash / partition_count`, so splitting a partition
+//! only moves keys between its two children.
+//!
+//! Since IDs derive from their parents, splitting every partition with
+//! `split_all_partitions` doubles the granularity of the ring without renumbering: state
+//! kept under a partition ID stays valid, and `parent_partition` and `child_partitions`
+//! relate it to the partitions that now hold its keys. `resize_partitions` instead
+//! changes `partition_count` and renumbers every partition.
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::error::Error;
+use std::hash::BuildHasher;
+use std::sync::Arc;
+
+use crate::load::PartitionLoad;
+use crate::trace::OpTimer;
+use crate::{routing, HashRing, Node};
+
+/// The maximum depth of a partition below its root partition.
+const MAX_SPLIT_DEPTH: u32 = 16;
+
+fn partition_id(partition_count: usize, root: usize, heap: usize) -> Option<usize> {
+    partition_count.checked_mul(heap - 1)?.checked_add(root)
+}
+
+fn decompose(partition_count: usize, part_id: usize) -> (usize, usize) {
+    (part_id % partition_count, part_id / partition_count + 1)
+}
+
+/// Returns the partition a key hash falls into, descending through split partitions.
+pub(crate) fn route_partition(partition_count: usize, splits: &BTreeSet<usize>, hash: u64) -> usize {
+    let root = (hash % partition_count as u64) as usize;
+    let mut bits = hash / partition_count as u64;
+    let mut heap = 1;
+    let mut part_id = root;
+    while splits.contains(&part_id) {
+        heap = heap * 2 + (bits & 1) as usize;
+        bits >>= 1;
+        part_id = root + partition_count * (heap - 1);
+    }
+    part_id
+}
+
+/// Returns `true` if the partition currently receives keys, i.e. every ancestor is split
+/// and the partition itself is not.
+pub(crate) fn is_leaf(partition_count: usize, splits: &BTreeSet<usize>, part_id: usize) -> bool {
+    if splits.contains(&part_id) {
+        return false;
+    }
+    let (root, mut heap) = decompose(partition_count, part_id);
+    while heap > 1 {
+        heap /= 2;
+        if !splits.contains(&(root + partition_count * (heap - 1))) {
+            return false;
+        }
+    }
+    true
+}
+
+/// Checks that every split partition is reachable from its root partition.
+pub(crate) fn validate_splits(partition_count: usize, splits: &BTreeSet<usize>) -> Result<(), Box<dyn Error>> {
+    for part_id in splits {
+        let (root, mut heap) = decompose(partition_count, *part_id);
+        if heap.ilog2() >= MAX_SPLIT_DEPTH {
+            return Err(format!("split partition {} is too deep", part_id).into());
+        }
+        while heap > 1 {
+            heap /= 2;
+            if !splits.contains(&(root + partition_count * (heap - 1))) {
+                return Err(format!("split partition {} has an unsplit ancestor", part_id).into());
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Returns the IDs of the two children a partition is split into.
+pub(crate) fn children(partition_count: usize, part_id: usize) -> Result<(usize, usize), Box<dyn Error>> {
+    let (root, heap) = decompose(partition_count, part_id);
+    if heap.ilog2() >= MAX_SPLIT_DEPTH {
+        return Err(format!("partition {} is already split {} times", part_id, MAX_SPLIT_DEPTH).into());
+    }
+    let left = partition_id(partition_count, root, heap * 2).ok_or("partition id overflow")?;
+    let right = partition_id(partition_count, root, heap * 2 + 1).ok_or("partition id overflow")?;
+    Ok((left, right))
+}
+
+/// Returns the ID of the parent two sibling partitions are merged into.
+pub(crate) fn parent_of_siblings(partition_count: usize, a: usize, b: usize) -> Result<usize, Box<dyn Error>> {
+    let (root_a, heap_a) = decompose(partition_count, a);
+    let (root_b, heap_b) = decompose(partition_count, b);
+    if root_a != root_b || heap_a == heap_b || heap_a / 2 != heap_b / 2 || heap_a == 1 {
+        return Err(format!("partitions {} and {} are not siblings", a, b).into());
+    }
+    Ok(root_a + partition_count * (heap_a / 2 - 1))
+}
+
+/// Returns every partition that currently receives keys, in ascending order.
+pub(crate) fn leaf_partitions(partition_count: usize, splits: &BTreeSet<usize>) -> Vec<usize> {
+    if splits.is_empty() {
+        return (0..partition_count).collect();
+    }
+
+    let mut leaves = Vec::with_capacity(partition_count + splits.len());
+    for root in 0..partition_count {
+        let mut stack = vec![1];
+        while let Some(heap) = stack.pop() {
+            let part_id = root + partition_count * (heap - 1);
+            if splits.contains(&part_id) {
+                stack.push(heap * 2 + 1);
+                stack.push(heap * 2);
+            } else {
+                leaves.push(part_id);
+            }
+        }
+    }
+    leaves.sort_unstable();
+    leaves
+}
+
+impl<'a, H, K> HashRing<'a, H, K>
+where
+    H: BuildHasher,
+    K: BuildHasher,
+{
+    /// Splits a partition into two children, leaving every other partition untouched.
+    ///
+    /// Keys of the partition are divided between the children, which are assigned to
+    /// nodes like any other partition. Load recorded for the partition is divided evenly.
+    ///
+    /// # Arguments
+    ///
+    /// * `part_id` - The ID of the partition to split.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<(usize, usize), Box<dyn Error>>` - The IDs of the two children.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use hashring::{HashRing, Config};
+    ///
+    /// let mut hash_ring = HashRing::new(Config::default()).unwrap();
+    /// let (left, right) = hash_ring.split_partition(7).unwrap();
+    ///
+    /// assert_eq!(hash_ring.merge_partitions(left, right).unwrap(), 7);
+    /// ```
+    pub fn split_partition(&mut self, part_id: usize) -> Result<(usize, usize), Box<dyn Error>> {
+        let _events = self.dispatch_events();
+        let timer = OpTimer::start();
+        let count = self.config().partition_count;
+        let sorted_set = self.sorted_nodes_hash_set.read()?;
+        let mut partitions = self.partitions.write()?;
+        let mut routes = self.routes.write()?;
+        let mut load = self.partition_load.write()?;
+
+        if !is_leaf(count, &routes.splits, part_id) {
+            return Err(format!("partition {} does not exist", part_id).into());
+        }
+        let children = self.split_leaf(&sorted_set, &mut partitions, &mut routes.splits, &mut load, part_id)?;
+        // The children are assigned from the ring, so a move of the partition ends.
+        routes.moved.remove(&part_id);
+
+        self.bump_version();
+        self.log_changes("split_partition", &timer, None, &partitions);
+        Ok(children)
+    }
+
+    /// Splits every partition that currently receives keys, doubling the granularity of
+    /// the ring without renumbering any existing partition.
+    ///
+    /// Unlike `resize_partitions`, the ID of every partition keeps its meaning: the keys
+    /// of a split partition are exactly those of its two children, so state keyed by
+    /// partition ID can be carried over with `child_partitions`.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<BTreeMap<usize, (usize, usize)>, Box<dyn Error>>` - The children of every split partition.
+    ///   Nothing is split if any partition is already at the maximum depth.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use hashring::{HashRing, Config};
+    ///
+    /// let mut config = Config::default();
+    /// config.partition_count = 4;
+    /// let mut hash_ring = HashRing::new(config).unwrap();
+    /// let part_id = hash_ring.get_partition(b"some_key");
+    ///
+    /// let children = hash_ring.split_all_partitions().unwrap();
+    /// assert_eq!(hash_ring.partition_ids().len(), 8);
+    /// let (left, right) = children[&part_id];
+    /// assert!([left, right].contains(&hash_ring.get_partition(b"some_key")));
+    /// assert_eq!(hash_ring.parent_partition(left), Some(part_id));
+    /// ```
+    pub fn split_all_partitions(&mut self) -> Result<BTreeMap<usize, (usize, usize)>, Box<dyn Error>> {
+        let _events = self.dispatch_events();
+        let timer = OpTimer::start();
+        let count = self.config().partition_count;
+        let sorted_set = self.sorted_nodes_hash_set.read()?;
+        let mut partitions = self.partitions.write()?;
+        let mut routes = self.routes.write()?;
+        let mut load = self.partition_load.write()?;
+
+        let leaves = leaf_partitions(count, &routes.splits);
+        if let Some(part_id) = leaves.iter().find(|part_id| decompose(count, **part_id).1.ilog2() >= MAX_SPLIT_DEPTH) {
+            return Err(format!("partition {} is already split {} times", part_id, MAX_SPLIT_DEPTH).into());
+        }
+        routes.moved.clear();
+        let mut children = BTreeMap::new();
+        for part_id in leaves {
+            children.insert(part_id, self.split_leaf(&sorted_set, &mut partitions, &mut routes.splits, &mut load, part_id)?);
+        }
+
+        self.bump_version();
+        self.log_changes("split_all_partitions", &timer, None, &partitions);
+        timer.finish("split_all_partitions", children.len(), sorted_set.len());
+        Ok(children)
+    }
+
+    /// Splits a leaf partition, assigning its children and dividing its load.
+    fn split_leaf(
+        &self,
+        sorted_set: &BTreeMap<u64, Arc<dyn Node<'a> + 'a>>,
+        partitions: &mut HashMap<usize, Arc<dyn Node<'a> + 'a>>,
+        splits: &mut BTreeSet<usize>,
+        load: &mut HashMap<usize, PartitionLoad>,
+        part_id: usize,
+    ) -> Result<(usize, usize), Box<dyn Error>> {
+        let (left, right) = children(self.config().partition_count, part_id)?;
+        splits.insert(part_id);
+        partitions.remove(&part_id);
+        for child in [left, right] {
+            if let Some(node) = routing::partition_owner(&self.node_hasher, sorted_set, child) {
+                partitions.insert(child, node.clone());
+            }
+        }
+
+        if let Some(l) = load.remove(&part_id) {
+            let (left_load, right_load) = l.halve();
+            load.insert(left, left_load);
+            load.insert(right, right_load);
+        }
+        Ok((left, right))
+    }
+
+    /// Merges two sibling partitions back into their parent.
+    ///
+    /// # Arguments
+    ///
+    /// * `a` - The ID of one child.
+    /// * `b` - The ID of its sibling.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<usize, Box<dyn Error>>` - The ID of the parent partition.
+    pub fn merge_partitions(&mut self, a: usize, b: usize) -> Result<usize, Box<dyn Error>> {
+        let _events = self.dispatch_events();
+        let timer = OpTimer::start();
+        let count = self.config().partition_count;
+        let sorted_set = self.sorted_nodes_hash_set.read()?;
+        let mut partitions = self.partitions.write()?;
+        let mut routes = self.routes.write()?;
+        let mut load = self.partition_load.write()?;
+
+        for part_id in [a, b] {
+            if !is_leaf(count, &routes.splits, part_id) {
+                return Err(format!("partition {} does not exist", part_id).into());
+            }
+        }
+        let parent = parent_of_siblings(count, a, b)?;
+
+        routes.splits.remove(&parent);
+        partitions.remove(&a);
+        partitions.remove(&b);
+        routes.moved.remove(&a);
+        routes.moved.remove(&b);
+        if let Some(node) = routing::partition_owner(&self.node_hasher, &sorted_set, parent) {
+            partitions.insert(parent, node.clone());
+        }
+
+        let merged = load.remove(&a).unwrap_or_default().merge(load.remove(&b).unwrap_or_default());
+        if merged.total > 0 {
+            load.insert(parent, merged);
+        }
+
+        self.bump_version();
+        self.log_changes("merge_partitions", &timer, None, &partitions);
+        Ok(parent)
+    }
+
+    /// Returns the IDs of every partition that currently receives keys, in ascending order.
+    ///
+    /// Without splits this is `0..partition_count`.
+    pub fn partition_ids(&self) -> Vec<usize> {
+        let partition_count = self.config.wait_read().partition_count;
+        leaf_partitions(partition_count, &self.routes.wait_read().splits)
+    }
+
+    /// Returns `true` if the partition currently receives keys.
+    pub fn has_partition(&self, part_id: usize) -> bool {
+        let partition_count = self.config.wait_read().partition_count;
+        is_leaf(partition_count, &self.routes.wait_read().splits, part_id)
+    }
+
+    /// Returns the partition a partition was split from, or `None` for a root partition.
+    ///
+    /// This only depends on the partition count, so it also works for partitions that
+    /// have been merged away since.
+    pub fn parent_partition(&self, part_id: usize) -> Option<usize> {
+        let count = self.config().partition_count;
+        let (root, heap) = decompose(count, part_id);
+        (heap > 1).then(|| root + count * (heap / 2 - 1))
+    }
+
+    /// Returns the IDs the two children of a partition have, or would have once it is
+    /// split.
+    pub fn child_partitions(&self, part_id: usize) -> Option<(usize, usize)> {
+        let count = self.config().partition_count;
+        let (root, heap) = decompose(count, part_id);
+        let left = heap.checked_mul(2)?;
+        Some((partition_id(count, root, left)?, partition_id(count, root, left + 1)?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use crate::tests::test_nodes;
+    use crate::{Config, HashRing, Node, RingSnapshot};
+
+    fn ring() -> HashRing<'static> {
+        let mut hash_ring = HashRing::new(Config {
+            partition_count: 10,
+            ..Config::default()
+        })
+        .unwrap();
+        for node in test_nodes() {
+            hash_ring.add_node(node).unwrap();
+        }
+        hash_ring
+    }
+
+    #[test]
+    fn test_split_only_moves_keys_of_split_partition() {
+        let mut hash_ring = ring();
+        let before: Vec<usize> = (0..500).map(|i| hash_ring.get_partition(format!("key-{}", i).as_bytes())).collect();
+
+        let (left, right) = hash_ring.split_partition(3).unwrap();
+        assert_eq!((left, right), (13, 23));
+        assert!(!hash_ring.has_partition(3));
+        assert_eq!(hash_ring.partition_ids().len(), 11);
+        assert!(hash_ring.get_partition_owner(left).is_some());
+        assert!(hash_ring.get_partition_owner(3).is_none());
+
+        let mut seen = [false; 2];
+        for (i, part_id) in before.into_iter().enumerate() {
+            let after = hash_ring.get_partition(format!("key-{}", i).as_bytes());
+            if part_id == 3 {
+                assert!(after == left || after == right);
+                seen[(after == right) as usize] = true;
+            } else {
+                assert_eq!(after, part_id);
+            }
+        }
+        assert_eq!(seen, [true, true]);
+    }
+
+    #[test]
+    fn test_split_ids_are_stable_and_merge_restores() {
+        let mut hash_ring = ring();
+        let (left, right) = hash_ring.split_partition(3).unwrap();
+        let (ll, lr) = hash_ring.split_partition(left).unwrap();
+        assert_eq!((ll, lr), (33, 43));
+        assert!(hash_ring.split_partition(3).is_err());
+        assert!(hash_ring.merge_partitions(left, right).is_err());
+        assert!(hash_ring.merge_partitions(lr, right).is_err());
+
+        hash_ring.record_partition_load(ll, 10);
+        hash_ring.record_partition_load(lr, 5);
+        assert_eq!(hash_ring.merge_partitions(ll, lr).unwrap(), left);
+        assert_eq!(hash_ring.hottest_partitions(1), vec![(left, 15)]);
+        assert_eq!(hash_ring.merge_partitions(right, left).unwrap(), 3);
+        assert_eq!(hash_ring.partition_ids(), (0..10).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_contended_merge_leaves_partitions_untouched() {
+        let mut hash_ring = ring();
+        let (left, right) = hash_ring.split_partition(3).unwrap();
+        hash_ring.set_lock_timeout(Some(Duration::from_millis(20)));
+
+        // The load is locked elsewhere, so the merge fails before changing anything.
+        let stuck = hash_ring.share();
+        let writer = stuck.partition_load.wait_write();
+        assert!(hash_ring.merge_partitions(left, right).is_err());
+        drop(writer);
+        assert!(hash_ring.has_partition(left) && hash_ring.has_partition(right));
+        assert!(hash_ring.get_partition_owner(left).is_some());
+        assert_eq!(hash_ring.merge_partitions(left, right).unwrap(), 3);
+    }
+
+    #[test]
+    fn test_split_all_partitions_keeps_ids() {
+        let mut hash_ring = ring();
+        hash_ring.split_partition(3).unwrap();
+        let before: Vec<usize> = (0..500).map(|i| hash_ring.get_partition(format!("key-{}", i).as_bytes())).collect();
+
+        let children = hash_ring.split_all_partitions().unwrap();
+        assert_eq!(children.len(), 11);
+        assert_eq!(children[&13], (33, 43));
+        assert_eq!(hash_ring.partition_ids().len(), 22);
+        for (i, part_id) in before.into_iter().enumerate() {
+            let after = hash_ring.get_partition(format!("key-{}", i).as_bytes());
+            assert_eq!(hash_ring.parent_partition(after), Some(part_id));
+            assert!(hash_ring.get_partition_owner(after).is_some());
+        }
+        assert_eq!(hash_ring.child_partitions(13), Some((33, 43)));
+        assert_eq!(hash_ring.parent_partition(13), Some(3));
+        assert_eq!(hash_ring.parent_partition(3), None);
+    }
+
+    #[test]
+    fn test_splits_survive_snapshot_restore() {
+        let nodes = test_nodes();
+        let mut hash_ring = ring();
+        hash_ring.split_partition(3).unwrap();
+        let snapshot = RingSnapshot::from_bytes(&hash_ring.snapshot().to_bytes().unwrap()).unwrap();
+        assert_eq!(snapshot.splits.iter().copied().collect::<Vec<_>>(), vec![3]);
+
+        let restored: HashRing = HashRing::from_snapshot(&snapshot, Default::default(), Default::default(), |id| {
+            nodes.iter().find(|n| n.id() == id).map(|n| n.clone() as Arc<dyn Node<'static>>)
+        })
+        .unwrap();
+        assert_eq!(restored.partition_ids(), hash_ring.partition_ids());
+        assert_eq!(restored.get_partition(b"some_key"), hash_ring.get_partition(b"some_key"));
+
+        let mut invalid = snapshot.clone();
+        invalid.splits.insert(33);
+        let mut other = ring();
+        assert!(other.restore(&invalid, |_| None).is_err());
+    }
+}
@@ -0,0 +1,400 @@
+//! The locks guarding the state of a ring.
+//!
+//! Every lock of a `HashRing` shares a `LockMonitor` counting the acquisitions that had
+//! to wait and the time spent waiting. Uncontended acquisitions aren't counted, so they
+//! don't write to memory shared by every lookup. With a lock timeout set through `set_lock_timeout`, `read`
+//! and `write` give up after waiting that long and return `LockError::LockContended`,
+//! so a stuck writer makes lookups fail instead of blocking them forever. Operations
+//! that cannot fail acquire their locks with `wait_read` and `wait_write`, which ignore
+//! the timeout.
+//!
+//! Locks recover from poisoning: a thread panicking while holding one, for instance in
+//! an event sink, doesn't make every later operation on the ring fail. The state may
+//! then reflect part of the interrupted operation, as if it had been cut short.
+//!
+//! The locks are std `RwLock`s, or `parking_lot` ones with the `parking_lot` feature,
+//! which are faster under contention and wait for a lock with a deadline instead of
+//! polling it.
+
+use std::error::Error;
+use std::fmt;
+use std::hash::BuildHasher;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::sync::{Mutex, MutexGuard, PoisonError};
+use std::time::{Duration, Instant};
+
+pub(crate) use imp::{ReadGuard, WriteGuard};
+
+use crate::HashRing;
+
+#[cfg(not(feature = "parking_lot"))]
+mod imp {
+    use std::sync::{PoisonError, TryLockError, TryLockResult};
+    use std::time::{Duration, Instant};
+
+    pub(crate) type RawRwLock<T> = std::sync::RwLock<T>;
+    pub(crate) type ReadGuard<'l, T> = std::sync::RwLockReadGuard<'l, T>;
+    pub(crate) type WriteGuard<'l, T> = std::sync::RwLockWriteGuard<'l, T>;
+
+    /// The longest pause between two attempts to take a contended lock.
+    const MAX_BACKOFF: Duration = Duration::from_millis(1);
+
+    pub(crate) fn try_read<T>(lock: &RawRwLock<T>) -> Option<ReadGuard<'_, T>> {
+        recover(lock.try_read())
+    }
+
+    pub(crate) fn try_write<T>(lock: &RawRwLock<T>) -> Option<WriteGuard<'_, T>> {
+        recover(lock.try_write())
+    }
+
+    pub(crate) fn read<T>(lock: &RawRwLock<T>) -> ReadGuard<'_, T> {
+        lock.read().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    pub(crate) fn write<T>(lock: &RawRwLock<T>) -> WriteGuard<'_, T> {
+        lock.write().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    pub(crate) fn read_for<T>(lock: &RawRwLock<T>, timeout: Duration) -> Option<ReadGuard<'_, T>> {
+        poll(|| try_read(lock), timeout)
+    }
+
+    pub(crate) fn write_for<T>(lock: &RawRwLock<T>, timeout: Duration) -> Option<WriteGuard<'_, T>> {
+        poll(|| try_write(lock), timeout)
+    }
+
+    fn recover<G>(result: TryLockResult<G>) -> Option<G> {
+        match result {
+            Ok(guard) => Some(guard),
+            Err(TryLockError::Poisoned(poisoned)) => Some(poisoned.into_inner()),
+            Err(TryLockError::WouldBlock) => None,
+        }
+    }
+
+    /// Std locks can't be waited on with a deadline, so poll with a growing pause.
+    fn poll<G>(try_lock: impl Fn() -> Option<G>, timeout: Duration) -> Option<G> {
+        let started = Instant::now();
+        let mut backoff = Duration::from_micros(1);
+        loop {
+            let waited = started.elapsed();
+            if waited >= timeout {
+                return None;
+            }
+            std::thread::sleep(backoff.min(timeout - waited));
+            backoff = (backoff * 2).min(MAX_BACKOFF);
+            if let Some(guard) = try_lock() {
+                return Some(guard);
+            }
+        }
+    }
+}
+
+#[cfg(feature = "parking_lot")]
+mod imp {
+    use std::time::Duration;
+
+    pub(crate) type RawRwLock<T> = parking_lot::RwLock<T>;
+    pub(crate) type ReadGuard<'l, T> = parking_lot::RwLockReadGuard<'l, T>;
+    pub(crate) type WriteGuard<'l, T> = parking_lot::RwLockWriteGuard<'l, T>;
+
+    pub(crate) fn try_read<T>(lock: &RawRwLock<T>) -> Option<ReadGuard<'_, T>> {
+        lock.try_read()
+    }
+
+    pub(crate) fn try_write<T>(lock: &RawRwLock<T>) -> Option<WriteGuard<'_, T>> {
+        lock.try_write()
+    }
+
+    pub(crate) fn read<T>(lock: &RawRwLock<T>) -> ReadGuard<'_, T> {
+        lock.read()
+    }
+
+    pub(crate) fn write<T>(lock: &RawRwLock<T>) -> WriteGuard<'_, T> {
+        lock.write()
+    }
+
+    pub(crate) fn read_for<T>(lock: &RawRwLock<T>, timeout: Duration) -> Option<ReadGuard<'_, T>> {
+        lock.try_read_for(timeout)
+    }
+
+    pub(crate) fn write_for<T>(lock: &RawRwLock<T>, timeout: Duration) -> Option<WriteGuard<'_, T>> {
+        lock.try_write_for(timeout)
+    }
+}
+
+/// An error acquiring an internal lock of a ring.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LockError {
+    /// The lock was held by another thread for longer than the lock timeout.
+    LockContended { waited: Duration },
+}
+
+impl fmt::Display for LockError {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LockError::LockContended { waited } => write!(fmt, "lock contended, gave up after {:?}", waited),
+        }
+    }
+}
+
+impl Error for LockError {}
+
+/// Lock acquisition counters of a ring, see `HashRing::lock_stats`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct LockStats {
+    /// The number of acquisitions that had to wait for another thread.
+    pub contended: u64,
+    /// The number of acquisitions abandoned after the lock timeout.
+    pub timeouts: u64,
+    /// The longest time an acquisition waited.
+    pub max_wait: Duration,
+}
+
+/// The lock timeout and counters shared by the locks of a ring.
+#[derive(Debug, Default)]
+pub(crate) struct LockMonitor {
+    /// The timeout in nanoseconds, 0 for none.
+    timeout: AtomicU64,
+    contended: AtomicU64,
+    timeouts: AtomicU64,
+    max_wait: AtomicU64,
+}
+
+impl LockMonitor {
+    pub(crate) fn timeout(&self) -> Option<Duration> {
+        match self.timeout.load(Ordering::Relaxed) {
+            0 => None,
+            nanos => Some(Duration::from_nanos(nanos)),
+        }
+    }
+
+    pub(crate) fn set_timeout(&self, timeout: Option<Duration>) {
+        let nanos = timeout.map_or(0, |timeout| timeout.as_nanos().clamp(1, u64::MAX as u128) as u64);
+        self.timeout.store(nanos, Ordering::Relaxed);
+    }
+
+    pub(crate) fn stats(&self) -> LockStats {
+        LockStats {
+            contended: self.contended.load(Ordering::Relaxed),
+            timeouts: self.timeouts.load(Ordering::Relaxed),
+            max_wait: Duration::from_nanos(self.max_wait.load(Ordering::Relaxed)),
+        }
+    }
+
+    fn record_wait(&self, waited: Duration) {
+        self.contended.fetch_add(1, Ordering::Relaxed);
+        self.max_wait.fetch_max(waited.as_nanos().min(u64::MAX as u128) as u64, Ordering::Relaxed);
+    }
+}
+
+/// A reader-writer lock reporting to the `LockMonitor` of its ring.
+pub(crate) struct RwLock<T> {
+    inner: imp::RawRwLock<T>,
+    monitor: Arc<LockMonitor>,
+}
+
+impl<T: fmt::Debug> fmt::Debug for RwLock<T> {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.inner.fmt(fmt)
+    }
+}
+
+impl<T> RwLock<T> {
+    pub(crate) fn new(value: T, monitor: &Arc<LockMonitor>) -> Self {
+        RwLock {
+            inner: imp::RawRwLock::new(value),
+            monitor: monitor.clone(),
+        }
+    }
+
+    /// Acquires the lock for reading, giving up after the lock timeout.
+    pub(crate) fn read(&self) -> Result<ReadGuard<'_, T>, LockError> {
+        let timeout = self.monitor.timeout();
+        self.acquire(|| imp::try_read(&self.inner), || imp::read(&self.inner), |timeout| imp::read_for(&self.inner, timeout), timeout)
+    }
+
+    /// Acquires the lock for writing, giving up after the lock timeout.
+    pub(crate) fn write(&self) -> Result<WriteGuard<'_, T>, LockError> {
+        let timeout = self.monitor.timeout();
+        self.acquire(|| imp::try_write(&self.inner), || imp::write(&self.inner), |timeout| imp::write_for(&self.inner, timeout), timeout)
+    }
+
+    /// Acquires the lock for reading, however long it takes.
+    pub(crate) fn wait_read(&self) -> ReadGuard<'_, T> {
+        // Without a timeout, acquiring can't fail.
+        self.acquire(|| imp::try_read(&self.inner), || imp::read(&self.inner), |_| None, None).unwrap()
+    }
+
+    /// Acquires the lock for writing, however long it takes.
+    pub(crate) fn wait_write(&self) -> WriteGuard<'_, T> {
+        self.acquire(|| imp::try_write(&self.inner), || imp::write(&self.inner), |_| None, None).unwrap()
+    }
+
+    fn acquire<G>(
+        &self,
+        try_lock: impl FnOnce() -> Option<G>,
+        lock: impl FnOnce() -> G,
+        lock_for: impl FnOnce(Duration) -> Option<G>,
+        timeout: Option<Duration>,
+    ) -> Result<G, LockError> {
+        if let Some(guard) = try_lock() {
+            return Ok(guard);
+        }
+
+        let started = Instant::now();
+        let guard = match timeout {
+            Some(timeout) => lock_for(timeout),
+            None => Some(lock()),
+        };
+        let waited = started.elapsed();
+        self.monitor.record_wait(waited);
+        guard.ok_or_else(|| {
+            self.monitor.timeouts.fetch_add(1, Ordering::Relaxed);
+            LockError::LockContended { waited }
+        })
+    }
+}
+
+/// Locks a mutex, recovering it if a thread panicked while holding it.
+pub(crate) fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+impl<'a, H, K> HashRing<'a, H, K>
+where
+    H: BuildHasher,
+    K: BuildHasher,
+{
+    /// Sets how long operations wait for a lock held by another thread before failing
+    /// with `LockError::LockContended`, or lets them wait forever with `None`, the default.
+    ///
+    /// Lookups of nodes give up too: those returning an `Option` return `None`, and those
+    /// returning a list return an empty one. `config` and `get_partition` can't fail and
+    /// always wait. The timeout is shared with every handle made with `share`, and copied
+    /// by `clone`.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use std::time::Duration;
+    /// use hashring::{HashRing, Config};
+    ///
+    /// let hash_ring = HashRing::new(Config::default()).unwrap();
+    /// hash_ring.set_lock_timeout(Some(Duration::from_millis(50)));
+    ///
+    /// hash_ring.get_key(b"some_key");
+    /// let stats = hash_ring.lock_stats();
+    /// assert_eq!(stats.contended, 0);
+    /// assert_eq!(stats.timeouts, 0);
+    /// ```
+    pub fn set_lock_timeout(&self, timeout: Option<Duration>) {
+        self.locks.set_timeout(timeout);
+    }
+
+    /// Returns the lock timeout, if one is set.
+    pub fn lock_timeout(&self) -> Option<Duration> {
+        self.locks.timeout()
+    }
+
+    /// Returns the counters of contended lock acquisitions of the ring and the handles
+    /// sharing it.
+    pub fn lock_stats(&self) -> LockStats {
+        self.locks.stats()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::hash::BuildHasher;
+    use std::panic::AssertUnwindSafe;
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use xxhash_rust::xxh3::Xxh3;
+
+    use crate::tests::test_nodes;
+    use crate::{Config, HashRing, LockError, RingEvent, XxHash64Hasher};
+
+    #[test]
+    fn test_contended_lock_times_out() {
+        let mut hash_ring = HashRing::new(Config::default()).unwrap();
+        hash_ring.add_node(test_nodes()[0].clone()).unwrap();
+        hash_ring.set_lock_timeout(Some(Duration::from_millis(20)));
+        assert_eq!(hash_ring.lock_timeout(), Some(Duration::from_millis(20)));
+
+        // A writer stuck on another handle of the ring.
+        let stuck = hash_ring.share();
+        let writer = stuck.sorted_nodes_hash_set.wait_write();
+        assert!(hash_ring.get_key(b"some_key").is_none());
+        let err = hash_ring.add_node(test_nodes()[1].clone()).unwrap_err();
+        assert!(matches!(err.downcast_ref::<LockError>(), Some(LockError::LockContended { waited }) if *waited >= Duration::from_millis(20)));
+        drop(writer);
+
+        let stats = hash_ring.lock_stats();
+        assert_eq!(stats.timeouts, 2);
+        assert!(stats.contended >= 2);
+        assert!(stats.max_wait >= Duration::from_millis(20));
+        assert!(hash_ring.get_key(b"some_key").is_some());
+
+        // Lookups also give up on the configuration and the routes.
+        let writer = stuck.config.wait_write();
+        assert!(hash_ring.get_key_id(b"some_key").is_none());
+        assert!(hash_ring.get_preference_list_n(b"some_key", 1).is_empty());
+        drop(writer);
+        let writer = stuck.routes.wait_write();
+        assert!(hash_ring.get_key(b"some_key").is_none());
+        assert!(hash_ring.retry_sequence(b"some_key").next().is_none());
+        drop(writer);
+        assert_eq!(hash_ring.get_preference_list_n(b"some_key", 1).len(), 1);
+    }
+
+    /// A node hasher panicking while `panics` is set.
+    #[derive(Clone, Default)]
+    struct PanickingHasher {
+        panics: Arc<AtomicBool>,
+    }
+
+    impl BuildHasher for PanickingHasher {
+        type Hasher = Xxh3;
+
+        fn build_hasher(&self) -> Xxh3 {
+            if self.panics.load(Ordering::Relaxed) {
+                panic!("hasher failed");
+            }
+            Xxh3::default()
+        }
+    }
+
+    #[test]
+    fn test_panicking_writer_does_not_poison_ring() {
+        let hasher = PanickingHasher::default();
+        let mut hash_ring = HashRing::with_hashers(Config::default(), hasher.clone(), XxHash64Hasher::default()).unwrap();
+        let nodes = test_nodes();
+        hash_ring.add_node(nodes[0].clone()).unwrap();
+        // Placing the virtual nodes panics with the locks of the ring held.
+        hasher.panics.store(true, Ordering::Relaxed);
+        let result = std::panic::catch_unwind(AssertUnwindSafe(|| hash_ring.add_node(nodes[1].clone())));
+        assert!(result.is_err());
+        hasher.panics.store(false, Ordering::Relaxed);
+
+        assert!(hash_ring.get_key(b"some_key").is_some());
+        hash_ring.add_node(nodes[2].clone()).unwrap();
+        hash_ring.remove_node("node1").unwrap();
+        assert_eq!(hash_ring.virtual_nodes_per_node().len(), 1);
+
+        // A panicking sink doesn't stop the events of later changes from being delivered.
+        hash_ring.add_event_sink(Arc::new(|_: &RingEvent| panic!("sink failed")));
+        let result = std::panic::catch_unwind(AssertUnwindSafe(|| hash_ring.add_node(nodes[1].clone())));
+        assert!(result.is_err());
+        hash_ring.clear_event_sinks();
+        let delivered = Arc::new(AtomicUsize::new(0));
+        let counter = delivered.clone();
+        hash_ring.add_event_sink(Arc::new(move |_: &RingEvent| {
+            counter.fetch_add(1, Ordering::Relaxed);
+        }));
+        hash_ring.remove_node("node2").unwrap();
+        assert_eq!(delivered.load(Ordering::Relaxed), 1);
+    }
+}
@@ -0,0 +1,194 @@
+//! Fixtures for writing stable tests against ring behavior, behind the `testing` feature.
+//!
+//! `HashRing::deterministic` builds rings with a fixed configuration, fixed synthetic
+//! nodes and a self-contained hasher, so their virtual node layout doesn't change with the
+//! version of the default hasher. Golden layouts are compared through a digest of
+//! every virtual node and partition owner.
+//!
+//! Placement never reads the clock, so rings built here behave the same on every run.
+
+use std::collections::HashSet;
+use std::hash::{BuildHasher, BuildHasherDefault, Hasher};
+use std::sync::{Arc, Mutex, OnceLock};
+
+use crate::sync;
+use crate::{crc32, Config, HashRing, Node};
+
+/// The 64-bit FNV-1a hash followed by the MurmurHash3 finalizer, which spreads
+/// inputs that only differ in their last bytes.
+#[derive(Debug, Clone, Copy)]
+pub struct StableHasher(u64);
+
+impl Default for StableHasher {
+    fn default() -> StableHasher {
+        StableHasher(0xcbf2_9ce4_8422_2325)
+    }
+}
+
+impl Hasher for StableHasher {
+    fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 ^= *byte as u64;
+            self.0 = self.0.wrapping_mul(0x0000_0100_0000_01b3);
+        }
+    }
+
+    fn finish(&self) -> u64 {
+        let mut hash = self.0;
+        hash ^= hash >> 33;
+        hash = hash.wrapping_mul(0xff51_afd7_ed55_8ccd);
+        hash ^= hash >> 33;
+        hash = hash.wrapping_mul(0xc4ce_b9fe_1a85_ec53);
+        hash ^ (hash >> 33)
+    }
+}
+
+/// The hasher of deterministic rings.
+pub type DeterministicHasher = BuildHasherDefault<StableHasher>;
+
+/// A node generated for tests, named `node-{i}` and spread over three zones.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SyntheticNode {
+    id: &'static str,
+    zone: &'static str,
+}
+
+impl<'a> Node<'a> for SyntheticNode {
+    fn id(&self) -> &'a str {
+        self.id
+    }
+
+    fn zone(&self) -> Option<&'a str> {
+        Some(self.zone)
+    }
+}
+
+/// Returns a `'static` copy of `name`, allocating it at most once.
+fn intern(name: String) -> &'static str {
+    static NAMES: OnceLock<Mutex<HashSet<&'static str>>> = OnceLock::new();
+    let mut names = sync::lock(NAMES.get_or_init(Default::default));
+    if let Some(interned) = names.get(name.as_str()) {
+        return interned;
+    }
+    let interned: &'static str = Box::leak(name.into_boxed_str());
+    names.insert(interned);
+    interned
+}
+
+/// Generates `count` nodes named `node-0` to `node-{count - 1}`.
+pub fn synthetic_nodes(count: usize) -> Vec<Arc<SyntheticNode>> {
+    (0..count)
+        .map(|i| {
+            Arc::new(SyntheticNode {
+                id: intern(format!("node-{}", i)),
+                zone: intern(format!("zone-{}", i % 3)),
+            })
+        })
+        .collect()
+}
+
+/// Generates `count` keys named `key-0` to `key-{count - 1}`.
+pub fn synthetic_keys(count: usize) -> Vec<Vec<u8>> {
+    (0..count).map(|i| format!("key-{}", i).into_bytes()).collect()
+}
+
+/// The configuration of deterministic rings: 8 virtual nodes per node and 64 partitions.
+pub fn deterministic_config() -> Config {
+    Config {
+        replication_factor: 8,
+        partition_count: 64,
+        ..Config::default()
+    }
+}
+
+impl HashRing<'static, DeterministicHasher> {
+    /// Creates a ring with `node_count` synthetic nodes and a layout that is the same on every run.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use hashring::HashRing;
+    /// use hashring::testing::{assert_golden_layout, layout_digest};
+    ///
+    /// let hash_ring = HashRing::deterministic(3);
+    /// assert_golden_layout(&hash_ring, layout_digest(&HashRing::deterministic(3)));
+    /// ```
+    pub fn deterministic(node_count: usize) -> HashRing<'static, DeterministicHasher> {
+        let mut hash_ring = HashRing::with_hasher(deterministic_config(), DeterministicHasher::default()).unwrap();
+        for node in synthetic_nodes(node_count) {
+            hash_ring.add_node(node).unwrap();
+        }
+        hash_ring
+    }
+}
+
+/// Returns a digest of the virtual nodes and partition owners of the ring.
+pub fn layout_digest<H, K>(hash_ring: &HashRing<'_, H, K>) -> u32
+where
+    H: BuildHasher,
+    K: BuildHasher,
+{
+    let mut layout = Vec::new();
+    for (point, node) in hash_ring.sorted_nodes_hash_set.wait_read().iter() {
+        layout.extend_from_slice(&point.to_le_bytes());
+        layout.extend_from_slice(node.id().as_bytes());
+        layout.push(0);
+    }
+    for part_id in hash_ring.partition_ids() {
+        layout.extend_from_slice(&(part_id as u64).to_le_bytes());
+        if let Some(node) = hash_ring.get_partition_owner(part_id) {
+            layout.extend_from_slice(node.id().as_bytes());
+        }
+        layout.push(0);
+    }
+    crc32(&layout)
+}
+
+/// Panics unless the layout of the ring has the expected digest.
+///
+/// The message includes the actual digest, to update the golden value after an intended change.
+#[track_caller]
+pub fn assert_golden_layout<H, K>(hash_ring: &HashRing<'_, H, K>, expected: u32)
+where
+    H: BuildHasher,
+    K: BuildHasher,
+{
+    let actual = layout_digest(hash_ring);
+    assert!(actual == expected, "ring layout changed: digest is {:#010x}, expected {:#010x}", actual, expected);
+}
+
+/// Panics unless every key is owned by the expected node.
+#[track_caller]
+pub fn assert_routes<H, K>(hash_ring: &HashRing<'_, H, K>, expected: &[(&[u8], &str)])
+where
+    H: BuildHasher,
+    K: BuildHasher,
+{
+    for (key, id) in expected {
+        let owner = hash_ring.get_key(key).map(|node| node.id().to_string());
+        assert!(
+            owner.as_deref() == Some(*id),
+            "{} is owned by {:?}, expected {}",
+            String::from_utf8_lossy(key),
+            owner,
+            id
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_deterministic_layout_is_golden() {
+        let hash_ring = HashRing::deterministic(5);
+        assert_golden_layout(&hash_ring, 0xd3fd_a09b);
+        assert_eq!(layout_digest(&hash_ring), layout_digest(&HashRing::deterministic(5)));
+        assert_ne!(layout_digest(&hash_ring), layout_digest(&HashRing::deterministic(4)));
+
+        let keys = synthetic_keys(3);
+        assert_routes(&hash_ring, &[(&keys[0], "node-3"), (&keys[1], "node-3"), (&keys[2], "node-0")]);
+        assert!(std::ptr::eq(synthetic_nodes(2)[1].id, synthetic_nodes(3)[1].id));
+    }
+}
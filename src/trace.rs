@@ -0,0 +1,36 @@
+//! Timing of ring operations, reported through `tracing` when the feature is enabled.
+//!
+//! Without the `tracing` feature `finish` compiles away. The start time is kept either
+//! way, since change events report how long the operation took.
+
+use std::time::{Duration, Instant};
+
+pub(crate) struct OpTimer {
+    started: Instant,
+}
+
+impl OpTimer {
+    pub(crate) fn start() -> OpTimer {
+        OpTimer { started: Instant::now() }
+    }
+
+    /// Returns the time since the operation started.
+    pub(crate) fn elapsed(&self) -> Duration {
+        self.started.elapsed()
+    }
+
+    /// Emits a debug event for a completed membership or partition operation.
+    #[cfg(feature = "tracing")]
+    pub(crate) fn finish(self, op: &'static str, node_count: usize, vnode_count: usize) {
+        tracing::debug!(
+            op,
+            node_count,
+            vnode_count,
+            elapsed_us = self.started.elapsed().as_micros() as u64,
+            "ring operation completed"
+        );
+    }
+
+    #[cfg(not(feature = "tracing"))]
+    pub(crate) fn finish(self, _op: &'static str, _node_count: usize, _vnode_count: usize) {}
+}
@@ -0,0 +1,298 @@
+//! All-or-nothing membership changes.
+//!
+//! A `Transaction` stages additions and removals against a copy of the membership.
+//! Nothing touches the ring until the closure returns successfully, and the changes
+//! are then published while holding every internal lock, so readers either observe
+//! the ring before or after the transaction, never in between.
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::hash::BuildHasher;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+
+use crate::trace::OpTimer;
+use crate::{HashRing, Node};
+
+/// Staged membership changes, applied by `HashRing::transaction`.
+#[derive(Debug)]
+pub struct Transaction<'a> {
+    members: HashMap<String, Arc<dyn Node<'a> + 'a>>,
+}
+
+impl<'a> Transaction<'a> {
+    /// Stages the addition of a node.
+    pub fn add(&mut self, node: Arc<dyn Node<'a> + 'a>) -> Result<(), Box<dyn Error>> {
+        if self.members.contains_key(node.id()) {
+            return Err("node already exist".into());
+        }
+        self.members.insert(node.id().to_string(), node);
+        Ok(())
+    }
+
+    /// Stages the removal of a node.
+    pub fn remove(&mut self, id: &str) -> Result<(), Box<dyn Error>> {
+        if self.members.remove(id).is_none() {
+            return Err("node not found".into());
+        }
+        Ok(())
+    }
+
+    /// Returns `true` if the node is a member once the staged changes are applied.
+    pub fn contains(&self, id: &str) -> bool {
+        self.members.contains_key(id)
+    }
+
+    /// Returns the number of members once the staged changes are applied.
+    pub fn len(&self) -> usize {
+        self.members.len()
+    }
+
+    /// Returns `true` if no members are left once the staged changes are applied.
+    pub fn is_empty(&self) -> bool {
+        self.members.is_empty()
+    }
+}
+
+impl<'a, H, K> HashRing<'a, H, K>
+where
+    H: BuildHasher,
+    K: BuildHasher,
+{
+    /// Applies a set of membership changes atomically.
+    ///
+    /// The closure stages changes on a `Transaction`. If it returns an error, or any staged
+    /// operation fails, the ring is left untouched. The ring isn't locked while the closure
+    /// runs, so it may look up keys; if the membership changes meanwhile, through another
+    /// handle, the transaction fails instead of overwriting that change.
+    ///
+    /// # Arguments
+    ///
+    /// * `f` - A closure staging the changes.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<R, Box<dyn Error>>` - The value returned by the closure, once the changes are applied.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use std::sync::Arc;
+    /// use hashring::{HashRing, Config, Node};
+    ///
+    /// #[derive(Debug)]
+    /// struct MyNode<'a> {
+    ///     name: &'a str,
+    /// }
+    ///
+    /// impl<'a> Node<'a> for MyNode<'a> {
+    ///     fn id(&self) -> &'a str {
+    ///         self.name
+    ///     }
+    /// }
+    ///
+    /// let mut hash_ring = HashRing::new(Config::default()).unwrap();
+    /// hash_ring.add_node(Arc::new(MyNode { name: "node1" })).unwrap();
+    ///
+    /// hash_ring.transaction(|tx| {
+    ///     tx.add(Arc::new(MyNode { name: "node2" }))?;
+    ///     tx.remove("node1")?;
+    ///     Ok(())
+    /// }).unwrap();
+    /// ```
+    pub fn transaction<F, R>(&mut self, f: F) -> Result<R, Box<dyn Error>>
+    where
+        F: FnOnce(&mut Transaction<'a>) -> Result<R, Box<dyn Error>>,
+    {
+        let _events = self.dispatch_events();
+        let timer = OpTimer::start();
+        let base = self.nodes.read()?.clone();
+        let mut tx = Transaction { members: base.clone() };
+        let result = f(&mut tx)?;
+
+        let mut nodes = self.nodes.write()?;
+        let unchanged = nodes.len() == base.len() && nodes.iter().all(|(id, node)| base.get(id).is_some_and(|n| Arc::ptr_eq(n, node)));
+        if !unchanged {
+            return Err("the membership changed during the transaction".into());
+        }
+        let mut sorted_set = self.sorted_nodes_hash_set.write()?;
+        let mut partitions = self.partitions.write()?;
+
+        let config = self.config();
+        let replicas = 0..config.replication_factor;
+        let mut updated = sorted_set.clone();
+        for (id, node) in nodes.iter() {
+            if !tx.members.get(id).is_some_and(|n| Arc::ptr_eq(n, node)) {
+                self.remove_vnodes(&mut updated, id, replicas.clone());
+            }
+        }
+        for (id, node) in tx.members.iter() {
+            if !nodes.get(id).is_some_and(|n| Arc::ptr_eq(n, node)) {
+                config.node_id_policy.validate(id)?;
+                self.place_vnodes(&mut updated, node, replicas.clone(), config.collision_policy)?;
+            }
+        }
+
+        // Joins are refused for quarantined nodes: every join is checked before any
+        // change is recorded, so a refused transaction leaves the flap counters alone.
+        let removed: Vec<&str> = nodes.keys().filter(|id| !tx.members.contains_key(*id)).map(String::as_str).collect();
+        let joined: Vec<&str> = tx.members.keys().filter(|id| !nodes.contains_key(*id)).map(String::as_str).collect();
+        self.record_membership_changes(&removed, &joined)?;
+        for id in &removed {
+            self.forget_member(id);
+        }
+
+        self.record_history(std::mem::replace(&mut *sorted_set, updated));
+        *nodes = tx.members;
+        if self.is_updating() {
+            self.partitions_dirty.store(true, Ordering::Release);
+        } else {
+            *partitions = self.compute_partitions(&sorted_set, &partitions);
+        }
+        self.bump_version();
+        self.log_changes("transaction", &timer, Some(&nodes), &partitions);
+        timer.finish("transaction", nodes.len(), sorted_set.len());
+
+        Ok(result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use crate::tests::{test_nodes, TestNode};
+    use crate::{Config, FlapPolicy, HashRing};
+
+    #[test]
+    fn test_transaction_applies_all_changes() {
+        let nodes = test_nodes();
+        let mut hash_ring = HashRing::new(Config::default()).unwrap();
+        hash_ring.add_node(nodes[0].clone()).unwrap();
+
+        let size = hash_ring
+            .transaction(|tx| {
+                tx.add(nodes[1].clone())?;
+                tx.add(nodes[2].clone())?;
+                tx.remove("node1")?;
+                Ok(tx.len())
+            })
+            .unwrap();
+        assert_eq!(size, 2);
+
+        let virtual_nodes = hash_ring.virtual_nodes_per_node();
+        assert_eq!(virtual_nodes.get("node1"), None);
+        assert_eq!(virtual_nodes.get("node2"), Some(&hash_ring.config().replication_factor));
+        assert!(hash_ring.snapshot().partitions.values().all(|id| id != "node1"));
+    }
+
+    #[test]
+    fn test_transaction_rolls_back_on_error() {
+        let nodes = test_nodes();
+        let mut hash_ring = HashRing::new(Config::default()).unwrap();
+        hash_ring.add_node(nodes[0].clone()).unwrap();
+        let before = hash_ring.snapshot();
+
+        let result = hash_ring.transaction(|tx| {
+            tx.add(nodes[1].clone())?;
+            tx.remove("missing")?;
+            Ok(())
+        });
+        assert!(result.is_err());
+
+        let after = hash_ring.snapshot();
+        assert_eq!(after.nodes, before.nodes);
+        assert_eq!(after.partitions, before.partitions);
+        assert_eq!(hash_ring.virtual_nodes_per_node().len(), 1);
+    }
+
+    #[test]
+    fn test_transaction_replaces_node_with_same_id() {
+        let mut hash_ring = HashRing::new(Config::default()).unwrap();
+        hash_ring.add_node(test_nodes()[0].clone()).unwrap();
+
+        let replacement = Arc::new(TestNode {
+            ip_addr: "10.0.0.1".to_string(),
+            name: "node1",
+        });
+        hash_ring
+            .transaction(|tx| {
+                tx.remove("node1")?;
+                tx.add(replacement.clone())
+            })
+            .unwrap();
+
+        assert_eq!(hash_ring.virtual_nodes_per_node().get("node1"), Some(&hash_ring.config().replication_factor));
+        assert!(Arc::ptr_eq(
+            &hash_ring.get_key(b"some_key").unwrap(),
+            &(replacement as Arc<dyn crate::Node<'static>>)
+        ));
+    }
+
+    #[test]
+    fn test_transaction_keeps_membership_bookkeeping() {
+        let nodes = test_nodes();
+        let mut hash_ring = HashRing::new(Config::default()).unwrap();
+        hash_ring.add_node_with_ttl(nodes[0].clone(), Duration::from_secs(60)).unwrap();
+        hash_ring.set_flap_policy(Some(FlapPolicy {
+            window: Duration::from_secs(60),
+            max_changes: 1,
+            quarantine: Duration::from_secs(60),
+        }));
+
+        // The closure can look keys up, and removed nodes lose their lease.
+        let mut shared = hash_ring.share();
+        hash_ring
+            .transaction(|tx| {
+                assert!(shared.get_key(b"some_key").is_some());
+                assert!(shared.check_invariants().is_ok());
+                tx.add(nodes[1].clone())?;
+                tx.remove("node1")
+            })
+            .unwrap();
+        assert!(hash_ring.node_ttl("node1").is_none());
+
+        // Removing node2 quarantines it, so adding it back is refused.
+        hash_ring.transaction(|tx| tx.remove("node2")).unwrap();
+        assert!(hash_ring.is_quarantined("node2"));
+        assert!(hash_ring.transaction(|tx| tx.add(nodes[1].clone())).is_err());
+        assert!(hash_ring.virtual_nodes_per_node().is_empty());
+
+        // Changes made through another handle while the closure runs aren't overwritten.
+        let result = hash_ring.transaction(|tx| {
+            shared.add_node(nodes[2].clone())?;
+            tx.add(nodes[0].clone())
+        });
+        assert!(result.is_err());
+        assert_eq!(hash_ring.virtual_nodes_per_node().len(), 1);
+    }
+
+    #[test]
+    fn test_refused_join_records_no_changes() {
+        let nodes = test_nodes();
+        let mut hash_ring = HashRing::new(Config::default()).unwrap();
+        hash_ring.add_node(nodes[0].clone()).unwrap();
+        hash_ring.add_node(nodes[1].clone()).unwrap();
+        hash_ring.set_flap_policy(Some(FlapPolicy {
+            window: Duration::from_secs(60),
+            max_changes: 0,
+            quarantine: Duration::from_secs(60),
+        }));
+        assert!(hash_ring.add_node(nodes[2].clone()).is_err());
+        assert!(hash_ring.is_quarantined("node3"));
+
+        // Removing node1 alone would quarantine it, but the join of node3 is refused first.
+        let version = hash_ring.version();
+        assert!(hash_ring
+            .transaction(|tx| {
+                tx.remove("node1")?;
+                tx.add(nodes[2].clone())
+            })
+            .is_err());
+        assert!(!hash_ring.is_quarantined("node1"));
+        assert_eq!(hash_ring.quarantined_nodes().len(), 1);
+        assert_eq!(hash_ring.version(), version);
+        assert_eq!(hash_ring.virtual_nodes_per_node().len(), 2);
+    }
+}
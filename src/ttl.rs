@@ -0,0 +1,253 @@
+//! Nodes registered with a time to live.
+//!
+//! Service registries built on heartbeats add a node when it first reports in and
+//! expect it to disappear once it stops. A node added with `add_node_with_ttl` stays in
+//! the ring while `refresh_node` is called within its TTL; `expire_stale` removes the
+//! others, and with the `tokio` feature `spawn_expiry` runs the sweep periodically.
+//!
+//! The registrations double as leases: `renew` extends a lease only while it is still
+//! held, so a node whose lease ran out must register again, and `leases` reports the
+//! time each node has left.
+
+use std::error::Error;
+use std::hash::BuildHasher;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::{HashRing, Node};
+
+/// The TTL of a node and when its registration runs out.
+#[derive(Debug, Clone)]
+pub(crate) struct Registration {
+    ttl: Duration,
+    expires: Instant,
+}
+
+impl<'a, H, K> HashRing<'a, H, K>
+where
+    H: BuildHasher,
+    K: BuildHasher,
+{
+    /// Adds a node that is removed by `expire_stale` unless refreshed within `ttl`.
+    ///
+    /// # Arguments
+    ///
+    /// * `node` - The node to add.
+    /// * `ttl` - How long the registration lasts without a refresh.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use std::sync::Arc;
+    /// use std::time::Duration;
+    /// use hashring::{HashRing, Config, Node};
+    ///
+    /// #[derive(Debug)]
+    /// struct MyNode<'a> {
+    ///     name: &'a str,
+    /// }
+    ///
+    /// impl<'a> Node<'a> for MyNode<'a> {
+    ///     fn id(&self) -> &'a str {
+    ///         self.name
+    ///     }
+    /// }
+    ///
+    /// let mut hash_ring = HashRing::new(Config::default()).unwrap();
+    /// hash_ring.add_node_with_ttl(Arc::new(MyNode { name: "node1" }), Duration::from_secs(30)).unwrap();
+    ///
+    /// // On every heartbeat:
+    /// hash_ring.refresh_node("node1").unwrap();
+    ///
+    /// // Periodically:
+    /// let expired = hash_ring.expire_stale(hash_ring.now()).unwrap();
+    /// assert!(expired.is_empty());
+    /// ```
+    pub fn add_node_with_ttl(&mut self, node: Arc<dyn Node<'a> + 'a>, ttl: Duration) -> Result<Arc<dyn Node<'a> + 'a>, Box<dyn Error>> {
+        let node = self.add_node(node)?;
+        let registration = Registration {
+            ttl,
+            expires: self.now() + ttl,
+        };
+        self.registrations.write()?.insert(node.id().to_string(), registration);
+        Ok(node)
+    }
+
+    /// Extends the registration of a node by its TTL from now.
+    ///
+    /// Returns an error if the node wasn't added with a TTL or has already expired.
+    pub fn refresh_node(&self, id: &str) -> Result<(), Box<dyn Error>> {
+        let now = self.now();
+        let mut registrations = self.registrations.write()?;
+        let registration = registrations.get_mut(id).ok_or_else(|| format!("node {} has no ttl", id))?;
+        registration.expires = now + registration.ttl;
+        Ok(())
+    }
+
+    /// Renews the lease of a node for another TTL, if it hasn't run out.
+    ///
+    /// Unlike `refresh_node`, a lease that ran out can't be renewed even before
+    /// `expire_stale` removes the node: the node must be added again.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Duration, Box<dyn Error>>` - The time until the renewed lease runs out.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use std::sync::Arc;
+    /// use std::time::Duration;
+    /// use hashring::{HashRing, Config, Node};
+    ///
+    /// #[derive(Debug)]
+    /// struct MyNode<'a> {
+    ///     name: &'a str,
+    /// }
+    ///
+    /// impl<'a> Node<'a> for MyNode<'a> {
+    ///     fn id(&self) -> &'a str {
+    ///         self.name
+    ///     }
+    /// }
+    ///
+    /// let mut hash_ring = HashRing::new(Config::default()).unwrap();
+    /// hash_ring.add_node_with_ttl(Arc::new(MyNode { name: "node1" }), Duration::from_secs(10)).unwrap();
+    ///
+    /// assert_eq!(hash_ring.renew("node1").unwrap(), Duration::from_secs(10));
+    /// let leases = hash_ring.leases();
+    /// assert_eq!(leases[0].0, "node1");
+    /// assert!(leases[0].1 <= Duration::from_secs(10));
+    /// ```
+    pub fn renew(&self, node_id: &str) -> Result<Duration, Box<dyn Error>> {
+        let now = self.now();
+        let mut registrations = self.registrations.write()?;
+        let registration = registrations.get_mut(node_id).ok_or_else(|| format!("node {} holds no lease", node_id))?;
+        if registration.expires <= now {
+            return Err(format!("lease of node {} has run out", node_id).into());
+        }
+        registration.expires = now + registration.ttl;
+        Ok(registration.ttl)
+    }
+
+    /// Returns the nodes holding a lease with the time left before it runs out, by ID.
+    ///
+    /// Leases that ran out but weren't swept by `expire_stale` yet have no time left.
+    pub fn leases(&self) -> Vec<(String, Duration)> {
+        let now = self.now();
+        let registrations = self.registrations.wait_read();
+        let mut leases: Vec<(String, Duration)> = registrations
+            .iter()
+            .map(|(id, registration)| (id.clone(), registration.expires.saturating_duration_since(now)))
+            .collect();
+        leases.sort();
+        leases
+    }
+
+    /// Returns the time left before a node expires, `None` if it has no TTL.
+    pub fn node_ttl(&self, id: &str) -> Option<Duration> {
+        let now = self.now();
+        let registrations = self.registrations.read().ok()?;
+        registrations.get(id).map(|registration| registration.expires.saturating_duration_since(now))
+    }
+
+    /// Removes every node whose registration ran out at `now`, usually the `now` of the
+    /// ring's clock.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Vec<String>, Box<dyn Error>>` - The IDs of the removed nodes, sorted.
+    pub fn expire_stale(&mut self, now: Instant) -> Result<Vec<String>, Box<dyn Error>> {
+        let mut expired: Vec<String> = {
+            let mut registrations = self.registrations.write()?;
+            let expired = registrations.iter().filter(|(_, registration)| registration.expires <= now).map(|(id, _)| id.clone()).collect();
+            registrations.retain(|_, registration| registration.expires > now);
+            expired
+        };
+        expired.sort_unstable();
+        // Nodes removed by hand since are already gone.
+        expired.retain(|id| self.remove_node(id).is_ok());
+        Ok(expired)
+    }
+
+    /// Forgets the TTL of a removed node, so that it isn't expired if it rejoins.
+    pub(crate) fn forget_registration(&self, id: &str) {
+        if let Ok(mut registrations) = self.registrations.write() {
+            registrations.remove(id);
+        }
+    }
+
+    /// Spawns a task calling `expire_stale` every `interval`, until it is aborted.
+    ///
+    /// The task works on a handle sharing the state of the ring. It must be called
+    /// from within a tokio runtime.
+    #[cfg(feature = "tokio")]
+    pub fn spawn_expiry(&self, interval: Duration) -> tokio::task::JoinHandle<()>
+    where
+        'a: 'static,
+        H: Clone + Send + Sync + 'static,
+        K: Clone + Send + Sync + 'static,
+    {
+        let mut hash_ring = self.share();
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            loop {
+                ticker.tick().await;
+                let _ = hash_ring.expire_stale(hash_ring.now());
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use crate::tests::test_nodes;
+    use crate::{Config, HashRing};
+
+    #[test]
+    fn test_unrefreshed_nodes_expire() {
+        let mut hash_ring = HashRing::new(Config::default()).unwrap();
+        let nodes = test_nodes();
+        let ttl = Duration::from_secs(30);
+        hash_ring.add_node(nodes[0].clone()).unwrap();
+        hash_ring.add_node_with_ttl(nodes[1].clone(), ttl).unwrap();
+        hash_ring.add_node_with_ttl(nodes[2].clone(), ttl).unwrap();
+        assert!(hash_ring.refresh_node("node1").is_err());
+        assert!(hash_ring.node_ttl("node1").is_none());
+        assert!(hash_ring.expire_stale(Instant::now()).unwrap().is_empty());
+
+        let later = Instant::now() + ttl;
+        hash_ring.refresh_node("node2").unwrap();
+        assert_eq!(hash_ring.expire_stale(later).unwrap(), ["node3"]);
+        assert!(hash_ring.get_key(b"some_key").is_some());
+        assert_eq!(hash_ring.virtual_nodes_per_node().len(), 2);
+
+        // A node removed by hand and added back without a TTL is left alone.
+        hash_ring.remove_node("node2").unwrap();
+        hash_ring.add_node(nodes[1].clone()).unwrap();
+        assert!(hash_ring.expire_stale(later + ttl).unwrap().is_empty());
+        assert!(hash_ring.refresh_node("node3").is_err());
+    }
+
+    #[test]
+    fn test_lapsed_lease_cannot_be_renewed() {
+        let mut hash_ring = HashRing::new(Config::default()).unwrap();
+        let nodes = test_nodes();
+        hash_ring.add_node_with_ttl(nodes[0].clone(), Duration::from_secs(60)).unwrap();
+        hash_ring.add_node_with_ttl(nodes[1].clone(), Duration::from_millis(20)).unwrap();
+        hash_ring.add_node(nodes[2].clone()).unwrap();
+        assert!(hash_ring.renew("node3").is_err());
+        assert_eq!(hash_ring.renew("node2").unwrap(), Duration::from_millis(20));
+
+        std::thread::sleep(Duration::from_millis(30));
+        let leases = hash_ring.leases();
+        assert_eq!(leases.len(), 2);
+        assert!(leases[0].1 > Duration::from_secs(59));
+        assert_eq!(leases[1], ("node2".to_string(), Duration::ZERO));
+        assert!(hash_ring.renew("node2").is_err());
+        assert_eq!(hash_ring.expire_stale(Instant::now()).unwrap(), ["node2"]);
+        assert_eq!(hash_ring.leases().len(), 1);
+    }
+}
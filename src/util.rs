@@ -0,0 +1,101 @@
+//! Ring arithmetic for custom placement logic.
+//!
+//! The ring is the space of `u64` hashes, wrapping from `u64::MAX` back to `0`. These
+//! are the helpers the rings of this crate place nodes and route keys with, so that
+//! placement built on top of them agrees with a `HashRing` using the same hashers.
+//!
+//! ```
+//! use std::collections::hash_map::DefaultHasher;
+//! use std::collections::BTreeMap;
+//! use std::hash::BuildHasherDefault;
+//! use hashring::util;
+//! use hashring::{Config, VnodeEncoding};
+//!
+//! let hasher = BuildHasherDefault::<DefaultHasher>::default();
+//! let mut points = BTreeMap::new();
+//! for node in ["node1", "node2"] {
+//!     for replica in 0..3 {
+//!         points.insert(util::hash_with_replica_idx(&hasher, VnodeEncoding::Legacy, node, replica), node);
+//!     }
+//! }
+//!
+//! let hash = util::hash_key(&hasher, &Config::default(), b"some_key");
+//! let (point, owner) = util::next_point(&points, hash).unwrap();
+//! let (previous, _) = util::previous_point(&points, point).unwrap();
+//! assert!(util::arc_length(previous, hash) < util::arc_length(previous, point) || hash == point);
+//! println!("some_key is on {}", owner);
+//! ```
+
+use std::collections::BTreeMap;
+use std::hash::{BuildHasher, Hasher};
+
+use crate::collision::vnode_hash;
+use crate::{Config, VnodeEncoding};
+
+/// Hashes a key as a ring with the configuration `config` does, including its key
+/// namespace.
+pub fn hash_key(key_hasher: &impl BuildHasher, config: &Config, key: &[u8]) -> u64 {
+    let mut hasher = key_hasher.build_hasher();
+    config.write_namespace(&mut hasher);
+    hasher.write(key);
+    hasher.finish()
+}
+
+/// Returns the point of the virtual node `replica` of the node `id`, as placed when it
+/// doesn't collide with another virtual node.
+pub fn hash_with_replica_idx(node_hasher: &impl BuildHasher, encoding: VnodeEncoding, id: &str, replica: usize) -> u64 {
+    vnode_hash(node_hasher, encoding, id, replica, 0)
+}
+
+/// Returns the length of the arc going clockwise from `from` to `to`, which is `0` when
+/// they are the same point.
+pub fn arc_length(from: u64, to: u64) -> u64 {
+    to.wrapping_sub(from)
+}
+
+/// Returns the distance between two points, going whichever way around the ring is
+/// shorter.
+pub fn ring_distance(a: u64, b: u64) -> u64 {
+    arc_length(a, b).min(arc_length(b, a))
+}
+
+/// Returns the fraction of the ring an arc of length `length` covers, from `0.0` to `1.0`.
+pub fn arc_fraction(length: u64) -> f64 {
+    length as f64 / (u64::MAX as f64 + 1.0)
+}
+
+/// Returns the first point at or after `hash`, wrapping around to the first point of the
+/// ring. This is the point owning `hash`.
+pub fn next_point<V>(points: &BTreeMap<u64, V>, hash: u64) -> Option<(u64, &V)> {
+    points.range(hash..).next().or_else(|| points.iter().next()).map(|(point, value)| (*point, value))
+}
+
+/// Returns the last point strictly before `hash`, wrapping around to the last point of
+/// the ring.
+pub fn previous_point<V>(points: &BTreeMap<u64, V>, hash: u64) -> Option<(u64, &V)> {
+    points.range(..hash).next_back().or_else(|| points.iter().next_back()).map(|(point, value)| (*point, value))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use super::{arc_fraction, arc_length, next_point, previous_point, ring_distance};
+
+    #[test]
+    fn test_ring_arithmetic_wraps() {
+        assert_eq!(arc_length(10, 30), 20);
+        assert_eq!(arc_length(u64::MAX - 4, 5), 10);
+        assert_eq!(ring_distance(5, u64::MAX - 4), 10);
+        assert_eq!(ring_distance(30, 10), 20);
+        assert_eq!(arc_fraction(u64::MAX / 2 + 1), 0.5);
+
+        let points: BTreeMap<u64, &str> = [(100, "a"), (200, "b")].into();
+        assert_eq!(next_point(&points, 100), Some((100, &"a")));
+        assert_eq!(next_point(&points, 150), Some((200, &"b")));
+        assert_eq!(next_point(&points, 250), Some((100, &"a")));
+        assert_eq!(previous_point(&points, 200), Some((100, &"a")));
+        assert_eq!(previous_point(&points, 50), Some((200, &"b")));
+        assert_eq!(next_point(&BTreeMap::<u64, ()>::new(), 0), None);
+    }
+}
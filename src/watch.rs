@@ -0,0 +1,440 @@
+//! Reloading the replication factor, members and weights from a config file, behind the
+//! `watch` feature.
+//!
+//! Deployments managed through config management describe the ring in a file:
+//!
+//! ```toml
+//! replication_factor = 40
+//! members = ["node1", "node2", "node3"]
+//!
+//! [weights]
+//! node3 = 0.5
+//! ```
+//!
+//! `watch_config` watches the file for changes with `notify`, and
+//! `ConfigWatcher::apply_changes` reloads it once it changed and applies the difference
+//! with the ring in a single update: readers see either the old or the new topology.
+//! Files ending in `.json` are parsed as JSON, anything else as TOML.
+//!
+//! A weight is the fraction of the replication factor a node places virtual nodes for,
+//! from `0.0` exclusive to `1.0`, the default. The ring keeps the weights, so they still
+//! apply after `set_replication_factor` or a snapshot restore. Virtual node `i` of a node
+//! sits at the same place whatever the weight or replication factor, so a reload only
+//! adds or removes the virtual nodes that differ, as `set_replication_factor` and `decay` do.
+//!
+//! Applying changes needs `&mut HashRing`, so the owner of the ring calls
+//! `apply_changes` from its own loop rather than the watcher mutating the ring behind it.
+
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt;
+use std::hash::BuildHasher;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver};
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+
+use notify::{RecommendedWatcher, RecursiveMode, Watcher};
+use serde::{Deserialize, Serialize};
+
+use crate::trace::OpTimer;
+use crate::{weight, Config, HashRing, Node};
+
+/// The ring settings managed by a config file.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FileConfig {
+    /// The replication factor, or `None` to keep the current one.
+    pub replication_factor: Option<usize>,
+    /// The IDs of the nodes that should be members of the ring.
+    pub members: Vec<String>,
+    /// The weights of members not at full weight, by ID.
+    pub weights: BTreeMap<String, f64>,
+}
+
+impl FileConfig {
+    /// Reads a config file, as JSON if its extension is `.json` and as TOML otherwise.
+    pub fn load(path: impl AsRef<Path>) -> Result<FileConfig, Box<dyn Error>> {
+        let path = path.as_ref();
+        let data = std::fs::read_to_string(path)?;
+        let config: FileConfig = match path.extension().and_then(|ext| ext.to_str()) {
+            Some("json") => serde_json::from_str(&data)?,
+            _ => toml::from_str(&data)?,
+        };
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Checks the replication factor and weights, and that members are listed once.
+    pub fn validate(&self) -> Result<(), Box<dyn Error>> {
+        if self.replication_factor == Some(0) {
+            return Err("Replication factor must be greater than 0".into());
+        }
+        let mut members: Vec<&String> = self.members.iter().collect();
+        members.sort_unstable();
+        if let Some(pair) = members.windows(2).find(|pair| pair[0] == pair[1]) {
+            return Err(format!("member {} is listed twice", pair[0]).into());
+        }
+        for (id, weight) in &self.weights {
+            if !self.members.contains(id) {
+                return Err(format!("weight given for {}, which is not a member", id).into());
+            }
+            if !(*weight > 0.0 && *weight <= 1.0) {
+                return Err(format!("weight of {} must be greater than 0 and at most 1", id).into());
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the weight of a member, `1.0` unless the file lowers it.
+    fn weight(&self, id: &str) -> f64 {
+        self.weights.get(id).copied().unwrap_or(1.0)
+    }
+}
+
+/// What applying a config file changed.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ConfigDelta {
+    /// The replication factor before and after, if it changed.
+    pub replication_factor: Option<(usize, usize)>,
+    /// The IDs of the nodes added, sorted.
+    pub added: Vec<String>,
+    /// The IDs of the nodes removed, sorted.
+    pub removed: Vec<String>,
+    /// The IDs of the remaining nodes whose number of virtual nodes changed, sorted.
+    pub reweighted: Vec<String>,
+}
+
+impl ConfigDelta {
+    /// Returns `true` if the ring already matched the file.
+    pub fn is_empty(&self) -> bool {
+        self.replication_factor.is_none() && self.added.is_empty() && self.removed.is_empty() && self.reweighted.is_empty()
+    }
+}
+
+/// Watches a config file, returned by `watch_config`.
+///
+/// Dropping the watcher stops watching.
+pub struct ConfigWatcher {
+    path: PathBuf,
+    events: Receiver<notify::Result<notify::Event>>,
+    applied: Option<FileConfig>,
+    _watcher: RecommendedWatcher,
+}
+
+impl fmt::Debug for ConfigWatcher {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt.debug_struct("ConfigWatcher").field("path", &self.path).field("applied", &self.applied).finish()
+    }
+}
+
+impl ConfigWatcher {
+    /// Returns the path of the watched file.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Returns the contents of the file as last applied, `None` before the first apply.
+    pub fn applied(&self) -> Option<&FileConfig> {
+        self.applied.as_ref()
+    }
+
+    /// Returns `true` if the file was written, created or replaced since the last call.
+    fn changed(&self) -> Result<bool, Box<dyn Error>> {
+        let mut changed = false;
+        for event in self.events.try_iter() {
+            let event = event?;
+            let touches_file = event.paths.iter().any(|path| path.file_name() == self.path.file_name());
+            changed |= touches_file && (event.kind.is_create() || event.kind.is_modify() || event.kind.is_remove());
+        }
+        Ok(changed)
+    }
+
+    /// Reloads the file and applies it to the ring if it changed since it was last
+    /// applied. The first call always applies it.
+    ///
+    /// # Arguments
+    ///
+    /// * `hash_ring` - The ring the file describes.
+    /// * `resolver` - Returns the node for the ID of an added member, or `None` if it is unknown.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Option<ConfigDelta>, Box<dyn Error>>` - What changed, or `None` if the file
+    ///   didn't. On error, e.g. while an editor is halfway through writing the file, the ring
+    ///   is unchanged and the next call tries again.
+    pub fn apply_changes<'a, H, K, F>(&mut self, hash_ring: &mut HashRing<'a, H, K>, resolver: F) -> Result<Option<ConfigDelta>, Box<dyn Error>>
+    where
+        H: BuildHasher,
+        K: BuildHasher,
+        F: FnMut(&str) -> Option<Arc<dyn Node<'a> + 'a>>,
+    {
+        if !self.changed()? && self.applied.is_some() {
+            return Ok(None);
+        }
+        let config = match FileConfig::load(&self.path) {
+            Ok(config) => config,
+            Err(err) => {
+                // Retry on the next call, even if no other event arrives.
+                self.applied = None;
+                return Err(err);
+            }
+        };
+        if self.applied.as_ref() == Some(&config) {
+            return Ok(None);
+        }
+        let delta = hash_ring.apply_file_config(&config, resolver);
+        self.applied = delta.is_ok().then_some(config);
+        delta.map(Some)
+    }
+}
+
+impl<'a, H, K> HashRing<'a, H, K>
+where
+    H: BuildHasher,
+    K: BuildHasher,
+{
+    /// Starts watching a config file for changes.
+    ///
+    /// The parent directory is watched rather than the file, so files replaced by an
+    /// editor or by config management keep being picked up. Call `apply_changes` on the
+    /// returned watcher to apply the file, once at startup and then periodically.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The path of the config file.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<ConfigWatcher, Box<dyn Error>>` - The watcher, or an error if the file can't be watched.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use std::sync::Arc;
+    /// use std::time::Duration;
+    /// use hashring::{HashRing, Config, Node};
+    ///
+    /// #[derive(Debug)]
+    /// struct MyNode {
+    ///     name: &'static str,
+    /// }
+    ///
+    /// impl Node<'static> for MyNode {
+    ///     fn id(&self) -> &'static str {
+    ///         self.name
+    ///     }
+    /// }
+    ///
+    /// let mut hash_ring = HashRing::new(Config::default()).unwrap();
+    /// let mut watcher = hash_ring.watch_config("/etc/myapp/ring.toml").unwrap();
+    /// loop {
+    ///     let resolver = |id: &str| -> Option<Arc<dyn Node<'static>>> {
+    ///         Some(Arc::new(MyNode { name: Box::leak(id.to_string().into_boxed_str()) }))
+    ///     };
+    ///     match watcher.apply_changes(&mut hash_ring, resolver) {
+    ///         Ok(Some(delta)) => println!("ring reloaded: {:?}", delta),
+    ///         Ok(None) => {}
+    ///         Err(err) => eprintln!("unable to reload the ring: {}", err),
+    ///     }
+    ///     std::thread::sleep(Duration::from_secs(1));
+    /// }
+    /// ```
+    pub fn watch_config(&self, path: impl AsRef<Path>) -> Result<ConfigWatcher, Box<dyn Error>> {
+        let path = path.as_ref().to_path_buf();
+        if path.file_name().is_none() {
+            return Err(format!("{} is not a file", path.display()).into());
+        }
+        let dir = match path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir.to_path_buf(),
+            _ => PathBuf::from("."),
+        };
+        let (tx, events) = mpsc::channel();
+        let mut watcher = notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+            let _ = tx.send(event);
+        })?;
+        watcher.watch(&dir, RecursiveMode::NonRecursive)?;
+        Ok(ConfigWatcher {
+            path,
+            events,
+            applied: None,
+            _watcher: watcher,
+        })
+    }
+
+    /// Makes the ring match a config file in a single update.
+    ///
+    /// Members missing from the file are removed, new ones are resolved and added, and
+    /// every member ends up with the weight the file gives it, and the virtual nodes that
+    /// weight gives it under the new replication factor. Removals and joins count towards
+    /// flapping and drop the state of removed nodes like `remove_node` does. Nothing is
+    /// applied if any new member can't be resolved or is quarantined, or any virtual node
+    /// can't be placed.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<ConfigDelta, Box<dyn Error>>` - What changed, empty if the ring already matched.
+    pub fn apply_file_config<F>(&mut self, config: &FileConfig, mut resolver: F) -> Result<ConfigDelta, Box<dyn Error>>
+    where
+        F: FnMut(&str) -> Option<Arc<dyn Node<'a> + 'a>>,
+    {
+        let _events = self.dispatch_events();
+        let timer = OpTimer::start();
+        config.validate()?;
+        let mut nodes = self.nodes.write()?;
+        let mut sorted_set = self.sorted_nodes_hash_set.write()?;
+        let mut partitions = self.partitions.write()?;
+        let mut weights = self.weights.write()?;
+
+        let ring_config = self.config();
+        let previous = ring_config.replication_factor;
+        let replication_factor = config.replication_factor.unwrap_or(previous);
+        let mut delta = ConfigDelta {
+            replication_factor: (replication_factor != previous).then_some((previous, replication_factor)),
+            ..ConfigDelta::default()
+        };
+
+        let mut members = BTreeMap::new();
+        for id in &config.members {
+            let node = match nodes.get(id) {
+                Some(node) => node.clone(),
+                None => {
+                    ring_config.node_id_policy.validate(id)?;
+                    let node = resolver(id).ok_or_else(|| format!("unable to resolve node {}", id))?;
+                    if node.id() != id {
+                        return Err(format!("resolved node {} has a different id {}", id, node.id()).into());
+                    }
+                    delta.added.push(id.clone());
+                    node
+                }
+            };
+            members.insert(id.clone(), node);
+        }
+        delta.removed = nodes.keys().filter(|id| !members.contains_key(*id)).cloned().collect();
+        delta.removed.sort_unstable();
+
+        let mut counts: BTreeMap<&str, usize> = BTreeMap::new();
+        for node in sorted_set.values() {
+            *counts.entry(node.id()).or_default() += 1;
+        }
+        let mut updated = sorted_set.clone();
+        for id in &delta.removed {
+            self.remove_vnodes(&mut updated, id, 0..counts.get(id.as_str()).copied().unwrap_or(0));
+        }
+        for (id, node) in &members {
+            let current = counts.get(id.as_str()).copied().unwrap_or(0);
+            let target = weight::vnode_count(config.weight(id), replication_factor);
+            if target > current {
+                self.place_vnodes(&mut updated, node, current..target, ring_config.collision_policy)?;
+            } else {
+                self.remove_vnodes(&mut updated, id, target..current);
+            }
+            if target != current && nodes.contains_key(id) {
+                delta.reweighted.push(id.clone());
+            }
+        }
+        let updated_weights: weight::Weights = config.weights.iter().filter(|(_, weight)| **weight < 1.0).map(|(id, weight)| (id.clone(), *weight)).collect();
+        if delta.is_empty() && *weights == updated_weights {
+            return Ok(delta);
+        }
+        let removed: Vec<&str> = delta.removed.iter().map(String::as_str).collect();
+        let added: Vec<&str> = delta.added.iter().map(String::as_str).collect();
+        self.record_membership_changes(&removed, &added)?;
+
+        *weights = updated_weights;
+        drop(weights);
+        for id in &delta.removed {
+            self.forget_member(id);
+        }
+        self.set_config(Config {
+            replication_factor,
+            ..ring_config.as_ref().clone()
+        });
+        self.record_history(std::mem::replace(&mut *sorted_set, updated));
+        *nodes = members.into_iter().collect();
+        if self.is_updating() {
+            self.partitions_dirty.store(true, Ordering::Release);
+        } else {
+            *partitions = self.compute_partitions(&sorted_set, &partitions);
+        }
+        self.bump_version();
+        self.log_changes("apply_file_config", &timer, Some(&nodes), &partitions);
+        timer.finish("apply_file_config", nodes.len(), sorted_set.len());
+        Ok(delta)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use crate::tests::test_nodes;
+    use crate::{Config, FileConfig, FlapPolicy, HashRing, Node, RingSnapshot};
+
+    #[test]
+    fn test_apply_file_config_applies_deltas() {
+        let nodes = test_nodes();
+        let resolve = |id: &str| nodes.iter().find(|node| node.id() == id).map(|node| node.clone() as Arc<dyn Node<'static>>);
+        let mut hash_ring = HashRing::new(Config::default()).unwrap();
+
+        let config: FileConfig = toml::from_str("members = [\"node1\", \"node2\"]\n[weights]\nnode2 = 0.5\n").unwrap();
+        let delta = hash_ring.apply_file_config(&config, resolve).unwrap();
+        assert_eq!(delta.added, ["node1", "node2"]);
+        assert_eq!(hash_ring.virtual_nodes_per_node().get("node2"), Some(&10));
+        let version = hash_ring.version();
+        assert!(hash_ring.apply_file_config(&config, resolve).unwrap().is_empty());
+        assert_eq!(hash_ring.version(), version);
+
+        let config: FileConfig = serde_json::from_str(r#"{"replication_factor": 40, "members": ["node2", "node3"]}"#).unwrap();
+        let delta = hash_ring.apply_file_config(&config, resolve).unwrap();
+        assert_eq!(delta.replication_factor, Some((20, 40)));
+        assert_eq!((delta.added, delta.removed, delta.reweighted), (vec!["node3".to_string()], vec!["node1".to_string()], vec!["node2".to_string()]));
+        assert_eq!(hash_ring.virtual_nodes_per_node(), [("node2".to_string(), 40), ("node3".to_string(), 40)].into_iter().collect());
+
+        // Nothing is applied when a member can't be resolved or a weight is invalid.
+        let version = hash_ring.version();
+        let unknown = FileConfig { members: vec!["node9".to_string()], ..FileConfig::default() };
+        assert!(hash_ring.apply_file_config(&unknown, resolve).is_err());
+        let invalid = FileConfig {
+            members: vec!["node2".to_string()],
+            weights: BTreeMap::from([("node2".to_string(), 1.5)]),
+            ..FileConfig::default()
+        };
+        assert!(hash_ring.apply_file_config(&invalid, resolve).is_err());
+        assert_eq!(hash_ring.version(), version);
+    }
+
+    #[test]
+    fn test_file_weights_are_kept_by_the_ring() {
+        let nodes = test_nodes();
+        let resolve = |id: &str| nodes.iter().find(|node| node.id() == id).map(|node| node.clone() as Arc<dyn Node<'static>>);
+        let mut hash_ring = HashRing::new(Config::default()).unwrap();
+        let config: FileConfig = toml::from_str("members = [\"node1\", \"node2\", \"node3\"]\n[weights]\nnode2 = 0.5\n").unwrap();
+        hash_ring.apply_file_config(&config, resolve).unwrap();
+        assert_eq!(hash_ring.node_weight("node2"), Some(0.5));
+
+        // The weight still applies under another replication factor and after a restore.
+        hash_ring.set_replication_factor(40).unwrap();
+        assert_eq!(hash_ring.virtual_nodes_per_node().get("node2"), Some(&20));
+        let snapshot = RingSnapshot::from_bytes(&hash_ring.snapshot().to_bytes().unwrap()).unwrap();
+        let restored: HashRing = HashRing::from_snapshot(&snapshot, Default::default(), Default::default(), resolve).unwrap();
+        assert_eq!(restored.virtual_nodes_per_node(), hash_ring.virtual_nodes_per_node());
+        assert_eq!(restored.node_weight("node2"), Some(0.5));
+
+        // Removals count towards flapping and drop the weight of the node.
+        hash_ring.set_flap_policy(Some(FlapPolicy {
+            window: Duration::from_secs(60),
+            max_changes: 0,
+            quarantine: Duration::from_secs(60),
+        }));
+        let config: FileConfig = toml::from_str("members = [\"node1\", \"node3\"]").unwrap();
+        hash_ring.apply_file_config(&config, resolve).unwrap();
+        assert!(hash_ring.is_quarantined("node2"));
+        assert_eq!(hash_ring.node_weight("node2"), None);
+        let config: FileConfig = toml::from_str("members = [\"node1\", \"node2\", \"node3\"]").unwrap();
+        assert!(hash_ring.apply_file_config(&config, resolve).is_err());
+        assert_eq!(hash_ring.virtual_nodes_per_node().len(), 2);
+    }
+}
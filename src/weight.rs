@@ -0,0 +1,83 @@
+//! Per-node weights.
+//!
+//! A weight is the fraction of the replication factor a node places virtual nodes for,
+//! from `0.0` to `1.0`, the default. Weights are set from config files and lowered by
+//! `decay`. The ring keeps them, so a node keeps its share of keys when the replication
+//! factor changes or the ring is restored from a snapshot.
+
+use std::collections::HashMap;
+use std::hash::BuildHasher;
+
+use crate::HashRing;
+
+/// The weights of the members below full weight, by ID.
+pub(crate) type Weights = HashMap<String, f64>;
+
+/// Returns the number of virtual nodes of a node at `weight` under the replication
+/// factor: at least one unless the weight is zero.
+pub(crate) fn vnode_count(weight: f64, replication_factor: usize) -> usize {
+    let count = ((weight * replication_factor as f64).round() as usize).min(replication_factor);
+    if weight > 0.0 {
+        count.max(1)
+    } else {
+        count
+    }
+}
+
+impl<'a, H, K> HashRing<'a, H, K>
+where
+    H: BuildHasher,
+    K: BuildHasher,
+{
+    /// Returns the weight of a member, or `None` if the node is not a member.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use std::sync::Arc;
+    /// use hashring::{HashRing, Config, Node};
+    ///
+    /// #[derive(Debug)]
+    /// struct MyNode<'a> {
+    ///     name: &'a str,
+    /// }
+    ///
+    /// impl<'a> Node<'a> for MyNode<'a> {
+    ///     fn id(&self) -> &'a str {
+    ///         self.name
+    ///     }
+    /// }
+    ///
+    /// let mut hash_ring = HashRing::new(Config::default()).unwrap();
+    /// hash_ring.add_node(Arc::new(MyNode { name: "node1" })).unwrap();
+    /// assert_eq!(hash_ring.node_weight("node1"), Some(1.0));
+    ///
+    /// hash_ring.decay("node1", 0.5).unwrap();
+    /// assert_eq!(hash_ring.node_weight("node1"), Some(0.5));
+    /// ```
+    pub fn node_weight(&self, id: &str) -> Option<f64> {
+        if !self.nodes.read().ok()?.contains_key(id) {
+            return None;
+        }
+        Some(self.weights.read().ok()?.get(id).copied().unwrap_or(1.0))
+    }
+
+    /// Forgets the weight of a node leaving the ring, so it rejoins at full weight.
+    pub(crate) fn forget_weight(&self, id: &str) {
+        self.weights.wait_write().remove(id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::vnode_count;
+
+    #[test]
+    fn test_vnode_count() {
+        assert_eq!(vnode_count(1.0, 20), 20);
+        assert_eq!(vnode_count(0.5, 20), 10);
+        assert_eq!(vnode_count(0.01, 20), 1);
+        assert_eq!(vnode_count(0.0, 20), 0);
+        assert_eq!(vnode_count(0.5, 40), 20);
+    }
+}